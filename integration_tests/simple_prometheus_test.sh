@@ -32,15 +32,15 @@ echo "$all_contents"
 
 # Check static metrics in some of the stores. These settings are set
 # in the config file of integration tests for the CAS.
-echo 'Checking: nativelink_stores_AC_MAIN_STORE_evicting_map_max_bytes 500000000'
-grep -q 'nativelink_stores_AC_MAIN_STORE_evicting_map_max_bytes 500000000' <<< "$all_contents"
-echo 'Checking: nativelink_stores_AC_MAIN_STORE_read_buffer_size 32768'
-grep -q 'nativelink_stores_AC_MAIN_STORE_read_buffer_size 32768' <<< "$all_contents"
-echo 'Checking: nativelink_stores_CAS_MAIN_STORE_inner_store_evicting_map_max_bytes 10000000000'
-grep -q 'nativelink_stores_CAS_MAIN_STORE_inner_store_evicting_map_max_bytes 10000000000' <<< "$all_contents"
+echo 'Checking: nativelink_stores_evicting_map_max_bytes{store="AC_MAIN_STORE"} 500000000'
+grep -q 'nativelink_stores_evicting_map_max_bytes{store="AC_MAIN_STORE"} 500000000' <<< "$all_contents"
+echo 'Checking: nativelink_stores_read_buffer_size{store="AC_MAIN_STORE"} 32768'
+grep -q 'nativelink_stores_read_buffer_size{store="AC_MAIN_STORE"} 32768' <<< "$all_contents"
+echo 'Checking: nativelink_stores_inner_store_evicting_map_max_bytes{store="CAS_MAIN_STORE"} 10000000000'
+grep -q 'nativelink_stores_inner_store_evicting_map_max_bytes{store="CAS_MAIN_STORE"} 10000000000' <<< "$all_contents"
 
 # Ensure our store metrics are only published once.
-count=$(grep 'nativelink_stores_CAS_MAIN_STORE_inner_store_evicting_map_max_bytes 10000000000' <<< "$all_contents" | wc -l)
+count=$(grep 'nativelink_stores_inner_store_evicting_map_max_bytes{store="CAS_MAIN_STORE"} 10000000000' <<< "$all_contents" | wc -l)
 if [[ $count -ne 1 ]]; then
   echo "Expected to find 1 instance of CAS_MAIN_STORE, but found $count"
   exit 1
@@ -23,7 +23,7 @@ use crate::serde_utils::{
     convert_optional_string_with_shellexpand, convert_string_with_shellexpand,
     convert_vec_string_with_shellexpand,
 };
-use crate::stores::{ClientTlsConfig, ConfigDigestHashFunction, StoreRefName, StoreSpec};
+use crate::stores::{ClientTlsConfig, ConfigDigestHashFunction, Retry, StoreRefName, StoreSpec};
 
 /// Name of the scheduler. This type will be used when referencing a
 /// scheduler in the `CasConfig::schedulers`'s map key.
@@ -87,15 +87,110 @@ pub struct AcStoreConfig {
     /// it is only possible to read from the Action Cache.
     #[serde(default)]
     pub read_only: bool,
+
+    /// If set, `UpdateActionResult` requests are rejected with
+    /// `FailedPrecondition` unless every output file, output directory tree,
+    /// stdout and stderr digest referenced by the action result exists in
+    /// this CAS store. This prevents dangling entries in the Action Cache
+    /// at the cost of one existence lookup per write.
+    /// Default: {No verification is done}
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub verify_cas_store: Option<StoreRefName>,
+
+    /// If set, `GetActionResult` requests that set `inline_stdout` or
+    /// `inline_stderr` get the stdout or stderr of the action result read
+    /// from this CAS store and inlined in the response, as long as it is at
+    /// most `max_inline_output_bytes` long. Unless the worker inlined them
+    /// (see the worker's `max_inline_output_bytes`), stdout and stderr are
+    /// stored in the CAS and the Action Cache only references them by digest.
+    /// Default: {Nothing is inlined}
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub inline_output_cas_store: Option<StoreRefName>,
+
+    /// Maximum size of stdout or stderr inlined in a `GetActionResult`
+    /// response. See `inline_output_cas_store`.
+    ///
+    /// Default: 1048576 (1mb)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_inline_output_bytes: usize,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct CasStoreConfig {
     /// The store name referenced in the `stores` map in the main config.
     /// This store name referenced here may be reused multiple times.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub cas_store: StoreRefName,
+
+    /// The maximum sum of all digest sizes allowed in a single
+    /// `BatchReadBlobs` or `BatchUpdateBlobs` request. Requests above this
+    /// limit are rejected with `ResourceExhausted` before any data is read.
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_bytes_per_batch: u64,
+
+    /// The maximum digest size of any individual blob in a `BatchReadBlobs`
+    /// or `BatchUpdateBlobs` request. Requests containing a larger blob are
+    /// rejected with `ResourceExhausted` before any data is read. Clients
+    /// should use the `ByteStream` service for larger blobs.
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_blob_size: u64,
+
+    /// The maximum number of digests allowed in a single `FindMissingBlobs`
+    /// request. Larger requests are rejected with `ResourceExhausted`
+    /// before the store is queried.
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_digests_per_find_missing: usize,
+
+    /// The maximum number of directory levels a single `GetTree` request
+    /// will walk below its starting directory. Deeper trees fail with
+    /// `ResourceExhausted`. When a tree is fetched in pages, each page is
+    /// counted from the directories it starts from.
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_tree_depth: usize,
+
+    /// The maximum number of directories a single `GetTree` request will
+    /// visit or queue for visiting. Larger trees fail with
+    /// `ResourceExhausted`. When a tree is fetched in pages, the limit
+    /// applies to each page separately.
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_tree_nodes: usize,
+
+    /// The digest function this instance uses. Requests that do not specify
+    /// a digest function are handled with this function and requests that
+    /// specify a different one are rejected with `InvalidArgument`.
+    /// The `execution` service of the same instance uses this function too.
+    /// If the instance is also served by `bytestream`, its entry in
+    /// `bytestream.digest_functions` must be the same, otherwise the server
+    /// fails to start.
+    ///
+    /// Default: None (any supported digest function, falling back to
+    /// `default_digest_hash_function` if the request does not specify one)
+    #[serde(default)]
+    pub digest_function: Option<ConfigDigestHashFunction>,
+
+    /// Directory `BatchUpdateBlobs` spills blobs to before uploading them
+    /// with `update_with_whole_file`, when the store supports file updates
+    /// (eg: `filesystem` store). This allows the store to move the file into
+    /// place instead of copying the data. This directory should be on the
+    /// same filesystem as the store's `content_path`, otherwise the upload
+    /// will fail. The directory is created if it does not exist.
+    ///
+    /// Default: None (blobs are always uploaded from memory)
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub batch_update_temp_path: Option<String>,
+
+    /// Blobs smaller than this are uploaded from memory even when
+    /// `batch_update_temp_path` is set, since spilling small blobs to disk
+    /// costs more than it saves.
+    /// Default: 0 (all non-empty blobs are spilled)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub batch_update_min_file_size: u64,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -135,6 +230,17 @@ pub struct ByteStreamConfig {
     /// Name of the store in the "stores" configuration.
     pub cas_stores: HashMap<InstanceName, StoreRefName>,
 
+    /// The digest function used by each instance in `cas_stores`. Resource
+    /// names that do not contain a digest function are handled with the
+    /// instance's function and resource names with a different digest
+    /// function are rejected with `InvalidArgument`. Instances that are not
+    /// listed accept any supported digest function. Must match the
+    /// `digest_function` of the instance in the `cas` service.
+    ///
+    /// Default: {} (no instance is restricted)
+    #[serde(default)]
+    pub digest_functions: HashMap<InstanceName, ConfigDigestHashFunction>,
+
     /// Max number of bytes to send on each grpc stream chunk.
     /// According to <https://github.com/grpc/grpc.github.io/issues/371>
     /// 16KiB - 64KiB is optimal.
@@ -152,11 +258,28 @@ pub struct ByteStreamConfig {
     /// In the event a client disconnects while uploading a blob, we will hold
     /// the internal stream open for this many seconds before closing it.
     /// This allows clients that disconnect to reconnect and continue uploading
-    /// the same blob.
+    /// the same blob. Uploads that are not resumed within this time are
+    /// discarded and have to be restarted from the beginning.
     ///
-    /// Default: 10 (seconds)
+    /// Default: 60 (seconds)
     #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
     pub persist_stream_on_disconnect_timeout: usize,
+
+    /// Maximum number of `Write` streams a single client (identified by its
+    /// IP address) may have in flight at the same time. Additional streams
+    /// are rejected with `ResourceExhausted`.
+    ///
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_writes_per_client: usize,
+
+    /// If a client does not send any data on a `Write` stream for this many
+    /// seconds the stream is aborted with `DeadlineExceeded`. The upload can
+    /// still be resumed within `persist_stream_on_disconnect_timeout`.
+    ///
+    /// Default: 0 (streams never time out)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub write_idle_timeout: usize,
 }
 
 #[derive(Deserialize, Debug)]
@@ -165,6 +288,37 @@ pub struct WorkerApiConfig {
     /// The scheduler name referenced in the `schedulers` map in the main config.
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub scheduler: SchedulerRefName,
+
+    /// If nothing was sent to a connected worker for this long, a
+    /// keep-alive is sent to it. Workers use these to notice a dead
+    /// connection, see `scheduler_keep_alive_timeout` in the worker config.
+    /// Workers that stop sending keep-alives are removed after the
+    /// scheduler's `worker_timeout_s`. Value in seconds.
+    ///
+    /// Default: 0 (no keep-alives are sent)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub keep_alive_interval: usize,
+
+    /// The maximum size in bytes of the encoded `ExecuteResponse` a worker
+    /// may report for an action. The scheduler holds results in memory and
+    /// sends them to every client watching the operation, so a larger result
+    /// is rejected and the action completes with an error instead.
+    /// Note: Workers publish results to the action cache before reporting
+    /// them, so set `max_action_result_size` in their
+    /// `UploadActionResultConfig` to the same value.
+    ///
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_action_result_size: usize,
+
+    /// If set, platform properties advertised by a connecting worker that
+    /// the scheduler is not configured with are ignored and a warning is
+    /// logged. Otherwise the worker is rejected with an error listing every
+    /// unknown property, which catches misconfigured workers early.
+    ///
+    /// Default: false (workers advertising unknown properties are rejected)
+    #[serde(default)]
+    pub ignore_unknown_worker_properties: bool,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -186,9 +340,30 @@ pub struct AdminConfig {
     /// domain is "example.com", you can reach the endpoint with:
     /// <http://example.com/admin>.
     ///
+    /// Scheduler operations can be listed with a GET request to
+    /// `{path}/scheduler/{instance_name}/operations/{state}`, where `state`
+    /// is one of `cache_check`, `queued`, `executing` or `completed`. Each
+    /// listed operation has a `cursor`; appending it to the path as
+    /// `{path}/scheduler/{instance_name}/operations/{state}/{cursor}`
+    /// returns the next page.
+    ///
     /// Default: "/admin"
     #[serde(default)]
     pub path: String,
+
+    /// Token that authorizes purging entries from stores. If set, an entry
+    /// can be removed from the store named `store_name` with a POST request
+    /// to `{path}/store/{store_name}/purge/digest/{hash}/{size}`, and every
+    /// entry whose key starts with a prefix, usually part of a hash, with a
+    /// POST request to `{path}/store/{store_name}/purge/prefix/{prefix}`.
+    /// Both return the number of removed entries as `{"removed": N}`.
+    /// Requests must send the header `Authorization: Bearer {token}`.
+    /// Only stores that support removal can be purged, and a purge only
+    /// reaches the backends of the store, not the caches of other stores.
+    ///
+    /// Default: {Purging is disabled}
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub purge_auth_token: String,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -245,6 +420,21 @@ pub struct OriginEventsSpec {
     pub max_event_queue_size: usize,
 }
 
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcHealthConfig {
+    /// How often the health checks of the stores used by each service are
+    /// run. Value in seconds.
+    ///
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub check_interval: usize,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcReflectionConfig {}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct ServicesConfig {
@@ -268,6 +458,23 @@ pub struct ServicesConfig {
     /// place holder.
     pub execution: Option<HashMap<InstanceName, ExecutionConfig>>,
 
+    /// Maps the `instance_name` a client sends to the `instance_name` it
+    /// is served as. Requests to the `cas`, `ac`, `capabilities`,
+    /// `execution` and `bytestream` services for an alias are rewritten
+    /// before the store or scheduler is looked up, so they behave exactly
+    /// like requests for the aliased instance.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "instance_name_aliases": {
+    ///   "default": "prod-cas"
+    /// }
+    /// ```
+    ///
+    /// Default: {} (no aliases)
+    #[serde(default)]
+    pub instance_name_aliases: HashMap<InstanceName, InstanceName>,
+
     /// This is the service used to stream data to and from the CAS.
     /// Bazel's protocol strongly encourages users to use this streaming
     /// interface to interact with the CAS when the data is large.
@@ -297,6 +504,18 @@ pub struct ServicesConfig {
 
     /// This is the service for health status check.
     pub health: Option<HealthConfig>,
+
+    /// Experimental - Serves the standard gRPC health service
+    /// (`grpc.health.v1.Health`) next to the other gRPC services of this
+    /// server. Each service is reported as SERVING or NOT_SERVING based on
+    /// the health checks of the stores it uses.
+    pub experimental_grpc_health: Option<GrpcHealthConfig>,
+
+    /// Experimental - Serves the gRPC server reflection service
+    /// (`grpc.reflection.v1.ServerReflection` and its `v1alpha`
+    /// predecessor), which lets tools like `grpcurl` list the gRPC services
+    /// configured on this server and fetch their proto definitions.
+    pub experimental_grpc_reflection: Option<GrpcReflectionConfig>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -413,6 +632,29 @@ pub struct HttpListener {
     #[serde(default)]
     pub compression: HttpCompressionConfig,
 
+    /// Maximum size of a single gRPC message the CAS and Execution services
+    /// will decode, eg: a large `BatchUpdateBlobsRequest`. The ByteStream
+    /// service is configured with `ByteStreamConfig::max_decoding_message_size`.
+    ///
+    /// Default: 4 MiB (the tonic default)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_decoding_message_size: usize,
+
+    /// Maximum size of a single gRPC message the CAS and Execution services
+    /// will encode, eg: a large `BatchReadBlobsResponse`.
+    ///
+    /// Default: unlimited (the tonic default)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_encoding_message_size: usize,
+
+    /// Maximum number of connections a single client (identified by its IP
+    /// address) may have open to this listener. Additional connections are
+    /// closed as soon as they are accepted.
+    ///
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_connections_per_client: usize,
+
     /// Advanced Http server configuration.
     #[serde(default)]
     pub advanced_http: HttpServerConfig,
@@ -477,6 +719,13 @@ pub struct EndpointConfig {
     pub tls_config: Option<ClientTlsConfig>,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum OutputCompressor {
+    /// Upload outputs as `compressed-blobs/zstd` resources.
+    zstd,
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, Deserialize, Debug, Default)]
 pub enum UploadCacheResultsStrategy {
@@ -558,6 +807,15 @@ pub struct UploadActionResultConfig {
     #[serde(default)]
     pub upload_ac_results_strategy: UploadCacheResultsStrategy,
 
+    /// Results whose encoded `ExecuteResponse` is larger than this many
+    /// bytes are not published to the `ac_store`. This should match the
+    /// `max_action_result_size` of the scheduler's `WorkerApiConfig`, so a
+    /// result the scheduler rejects is never served from the action cache.
+    ///
+    /// Default: 0 (no limit)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_action_result_size: usize,
+
     /// Store to upload historical results to. This should be a CAS store if set.
     ///
     /// Default: {CAS store of parent}
@@ -572,6 +830,19 @@ pub struct UploadActionResultConfig {
     #[serde(default)]
     pub upload_historical_results_strategy: Option<UploadCacheResultsStrategy>,
 
+    /// Compression to apply to the outputs of actions, keyed by the instance
+    /// name of the action. Outputs of these actions are uploaded to the
+    /// `slow` store of `cas_fast_slow_store` as REAPI `compressed-blobs`
+    /// resources, which saves bandwidth when that store is remote. The
+    /// upstream decompresses them on ingest, so the CAS holds the same
+    /// content under the same digest as for uncompressed uploads.
+    /// Note: The `slow` store must be a `grpc` store whose upstream supports
+    /// the selected compressor.
+    ///
+    /// Default: {No compression}
+    #[serde(default)]
+    pub output_compression: HashMap<String, OutputCompressor>,
+
     /// Template to use for the `ExecuteResponse.message` property. This message
     /// is attached to the response before it is sent to the client. The following
     /// special variables are supported:
@@ -580,6 +851,8 @@ pub struct UploadActionResultConfig {
     /// - `action_digest_size`: Action digest size.
     /// - `historical_results_hash`: `HistoricalExecuteResponse` digest hash.
     /// - `historical_results_size`: `HistoricalExecuteResponse` digest size.
+    /// - `exit_reason`: Why the action finished, eg: that it timed out or
+    ///   the exit code of the command.
     ///
     /// A common use case of this is to provide a link to the web page that
     /// contains more useful information for the user.
@@ -613,6 +886,35 @@ pub struct LocalWorkerConfig {
     /// Endpoint which the worker will connect to the scheduler's `WorkerApiService`.
     pub worker_api_endpoint: EndpointConfig,
 
+    /// How often the worker sends a keep-alive to the scheduler. This must
+    /// be well below the scheduler's `worker_timeout_s`, otherwise the
+    /// scheduler removes the worker and re-queues its actions. Value in
+    /// seconds.
+    ///
+    /// Default: 0 (half of `worker_api_endpoint.timeout`)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub keep_alive_interval: usize,
+
+    /// If the worker receives nothing from the scheduler for this long, it
+    /// assumes the connection is dead and reconnects. This should be set together with `keep_alive_interval`
+    /// in the scheduler's `worker_api` config, so idle connections still
+    /// receive messages. Value in seconds.
+    ///
+    /// Default: 0 (disabled)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub scheduler_keep_alive_timeout: usize,
+
+    /// Backoff used to reconnect to the scheduler when the connection could
+    /// not be made or was lost. The delay doubles after every failed attempt
+    /// until it has doubled `max_retries` times, and is reset once the worker
+    /// is registered with the scheduler again. The worker never stops trying
+    /// to reconnect. Running actions are not killed while the worker is
+    /// disconnected, their results are sent once it has reconnected.
+    ///
+    /// Default: A delay of 0.5 seconds, without backoff or jitter.
+    #[serde(default)]
+    pub connection_retry: Retry,
+
     /// The maximum time an action is allowed to run. If a task requests for a timeout
     /// longer than this time limit, the task will be rejected. Value in seconds.
     ///
@@ -640,6 +942,54 @@ pub struct LocalWorkerConfig {
     #[serde(default)]
     pub timeout_handled_externally: bool,
 
+    /// Maximum number of actions this worker will run at the same time.
+    /// Once reached, new actions are rejected with `ResourceExhausted`
+    /// unless `queue_actions_over_limit` is set.
+    ///
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_actions: usize,
+
+    /// If set, actions received while `max_concurrent_actions` actions are
+    /// running wait for one of them to finish instead of being rejected.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub queue_actions_over_limit: bool,
+
+    /// Entries in `work_directory` that do not belong to a running action
+    /// and have not been modified for this long are removed. They are left
+    /// behind when the worker crashes before an action is cleaned up. This
+    /// is also how often the `work_directory` is checked. Value in seconds.
+    ///
+    /// Default: 0 (only checked on startup)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub stale_action_directory_timeout: usize,
+
+    /// If a running action has not written to stdout or stderr for this
+    /// long, a warning is logged and the action is counted as stuck in the
+    /// `stuck_actions` metric until it writes output again or finishes.
+    /// This only helps finding hung actions, they are not killed. Value in
+    /// seconds.
+    ///
+    /// Default: 0 (disabled)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub stuck_action_warning_threshold: usize,
+
+    /// If the filesystem of `work_directory` has less free space than this,
+    /// the worker asks the scheduler to stop sending it new actions, instead
+    /// of failing late while their outputs are written. Actions that still
+    /// arrive are rejected with `ResourceExhausted` so the scheduler gives
+    /// them to another worker. The free space is checked every second and
+    /// the worker asks for actions again once enough space is freed, for
+    /// example by the filesystem store evicting entries. Since
+    /// the filesystem store's `content_path` must be on the same filesystem,
+    /// this covers it too.
+    ///
+    /// Default: 0 (disabled)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub min_free_disk_space: u64,
+
     /// The command to execute on every execution request. This will be parsed as
     /// a command + arguments (not shell).
     /// Example: "run.sh" and a job with command: "sleep 5" will result in a
@@ -679,17 +1029,112 @@ pub struct LocalWorkerConfig {
     #[serde(deserialize_with = "convert_string_with_shellexpand")]
     pub work_directory: String,
 
+    /// If set, input files are also hardlinked into this directory under
+    /// their digest when actions are prepared. Later actions link inputs
+    /// found here directly, as long as the filesystem store still has their
+    /// digest, which also keeps the store entry from being evicted. Files
+    /// whose digest is no longer in the filesystem store are fetched again
+    /// and are removed on startup and every `input_staging_prune_interval`.
+    /// Like `work_directory`, it must be on the same filesystem as the
+    /// filesystem store's `content_path`.
+    ///
+    /// Default: {No staging directory}
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub input_staging_directory: String,
+
+    /// How often files of evicted digests are removed from
+    /// `input_staging_directory`. Staged files are hardlinks, so the disk
+    /// space of an evicted input is only freed once it is removed from the
+    /// staging directory too. Value in seconds.
+    ///
+    /// Default: 300 (5 minutes)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub input_staging_prune_interval: usize,
+
     /// Properties of this worker. This configuration will be sent to the scheduler
     /// and used to tell the scheduler to restrict what should be executed on this
     /// worker.
     pub platform_properties: HashMap<String, WorkerProperty>,
 
+    /// If set, the worker detects the number of CPUs available to it and the
+    /// memory of the host and advertises them as the `cpu_count` and
+    /// `memory_kb` platform properties. Configure these properties as
+    /// `minimum` in the scheduler so actions are only matched to workers
+    /// with enough resources left. On Linux the memory is capped by the
+    /// cgroup v2 memory limit of the worker, if one is set. A property of
+    /// the same name in `platform_properties` overrides the detected value.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub advertise_host_resources: bool,
+
+    /// If set, every action runs in its own cgroup v2 created below this
+    /// directory, eg: "/sys/fs/cgroup/nativelink". The action is limited to
+    /// the number of CPUs and the memory it requested in its `cpu_count` and
+    /// `memory_kb` platform properties, see `advertise_host_resources`.
+    /// Actions that use more memory are killed and fail with
+    /// `ResourceExhausted`. The cgroup is removed, together with any process
+    /// left in it, when the action is cleaned up.
+    /// The directory must exist, be writable by the worker and have the
+    /// `cpu` and `memory` controllers enabled in its `cgroup.subtree_control`.
+    /// Only supported on Linux.
+    ///
+    /// Default: {No cgroup confinement}
+    #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
+    pub action_cgroup_directory: String,
+
+    /// The stdout and stderr of an action that are at most this many bytes
+    /// are inlined in its `ActionResult` instead of being uploaded to the
+    /// CAS. This saves a round trip to the CAS for commands with little
+    /// output. Larger outputs are uploaded and referenced by digest.
+    /// Inlined outputs are not in the CAS, so clients that only read them
+    /// by digest can not get them.
+    ///
+    /// Default: 0 (stdout and stderr are always uploaded to the CAS)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_inline_output_bytes: usize,
+
     /// An optional mapping of environment names to set for the execution
     /// as well as those specified in the action itself.  If set, will set each
     /// key as an environment variable before executing the job with the value
     /// of the environment variable being the value of the property of the
     /// action being executed of that name or the fixed value.
     pub additional_environment: Option<HashMap<String, EnvironmentSource>>,
+
+    /// If set, symlinks in an action's input root may point to absolute
+    /// paths. Relative symlink targets must always stay inside the action's
+    /// work directory.
+    ///
+    /// Default: false (absolute symlink targets are rejected)
+    #[serde(default)]
+    pub allow_absolute_symlink_targets: bool,
+
+    /// If set, the worker checks that every file in an action's input root
+    /// exists in the CAS with a single existence check before downloading
+    /// any of them. Actions with missing inputs then fail right away with an
+    /// error listing every missing digest, instead of failing part way
+    /// through building the work directory.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub check_inputs_exist_before_download: bool,
+
+    /// Maximum number of directory levels below an output directory that
+    /// the worker will upload. Actions producing deeper output directories
+    /// fail with a `ResourceExhausted` error instead of uploading them.
+    ///
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_tree_depth: usize,
+
+    /// Maximum number of directories in a single output directory tree
+    /// that the worker will upload, including the output directory itself.
+    /// Actions producing larger trees fail with a `ResourceExhausted` error
+    /// instead of uploading them.
+    ///
+    /// Default: 0 (unlimited)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_tree_nodes: usize,
 }
 
 #[allow(non_camel_case_types)]
@@ -730,6 +1175,19 @@ pub struct GlobalConfig {
     #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
     pub idle_file_descriptor_timeout_millis: u64,
 
+    /// Maximum number of requests all S3 stores may make to S3 at the same
+    /// time. Requests over this limit wait until another request finishes.
+    /// Downloads only count until the response headers arrived, the body
+    /// is read without holding back other requests.
+    /// The number of requests in flight and the time spent waiting are
+    /// published in the metrics of each S3 store. Independent of this limit,
+    /// every connection to S3 also counts against `max_open_files`.
+    /// A value of zero is treated as unlimited.
+    ///
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_s3_requests: usize,
+
     /// This flag can be used to prevent metrics from being collected at runtime.
     /// Metrics are still able to be collected, but this flag prevents metrics that
     /// are collected at runtime (performance metrics) from being tallied. The
@@ -757,6 +1215,15 @@ pub struct GlobalConfig {
     /// Default: 1024*1024 (1MiB)
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub default_digest_size_health_check: usize,
+
+    /// When the process receives SIGTERM, the CAS and `ByteStream` services
+    /// stop accepting new uploads and wait up to this long for uploads that
+    /// are already in flight to finish before the process exits. Uploads
+    /// that are still running after this time are aborted.
+    ///
+    /// Default: 30 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub shutdown_write_drain_timeout: usize,
 }
 
 #[derive(Deserialize, Debug)]
@@ -45,10 +45,9 @@ pub enum PropertyType {
     exact,
 
     /// Does not restrict on this value and instead will be passed to the worker
-    /// as an informational piece.
-    /// TODO(allada) In the future this will be used by the scheduler and worker
-    /// to cause the scheduler to prefer certain workers over others, but not
-    /// restrict them based on these values.
+    /// as an informational piece. When multiple workers are able to run the
+    /// task, the scheduler will prefer the workers that have the most of these
+    /// properties set to the exact same value as the task.
     priority,
 }
 
@@ -122,10 +121,45 @@ pub struct SimpleSpec {
     #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
     pub max_job_retries: usize,
 
+    /// The maximum number of actions that may be waiting in the queue for a
+    /// worker. Once reached, new actions are rejected with `ResourceExhausted`
+    /// so clients can back off and retry later. Actions that are already
+    /// executing or being checked against the cache do not count against
+    /// this limit, and actions that join an existing action are always
+    /// accepted. A value of zero is treated as unlimited.
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_queued_actions: usize,
+
     /// The strategy used to assign workers jobs.
     #[serde(default)]
     pub allocation_strategy: WorkerAllocationStrategy,
 
+    /// If set, when a queued job cannot be matched to any worker the
+    /// scheduler will look for a running job with a strictly lower priority
+    /// whose resources would allow the queued job to run. That running job
+    /// is killed on the worker and put back in the queue. The queued job is
+    /// only assigned to that worker once it has confirmed the kill, or to
+    /// another worker if it is evicted first. Preempted jobs do not count
+    /// against `max_job_retries`.
+    /// Default: false
+    #[serde(default)]
+    pub preempt_lower_priority_actions: bool,
+
+    /// If set, the scheduler periodically touches the action, command and
+    /// input tree of every executing action and the outputs of every
+    /// completed action in this CAS store, so stores with LRU eviction keep
+    /// them while workers or clients may still fetch them.
+    /// Default: None
+    #[serde(default)]
+    pub touch_running_action_inputs_store: Option<StoreRefName>,
+
+    /// How often the inputs of executing actions are touched when
+    /// `touch_running_action_inputs_store` is set.
+    /// Default: 60 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub touch_running_action_inputs_interval_s: u64,
+
     /// The storage backend to use for the scheduler.
     /// Default: memory
     pub experimental_backend: Option<ExperimentalSimpleSchedulerBackend>,
@@ -172,6 +206,20 @@ pub struct GrpcSpec {
     /// the load over multiple TCP connections.  Default 1.
     #[serde(default)]
     pub connections_per_endpoint: usize,
+
+    /// The maximum number of instance names to cache the supported platform
+    /// properties of.  When full, the least recently used instance name is
+    /// evicted and its properties are fetched from the upstream again the
+    /// next time they are needed.
+    /// Default: 1000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_cached_instance_names: u64,
+
+    /// How long the supported platform properties of an instance name are
+    /// cached before they are fetched from the upstream again.
+    /// Default: 600 (seconds)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub supported_properties_ttl_s: u64,
 }
 
 #[derive(Deserialize, Debug)]
@@ -16,8 +16,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::serde_utils::{
     convert_data_size_with_shellexpand, convert_duration_with_shellexpand,
-    convert_numeric_with_shellexpand, convert_optional_string_with_shellexpand,
-    convert_string_with_shellexpand, convert_vec_string_with_shellexpand,
+    convert_numeric_with_shellexpand, convert_optional_numeric_with_shellexpand,
+    convert_optional_string_with_shellexpand, convert_string_with_shellexpand,
+    convert_vec_string_with_shellexpand,
 };
 
 /// Name of the store. This type will be used when referencing a store
@@ -34,6 +35,10 @@ pub enum ConfigDigestHashFunction {
     /// Use the blake3 hash function.
     /// <https://en.wikipedia.org/wiki/BLAKE_(hash_function)>
     blake3,
+
+    /// Use the sha512 hash function.
+    /// <https://en.wikipedia.org/wiki/SHA-2>
+    sha512,
 }
 
 #[allow(non_camel_case_types)]
@@ -130,6 +135,29 @@ pub enum StoreSpec {
     ///
     completeness_checking(Box<CompletenessCheckingSpec>),
 
+    /// Action result TTL store stamps every action result written through it
+    /// with the time it was stored and treats action results older than
+    /// `ttl_s` as missing. This reduces the chance of returning action
+    /// results whose outputs were already evicted from the CAS.
+    /// Note: This store should only be used on AC stores, and entries must
+    /// always be written through it since it changes the stored format.
+    /// Entries written to the backend before this store was added are read
+    /// as-is and never expire.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "action_result_ttl": {
+    ///     "backend": {
+    ///       "ref_store": {
+    ///         "name": "AC_MAIN_STORE"
+    ///       }
+    ///     },
+    ///     "ttl_s": 604800, // 7 days.
+    ///   }
+    /// ```
+    ///
+    action_result_ttl(Box<ActionResultTtlSpec>),
+
     /// A compression store that will compress the data inbound and
     /// outbound. There will be a non-trivial cost to compress and
     /// decompress the data, but in many cases if the final store is
@@ -223,6 +251,45 @@ pub enum StoreSpec {
     ///
     dedup(Box<DedupSpec>),
 
+    /// A chunked store splits blobs larger than `chunk_size` into
+    /// multiple objects in the `backend` store and writes a small
+    /// manifest listing those objects under the original key. Reads
+    /// load the manifest and only fetch the chunks that cover the
+    /// requested offset and length.
+    ///
+    /// This is useful for backends that limit the size of a single
+    /// object. Unlike `DedupSpec` the chunks are cut at fixed offsets,
+    /// so no content is shared between entries.
+    ///
+    /// Digest keys whose size is at most `chunk_size` are forwarded to
+    /// the `backend` unchanged. Every other key is stored as a manifest.
+    ///
+    /// Overwriting an existing key removes the chunks of the old entry
+    /// from the `backend` once the new manifest is written. Readers still
+    /// fetching the old chunks fail instead of mixing both entries.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "chunked": {
+    ///   "chunk_size": "4gb",
+    ///   "backend": {
+    ///     "experimental_s3_store": {
+    ///       "region": "eu-north-1",
+    ///       "bucket": "crossplane-bucket-af79aeca9",
+    ///       "key_prefix": "test-prefix-index/",
+    ///       "retry": {
+    ///         "max_retries": 6,
+    ///         "delay": 0.3,
+    ///         "jitter": 0.5
+    ///       },
+    ///       "multipart_max_concurrent_uploads": 10
+    ///     }
+    ///   }
+    /// }
+    /// ```
+    ///
+    chunked(Box<ChunkedSpec>),
+
     /// Existence store will wrap around another store and cache calls
     /// to has so that subsequent `has_with_results` calls will be
     /// faster. This is useful for cases when you have a store that
@@ -250,6 +317,163 @@ pub enum StoreSpec {
     ///
     existence_cache(Box<ExistenceCacheSpec>),
 
+    /// Singleflight store will wrap around another store and coalesce
+    /// concurrent reads of the same range of the same object into a single
+    /// read of the backend. The data is fanned out to all readers once the
+    /// backend read completes. This is useful in front of slow stores, like
+    /// `FastSlowSpec` or `GrpcSpec`, when many clients fetch the same cold
+    /// object at the same time.
+    /// Note: Coalesced reads are buffered in memory, use `max_size` to bound
+    /// the size of reads that are coalesced.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "singleflight": {
+    ///     "backend": {
+    ///       "grpc": {
+    ///         "instance_name": "main",
+    ///         "endpoints": [
+    ///           {"address": "grpc://${CAS_ENDPOINT:-127.0.0.1}:50051"}
+    ///         ],
+    ///         "store_type": "cas"
+    ///       }
+    ///     },
+    ///     "max_size": 67108864, // 64mib.
+    ///   }
+    /// ```
+    ///
+    singleflight(Box<SingleflightSpec>),
+
+    /// Read cache store will wrap around another store and keep recently
+    /// read blobs in memory, so repeated reads of the same blob do not reach
+    /// the backend. Unlike `FastSlowSpec`, writes are passed straight through
+    /// to the backend and are not cached. This is useful as a small hot
+    /// cache in front of slow stores, like `S3Spec`.
+    /// Note: This store should only be used on CAS stores.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "read_cache": {
+    ///     "backend": {
+    ///       "ref_store": {
+    ///         "name": "S3_STORE"
+    ///       }
+    ///     },
+    ///     "eviction_policy": {
+    ///       // 500mb.
+    ///       "max_bytes": 500000000,
+    ///     },
+    ///     "max_size": 1048576, // 1mib.
+    ///   }
+    /// ```
+    ///
+    read_cache(Box<ReadCacheSpec>),
+
+    /// Timeout store will wrap around another store and fail `has`,
+    /// `get_part` and `update` calls that make no progress for `timeout_ms`
+    /// with `DeadlineExceeded`, so a hung connection to a backend like
+    /// `S3Spec` or `GrpcSpec` does not block a client forever.
+    /// Note: For `get_part` and `update` the timeout restarts every time a
+    /// chunk of data is transferred, so large blobs that keep streaming are
+    /// never cut off.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "timeout": {
+    ///     "backend": {
+    ///       "ref_store": {
+    ///         "name": "S3_STORE"
+    ///       }
+    ///     },
+    ///     "timeout_ms": 300000, // 5 minutes.
+    ///   }
+    /// ```
+    ///
+    timeout(Box<TimeoutSpec>),
+
+    /// Rate limit store will wrap around another store and cap how many
+    /// `has`, `get_part` and `update` calls per second reach the backend
+    /// using a token bucket. This is useful to protect fragile or metered
+    /// backends, like a third party object store. Calls over the limit wait
+    /// for up to `max_wait_ms` and then fail with `ResourceExhausted`.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "rate_limit": {
+    ///     "backend": {
+    ///       "ref_store": {
+    ///         "name": "S3_STORE"
+    ///       }
+    ///     },
+    ///     "requests_per_second": 100,
+    ///     "max_burst": 200,
+    ///     "max_wait_ms": 5000, // 5 seconds.
+    ///   }
+    /// ```
+    ///
+    rate_limit(Box<RateLimitSpec>),
+
+    /// Fault injection store wraps another store and makes its operations
+    /// fail with `Unavailable`, take longer or return truncated data. This
+    /// is useful to test how retries and fallbacks behave when a backend is
+    /// unreliable.
+    /// Note: This store is only available if nativelink is built with the
+    /// `fault_injection` feature, so it can not be enabled in production
+    /// builds by accident.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "fault_injection": {
+    ///     "backend": {
+    ///       "memory": {}
+    ///     },
+    ///     "error_rate": 0.1,
+    ///     "truncated_read_rate": 0.05,
+    ///     "min_latency_ms": 10,
+    ///     "max_latency_ms": 200,
+    ///     "seed": 42
+    ///   }
+    /// ```
+    ///
+    fault_injection(Box<FaultInjectionSpec>),
+
+    /// Async mirror store writes to the `primary` store and then copies the
+    /// written blob to the `secondary` store in the background, without
+    /// making the client wait for the copy. This is useful to keep a backup
+    /// of a CAS in another region or bucket for disaster recovery.
+    /// Reads, existence checks and removals only go to the `primary` store,
+    /// unless `read_fallback_to_secondary` is set.
+    /// Note: Copies are best effort. If the queue of pending copies is full
+    /// or a copy fails, the blob is not retried and only counted in the
+    /// metrics.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "async_mirror": {
+    ///     "primary": {
+    ///       "ref_store": {
+    ///         "name": "CAS_MAIN_STORE"
+    ///       }
+    ///     },
+    ///     "secondary": {
+    ///       "experimental_s3_store": {
+    ///         "region": "eu-north-1",
+    ///         "bucket": "backup-bucket",
+    ///         "key_prefix": "cas/",
+    ///         "retry": {
+    ///           "max_retries": 6,
+    ///           "delay": 0.3,
+    ///           "jitter": 0.5
+    ///         }
+    ///       }
+    ///     },
+    ///     "max_pending_mirrors": 10000,
+    ///     "max_concurrent_mirrors": 20
+    ///   }
+    /// ```
+    ///
+    async_mirror(Box<AsyncMirrorSpec>),
+
     /// `FastSlow` store will first try to fetch the data from the `fast`
     /// store and then if it does not exist try the `slow` store.
     /// When the object does exist in the `slow` store, it will copy
@@ -258,12 +482,14 @@ pub enum StoreSpec {
     /// the data to the `fast` store.
     /// On uploads it will mirror data to both `fast` and `slow` stores.
     ///
-    /// WARNING: If you need data to always exist in the `slow` store
-    /// for something like remote execution, be careful because this
-    /// store will never check to see if the objects exist in the
-    /// `slow` store if it exists in the `fast` store (ie: it assumes
-    /// that if an object exists `fast` store it will exist in `slow`
-    /// store).
+    /// `has` only reports objects that exist in the `slow` store, so an
+    /// object that only exists in the `fast` store is reported missing
+    /// and clients upload it again. The exception is a `noop` `slow`
+    /// store, in which case only the `fast` store is checked.
+    ///
+    /// WARNING: Reads are served from the `fast` store without checking
+    /// the `slow` store (ie: they assume that if an object exists in the
+    /// `fast` store it will exist in the `slow` store).
     ///
     /// ***Example JSON Config:***
     /// ```json
@@ -357,7 +583,8 @@ pub enum StoreSpec {
     /// used if the size field is the real size of the content, in other
     /// words, don't use on AC (Action Cache) stores. Any store where you can
     /// safely use `VerifySpec.verify_size = true`, this store should be safe
-    /// to use (ie: CAS stores).
+    /// to use (ie: CAS stores). Setting `predicate` to `hash_prefix` routes
+    /// on the hash instead, which does not depend on the size field.
     ///
     /// **Example JSON Config:**
     /// ```json
@@ -457,10 +684,15 @@ pub struct ShardSpec {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct SizePartitioningSpec {
-    /// Size to partition the data on.
-    #[serde(deserialize_with = "convert_data_size_with_shellexpand")]
+    /// Size to partition the data on. Only used by the `size` predicate.
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub size: u64,
 
+    /// How to choose between `lower_store` and `upper_store` for a digest.
+    /// Default: size
+    #[serde(default)]
+    pub predicate: PartitionPredicate,
+
     /// Store to send data when object is < (less than) size.
     pub lower_store: StoreSpec,
 
@@ -468,6 +700,39 @@ pub struct SizePartitioningSpec {
     pub upper_store: StoreSpec,
 }
 
+/// Decides which sub-store of a `SizePartitioningSpec` a digest is routed to.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub enum PartitionPredicate {
+    /// Objects smaller than `size` go to `lower_store`, everything else
+    /// goes to `upper_store`.
+    #[default]
+    size,
+
+    /// Objects whose hash starts with a prefix inside any of the listed
+    /// ranges go to `upper_store`, everything else goes to `lower_store`.
+    /// This is useful for tiering a fixed slice of the key space.
+    ///
+    /// **Example JSON Config:**
+    /// ```json
+    /// "predicate": {
+    ///   "hash_prefix": [{"start": "00", "end": "3f"}]
+    /// }
+    /// ```
+    hash_prefix(Vec<HashPrefixRange>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HashPrefixRange {
+    /// Lowest hex prefix (inclusive) of the range.
+    pub start: String,
+
+    /// Highest hex prefix (inclusive) of the range. A hash matches if its
+    /// first `end.len()` hex characters are less than or equal to `end`.
+    pub end: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct RefSpec {
@@ -501,6 +766,15 @@ pub struct FilesystemSpec {
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub read_buffer_size: u32,
 
+    /// If set, reads advise the kernel (via `posix_fadvise`) that the file
+    /// will be read sequentially, so it reads ahead more aggressively. Range
+    /// reads only advise the requested window. This helps throughput of
+    /// large reads from spinning disks. Has no effect on platforms other
+    /// than Linux.
+    /// Default: false
+    #[serde(default)]
+    pub sequential_read_advice: bool,
+
     /// Policy used to evict items out of the store. Failure to set this
     /// value will cause items to never be removed from the store causing
     /// infinite memory usage.
@@ -512,6 +786,54 @@ pub struct FilesystemSpec {
     /// Default: 4096
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub block_size: u64,
+
+    /// If set, files are synced to disk before they are moved into
+    /// `content_path` and the directory they are moved into is synced after
+    /// the move, so stored content survives a power loss. This makes every
+    /// upload slower.
+    /// Default: false
+    #[serde(default)]
+    pub sync_renames: bool,
+
+    /// Number of levels of subdirectories digest files are sharded into
+    /// below `content_path`, named after the next two hex chars of the hash.
+    /// Eg: with a depth of 2 the digest `abcd...-5` is stored in
+    /// `d/ab/cd/abcd...-5`. This keeps the number of files per directory
+    /// low on filesystems that slow down with large directories.
+    /// Files already on disk are moved into the configured layout on
+    /// startup, so this value can be changed at any time. The maximum is 4.
+    /// Default: 0 (all files are stored in a single directory)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub content_shard_depth: usize,
+
+    /// If set, reads of a whole digest entry hash the content while it is
+    /// streamed out and fail with `DataLoss` if it does not match the
+    /// digest, which catches files corrupted on disk. Reads with an offset
+    /// or a length shorter than the entry can not be verified and are
+    /// served unchecked, as are string keys. The digest function of the
+    /// request is used, like in `VerifySpec`.
+    /// Note: The corrupt data has already been sent when the mismatch is
+    /// detected, the error only prevents the reader from accepting it.
+    /// Default: false
+    #[serde(default)]
+    pub verify_on_read: bool,
+
+    /// If set together with `verify_on_read`, an entry that fails
+    /// verification is removed from the store, so the next request fetches
+    /// it again from wherever it came from.
+    /// Default: false
+    #[serde(default)]
+    pub evict_on_verify_failure: bool,
+
+    /// Ranges of a digest written at an offset are kept in `temp_path`
+    /// until every range of the digest was written. If no range of a digest
+    /// was written for this many seconds, the upload is considered abandoned
+    /// and the ranges written so far are deleted.
+    /// Note: None of the services write ranges at an offset yet, ByteStream
+    /// writes still have to start at offset zero of a blob.
+    /// Default: 3600 (1 hour)
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub partial_upload_timeout_s: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -524,6 +846,27 @@ pub struct FastSlowSpec {
     /// If the object does not exist in the `fast` store it will try to
     /// get it from this store.
     pub slow: StoreSpec,
+
+    /// Maximum number of objects that may be copied from the `slow` store
+    /// into the `fast` store at the same time while serving reads. Reads that
+    /// miss the `fast` store while this limit is reached are streamed directly
+    /// from the `slow` store without populating the `fast` store. Explicit
+    /// population requests (eg: from workers) wait for the limit instead.
+    /// A value of zero is treated as unlimited.
+    ///
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_populations: usize,
+
+    /// Blobs larger than this many bytes are only written to the `slow`
+    /// store, so large blobs that are unlikely to be read again soon do not
+    /// take up space in the `fast` store. Reads of such blobs still populate
+    /// the `fast` store. Only applies to uploads of a known size.
+    /// A value of zero is treated as unlimited.
+    ///
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub fast_store_max_write_size: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -533,6 +876,35 @@ pub struct MemorySpec {
     /// value will cause items to never be removed from the store causing
     /// infinite memory usage.
     pub eviction_policy: Option<EvictionPolicy>,
+
+    /// Blobs of this size or larger are written to `spill_store` instead
+    /// of being held in memory. Zero disables spilling.
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub spill_threshold: u64,
+
+    /// Filesystem store that blobs of at least `spill_threshold` bytes are
+    /// written to. Reads and existence checks fall back to this store, so
+    /// where a blob lives is transparent to clients.
+    /// Default: None (all blobs are held in memory)
+    #[serde(default)]
+    pub spill_store: Option<FilesystemSpec>,
+}
+
+/// Algorithm used by `DedupStore` to decide where to split content into
+/// chunks.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DedupChunkingAlgorithm {
+    /// `FastCDC` with normalized chunking. Chunk sizes stay close to
+    /// `normal_size`.
+    #[default]
+    fastcdc,
+
+    /// A plain Gear rolling hash with a single mask. Cheaper per byte than
+    /// `fastcdc`, but chunk sizes are spread more evenly between
+    /// `min_size` and `max_size`.
+    gear,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -588,6 +960,36 @@ pub struct DedupSpec {
     /// Default: 10
     #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
     pub max_concurrent_fetch_per_get: u32,
+
+    /// Algorithm used to find chunk boundaries. The index format does not
+    /// depend on this setting, so entries written with one algorithm can
+    /// still be read after switching to another, but content will not
+    /// dedup against chunks produced by the previous algorithm.
+    ///
+    /// Default: fastcdc
+    #[serde(default)]
+    pub chunking_algorithm: DedupChunkingAlgorithm,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkedSpec {
+    /// The store that holds the manifests and the individual chunks.
+    pub backend: StoreSpec,
+
+    /// Maximum size of a single chunk written to the `backend`.
+    ///
+    /// Default: 67108864 (64mb)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub chunk_size: u64,
+
+    /// Maximum number of chunks fetched at the same time for a single
+    /// `get()` request. Chunks are streamed, so only a small buffer per
+    /// fetch is held in memory while the chunks before it are sent.
+    ///
+    /// Default: 4
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_fetch_per_get: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -606,6 +1008,160 @@ pub struct ExistenceCacheSpec {
     pub eviction_policy: Option<EvictionPolicy>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SingleflightSpec {
+    /// The underlying store to coalesce reads of. Writes and existence
+    /// checks are passed through unchanged.
+    pub backend: StoreSpec,
+
+    /// Reads of more than this many bytes are passed straight through to
+    /// the backend instead of being coalesced, since coalesced reads are
+    /// buffered in memory. Reads with a string key and no `length` are never
+    /// coalesced, as their size is not known up front.
+    ///
+    /// Default: 16777216 (16mib)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReadCacheSpec {
+    /// The underlying store to cache reads of. Writes and existence checks
+    /// are passed through unchanged.
+    pub backend: StoreSpec,
+
+    /// Policy used to evict blobs out of the cache.
+    ///
+    /// Default: {"max_bytes": 268435456} (256mib)
+    pub eviction_policy: Option<EvictionPolicy>,
+
+    /// Blobs larger than this many bytes are streamed straight from the
+    /// backend and never cached. Reads of cached blobs, including ranged
+    /// reads, fetch the whole blob, so this bounds how much is read for
+    /// a single request.
+    ///
+    /// Default: 1048576 (1mib)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutSpec {
+    /// The underlying store whose operations are bounded.
+    pub backend: StoreSpec,
+
+    /// The amount of time in milliseconds a `has` call may take, or a
+    /// `get_part` or `update` call may go without transferring data, before
+    /// it fails with `DeadlineExceeded`.
+    ///
+    /// Default: 60000 (60 seconds)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub timeout_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitSpec {
+    /// The underlying store whose operations are rate limited.
+    pub backend: StoreSpec,
+
+    /// The number of `has`, `get_part` and `update` calls per second that
+    /// are allowed to reach the backend over time. Must be greater than 0.
+    #[serde(deserialize_with = "convert_numeric_with_shellexpand")]
+    pub requests_per_second: u64,
+
+    /// The number of calls that may reach the backend at once after the
+    /// store has been idle.
+    ///
+    /// Default: `requests_per_second`
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_burst: u64,
+
+    /// The amount of time in milliseconds a call over the limit may wait
+    /// for its turn. Calls that would have to wait longer fail with
+    /// `ResourceExhausted` instead.
+    ///
+    /// Default: 0 (calls over the limit fail immediately)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_wait_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AsyncMirrorSpec {
+    /// The store that writes complete in before returning to the client and
+    /// that all reads are served from.
+    pub primary: StoreSpec,
+
+    /// The store that written blobs are copied to in the background.
+    pub secondary: StoreSpec,
+
+    /// The maximum number of blobs waiting to be copied to the `secondary`
+    /// store. Once reached, newly written blobs are not copied.
+    ///
+    /// Default: 1000
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_pending_mirrors: usize,
+
+    /// The maximum number of blobs copied to the `secondary` store at the
+    /// same time.
+    ///
+    /// Default: 10
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_mirrors: usize,
+
+    /// If set, reads and existence checks of blobs that are not in the
+    /// `primary` store are tried on the `secondary` store.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub read_fallback_to_secondary: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FaultInjectionSpec {
+    /// The underlying store to inject faults into.
+    pub backend: StoreSpec,
+
+    /// The fraction of operations, between 0.0 and 1.0, that fail with
+    /// `Unavailable` without reaching the backend.
+    ///
+    /// Default: 0.0
+    #[serde(default)]
+    pub error_rate: f64,
+
+    /// The fraction of reads, between 0.0 and 1.0, that return only part of
+    /// the data before failing with `Unavailable`.
+    ///
+    /// Default: 0.0
+    #[serde(default)]
+    pub truncated_read_rate: f64,
+
+    /// The minimum latency added to every operation, in milliseconds.
+    ///
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub min_latency_ms: u64,
+
+    /// The maximum latency added to every operation, in milliseconds. The
+    /// added latency is picked uniformly between `min_latency_ms` and this.
+    ///
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_latency_ms: u64,
+
+    /// Seed for the random number generator that decides which operations
+    /// fail, so a run can be reproduced.
+    ///
+    /// Default: None (a random seed)
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct VerifySpec {
@@ -625,7 +1181,9 @@ pub struct VerifySpec {
 
     /// If the data should be hashed and verify that the key matches the
     /// computed hash. The hash function is automatically determined based
-    /// request and if not set will use the global default.
+    /// request and if not set will use the global default. The data is
+    /// hashed while it is streamed to the `backend` and the upload to the
+    /// `backend` is aborted if the hash does not match.
     ///
     /// This should be set to None for AC, but hashing function like `sha256` for CAS stores.
     #[serde(default)]
@@ -641,6 +1199,28 @@ pub struct CompletenessCheckingSpec {
     /// When a request is made, the results are decoded and all output digests/files are verified
     /// to exist in this CAS store before returning success.
     pub cas_store: StoreSpec,
+
+    /// Percentage of the output files of each action result that are verified
+    /// to exist in `cas_store`, chosen at random. Lower values make requests
+    /// for actions with many outputs cheaper, at the risk of returning an
+    /// action result with some of its outputs missing. Values of 0 or 100
+    /// verify every output.
+    /// Default: 100
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub verify_outputs_sample_percent: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ActionResultTtlSpec {
+    /// The underlying AC store that stamped action results are stored in.
+    pub backend: StoreSpec,
+
+    /// Action results stored more than this many seconds ago are treated
+    /// as if they did not exist. Zero means action results never expire.
+    /// Default: 0
+    #[serde(default, deserialize_with = "convert_duration_with_shellexpand")]
+    pub ttl_s: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone, Copy)]
@@ -708,7 +1288,9 @@ pub struct EvictionPolicy {
     /// When eviction starts based on hitting `max_bytes`, continue until
     /// `max_bytes - evict_bytes` is met to create a low watermark.  This stops
     /// operations from thrashing when the store is close to the limit.
-    /// Default: 0
+    /// Filesystem and memory stores use 10% of `max_bytes` if this is zero,
+    /// set it to 1 to evict only as much as needed.
+    /// Default: 0 (10% of `max_bytes` for filesystem and memory stores)
     #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
     pub evict_bytes: usize,
 
@@ -728,6 +1310,11 @@ pub struct EvictionPolicy {
 #[serde(deny_unknown_fields)]
 pub struct S3Spec {
     /// S3 region. Usually us-east-1, us-west-2, af-south-1, exc...
+    /// If empty, the region the bucket is in is detected on startup, falling
+    /// back to the region from the environment (eg: `AWS_REGION`, the AWS
+    /// profile or instance metadata).
+    ///
+    /// Default: "" (detect the region)
     #[serde(default, deserialize_with = "convert_string_with_shellexpand")]
     pub region: String,
 
@@ -770,6 +1357,16 @@ pub struct S3Spec {
     /// Default: 10.
     pub multipart_max_concurrent_uploads: Option<usize>,
 
+    /// Size of the parts uploads are split into when using a
+    /// `MultipartUpload`. Uploads of a known size smaller than this are sent
+    /// in a single `PutObject` request instead, uploads of an unknown size
+    /// always use a `MultipartUpload`. Values are clamped to the 5MB to 5GB
+    /// S3 allows and parts are grown as needed to stay within the 10,000
+    /// parts limit.
+    ///
+    /// Default: 5MB.
+    pub multipart_part_size: Option<u64>,
+
     /// Allow unencrypted HTTP connections. Only use this for local testing.
     ///
     /// Default: false
@@ -785,6 +1382,60 @@ pub struct S3Spec {
     /// Default: false
     #[serde(default)]
     pub disable_http2: bool,
+
+    /// Server side encryption S3 applies to uploaded objects.
+    ///
+    /// Default: None (the bucket's default encryption is used)
+    #[serde(default)]
+    pub sse: Option<S3ServerSideEncryption>,
+
+    /// ID or ARN of the KMS key to encrypt uploaded objects with. Only
+    /// valid when `sse` is `aws_kms`. If unset with `aws_kms`, the AWS
+    /// managed key for S3 is used.
+    ///
+    /// Default: None
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub kms_key_id: Option<String>,
+
+    /// Storage class of uploaded objects, eg: `STANDARD`, `STANDARD_IA` or
+    /// `INTELLIGENT_TIERING`.
+    ///
+    /// Default: None (the bucket's default storage class is used)
+    #[serde(default, deserialize_with = "convert_optional_string_with_shellexpand")]
+    pub storage_class: Option<String>,
+
+    /// Compress objects on upload and decompress them on download. The S3
+    /// `Content-Encoding` header is set on uploaded objects so other tools
+    /// can read them too. Uploads of unknown size are stored uncompressed,
+    /// and objects are only decompressed on download if they carry the
+    /// `Content-Encoding` header. Ranged reads of uncompressed objects only
+    /// download the range. Ranged reads of compressed objects send a second
+    /// request that downloads and decompresses the object from its start,
+    /// so reading the end of a large compressed object costs as much as
+    /// reading all of it.
+    ///
+    /// Default: None (objects are stored as-is)
+    #[serde(default)]
+    pub content_encoding: Option<S3ContentEncoding>,
+}
+
+/// Content encoding applied to objects uploaded to S3.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3ContentEncoding {
+    /// Compress objects with gzip.
+    gzip,
+}
+
+/// Server side encryption of objects uploaded to S3.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S3ServerSideEncryption {
+    /// Encrypt with keys managed by S3 (SSE-S3).
+    aes256,
+
+    /// Encrypt with a key managed by AWS KMS (SSE-KMS). See `kms_key_id`.
+    aws_kms,
 }
 
 #[allow(non_camel_case_types)]
@@ -811,7 +1462,7 @@ pub struct ClientTlsConfig {
     pub key_file: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct GrpcEndpoint {
     /// The endpoint address (i.e. grpc(s)://example.com:443).
@@ -820,7 +1471,68 @@ pub struct GrpcEndpoint {
     /// The TLS configuration to use to connect to the endpoint (if grpcs).
     pub tls_config: Option<ClientTlsConfig>,
     /// The maximum concurrency to allow on this endpoint.
+    /// Note: This limit is applied to each connection made to the endpoint,
+    /// so with `connections_per_endpoint` connections up to
+    /// `connections_per_endpoint * concurrency_limit` requests may be in
+    /// flight to this endpoint.
     pub concurrency_limit: Option<usize>,
+
+    /// Interval to send HTTP2 keep-alive pings on each connection to this
+    /// endpoint. Connections that fail to acknowledge a ping are closed and
+    /// re-established by the connection manager, which also moves requests
+    /// to the other connections and endpoints in the meantime.
+    /// Note: This is in seconds.
+    ///
+    /// Default: None (no keep-alive pings)
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub http2_keep_alive_interval: Option<u32>,
+
+    /// Time to wait for a keep-alive ping to be acknowledged before the
+    /// connection is closed. Requires `http2_keep_alive_interval`.
+    /// Note: This is in seconds.
+    ///
+    /// Default: None (20 seconds)
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub http2_keep_alive_timeout: Option<u32>,
+
+    /// If keep-alive pings should also be sent while no requests are in
+    /// flight. This keeps idle connections from being dropped by proxies
+    /// and load balancers. Requires `http2_keep_alive_interval`.
+    ///
+    /// Default: None (false)
+    #[serde(default)]
+    pub http2_keep_alive_while_idle: Option<bool>,
+
+    /// If `TCP_NODELAY` should be set on connections to this endpoint.
+    ///
+    /// Default: None (true)
+    #[serde(default)]
+    pub tcp_nodelay: Option<bool>,
+
+    /// The HTTP2 initial flow control window size of each stream in bytes.
+    ///
+    /// Default: None (hyper's default)
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub http2_initial_stream_window_size: Option<u32>,
+
+    /// The HTTP2 initial flow control window size of each connection in
+    /// bytes.
+    ///
+    /// Default: None (hyper's default)
+    #[serde(
+        default,
+        deserialize_with = "convert_optional_numeric_with_shellexpand"
+    )]
+    pub http2_initial_connection_window_size: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -850,6 +1562,33 @@ pub struct GrpcSpec {
     /// the load over multiple TCP connections.  Default 1.
     #[serde(default)]
     pub connections_per_endpoint: usize,
+
+    /// The maximum size of stdout, stderr or an output file that will be
+    /// passed through inline in a proxied `GetActionResult` response. Content
+    /// the upstream inlined that is larger than this is removed from the
+    /// response and clients fetch it from the CAS by digest instead. Only
+    /// used when `store_type` is `ac`.
+    ///
+    /// Default: 0 (no limit, whatever the upstream inlines is returned)
+    #[serde(default, deserialize_with = "convert_data_size_with_shellexpand")]
+    pub max_inline_size: u64,
+
+    /// The maximum number of `GetActionResult` requests a single existence
+    /// check for many digests sends upstream at the same time. The others
+    /// wait until one of them finishes. Only used when `store_type` is `ac`,
+    /// a CAS store checks all digests with one `FindMissingBlobs` request.
+    ///
+    /// Default: 0 (32)
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_concurrent_has_requests: usize,
+
+    /// If set, blobs written through `ByteStream` are zstd compressed and
+    /// uploaded as `compressed-blobs/zstd` resources. The upstream must
+    /// support zstd compressed uploads. Only used when `store_type` is `cas`.
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub compress_uploads: bool,
 }
 
 /// The possible error codes that might occur on an upstream request.
@@ -987,6 +1726,7 @@ pub struct RedisSpec {
     ///   delay: 0.1, /* 100ms */
     ///   jitter: 0.5, /* 50% */
     ///   retry_on_errors: None, /* not used in redis store */
+    ///   retry_budget: None, /* not used in redis store */
     /// }
     /// ```
     #[serde(default)]
@@ -1062,4 +1802,43 @@ pub struct Retry {
     ///  - `DataLoss`
     #[serde(default)]
     pub retry_on_errors: Option<Vec<ErrorCode>>,
+
+    /// Limits the retries of all requests made with this configuration
+    /// together, so a struggling backend is not flooded with retries from
+    /// every in-flight request at once. Once the budget is spent, failing
+    /// requests return their error without being retried.
+    ///
+    /// Default: None (every request may retry up to `max_retries` times)
+    #[serde(default)]
+    pub retry_budget: Option<RetryBudget>,
+}
+
+/// Token bucket shared by all requests using the same `Retry`
+/// configuration. Every retry takes one token out of the bucket and every
+/// successful request puts `token_ratio` tokens back.
+///
+/// **Example JSON Config:**
+/// ```json
+/// "retry_budget": {
+///   "max_tokens": 100,
+///   "token_ratio": 0.1
+/// }
+/// ```
+/// allows a burst of 100 retries, after which retries are limited to one
+/// for every ten successful requests.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RetryBudget {
+    /// Maximum number of tokens the bucket can hold. The bucket starts
+    /// full.
+    ///
+    /// Default: 100
+    #[serde(default, deserialize_with = "convert_numeric_with_shellexpand")]
+    pub max_tokens: u32,
+
+    /// Number of tokens added to the bucket by every successful request.
+    ///
+    /// Default: 0.1
+    #[serde(default)]
+    pub token_ratio: f32,
 }
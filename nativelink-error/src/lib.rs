@@ -50,6 +50,20 @@ macro_rules! error_if {
 pub struct Error {
     pub code: Code,
     pub messages: Vec<String>,
+    /// Why the error happened, if known. Unlike `messages` this is meant to
+    /// be inspected by code, for example to decide what to tell clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ErrorReason>,
+}
+
+/// Machine-readable reason of an `Error`, kept separately from its messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive] // New reasons may be added in the future, so never exhaustively match!
+pub enum ErrorReason {
+    /// The requested item does not exist. This is common and expected, so
+    /// clients are only sent the message the error was created with, while
+    /// the context added as it propagated is only logged.
+    NotFound,
 }
 
 impl MetricsComponent for Error {
@@ -71,9 +85,17 @@ impl Error {
         Self {
             code,
             messages: msgs,
+            reason: None,
         }
     }
 
+    #[inline]
+    #[must_use]
+    pub fn with_reason(mut self, reason: ErrorReason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
     #[inline]
     #[must_use]
     pub fn append<S: Into<String>>(mut self, msg: S) -> Self {
@@ -111,6 +133,15 @@ impl Error {
     pub fn message_string(&self) -> String {
         self.messages.join(" : ")
     }
+
+    /// Returns the message to send to clients. This is the full message
+    /// unless the `reason` says clients should only see part of it.
+    pub fn client_message(&self) -> String {
+        match self.reason {
+            Some(ErrorReason::NotFound) => self.messages.first().cloned().unwrap_or_default(),
+            None => self.message_string(),
+        }
+    }
 }
 
 impl std::error::Error for Error {}
@@ -119,7 +150,7 @@ impl From<Error> for nativelink_proto::google::rpc::Status {
     fn from(val: Error) -> Self {
         Self {
             code: val.code as i32,
-            message: val.message_string(),
+            message: val.client_message(),
             details: vec![],
         }
     }
@@ -130,6 +161,7 @@ impl From<nativelink_proto::google::rpc::Status> for Error {
         Self {
             code: val.code.into(),
             messages: vec![val.message],
+            reason: None,
         }
     }
 }
@@ -145,6 +177,10 @@ impl std::fmt::Display for Error {
             builder.field("messages", &self.messages);
         }
 
+        if let Some(reason) = &self.reason {
+            builder.field("reason", reason);
+        }
+
         builder.finish()
     }
 }
@@ -209,6 +245,7 @@ impl From<std::io::Error> for Error {
         Self {
             code: err.kind().into(),
             messages: vec![err.to_string()],
+            reason: None,
         }
     }
 }
@@ -256,7 +293,7 @@ impl From<tonic::Status> for Error {
 
 impl From<Error> for tonic::Status {
     fn from(val: Error) -> Self {
-        Self::new(val.code.into(), val.messages.join(" : "))
+        Self::new(val.code.into(), val.client_message())
     }
 }
 
@@ -296,6 +333,10 @@ impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
         self.map_err(|e| {
             let mut error: Error = e.into();
             let (code, message) = tip_fn(&error);
+            if code != error.code {
+                // The reason may no longer apply to the new code.
+                error.reason = None;
+            }
             error.code = code;
             error.messages.push(message.to_string());
             error
@@ -332,6 +373,7 @@ impl<T> ResultExt<T> for Option<T> {
             let mut error = Error {
                 code: Code::Internal,
                 messages: vec![],
+                reason: None,
             };
             let (code, message) = tip_fn(&error);
             error.code = code;
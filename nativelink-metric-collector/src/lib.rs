@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-pub use otel_exporter::otel_export;
+pub use otel_exporter::{otel_export, otel_export_with_labels};
 pub use tracing_layers::MetricsCollectorLayer;
 
 mod metrics_collection;
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use opentelemetry::metrics::Meter;
+use opentelemetry::KeyValue;
 use tracing::info;
 
 use crate::metrics_collection::{
@@ -26,35 +27,97 @@ const MAX_METRIC_NAME_LENGTH: usize = 256;
 
 /// Export the collected metrics to the OpenTelemetry meter.
 pub fn otel_export(
+    root_prefix: String,
+    meter: &Meter,
+    root_collected_metrics: &RootMetricCollectedMetrics,
+) {
+    otel_export_with_labels(root_prefix, meter, root_collected_metrics, &[]);
+}
+
+/// Export the collected metrics to the OpenTelemetry meter, turning the
+/// children of the given top level groups into labels instead of name
+/// segments. Each entry in `label_groups` is a `(group, label_key)` pair,
+/// so `("stores", "store")` exports `stores_main_cas_foo` as
+/// `stores_foo{store="main_cas"}`.
+pub fn otel_export_with_labels(
     mut root_prefix: String,
     meter: &Meter,
     root_collected_metrics: &RootMetricCollectedMetrics,
+    label_groups: &[(&str, &str)],
 ) {
     if !root_prefix.is_empty() {
         root_prefix.push('_');
     }
-    process_children(&mut root_prefix, meter, root_collected_metrics);
+    let mut labels = Vec::new();
+    for (name, child) in root_collected_metrics.iter() {
+        let label_key = label_groups
+            .iter()
+            .find_map(|(group, label_key)| (*group == name.as_str()).then_some(*label_key));
+        match (child, label_key) {
+            (CollectedMetrics::Component(component), Some(label_key)) => {
+                root_prefix.push_str(name);
+                root_prefix.push('_');
+                for (label_value, grandchild) in component.iter() {
+                    if let CollectedMetrics::Component(grandchild) = grandchild {
+                        labels.push(KeyValue::new(label_key.to_string(), label_value.clone()));
+                        process_children(&mut root_prefix, meter, grandchild, &mut labels);
+                        labels.pop();
+                    } else {
+                        process_child(
+                            &mut root_prefix,
+                            meter,
+                            label_value,
+                            grandchild,
+                            &mut labels,
+                        );
+                    }
+                }
+                root_prefix.truncate(root_prefix.len() - name.len() - 1);
+            }
+            _ => process_child(&mut root_prefix, meter, name, child, &mut labels),
+        }
+    }
 }
 
-fn process_children(prefix: &mut String, meter: &Meter, children: &CollectedMetricChildren) {
+fn process_children(
+    prefix: &mut String,
+    meter: &Meter,
+    children: &CollectedMetricChildren,
+    labels: &mut Vec<KeyValue>,
+) {
     for (name, child) in children {
-        prefix.push_str(name);
-        let mut added_prefix_len = name.len();
-        match child {
-            CollectedMetrics::Primitive(primitive) => {
-                process_primitive(prefix, meter, primitive);
-            }
-            CollectedMetrics::Component(component) => {
-                prefix.push('_');
-                added_prefix_len += 1;
-                process_children(prefix, meter, component);
-            }
+        process_child(prefix, meter, name, child, labels);
+    }
+}
+
+fn process_child(
+    prefix: &mut String,
+    meter: &Meter,
+    name: &str,
+    child: &CollectedMetrics,
+    labels: &mut Vec<KeyValue>,
+) {
+    prefix.push_str(name);
+    let mut added_prefix_len = name.len();
+    match child {
+        CollectedMetrics::Primitive(primitive) => {
+            process_primitive(prefix, meter, primitive, labels);
+        }
+        CollectedMetrics::Component(component) => {
+            prefix.push('_');
+            added_prefix_len += 1;
+            process_children(prefix, meter, component, labels);
         }
-        prefix.truncate(prefix.len() - added_prefix_len);
     }
+    prefix.truncate(prefix.len() - added_prefix_len);
 }
 
-fn process_primitive(prefix: &mut String, meter: &Meter, primitive: &CollectedMetricPrimitive) {
+fn process_primitive(
+    prefix: &mut String,
+    meter: &Meter,
+    primitive: &CollectedMetricPrimitive,
+    labels: &[KeyValue],
+) {
     match &primitive.value {
         Some(CollectedMetricPrimitiveValue::Counter(value)) => {
             if prefix.len() > MAX_METRIC_NAME_LENGTH {
@@ -65,7 +128,7 @@ fn process_primitive(prefix: &mut String, meter: &Meter, primitive: &CollectedMe
                 .u64_counter(prefix.clone())
                 .with_description(primitive.help.clone())
                 .build();
-            counter.add(*value, &[]);
+            counter.add(*value, labels);
         }
         Some(CollectedMetricPrimitiveValue::String(_value)) => {
             // We don't publish strings in metrics.
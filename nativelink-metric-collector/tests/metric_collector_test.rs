@@ -19,7 +19,7 @@ use std::marker::PhantomData;
 use std::str::from_utf8;
 
 use nativelink_metric::{MetricFieldData, MetricKind, MetricsComponent};
-use nativelink_metric_collector::{otel_export, MetricsCollectorLayer};
+use nativelink_metric_collector::{otel_export, otel_export_with_labels, MetricsCollectorLayer};
 use opentelemetry::metrics::MeterProvider;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use prometheus::{Encoder, TextEncoder};
@@ -44,6 +44,15 @@ pub struct MultiStruct {
     sub_struct: Foo<'static, String>,
 }
 
+#[derive(MetricsComponent)]
+struct MultiStore {
+    #[metric(help = "dummy help pub_u64")]
+    pub_u64: u64,
+
+    #[metric(group = "stores")]
+    stores: HashMap<String, Foo<'static, String>>,
+}
+
 #[derive(MetricsComponent)]
 struct Foo<'a, T: Debug + Send + Sync> {
     #[metric(help = "help str1", handler = ToString::to_string)]
@@ -188,3 +197,88 @@ target_info{service_name="unknown_service",telemetry_sdk_language="rust",telemet
 
     assert_eq!(output, expected_output);
 }
+
+// Note: Special case to not use nativelink-test macro. We want this test
+// to be very lightweight and not depend on other crates.
+#[test]
+fn test_prometheus_exporter_with_labels() {
+    let multi_store = MultiStore {
+        pub_u64: 1,
+        stores: HashMap::from([
+            (
+                "store_a".to_string(),
+                Foo {
+                    custom_handler_num_str: 3,
+                    custom_handler_num_counter: 4,
+                    _bar: &PhantomData,
+                },
+            ),
+            (
+                "store_b".to_string(),
+                Foo {
+                    custom_handler_num_str: 5,
+                    custom_handler_num_counter: 6,
+                    _bar: &PhantomData,
+                },
+            ),
+        ]),
+    };
+    let (layer, output_metrics) = MetricsCollectorLayer::new();
+    let subscriber = tracing_subscriber::registry().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        MetricsComponent::publish(
+            &multi_store,
+            MetricKind::Component,
+            MetricFieldData::default(),
+        )
+        .unwrap();
+    });
+
+    let registry = prometheus::Registry::new();
+    let exporter = opentelemetry_prometheus::exporter()
+        .with_registry(registry.clone())
+        .without_counter_suffixes()
+        .without_scope_info()
+        .build()
+        .unwrap();
+    let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+    let meter = provider.meter("nativelink");
+
+    // Children of the "stores" group become a "store" label instead of
+    // being part of the metric name.
+    otel_export_with_labels(
+        "nativelink".to_string(),
+        &meter,
+        &output_metrics.lock(),
+        &[("stores", "store")],
+    );
+
+    let mut result = vec![];
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut result)
+        .unwrap();
+
+    let mut output: Vec<String> = Cursor::new(from_utf8(&result).unwrap())
+        .lines()
+        .map(|v| v.unwrap())
+        .collect();
+    let mut expected_output: Vec<String> = Cursor::new(r#"
+# HELP nativelink_pub_u64 dummy help pub_u64
+# HELP nativelink_stores_custom_handler_num_counter help str2
+# HELP target_info Target metadata
+# TYPE nativelink_pub_u64 counter
+# TYPE nativelink_stores_custom_handler_num_counter counter
+# TYPE target_info gauge
+nativelink_pub_u64 1
+nativelink_stores_custom_handler_num_counter{store="store_a"} 4
+nativelink_stores_custom_handler_num_counter{store="store_b"} 6
+target_info{service_name="unknown_service",telemetry_sdk_language="rust",telemetry_sdk_name="opentelemetry",telemetry_sdk_version="0.27.1"} 1
+"#.trim()).lines().map(|v| v.unwrap()).collect();
+
+    // We need to sort because the output order is non-deterministic.
+    output.sort();
+    expected_output.sort();
+
+    assert_eq!(output, expected_output);
+}
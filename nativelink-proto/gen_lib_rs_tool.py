@@ -60,18 +60,28 @@ def print_package_part_to_mod(tree, indents = 0):
 
 
 
+_FILE_DESCRIPTOR_SET = """\
+/// Encoded `FileDescriptorSet` of every proto in this crate, served by the
+/// gRPC reflection service.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("%s");
+"""
+
+
 def main():
     parser = argparse.ArgumentParser(description=__doc__)
     parser.add_argument('--rootdir', default=os.getcwd(),
                         help='Module paths will be relative to this path. Default CWD.')
     parser.add_argument('files', nargs='+',
-                        help='pb.rs files used to generate lib.rs')
+                        help='pb.rs files and the file descriptor set used to generate lib.rs')
     args = parser.parse_args()
     print(_HEADER)
 
     tree_root = { "children": {}, "filename": None }
     for filepath in args.files:
         filepath = os.path.relpath(os.path.normpath(filepath), args.rootdir)
+        if filepath.endswith('.bin'):
+            print(_FILE_DESCRIPTOR_SET % (filepath, ))
+            continue
         assert filepath.endswith('.pb.rs'), "Expected " + filepath + " to end in '.pb.rs'"
         package_parts = filepath.split('.')[:-2]  # Remove `.pb.rs'.
         assert '.' not in package_parts and '..' not in package_parts, \
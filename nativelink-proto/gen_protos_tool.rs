@@ -29,6 +29,8 @@ fn main() -> std::io::Result<()> {
     let mut config = Config::new();
     config.bytes(["."]);
     tonic_build::configure()
+        // Served by the gRPC reflection service.
+        .file_descriptor_set_path(output_dir.join("file_descriptor_set.bin"))
         .out_dir(output_dir)
         .compile_protos_with_config(config, &paths, &["nativelink-proto"])?;
     Ok(())
@@ -19,6 +19,11 @@ pub struct KeepAliveRequest {
     /// / ID of the worker making the request.
     #[prost(string, tag = "1")]
     pub worker_id: ::prost::alloc::string::String,
+    /// / Set while the worker can not take new actions, for example because
+    /// / its work_directory is low on disk space. The scheduler gives it no
+    /// / new actions until a keep alive without it is received.
+    #[prost(bool, tag = "2")]
+    pub paused: bool,
 }
 /// / Request object for going away requests.
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -25,6 +25,10 @@
     rustdoc::invalid_html_tags
 )]
 
+/// Encoded `FileDescriptorSet` of every proto in this crate, served by the
+/// gRPC reflection service.
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!("file_descriptor_set.bin");
+
 pub mod build {
     pub mod bazel {
         pub mod remote {
@@ -24,6 +24,9 @@ import shutil
 _BAZEL_DIR = os.path.join("nativelink-proto")
 _REPO_DIR = os.path.join(os.path.dirname(os.path.realpath(__file__)), "genproto")
 
+# Encoded descriptors of every proto, served by the gRPC reflection service.
+_FILE_DESCRIPTOR_SET = "file_descriptor_set.bin"
+
 _RUST_LICENSE = """\
 // Copyright 2022 The NativeLink Authors. All rights reserved.
 //
@@ -61,9 +64,10 @@ def update(proto_packages):
     for pkg in proto_packages:
         with open(repo_file_path(pkg), "wb") as outfile:
             outfile.write(expected_contents(pkg))
-    with open(_REPO_DIR + "/lib.rs", "wb") as outfile:
-        with open(_BAZEL_DIR + "/lib.rs", "rb") as infile:
-            outfile.write(infile.read())
+    for filename in ("lib.rs", _FILE_DESCRIPTOR_SET):
+        with open(os.path.join(_REPO_DIR, filename), "wb") as outfile:
+            with open(os.path.join(_BAZEL_DIR, filename), "rb") as infile:
+                outfile.write(infile.read())
 
 
 def check(proto_packages):
@@ -95,12 +99,30 @@ def check(proto_packages):
     except OSError as e:
         failed = True
         print("Could not read package lib.rs: %s" % e)
-    # Ignore differences between newlines on Unix and Windows.
-    if expected.splitlines() == actual.splitlines():
-        print("%s OK" % dst)
     else:
-        print("%s out of date" % dst)
+        # Ignore differences between newlines on Unix and Windows.
+        if expected.splitlines() == actual.splitlines():
+            print("%s OK" % dst)
+        else:
+            print("%s out of date" % dst)
+            failed = True
+
+    # The file descriptor set is binary, so it has to match exactly.
+    dst = os.path.join(_REPO_DIR, _FILE_DESCRIPTOR_SET)
+    try:
+        with open(os.path.join(_BAZEL_DIR, _FILE_DESCRIPTOR_SET), "rb") as infile:
+            expected = infile.read()
+        with open(dst, "rb") as infile:
+            actual = infile.read()
+    except OSError as e:
         failed = True
+        print("Could not read %s: %s" % (_FILE_DESCRIPTOR_SET, e))
+    else:
+        if expected == actual:
+            print("%s OK" % dst)
+        else:
+            print("%s out of date" % dst)
+            failed = True
 
     if failed:
         print("To update, run: 'bazel run nativelink-proto:update_protos'")
@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::ops::{Deref, DerefMut};
+use std::num::NonZeroUsize;
+use std::ops::{Bound, Deref, DerefMut};
 use std::sync::Arc;
 
 use async_lock::Mutex;
@@ -33,10 +34,17 @@ use tokio::sync::Notify;
 use tonic::async_trait;
 use tracing::{event, Level};
 
+use crate::awaited_action_db::{
+    OperationLister, OperationSummary, SortedAwaitedAction, SortedAwaitedActionState,
+};
 use crate::platform_property_manager::PlatformPropertyManager;
 use crate::worker::{ActionInfoWithProps, Worker, WorkerTimestamp, WorkerUpdate};
 use crate::worker_scheduler::WorkerScheduler;
 
+/// Maximum number of operations of evicted workers whose result is still
+/// accepted from the worker they were running on.
+const MAX_ORPHANED_OPERATIONS: NonZeroUsize = NonZeroUsize::new(10_000).unwrap();
+
 struct Workers(LruCache<WorkerId, Worker>);
 
 impl Deref for Workers {
@@ -86,6 +94,11 @@ struct ApiWorkerSchedulerImpl {
     worker_change_notify: Arc<Notify>,
     /// A channel to notify that an operation is still alive.
     operation_keep_alive_tx: UnboundedSender<(OperationId, WorkerId)>,
+    /// Operations that were running on evicted workers, and the worker they
+    /// were running on. Workers keep running their actions when they lose the
+    /// connection to the scheduler, so their result is accepted from that
+    /// worker until the operation is assigned to another worker.
+    orphaned_operations: LruCache<OperationId, WorkerId>,
 }
 
 impl ApiWorkerSchedulerImpl {
@@ -174,10 +187,39 @@ impl ApiWorkerSchedulerImpl {
         Ok(())
     }
 
+    /// Sets if the worker asked not to be given new actions. Workers report
+    /// this with every keep alive, so only changes notify the matching
+    /// engine.
+    fn set_worker_intake_paused(
+        &mut self,
+        worker_id: &WorkerId,
+        is_intake_paused: bool,
+    ) -> Result<(), Error> {
+        let worker = self
+            .workers
+            .get_mut(worker_id)
+            .err_tip(|| format!("Worker {worker_id} doesn't exist in the pool"))?;
+        if worker.is_intake_paused == is_intake_paused {
+            return Ok(());
+        }
+        event!(
+            Level::INFO,
+            ?worker_id,
+            is_intake_paused,
+            "Worker changed its intake of actions"
+        );
+        worker.is_intake_paused = is_intake_paused;
+        self.worker_change_notify.notify_one();
+        Ok(())
+    }
+
     fn inner_find_worker_for_action(
         &self,
         platform_properties: &PlatformProperties,
     ) -> Option<WorkerId> {
+        if platform_properties.has_priority_properties() {
+            return self.inner_find_best_priority_worker_for_action(platform_properties);
+        }
         let mut workers_iter = self.workers.iter();
         let workers_iter = match self.allocation_strategy {
             // Use rfind to get the least recently used that satisfies the properties.
@@ -192,15 +234,159 @@ impl ApiWorkerSchedulerImpl {
         workers_iter.map(|(_, w)| &w.id).copied()
     }
 
+    /// Same as `inner_find_worker_for_action`, but prefers the worker with
+    /// the most matching `Priority` properties. Ties are broken using the
+    /// allocation strategy.
+    fn inner_find_best_priority_worker_for_action(
+        &self,
+        platform_properties: &PlatformProperties,
+    ) -> Option<WorkerId> {
+        let workers_iter: Box<dyn Iterator<Item = &Worker>> = match self.allocation_strategy {
+            WorkerAllocationStrategy::least_recently_used => {
+                Box::new(self.workers.iter().rev().map(|(_, w)| w))
+            }
+            WorkerAllocationStrategy::most_recently_used => {
+                Box::new(self.workers.iter().map(|(_, w)| w))
+            }
+        };
+        let mut best_worker: Option<(usize, WorkerId)> = None;
+        for worker in workers_iter {
+            if !worker.can_accept_work()
+                || !platform_properties.is_satisfied_by(&worker.platform_properties)
+            {
+                continue;
+            }
+            let match_count = platform_properties.priority_match_count(&worker.platform_properties);
+            if best_worker.is_none_or(|(best_match_count, _)| match_count > best_match_count) {
+                best_worker = Some((match_count, worker.id));
+            }
+        }
+        best_worker.map(|(_, worker_id)| worker_id)
+    }
+
+    /// Returns true if a worker will be able to run an action with the given
+    /// properties once its pending preemptions have been killed.
+    fn inner_is_waiting_on_preemption(&self, platform_properties: &PlatformProperties) -> bool {
+        self.workers.iter().any(|(_, worker)| {
+            worker.can_accept_work()
+                && !worker.preempted_operations.is_empty()
+                && platform_properties.is_satisfied_by(&worker.platform_properties_without([]))
+        })
+    }
+
+    /// Finds the lowest priority running action that, if removed from its
+    /// worker, would allow that worker to run an action with the given
+    /// properties. Only actions with a strictly lower priority are considered.
+    fn inner_find_action_to_preempt(
+        &self,
+        platform_properties: &PlatformProperties,
+        priority: i32,
+    ) -> Option<(WorkerId, OperationId)> {
+        let mut best_victim: Option<(i32, WorkerId, &OperationId)> = None;
+        for (worker_id, worker) in self.workers.iter() {
+            if !worker.can_accept_work() {
+                continue;
+            }
+            for (operation_id, action_info) in &worker.running_action_infos {
+                let victim_priority = action_info.inner.priority;
+                if victim_priority >= priority
+                    || best_victim
+                        .is_some_and(|(best_priority, _, _)| best_priority <= victim_priority)
+                {
+                    continue;
+                }
+                if platform_properties
+                    .is_satisfied_by(&worker.platform_properties_without([action_info]))
+                {
+                    best_victim = Some((victim_priority, *worker_id, operation_id));
+                }
+            }
+        }
+        best_victim.map(|(_, worker_id, operation_id)| (worker_id, operation_id.clone()))
+    }
+
+    /// Preempts a lower priority action to make room for an action with the
+    /// given properties and priority. The preempted action is put back in the
+    /// queue. Its worker only becomes available once it has reported the
+    /// preempted action as finished, or once it is evicted.
+    async fn preempt_worker_for_action(
+        &mut self,
+        platform_properties: &PlatformProperties,
+        priority: i32,
+    ) -> Result<(), Error> {
+        // Do not preempt another action while an earlier preemption that
+        // makes room for this action is still being killed.
+        if self.inner_is_waiting_on_preemption(platform_properties) {
+            return Ok(());
+        }
+        let Some((worker_id, operation_id)) =
+            self.inner_find_action_to_preempt(platform_properties, priority)
+        else {
+            return Ok(());
+        };
+        let worker = self.workers.peek_mut(&worker_id).err_tip(|| {
+            format!(
+                "Worker {worker_id} does not exist in SimpleScheduler::preempt_worker_for_action"
+            )
+        })?;
+        if let Err(err) = worker.preempt_action(&operation_id) {
+            return Result::<(), _>::Err(err.clone())
+                .merge(self.immediate_evict_worker(&worker_id, err).await);
+        }
+        event!(
+            Level::INFO,
+            ?operation_id,
+            ?worker_id,
+            priority,
+            "Preempted operation for higher priority action"
+        );
+        // Backpressure errors are not counted as an attempt, so the preempted
+        // action is simply put back in the queue.
+        self.worker_state_manager
+            .update_operation(
+                &operation_id,
+                &worker_id,
+                UpdateOperationType::UpdateWithError(make_err!(
+                    Code::ResourceExhausted,
+                    "Operation {operation_id} was preempted by a higher priority action"
+                )),
+            )
+            .await
+            .err_tip(|| "in update_operation on SimpleScheduler::preempt_worker_for_action")
+    }
+
     async fn update_action(
         &mut self,
         worker_id: &WorkerId,
         operation_id: &OperationId,
         update: UpdateOperationType,
     ) -> Result<(), Error> {
-        let worker = self.workers.get_mut(worker_id).err_tip(|| {
-            format!("Worker {worker_id} does not exist in SimpleScheduler::update_action")
-        })?;
+        let Some(worker) = self.workers.get_mut(worker_id) else {
+            return self
+                .update_orphaned_action(worker_id, operation_id, update)
+                .await
+                .err_tip(|| {
+                    format!("Worker {worker_id} does not exist in SimpleScheduler::update_action")
+                });
+        };
+
+        // Preempted operations will still send updates until the worker has
+        // finished killing them, these updates are expected and ignored.
+        if worker.preempted_operations.contains_key(operation_id) {
+            let is_finished = match &update {
+                UpdateOperationType::UpdateWithActionStage(action_stage) => {
+                    action_stage.is_finished()
+                }
+                UpdateOperationType::KeepAlive => false,
+                UpdateOperationType::UpdateWithError(_) => true,
+            };
+            // The resources of the preempted operation are now free, so the
+            // action it was preempted for can be matched to this worker.
+            if is_finished && worker.finish_preempted_action(operation_id) {
+                self.worker_change_notify.notify_one();
+            }
+            return Ok(());
+        }
 
         // Ensure the worker is supposed to be running the operation.
         if !worker.running_action_infos.contains_key(operation_id) {
@@ -264,6 +450,36 @@ impl ApiWorkerSchedulerImpl {
         complete_action_res
     }
 
+    /// Accepts the result of an operation that was running on a worker when it
+    /// was evicted, as long as the operation was not assigned to another
+    /// worker since. Only finished results are accepted, errors of a worker
+    /// that is no longer connected are not trusted.
+    async fn update_orphaned_action(
+        &mut self,
+        worker_id: &WorkerId,
+        operation_id: &OperationId,
+        update: UpdateOperationType,
+    ) -> Result<(), Error> {
+        let UpdateOperationType::UpdateWithActionStage(action_stage) = &update else {
+            return Err(make_input_err!(
+                "Only finished results are accepted from disconnected workers"
+            ));
+        };
+        error_if!(
+            !action_stage.is_finished(),
+            "Only finished results are accepted from disconnected workers"
+        );
+        error_if!(
+            self.orphaned_operations.peek(operation_id) != Some(worker_id),
+            "Operation {operation_id} was not running on worker {worker_id} when it was evicted"
+        );
+        self.orphaned_operations.pop(operation_id);
+        self.worker_state_manager
+            .update_operation(operation_id, worker_id, update)
+            .await
+            .err_tip(|| "in update_operation on SimpleScheduler::update_orphaned_action")
+    }
+
     /// Notifies the specified worker to run the given action and handles errors by evicting
     /// the worker if the notification fails.
     async fn worker_notify_run_action(
@@ -272,6 +488,8 @@ impl ApiWorkerSchedulerImpl {
         operation_id: OperationId,
         action_info: ActionInfoWithProps,
     ) -> Result<(), Error> {
+        // The result of a previous attempt is no longer accepted.
+        self.orphaned_operations.pop(&operation_id);
         if let Some(worker) = self.workers.get_mut(&worker_id) {
             let notify_worker_result =
                 worker.notify_update(WorkerUpdate::RunAction((operation_id, action_info.clone())));
@@ -316,6 +534,8 @@ impl ApiWorkerSchedulerImpl {
             // We don't care if we fail to send message to worker, this is only a best attempt.
             let _ = worker.notify_update(WorkerUpdate::Disconnect);
             for (operation_id, _) in worker.running_action_infos.drain() {
+                self.orphaned_operations
+                    .put(operation_id.clone(), *worker_id);
                 result = result.merge(
                     self.worker_state_manager
                         .update_operation(
@@ -345,6 +565,8 @@ pub struct ApiWorkerScheduler {
         help = "Timeout of how long to evict workers if no response in this given amount of time in seconds."
     )]
     worker_timeout_s: u64,
+    /// Lists the operations of the scheduler for operators, if supported.
+    operation_lister: Option<Arc<dyn OperationLister>>,
     _operation_keep_alive_spawn: JoinHandleDropGuard<()>,
 }
 
@@ -355,6 +577,7 @@ impl ApiWorkerScheduler {
         allocation_strategy: WorkerAllocationStrategy,
         worker_change_notify: Arc<Notify>,
         worker_timeout_s: u64,
+        operation_lister: Option<Arc<dyn OperationLister>>,
     ) -> Arc<Self> {
         let (operation_keep_alive_tx, mut operation_keep_alive_rx) = mpsc::unbounded_channel();
         Arc::new(Self {
@@ -364,9 +587,11 @@ impl ApiWorkerScheduler {
                 allocation_strategy,
                 worker_change_notify,
                 operation_keep_alive_tx,
+                orphaned_operations: LruCache::new(MAX_ORPHANED_OPERATIONS),
             }),
             platform_property_manager,
             worker_timeout_s,
+            operation_lister,
             _operation_keep_alive_spawn: spawn!(
                 "simple_scheduler_operation_keep_alive",
                 async move {
@@ -422,6 +647,21 @@ impl ApiWorkerScheduler {
         inner.inner_find_worker_for_action(platform_properties)
     }
 
+    /// Attempts to make room for an action by preempting a running action
+    /// with a strictly lower priority. The preempted action is put back in
+    /// the queue. The action stays queued until the worker has confirmed the
+    /// preempted action was killed.
+    pub async fn preempt_worker_for_action(
+        &self,
+        platform_properties: &PlatformProperties,
+        priority: i32,
+    ) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+        inner
+            .preempt_worker_for_action(platform_properties, priority)
+            .await
+    }
+
     /// Checks to see if the worker exists in the worker pool. Should only be used in unit tests.
     #[must_use]
     pub async fn contains_worker_for_test(&self, worker_id: &WorkerId) -> bool {
@@ -536,6 +776,28 @@ impl WorkerScheduler for ApiWorkerScheduler {
         let mut inner = self.inner.lock().await;
         inner.set_drain_worker(worker_id, is_draining).await
     }
+
+    async fn set_worker_intake_paused(
+        &self,
+        worker_id: &WorkerId,
+        is_intake_paused: bool,
+    ) -> Result<(), Error> {
+        let mut inner = self.inner.lock().await;
+        inner.set_worker_intake_paused(worker_id, is_intake_paused)
+    }
+
+    async fn list_operations(
+        &self,
+        state: SortedAwaitedActionState,
+        start: Bound<SortedAwaitedAction>,
+        limit: usize,
+    ) -> Result<Vec<OperationSummary>, Error> {
+        self.operation_lister
+            .as_ref()
+            .err_tip(|| "Listing operations is not supported by this scheduler")?
+            .list_operations(state, start, limit)
+            .await
+    }
 }
 
 impl RootMetricsComponent for ApiWorkerScheduler {}
@@ -238,6 +238,10 @@ impl AwaitedActionSortKey {
     pub(crate) fn as_u64(self) -> u64 {
         self.0
     }
+
+    pub(crate) const fn from_u64(value: u64) -> Self {
+        Self(value)
+    }
 }
 
 // Ensure the size of the sort key is the same as a `u64`.
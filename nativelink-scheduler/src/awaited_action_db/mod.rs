@@ -14,8 +14,11 @@
 
 use std::cmp;
 use std::ops::Bound;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
+use async_trait::async_trait;
 pub use awaited_action::{AwaitedAction, AwaitedActionSortKey};
 use futures::{Future, Stream};
 use nativelink_error::{make_input_err, Error, ResultExt};
@@ -26,7 +29,7 @@ use serde::{Deserialize, Serialize};
 mod awaited_action;
 
 /// A simple enum to represent the state of an `AwaitedAction`.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SortedAwaitedActionState {
     CacheCheck,
     Queued,
@@ -54,6 +57,21 @@ impl TryFrom<ActionStage> for SortedAwaitedActionState {
     }
 }
 
+impl FromStr for SortedAwaitedActionState {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Error> {
+        match value {
+            "cache_check" => Ok(Self::CacheCheck),
+            "queued" => Ok(Self::Queued),
+            "executing" => Ok(Self::Executing),
+            "completed" => Ok(Self::Completed),
+            _ => Err(make_input_err!(
+                "Invalid state '{value}', expected one of cache_check, queued, executing or completed"
+            )),
+        }
+    }
+}
+
 /// A struct pointing to an `AwaitedAction` that can be sorted.
 #[derive(Debug, Clone, Serialize, Deserialize, MetricsComponent)]
 pub struct SortedAwaitedAction {
@@ -94,6 +112,24 @@ impl std::fmt::Display for SortedAwaitedAction {
     }
 }
 
+/// Parses the `Display` format of a `SortedAwaitedAction`, which is used
+/// as the pagination cursor of `OperationLister::list_operations`.
+impl FromStr for SortedAwaitedAction {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let (sort_key, operation_id) = value.split_once('-').ok_or_else(|| {
+            make_input_err!("Expected '<sort_key>-<operation_id>', got '{value}'")
+        })?;
+        let sort_key = sort_key
+            .parse::<u64>()
+            .map_err(|e| make_input_err!("Invalid sort key '{sort_key}': {e}"))?;
+        Ok(Self {
+            sort_key: AwaitedActionSortKey::from_u64(sort_key),
+            operation_id: OperationId::from(operation_id),
+        })
+    }
+}
+
 impl From<&AwaitedAction> for SortedAwaitedAction {
     fn from(value: &AwaitedAction) -> Self {
         Self {
@@ -127,6 +163,55 @@ impl TryFrom<&[u8]> for SortedAwaitedAction {
     }
 }
 
+/// A snapshot of an operation in an `AwaitedActionDb`, for operators
+/// inspecting the live queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationSummary {
+    pub operation_id: OperationId,
+    /// The state bucket the operation was listed from.
+    pub state: SortedAwaitedActionState,
+    pub sort_key: AwaitedActionSortKey,
+    /// Seconds since the action was first inserted.
+    pub age_s: u64,
+    /// Pass this to the next `list_operations` call to list the operations
+    /// sorted after this one.
+    pub cursor: String,
+}
+
+impl OperationSummary {
+    pub(crate) fn new(
+        awaited_action: &AwaitedAction,
+        state: SortedAwaitedActionState,
+        now: SystemTime,
+    ) -> Self {
+        let sorted_awaited_action = SortedAwaitedAction::from(awaited_action);
+        Self {
+            cursor: sorted_awaited_action.to_string(),
+            operation_id: sorted_awaited_action.operation_id,
+            state,
+            sort_key: sorted_awaited_action.sort_key,
+            age_s: now
+                .duration_since(awaited_action.action_info().insert_timestamp)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// Lists the operations held by a scheduler's `AwaitedActionDb`.
+#[async_trait]
+pub trait OperationLister: Send + Sync + 'static {
+    /// Lists up to `limit` operations in `state` in sorted order, starting
+    /// at `start`. Use `Bound::Excluded` with the cursor of the last
+    /// returned operation to fetch the next page.
+    async fn list_operations(
+        &self,
+        state: SortedAwaitedActionState,
+        start: Bound<SortedAwaitedAction>,
+        limit: usize,
+    ) -> Result<Vec<OperationSummary>, Error>;
+}
+
 /// Subscriber that can be used to monitor when `AwaitedActions` change.
 pub trait AwaitedActionSubscriber: Send + Sync + Sized + 'static {
     /// Wait for `AwaitedAction` to change.
@@ -177,10 +262,13 @@ pub trait AwaitedActionDb: Send + Sync + MetricsComponent + Unpin + 'static {
     ) -> impl Future<Output = Result<(), Error>> + Send;
 
     /// Add (or join) an action to the `AwaitedActionDb` and subscribe
-    /// to changes.
+    /// to changes. An action that can not join an existing one is rejected
+    /// with `Code::ResourceExhausted` if `max_queued_actions` actions are
+    /// already queued. Zero means unlimited.
     fn add_action(
         &self,
         client_operation_id: OperationId,
         action_info: Arc<ActionInfo>,
+        max_queued_actions: usize,
     ) -> impl Future<Output = Result<Self::Subscriber, Error>> + Send;
 }
@@ -22,6 +22,7 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
     ActionResult as ProtoActionResult, GetActionResultRequest,
 };
 use nativelink_store::ac_utils::get_and_decode_digest;
+use nativelink_store::completeness_checking_store::CompletenessCheckingStore;
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_util::action_messages::{
     ActionInfo, ActionStage, ActionState, ActionUniqueKey, ActionUniqueQualifier, OperationId,
@@ -30,10 +31,11 @@ use nativelink_util::background_spawn;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
 use nativelink_util::known_platform_property_provider::KnownPlatformPropertyProvider;
+use nativelink_util::metrics_utils::CounterWithTime;
 use nativelink_util::operation_state_manager::{
     ActionStateResult, ActionStateResultStream, ClientStateManager, OperationFilter,
 };
-use nativelink_util::store_trait::Store;
+use nativelink_util::store_trait::{Store, StoreLike};
 use parking_lot::{Mutex, MutexGuard};
 use scopeguard::guard;
 use tokio::sync::oneshot;
@@ -63,6 +65,21 @@ pub struct CacheLookupScheduler {
     action_scheduler: Arc<dyn ClientStateManager>,
     /// Actions that are currently performing a `CacheCheck`.
     inflight_cache_checks: Arc<Mutex<CheckActions>>,
+    /// Stats about the results of cache lookups.
+    #[metric]
+    metrics: Arc<CacheLookupMetrics>,
+}
+
+#[derive(Default, MetricsComponent)]
+pub struct CacheLookupMetrics {
+    #[metric(help = "The number of actions found in the action cache.")]
+    pub cache_hits: CounterWithTime,
+    #[metric(help = "The number of actions not found in the action cache.")]
+    pub cache_misses: CounterWithTime,
+    #[metric(
+        help = "The number of actions found in the action cache whose outputs are missing from the CAS."
+    )]
+    pub cache_hits_missing_outputs: CounterWithTime,
 }
 
 async fn get_action_from_store(
@@ -90,6 +107,24 @@ async fn get_action_from_store(
     }
 }
 
+/// Returns true if `ac_store` is a `CompletenessCheckingStore` holding an
+/// entry for `action_digest` whose outputs are missing from the CAS. Only
+/// called once the entry was not found, so hits are not slowed down.
+async fn has_entry_with_missing_outputs(ac_store: &Store, action_digest: DigestInfo) -> bool {
+    let Some(completeness_checking_store) =
+        ac_store.downcast_ref::<CompletenessCheckingStore>(Some(action_digest.into()))
+    else {
+        return false;
+    };
+    matches!(
+        completeness_checking_store
+            .ac_store()
+            .has(action_digest)
+            .await,
+        Ok(Some(_))
+    )
+}
+
 /// Future for when `ActionStateResults` are known.
 type ActionStateResultOneshot = oneshot::Receiver<Result<Box<dyn ActionStateResult>, Error>>;
 
@@ -148,9 +183,14 @@ impl CacheLookupScheduler {
             ac_store,
             action_scheduler,
             inflight_cache_checks: Arc::default(),
+            metrics: Arc::default(),
         })
     }
 
+    pub fn metrics(&self) -> &Arc<CacheLookupMetrics> {
+        &self.metrics
+    }
+
     async fn inner_add_action(
         &self,
         client_operation_id: OperationId,
@@ -208,6 +248,7 @@ impl CacheLookupScheduler {
         let ac_store = self.ac_store.clone();
         let action_scheduler = self.action_scheduler.clone();
         let inflight_cache_checks = self.inflight_cache_checks.clone();
+        let metrics = self.metrics.clone();
         // We need this spawn because we are returning a stream and this spawn will populate the stream's data.
         background_spawn!("cache_lookup_scheduler_add_action", async move {
             // If our spawn ever dies, we will remove the action from the inflight_cache_checks map.
@@ -236,6 +277,7 @@ impl CacheLookupScheduler {
             .await;
             match maybe_action_result {
                 Ok(action_result) => {
+                    metrics.cache_hits.inc();
                     let maybe_pending_txs = {
                         let mut inflight_cache_checks = inflight_cache_checks.lock();
                         // We are ready to resolve the in-flight actions. We remove the
@@ -280,6 +322,11 @@ impl CacheLookupScheduler {
                         }
                         return;
                     }
+                    if has_entry_with_missing_outputs(&ac_store, action_info.digest()).await {
+                        metrics.cache_hits_missing_outputs.inc();
+                    } else {
+                        metrics.cache_misses.inc();
+                    }
                 }
             }
 
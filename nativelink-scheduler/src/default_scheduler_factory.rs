@@ -12,18 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+use nativelink_config::cas_server::SchedulerRefName;
 use nativelink_config::schedulers::{
     ExperimentalSimpleSchedulerBackend, SchedulerSpec, SimpleSpec,
 };
-use nativelink_config::stores::EvictionPolicy;
+use nativelink_config::stores::{EvictionPolicy, StoreRefName, StoreSpec};
 use nativelink_error::{make_input_err, Error, ResultExt};
 use nativelink_store::redis_store::RedisStore;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::operation_state_manager::ClientStateManager;
+use nativelink_util::tls_utils;
 use tokio::sync::Notify;
 
 use crate::cache_lookup_scheduler::CacheLookupScheduler;
@@ -32,12 +35,18 @@ use crate::memory_awaited_action_db::MemoryAwaitedActionDb;
 use crate::property_modifier_scheduler::PropertyModifierScheduler;
 use crate::simple_scheduler::SimpleScheduler;
 use crate::store_awaited_action_db::StoreAwaitedActionDb;
+use crate::touch_running_actions::spawn_touch_running_action_inputs;
 use crate::worker_scheduler::WorkerScheduler;
 
 /// Default timeout for recently completed actions in seconds.
 /// If this changes, remember to change the documentation in the config.
 const DEFAULT_RETAIN_COMPLETED_FOR_S: u32 = 60;
 
+/// Default interval in seconds between touching the inputs of running
+/// actions.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_TOUCH_RUNNING_ACTION_INPUTS_INTERVAL_S: u64 = 60;
+
 pub type SchedulerFactoryResults = (
     Option<Arc<dyn ClientStateManager>>,
     Option<Arc<dyn WorkerScheduler>>,
@@ -87,12 +96,80 @@ fn inner_scheduler_factory(
     Ok(scheduler)
 }
 
+/// Checks `schedulers` against the configured `stores` without constructing
+/// any scheduler, so no connections are made. Every problem found is
+/// returned instead of stopping at the first one.
+pub fn validate_scheduler_specs(
+    schedulers: &HashMap<SchedulerRefName, SchedulerSpec>,
+    stores: &HashMap<StoreRefName, StoreSpec>,
+) -> Vec<Error> {
+    let mut errors = Vec::new();
+    // Sorted so that errors are reported in a stable order.
+    for (name, spec) in schedulers.iter().collect::<BTreeMap<_, _>>() {
+        validate_scheduler_spec(name, spec, stores, &mut errors);
+    }
+    errors
+}
+
+fn validate_scheduler_spec(
+    name: &str,
+    spec: &SchedulerSpec,
+    stores: &HashMap<StoreRefName, StoreSpec>,
+    errors: &mut Vec<Error>,
+) {
+    match spec {
+        SchedulerSpec::simple(spec) => {
+            if let Some(ExperimentalSimpleSchedulerBackend::redis(redis_config)) =
+                &spec.experimental_backend
+            {
+                match stores.get(&redis_config.redis_store) {
+                    Some(StoreSpec::redis_store(_)) => {}
+                    Some(_) => errors.push(make_input_err!(
+                        "'redis_store': '{}' of scheduler '{name}' must be a 'redis_store' store",
+                        redis_config.redis_store
+                    )),
+                    None => errors.push(make_input_err!(
+                        "'redis_store': '{}' of scheduler '{name}' does not exist",
+                        redis_config.redis_store
+                    )),
+                }
+            }
+        }
+        SchedulerSpec::grpc(spec) => {
+            if let Err(err) = tls_utils::endpoint(&spec.endpoint) {
+                errors.push(err.append(format!("In 'endpoint' of scheduler '{name}'")));
+            }
+        }
+        SchedulerSpec::cache_lookup(spec) => {
+            if !stores.contains_key(&spec.ac_store) {
+                errors.push(make_input_err!(
+                    "'ac_store': '{}' of scheduler '{name}' does not exist",
+                    spec.ac_store
+                ));
+            }
+            validate_scheduler_spec(name, &spec.scheduler, stores, errors);
+        }
+        SchedulerSpec::property_modifier(spec) => {
+            validate_scheduler_spec(name, &spec.scheduler, stores, errors);
+        }
+    }
+}
+
 fn simple_scheduler_factory(
     spec: &SimpleSpec,
     store_manager: &StoreManager,
     now_fn: fn() -> SystemTime,
 ) -> Result<SchedulerFactoryResults, Error> {
-    match spec
+    let touch_store = spec
+        .touch_running_action_inputs_store
+        .as_ref()
+        .map(|store_name| {
+            store_manager.get_store(store_name).err_tip(|| {
+                format!("'touch_running_action_inputs_store': '{store_name}' does not exist")
+            })
+        })
+        .transpose()?;
+    let (action_scheduler, worker_scheduler) = match spec
         .experimental_backend
         .as_ref()
         .unwrap_or(&ExperimentalSimpleSchedulerBackend::memory)
@@ -104,9 +181,7 @@ fn simple_scheduler_factory(
                 &task_change_notify.clone(),
                 SystemTime::now,
             );
-            let (action_scheduler, worker_scheduler) =
-                SimpleScheduler::new(spec, awaited_action_db, task_change_notify);
-            Ok((Some(action_scheduler), Some(worker_scheduler)))
+            SimpleScheduler::new(spec, awaited_action_db, task_change_notify)
         }
         ExperimentalSimpleSchedulerBackend::redis(redis_config) => {
             let store = store_manager
@@ -134,11 +209,22 @@ fn simple_scheduler_factory(
                 Default::default,
             )
             .err_tip(|| "In state_manager_factory::redis_state_manager")?;
-            let (action_scheduler, worker_scheduler) =
-                SimpleScheduler::new(spec, awaited_action_db, task_change_notify);
-            Ok((Some(action_scheduler), Some(worker_scheduler)))
+            SimpleScheduler::new(spec, awaited_action_db, task_change_notify)
+        }
+    };
+    if let Some(touch_store) = touch_store {
+        let mut interval_s = spec.touch_running_action_inputs_interval_s;
+        if interval_s == 0 {
+            interval_s = DEFAULT_TOUCH_RUNNING_ACTION_INPUTS_INTERVAL_S;
         }
+        let client_state_manager: Arc<dyn ClientStateManager> = action_scheduler.clone();
+        spawn_touch_running_action_inputs(
+            Arc::downgrade(&client_state_manager),
+            touch_store,
+            Duration::from_secs(interval_s),
+        );
     }
+    Ok((Some(action_scheduler), Some(worker_scheduler)))
 }
 
 pub fn memory_awaited_action_db_factory<I, NowFn>(
@@ -12,15 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
 use std::future::Future;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use futures::stream::unfold;
 use futures::{StreamExt, TryFutureExt};
 use nativelink_config::schedulers::GrpcSpec;
+use nativelink_config::stores::EvictionPolicy;
 use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
 use nativelink_proto::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
@@ -33,13 +33,14 @@ use nativelink_util::action_messages::{
     ActionInfo, ActionState, ActionUniqueQualifier, OperationId, DEFAULT_EXECUTION_PRIORITY,
 };
 use nativelink_util::connection_manager::ConnectionManager;
+use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::instant_wrapper::InstantWrapper;
 use nativelink_util::known_platform_property_provider::KnownPlatformPropertyProvider;
 use nativelink_util::operation_state_manager::{
     ActionStateResult, ActionStateResultStream, ClientStateManager, OperationFilter,
 };
 use nativelink_util::retry::{Retrier, RetryResult};
 use nativelink_util::{background_spawn, tls_utils};
-use parking_lot::Mutex;
 use rand::rngs::OsRng;
 use rand::Rng;
 use tokio::select;
@@ -83,15 +84,46 @@ impl ActionStateResult for GrpcActionStateResult {
     }
 }
 
+/// Default for `GrpcSpec::max_cached_instance_names`.
+const DEFAULT_MAX_CACHED_INSTANCE_NAMES: u64 = 1000;
+
+/// Default for `GrpcSpec::supported_properties_ttl_s`.
+const DEFAULT_SUPPORTED_PROPERTIES_TTL_S: u64 = 600;
+
+/// The platform properties an upstream instance name supports and when
+/// they were fetched.
+#[derive(Debug, Clone)]
+struct SupportedProperties {
+    properties: Vec<String>,
+    fetched_at: SystemTime,
+}
+
+/// Note: The `EvictingMap` is only used to bound the number of cached
+/// instance names, which is why the sizes are fixed.
+impl LenEntry for SupportedProperties {
+    #[inline]
+    fn len(&self) -> u64 {
+        0
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        true
+    }
+}
+
 #[derive(MetricsComponent)]
-pub struct GrpcScheduler {
+pub struct GrpcScheduler<I: InstantWrapper, NowFn: Fn() -> I> {
     #[metric(group = "property_managers")]
-    supported_props: Mutex<HashMap<String, Vec<String>>>,
+    supported_props: EvictingMap<String, SupportedProperties, I>,
+    #[metric(help = "Seconds before the supported properties of an instance are fetched again")]
+    supported_props_ttl_s: u64,
     retrier: Retrier,
     connection_manager: ConnectionManager,
+    now_fn: NowFn,
 }
 
-impl GrpcScheduler {
+impl GrpcScheduler<SystemTime, fn() -> SystemTime> {
     pub fn new(spec: &GrpcSpec) -> Result<Self, Error> {
         let jitter_amt = spec.retry.jitter;
         Self::new_with_jitter(
@@ -110,11 +142,42 @@ impl GrpcScheduler {
     pub fn new_with_jitter(
         spec: &GrpcSpec,
         jitter_fn: Box<dyn Fn(Duration) -> Duration + Send + Sync>,
+    ) -> Result<Self, Error> {
+        Self::new_with_now_fn(spec, jitter_fn, SystemTime::now)
+    }
+}
+
+impl<I, NowFn> GrpcScheduler<I, NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + 'static,
+{
+    pub fn new_with_now_fn(
+        spec: &GrpcSpec,
+        jitter_fn: Box<dyn Fn(Duration) -> Duration + Send + Sync>,
+        now_fn: NowFn,
     ) -> Result<Self, Error> {
         let endpoint = tls_utils::endpoint(&spec.endpoint)?;
         let jitter_fn = Arc::new(jitter_fn);
+        let max_cached_instance_names = if spec.max_cached_instance_names == 0 {
+            DEFAULT_MAX_CACHED_INSTANCE_NAMES
+        } else {
+            spec.max_cached_instance_names
+        };
+        let supported_props_ttl_s = if spec.supported_properties_ttl_s == 0 {
+            DEFAULT_SUPPORTED_PROPERTIES_TTL_S
+        } else {
+            spec.supported_properties_ttl_s
+        };
         Ok(Self {
-            supported_props: Mutex::new(HashMap::new()),
+            supported_props: EvictingMap::new(
+                &EvictionPolicy {
+                    max_count: max_cached_instance_names,
+                    ..Default::default()
+                },
+                (now_fn)(),
+            ),
+            supported_props_ttl_s,
             retrier: Retrier::new(
                 Arc::new(|duration| Box::pin(sleep(duration))),
                 jitter_fn.clone(),
@@ -127,15 +190,16 @@ impl GrpcScheduler {
                 spec.retry.clone(),
                 jitter_fn,
             ),
+            now_fn,
         })
     }
 
-    async fn perform_request<F, Fut, R, I>(&self, input: I, mut request: F) -> Result<R, Error>
+    async fn perform_request<F, Fut, R, In>(&self, input: In, mut request: F) -> Result<R, Error>
     where
-        F: FnMut(I) -> Fut + Send + Copy,
+        F: FnMut(In) -> Fut + Send + Copy,
         Fut: Future<Output = Result<R, Error>> + Send,
         R: Send,
-        I: Send + Clone,
+        In: Send + Clone,
     {
         self.retrier
             .retry(unfold(input, move |input| async move {
@@ -220,12 +284,18 @@ impl GrpcScheduler {
     }
 
     async fn inner_get_known_properties(&self, instance_name: &str) -> Result<Vec<String>, Error> {
-        if let Some(supported_props) = self.supported_props.lock().get(instance_name) {
-            return Ok(supported_props.clone());
+        if let Some(supported_props) = self.supported_props.get(&instance_name.to_string()).await {
+            let age = (self.now_fn)()
+                .now()
+                .duration_since(supported_props.fetched_at)
+                .unwrap_or_default();
+            if age < Duration::from_secs(self.supported_props_ttl_s) {
+                return Ok(supported_props.properties);
+            }
         }
 
         self.perform_request(instance_name, |instance_name| async move {
-            // Not in the cache, lookup the capabilities with the upstream.
+            // Not in the cache or stale, lookup the capabilities with the upstream.
             let channel = self
                 .connection_manager
                 .connection()
@@ -246,8 +316,14 @@ impl GrpcScheduler {
                 .collect::<Vec<String>>();
 
             self.supported_props
-                .lock()
-                .insert(instance_name.to_string(), supported_props.clone());
+                .insert(
+                    instance_name.to_string(),
+                    SupportedProperties {
+                        properties: supported_props.clone(),
+                        fetched_at: (self.now_fn)().now(),
+                    },
+                )
+                .await;
             Ok(supported_props)
         })
         .await
@@ -346,7 +422,11 @@ impl GrpcScheduler {
 }
 
 #[async_trait]
-impl ClientStateManager for GrpcScheduler {
+impl<I, NowFn> ClientStateManager for GrpcScheduler<I, NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
     async fn add_action(
         &self,
         client_operation_id: OperationId,
@@ -369,10 +449,19 @@ impl ClientStateManager for GrpcScheduler {
 }
 
 #[async_trait]
-impl KnownPlatformPropertyProvider for GrpcScheduler {
+impl<I, NowFn> KnownPlatformPropertyProvider for GrpcScheduler<I, NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
     async fn get_known_properties(&self, instance_name: &str) -> Result<Vec<String>, Error> {
         self.inner_get_known_properties(instance_name).await
     }
 }
 
-impl RootMetricsComponent for GrpcScheduler {}
+impl<I, NowFn> RootMetricsComponent for GrpcScheduler<I, NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + 'static,
+{
+}
@@ -23,5 +23,6 @@ pub mod property_modifier_scheduler;
 pub mod simple_scheduler;
 mod simple_scheduler_state_manager;
 pub mod store_awaited_action_db;
+pub mod touch_running_actions;
 pub mod worker;
 pub mod worker_scheduler;
@@ -664,6 +664,7 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
         &mut self,
         client_operation_id: OperationId,
         action_info: Arc<ActionInfo>,
+        max_queued_actions: usize,
     ) -> Result<MemoryAwaitedActionSubscriber<I, NowFn>, Error> {
         // Check to see if the action is already known and subscribe if it is.
         let subscription_result = self
@@ -680,6 +681,14 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync> AwaitedActionDbI
             Ok(None) => { /* Add item to queue. */ }
         }
 
+        let queued_actions = self.sorted_action_info_hash_keys.queued.len();
+        if max_queued_actions != 0 && queued_actions >= max_queued_actions {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Scheduler queue is full ({queued_actions} of {max_queued_actions} actions queued), try again later",
+            ));
+        }
+
         let maybe_unique_key = match &action_info.unique_qualifier {
             ActionUniqueQualifier::Cachable(unique_key) => Some(unique_key.clone()),
             ActionUniqueQualifier::Uncachable(_unique_key) => None,
@@ -960,12 +969,13 @@ impl<I: InstantWrapper, NowFn: Fn() -> I + Clone + Send + Sync + 'static> Awaite
         &self,
         client_operation_id: OperationId,
         action_info: Arc<ActionInfo>,
+        max_queued_actions: usize,
     ) -> Result<Self::Subscriber, Error> {
         let subscriber = self
             .inner
             .lock()
             .await
-            .add_action(client_operation_id, action_info)
+            .add_action(client_operation_id, action_info, max_queued_actions)
             .await?;
         self.tasks_change_notify.notify_one();
         Ok(subscriber)
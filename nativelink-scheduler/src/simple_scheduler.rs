@@ -12,11 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ops::Bound;
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use async_trait::async_trait;
-use futures::Future;
+use futures::{stream, Future};
 use nativelink_config::schedulers::SimpleSpec;
 use nativelink_error::{Code, Error, ResultExt};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
@@ -35,7 +36,9 @@ use tokio_stream::StreamExt;
 use tracing::{event, Level};
 
 use crate::api_worker_scheduler::ApiWorkerScheduler;
-use crate::awaited_action_db::AwaitedActionDb;
+use crate::awaited_action_db::{
+    AwaitedActionDb, OperationSummary, SortedAwaitedAction, SortedAwaitedActionState,
+};
 use crate::platform_property_manager::PlatformPropertyManager;
 use crate::simple_scheduler_state_manager::SimpleSchedulerStateManager;
 use crate::worker::{ActionInfoWithProps, Worker, WorkerTimestamp};
@@ -127,6 +130,11 @@ pub struct SimpleScheduler {
     #[metric(group = "worker_scheduler")]
     worker_scheduler: Arc<ApiWorkerScheduler>,
 
+    /// If running actions with a lower priority may be preempted when a
+    /// queued action cannot be matched to any worker.
+    #[metric(help = "If lower priority actions may be preempted.")]
+    preempt_lower_priority_actions: bool,
+
     /// Background task that tries to match actions to workers. If this struct
     /// is dropped the spawn will be cancelled as well.
     _task_worker_matching_spawn: JoinHandleDropGuard<()>,
@@ -156,10 +164,67 @@ impl SimpleScheduler {
         )))
     }
 
+    /// Finds an existing operation using the id the client knows it by.
+    /// The id is first resolved as a client operation id and, if no client
+    /// operation is registered under it, as the scheduler's own operation id.
+    /// This allows a client that only kept the operation name to re-subscribe
+    /// to an action, including one that already completed but is still held
+    /// by the `AwaitedActionDb`.
+    pub async fn find_existing_action(
+        &self,
+        operation_id: &OperationId,
+    ) -> Result<Option<Box<dyn ActionStateResult>>, Error> {
+        let maybe_action_state_result = self
+            .client_state_manager
+            .filter_operations(OperationFilter {
+                client_operation_id: Some(operation_id.clone()),
+                ..Default::default()
+            })
+            .await
+            .err_tip(|| "In SimpleScheduler::find_existing_action by client_operation_id")?
+            .next()
+            .await;
+        if maybe_action_state_result.is_some() {
+            return Ok(maybe_action_state_result);
+        }
+        let maybe_action_state_result = self
+            .client_state_manager
+            .filter_operations(OperationFilter {
+                operation_id: Some(operation_id.clone()),
+                ..Default::default()
+            })
+            .await
+            .err_tip(|| "In SimpleScheduler::find_existing_action by operation_id")?
+            .next()
+            .await;
+        Ok(maybe_action_state_result.map(|action_state_result| {
+            // Ensure the client only ever sees the id it used to look up the operation.
+            Box::new(SimpleSchedulerActionStateResult::new(
+                operation_id.clone(),
+                action_state_result,
+            )) as Box<dyn ActionStateResult>
+        }))
+    }
+
     async fn inner_filter_operations(
         &self,
         filter: OperationFilter,
     ) -> Result<ActionStateResultStream, Error> {
+        // A lookup by only the client operation id is how clients reconnect
+        // (eg: `WaitExecution`), so allow it to resolve by operation id too.
+        if let Some(client_operation_id) = &filter.client_operation_id {
+            let lookup_only_filter = OperationFilter {
+                client_operation_id: Some(client_operation_id.clone()),
+                ..Default::default()
+            };
+            if filter == lookup_only_filter {
+                let maybe_action_state_result = self
+                    .find_existing_action(client_operation_id)
+                    .await
+                    .err_tip(|| "In SimpleScheduler::filter_operations")?;
+                return Ok(Box::pin(stream::iter(maybe_action_state_result)));
+            }
+        }
         self.client_state_manager
             .filter_operations(filter)
             .await
@@ -191,6 +256,7 @@ impl SimpleScheduler {
             workers: &ApiWorkerScheduler,
             matching_engine_state_manager: &dyn MatchingEngineStateManager,
             platform_property_manager: &PlatformPropertyManager,
+            preempt_lower_priority_actions: bool,
         ) -> Result<(), Error> {
             let action_info = action_state_result
                 .as_action_info()
@@ -217,6 +283,19 @@ impl SimpleScheduler {
                     .await
                 {
                     Some(worker_id) => worker_id,
+                    // The action stays queued until the preempted action has
+                    // been killed and its worker frees up.
+                    None if preempt_lower_priority_actions => {
+                        return workers
+                            .preempt_worker_for_action(
+                                &action_info.platform_properties,
+                                action_info.inner.priority,
+                            )
+                            .await
+                            .err_tip(|| {
+                                "Failed to preempt action in SimpleScheduler::do_try_match"
+                            });
+                    }
                     // If we could not find a worker for the action,
                     // we have nothing to do.
                     None => return Ok(()),
@@ -272,6 +351,7 @@ impl SimpleScheduler {
                     self.worker_scheduler.as_ref(),
                     self.matching_engine_state_manager.as_ref(),
                     self.platform_property_manager.as_ref(),
+                    self.preempt_lower_priority_actions,
                 )
                 .await,
             );
@@ -342,6 +422,7 @@ impl SimpleScheduler {
         let worker_change_notify = Arc::new(Notify::new());
         let state_manager = SimpleSchedulerStateManager::new(
             max_job_retries,
+            spec.max_queued_actions,
             Duration::from_secs(worker_timeout_s),
             Duration::from_secs(client_action_timeout_s),
             awaited_action_db,
@@ -354,9 +435,11 @@ impl SimpleScheduler {
             spec.allocation_strategy,
             worker_change_notify.clone(),
             worker_timeout_s,
+            Some(state_manager.clone()),
         );
 
         let worker_scheduler_clone = worker_scheduler.clone();
+        let preempt_lower_priority_actions = spec.preempt_lower_priority_actions;
 
         let action_scheduler = Arc::new_cyclic(move |weak_self| -> Self {
             let weak_inner = weak_self.clone();
@@ -389,6 +472,7 @@ impl SimpleScheduler {
                 client_state_manager: state_manager.clone(),
                 worker_scheduler,
                 platform_property_manager,
+                preempt_lower_priority_actions,
                 _task_worker_matching_spawn: task_worker_matching_spawn,
             }
         });
@@ -478,6 +562,27 @@ impl WorkerScheduler for SimpleScheduler {
             .set_drain_worker(worker_id, is_draining)
             .await
     }
+
+    async fn set_worker_intake_paused(
+        &self,
+        worker_id: &WorkerId,
+        is_intake_paused: bool,
+    ) -> Result<(), Error> {
+        self.worker_scheduler
+            .set_worker_intake_paused(worker_id, is_intake_paused)
+            .await
+    }
+
+    async fn list_operations(
+        &self,
+        state: SortedAwaitedActionState,
+        start: Bound<SortedAwaitedAction>,
+        limit: usize,
+    ) -> Result<Vec<OperationSummary>, Error> {
+        self.worker_scheduler
+            .list_operations(state, start, limit)
+            .await
+    }
 }
 
 impl RootMetricsComponent for SimpleScheduler {}
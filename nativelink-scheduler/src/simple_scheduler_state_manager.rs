@@ -35,7 +35,8 @@ use nativelink_util::operation_state_manager::{
 use tracing::{event, Level};
 
 use super::awaited_action_db::{
-    AwaitedAction, AwaitedActionDb, AwaitedActionSubscriber, SortedAwaitedActionState,
+    AwaitedAction, AwaitedActionDb, AwaitedActionSubscriber, OperationLister, OperationSummary,
+    SortedAwaitedAction, SortedAwaitedActionState,
 };
 
 /// Maximum number of times an update to the database
@@ -257,6 +258,11 @@ where
     #[metric(help = "Maximum number of times a job can be retried")]
     max_job_retries: usize,
 
+    /// Maximum number of queued actions before new actions are rejected.
+    /// Zero means unlimited.
+    #[metric(help = "Maximum number of queued actions before new actions are rejected.")]
+    max_queued_actions: usize,
+
     /// Duration after which an action is considered to be timed out if
     /// no event is received.
     #[metric(
@@ -291,6 +297,7 @@ where
 {
     pub fn new(
         max_job_retries: usize,
+        max_queued_actions: usize,
         no_event_action_timeout: Duration,
         client_action_timeout: Duration,
         action_db: T,
@@ -299,6 +306,7 @@ where
         Arc::new_cyclic(|weak_self| Self {
             action_db,
             max_job_retries,
+            max_queued_actions,
             no_event_action_timeout,
             client_action_timeout,
             timeout_operation_mux: Mutex::new(()),
@@ -617,7 +625,11 @@ where
         action_info: Arc<ActionInfo>,
     ) -> Result<T::Subscriber, Error> {
         self.action_db
-            .add_action(new_client_operation_id, action_info)
+            .add_action(
+                new_client_operation_id,
+                action_info,
+                self.max_queued_actions,
+            )
             .await
             .err_tip(|| "In SimpleSchedulerStateManager::add_operation")
     }
@@ -864,3 +876,30 @@ where
             .await
     }
 }
+
+#[async_trait]
+impl<T, I, NowFn> OperationLister for SimpleSchedulerStateManager<T, I, NowFn>
+where
+    T: AwaitedActionDb,
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Clone + Send + Unpin + Sync + 'static,
+{
+    async fn list_operations(
+        &self,
+        state: SortedAwaitedActionState,
+        start: Bound<SortedAwaitedAction>,
+        limit: usize,
+    ) -> Result<Vec<OperationSummary>, Error> {
+        let now = (self.now_fn)().now();
+        self.action_db
+            .get_range_of_actions(state, start, Bound::Unbounded, false)
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::list_operations")?
+            .take(limit)
+            .and_then(|subscriber| async move { subscriber.borrow().await })
+            .map_ok(|awaited_action| OperationSummary::new(&awaited_action, state, now))
+            .try_collect()
+            .await
+            .err_tip(|| "In SimpleSchedulerStateManager::list_operations")
+    }
+}
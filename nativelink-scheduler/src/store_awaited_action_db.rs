@@ -13,13 +13,13 @@
 // limitations under the License.
 
 use std::borrow::Cow;
-use std::ops::Bound;
+use std::ops::{Bound, RangeBounds};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 use std::time::Duration;
 
 use bytes::Bytes;
-use futures::{Stream, TryStreamExt};
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::action_messages::{
@@ -568,6 +568,7 @@ where
         &self,
         client_operation_id: ClientOperationId,
         action_info: Arc<ActionInfo>,
+        max_queued_actions: usize,
     ) -> Result<Self::Subscriber, Error> {
         // Check to see if the action is already known and subscribe if it is.
         let subscription = self
@@ -582,6 +583,29 @@ where
             return Ok(sub);
         }
 
+        if max_queued_actions != 0 {
+            // The queue may be shared with other schedulers, so it is
+            // counted in the store. Counting stops at the limit, so a full
+            // queue is not scanned on every new action.
+            let queued_actions = self
+                .store
+                .search_by_index_prefix(SearchStateToAwaitedAction(get_state_prefix(
+                    SortedAwaitedActionState::Queued,
+                )))
+                .await
+                .err_tip(|| "In RedisAwaitedActionDb::add_action")?
+                .take(max_queued_actions)
+                .try_fold(0, |count, _| async move { Ok(count + 1) })
+                .await
+                .err_tip(|| "In RedisAwaitedActionDb::add_action")?;
+            if queued_actions >= max_queued_actions {
+                return Err(make_err!(
+                    Code::ResourceExhausted,
+                    "Scheduler queue is full ({queued_actions} of {max_queued_actions} actions queued), try again later",
+                ));
+            }
+        }
+
         let new_operation_id = (self.operation_id_creator)();
         let awaited_action =
             AwaitedAction::new(new_operation_id.clone(), action_info, (self.now_fn)().now());
@@ -623,39 +647,50 @@ where
         end: Bound<SortedAwaitedAction>,
         desc: bool,
     ) -> Result<impl Stream<Item = Result<Self::Subscriber, Error>> + Send, Error> {
-        if !matches!(start, Bound::Unbounded) {
-            return Err(make_err!(
-                Code::Unimplemented,
-                "Start bound is not supported in RedisAwaitedActionDb::get_range_of_actions",
-            ));
-        }
-        if !matches!(end, Bound::Unbounded) {
-            return Err(make_err!(
-                Code::Unimplemented,
-                "Start bound is not supported in RedisAwaitedActionDb::get_range_of_actions",
-            ));
-        }
-        // TODO(allada) This API is not difficult to implement, but there is no code path
-        // that uses it, so no reason to implement it yet.
-        if !desc {
-            return Err(make_err!(
-                Code::Unimplemented,
-                "Descending order is not supported in RedisAwaitedActionDb::get_range_of_actions",
-            ));
-        }
-        Ok(self
+        let awaited_actions = self
             .store
             .search_by_index_prefix(SearchStateToAwaitedAction(get_state_prefix(state)))
             .await
-            .err_tip(|| "In RedisAwaitedActionDb::get_range_of_actions")?
-            .map_ok(move |awaited_action| {
-                OperationSubscriber::new(
-                    None,
-                    OperationIdToAwaitedAction(Cow::Owned(awaited_action.operation_id().clone())),
-                    Arc::downgrade(&self.store),
-                    self.now_fn.clone(),
+            .err_tip(|| "In RedisAwaitedActionDb::get_range_of_actions")?;
+        let to_subscriber = move |awaited_action: AwaitedAction| {
+            OperationSubscriber::new(
+                None,
+                OperationIdToAwaitedAction(Cow::Owned(awaited_action.operation_id().clone())),
+                Arc::downgrade(&self.store),
+                self.now_fn.clone(),
+            )
+        };
+        // The scheduler walks whole states, which are streamed in the order
+        // of the sort key index.
+        if matches!(
+            (&start, &end, desc),
+            (Bound::Unbounded, Bound::Unbounded, true)
+        ) {
+            return Ok(awaited_actions.map_ok(to_subscriber).left_stream());
+        }
+        // Ranges are only used to page through the operations of a state,
+        // which needs the exact order and bounds of `SortedAwaitedAction`,
+        // so the state is filtered and sorted here.
+        let mut awaited_actions: Vec<AwaitedAction> = awaited_actions
+            .try_filter(|awaited_action| {
+                future::ready(
+                    (start.as_ref(), end.as_ref())
+                        .contains(&SortedAwaitedAction::from(awaited_action)),
                 )
-            }))
+            })
+            .try_collect()
+            .await
+            .err_tip(|| "In RedisAwaitedActionDb::get_range_of_actions")?;
+        awaited_actions.sort_unstable_by_key(SortedAwaitedAction::from);
+        if desc {
+            awaited_actions.reverse();
+        }
+        Ok(stream::iter(
+            awaited_actions
+                .into_iter()
+                .map(move |awaited_action| Ok::<_, Error>(to_subscriber(awaited_action))),
+        )
+        .right_stream())
     }
 
     async fn get_all_awaited_actions(
@@ -0,0 +1,222 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Weak;
+use std::time::Duration;
+
+use nativelink_error::{Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::Directory;
+use nativelink_store::ac_utils::get_and_decode_digest;
+use nativelink_util::action_messages::{ActionInfo, ActionResult, ActionStage, OperationId};
+use nativelink_util::background_spawn;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::operation_state_manager::{
+    ActionStateResult, ClientStateManager, OperationFilter, OperationStageFlags,
+};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
+use tokio_stream::StreamExt;
+use tracing::{event, Level};
+
+/// Collects every digest an action needs to run: the action itself, its
+/// command and the full input root tree.
+/// Outputs are only known once the action completes, so they are not part
+/// of the result.
+pub async fn action_input_digests(
+    cas_store: &Store,
+    action_info: &ActionInfo,
+) -> Result<Vec<DigestInfo>, Error> {
+    let mut digests = vec![
+        action_info.digest(),
+        action_info.command_digest,
+        action_info.input_root_digest,
+    ];
+    let mut seen_directories = HashSet::new();
+    let mut pending_directories = vec![action_info.input_root_digest];
+    while let Some(directory_digest) = pending_directories.pop() {
+        if !seen_directories.insert(directory_digest) {
+            continue;
+        }
+        let directory = get_and_decode_digest::<Directory>(cas_store, directory_digest.into())
+            .await
+            .err_tip(|| "Converting digest to Directory in action_input_digests")?;
+        for file in directory.files {
+            let digest: DigestInfo = file
+                .digest
+                .err_tip(|| "Expected Digest to exist in Directory::file::digest")?
+                .try_into()
+                .err_tip(|| "In Directory::file::digest")?;
+            digests.push(digest);
+        }
+        for child in directory.directories {
+            let digest: DigestInfo = child
+                .digest
+                .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+                .try_into()
+                .err_tip(|| "In Directory::directories::digest")?;
+            digests.push(digest);
+            pending_directories.push(digest);
+        }
+    }
+    Ok(digests)
+}
+
+/// Upper bound on the number of input keys kept between calls to
+/// [`touch_running_action_inputs`]. Inputs of actions that do not fit are
+/// collected again from their input tree on the next call.
+const MAX_CACHED_INPUT_KEYS: usize = 1 << 20;
+
+/// Touches the inputs of every executing action and the outputs of every
+/// completed action in `cas_store`, so they are not evicted while a worker
+/// may still need to fetch them or before the client has downloaded them.
+/// `inputs_for_operation` caches the input keys of each operation between
+/// calls, so the input tree of an action is usually only read once. At most
+/// [`MAX_CACHED_INPUT_KEYS`] keys are cached and operations that are no
+/// longer executing are dropped from it. Failing to touch one action is
+/// logged and does not stop the others from being touched.
+pub async fn touch_running_action_inputs(
+    client_state_manager: &dyn ClientStateManager,
+    cas_store: &Store,
+    inputs_for_operation: &mut HashMap<OperationId, Vec<StoreKey<'static>>>,
+) -> Result<u64, Error> {
+    let mut action_state_results = client_state_manager
+        .filter_operations(OperationFilter {
+            stages: OperationStageFlags::Executing | OperationStageFlags::Completed,
+            ..Default::default()
+        })
+        .await
+        .err_tip(|| "In touch_running_action_inputs")?;
+    let mut running_inputs = HashMap::with_capacity(inputs_for_operation.len());
+    let mut cached_keys = 0;
+    let mut touched = 0;
+    while let Some(action_state_result) = action_state_results.next().await {
+        match touch_action(
+            action_state_result.as_ref(),
+            cas_store,
+            inputs_for_operation,
+            &mut running_inputs,
+            &mut cached_keys,
+        )
+        .await
+        {
+            Ok(action_touched) => touched += action_touched,
+            Err(err) => {
+                event!(Level::WARN, ?err, "Failed to touch running action");
+            }
+        }
+    }
+    *inputs_for_operation = running_inputs;
+    Ok(touched)
+}
+
+/// Touches the inputs of an executing action or the outputs of a completed
+/// one. Input keys are taken from `cached_inputs` if they are known and
+/// recorded in `running_inputs` while fewer than [`MAX_CACHED_INPUT_KEYS`]
+/// keys are cached.
+async fn touch_action(
+    action_state_result: &dyn ActionStateResult,
+    cas_store: &Store,
+    cached_inputs: &mut HashMap<OperationId, Vec<StoreKey<'static>>>,
+    running_inputs: &mut HashMap<OperationId, Vec<StoreKey<'static>>>,
+    cached_keys: &mut usize,
+) -> Result<u64, Error> {
+    let action_state = action_state_result
+        .as_state()
+        .await
+        .err_tip(|| "In touch_action")?;
+    let operation_id = &action_state.client_operation_id;
+    if let ActionStage::Completed(action_result) = &action_state.stage {
+        let keys: Vec<StoreKey> = action_output_digests(action_result)
+            .into_iter()
+            .map(StoreKey::from)
+            .collect();
+        return cas_store
+            .touch(&keys)
+            .await
+            .err_tip(|| format!("Touching outputs of {operation_id}"));
+    }
+    let keys = match cached_inputs.remove(operation_id) {
+        Some(keys) => keys,
+        None => {
+            let action_info = action_state_result
+                .as_action_info()
+                .await
+                .err_tip(|| "In touch_action")?;
+            action_input_digests(cas_store, &action_info)
+                .await
+                .err_tip(|| format!("Collecting inputs of {operation_id}"))?
+                .into_iter()
+                .map(StoreKey::from)
+                .collect()
+        }
+    };
+    let touched = cas_store
+        .touch(&keys)
+        .await
+        .err_tip(|| format!("Touching inputs of {operation_id}"));
+    if *cached_keys + keys.len() <= MAX_CACHED_INPUT_KEYS {
+        *cached_keys += keys.len();
+        running_inputs.insert(operation_id.clone(), keys);
+    }
+    touched
+}
+
+/// Collects the digests of the outputs, stdout and stderr of a completed
+/// action. Inlined stdout and stderr are not in the CAS and are skipped.
+fn action_output_digests(action_result: &ActionResult) -> Vec<DigestInfo> {
+    action_result
+        .output_files
+        .iter()
+        .map(|file| file.digest)
+        .chain(
+            action_result
+                .output_folders
+                .iter()
+                .map(|folder| folder.tree_digest),
+        )
+        .chain(action_result.stdout_digest)
+        .chain(action_result.stderr_digest)
+        .collect()
+}
+
+/// Spawns a task that calls [`touch_running_action_inputs`] every
+/// `interval`. The task stops once `client_state_manager` is dropped.
+pub fn spawn_touch_running_action_inputs(
+    client_state_manager: Weak<dyn ClientStateManager>,
+    cas_store: Store,
+    interval: Duration,
+) {
+    background_spawn!("touch_running_action_inputs", async move {
+        let mut inputs_for_operation = HashMap::new();
+        loop {
+            tokio::time::sleep(interval).await;
+            let Some(client_state_manager) = client_state_manager.upgrade() else {
+                return;
+            };
+            if let Err(err) = touch_running_action_inputs(
+                client_state_manager.as_ref(),
+                &cas_store,
+                &mut inputs_for_operation,
+            )
+            .await
+            {
+                event!(
+                    Level::WARN,
+                    ?err,
+                    "Failed to touch inputs of running actions"
+                );
+            }
+        }
+    });
+}
@@ -20,12 +20,13 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    update_for_worker, ConnectionResult, StartExecute, UpdateForWorker,
+    update_for_worker, ConnectionResult, KillOperationRequest, StartExecute, UpdateForWorker,
 };
 use nativelink_util::action_messages::{ActionInfo, OperationId, WorkerId};
 use nativelink_util::metrics_utils::{CounterWithTime, FuncCounterWrapper};
 use nativelink_util::platform_properties::{PlatformProperties, PlatformPropertyValue};
 use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, info_span, Level};
 
 pub type WorkerTimestamp = u64;
 
@@ -71,6 +72,11 @@ pub struct Worker {
     #[metric(group = "running_action_infos")]
     pub running_action_infos: HashMap<OperationId, ActionInfoWithProps>,
 
+    /// Operations that were preempted on this worker, but the worker has
+    /// not yet reported as finished. Their platform properties stay
+    /// reserved until the worker confirms they were killed.
+    pub preempted_operations: HashMap<OperationId, ActionInfoWithProps>,
+
     /// Timestamp of last time this worker had been communicated with.
     // Warning: Do not update this timestamp without updating the placement of the worker in
     // the LRUCache in the Workers struct.
@@ -85,6 +91,11 @@ pub struct Worker {
     #[metric(help = "If the worker is draining.")]
     pub is_draining: bool,
 
+    /// Whether the worker asked not to be given new actions, for example
+    /// because it is low on disk space.
+    #[metric(help = "If the worker paused its own intake of actions.")]
+    pub is_intake_paused: bool,
+
     /// Stats about the worker.
     #[metric]
     metrics: Arc<Metrics>,
@@ -130,15 +141,18 @@ impl Worker {
             platform_properties,
             tx,
             running_action_infos: HashMap::new(),
+            preempted_operations: HashMap::new(),
             last_update_timestamp: timestamp,
             is_paused: false,
             is_draining: false,
+            is_intake_paused: false,
             metrics: Arc::new(Metrics {
                 connected_timestamp: SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
                     .as_secs(),
                 actions_completed: CounterWithTime::default(),
+                actions_preempted: CounterWithTime::default(),
                 run_action: FuncCounterWrapper::default(),
                 keep_alive: FuncCounterWrapper::default(),
                 notify_disconnect: CounterWithTime::default(),
@@ -185,6 +199,14 @@ impl Worker {
         operation_id: OperationId,
         action_info: ActionInfoWithProps,
     ) -> Result<(), Error> {
+        // The operation id doubles as the trace id of the action, so logs
+        // on the scheduler and the worker can be correlated.
+        let _span =
+            info_span!("run_action", trace_id = %operation_id, worker_id = %self.id).entered();
+        event!(Level::DEBUG, "Sending action to worker");
+        // The operation may be re-assigned to this worker before the worker
+        // has confirmed the kill of its preempted run.
+        self.finish_preempted_action(&operation_id);
         let tx = &mut self.tx;
         let worker_platform_properties = &mut self.platform_properties;
         let running_action_infos = &mut self.running_action_infos;
@@ -220,6 +242,66 @@ impl Worker {
         Ok(())
     }
 
+    /// Stops tracking the operation as running on this worker and requests
+    /// the worker to kill it. Its resources stay reserved until the worker
+    /// reports the operation as finished, see `finish_preempted_action`.
+    pub(crate) fn preempt_action(&mut self, operation_id: &OperationId) -> Result<(), Error> {
+        let action_info = self.running_action_infos.remove(operation_id).err_tip(|| {
+            format!(
+                "Worker {} tried to preempt operation {} that was not running",
+                self.id, operation_id
+            )
+        })?;
+        self.preempted_operations
+            .insert(operation_id.clone(), action_info);
+        self.metrics.actions_preempted.inc();
+        send_msg_to_worker(
+            &mut self.tx,
+            update_for_worker::Update::KillOperationRequest(KillOperationRequest {
+                operation_id: operation_id.to_string(),
+            }),
+        )
+        .err_tip(|| {
+            format!(
+                "Failed to send KillOperationRequest to worker : {}",
+                self.id
+            )
+        })
+    }
+
+    /// Releases the resources of a preempted operation once the worker has
+    /// reported it as finished. Returns false if the operation was not
+    /// preempted on this worker.
+    pub(crate) fn finish_preempted_action(&mut self, operation_id: &OperationId) -> bool {
+        let Some(action_info) = self.preempted_operations.remove(operation_id) else {
+            return false;
+        };
+        self.restore_platform_properties(&action_info.platform_properties);
+        true
+    }
+
+    /// Returns the platform properties this worker would have available once
+    /// all pending preemptions have finished and the given running actions
+    /// were no longer running on it.
+    pub(crate) fn platform_properties_without<'a>(
+        &'a self,
+        action_infos: impl IntoIterator<Item = &'a ActionInfoWithProps>,
+    ) -> PlatformProperties {
+        let mut platform_properties = self.platform_properties.clone();
+        for action_info in self.preempted_operations.values().chain(action_infos) {
+            for (property, prop_value) in &action_info.platform_properties.properties {
+                if let PlatformPropertyValue::Minimum(value) = prop_value {
+                    if let Some(PlatformPropertyValue::Minimum(worker_value)) =
+                        platform_properties.properties.get_mut(property)
+                    {
+                        *worker_value += value;
+                    }
+                }
+            }
+        }
+        platform_properties
+    }
+
     pub fn has_actions(&self) -> bool {
         !self.running_action_infos.is_empty()
     }
@@ -238,7 +320,7 @@ impl Worker {
     }
 
     pub fn can_accept_work(&self) -> bool {
-        !self.is_paused && !self.is_draining
+        !self.is_paused && !self.is_draining && !self.is_intake_paused
     }
 }
 
@@ -262,6 +344,8 @@ struct Metrics {
     connected_timestamp: u64,
     #[metric(help = "The number of actions completed for this worker.")]
     actions_completed: CounterWithTime,
+    #[metric(help = "The number of actions preempted on this worker.")]
+    actions_preempted: CounterWithTime,
     #[metric(help = "The number of actions started for this worker.")]
     run_action: FuncCounterWrapper,
     #[metric(help = "The number of keep_alive sent to this worker.")]
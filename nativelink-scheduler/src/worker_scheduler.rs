@@ -12,12 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ops::Bound;
+
 use async_trait::async_trait;
 use nativelink_error::Error;
 use nativelink_metric::RootMetricsComponent;
 use nativelink_util::action_messages::{OperationId, WorkerId};
 use nativelink_util::operation_state_manager::UpdateOperationType;
 
+use crate::awaited_action_db::{OperationSummary, SortedAwaitedAction, SortedAwaitedActionState};
 use crate::platform_property_manager::PlatformPropertyManager;
 use crate::worker::{Worker, WorkerTimestamp};
 
@@ -55,4 +58,20 @@ pub trait WorkerScheduler: Sync + Send + Unpin + RootMetricsComponent + 'static
 
     /// Sets if the worker is draining or not.
     async fn set_drain_worker(&self, worker_id: &WorkerId, is_draining: bool) -> Result<(), Error>;
+
+    /// Sets if the worker asked not to be given new actions.
+    async fn set_worker_intake_paused(
+        &self,
+        worker_id: &WorkerId,
+        is_intake_paused: bool,
+    ) -> Result<(), Error>;
+
+    /// Lists up to `limit` operations in `state` in sorted order, starting
+    /// at `start`. See `OperationLister::list_operations`.
+    async fn list_operations(
+        &self,
+        state: SortedAwaitedActionState,
+        start: Bound<SortedAwaitedAction>,
+        limit: usize,
+    ) -> Result<Vec<OperationSummary>, Error>;
 }
@@ -68,8 +68,10 @@ async fn execute_response_status_message_is_some_on_success_test() -> Result<(),
         output_file_symlinks: vec![],
         output_directory_symlinks: vec![],
         exit_code: 0,
-        stdout_digest: DigestInfo::new([2u8; 32], 5),
-        stderr_digest: DigestInfo::new([3u8; 32], 5),
+        stdout_digest: Some(DigestInfo::new([2u8; 32], 5)),
+        stderr_digest: Some(DigestInfo::new([3u8; 32], 5)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: "foo_worker_id".to_string(),
             queued_timestamp: SystemTime::UNIX_EPOCH,
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::UNIX_EPOCH;
 
@@ -24,11 +25,14 @@ use futures::join;
 use nativelink_config::stores::MemorySpec;
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
-use nativelink_proto::build::bazel::remote::execution::v2::ActionResult as ProtoActionResult;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    ActionResult as ProtoActionResult, OutputFile,
+};
 use nativelink_scheduler::cache_lookup_scheduler::CacheLookupScheduler;
+use nativelink_store::completeness_checking_store::CompletenessCheckingStore;
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::action_messages::{
-    ActionResult, ActionStage, ActionState, ActionUniqueQualifier, OperationId,
+    ActionInfo, ActionResult, ActionStage, ActionState, ActionUniqueQualifier, OperationId,
 };
 use nativelink_util::common::DigestInfo;
 use nativelink_util::operation_state_manager::{ClientStateManager, OperationFilter};
@@ -48,8 +52,11 @@ struct TestContext {
 }
 
 fn make_cache_scheduler() -> Result<TestContext, Error> {
+    make_cache_scheduler_with_ac_store(Store::new(MemoryStore::new(&MemorySpec::default())))
+}
+
+fn make_cache_scheduler_with_ac_store(ac_store: Store) -> Result<TestContext, Error> {
     let mock_scheduler = Arc::new(MockActionScheduler::new());
-    let ac_store = Store::new(MemoryStore::new(&MemorySpec::default()));
     let cache_scheduler = CacheLookupScheduler::new(ac_store.clone(), mock_scheduler.clone())?;
     Ok(TestContext {
         mock_scheduler,
@@ -95,6 +102,33 @@ async fn add_action_handles_skip_cache() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn add_action_looks_up_do_not_cache_action() -> Result<(), Error> {
+    let context = make_cache_scheduler()?;
+    let mut action_info = make_base_action_info(UNIX_EPOCH, DigestInfo::zero_digest())
+        .as_ref()
+        .clone();
+    // `do_not_cache` only prevents writing the result, so a cached result is
+    // still returned.
+    action_info.do_not_cache = true;
+    let action_result = ProtoActionResult::from(ActionResult::default());
+    context
+        .ac_store
+        .update_oneshot(action_info.digest(), action_result.encode_to_vec().into())
+        .await?;
+    let action_state = context
+        .cache_scheduler
+        .add_action(OperationId::default(), Arc::new(action_info))
+        .await?
+        .as_state()
+        .await?;
+    assert!(matches!(
+        action_state.stage,
+        ActionStage::CompletedFromCache(_)
+    ));
+    Ok(())
+}
+
 #[nativelink_test]
 async fn find_by_client_operation_id_call_passed() -> Result<(), Error> {
     let context = make_cache_scheduler()?;
@@ -118,3 +152,110 @@ async fn find_by_client_operation_id_call_passed() -> Result<(), Error> {
     );
     Ok(())
 }
+
+/// Adds `action_info` to the cache scheduler, expecting it to be forwarded
+/// to the underlying scheduler.
+async fn add_forwarded_action(
+    context: &TestContext,
+    action_info: Arc<ActionInfo>,
+) -> Result<(), Error> {
+    let (_forward_watch_channel_tx, forward_watch_channel_rx) =
+        watch::channel(Arc::new(ActionState {
+            client_operation_id: OperationId::default(),
+            stage: ActionStage::Queued,
+            action_digest: action_info.unique_qualifier.digest(),
+        }));
+    let client_operation_id = OperationId::default();
+    let (result, _) = join!(
+        context
+            .cache_scheduler
+            .add_action(client_operation_id.clone(), action_info.clone()),
+        context
+            .mock_scheduler
+            .expect_add_action(Ok(Box::new(TokioWatchActionStateResult::new(
+                client_operation_id,
+                action_info,
+                forward_watch_channel_rx
+            ))))
+    );
+    result.map(|_| ())
+}
+
+#[nativelink_test]
+async fn add_action_counts_cache_hits_and_misses() -> Result<(), Error> {
+    let context = make_cache_scheduler()?;
+    let metrics = context.cache_scheduler.metrics().clone();
+
+    let missing_action_info = make_base_action_info(UNIX_EPOCH, DigestInfo::new([1u8; 32], 1));
+    add_forwarded_action(&context, missing_action_info).await?;
+
+    let cached_action_info = make_base_action_info(UNIX_EPOCH, DigestInfo::new([2u8; 32], 1));
+    let action_result = ProtoActionResult::from(ActionResult::default());
+    context
+        .ac_store
+        .update_oneshot(
+            cached_action_info.digest(),
+            action_result.encode_to_vec().into(),
+        )
+        .await?;
+    let action_state = context
+        .cache_scheduler
+        .add_action(OperationId::default(), cached_action_info)
+        .await?
+        .as_state()
+        .await?;
+    assert!(matches!(
+        action_state.stage,
+        ActionStage::CompletedFromCache(_)
+    ));
+
+    assert_eq!(metrics.cache_hits.counter.load(Ordering::Acquire), 1);
+    assert_eq!(metrics.cache_misses.counter.load(Ordering::Acquire), 1);
+    assert_eq!(
+        metrics
+            .cache_hits_missing_outputs
+            .counter
+            .load(Ordering::Acquire),
+        0
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn add_action_counts_cache_hits_with_missing_outputs() -> Result<(), Error> {
+    let backend_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let cas_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let context = make_cache_scheduler_with_ac_store(Store::new(CompletenessCheckingStore::new(
+        backend_store.clone(),
+        cas_store,
+    )))?;
+    let metrics = context.cache_scheduler.metrics().clone();
+
+    // The action result exists, but its output file was never uploaded to
+    // the CAS (for example because it was evicted).
+    let action_info = make_base_action_info(UNIX_EPOCH, DigestInfo::new([1u8; 32], 1));
+    let action_result = ProtoActionResult {
+        output_files: vec![OutputFile {
+            path: "output".to_string(),
+            digest: Some(DigestInfo::new([3u8; 32], 1).into()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    backend_store
+        .update_oneshot(action_info.digest(), action_result.encode_to_vec().into())
+        .await?;
+
+    add_forwarded_action(&context, action_info).await?;
+
+    assert_eq!(metrics.cache_hits.counter.load(Ordering::Acquire), 0);
+    assert_eq!(metrics.cache_misses.counter.load(Ordering::Acquire), 0);
+    assert_eq!(
+        metrics
+            .cache_hits_missing_outputs
+            .counter
+            .load(Ordering::Acquire),
+        1
+    );
+    Ok(())
+}
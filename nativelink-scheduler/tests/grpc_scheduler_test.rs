@@ -0,0 +1,257 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use futures::stream::{unfold, BoxStream};
+use mock_instant::thread_local::MockClock;
+use nativelink_config::schedulers::GrpcSpec;
+use nativelink_config::stores::GrpcEndpoint;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::capabilities_server::{
+    Capabilities, CapabilitiesServer,
+};
+use nativelink_proto::build::bazel::remote::execution::v2::digest_function;
+use nativelink_proto::build::bazel::remote::execution::v2::execution_server::{
+    Execution, ExecutionServer,
+};
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    ExecuteRequest, ExecutionCapabilities, GetCapabilitiesRequest, ServerCapabilities,
+    WaitExecutionRequest,
+};
+use nativelink_proto::google::longrunning::Operation;
+use nativelink_scheduler::grpc_scheduler::GrpcScheduler;
+use nativelink_util::action_messages::{
+    ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::known_platform_property_provider::KnownPlatformPropertyProvider;
+use nativelink_util::operation_state_manager::ClientStateManager;
+use nativelink_util::spawn;
+use nativelink_util::task::JoinHandleDropGuard;
+use parking_lot::Mutex;
+use pretty_assertions::assert_eq;
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+/// Upstream that supports a single property named after the instance name
+/// and counts the capabilities requests for each instance name.
+#[derive(Clone, Default)]
+struct CountingCapabilities {
+    requests: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl CountingCapabilities {
+    fn request_count(&self, instance_name: &str) -> usize {
+        self.requests
+            .lock()
+            .get(instance_name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[tonic::async_trait]
+impl Capabilities for CountingCapabilities {
+    async fn get_capabilities(
+        &self,
+        request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<ServerCapabilities>, Status> {
+        let instance_name = request.into_inner().instance_name;
+        *self
+            .requests
+            .lock()
+            .entry(instance_name.clone())
+            .or_default() += 1;
+        Ok(Response::new(ServerCapabilities {
+            execution_capabilities: Some(ExecutionCapabilities {
+                supported_node_properties: vec![instance_name],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+    }
+}
+
+/// Upstream that records every `ExecuteRequest` it receives and rejects it.
+#[derive(Clone, Default)]
+struct RecordingExecution {
+    requests: Arc<Mutex<Vec<ExecuteRequest>>>,
+}
+
+#[tonic::async_trait]
+impl Execution for RecordingExecution {
+    type ExecuteStream = BoxStream<'static, Result<Operation, Status>>;
+    type WaitExecutionStream = BoxStream<'static, Result<Operation, Status>>;
+
+    async fn execute(
+        &self,
+        request: Request<ExecuteRequest>,
+    ) -> Result<Response<Self::ExecuteStream>, Status> {
+        self.requests.lock().push(request.into_inner());
+        Err(Status::unimplemented("RecordingExecution only records"))
+    }
+
+    async fn wait_execution(
+        &self,
+        _request: Request<WaitExecutionRequest>,
+    ) -> Result<Response<Self::WaitExecutionStream>, Status> {
+        Err(Status::unimplemented("RecordingExecution only records"))
+    }
+}
+
+type TestGrpcScheduler = GrpcScheduler<MockInstantWrapped, fn() -> MockInstantWrapped>;
+
+async fn make_upstream_and_scheduler(
+    max_cached_instance_names: u64,
+    supported_properties_ttl_s: u64,
+) -> Result<
+    (
+        JoinHandleDropGuard<()>,
+        CountingCapabilities,
+        RecordingExecution,
+        TestGrpcScheduler,
+    ),
+    Error,
+> {
+    let upstream = CountingCapabilities::default();
+    let execution = RecordingExecution::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let server_upstream = upstream.clone();
+    let server_execution = execution.clone();
+    let server_spawn = spawn!("upstream_capabilities", async move {
+        let incoming = unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        Server::builder()
+            .add_service(CapabilitiesServer::new(server_upstream))
+            .add_service(ExecutionServer::new(server_execution))
+            .serve_with_incoming(incoming)
+            .await
+            .expect("Upstream capabilities failed");
+    });
+    let scheduler = GrpcScheduler::new_with_now_fn(
+        &GrpcSpec {
+            endpoint: GrpcEndpoint {
+                address: format!("grpc://{address}"),
+                ..Default::default()
+            },
+            retry: Default::default(),
+            max_concurrent_requests: 0,
+            connections_per_endpoint: 0,
+            max_cached_instance_names,
+            supported_properties_ttl_s,
+        },
+        Box::new(|delay| delay),
+        MockInstantWrapped::default as fn() -> MockInstantWrapped,
+    )?;
+    Ok((server_spawn, upstream, execution, scheduler))
+}
+
+#[nativelink_test]
+async fn supported_properties_evicted_past_capacity() -> Result<(), Error> {
+    let (_server_spawn, upstream, _execution, scheduler) =
+        make_upstream_and_scheduler(2, 0).await?;
+
+    for instance_name in ["a", "b", "c"] {
+        assert_eq!(
+            scheduler.get_known_properties(instance_name).await?,
+            vec![instance_name.to_string()]
+        );
+    }
+    // "c" is still cached, so the upstream is not queried again.
+    scheduler.get_known_properties("c").await?;
+    assert_eq!(upstream.request_count("c"), 1);
+
+    // "a" was the least recently used entry when "c" was added.
+    assert_eq!(
+        scheduler.get_known_properties("a").await?,
+        vec!["a".to_string()]
+    );
+    assert_eq!(upstream.request_count("a"), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn supported_properties_refreshed_after_ttl() -> Result<(), Error> {
+    let (_server_spawn, upstream, _execution, scheduler) =
+        make_upstream_and_scheduler(0, 10).await?;
+
+    scheduler.get_known_properties("a").await?;
+    assert_eq!(upstream.request_count("a"), 1);
+
+    MockClock::advance(Duration::from_secs(9));
+    scheduler.get_known_properties("a").await?;
+    assert_eq!(upstream.request_count("a"), 1);
+
+    MockClock::advance(Duration::from_secs(1));
+    assert_eq!(
+        scheduler.get_known_properties("a").await?,
+        vec!["a".to_string()]
+    );
+    assert_eq!(upstream.request_count("a"), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn add_action_sends_action_digest_function() -> Result<(), Error> {
+    let (_server_spawn, _upstream, execution, scheduler) =
+        make_upstream_and_scheduler(0, 0).await?;
+
+    for digest_function in [DigestHasherFunc::Blake3, DigestHasherFunc::Sha256] {
+        let action_info = Arc::new(ActionInfo {
+            command_digest: DigestInfo::new([0u8; 32], 0),
+            input_root_digest: DigestInfo::new([0u8; 32], 0),
+            timeout: Duration::MAX,
+            platform_properties: HashMap::new(),
+            priority: 0,
+            load_timestamp: UNIX_EPOCH,
+            insert_timestamp: UNIX_EPOCH,
+            unique_qualifier: ActionUniqueQualifier::Cachable(ActionUniqueKey {
+                instance_name: "instance_name".to_string(),
+                digest_function,
+                digest: DigestInfo::new([1u8; 32], 1),
+            }),
+            do_not_cache: false,
+        });
+        // The upstream rejects every action, only the request matters.
+        assert!(scheduler
+            .add_action(OperationId::default(), action_info)
+            .await
+            .is_err());
+    }
+
+    let digest_functions: Vec<i32> = execution
+        .requests
+        .lock()
+        .iter()
+        .map(|request| request.digest_function)
+        .collect();
+    assert_eq!(
+        digest_functions,
+        vec![
+            digest_function::Value::Blake3 as i32,
+            digest_function::Value::Sha256 as i32,
+        ]
+    );
+    Ok(())
+}
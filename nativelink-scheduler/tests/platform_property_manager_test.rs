@@ -0,0 +1,98 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use nativelink_config::schedulers::PropertyType;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::{platform, Platform};
+use nativelink_scheduler::platform_property_manager::PlatformPropertyManager;
+use nativelink_util::platform_properties::{
+    merge_proto_platforms, PlatformProperties, PlatformPropertyValue,
+};
+use pretty_assertions::assert_eq;
+
+fn make_platform(properties: &[(&str, &str)]) -> Platform {
+    Platform {
+        properties: properties
+            .iter()
+            .map(|(name, value)| platform::Property {
+                name: (*name).to_string(),
+                value: (*value).to_string(),
+            })
+            .collect(),
+    }
+}
+
+fn make_manager() -> PlatformPropertyManager {
+    PlatformPropertyManager::new(HashMap::from([
+        ("cpu_count".to_string(), PropertyType::minimum),
+        ("OSFamily".to_string(), PropertyType::exact),
+        ("pool".to_string(), PropertyType::priority),
+    ]))
+}
+
+#[nativelink_test]
+async fn command_platform_takes_precedence_over_action_platform() -> Result<(), Error> {
+    let merged = merge_proto_platforms(
+        Some(make_platform(&[("cpu_count", "2"), ("OSFamily", "linux")])),
+        Some(make_platform(&[("cpu_count", "8"), ("pool", "large")])),
+    );
+    assert_eq!(
+        make_manager().make_platform_properties(merged)?,
+        PlatformProperties::new(HashMap::from([
+            ("cpu_count".to_string(), PlatformPropertyValue::Minimum(8)),
+            (
+                "OSFamily".to_string(),
+                PlatformPropertyValue::Exact("linux".to_string())
+            ),
+            (
+                "pool".to_string(),
+                PlatformPropertyValue::Priority("large".to_string())
+            ),
+        ]))
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn either_platform_may_be_missing() -> Result<(), Error> {
+    let platform = make_platform(&[("OSFamily", "linux")]);
+    let expected = HashMap::from([("OSFamily".to_string(), "linux".to_string())]);
+    assert_eq!(
+        merge_proto_platforms(Some(platform.clone()), None),
+        expected
+    );
+    assert_eq!(merge_proto_platforms(None, Some(platform)), expected);
+    assert_eq!(merge_proto_platforms(None, None), HashMap::new());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn unknown_property_in_merged_platform_is_rejected() -> Result<(), Error> {
+    let merged = merge_proto_platforms(
+        Some(make_platform(&[("OSFamily", "linux")])),
+        Some(make_platform(&[("gpu_model", "a100")])),
+    );
+    let err = make_manager()
+        .make_platform_properties(merged)
+        .expect_err("Expected unknown property to be rejected");
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("gpu_model"),
+        "Expected error to name the unknown property, got: {err:?}"
+    );
+    Ok(())
+}
@@ -185,6 +185,7 @@ async fn add_action_smoke_test() -> Result<(), Error> {
                 digest_function: DigestHasherFunc::Sha256,
                 digest: DigestInfo::zero_digest(),
             }),
+            do_not_cache: false,
         }),
         MockSystemTime::now().into(),
     );
@@ -439,6 +440,7 @@ async fn add_action_smoke_test() -> Result<(), Error> {
         .add_action(
             CLIENT_OPERATION_ID.into(),
             worker_awaited_action.action_info().clone(),
+            0,
         )
         .await
         .unwrap();
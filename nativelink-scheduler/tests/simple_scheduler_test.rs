@@ -30,7 +30,7 @@ use nativelink_macro::nativelink_test;
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::{digest_function, ExecuteRequest};
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    update_for_worker, ConnectionResult, StartExecute, UpdateForWorker,
+    update_for_worker, ConnectionResult, KillOperationRequest, StartExecute, UpdateForWorker,
 };
 use nativelink_scheduler::awaited_action_db::{
     AwaitedAction, AwaitedActionDb, AwaitedActionSubscriber, SortedAwaitedAction,
@@ -152,6 +152,30 @@ async fn setup_action(
     result
 }
 
+async fn setup_action_with_priority(
+    scheduler: &SimpleScheduler,
+    action_digest: DigestInfo,
+    platform_properties: HashMap<String, String>,
+    priority: i32,
+    insert_timestamp: SystemTime,
+) -> Result<Box<dyn ActionStateResult>, Error> {
+    let mut action_info = make_base_action_info(insert_timestamp, action_digest);
+    Arc::make_mut(&mut action_info).platform_properties = platform_properties;
+    Arc::make_mut(&mut action_info).priority = priority;
+    let result = scheduler
+        .add_action(OperationId::default(), action_info)
+        .await;
+    tokio::task::yield_now().await; // Allow task<->worker matcher to run.
+    result
+}
+
+fn start_execute_from_update(update: UpdateForWorker) -> StartExecute {
+    match update.update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => start_execute,
+        v => panic!("Expected StartAction, got : {v:?}"),
+    }
+}
+
 const WORKER_TIMEOUT_S: u64 = 100;
 
 #[nativelink_test]
@@ -362,6 +386,110 @@ async fn find_executing_action() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn find_existing_action_by_operation_id_resumes_updates() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let action_listener = setup_action(
+        &scheduler,
+        action_digest,
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
+    };
+    // Simulate a client disconnect, then reconnect with only the operation id.
+    drop(action_listener);
+    let mut action_listener = scheduler
+        .find_existing_action(&operation_id)
+        .await?
+        .expect("Action not found by operation id");
+    {
+        let action_state = action_listener.changed().await?;
+        assert_eq!(action_state.stage, ActionStage::Executing);
+        // The client must see the id it used to reconnect.
+        assert_eq!(action_state.client_operation_id, operation_id);
+    }
+
+    // Filtering by only a client operation id also falls back to the operation id.
+    let mut filtered_action_listener = scheduler
+        .filter_operations(OperationFilter {
+            client_operation_id: Some(operation_id.clone()),
+            ..Default::default()
+        })
+        .await?
+        .next()
+        .await
+        .expect("Action not found by filter");
+    assert_eq!(
+        filtered_action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    let action_result = ActionResult {
+        execution_metadata: ExecutionMetadata {
+            worker: worker_id.to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                action_result.clone(),
+            )),
+        )
+        .await?;
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Completed(action_result.clone())
+    );
+    drop(action_listener);
+    drop(filtered_action_listener);
+
+    // Reconnecting after completion still yields the final result.
+    let mut action_listener = scheduler
+        .find_existing_action(&operation_id)
+        .await?
+        .expect("Completed action not found by operation id");
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Completed(action_result)
+    );
+
+    // Unknown ids are not found.
+    assert!(scheduler
+        .find_existing_action(&OperationId::default())
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn remove_worker_reschedules_multiple_running_job_test() -> Result<(), Error> {
     let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
@@ -620,6 +748,88 @@ async fn set_drain_worker_pauses_and_resumes_worker_test() -> Result<(), Error>
     Ok(())
 }
 
+#[nativelink_test]
+async fn set_worker_intake_paused_pauses_and_resumes_worker_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let insert_timestamp = make_system_time(1);
+    let mut action_listener =
+        setup_action(&scheduler, action_digest, HashMap::new(), insert_timestamp).await?;
+
+    let _operation_id = {
+        // Other tests check full data. We only care if we got StartAction.
+        let operation_id = match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(start_execute)) => {
+                OperationId::from(start_execute.operation_id)
+            }
+            v => panic!("Expected StartAction, got : {v:?}"),
+        };
+        // Other tests check full data. We only care if client thinks we are Executing.
+        assert_eq!(
+            action_listener.changed().await.unwrap().stage,
+            ActionStage::Executing
+        );
+        operation_id
+    };
+
+    // The worker asks not to be given new actions.
+    scheduler.set_worker_intake_paused(&worker_id, true).await?;
+    tokio::task::yield_now().await;
+
+    let action_digest = DigestInfo::new([88u8; 32], 512);
+    let insert_timestamp = make_system_time(14);
+    let mut action_listener =
+        setup_action(&scheduler, action_digest, HashMap::new(), insert_timestamp).await?;
+
+    {
+        // Client should get notification saying it's been queued.
+        let action_state = action_listener.changed().await.unwrap();
+        let expected_action_state = ActionState {
+            // Name is a random string, so we ignore it and just make it the same.
+            client_operation_id: action_state.client_operation_id.clone(),
+            stage: ActionStage::Queued,
+            action_digest: action_state.action_digest,
+        };
+        assert_eq!(action_state.as_ref(), &expected_action_state);
+    }
+
+    // The worker asks for actions again.
+    scheduler
+        .set_worker_intake_paused(&worker_id, false)
+        .await?;
+    tokio::task::yield_now().await;
+
+    {
+        // Client should get notification saying it's being executed.
+        let action_state = action_listener.changed().await.unwrap();
+        let expected_action_state = ActionState {
+            // Name is a random string, so we ignore it and just make it the same.
+            client_operation_id: action_state.client_operation_id.clone(),
+            stage: ActionStage::Executing,
+            action_digest: action_state.action_digest,
+        };
+        assert_eq!(action_state.as_ref(), &expected_action_state);
+    }
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn worker_should_not_queue_if_properties_dont_match_test() -> Result<(), Error> {
     let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
@@ -718,6 +928,84 @@ async fn worker_should_not_queue_if_properties_dont_match_test() -> Result<(), E
     Ok(())
 }
 
+#[nativelink_test]
+async fn worker_without_enough_resources_is_not_matched_test() -> Result<(), Error> {
+    let small_worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    let large_worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    // These are the properties workers advertise with `advertise_host_resources`.
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("cpu_count".to_string(), PropertyType::minimum);
+    prop_defs.insert("memory_kb".to_string(), PropertyType::minimum);
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let make_worker_properties = |cpu_count, memory_kb| PlatformProperties {
+        properties: HashMap::from([
+            (
+                "cpu_count".to_string(),
+                PlatformPropertyValue::Minimum(cpu_count),
+            ),
+            (
+                "memory_kb".to_string(),
+                PlatformPropertyValue::Minimum(memory_kb),
+            ),
+        ]),
+    };
+    // The small worker has enough CPUs but not enough memory.
+    let mut rx_from_small_worker =
+        setup_new_worker(&scheduler, small_worker_id, make_worker_properties(8, 1024)).await?;
+    let mut rx_from_large_worker = setup_new_worker(
+        &scheduler,
+        large_worker_id,
+        make_worker_properties(8, 16384),
+    )
+    .await?;
+
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+    let mut action_listener = setup_action(
+        &scheduler,
+        action_digest,
+        HashMap::from([
+            ("cpu_count".to_string(), "4".to_string()),
+            ("memory_kb".to_string(), "2048".to_string()),
+        ]),
+        make_system_time(1),
+    )
+    .await?;
+
+    let start_execute = start_execute_from_update(rx_from_large_worker.recv().await.unwrap());
+    assert_eq!(
+        start_execute
+            .execute_request
+            .and_then(|request| request.action_digest),
+        Some(action_digest.into())
+    );
+    assert_eq!(
+        action_listener.changed().await.unwrap().stage,
+        ActionStage::Executing
+    );
+    assert_eq!(
+        rx_from_small_worker.try_recv(),
+        Err(mpsc::error::TryRecvError::Empty)
+    );
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn cacheable_items_join_same_action_queued_test() -> Result<(), Error> {
     let worker_id: WorkerId = WorkerId(Uuid::new_v4());
@@ -972,6 +1260,7 @@ impl AwaitedActionDb for RxMockAwaitedAction {
         &self,
         _client_operation_id: OperationId,
         _action_info: Arc<ActionInfo>,
+        _max_queued_actions: usize,
     ) -> Result<Self::Subscriber, Error> {
         unreachable!();
     }
@@ -1243,6 +1532,7 @@ async fn update_action_sends_completed_result_to_client_test() -> Result<(), Err
             name_or_path: NameOrPath::Name("hello".to_string()),
             digest: DigestInfo::new([5u8; 32], 18),
             is_executable: true,
+            unix_mode: None,
         }],
         output_folders: vec![DirectoryInfo {
             path: "123".to_string(),
@@ -1257,8 +1547,10 @@ async fn update_action_sends_completed_result_to_client_test() -> Result<(), Err
             target: "bar2".to_string(),
         }],
         exit_code: 0,
-        stdout_digest: DigestInfo::new([6u8; 32], 19),
-        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        stdout_digest: Some(DigestInfo::new([6u8; 32], 19)),
+        stderr_digest: Some(DigestInfo::new([7u8; 32], 20)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: worker_id.to_string(),
             queued_timestamp: make_system_time(5),
@@ -1349,6 +1641,7 @@ async fn update_action_sends_completed_result_after_disconnect() -> Result<(), E
             name_or_path: NameOrPath::Name("hello".to_string()),
             digest: DigestInfo::new([5u8; 32], 18),
             is_executable: true,
+            unix_mode: None,
         }],
         output_folders: vec![DirectoryInfo {
             path: "123".to_string(),
@@ -1363,8 +1656,10 @@ async fn update_action_sends_completed_result_after_disconnect() -> Result<(), E
             target: "bar2".to_string(),
         }],
         exit_code: 0,
-        stdout_digest: DigestInfo::new([6u8; 32], 19),
-        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        stdout_digest: Some(DigestInfo::new([6u8; 32], 19)),
+        stderr_digest: Some(DigestInfo::new([7u8; 32], 20)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: worker_id.to_string(),
             queued_timestamp: make_system_time(5),
@@ -1442,18 +1737,21 @@ async fn update_action_with_wrong_worker_id_errors_test() -> Result<(), Error> {
     let mut action_listener =
         setup_action(&scheduler, action_digest, HashMap::new(), insert_timestamp).await?;
 
-    {
+    let operation_id = {
         // Other tests check full data. We only care if we got StartAction.
-        match rx_from_worker.recv().await.unwrap().update {
-            Some(update_for_worker::Update::StartAction(_)) => { /* Success */ }
+        let operation_id = match rx_from_worker.recv().await.unwrap().update {
+            Some(update_for_worker::Update::StartAction(start_execute)) => {
+                OperationId::from(start_execute.operation_id)
+            }
             v => panic!("Expected StartAction, got : {v:?}"),
-        }
+        };
         // Other tests check full data. We only care if client thinks we are Executing.
         assert_eq!(
             action_listener.changed().await.unwrap().stage,
             ActionStage::Executing
         );
-    }
+        operation_id
+    };
     let _ = setup_new_worker(&scheduler, rogue_worker_id, PlatformProperties::default()).await?;
 
     let action_result = ActionResult {
@@ -1462,8 +1760,10 @@ async fn update_action_with_wrong_worker_id_errors_test() -> Result<(), Error> {
         output_file_symlinks: Vec::default(),
         output_directory_symlinks: Vec::default(),
         exit_code: 0,
-        stdout_digest: DigestInfo::new([6u8; 32], 19),
-        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        stdout_digest: Some(DigestInfo::new([6u8; 32], 19)),
+        stderr_digest: Some(DigestInfo::new([7u8; 32], 20)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: good_worker_id.to_string(),
             queued_timestamp: make_system_time(5),
@@ -1483,7 +1783,7 @@ async fn update_action_with_wrong_worker_id_errors_test() -> Result<(), Error> {
     let update_action_result = scheduler
         .update_action(
             &rogue_worker_id,
-            &OperationId::default(),
+            &operation_id,
             UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
                 action_result.clone(),
             )),
@@ -1517,12 +1817,19 @@ async fn update_action_with_wrong_worker_id_errors_test() -> Result<(), Error> {
 }
 
 #[nativelink_test]
-async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Error> {
+async fn result_from_evicted_worker_completes_requeued_action_test() -> Result<(), Error> {
     let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    let reconnected_worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("prop".to_string(), PropertyType::exact);
 
     let task_change_notify = Arc::new(Notify::new());
     let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
-        &SimpleSpec::default(),
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            ..Default::default()
+        },
         memory_awaited_action_db_factory(
             0,
             &task_change_notify.clone(),
@@ -1534,14 +1841,124 @@ async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Erro
     );
     let action_digest = DigestInfo::new([99u8; 32], 512);
 
-    let client_operation_id = OperationId::default();
-    let mut expected_action_state = ActionState {
-        client_operation_id,
-        stage: ActionStage::Executing,
+    let mut worker_properties = PlatformProperties::default();
+    worker_properties.properties.insert(
+        "prop".to_string(),
+        PlatformPropertyValue::Exact("1".to_string()),
+    );
+    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, worker_properties).await?;
+    let mut platform_properties = HashMap::new();
+    platform_properties.insert("prop".to_string(), "1".to_string());
+    let mut action_listener = setup_action(
+        &scheduler,
         action_digest,
+        platform_properties,
+        make_system_time(1),
+    )
+    .await?;
+    let operation_id = match rx_from_worker.recv().await.unwrap().update {
+        Some(update_for_worker::Update::StartAction(start_execute)) => {
+            OperationId::from(start_execute.operation_id)
+        }
+        v => panic!("Expected StartAction, got : {v:?}"),
     };
-
-    let insert_timestamp = make_system_time(1);
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // The worker loses its connection, so the action is put back in the queue.
+    scheduler.remove_worker(&worker_id).await?;
+    assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+
+    // The worker reconnects under a new worker id. It advertises other
+    // properties, so the action is not matched to it again.
+    let mut rx_from_reconnected_worker = setup_new_worker(
+        &scheduler,
+        reconnected_worker_id,
+        PlatformProperties::default(),
+    )
+    .await?;
+    let action_result = ActionResult {
+        execution_metadata: ExecutionMetadata {
+            worker: worker_id.to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // Errors of a worker that is no longer connected are not trusted.
+    let update_err = scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Internal, "Worker went away")),
+        )
+        .await;
+    assert!(update_err.is_err(), "Expected error, got {update_err:?}");
+
+    // The operation was never assigned to the reconnected worker, so its
+    // result is rejected and the worker is evicted.
+    let update_err = scheduler
+        .update_action(
+            &reconnected_worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                action_result.clone(),
+            )),
+        )
+        .await;
+    assert!(update_err.is_err(), "Expected error, got {update_err:?}");
+    assert_eq!(
+        rx_from_reconnected_worker.recv().await.unwrap().update,
+        Some(update_for_worker::Update::Disconnect(()))
+    );
+
+    // The worker kept running the action and reports its result under the
+    // worker id the action was started on.
+    scheduler
+        .update_action(
+            &worker_id,
+            &operation_id,
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(
+                action_result.clone(),
+            )),
+        )
+        .await?;
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Completed(action_result)
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+
+    let client_operation_id = OperationId::default();
+    let mut expected_action_state = ActionState {
+        client_operation_id,
+        stage: ActionStage::Executing,
+        action_digest,
+    };
+
+    let insert_timestamp = make_system_time(1);
     let mut action_listener =
         setup_action(&scheduler, action_digest, HashMap::new(), insert_timestamp)
             .await
@@ -1593,8 +2010,10 @@ async fn does_not_crash_if_operation_joined_then_relaunched() -> Result<(), Erro
         output_directory_symlinks: Vec::default(),
         output_file_symlinks: Vec::default(),
         exit_code: Default::default(),
-        stdout_digest: DigestInfo::new([1u8; 32], 512),
-        stderr_digest: DigestInfo::new([2u8; 32], 512),
+        stdout_digest: Some(DigestInfo::new([1u8; 32], 512)),
+        stderr_digest: Some(DigestInfo::new([2u8; 32], 512)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: String::new(),
             queued_timestamp: SystemTime::UNIX_EPOCH,
@@ -1726,8 +2145,10 @@ async fn run_two_jobs_on_same_worker_with_platform_properties_restrictions() ->
         output_file_symlinks: Vec::default(),
         output_directory_symlinks: Vec::default(),
         exit_code: 0,
-        stdout_digest: DigestInfo::new([6u8; 32], 19),
-        stderr_digest: DigestInfo::new([7u8; 32], 20),
+        stdout_digest: Some(DigestInfo::new([6u8; 32], 19)),
+        stderr_digest: Some(DigestInfo::new([7u8; 32], 20)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: worker_id.to_string(),
             queued_timestamp: make_system_time(5),
@@ -1984,8 +2405,10 @@ async fn worker_retries_on_internal_error_and_fails_test() -> Result<(), Error>
                 output_file_symlinks: Vec::default(),
                 output_directory_symlinks: Vec::default(),
                 exit_code: INTERNAL_ERROR_EXIT_CODE,
-                stdout_digest: DigestInfo::zero_digest(),
-                stderr_digest: DigestInfo::zero_digest(),
+                stdout_digest: Some(DigestInfo::zero_digest()),
+                stderr_digest: Some(DigestInfo::zero_digest()),
+                stdout_raw: Vec::new(),
+                stderr_raw: Vec::new(),
                 execution_metadata: ExecutionMetadata {
                     worker: worker_id.to_string(),
                     queued_timestamp: SystemTime::UNIX_EPOCH,
@@ -2221,3 +2644,547 @@ async fn client_reconnect_keeps_action_alive() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn worker_with_matching_priority_property_is_preferred_test() -> Result<(), Error> {
+    let worker_id1: WorkerId = WorkerId(Uuid::new_v4());
+    let worker_id2: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("pool".to_string(), PropertyType::priority);
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let mut worker1_properties = PlatformProperties::default();
+    worker1_properties.properties.insert(
+        "pool".to_string(),
+        PlatformPropertyValue::Priority("slow".to_string()),
+    );
+    let mut worker2_properties = PlatformProperties::default();
+    worker2_properties.properties.insert(
+        "pool".to_string(),
+        PlatformPropertyValue::Priority("fast".to_string()),
+    );
+    // Worker1 is the least recently used worker, so without priority
+    // matching it would be selected.
+    let mut rx_from_worker1 = setup_new_worker(&scheduler, worker_id1, worker1_properties).await?;
+    let mut rx_from_worker2 = setup_new_worker(&scheduler, worker_id2, worker2_properties).await?;
+
+    let action_digest = DigestInfo::new([99u8; 32], 512);
+    let mut platform_properties = HashMap::new();
+    platform_properties.insert("pool".to_string(), "fast".to_string());
+    let mut action_listener = setup_action(
+        &scheduler,
+        action_digest,
+        platform_properties,
+        make_system_time(1),
+    )
+    .await?;
+    scheduler.do_try_match_for_test().await?;
+
+    let start_execute = start_execute_from_update(rx_from_worker2.recv().await.unwrap());
+    assert_eq!(
+        start_execute.execute_request.unwrap().action_digest,
+        Some(action_digest.into())
+    );
+    assert_eq!(
+        action_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    assert!(
+        rx_from_worker1.try_recv().is_err(),
+        "Worker1 should not have received any action"
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn high_priority_action_jumps_ahead_in_queue_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let low_priority_action_digest = DigestInfo::new([11u8; 32], 512);
+    let high_priority_action_digest = DigestInfo::new([22u8; 32], 512);
+
+    // Queue both actions before any worker is available.
+    let _low_priority_listener = setup_action_with_priority(
+        &scheduler,
+        low_priority_action_digest,
+        HashMap::new(),
+        0,
+        make_system_time(1),
+    )
+    .await?;
+    let _high_priority_listener = setup_action_with_priority(
+        &scheduler,
+        high_priority_action_digest,
+        HashMap::new(),
+        10,
+        make_system_time(2),
+    )
+    .await?;
+
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    scheduler.do_try_match_for_test().await?;
+
+    let first_start_execute = start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        first_start_execute.execute_request.unwrap().action_digest,
+        Some(high_priority_action_digest.into())
+    );
+    let second_start_execute = start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        second_start_execute.execute_request.unwrap().action_digest,
+        Some(low_priority_action_digest.into())
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn preempted_action_is_requeued_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("cpu".to_string(), PropertyType::minimum);
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            preempt_lower_priority_actions: true,
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let mut worker_properties = PlatformProperties::default();
+    worker_properties
+        .properties
+        .insert("cpu".to_string(), PlatformPropertyValue::Minimum(1));
+    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, worker_properties).await?;
+
+    let mut platform_properties = HashMap::new();
+    platform_properties.insert("cpu".to_string(), "1".to_string());
+    let low_priority_action_digest = DigestInfo::new([11u8; 32], 512);
+    let high_priority_action_digest = DigestInfo::new([22u8; 32], 512);
+
+    let mut low_priority_listener = setup_action_with_priority(
+        &scheduler,
+        low_priority_action_digest,
+        platform_properties.clone(),
+        0,
+        make_system_time(1),
+    )
+    .await?;
+    scheduler.do_try_match_for_test().await?;
+    let low_priority_operation_id = OperationId::from(
+        start_execute_from_update(rx_from_worker.recv().await.unwrap()).operation_id,
+    );
+    assert_eq!(
+        low_priority_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    let mut high_priority_listener = setup_action_with_priority(
+        &scheduler,
+        high_priority_action_digest,
+        platform_properties,
+        10,
+        make_system_time(2),
+    )
+    .await?;
+    scheduler.do_try_match_for_test().await?;
+
+    // The worker should be asked to kill the low priority action.
+    assert_eq!(
+        rx_from_worker.recv().await.unwrap().update,
+        Some(update_for_worker::Update::KillOperationRequest(
+            KillOperationRequest {
+                operation_id: low_priority_operation_id.to_string(),
+            }
+        ))
+    );
+    // The low priority action must be re-queued, not dropped.
+    assert_eq!(
+        low_priority_listener.changed().await?.stage,
+        ActionStage::Queued
+    );
+
+    // The high priority action must not start until the worker has
+    // confirmed the low priority action was killed.
+    scheduler.do_try_match_for_test().await?;
+    assert_eq!(
+        rx_from_worker.try_recv(),
+        Err(mpsc::error::TryRecvError::Empty)
+    );
+
+    // The worker reporting the killed action must not evict the worker.
+    scheduler
+        .update_action(
+            &worker_id,
+            &low_priority_operation_id,
+            UpdateOperationType::UpdateWithError(make_err!(Code::Aborted, "Killed")),
+        )
+        .await?;
+    scheduler.do_try_match_for_test().await?;
+    let high_priority_start_execute =
+        start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        high_priority_start_execute
+            .execute_request
+            .unwrap()
+            .action_digest,
+        Some(high_priority_action_digest.into())
+    );
+    assert_eq!(
+        high_priority_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    // Once the high priority action finishes, the low priority action runs again.
+    scheduler
+        .update_action(
+            &worker_id,
+            &OperationId::from(high_priority_start_execute.operation_id),
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(ActionResult {
+                execution_metadata: ExecutionMetadata {
+                    worker: worker_id.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })),
+        )
+        .await?;
+    scheduler.do_try_match_for_test().await?;
+    let restarted_start_execute = start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        restarted_start_execute
+            .execute_request
+            .unwrap()
+            .action_digest,
+        Some(low_priority_action_digest.into())
+    );
+    assert_eq!(
+        low_priority_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn add_action_rejected_when_queue_is_full_test() -> Result<(), Error> {
+    const MAX_QUEUED_ACTIONS: usize = 2;
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            max_queued_actions: MAX_QUEUED_ACTIONS,
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    // No workers are connected, so every action stays queued.
+    let mut action_listeners = Vec::new();
+    for i in 0..MAX_QUEUED_ACTIONS {
+        let action_digest = DigestInfo::new([i as u8; 32], 512);
+        let mut action_listener = setup_action(
+            &scheduler,
+            action_digest,
+            HashMap::new(),
+            make_system_time(1),
+        )
+        .await?;
+        assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+        action_listeners.push(action_listener);
+    }
+
+    let err = setup_action(
+        &scheduler,
+        DigestInfo::new([99u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await
+    .err()
+    .expect("Expected queue full error");
+    assert_eq!(err.code, Code::ResourceExhausted);
+
+    // An action that joins one that is already queued does not grow the
+    // queue, so it is accepted.
+    let mut joined_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([0u8; 32], 512),
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    assert_eq!(joined_listener.changed().await?.stage, ActionStage::Queued);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn completed_action_frees_queue_slot_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, _worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            max_queued_actions: 1,
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+    let first_action_digest = DigestInfo::new([1u8; 32], 512);
+    let second_action_digest = DigestInfo::new([2u8; 32], 512);
+
+    let mut first_listener = setup_action(
+        &scheduler,
+        first_action_digest,
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await?;
+    assert_eq!(first_listener.changed().await?.stage, ActionStage::Queued);
+
+    let err = setup_action(
+        &scheduler,
+        second_action_digest,
+        HashMap::new(),
+        make_system_time(1),
+    )
+    .await
+    .err()
+    .expect("Expected queue full error");
+    assert_eq!(err.code, Code::ResourceExhausted);
+
+    // Once a worker picks up the queued action it no longer counts
+    // against the queue and runs to completion.
+    let mut rx_from_worker =
+        setup_new_worker(&scheduler, worker_id, PlatformProperties::default()).await?;
+    let start_execute = start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        first_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    scheduler
+        .update_action(
+            &worker_id,
+            &OperationId::from(start_execute.operation_id),
+            UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(ActionResult {
+                execution_metadata: ExecutionMetadata {
+                    worker: worker_id.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })),
+        )
+        .await?;
+    assert!(matches!(
+        first_listener.changed().await?.stage,
+        ActionStage::Completed(_)
+    ));
+
+    let mut second_listener = setup_action(
+        &scheduler,
+        second_action_digest,
+        HashMap::new(),
+        make_system_time(2),
+    )
+    .await?;
+    assert_eq!(
+        start_execute_from_update(rx_from_worker.recv().await.unwrap())
+            .execute_request
+            .unwrap()
+            .action_digest,
+        Some(second_action_digest.into())
+    );
+    assert_eq!(
+        second_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn list_operations_paginates_queued_actions_test() -> Result<(), Error> {
+    const NUM_ACTIONS: u8 = 3;
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec::default(),
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    // No workers are connected, so every action stays queued.
+    let mut action_listeners = Vec::new();
+    for i in 0..NUM_ACTIONS {
+        let mut action_listener = setup_action(
+            &scheduler,
+            DigestInfo::new([i; 32], 512),
+            HashMap::new(),
+            make_system_time(1),
+        )
+        .await?;
+        assert_eq!(action_listener.changed().await?.stage, ActionStage::Queued);
+        action_listeners.push(action_listener);
+    }
+
+    assert_eq!(
+        worker_scheduler
+            .list_operations(SortedAwaitedActionState::Executing, Bound::Unbounded, 10)
+            .await?,
+        vec![]
+    );
+
+    let first_page = worker_scheduler
+        .list_operations(SortedAwaitedActionState::Queued, Bound::Unbounded, 2)
+        .await?;
+    assert_eq!(first_page.len(), 2);
+    let second_page = worker_scheduler
+        .list_operations(
+            SortedAwaitedActionState::Queued,
+            Bound::Excluded(first_page[1].cursor.parse::<SortedAwaitedAction>()?),
+            2,
+        )
+        .await?;
+    assert_eq!(second_page.len(), 1);
+
+    let mut listed_ids: Vec<OperationId> = first_page
+        .iter()
+        .chain(second_page.iter())
+        .inspect(|summary| assert_eq!(summary.state, SortedAwaitedActionState::Queued))
+        .map(|summary| summary.operation_id.clone())
+        .collect();
+    listed_ids.sort();
+    listed_ids.dedup();
+    assert_eq!(listed_ids.len(), usize::from(NUM_ACTIONS));
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn list_operations_lists_executing_actions_test() -> Result<(), Error> {
+    let worker_id: WorkerId = WorkerId(Uuid::new_v4());
+
+    let mut prop_defs = HashMap::new();
+    prop_defs.insert("prop".to_string(), PropertyType::exact);
+
+    let task_change_notify = Arc::new(Notify::new());
+    let (scheduler, worker_scheduler) = SimpleScheduler::new_with_callback(
+        &SimpleSpec {
+            supported_platform_properties: Some(prop_defs),
+            ..Default::default()
+        },
+        memory_awaited_action_db_factory(
+            0,
+            &task_change_notify.clone(),
+            MockInstantWrapped::default,
+        ),
+        || async move {},
+        task_change_notify,
+        MockInstantWrapped::default,
+    );
+
+    let mut worker_properties = PlatformProperties::default();
+    worker_properties.properties.insert(
+        "prop".to_string(),
+        PlatformPropertyValue::Exact("1".to_string()),
+    );
+    let mut rx_from_worker = setup_new_worker(&scheduler, worker_id, worker_properties).await?;
+
+    // Only the first action matches the worker, the second stays queued.
+    let mut executing_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([1u8; 32], 512),
+        HashMap::from([("prop".to_string(), "1".to_string())]),
+        make_system_time(1),
+    )
+    .await?;
+    let start_execute = start_execute_from_update(rx_from_worker.recv().await.unwrap());
+    assert_eq!(
+        executing_listener.changed().await?.stage,
+        ActionStage::Executing
+    );
+    let mut queued_listener = setup_action(
+        &scheduler,
+        DigestInfo::new([2u8; 32], 512),
+        HashMap::from([("prop".to_string(), "2".to_string())]),
+        make_system_time(1),
+    )
+    .await?;
+    assert_eq!(queued_listener.changed().await?.stage, ActionStage::Queued);
+
+    let executing = worker_scheduler
+        .list_operations(SortedAwaitedActionState::Executing, Bound::Unbounded, 10)
+        .await?;
+    assert_eq!(executing.len(), 1);
+    assert_eq!(executing[0].state, SortedAwaitedActionState::Executing);
+    assert_eq!(
+        executing[0].operation_id,
+        OperationId::from(start_execute.operation_id.as_str())
+    );
+
+    let queued = worker_scheduler
+        .list_operations(SortedAwaitedActionState::Queued, Bound::Unbounded, 10)
+        .await?;
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].state, SortedAwaitedActionState::Queued);
+    assert_ne!(queued[0].operation_id, executing[0].operation_id);
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+mod utils {
+    pub(crate) mod mock_scheduler;
+    pub(crate) mod scheduler_utils;
+}
+
+use futures::join;
+use nativelink_config::stores::MemorySpec;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::{Directory, FileNode};
+use nativelink_scheduler::touch_running_actions::touch_running_action_inputs;
+use nativelink_store::ac_utils::serialize_and_upload_message;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::action_messages::{
+    ActionInfo, ActionResult, ActionStage, ActionState, FileInfo, NameOrPath, OperationId,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::operation_state_manager::{
+    ActionStateResult, ActionStateResultStream, OperationFilter, OperationStageFlags,
+};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
+use pretty_assertions::assert_eq;
+use tokio::sync::watch;
+use utils::mock_scheduler::MockActionScheduler;
+use utils::scheduler_utils::{make_base_action_info, TokioWatchActionStateResult};
+
+const FILE_DATA: &str = "file data";
+
+fn make_executing_action(
+    operation_id: &OperationId,
+    action_info: Arc<ActionInfo>,
+) -> Box<dyn ActionStateResult> {
+    make_action_in_stage(operation_id, action_info, ActionStage::Executing)
+}
+
+fn make_action_in_stage(
+    operation_id: &OperationId,
+    action_info: Arc<ActionInfo>,
+    stage: ActionStage,
+) -> Box<dyn ActionStateResult> {
+    let (_tx, rx) = watch::channel(Arc::new(ActionState {
+        client_operation_id: operation_id.clone(),
+        stage,
+        action_digest: action_info.unique_qualifier.digest(),
+    }));
+    Box::new(TokioWatchActionStateResult::new(
+        operation_id.clone(),
+        action_info,
+        rx,
+    ))
+}
+
+/// Runs a single pass over the executing actions `mock_scheduler` returns.
+async fn touch_pass(
+    mock_scheduler: &MockActionScheduler,
+    cas_store: &Store,
+    inputs_for_operation: &mut HashMap<OperationId, Vec<StoreKey<'static>>>,
+    actions: Vec<Box<dyn ActionStateResult>>,
+) -> Result<u64, Error> {
+    let stream: ActionStateResultStream<'static> = Box::pin(futures::stream::iter(actions));
+    let (touched, filter) = join!(
+        touch_running_action_inputs(mock_scheduler, cas_store, inputs_for_operation),
+        mock_scheduler.expect_filter_operations(Ok(stream)),
+    );
+    assert_eq!(
+        filter,
+        OperationFilter {
+            stages: OperationStageFlags::Executing | OperationStageFlags::Completed,
+            ..Default::default()
+        }
+    );
+    touched
+}
+
+#[nativelink_test]
+async fn touch_reuses_inputs_and_skips_failed_actions() -> Result<(), Error> {
+    let mock_scheduler = MockActionScheduler::new();
+    let cas_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+
+    let file_digest = DigestInfo::new([1u8; 32], FILE_DATA.len() as u64);
+    cas_store
+        .update_oneshot(file_digest, FILE_DATA.into())
+        .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory {
+            files: vec![FileNode {
+                name: "file".to_string(),
+                digest: Some(file_digest.into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let running_operation_id = OperationId::default();
+    let mut running_action_info =
+        (*make_base_action_info(UNIX_EPOCH, DigestInfo::new([2u8; 32], 0))).clone();
+    running_action_info.input_root_digest = input_root_digest;
+    let running_action_info = Arc::new(running_action_info);
+
+    // The input root of this action is missing, so its inputs can not be
+    // collected.
+    let broken_operation_id = OperationId::default();
+    let mut broken_action_info =
+        (*make_base_action_info(UNIX_EPOCH, DigestInfo::new([3u8; 32], 0))).clone();
+    broken_action_info.input_root_digest = DigestInfo::new([4u8; 32], 10);
+    let broken_action_info = Arc::new(broken_action_info);
+
+    let mut inputs_for_operation = HashMap::new();
+    {
+        // The broken action does not stop the input root and the file of
+        // the running action from being touched.
+        let touched = touch_pass(
+            &mock_scheduler,
+            &cas_store,
+            &mut inputs_for_operation,
+            vec![
+                make_executing_action(&broken_operation_id, broken_action_info.clone()),
+                make_executing_action(&running_operation_id, running_action_info.clone()),
+            ],
+        )
+        .await?;
+        assert_eq!(touched, 2);
+        assert_eq!(
+            inputs_for_operation.keys().collect::<Vec<_>>(),
+            vec![&running_operation_id]
+        );
+    }
+    {
+        // The input root is not read again, so the file is still touched
+        // after the input root is gone.
+        cas_store.remove(input_root_digest).await?;
+        let touched = touch_pass(
+            &mock_scheduler,
+            &cas_store,
+            &mut inputs_for_operation,
+            vec![make_executing_action(
+                &running_operation_id,
+                running_action_info,
+            )],
+        )
+        .await?;
+        assert_eq!(touched, 1);
+    }
+    {
+        // Inputs of actions that stopped executing are forgotten.
+        let touched = touch_pass(
+            &mock_scheduler,
+            &cas_store,
+            &mut inputs_for_operation,
+            Vec::new(),
+        )
+        .await?;
+        assert_eq!(touched, 0);
+        assert!(inputs_for_operation.is_empty());
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn touch_outputs_of_completed_actions() -> Result<(), Error> {
+    let mock_scheduler = MockActionScheduler::new();
+    let cas_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+
+    let output_digest = DigestInfo::new([5u8; 32], FILE_DATA.len() as u64);
+    cas_store
+        .update_oneshot(output_digest, FILE_DATA.into())
+        .await?;
+    let stdout_digest = DigestInfo::new([6u8; 32], FILE_DATA.len() as u64);
+    cas_store
+        .update_oneshot(stdout_digest, FILE_DATA.into())
+        .await?;
+
+    let operation_id = OperationId::default();
+    let action_result = ActionResult {
+        output_files: vec![FileInfo {
+            name_or_path: NameOrPath::Path("out".to_string()),
+            digest: output_digest,
+            is_executable: false,
+            unix_mode: None,
+        }],
+        stdout_digest: Some(stdout_digest),
+        ..Default::default()
+    };
+    let mut inputs_for_operation = HashMap::new();
+    // The output file and stdout exist, the empty stderr does not. The
+    // inputs of completed actions are not collected.
+    let touched = touch_pass(
+        &mock_scheduler,
+        &cas_store,
+        &mut inputs_for_operation,
+        vec![make_action_in_stage(
+            &operation_id,
+            make_base_action_info(UNIX_EPOCH, DigestInfo::new([7u8; 32], 0)),
+            ActionStage::Completed(action_result),
+        )],
+    )
+    .await?;
+    assert_eq!(touched, 2);
+    assert!(inputs_for_operation.is_empty());
+    Ok(())
+}
@@ -83,6 +83,7 @@ impl MockActionScheduler {
         req
     }
 
+    #[allow(dead_code)] // See https://github.com/rust-lang/rust/issues/46379
     pub async fn expect_add_action(
         &self,
         result: Result<Box<dyn ActionStateResult>, Error>,
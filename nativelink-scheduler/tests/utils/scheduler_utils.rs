@@ -45,6 +45,7 @@ pub fn make_base_action_info(
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        do_not_cache: false,
     })
 }
 
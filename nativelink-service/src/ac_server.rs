@@ -16,34 +16,44 @@ use std::collections::HashMap;
 use std::convert::Into;
 use std::fmt::Debug;
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use nativelink_config::cas_server::{AcStoreConfig, InstanceName};
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_server::{
     ActionCache, ActionCacheServer as Server,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    ActionResult, GetActionResultRequest, UpdateActionResultRequest,
+    ActionResult, Digest, GetActionResultRequest, UpdateActionResultRequest,
 };
 use nativelink_store::ac_utils::{get_and_decode_digest, ESTIMATED_DIGEST_SIZE};
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::make_ctx_for_hash_func;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
 use nativelink_util::origin_event::OriginEventContext;
-use nativelink_util::store_trait::{Store, StoreLike};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike};
 use prost::Message;
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
 
+// NOTE: If this changes update the comments in `cas_server.rs` to reflect
+// the new default.
+const DEFAULT_MAX_INLINE_OUTPUT_BYTES: u64 = 1024 * 1024;
+
 #[derive(Clone)]
 pub struct AcStoreInfo {
     store: Store,
     read_only: bool,
+    verify_cas_store: Option<Store>,
+    inline_output_cas_store: Option<Store>,
+    max_inline_output_bytes: u64,
 }
 
 pub struct AcServer {
     stores: HashMap<String, AcStoreInfo>,
+    /// Client facing instance names and the instance they are served as.
+    instance_name_aliases: InstanceNameAliases,
 }
 
 impl Debug for AcServer {
@@ -62,19 +72,57 @@ impl AcServer {
             let store = store_manager.get_store(&ac_cfg.ac_store).ok_or_else(|| {
                 make_input_err!("'ac_store': '{}' does not exist", ac_cfg.ac_store)
             })?;
+            let verify_cas_store = ac_cfg
+                .verify_cas_store
+                .as_ref()
+                .map(|cas_store| {
+                    store_manager.get_store(cas_store).ok_or_else(|| {
+                        make_input_err!("'verify_cas_store': '{}' does not exist", cas_store)
+                    })
+                })
+                .transpose()?;
+            let inline_output_cas_store = ac_cfg
+                .inline_output_cas_store
+                .as_ref()
+                .map(|cas_store| {
+                    store_manager.get_store(cas_store).ok_or_else(|| {
+                        make_input_err!("'inline_output_cas_store': '{}' does not exist", cas_store)
+                    })
+                })
+                .transpose()?;
+            let max_inline_output_bytes = if ac_cfg.max_inline_output_bytes == 0 {
+                DEFAULT_MAX_INLINE_OUTPUT_BYTES
+            } else {
+                ac_cfg.max_inline_output_bytes as u64
+            };
             stores.insert(
                 instance_name.to_string(),
                 AcStoreInfo {
                     store,
                     read_only: ac_cfg.read_only,
+                    verify_cas_store,
+                    inline_output_cas_store,
+                    max_inline_output_bytes,
                 },
             );
         }
         Ok(AcServer {
             stores: stores.clone(),
+            instance_name_aliases: InstanceNameAliases::default(),
         })
     }
 
+    /// Serves requests for each alias in `instance_name_aliases` as if they
+    /// were for the instance it maps to.
+    #[must_use]
+    pub fn with_instance_name_aliases(
+        mut self,
+        instance_name_aliases: InstanceNameAliases,
+    ) -> Self {
+        self.instance_name_aliases = instance_name_aliases;
+        self
+    }
+
     pub fn into_service(self) -> Server<AcServer> {
         Server::new(self)
     }
@@ -106,7 +154,38 @@ impl AcServer {
 
         let res = get_and_decode_digest::<ActionResult>(&store_info.store, digest.into()).await;
         match res {
-            Ok(action_result) => Ok(Response::new(action_result)),
+            Ok(mut action_result) => {
+                // Output that was inlined when the entry was written is only
+                // sent to clients that asked for it, unless it is not in the
+                // CAS because the worker inlined it.
+                if !request.inline_stdout && action_result.stdout_digest.is_some() {
+                    action_result.stdout_raw = Bytes::new();
+                }
+                if !request.inline_stderr && action_result.stderr_digest.is_some() {
+                    action_result.stderr_raw = Bytes::new();
+                }
+                if let Some(cas_store) = &store_info.inline_output_cas_store {
+                    if request.inline_stdout {
+                        inline_output(
+                            cas_store,
+                            store_info.max_inline_output_bytes,
+                            action_result.stdout_digest.as_ref(),
+                            &mut action_result.stdout_raw,
+                        )
+                        .await;
+                    }
+                    if request.inline_stderr {
+                        inline_output(
+                            cas_store,
+                            store_info.max_inline_output_bytes,
+                            action_result.stderr_digest.as_ref(),
+                            &mut action_result.stderr_raw,
+                        )
+                        .await;
+                    }
+                }
+                Ok(Response::new(action_result))
+            }
             Err(mut e) => {
                 if e.code == Code::NotFound {
                     // `get_action_result` is frequent to get NotFound errors, so remove all
@@ -141,6 +220,18 @@ impl AcServer {
             .err_tip(|| "Action digest was not set in message")?
             .try_into()?;
 
+        if let Some(cas_store) = &store_info.verify_cas_store {
+            verify_outputs_in_cas(
+                cas_store,
+                request
+                    .action_result
+                    .as_ref()
+                    .err_tip(|| "Action result was not set in message")?,
+            )
+            .await
+            .err_tip(|| format!("Rejecting action result for {digest}"))?;
+        }
+
         // If we are a GrpcStore we shortcut here, as this is a special store.
         if let Some(grpc_store) = store_info
             .store
@@ -167,6 +258,93 @@ impl AcServer {
     }
 }
 
+/// Reads the blob of `digest` from `cas_store` into `raw` unless it is
+/// already inlined, empty or larger than `max_inline_output_bytes`. Inlining
+/// is optional, so a blob that can not be read is only logged.
+async fn inline_output(
+    cas_store: &Store,
+    max_inline_output_bytes: u64,
+    digest: Option<&Digest>,
+    raw: &mut Bytes,
+) {
+    let Some(digest) = digest else {
+        return;
+    };
+    if !raw.is_empty() {
+        return;
+    }
+    let digest = match DigestInfo::try_from(digest) {
+        Ok(digest) => digest,
+        Err(err) => {
+            event!(Level::WARN, ?err, "Invalid output digest in action result");
+            return;
+        }
+    };
+    if digest.size_bytes() == 0 || digest.size_bytes() > max_inline_output_bytes {
+        return;
+    }
+    match cas_store.get_part_unchunked(digest, 0, None).await {
+        Ok(data) => *raw = data,
+        Err(err) => event!(
+            Level::WARN,
+            ?err,
+            ?digest,
+            "Could not read output to inline in GetActionResult"
+        ),
+    }
+}
+
+/// Returns a `FailedPrecondition` error if any output file, output directory
+/// tree, stdout or stderr digest referenced by `action_result` is missing
+/// from `cas_store`.
+async fn verify_outputs_in_cas(
+    cas_store: &Store,
+    action_result: &ActionResult,
+) -> Result<(), Error> {
+    let mut digests = Vec::with_capacity(
+        action_result.output_files.len() + action_result.output_directories.len() + 2,
+    );
+    for output_file in &action_result.output_files {
+        digests.push(DigestInfo::try_from(output_file.digest.as_ref().err_tip(
+            || format!("Output file '{}' has no digest", output_file.path),
+        )?)?);
+    }
+    for output_directory in &action_result.output_directories {
+        digests.push(DigestInfo::try_from(
+            output_directory.tree_digest.as_ref().err_tip(|| {
+                format!(
+                    "Output directory '{}' has no tree digest",
+                    output_directory.path
+                )
+            })?,
+        )?);
+    }
+    for digest in [&action_result.stdout_digest, &action_result.stderr_digest]
+        .into_iter()
+        .flatten()
+    {
+        digests.push(DigestInfo::try_from(digest)?);
+    }
+
+    let keys: Vec<StoreKey> = digests.iter().map(|digest| (*digest).into()).collect();
+    let results = cas_store
+        .has_many(&keys)
+        .await
+        .err_tip(|| "Failed to check outputs exist in CAS")?;
+    let missing: Vec<&DigestInfo> = digests
+        .iter()
+        .zip(results)
+        .filter_map(|(digest, result)| result.is_none().then_some(digest))
+        .collect();
+    if !missing.is_empty() {
+        return Err(make_err!(
+            Code::FailedPrecondition,
+            "Action result references digests missing from CAS: {missing:?}"
+        ));
+    }
+    Ok(())
+}
+
 #[tonic::async_trait]
 impl ActionCache for AcServer {
     #[allow(clippy::blocks_in_conditions)]
@@ -180,7 +358,9 @@ impl ActionCache for AcServer {
         &self,
         grpc_request: Request<GetActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
 
         let resp = make_ctx_for_hash_func(request.digest_function)
@@ -211,7 +391,9 @@ impl ActionCache for AcServer {
         &self,
         grpc_request: Request<UpdateActionResultRequest>,
     ) -> Result<Response<ActionResult>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
         let resp = make_ctx_for_hash_func(request.digest_function)
             .err_tip(|| "In AcServer::update_action_result")?
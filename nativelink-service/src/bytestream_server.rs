@@ -16,16 +16,19 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::convert::Into;
 use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use bytes::Bytes;
 use futures::future::{pending, BoxFuture};
 use futures::stream::unfold;
 use futures::{try_join, Future, Stream, TryFutureExt};
 use nativelink_config::cas_server::ByteStreamConfig;
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::google::bytestream::byte_stream_server::{
     ByteStream, ByteStreamServer as Server,
 };
@@ -38,18 +41,21 @@ use nativelink_store::store_manager::StoreManager;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
-use nativelink_util::common::DigestInfo;
+use nativelink_util::common::{ClientAddr, DigestInfo};
 use nativelink_util::digest_hasher::{
-    default_digest_hasher_func, make_ctx_for_hash_func, DigestHasherFunc,
+    make_ctx_for_hash_func, resolve_instance_digest_function, DigestHasherFunc,
 };
+use nativelink_util::inflight_writes::InflightWrites;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
+use nativelink_util::origin_context::trace_id_from_metadata;
 use nativelink_util::origin_event::OriginEventContext;
 use nativelink_util::proto_stream_utils::WriteRequestStreamWrapper;
 use nativelink_util::resource_info::ResourceInfo;
-use nativelink_util::spawn;
 use nativelink_util::store_trait::{Store, StoreLike, UploadSizeInfo};
 use nativelink_util::task::JoinHandleDropGuard;
+use nativelink_util::{spawn, spawn_blocking};
 use parking_lot::Mutex;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 use tonic::{Request, Response, Status, Streaming};
 use tracing::{enabled, error_span, event, instrument, Instrument, Level};
 
@@ -67,6 +73,7 @@ type StoreUpdateFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send +
 
 struct StreamState {
     uuid: String,
+    digest: DigestInfo,
     tx: DropCloserWriteHalf,
     store_update_fut: StoreUpdateFuture,
 }
@@ -75,6 +82,7 @@ impl Debug for StreamState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("StreamState")
             .field("uuid", &self.uuid)
+            .field("digest", &self.digest)
             .finish()
     }
 }
@@ -156,14 +164,84 @@ impl IdleStream {
 
 type BytesWrittenAndIdleStream = (Arc<AtomicU64>, Option<IdleStream>);
 type SleepFn = Arc<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>;
+type ClientWriteCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Counts a `Write` stream against its client until dropped.
+struct ClientWriteGuard {
+    client_writes: ClientWriteCounts,
+    ip: IpAddr,
+}
+
+impl Drop for ClientWriteGuard {
+    fn drop(&mut self) {
+        let mut client_writes = self.client_writes.lock();
+        if let Entry::Occupied(mut entry) = client_writes.entry(self.ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// Returns true if the data of `resource_info` is zstd compressed. zstd
+/// is the only supported compressor besides `identity`.
+fn is_zstd_compressed(resource_info: &ResourceInfo) -> Result<bool, Error> {
+    match resource_info.compressor.as_deref() {
+        None | Some("identity") => Ok(false),
+        Some("zstd") => Ok(true),
+        Some(compressor) => Err(make_err!(
+            Code::Unimplemented,
+            "Compressor '{compressor}' is not supported"
+        )),
+    }
+}
+
+/// Collects the output of the zstd decoder of a compressed upload. Writes
+/// fail as soon as more than `limit` bytes are collected, so a small
+/// message that decompresses to a huge amount of data is rejected before
+/// it is held in memory.
+struct BoundedWriter {
+    data: Vec<u8>,
+    limit: u64,
+    exceeded: bool,
+}
+
+impl Write for BoundedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.data.len() as u64 + buf.len() as u64 > self.limit {
+            self.exceeded = true;
+            return Err(std::io::Error::other(
+                "Decompressed data is larger than the remaining size",
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
 pub struct ByteStreamServer {
     stores: HashMap<String, Store>,
+    // Digest function each restricted instance must use.
+    digest_functions: HashMap<String, DigestHasherFunc>,
     // Max number of bytes to send on each grpc stream chunk.
     max_bytes_per_stream: usize,
     max_decoding_message_size: usize,
     active_uploads: Arc<Mutex<HashMap<String, BytesWrittenAndIdleStream>>>,
     sleep_fn: SleepFn,
+    // Writes in flight, which are drained on shutdown.
+    inflight_writes: InflightWrites,
+    // Max number of writes each client may have in flight, 0 is unlimited.
+    max_concurrent_writes_per_client: usize,
+    client_writes: ClientWriteCounts,
+    // Abort write streams that receive no data for this long.
+    write_idle_timeout: Option<Duration>,
+    // Client facing instance names and the instance they are served as.
+    instance_name_aliases: InstanceNameAliases,
 }
 
 impl ByteStreamServer {
@@ -192,6 +270,13 @@ impl ByteStreamServer {
                 .ok_or_else(|| make_input_err!("'cas_store': '{}' does not exist", store_name))?;
             stores.insert(instance_name.to_string(), store);
         }
+        let digest_functions = config
+            .digest_functions
+            .iter()
+            .map(|(instance_name, digest_function)| {
+                (instance_name.to_string(), (*digest_function).into())
+            })
+            .collect();
         let max_bytes_per_stream = if config.max_bytes_per_stream == 0 {
             DEFAULT_MAX_BYTES_PER_STREAM
         } else {
@@ -204,18 +289,86 @@ impl ByteStreamServer {
         };
         Ok(ByteStreamServer {
             stores,
+            digest_functions,
             max_bytes_per_stream,
             max_decoding_message_size,
             active_uploads: Arc::new(Mutex::new(HashMap::new())),
             sleep_fn,
+            inflight_writes: InflightWrites::default(),
+            max_concurrent_writes_per_client: config.max_concurrent_writes_per_client,
+            client_writes: Arc::new(Mutex::new(HashMap::new())),
+            write_idle_timeout: (config.write_idle_timeout != 0)
+                .then(|| Duration::from_secs(config.write_idle_timeout as u64)),
+            instance_name_aliases: InstanceNameAliases::default(),
         })
     }
 
+    /// Serves requests for each alias in `instance_name_aliases` as if they
+    /// were for the instance it maps to.
+    #[must_use]
+    pub fn with_instance_name_aliases(
+        mut self,
+        instance_name_aliases: InstanceNameAliases,
+    ) -> Self {
+        self.instance_name_aliases = instance_name_aliases;
+        self
+    }
+
+    /// Tracks writes in `inflight_writes`, so new writes are rejected once it
+    /// starts draining.
+    #[must_use]
+    pub fn with_inflight_writes(mut self, inflight_writes: InflightWrites) -> Self {
+        self.inflight_writes = inflight_writes;
+        self
+    }
+
     pub fn into_service(self) -> Server<Self> {
         let max_decoding_message_size = self.max_decoding_message_size;
         Server::new(self).max_decoding_message_size(max_decoding_message_size)
     }
 
+    /// Counts a write against `client_addr`, failing with `ResourceExhausted`
+    /// if the client already has `max_concurrent_writes_per_client` writes in
+    /// flight. Requests without a client address are not limited.
+    fn try_start_client_write(
+        &self,
+        client_addr: Option<ClientAddr>,
+    ) -> Result<Option<ClientWriteGuard>, Error> {
+        let Some(ClientAddr(addr)) = client_addr else {
+            return Ok(None);
+        };
+        if self.max_concurrent_writes_per_client == 0 {
+            return Ok(None);
+        }
+        let ip = addr.ip();
+        let mut client_writes = self.client_writes.lock();
+        let count = client_writes.entry(ip).or_default();
+        if *count >= self.max_concurrent_writes_per_client {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Client {ip} already has {count} writes in flight"
+            ));
+        }
+        *count += 1;
+        Ok(Some(ClientWriteGuard {
+            client_writes: self.client_writes.clone(),
+            ip,
+        }))
+    }
+
+    /// Resolves the digest function for a request to `instance_name`, which
+    /// must match the digest function configured for the instance, if any.
+    fn digest_function_for_instance(
+        &self,
+        instance_name: &str,
+        requested: Option<&str>,
+    ) -> Result<DigestHasherFunc, Error> {
+        resolve_instance_digest_function(
+            requested.map(DigestHasherFunc::try_from).transpose()?,
+            self.digest_functions.get(instance_name).copied(),
+        )
+    }
+
     fn create_or_join_upload_stream(
         &self,
         uuid: String,
@@ -224,6 +377,18 @@ impl ByteStreamServer {
     ) -> Result<ActiveStreamGuard<'_>, Error> {
         let (uuid, bytes_received) = match self.active_uploads.lock().entry(uuid) {
             Entry::Occupied(mut entry) => {
+                // A resumed upload must be for the same blob as the original
+                // one, otherwise its data would end up in the wrong digest.
+                if let Some(idle_stream) = &entry.get().1 {
+                    if idle_stream.stream_state.digest != digest {
+                        return Err(make_input_err!(
+                            "Cannot resume upload {} of {} with data for {}",
+                            entry.key(),
+                            idle_stream.stream_state.digest,
+                            digest
+                        ));
+                    }
+                }
                 let maybe_idle_stream = entry.get_mut();
                 let Some(idle_stream) = maybe_idle_stream.1.take() else {
                     return Err(make_input_err!("Cannot upload same UUID simultaneously"));
@@ -257,6 +422,7 @@ impl ByteStreamServer {
         Ok(ActiveStreamGuard {
             stream_state: Some(StreamState {
                 uuid,
+                digest,
                 tx,
                 store_update_fut,
             }),
@@ -270,9 +436,11 @@ impl ByteStreamServer {
         store: Store,
         digest: DigestInfo,
         read_request: ReadRequest,
+        compress: bool,
     ) -> Result<impl Stream<Item = Result<ReadResponse, Status>> + Send + 'static, Error> {
         struct ReaderState {
             max_bytes_per_stream: usize,
+            compress: bool,
             rx: DropCloserReadHalf,
             maybe_get_part_result: Option<Result<(), Error>>,
             get_part_fut: Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>,
@@ -280,6 +448,14 @@ impl ByteStreamServer {
 
         let read_limit = u64::try_from(read_request.read_limit)
             .err_tip(|| "Could not convert read_limit to u64")?;
+        // Offsets and limits of compressed reads refer to the compressed data,
+        // which is only produced while streaming.
+        if compress && (read_request.read_offset != 0 || read_limit != 0) {
+            return Err(make_err!(
+                Code::Unimplemented,
+                "Compressed reads with a read_offset or read_limit are not supported"
+            ));
+        }
 
         let (tx, rx) = make_buf_channel_pair();
 
@@ -293,6 +469,7 @@ impl ByteStreamServer {
         let state = Some(ReaderState {
             rx,
             max_bytes_per_stream: self.max_bytes_per_stream,
+            compress,
             maybe_get_part_result: None,
             get_part_fut: Box::pin(async move {
                 store
@@ -329,7 +506,29 @@ impl ByteStreamServer {
                                         let err = make_err!(Code::Internal, "Returned store size was larger than read size");
                                         return Some((Err(err.into()), None));
                                     }
-                                    response.data = bytes;
+                                    if state.compress {
+                                        // Every chunk is compressed into its own zstd frame, a
+                                        // sequence of frames decompresses to the concatenated data.
+                                        // Compression runs on a blocking thread, so large chunks
+                                        // do not stall the async workers.
+                                        let compressed = spawn_blocking!("bytestream_read_compress", move || {
+                                            zstd::bulk::compress(&bytes, 0)
+                                        })
+                                        .await;
+                                        match compressed {
+                                            Ok(Ok(compressed)) => response.data = Bytes::from(compressed),
+                                            Ok(Err(err)) => {
+                                                let err = make_err!(Code::Internal, "Failed to zstd compress data: {err}");
+                                                return Some((Err(err.into()), None));
+                                            }
+                                            Err(err) => {
+                                                let err = make_err!(Code::Internal, "Failed to zstd compress data due to spawn failing {err:?}");
+                                                return Some((Err(err.into()), None));
+                                            }
+                                        }
+                                    } else {
+                                        response.data = bytes;
+                                    }
                                     if enabled!(Level::DEBUG) {
                                         event!(Level::INFO, response = ?response);
                                     } else {
@@ -352,12 +551,8 @@ impl ByteStreamServer {
                                     if let Err(err) = get_part_result {
                                         e = err.merge(e);
                                     }
-                                    if e.code == Code::NotFound {
-                                        // Trim the error code. Not Found is quite common and we don't want to send a large
-                                        // error (debug) message for something that is common. We resize to just the last
-                                        // message as it will be the most relevant.
-                                        e.messages.truncate(1);
-                                    }
+                                    // The full error is logged, but errors with a reason,
+                                    // like NotFound, only send the client part of it.
                                     event!(Level::ERROR, response = ?e);
                                     return Some((Err(e.into()), None))
                                 }
@@ -407,9 +602,21 @@ impl ByteStreamServer {
             tx: &mut DropCloserWriteHalf,
             outer_bytes_received: &Arc<AtomicU64>,
             expected_size: u64,
+            idle_timeout: Option<Duration>,
         ) -> Result<(), Error> {
             loop {
-                let write_request = match stream.next().await {
+                let next_request = match idle_timeout {
+                    Some(idle_timeout) => {
+                        timeout(idle_timeout, stream.next()).await.map_err(|_| {
+                            make_err!(
+                                Code::DeadlineExceeded,
+                                "Client sent no data for {idle_timeout:?}"
+                            )
+                        })?
+                    }
+                    None => stream.next().await,
+                };
+                let write_request = match next_request {
                     // Code path for when client tries to gracefully close the stream.
                     // If this happens it means there's a problem with the data sent,
                     // because we always close the stream from our end before this point
@@ -501,7 +708,8 @@ impl ByteStreamServer {
                 stream,
                 &mut active_stream.tx,
                 &active_stream_guard.bytes_received,
-                expected_size
+                expected_size,
+                self.write_idle_timeout,
             ),
             (&mut active_stream.store_update_fut)
                 .map_err(|err| { err.append("Error updating inner store") })
@@ -515,23 +723,130 @@ impl ByteStreamServer {
         }))
     }
 
+    /// Writes a zstd compressed upload, the data is decompressed before it
+    /// reaches the store. Unlike uncompressed uploads, these can not be
+    /// resumed.
+    async fn inner_write_zstd(
+        &self,
+        store: Store,
+        digest: DigestInfo,
+        stream: WriteRequestStreamWrapper<impl Stream<Item = Result<WriteRequest, Status>> + Unpin>,
+    ) -> Result<Response<WriteResponse>, Error> {
+        async fn process_client_stream(
+            mut stream: WriteRequestStreamWrapper<
+                impl Stream<Item = Result<WriteRequest, Status>> + Unpin,
+            >,
+            mut tx: DropCloserWriteHalf,
+            expected_size: u64,
+            idle_timeout: Option<Duration>,
+        ) -> Result<i64, Error> {
+            let mut decoder = zstd::stream::write::Decoder::new(BoundedWriter {
+                data: Vec::new(),
+                limit: 0,
+                exceeded: false,
+            })
+            .map_err(|err| make_err!(Code::Internal, "Failed to create zstd decoder: {err}"))?;
+            let mut compressed_bytes = 0;
+            loop {
+                let next_request = match idle_timeout {
+                    Some(idle_timeout) => {
+                        timeout(idle_timeout, stream.next()).await.map_err(|_| {
+                            make_err!(
+                                Code::DeadlineExceeded,
+                                "Client sent no data for {idle_timeout:?}"
+                            )
+                        })?
+                    }
+                    None => stream.next().await,
+                };
+                let write_request = match next_request {
+                    None => {
+                        return Err(make_input_err!(
+                            "Client closed stream before sending all data"
+                        ))
+                    }
+                    Some(Err(err)) => return Err(err),
+                    Some(Ok(write_request)) => write_request,
+                };
+
+                error_if!(
+                    write_request.write_offset != compressed_bytes,
+                    "Received out of order data. Got {}, expected {}",
+                    write_request.write_offset,
+                    compressed_bytes
+                );
+                compressed_bytes += write_request.data.len() as i64;
+
+                // One byte more than the remaining size is allowed, so data
+                // that decompresses past the expected size is detected.
+                decoder.get_mut().limit = expected_size - tx.get_bytes_written() + 1;
+                if let Err(err) = decoder
+                    .write_all(&write_request.data)
+                    .and_then(|()| decoder.flush())
+                {
+                    if decoder.get_ref().exceeded {
+                        return Err(make_input_err!(
+                            "Compressed data decompresses to more than {expected_size} bytes"
+                        ));
+                    }
+                    return Err(make_input_err!("Failed to decompress zstd data: {err}"));
+                }
+                let data = std::mem::take(&mut decoder.get_mut().data);
+                if !data.is_empty() {
+                    if let Err(mut err) = tx.send(Bytes::from(data)).await {
+                        err.code = Code::Internal;
+                        return Err(err);
+                    }
+                }
+
+                if expected_size < tx.get_bytes_written() {
+                    return Err(make_input_err!("Received more bytes than expected"));
+                }
+                if write_request.finish_write {
+                    error_if!(
+                        expected_size != tx.get_bytes_written(),
+                        "Compressed data decompressed to {} bytes, expected {}",
+                        tx.get_bytes_written(),
+                        expected_size
+                    );
+                    tx.send_eof()
+                        .err_tip(|| "Failed to send EOF in ByteStream::write")?;
+                    return Ok(compressed_bytes);
+                }
+            }
+        }
+
+        let expected_size = stream.resource_info.expected_size as u64;
+        let (tx, rx) = make_buf_channel_pair();
+        let (compressed_bytes, ()) = try_join!(
+            process_client_stream(stream, tx, expected_size, self.write_idle_timeout),
+            store
+                .update(digest, rx, UploadSizeInfo::ExactSize(expected_size))
+                .map_err(|err| { err.append("Error updating inner store") })
+        )?;
+
+        Ok(Response::new(WriteResponse {
+            committed_size: compressed_bytes,
+        }))
+    }
+
     async fn inner_query_write_status(
         &self,
         query_request: &QueryWriteStatusRequest,
     ) -> Result<Response<QueryWriteStatusResponse>, Error> {
         let mut resource_info = ResourceInfo::new(&query_request.resource_name, true)?;
+        let instance_name = self
+            .instance_name_aliases
+            .resolve(resource_info.instance_name.as_ref());
 
         let store_clone = self
             .stores
-            .get(resource_info.instance_name.as_ref())
-            .err_tip(|| {
-                format!(
-                    "'instance_name' not configured for '{}'",
-                    &resource_info.instance_name
-                )
-            })?
+            .get(instance_name)
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
             .clone();
 
+        self.digest_function_for_instance(instance_name, resource_info.digest_function.as_deref())?;
+
         let digest = DigestInfo::try_new(resource_info.hash.as_ref(), resource_info.expected_size)?;
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
@@ -585,7 +900,10 @@ impl ByteStream for ByteStreamServer {
         err,
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            request = ?grpc_request.get_ref(),
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+        )
     )]
     async fn read(
         &self,
@@ -595,13 +913,20 @@ impl ByteStream for ByteStreamServer {
         let ctx = OriginEventContext::new(|| &read_request).await;
 
         let resource_info = ResourceInfo::new(&read_request.resource_name, false)?;
-        let instance_name = resource_info.instance_name.as_ref();
+        let instance_name = self
+            .instance_name_aliases
+            .resolve(resource_info.instance_name.as_ref());
         let store = self
             .stores
             .get(instance_name)
             .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
             .clone();
 
+        let digest_function = self.digest_function_for_instance(
+            instance_name,
+            resource_info.digest_function.as_deref(),
+        )?;
+
         let digest = DigestInfo::try_new(resource_info.hash.as_ref(), resource_info.expected_size)?;
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
@@ -612,16 +937,14 @@ impl ByteStream for ByteStreamServer {
             return resp;
         }
 
-        let digest_function = resource_info.digest_function.as_deref().map_or_else(
-            || Ok(default_digest_hasher_func()),
-            DigestHasherFunc::try_from,
-        )?;
+        let compress =
+            is_zstd_compressed(&resource_info).err_tip(|| "In ByteStreamServer::read")?;
 
         let resp = make_ctx_for_hash_func(digest_function)
             .err_tip(|| "In BytestreamServer::read")?
             .wrap_async(
                 error_span!("bytestream_read"),
-                self.inner_read(store, digest, read_request),
+                self.inner_read(store, digest, read_request, compress),
             )
             .await
             .err_tip(|| "In ByteStreamServer::read")
@@ -642,12 +965,16 @@ impl ByteStream for ByteStreamServer {
         err,
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            request = ?grpc_request.get_ref(),
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+        )
     )]
     async fn write(
         &self,
         grpc_request: Request<Streaming<WriteRequest>>,
     ) -> Result<Response<WriteResponse>, Status> {
+        let client_addr = grpc_request.extensions().get::<ClientAddr>().copied();
         let request = grpc_request.into_inner();
         let ctx = OriginEventContext::new(|| &request).await;
         let stream = WriteRequestStreamWrapper::from(ctx.wrap_stream(request))
@@ -655,13 +982,31 @@ impl ByteStream for ByteStreamServer {
             .err_tip(|| "Could not unwrap first stream message")
             .map_err(Into::<Status>::into)?;
 
-        let instance_name = stream.resource_info.instance_name.as_ref();
+        // Held until the write finishes, so shutdown waits for it.
+        let _inflight_write = self
+            .inflight_writes
+            .try_start()
+            .err_tip(|| "In ByteStreamServer::write")?;
+        let _client_write = self
+            .try_start_client_write(client_addr)
+            .err_tip(|| "In ByteStreamServer::write")?;
+
+        let instance_name = self
+            .instance_name_aliases
+            .resolve(stream.resource_info.instance_name.as_ref());
         let store = self
             .stores
             .get(instance_name)
             .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
             .clone();
 
+        let digest_function = self
+            .digest_function_for_instance(
+                instance_name,
+                stream.resource_info.digest_function.as_deref(),
+            )
+            .err_tip(|| "Invalid digest function in ByteStream::write")?;
+
         let digest = DigestInfo::try_new(
             &stream.resource_info.hash,
             stream.resource_info.expected_size,
@@ -675,21 +1020,18 @@ impl ByteStream for ByteStreamServer {
             return resp;
         }
 
-        let digest_function = stream
-            .resource_info
-            .digest_function
-            .as_deref()
-            .map_or_else(
-                || Ok(default_digest_hasher_func()),
-                DigestHasherFunc::try_from,
-            )?;
+        let compressed =
+            is_zstd_compressed(&stream.resource_info).err_tip(|| "In ByteStreamServer::write")?;
 
         let resp = make_ctx_for_hash_func(digest_function)
             .err_tip(|| "In BytestreamServer::write")?
-            .wrap_async(
-                error_span!("bytestream_write"),
-                self.inner_write(store, digest, stream),
-            )
+            .wrap_async(error_span!("bytestream_write"), async {
+                if compressed {
+                    self.inner_write_zstd(store, digest, stream).await
+                } else {
+                    self.inner_write(store, digest, stream).await
+                }
+            })
             .await
             .err_tip(|| "In ByteStreamServer::write")
             .map_err(Into::into);
@@ -703,7 +1045,10 @@ impl ByteStream for ByteStreamServer {
         ret(level = Level::INFO),
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            request = ?grpc_request.get_ref(),
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+        )
     )]
     async fn query_write_status(
         &self,
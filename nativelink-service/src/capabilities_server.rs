@@ -15,7 +15,9 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use nativelink_config::cas_server::{CapabilitiesConfig, InstanceName};
+use nativelink_config::cas_server::{
+    AcStoreConfig, CapabilitiesConfig, CasStoreConfig, InstanceName,
+};
 use nativelink_error::{Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::capabilities_server::{
     Capabilities, CapabilitiesServer as Server,
@@ -24,30 +26,86 @@ use nativelink_proto::build::bazel::remote::execution::v2::digest_function::Valu
 use nativelink_proto::build::bazel::remote::execution::v2::priority_capabilities::PriorityRange;
 use nativelink_proto::build::bazel::remote::execution::v2::symlink_absolute_path_strategy::Value as SymlinkAbsolutePathStrategy;
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    ActionCacheUpdateCapabilities, CacheCapabilities, ExecutionCapabilities,
+    compressor, ActionCacheUpdateCapabilities, CacheCapabilities, ExecutionCapabilities,
     GetCapabilitiesRequest, PriorityCapabilities, ServerCapabilities,
 };
 use nativelink_proto::build::bazel::semver::SemVer;
-use nativelink_util::digest_hasher::default_digest_hasher_func;
+use nativelink_util::digest_hasher::{default_digest_hasher_func, DigestHasherFunc};
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
 use nativelink_util::operation_state_manager::ClientStateManager;
 use nativelink_util::origin_event::OriginEventContext;
 use tonic::{Request, Response, Status};
 use tracing::{event, instrument, Level};
 
+/// Batch size reported to clients of instances without a `max_bytes_per_batch`.
 const MAX_BATCH_TOTAL_SIZE: i64 = 64 * 1024;
 
+/// Capabilities of a single instance derived from its CAS and AC configs.
+#[derive(Debug)]
+struct InstanceCapabilities {
+    /// Digest function the instance is restricted to, if configured.
+    digest_function: Option<DigestHasherFunc>,
+    max_batch_total_size_bytes: i64,
+    update_enabled: bool,
+}
+
+impl Default for InstanceCapabilities {
+    fn default() -> Self {
+        Self {
+            digest_function: None,
+            max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE,
+            update_enabled: true,
+        }
+    }
+}
+
+impl InstanceCapabilities {
+    fn digest_functions(&self) -> Vec<i32> {
+        match self.digest_function {
+            Some(digest_function) => vec![digest_function.proto_digest_func().into()],
+            None => vec![
+                DigestFunction::Sha256.into(),
+                DigestFunction::Blake3.into(),
+                DigestFunction::Sha512.into(),
+            ],
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CapabilitiesServer {
     supported_node_properties_for_instance: HashMap<InstanceName, Vec<String>>,
+    capabilities_for_instance: HashMap<InstanceName, InstanceCapabilities>,
+    /// Client facing instance names and the instance they are served as.
+    instance_name_aliases: InstanceNameAliases,
 }
 
 impl CapabilitiesServer {
+    /// The CAS and AC configs of the same server are used to report the
+    /// digest functions, batch size limit and whether the action cache may
+    /// be updated for each instance.
     pub async fn new(
         config: &HashMap<InstanceName, CapabilitiesConfig>,
+        cas_config: Option<&HashMap<InstanceName, CasStoreConfig>>,
+        ac_config: Option<&HashMap<InstanceName, AcStoreConfig>>,
         scheduler_map: &HashMap<String, Arc<dyn ClientStateManager>>,
     ) -> Result<Self, Error> {
         let mut supported_node_properties_for_instance = HashMap::new();
+        let mut capabilities_for_instance = HashMap::with_capacity(config.len());
         for (instance_name, cfg) in config {
+            let mut capabilities = InstanceCapabilities::default();
+            if let Some(cas_cfg) = cas_config.and_then(|cas_config| cas_config.get(instance_name)) {
+                capabilities.digest_function = cas_cfg.digest_function.map(DigestHasherFunc::from);
+                if cas_cfg.max_bytes_per_batch != 0 {
+                    capabilities.max_batch_total_size_bytes =
+                        i64::try_from(cas_cfg.max_bytes_per_batch).unwrap_or(i64::MAX);
+                }
+            }
+            if let Some(ac_cfg) = ac_config.and_then(|ac_config| ac_config.get(instance_name)) {
+                capabilities.update_enabled = !ac_cfg.read_only;
+            }
+            capabilities_for_instance.insert(instance_name.clone(), capabilities);
+
             let mut properties = Vec::new();
             if let Some(remote_execution_cfg) = &cfg.remote_execution {
                 let scheduler =
@@ -81,9 +139,22 @@ impl CapabilitiesServer {
         }
         Ok(CapabilitiesServer {
             supported_node_properties_for_instance,
+            capabilities_for_instance,
+            instance_name_aliases: InstanceNameAliases::default(),
         })
     }
 
+    /// Reports the capabilities of the instance each alias in
+    /// `instance_name_aliases` maps to.
+    #[must_use]
+    pub fn with_instance_name_aliases(
+        mut self,
+        instance_name_aliases: InstanceNameAliases,
+    ) -> Self {
+        self.instance_name_aliases = instance_name_aliases;
+        self
+    }
+
     pub fn into_service(self) -> Server<CapabilitiesServer> {
         Server::new(self)
     }
@@ -106,13 +177,22 @@ impl Capabilities for CapabilitiesServer {
         let request = grpc_request.into_inner();
         let ctx = OriginEventContext::new(|| &request).await;
 
-        let instance_name = request.instance_name;
+        let instance_name = self.instance_name_aliases.resolve(&request.instance_name);
+        let default_capabilities = InstanceCapabilities::default();
+        let capabilities = self
+            .capabilities_for_instance
+            .get(instance_name)
+            .unwrap_or(&default_capabilities);
         let maybe_supported_node_properties = self
             .supported_node_properties_for_instance
-            .get(&instance_name);
+            .get(instance_name);
         let execution_capabilities =
             maybe_supported_node_properties.map(|props_for_instance| ExecutionCapabilities {
-                digest_function: default_digest_hasher_func().proto_digest_func().into(),
+                digest_function: capabilities
+                    .digest_function
+                    .unwrap_or_else(default_digest_hasher_func)
+                    .proto_digest_func()
+                    .into(),
                 exec_enabled: true, // TODO(blaise.bruer) Make this configurable.
                 execution_priority_capabilities: Some(PriorityCapabilities {
                     priorities: vec![PriorityRange {
@@ -121,25 +201,21 @@ impl Capabilities for CapabilitiesServer {
                     }],
                 }),
                 supported_node_properties: props_for_instance.clone(),
-                digest_functions: vec![
-                    DigestFunction::Sha256.into(),
-                    DigestFunction::Blake3.into(),
-                ],
+                digest_functions: capabilities.digest_functions(),
             });
 
         let resp = ServerCapabilities {
             cache_capabilities: Some(CacheCapabilities {
-                digest_functions: vec![
-                    DigestFunction::Sha256.into(),
-                    DigestFunction::Blake3.into(),
-                ],
+                digest_functions: capabilities.digest_functions(),
                 action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
-                    update_enabled: true,
+                    update_enabled: capabilities.update_enabled,
                 }),
                 cache_priority_capabilities: None,
-                max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE,
+                max_batch_total_size_bytes: capabilities.max_batch_total_size_bytes,
                 symlink_absolute_path_strategy: SymlinkAbsolutePathStrategy::Disallowed.into(),
-                supported_compressors: vec![],
+                // `ByteStream` accepts zstd compressed blobs, but
+                // `BatchUpdateBlobs` does not.
+                supported_compressors: vec![compressor::Value::Zstd.into()],
                 supported_batch_update_compressors: vec![],
             }),
             execution_capabilities,
@@ -12,36 +12,288 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::convert::Into;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
 use bytes::Bytes;
 use futures::stream::{FuturesUnordered, Stream};
 use futures::{StreamExt, TryStreamExt};
 use nativelink_config::cas_server::{CasStoreConfig, InstanceName};
-use nativelink_error::{error_if, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::{
     ContentAddressableStorage, ContentAddressableStorageServer as Server,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
     batch_read_blobs_response, batch_update_blobs_response, compressor, BatchReadBlobsRequest,
-    BatchReadBlobsResponse, BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, Directory,
+    BatchReadBlobsResponse, BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, Digest, Directory,
     FindMissingBlobsRequest, FindMissingBlobsResponse, GetTreeRequest, GetTreeResponse,
 };
 use nativelink_proto::google::rpc::Status as GrpcStatus;
 use nativelink_store::ac_utils::get_and_decode_digest;
 use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::store_manager::StoreManager;
-use nativelink_util::common::DigestInfo;
-use nativelink_util::digest_hasher::make_ctx_for_hash_func;
+use nativelink_util::common::{fs, DigestInfo};
+use nativelink_util::digest_hasher::{
+    default_digest_hasher_func, make_ctx_for_hash_func, resolve_instance_digest_function,
+    DigestHasherFunc, ACTIVE_HASHER_FUNC,
+};
+use nativelink_util::inflight_writes::InflightWrites;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
+use nativelink_util::origin_context::{trace_id_from_metadata, ActiveOriginContext, OriginContext};
 use nativelink_util::origin_event::OriginEventContext;
-use nativelink_util::store_trait::{Store, StoreLike};
+use nativelink_util::spawn_blocking;
+use nativelink_util::store_trait::{Store, StoreLike, StoreOptimizations, UploadSizeInfo};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
+use uuid::Uuid;
+
+/// Maximum number of digests checked with a single call to the store
+/// while handling a `FindMissingBlobs` request.
+const FIND_MISSING_BLOBS_CHUNK_SIZE: usize = 10_000;
+
+/// Parses a digest from a request, rejecting hashes that the digest function
+/// the request is served with could not have produced.
+fn digest_info_for_request(digest: Digest) -> Result<DigestInfo, Error> {
+    let digest_info = DigestInfo::try_from(digest)?;
+    ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+        .err_tip(|| "In digest_info_for_request")?
+        .map_or_else(default_digest_hasher_func, |v| *v)
+        .check_digest(&digest_info)?;
+    Ok(digest_info)
+}
+
+struct InstanceInfo {
+    store: Store,
+    /// Maximum sum of digest sizes in a single batch request. Zero is unlimited.
+    max_bytes_per_batch: u64,
+    /// Maximum digest size of an individual blob in a batch request. Zero is unlimited.
+    max_blob_size: u64,
+    /// Maximum number of digests in a `FindMissingBlobs` request. Zero is unlimited.
+    max_digests_per_find_missing: usize,
+    /// Maximum directory levels walked by a `GetTree` request. Zero is unlimited.
+    max_tree_depth: usize,
+    /// Maximum directories visited or queued by a `GetTree` request. Zero is unlimited.
+    max_tree_nodes: usize,
+    /// Digest function all requests to this instance must use, if configured.
+    digest_function: Option<DigestHasherFunc>,
+    /// Directory batch updates are spilled to for stores that support
+    /// `update_with_whole_file`. None disables spilling.
+    batch_update_temp_path: Option<PathBuf>,
+    /// Blobs smaller than this are always uploaded from memory.
+    batch_update_min_file_size: u64,
+}
+
+impl InstanceInfo {
+    /// Rejects a batch request if any digest or the sum of all digests is
+    /// larger than the configured limits. This only looks at the digests, so
+    /// it is safe to call before any data is read.
+    fn check_batch_limits<'a>(
+        &self,
+        digests: impl IntoIterator<Item = &'a Digest>,
+    ) -> Result<(), Error> {
+        let mut total_bytes: u64 = 0;
+        for digest in digests {
+            let size_bytes = u64::try_from(digest.size_bytes).map_err(|_| {
+                make_input_err!("Digest size_bytes was negative: {}", digest.size_bytes)
+            })?;
+            if self.max_blob_size != 0 && size_bytes > self.max_blob_size {
+                return Err(make_err!(
+                    Code::ResourceExhausted,
+                    "Blob {}-{} is larger than the max_blob_size of {} bytes, use ByteStream instead",
+                    digest.hash,
+                    size_bytes,
+                    self.max_blob_size
+                ));
+            }
+            total_bytes = total_bytes.saturating_add(size_bytes);
+        }
+        if self.max_bytes_per_batch != 0 && total_bytes > self.max_bytes_per_batch {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Batch request of {} bytes is larger than the max_bytes_per_batch of {} bytes",
+                total_bytes,
+                self.max_bytes_per_batch
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the directory to spill a batch update blob of `size_bytes` to,
+    /// if it should be uploaded from a file instead of from memory.
+    fn batch_update_temp_path_for(&self, size_bytes: u64) -> Option<&Path> {
+        if size_bytes == 0 || size_bytes < self.batch_update_min_file_size {
+            return None;
+        }
+        if !self.store.optimized_for(StoreOptimizations::FileUpdates) {
+            return None;
+        }
+        self.batch_update_temp_path.as_deref()
+    }
+}
+
+/// Writes `data` to a new file in `temp_path` and uploads it with
+/// `update_with_whole_file`, so stores that support it can take ownership of
+/// the file instead of copying the data again.
+async fn update_from_spilled_file(
+    store: &Store,
+    temp_path: &Path,
+    digest_info: DigestInfo,
+    data: Bytes,
+) -> Result<(), Error> {
+    let file_path = temp_path.join(format!("{digest_info}-{}", Uuid::new_v4()));
+    let result = async {
+        let mut file = fs::create_file(&file_path).await?;
+        {
+            let writer = file.as_writer().await?;
+            writer
+                .write_all(&data)
+                .await
+                .err_tip(|| format!("Could not write to {file_path:?}"))?;
+            writer
+                .flush()
+                .await
+                .err_tip(|| format!("Could not flush {file_path:?}"))?;
+            writer
+                .rewind()
+                .await
+                .err_tip(|| format!("Could not rewind {file_path:?}"))?;
+        }
+        // The data is on disk now, so release it before the upload.
+        drop(data);
+        store
+            .update_with_whole_file(
+                digest_info,
+                file,
+                UploadSizeInfo::ExactSize(digest_info.size_bytes()),
+            )
+            .await
+    }
+    .await;
+    if matches!(result, Ok(None)) {
+        // The store took ownership of the file.
+        return Ok(());
+    }
+    if let Err(err) = fs::remove_file(&file_path).await {
+        event!(
+            Level::WARN,
+            ?err,
+            ?file_path,
+            "Failed to remove spilled batch update file"
+        );
+    }
+    result
+        .map(|_| ())
+        .err_tip(|| "In CasServer::update_from_spilled_file")
+}
+
+/// Appends `digest` to a raw page token as the one byte length of the packed
+/// hash, the packed hash and the 8 byte big-endian `size_bytes`.
+fn push_page_token_digest(raw: &mut Vec<u8>, digest: &DigestInfo) {
+    let packed_hash = &digest.packed_hash()[..];
+    // `PackedHash` holds at most `MAX_SIZE_OF_PACKED_HASH` bytes, so the
+    // length always fits in a byte.
+    raw.push(packed_hash.len() as u8);
+    raw.extend_from_slice(packed_hash);
+    raw.extend_from_slice(&digest.size_bytes().to_be_bytes());
+}
+
+/// Reads a digest written by `push_page_token_digest` from the start of
+/// `raw`, returning it and the bytes after it.
+fn pop_page_token_digest(raw: &[u8]) -> Result<(DigestInfo, &[u8]), Error> {
+    let (&hash_len, rest) = raw
+        .split_first()
+        .ok_or_else(|| make_input_err!("Unexpected end of decoded page_token"))?;
+    let digest_len = usize::from(hash_len) + size_of::<u64>();
+    error_if!(
+        hash_len == 0 || rest.len() < digest_len,
+        "Invalid digest length {hash_len} in decoded page_token"
+    );
+    let (hash, rest) = rest.split_at(usize::from(hash_len));
+    let (size_bytes, rest) = rest.split_at(size_of::<u64>());
+    let mut size = [0u8; size_of::<u64>()];
+    size.copy_from_slice(size_bytes);
+    let digest = DigestInfo::try_new_from_packed_hash(hash, u64::from_be_bytes(size))
+        .err_tip(|| "Invalid digest in decoded page_token")?;
+    Ok((digest, rest))
+}
+
+/// Position of a `GetTree` traversal between two pages: the directory at
+/// `index` of the directories `level` levels below the root, in BFS order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct PageCursor {
+    level: u32,
+    index: u64,
+}
+
+/// Maximum length of a `GetTree` page token, which holds a digest and a
+/// `PageCursor` in at most 114 base64 chars. Longer tokens are rejected
+/// before they are decoded.
+const MAX_PAGE_TOKEN_SIZE: usize = 128;
+
+/// Encodes `cursor` of a `GetTree` traversal of `root_digest` into an opaque
+/// page token. The token is the URL safe, unpadded base64 encoding of
+/// `root_digest`, the 4 byte big-endian level and the 8 byte big-endian
+/// index of the cursor, so its size does not depend on the shape of the
+/// tree.
+fn encode_page_token(root_digest: &DigestInfo, cursor: PageCursor) -> String {
+    let mut raw = Vec::new();
+    push_page_token_digest(&mut raw, root_digest);
+    raw.extend_from_slice(&cursor.level.to_be_bytes());
+    raw.extend_from_slice(&cursor.index.to_be_bytes());
+    BASE64_URL_SAFE_NO_PAD.encode(raw)
+}
+
+/// Decodes a page token created by `encode_page_token` back into the cursor
+/// it was created from. Fails if the token was created for a tree other
+/// than `root_digest`. Tokens come from clients, so they are never echoed
+/// back in errors.
+fn decode_page_token(page_token: &str, root_digest: &DigestInfo) -> Result<PageCursor, Error> {
+    error_if!(
+        page_token.len() > MAX_PAGE_TOKEN_SIZE,
+        "page_token of {} bytes is larger than the maximum of {MAX_PAGE_TOKEN_SIZE}",
+        page_token.len()
+    );
+    let raw = BASE64_URL_SAFE_NO_PAD
+        .decode(page_token)
+        .map_err(|e| make_input_err!("Invalid base64 in page_token : {e:?}"))?;
+    let (token_root_digest, remaining) =
+        pop_page_token_digest(&raw).err_tip(|| "Invalid page_token")?;
+    error_if!(
+        token_root_digest != *root_digest,
+        "page_token was created for another root_digest than {root_digest}"
+    );
+    error_if!(
+        remaining.len() != size_of::<u32>() + size_of::<u64>(),
+        "Invalid cursor length {} in decoded page_token",
+        remaining.len()
+    );
+    let (level, index) = remaining.split_at(size_of::<u32>());
+    let mut level_bytes = [0u8; size_of::<u32>()];
+    level_bytes.copy_from_slice(level);
+    let mut index_bytes = [0u8; size_of::<u64>()];
+    index_bytes.copy_from_slice(index);
+    Ok(PageCursor {
+        level: u32::from_be_bytes(level_bytes),
+        index: u64::from_be_bytes(index_bytes),
+    })
+}
 
 pub struct CasServer {
-    stores: HashMap<String, Store>,
+    instance_infos: HashMap<String, InstanceInfo>,
+    /// Client facing instance names and the instance they are served as.
+    instance_name_aliases: InstanceNameAliases,
+    /// Writes in flight, which are drained on shutdown.
+    inflight_writes: InflightWrites,
+    /// Limits on the size of a single gRPC message, zero uses the tonic
+    /// default.
+    max_decoding_message_size: usize,
+    max_encoding_message_size: usize,
 }
 
 type GetTreeStream = Pin<Box<dyn Stream<Item = Result<GetTreeResponse, Status>> + Send + 'static>>;
@@ -51,18 +303,109 @@ impl CasServer {
         config: &HashMap<InstanceName, CasStoreConfig>,
         store_manager: &StoreManager,
     ) -> Result<Self, Error> {
-        let mut stores = HashMap::with_capacity(config.len());
+        let mut instance_infos = HashMap::with_capacity(config.len());
         for (instance_name, cas_cfg) in config {
             let store = store_manager.get_store(&cas_cfg.cas_store).ok_or_else(|| {
                 make_input_err!("'cas_store': '{}' does not exist", cas_cfg.cas_store)
             })?;
-            stores.insert(instance_name.to_string(), store);
+            if let Some(temp_path) = &cas_cfg.batch_update_temp_path {
+                std::fs::create_dir_all(temp_path)
+                    .err_tip(|| format!("Could not create 'batch_update_temp_path' {temp_path}"))?;
+            }
+            instance_infos.insert(
+                instance_name.to_string(),
+                InstanceInfo {
+                    store,
+                    max_bytes_per_batch: cas_cfg.max_bytes_per_batch,
+                    max_blob_size: cas_cfg.max_blob_size,
+                    max_digests_per_find_missing: cas_cfg.max_digests_per_find_missing,
+                    max_tree_depth: cas_cfg.max_tree_depth,
+                    max_tree_nodes: cas_cfg.max_tree_nodes,
+                    digest_function: cas_cfg.digest_function.map(DigestHasherFunc::from),
+                    batch_update_temp_path: cas_cfg
+                        .batch_update_temp_path
+                        .as_ref()
+                        .map(PathBuf::from),
+                    batch_update_min_file_size: cas_cfg.batch_update_min_file_size,
+                },
+            );
         }
-        Ok(CasServer { stores })
+        Ok(CasServer {
+            instance_infos,
+            instance_name_aliases: InstanceNameAliases::default(),
+            inflight_writes: InflightWrites::default(),
+            max_decoding_message_size: 0,
+            max_encoding_message_size: 0,
+        })
+    }
+
+    /// Serves requests for each alias in `instance_name_aliases` as if they
+    /// were for the instance it maps to.
+    #[must_use]
+    pub fn with_instance_name_aliases(
+        mut self,
+        instance_name_aliases: InstanceNameAliases,
+    ) -> Self {
+        self.instance_name_aliases = instance_name_aliases;
+        self
+    }
+
+    /// Tracks `BatchUpdateBlobs` requests in `inflight_writes`, so new
+    /// uploads are rejected once it starts draining.
+    #[must_use]
+    pub fn with_inflight_writes(mut self, inflight_writes: InflightWrites) -> Self {
+        self.inflight_writes = inflight_writes;
+        self
+    }
+
+    /// Sets the largest gRPC message the service will decode and encode.
+    /// Zero keeps the tonic default.
+    #[must_use]
+    pub fn with_max_message_sizes(
+        mut self,
+        max_decoding_message_size: usize,
+        max_encoding_message_size: usize,
+    ) -> Self {
+        self.max_decoding_message_size = max_decoding_message_size;
+        self.max_encoding_message_size = max_encoding_message_size;
+        self
     }
 
     pub fn into_service(self) -> Server<CasServer> {
-        Server::new(self)
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
+        let mut service = Server::new(self);
+        if max_decoding_message_size != 0 {
+            service = service.max_decoding_message_size(max_decoding_message_size);
+        }
+        if max_encoding_message_size != 0 {
+            service = service.max_encoding_message_size(max_encoding_message_size);
+        }
+        service
+    }
+
+    /// Creates the context a request is served in, using the digest function
+    /// configured for `instance_name` if the request does not specify one.
+    fn make_ctx_for_instance(
+        &self,
+        instance_name: &str,
+        digest_function: i32,
+    ) -> Result<Arc<OriginContext>, Error> {
+        // Zero means the client did not specify a digest function.
+        let requested = if digest_function == 0 {
+            None
+        } else {
+            Some(DigestHasherFunc::try_from(digest_function)?)
+        };
+        // Unknown instances are reported by the request handlers themselves.
+        let instance_digest_function = self
+            .instance_infos
+            .get(instance_name)
+            .and_then(|instance_info| instance_info.digest_function);
+        make_ctx_for_hash_func(resolve_instance_digest_function(
+            requested,
+            instance_digest_function,
+        )?)
     }
 
     async fn inner_find_missing_blobs(
@@ -70,20 +413,38 @@ impl CasServer {
         request: FindMissingBlobsRequest,
     ) -> Result<Response<FindMissingBlobsResponse>, Error> {
         let instance_name = &request.instance_name;
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        if instance_info.max_digests_per_find_missing != 0
+            && request.blob_digests.len() > instance_info.max_digests_per_find_missing
+        {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "FindMissingBlobs request with {} digests is larger than the max_digests_per_find_missing of {}",
+                request.blob_digests.len(),
+                instance_info.max_digests_per_find_missing
+            ));
+        }
 
         let mut requested_blobs = Vec::with_capacity(request.blob_digests.len());
         for digest in &request.blob_digests {
-            requested_blobs.push(DigestInfo::try_from(digest.clone())?.into());
+            requested_blobs.push(digest_info_for_request(digest.clone())?.into());
+        }
+        // Large requests are checked in chunks, so stores don't have to
+        // track the existence checks of every digest at once.
+        let mut sizes = vec![None; requested_blobs.len()];
+        for (blobs, sizes) in requested_blobs
+            .chunks(FIND_MISSING_BLOBS_CHUNK_SIZE)
+            .zip(sizes.chunks_mut(FIND_MISSING_BLOBS_CHUNK_SIZE))
+        {
+            instance_info
+                .store
+                .has_with_results(blobs, sizes)
+                .await
+                .err_tip(|| "In find_missing_blobs")?;
         }
-        let sizes = store
-            .has_many(&requested_blobs)
-            .await
-            .err_tip(|| "In find_missing_blobs")?;
         let missing_blob_digests = sizes
             .into_iter()
             .zip(request.blob_digests)
@@ -99,13 +460,27 @@ impl CasServer {
         &self,
         request: BatchUpdateBlobsRequest,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Error> {
+        // Held until all blobs are written, so shutdown waits for them.
+        let _inflight_write = self
+            .inflight_writes
+            .try_start()
+            .err_tip(|| "In CasServer::batch_update_blobs")?;
+
         let instance_name = &request.instance_name;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info
+            .check_batch_limits(
+                request
+                    .requests
+                    .iter()
+                    .filter_map(|request| request.digest.as_ref()),
+            )
+            .err_tip(|| "In CasServer::batch_update_blobs")?;
+        let store = instance_info.store.clone();
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
@@ -124,7 +499,7 @@ impl CasServer {
                     .clone()
                     .err_tip(|| "Digest not found in request")?;
                 let request_data = request.data;
-                let digest_info = DigestInfo::try_from(digest.clone())?;
+                let digest_info = digest_info_for_request(digest.clone())?;
                 let size_bytes = usize::try_from(digest_info.size_bytes())
                     .err_tip(|| "Digest size_bytes was not convertible to usize")?;
                 error_if!(
@@ -133,10 +508,16 @@ impl CasServer {
                     size_bytes,
                     request_data.len()
                 );
-                let result = store_ref
-                    .update_oneshot(digest_info, request_data)
-                    .await
-                    .err_tip(|| "Error writing to store");
+                let result = match instance_info
+                    .batch_update_temp_path_for(digest_info.size_bytes())
+                {
+                    Some(temp_path) => {
+                        update_from_spilled_file(store_ref, temp_path, digest_info, request_data)
+                            .await
+                    }
+                    None => store_ref.update_oneshot(digest_info, request_data).await,
+                }
+                .err_tip(|| "Error writing to store");
                 Ok::<_, Error>(batch_update_blobs_response::Response {
                     digest: Some(digest),
                     status: Some(result.map_or_else(Into::into, |()| GrpcStatus::default())),
@@ -156,11 +537,14 @@ impl CasServer {
     ) -> Result<Response<BatchReadBlobsResponse>, Error> {
         let instance_name = &request.instance_name;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        instance_info
+            .check_batch_limits(&request.digests)
+            .err_tip(|| "In CasServer::batch_read_blobs")?;
+        let store = instance_info.store.clone();
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
@@ -169,33 +553,56 @@ impl CasServer {
             return grpc_store.batch_read_blobs(Request::new(request)).await;
         }
 
+        // Identity is always acceptable, so zstd is only used if the client
+        // explicitly asked for it.
+        let use_zstd = request
+            .acceptable_compressors
+            .contains(&compressor::Value::Zstd.into());
         let store_ref = &store;
         let read_futures: FuturesUnordered<_> = request
             .digests
             .into_iter()
             .map(|digest| async move {
-                let digest_copy = DigestInfo::try_from(digest.clone())?;
-                // TODO(allada) There is a security risk here of someone taking all the memory on the instance.
+                let digest_copy = digest_info_for_request(digest.clone())?;
+                // Note: If limits are configured, this read is bounded by `check_batch_limits` above.
                 let result = store_ref
                     .get_part_unchunked(digest_copy, 0, None)
                     .await
                     .err_tip(|| "Error reading from store");
                 let (status, data) = result.map_or_else(
-                    |mut e| {
-                        if e.code == Code::NotFound {
-                            // Trim the error code. Not Found is quite common and we don't want to send a large
-                            // error (debug) message for something that is common. We resize to just the last
-                            // message as it will be the most relevant.
-                            e.messages.resize_with(1, String::new);
+                    |e| {
+                        // Errors with a reason, like NotFound, only send the
+                        // client part of the message, so log the full error.
+                        if e.reason.is_some() {
+                            event!(Level::DEBUG, ?e, "Failed to read blob in batch_read_blobs");
                         }
                         (e.into(), Bytes::new())
                     },
                     |v| (GrpcStatus::default(), v),
                 );
+                let (response_compressor, data) = if use_zstd && !data.is_empty() {
+                    // Compressing a blob up to the batch size limit can take
+                    // a while, so it is kept off of the async workers.
+                    let compressed = spawn_blocking!("batch_read_blobs_compress", move || {
+                        zstd::bulk::compress(&data, 0)
+                    })
+                    .await
+                    .map_err(|e| {
+                        make_err!(
+                            Code::Internal,
+                            "Failed to compress blob due to spawn failing {e:?}"
+                        )
+                    })?
+                    .map_err(|e| make_err!(Code::Internal, "Failed to compress blob: {e:?}"))
+                    .err_tip(|| "In CasServer::batch_read_blobs")?;
+                    (compressor::Value::Zstd, Bytes::from(compressed))
+                } else {
+                    (compressor::Value::Identity, data)
+                };
                 Ok::<_, Error>(batch_read_blobs_response::Response {
                     status: Some(status),
                     digest: Some(digest),
-                    compressor: compressor::Value::Identity.into(),
+                    compressor: response_compressor.into(),
                     data,
                 })
             })
@@ -213,11 +620,11 @@ impl CasServer {
     ) -> Result<impl Stream<Item = Result<GetTreeResponse, Status>> + Send + 'static, Error> {
         let instance_name = &request.instance_name;
 
-        let store = self
-            .stores
+        let instance_info = self
+            .instance_infos
             .get(instance_name)
-            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?
-            .clone();
+            .err_tip(|| format!("'instance_name' not configured for '{instance_name}'"))?;
+        let store = instance_info.store.clone();
 
         // If we are a GrpcStore we shortcut here, as this is a special store.
         // Note: We don't know the digests here, so we try perform a very shallow
@@ -235,61 +642,83 @@ impl CasServer {
             .try_into()
             .err_tip(|| "In GetTreeRequest::root_digest")?;
 
-        let mut deque: VecDeque<DigestInfo> = VecDeque::new();
-        let mut directories: Vec<Directory> = Vec::new();
-        // `page_token` will return the `{hash_str}-{size_bytes}` of the current request's first directory digest.
-        let page_token_digest = if request.page_token.is_empty() {
-            root_digest
+        // An empty `page_token` starts a new traversal at the root, otherwise
+        // the token holds the position in the BFS order to resume from.
+        let cursor = if request.page_token.is_empty() {
+            PageCursor::default()
         } else {
-            let mut page_token_parts = request.page_token.split('-');
-            DigestInfo::try_new(
-                page_token_parts
-                    .next()
-                    .err_tip(|| "Failed to parse `hash_str` in `page_token`")?,
-                page_token_parts
-                    .next()
-                    .err_tip(|| "Failed to parse `size_bytes` in `page_token`")?
-                    .parse::<i64>()
-                    .err_tip(|| "Failed to parse `size_bytes` as i64")?,
-            )
-            .err_tip(|| "Failed to parse `page_token` as `Digest` in `GetTreeRequest`")?
+            decode_page_token(&request.page_token, &root_digest)
+                .err_tip(|| "Failed to parse `page_token` in `GetTreeRequest`")?
         };
-        let page_size = request.page_size;
+        let mut directories: Vec<Directory> = Vec::new();
         // If `page_size` is 0, paging is not necessary.
-        let mut page_token_matched = page_size == 0;
-        deque.push_back(root_digest);
+        let page_size = usize::try_from(request.page_size).unwrap_or(0);
 
-        while !deque.is_empty() {
-            let digest: DigestInfo = deque.pop_front().err_tip(|| "In VecDeque::pop_front")?;
-            let directory = get_and_decode_digest::<Directory>(&store, digest.into())
-                .await
-                .err_tip(|| "Converting digest to Directory")?;
-            if digest == page_token_digest {
-                page_token_matched = true;
-            }
-            for directory in &directory.directories {
-                let digest: DigestInfo = directory
-                    .digest
-                    .clone()
-                    .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
-                    .try_into()
-                    .err_tip(|| "In Directory::file::digest")?;
-                deque.push_back(digest);
-            }
-            if page_token_matched {
-                directories.push(directory);
-                if directories.len() as i32 == page_size {
-                    break;
+        // The tree is walked one level at a time from the root. Levels above
+        // the cursor are only walked to find the directories of the next
+        // level, and so that the depth and number of directories the limits
+        // below apply to are always counted by the server for the whole
+        // tree, never taken from the client.
+        let mut level_digests = vec![root_digest];
+        let mut level: u32 = 0;
+        let mut node_count = 1;
+        let mut next_cursor = None;
+        'levels: while !level_digests.is_empty() {
+            let mut next_level_digests = Vec::new();
+            for (index, digest) in level_digests.into_iter().enumerate() {
+                let position = PageCursor {
+                    level,
+                    index: index as u64,
+                };
+                let in_page = position >= cursor;
+                if in_page && page_size != 0 && directories.len() == page_size {
+                    next_cursor = Some(position);
+                    break 'levels;
+                }
+                let directory = get_and_decode_digest::<Directory>(&store, digest.into())
+                    .await
+                    .err_tip(|| "Converting digest to Directory")?;
+                if !directory.directories.is_empty()
+                    && instance_info.max_tree_depth != 0
+                    && level as usize >= instance_info.max_tree_depth
+                {
+                    return Err(make_err!(
+                        Code::ResourceExhausted,
+                        "Tree of {root_digest} is deeper than the max_tree_depth of {}",
+                        instance_info.max_tree_depth
+                    ));
+                }
+                node_count += directory.directories.len();
+                if instance_info.max_tree_nodes != 0 && node_count > instance_info.max_tree_nodes {
+                    return Err(make_err!(
+                        Code::ResourceExhausted,
+                        "Tree of {root_digest} has more than the max_tree_nodes of {} directories",
+                        instance_info.max_tree_nodes
+                    ));
+                }
+                for directory in &directory.directories {
+                    let digest: DigestInfo = directory
+                        .digest
+                        .clone()
+                        .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+                        .try_into()
+                        .err_tip(|| "In Directory::file::digest")?;
+                    next_level_digests.push(digest);
+                }
+                if in_page {
+                    directories.push(directory);
                 }
             }
+            level_digests = next_level_digests;
+            level = level
+                .checked_add(1)
+                .err_tip(|| "Tree is too deep for GetTree")?;
         }
-        // `next_page_token` will return the `{hash_str}:{size_bytes}` of the next request's first directory digest.
-        // It will be an empty string when it reached the end of the directory tree.
-        let next_page_token: String = if let Some(value) = deque.front() {
-            format!("{value}")
-        } else {
-            String::new()
-        };
+        // `next_page_token` will be an empty string when it reached the end of
+        // the directory tree.
+        let next_page_token = next_cursor.map_or_else(String::new, |cursor| {
+            encode_page_token(&root_digest, cursor)
+        });
 
         Ok(futures::stream::once(async {
             Ok(GetTreeResponse {
@@ -311,15 +740,22 @@ impl ContentAddressableStorage for CasServer {
         ret(level = Level::INFO),
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            instance_name = %grpc_request.get_ref().instance_name,
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+            request = ?grpc_request.get_ref(),
+        )
     )]
     async fn find_missing_blobs(
         &self,
         grpc_request: Request<FindMissingBlobsRequest>,
     ) -> Result<Response<FindMissingBlobsResponse>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
-        let resp = make_ctx_for_hash_func(request.digest_function)
+        let resp = self
+            .make_ctx_for_instance(&request.instance_name, request.digest_function)
             .err_tip(|| "In CasServer::find_missing_blobs")?
             .wrap_async(
                 error_span!("cas_server_find_missing_blobs"),
@@ -338,15 +774,22 @@ impl ContentAddressableStorage for CasServer {
         ret(level = Level::INFO),
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            instance_name = %grpc_request.get_ref().instance_name,
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+            request = ?grpc_request.get_ref(),
+        )
     )]
     async fn batch_update_blobs(
         &self,
         grpc_request: Request<BatchUpdateBlobsRequest>,
     ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
-        let resp = make_ctx_for_hash_func(request.digest_function)
+        let resp = self
+            .make_ctx_for_instance(&request.instance_name, request.digest_function)
             .err_tip(|| "In CasServer::batch_update_blobs")?
             .wrap_async(
                 error_span!("cas_server_batch_update_blobs"),
@@ -365,15 +808,22 @@ impl ContentAddressableStorage for CasServer {
         ret(level = Level::INFO),
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            instance_name = %grpc_request.get_ref().instance_name,
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+            request = ?grpc_request.get_ref(),
+        )
     )]
     async fn batch_read_blobs(
         &self,
         grpc_request: Request<BatchReadBlobsRequest>,
     ) -> Result<Response<BatchReadBlobsResponse>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
-        let resp = make_ctx_for_hash_func(request.digest_function)
+        let resp = self
+            .make_ctx_for_instance(&request.instance_name, request.digest_function)
             .err_tip(|| "In CasServer::batch_read_blobs")?
             .wrap_async(
                 error_span!("cas_server_batch_read_blobs"),
@@ -391,15 +841,23 @@ impl ContentAddressableStorage for CasServer {
         err,
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            instance_name = %grpc_request.get_ref().instance_name,
+            trace_id = trace_id_from_metadata(grpc_request.metadata()),
+            digest = ?grpc_request.get_ref().root_digest,
+            request = ?grpc_request.get_ref(),
+        )
     )]
     async fn get_tree(
         &self,
         grpc_request: Request<GetTreeRequest>,
     ) -> Result<Response<Self::GetTreeStream>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
         let ctx = OriginEventContext::new(|| &request).await;
-        let resp = make_ctx_for_hash_func(request.digest_function)
+        let resp = self
+            .make_ctx_for_instance(&request.instance_name, request.digest_function)
             .err_tip(|| "In CasServer::get_tree")?
             .wrap_async(
                 error_span!("cas_server_get_tree"),
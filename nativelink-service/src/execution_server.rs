@@ -36,11 +36,15 @@ use nativelink_util::action_messages::{
     ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId, DEFAULT_EXECUTION_PRIORITY,
 };
 use nativelink_util::common::DigestInfo;
-use nativelink_util::digest_hasher::{make_ctx_for_hash_func, DigestHasherFunc};
+use nativelink_util::digest_hasher::{
+    make_ctx_for_hash_func, resolve_instance_digest_function, DigestHasherFunc,
+};
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
 use nativelink_util::operation_state_manager::{
     ActionStateResult, ClientStateManager, OperationFilter,
 };
 use nativelink_util::origin_event::OriginEventContext;
+use nativelink_util::platform_properties::merge_proto_platforms;
 use nativelink_util::store_trait::Store;
 use tonic::{Request, Response, Status};
 use tracing::{error_span, event, instrument, Level};
@@ -110,23 +114,10 @@ impl InstanceInfo {
             Duration::new(v.seconds as u64, v.nanos as u32)
         });
 
-        let mut platform_properties = HashMap::new();
-        if let Some(platform) = action.platform {
-            for property in platform.properties {
-                platform_properties.insert(property.name, property.value);
-            }
-        }
-
-        // Goma puts the properties in the Command.
-        if platform_properties.is_empty() {
-            let command =
-                get_and_decode_digest::<Command>(&self.cas_store, command_digest.into()).await?;
-            if let Some(platform) = command.platform {
-                for property in platform.properties {
-                    platform_properties.insert(property.name, property.value);
-                }
-            }
-        }
+        // The platform may be set on the Action, the Command (eg: Goma) or both.
+        let command =
+            get_and_decode_digest::<Command>(&self.cas_store, command_digest.into()).await?;
+        let platform_properties = merge_proto_platforms(action.platform, command.platform);
 
         let action_key = ActionUniqueKey {
             instance_name,
@@ -148,12 +139,22 @@ impl InstanceInfo {
             load_timestamp: UNIX_EPOCH,
             insert_timestamp: SystemTime::now(),
             unique_qualifier,
+            do_not_cache: action.do_not_cache,
         })
     }
 }
 
 pub struct ExecutionServer {
     instance_infos: HashMap<InstanceName, InstanceInfo>,
+    /// Client facing instance names and the instance they are served as.
+    instance_name_aliases: InstanceNameAliases,
+    /// The digest function each instance is restricted to, taken from the
+    /// CAS config of the same instance.
+    digest_functions: HashMap<InstanceName, DigestHasherFunc>,
+    /// Limits on the size of a single gRPC message, zero uses the tonic
+    /// default.
+    max_decoding_message_size: usize,
+    max_encoding_message_size: usize,
 }
 
 type ExecuteStream = Pin<Box<dyn Stream<Item = Result<Operation, Status>> + Send + 'static>>;
@@ -189,11 +190,79 @@ impl ExecutionServer {
                 },
             );
         }
-        Ok(Self { instance_infos })
+        Ok(Self {
+            instance_infos,
+            instance_name_aliases: InstanceNameAliases::default(),
+            digest_functions: HashMap::new(),
+            max_decoding_message_size: 0,
+            max_encoding_message_size: 0,
+        })
+    }
+
+    /// Restricts instances to a digest function, so actions are executed
+    /// with the same digest function their CAS is served with. Requests that
+    /// do not specify a digest function use the instance's function and
+    /// requests that specify a different one are rejected.
+    #[must_use]
+    pub fn with_instance_digest_functions(
+        mut self,
+        digest_functions: HashMap<InstanceName, DigestHasherFunc>,
+    ) -> Self {
+        self.digest_functions = digest_functions;
+        self
+    }
+
+    /// Serves requests for each alias in `instance_name_aliases` as if they
+    /// were for the instance it maps to.
+    #[must_use]
+    pub fn with_instance_name_aliases(
+        mut self,
+        instance_name_aliases: InstanceNameAliases,
+    ) -> Self {
+        self.instance_name_aliases = instance_name_aliases;
+        self
+    }
+
+    /// Sets the largest gRPC message the service will decode and encode.
+    /// Zero keeps the tonic default.
+    #[must_use]
+    pub fn with_max_message_sizes(
+        mut self,
+        max_decoding_message_size: usize,
+        max_encoding_message_size: usize,
+    ) -> Self {
+        self.max_decoding_message_size = max_decoding_message_size;
+        self.max_encoding_message_size = max_encoding_message_size;
+        self
+    }
+
+    fn digest_function_for_instance(
+        &self,
+        instance_name: &str,
+        digest_function: i32,
+    ) -> Result<DigestHasherFunc, Error> {
+        let requested = if digest_function == 0 {
+            None
+        } else {
+            Some(DigestHasherFunc::try_from(digest_function)?)
+        };
+        resolve_instance_digest_function(
+            requested,
+            self.digest_functions.get(instance_name).copied(),
+        )
     }
 
     pub fn into_service(self) -> Server<ExecutionServer> {
-        Server::new(self)
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
+        let mut service = Server::new(self);
+        if max_decoding_message_size != 0 {
+            service = service.max_decoding_message_size(max_decoding_message_size);
+        }
+        if max_encoding_message_size != 0 {
+            service = service.max_encoding_message_size(max_encoding_message_size);
+        }
+        service
     }
 
     fn to_execute_stream(
@@ -290,8 +359,10 @@ impl ExecutionServer {
         &self,
         request: WaitExecutionRequest,
     ) -> Result<impl Stream<Item = Result<Operation, Status>> + Send + 'static, Status> {
-        let nl_operation_id = NativelinkOperationId::from_name(&request.name)
+        let mut nl_operation_id = NativelinkOperationId::from_name(&request.name)
             .err_tip(|| "Failed to parse operation_id in ExecutionServer::wait_execution")?;
+        self.instance_name_aliases
+            .resolve_in_place(&mut nl_operation_id.instance_name);
         let Some(instance_info) = self.instance_infos.get(&nl_operation_id.instance_name) else {
             return Err(Status::not_found(format!(
                 "No scheduler with the instance name {}",
@@ -331,7 +402,14 @@ impl Execution for ExecutionServer {
         &self,
         grpc_request: Request<ExecuteRequest>,
     ) -> Result<Response<ExecuteStream>, Status> {
-        let request = grpc_request.into_inner();
+        let mut request = grpc_request.into_inner();
+        self.instance_name_aliases
+            .resolve_in_place(&mut request.instance_name);
+        request.digest_function = self
+            .digest_function_for_instance(&request.instance_name, request.digest_function)
+            .err_tip(|| "In ExecutionServer::execute")?
+            .proto_digest_func()
+            .into();
         let ctx = OriginEventContext::new(|| &request).await;
         let resp = make_ctx_for_hash_func(request.digest_function)
             .err_tip(|| "In ExecutionServer::execute")?
@@ -0,0 +1,163 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use futures::future::join_all;
+use nativelink_config::cas_server::ServicesConfig;
+use nativelink_error::{Error, ResultExt};
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    action_cache_server, capabilities_server, content_addressable_storage_server, execution_server,
+};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_server;
+use nativelink_proto::google::bytestream::byte_stream_server;
+use nativelink_proto::google::devtools::build::v1::publish_build_event_server;
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::health_utils::HealthStatus;
+use nativelink_util::store_trait::{Store, StoreLike};
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{event, Level};
+
+/// Serves the standard gRPC health service (`grpc.health.v1.Health`). A
+/// service is reported as SERVING while every store it uses passes its
+/// health check and NOT_SERVING otherwise. The status of the whole server
+/// (the empty service name) is SERVING only if every service is.
+pub struct GrpcHealthServer {
+    health_reporter: HealthReporter,
+    services: Vec<(&'static str, Vec<Store>)>,
+}
+
+impl GrpcHealthServer {
+    /// Registers every service configured in `services` along with the
+    /// stores it uses. Also returns the `grpc.health.v1.Health` service that
+    /// serves the statuses set by `update_statuses`.
+    pub fn new(
+        services: &ServicesConfig,
+        store_manager: &StoreManager,
+    ) -> Result<(Self, HealthServer<impl Health>), Error> {
+        let get_store = |name: &str| {
+            store_manager
+                .get_store(name)
+                .err_tip(|| format!("'{name}' store not found for gRPC health service"))
+        };
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+        let mut grpc_health_server = Self {
+            health_reporter,
+            services: Vec::new(),
+        };
+        if let Some(cfg) = &services.ac {
+            grpc_health_server.add_service(
+                action_cache_server::SERVICE_NAME,
+                cfg.values()
+                    .map(|ac_cfg| get_store(&ac_cfg.ac_store))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if let Some(cfg) = &services.cas {
+            grpc_health_server.add_service(
+                content_addressable_storage_server::SERVICE_NAME,
+                cfg.values()
+                    .map(|cas_cfg| get_store(&cas_cfg.cas_store))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if let Some(cfg) = &services.execution {
+            grpc_health_server.add_service(
+                execution_server::SERVICE_NAME,
+                cfg.values()
+                    .map(|exec_cfg| get_store(&exec_cfg.cas_store))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if let Some(cfg) = &services.bytestream {
+            grpc_health_server.add_service(
+                byte_stream_server::SERVICE_NAME,
+                cfg.cas_stores
+                    .values()
+                    .map(|store_name| get_store(store_name))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if services.capabilities.is_some() {
+            grpc_health_server.add_service(capabilities_server::SERVICE_NAME, Vec::new());
+        }
+        if services.worker_api.is_some() {
+            grpc_health_server.add_service(worker_api_server::SERVICE_NAME, Vec::new());
+        }
+        if let Some(cfg) = &services.experimental_bep {
+            grpc_health_server.add_service(
+                publish_build_event_server::SERVICE_NAME,
+                vec![get_store(&cfg.store)?],
+            );
+        }
+        Ok((grpc_health_server, health_service))
+    }
+
+    /// Registers `service_name`, which is healthy while all of `stores` are.
+    pub fn add_service(&mut self, service_name: &'static str, stores: Vec<Store>) {
+        self.services.push((service_name, stores));
+    }
+
+    /// Runs the health checks of the stores of every service and updates
+    /// the reported statuses.
+    pub async fn update_statuses(&mut self) {
+        let mut all_serving = true;
+        for (service_name, stores) in &self.services {
+            let health_statuses = join_all(
+                stores
+                    .iter()
+                    .map(|store| store.check_health(Cow::Borrowed(*service_name))),
+            )
+            .await;
+            let serving_status = match health_statuses
+                .into_iter()
+                .find(|status| matches!(status, HealthStatus::Failed { .. }))
+            {
+                Some(failed_status) => {
+                    event!(
+                        Level::WARN,
+                        service_name,
+                        ?failed_status,
+                        "Store health check failed, reporting service as not serving"
+                    );
+                    all_serving = false;
+                    ServingStatus::NotServing
+                }
+                None => ServingStatus::Serving,
+            };
+            self.health_reporter
+                .set_service_status(service_name, serving_status)
+                .await;
+        }
+        let server_status = if all_serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        self.health_reporter
+            .set_service_status("", server_status)
+            .await;
+    }
+
+    /// Updates the reported statuses every `check_interval`. Never returns.
+    pub async fn run(mut self, check_interval: Duration) {
+        loop {
+            self.update_statuses().await;
+            tokio::time::sleep(check_interval).await;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::cas_server::ServicesConfig;
+use nativelink_error::{make_err, Code, Error};
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    action_cache_server, capabilities_server, content_addressable_storage_server, execution_server,
+};
+use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_server;
+use nativelink_proto::google::bytestream::byte_stream_server;
+use nativelink_proto::google::devtools::build::v1::publish_build_event_server;
+use tonic_reflection::pb::v1::server_reflection_server::{
+    ServerReflection as ServerReflectionV1, ServerReflectionServer as ServerReflectionServerV1,
+};
+use tonic_reflection::pb::v1alpha::server_reflection_server::{
+    ServerReflection as ServerReflectionV1Alpha,
+    ServerReflectionServer as ServerReflectionServerV1Alpha,
+};
+use tonic_reflection::server::Builder;
+
+/// Serves the gRPC server reflection service in both its `v1` and `v1alpha`
+/// versions. Only the gRPC services configured on the server are listed.
+pub struct GrpcReflectionServer {
+    service_names: Vec<&'static str>,
+}
+
+impl GrpcReflectionServer {
+    pub fn new(services: &ServicesConfig) -> Self {
+        let mut service_names = vec![
+            tonic_reflection::pb::v1::server_reflection_server::SERVICE_NAME,
+            tonic_reflection::pb::v1alpha::server_reflection_server::SERVICE_NAME,
+        ];
+        let configured_services = [
+            (services.ac.is_some(), action_cache_server::SERVICE_NAME),
+            (
+                services.cas.is_some(),
+                content_addressable_storage_server::SERVICE_NAME,
+            ),
+            (services.execution.is_some(), execution_server::SERVICE_NAME),
+            (
+                services.bytestream.is_some(),
+                byte_stream_server::SERVICE_NAME,
+            ),
+            (
+                services.capabilities.is_some(),
+                capabilities_server::SERVICE_NAME,
+            ),
+            (
+                services.worker_api.is_some(),
+                worker_api_server::SERVICE_NAME,
+            ),
+            (
+                services.experimental_bep.is_some(),
+                publish_build_event_server::SERVICE_NAME,
+            ),
+            (
+                services.experimental_grpc_health.is_some(),
+                tonic_health::pb::health_server::SERVICE_NAME,
+            ),
+        ];
+        service_names.extend(
+            configured_services
+                .into_iter()
+                .filter_map(|(configured, service_name)| configured.then_some(service_name)),
+        );
+        Self { service_names }
+    }
+
+    fn builder(&self) -> Builder<'static> {
+        self.service_names.iter().fold(
+            Builder::configure()
+                .register_encoded_file_descriptor_set(nativelink_proto::FILE_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET),
+            |builder, service_name| builder.with_service_name(*service_name),
+        )
+    }
+
+    pub fn v1_service(&self) -> Result<ServerReflectionServerV1<impl ServerReflectionV1>, Error> {
+        self.builder()
+            .build_v1()
+            .map_err(|e| make_err!(Code::Internal, "Failed to build reflection service : {e:?}"))
+    }
+
+    pub fn v1alpha_service(
+        &self,
+    ) -> Result<ServerReflectionServerV1Alpha<impl ServerReflectionV1Alpha>, Error> {
+        self.builder()
+            .build_v1alpha()
+            .map_err(|e| make_err!(Code::Internal, "Failed to build reflection service : {e:?}"))
+    }
+}
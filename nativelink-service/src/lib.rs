@@ -18,5 +18,7 @@ pub mod bytestream_server;
 pub mod capabilities_server;
 pub mod cas_server;
 pub mod execution_server;
+pub mod grpc_health_server;
+pub mod grpc_reflection_server;
 pub mod health_server;
 pub mod worker_api_server;
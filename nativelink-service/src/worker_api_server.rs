@@ -21,21 +21,24 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use futures::stream::unfold;
 use futures::Stream;
 use nativelink_config::cas_server::WorkerApiConfig;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::worker_api_server::{
     WorkerApi, WorkerApiServer as Server,
 };
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    execute_result, ExecuteResult, GoingAwayRequest, KeepAliveRequest, SupportedProperties, UpdateForWorker,
+    execute_result, update_for_worker, ExecuteResult, GoingAwayRequest, KeepAliveRequest, SupportedProperties, UpdateForWorker,
 };
 use nativelink_scheduler::worker::Worker;
 use nativelink_scheduler::worker_scheduler::WorkerScheduler;
 use nativelink_util::background_spawn;
-use nativelink_util::action_messages::{OperationId, WorkerId};
+use nativelink_util::action_messages::{
+    ActionResult, ActionStage, ExecutionMetadata, OperationId, WorkerId,
+};
 use nativelink_util::operation_state_manager::UpdateOperationType;
 use nativelink_util::platform_properties::PlatformProperties;
+use prost::Message;
 use tokio::sync::mpsc;
-use tokio::time::interval;
+use tokio::time::{interval, timeout};
 use tonic::{Request, Response, Status};
 use tracing::{event, instrument, Level};
 use uuid::Uuid;
@@ -48,6 +51,14 @@ pub type NowFn = Box<dyn Fn() -> Result<Duration, Error> + Send + Sync>;
 pub struct WorkerApiServer {
     scheduler: Arc<dyn WorkerScheduler>,
     now_fn: NowFn,
+    /// A keep-alive is sent to workers that were sent nothing for this long.
+    keep_alive_interval: Option<Duration>,
+    /// Results whose encoded `ExecuteResponse` is larger than this are
+    /// rejected. Zero means no limit.
+    max_action_result_size: usize,
+    /// If workers advertising properties the scheduler doesn't know are
+    /// accepted, without those properties.
+    ignore_unknown_worker_properties: bool,
 }
 
 impl WorkerApiServer {
@@ -109,7 +120,15 @@ impl WorkerApiServer {
                 )
             })?
             .clone();
-        Ok(Self { scheduler, now_fn })
+        let keep_alive_interval = (config.keep_alive_interval != 0)
+            .then(|| Duration::from_secs(config.keep_alive_interval as u64));
+        Ok(Self {
+            scheduler,
+            now_fn,
+            keep_alive_interval,
+            max_action_result_size: config.max_action_result_size,
+            ignore_unknown_worker_properties: config.ignore_unknown_worker_properties,
+        })
     }
 
     pub fn into_service(self) -> Server<WorkerApiServer> {
@@ -124,16 +143,36 @@ impl WorkerApiServer {
 
         // First convert our proto platform properties into one our scheduler understands.
         let platform_properties = {
+            let platform_property_manager = self.scheduler.get_platform_property_manager();
+            let known_properties = platform_property_manager.get_known_properties();
+            let (properties, unknown_properties): (Vec<_>, Vec<_>) = supported_properties
+                .properties
+                .into_iter()
+                .partition(|property| known_properties.contains_key(&property.name));
+            if !unknown_properties.is_empty() {
+                let unknown_property_names: Vec<_> = unknown_properties
+                    .iter()
+                    .map(|property| property.name.as_str())
+                    .collect();
+                if !self.ignore_unknown_worker_properties {
+                    return Err(make_input_err!(
+                        "Worker advertised platform properties the scheduler is not configured to support: {unknown_property_names:?}"
+                    ));
+                }
+                event!(
+                    Level::WARN,
+                    ?unknown_property_names,
+                    "Ignoring platform properties of connecting worker the scheduler is not configured to support"
+                );
+            }
             let mut platform_properties = PlatformProperties::default();
-            for property in supported_properties.properties {
-                let platform_property_value = self
-                    .scheduler
-                    .get_platform_property_manager()
+            for property in properties {
+                let platform_property_value = platform_property_manager
                     .make_prop_value(&property.name, &property.value)
                     .err_tip(|| "Bad Property during connect_worker()")?;
                 platform_properties
                     .properties
-                    .insert(property.name.clone(), platform_property_value);
+                    .insert(property.name, platform_property_value);
             }
             platform_properties
         };
@@ -154,11 +193,22 @@ impl WorkerApiServer {
             worker_id
         };
 
+        let keep_alive_interval = self.keep_alive_interval;
         Ok(Response::new(Box::pin(unfold(
             (rx, worker_id),
             move |state| async move {
                 let (mut rx, worker_id) = state;
-                if let Some(update_for_worker) = rx.recv().await {
+                let maybe_update_for_worker = match keep_alive_interval {
+                    Some(keep_alive_interval) => timeout(keep_alive_interval, rx.recv())
+                        .await
+                        // Nothing was sent for a while, let the worker know
+                        // the connection is still alive.
+                        .unwrap_or(Some(UpdateForWorker {
+                            update: Some(update_for_worker::Update::KeepAlive(())),
+                        })),
+                    None => rx.recv().await,
+                };
+                if let Some(update_for_worker) = maybe_update_for_worker {
                     return Some((Ok(update_for_worker), (rx, worker_id)));
                 }
                 event!(
@@ -181,6 +231,10 @@ impl WorkerApiServer {
             .worker_keep_alive_received(&worker_id, (self.now_fn)()?.as_secs())
             .await
             .err_tip(|| "Could not process keep_alive from worker in inner_keep_alive()")?;
+        self.scheduler
+            .set_worker_intake_paused(&worker_id, keep_alive_request.paused)
+            .await
+            .err_tip(|| "Could not set intake pause of worker in inner_keep_alive()")?;
         Ok(Response::new(()))
     }
 
@@ -208,6 +262,40 @@ impl WorkerApiServer {
             .err_tip(|| "Expected result to exist in ExecuteResult")?
         {
             execute_result::Result::ExecuteResponse(finished_result) => {
+                let result_size = finished_result.encoded_len();
+                if self.max_action_result_size != 0 && result_size > self.max_action_result_size {
+                    event!(
+                        Level::WARN,
+                        ?operation_id,
+                        ?worker_id,
+                        result_size,
+                        max_action_result_size = self.max_action_result_size,
+                        "Rejecting action result larger than max_action_result_size",
+                    );
+                    // Complete the action with an error rather than failing it, so
+                    // it doesn't get retried only to produce the same result.
+                    let action_stage = ActionStage::Completed(ActionResult {
+                        execution_metadata: ExecutionMetadata {
+                            worker: worker_id.to_string(),
+                            ..ExecutionMetadata::default()
+                        },
+                        error: Some(make_err!(
+                            Code::ResourceExhausted,
+                            "Action result is {result_size} bytes, which exceeds the max_action_result_size of {} bytes",
+                            self.max_action_result_size
+                        )),
+                        ..ActionResult::default()
+                    });
+                    self.scheduler
+                        .update_action(
+                            &worker_id,
+                            &operation_id,
+                            UpdateOperationType::UpdateWithActionStage(action_stage),
+                        )
+                        .await
+                        .err_tip(|| format!("Failed to operation {operation_id:?}"))?;
+                    return Ok(Response::new(()));
+                }
                 let action_stage = finished_result
                     .try_into()
                     .err_tip(|| "Failed to convert ExecuteResponse into an ActionStage")?;
@@ -300,7 +388,10 @@ impl WorkerApi for WorkerApiServer {
         ret(level = Level::INFO),
         level = Level::ERROR,
         skip_all,
-        fields(request = ?grpc_request.get_ref())
+        fields(
+            request = ?grpc_request.get_ref(),
+            trace_id = %grpc_request.get_ref().operation_id,
+        )
     )]
     async fn execution_response(
         &self,
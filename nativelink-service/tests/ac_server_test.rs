@@ -22,12 +22,14 @@ use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_server::ActionCache;
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    digest_function, ActionResult, Digest, GetActionResultRequest, UpdateActionResultRequest,
+    digest_function, ActionResult, Digest, GetActionResultRequest, OutputFile,
+    UpdateActionResultRequest,
 };
 use nativelink_service::ac_server::AcServer;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::common::DigestInfo;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
 use nativelink_util::store_trait::StoreLike;
 use pretty_assertions::assert_eq;
 use prost::Message;
@@ -75,11 +77,21 @@ async fn make_store_manager() -> Result<Arc<StoreManager>, Error> {
 }
 
 fn make_ac_server(store_manager: &StoreManager) -> Result<AcServer, Error> {
+    make_ac_server_with_verify_cas_store(store_manager, None)
+}
+
+fn make_ac_server_with_verify_cas_store(
+    store_manager: &StoreManager,
+    verify_cas_store: Option<&str>,
+) -> Result<AcServer, Error> {
     AcServer::new(
         &hashmap! {
             "foo_instance_name".to_string() => nativelink_config::cas_server::AcStoreConfig{
                 ac_store: "main_ac".to_string(),
                 read_only: false,
+                verify_cas_store: verify_cas_store.map(str::to_string),
+                inline_output_cas_store: None,
+                max_inline_output_bytes: 0,
             }
         },
         store_manager,
@@ -219,3 +231,180 @@ async fn one_item_update_test() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(decoded_action_result, action_result);
     Ok(())
 }
+
+#[nativelink_test]
+async fn update_with_missing_cas_outputs_is_rejected_test() -> Result<(), Box<dyn std::error::Error>>
+{
+    const STDOUT_HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+    const OUTPUT_FILE_HASH: &str =
+        "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+    const STDOUT_DATA: &str = "stdout data";
+    const OUTPUT_FILE_DATA: &str = "output file data";
+
+    let store_manager = make_store_manager().await?;
+    let ac_server = make_ac_server_with_verify_cas_store(&store_manager, Some("main_cas"))?;
+    let cas_store = store_manager.get_store("main_cas").unwrap();
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+
+    let stdout_digest = DigestInfo::try_new(STDOUT_HASH, STDOUT_DATA.len())?;
+    let output_file_digest = DigestInfo::try_new(OUTPUT_FILE_HASH, OUTPUT_FILE_DATA.len())?;
+    cas_store
+        .update_oneshot(stdout_digest, STDOUT_DATA.into())
+        .await?;
+
+    let action_result = ActionResult {
+        exit_code: 0,
+        output_files: vec![OutputFile {
+            path: "some/output_file".to_string(),
+            digest: Some(output_file_digest.into()),
+            ..Default::default()
+        }],
+        stdout_digest: Some(stdout_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: HASH1_SIZE,
+    };
+
+    {
+        // The output file is not in the CAS yet, so the write is rejected.
+        let err = update_action_result(&ac_server, action_digest.clone(), action_result.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition, "{err:?}");
+        assert_eq!(
+            ac_store
+                .has(DigestInfo::try_new(HASH1, HASH1_SIZE)?)
+                .await?,
+            None
+        );
+    }
+    {
+        // Once every output exists the write is accepted.
+        cas_store
+            .update_oneshot(output_file_digest, OUTPUT_FILE_DATA.into())
+            .await?;
+        let response =
+            update_action_result(&ac_server, action_digest, action_result.clone()).await?;
+        assert_eq!(response.into_inner(), action_result);
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn instance_name_alias_routes_to_aliased_instance() -> Result<(), Box<dyn std::error::Error>>
+{
+    let store_manager = make_store_manager().await?;
+    let ac_server = make_ac_server(&store_manager)?.with_instance_name_aliases(
+        InstanceNameAliases::new(hashmap! {
+            "alias".to_string() => INSTANCE_NAME.to_string(),
+        }),
+    );
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+
+    let action_result = ActionResult {
+        exit_code: 45,
+        ..Default::default()
+    };
+    let size_bytes = get_encoded_proto_size(&action_result)? as i64;
+
+    ac_server
+        .update_action_result(Request::new(UpdateActionResultRequest {
+            instance_name: "alias".to_string(),
+            action_digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes,
+            }),
+            action_result: Some(action_result.clone()),
+            results_cache_policy: None,
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?;
+
+    // The result was written to the store of the aliased instance.
+    let digest = DigestInfo::try_new(HASH1, size_bytes)?;
+    assert!(ac_store.has(digest).await?.is_some());
+
+    let raw_response = ac_server
+        .get_action_result(Request::new(GetActionResultRequest {
+            instance_name: "alias".to_string(),
+            action_digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes,
+            }),
+            inline_stdout: false,
+            inline_stderr: false,
+            inline_output_files: vec![],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?;
+    assert_eq!(raw_response.into_inner(), action_result);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_action_result_inlines_requested_output_test() -> Result<(), Box<dyn std::error::Error>>
+{
+    const MAX_INLINE_OUTPUT_BYTES: usize = 16;
+    const STDOUT: &str = "foo-stdout";
+    const STDERR: &str = "stderr-larger-than-the-limit";
+    const STDOUT_HASH: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+    const STDERR_HASH: &str = "0123456789abcdef000000000000000000030000000000000123456789abcdef";
+
+    let store_manager = make_store_manager().await?;
+    let ac_server = AcServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => nativelink_config::cas_server::AcStoreConfig{
+                ac_store: "main_ac".to_string(),
+                read_only: false,
+                verify_cas_store: None,
+                inline_output_cas_store: Some("main_cas".to_string()),
+                max_inline_output_bytes: MAX_INLINE_OUTPUT_BYTES,
+            }
+        },
+        &store_manager,
+    )?;
+    let ac_store = store_manager.get_store("main_ac").unwrap();
+    let cas_store = store_manager.get_store("main_cas").unwrap();
+
+    let stdout_digest = DigestInfo::try_new(STDOUT_HASH, STDOUT.len())?;
+    let stderr_digest = DigestInfo::try_new(STDERR_HASH, STDERR.len())?;
+    cas_store
+        .update_oneshot(stdout_digest, STDOUT.into())
+        .await?;
+    cas_store
+        .update_oneshot(stderr_digest, STDERR.into())
+        .await?;
+    let action_result = ActionResult {
+        stdout_digest: Some(stdout_digest.into()),
+        stderr_digest: Some(stderr_digest.into()),
+        ..Default::default()
+    };
+    insert_into_store(ac_store.as_pin(), HASH1, HASH1_SIZE, &action_result).await?;
+
+    let get_inlined = |inline: bool| {
+        ac_server.get_action_result(Request::new(GetActionResultRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            action_digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes: HASH1_SIZE,
+            }),
+            inline_stdout: inline,
+            inline_stderr: inline,
+            inline_output_files: vec![],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+    };
+
+    // Only the stdout is small enough to be inlined.
+    let inlined_result = get_inlined(true).await?.into_inner();
+    assert_eq!(inlined_result.stdout_raw, STDOUT.as_bytes());
+    assert!(inlined_result.stderr_raw.is_empty());
+    assert_eq!(inlined_result.stdout_digest, Some(stdout_digest.into()));
+    assert_eq!(inlined_result.stderr_digest, Some(stderr_digest.into()));
+
+    // Nothing is inlined unless the client asks for it.
+    assert_eq!(get_inlined(false).await?.into_inner(), action_result);
+    Ok(())
+}
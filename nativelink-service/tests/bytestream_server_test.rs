@@ -12,20 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Bytes;
+use futures::poll;
 use futures::task::Poll;
-use futures::{poll, Future};
-use http_body_util::BodyExt;
 use hyper::body::Frame;
-use hyper::Uri;
-use hyper_util::rt::TokioIo;
-use hyper_util::server::conn::auto;
-use hyper_util::service::TowerToHyperService;
 use maplit::hashmap;
 use nativelink_config::cas_server::ByteStreamConfig;
-use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_config::stores::{ConfigDigestHashFunction, MemorySpec, StoreSpec};
 use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::google::bytestream::byte_stream_client::ByteStreamClient;
@@ -37,21 +34,27 @@ use nativelink_service::bytestream_server::ByteStreamServer;
 use nativelink_store::default_store_factory::store_factory;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::channel_body_for_tests::ChannelBody;
-use nativelink_util::common::{encode_stream_proto, DigestInfo};
+use nativelink_util::common::{encode_stream_proto, ClientAddr, DigestInfo};
+use nativelink_util::inflight_writes::InflightWrites;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
+use nativelink_util::spawn;
 use nativelink_util::store_trait::StoreLike;
 use nativelink_util::task::JoinHandleDropGuard;
-use nativelink_util::{background_spawn, spawn};
 use pretty_assertions::assert_eq;
-use tokio::io::DuplexStream;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Notify;
 use tokio::task::yield_now;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
 use tonic::codec::{Codec, CompressionEncoding, ProstCodec};
-use tonic::transport::{Channel, Endpoint};
+use tonic::service::Routes;
 use tonic::{Request, Response, Streaming};
-use tower::service_fn;
+use utils::server_utils::server_and_client_stub;
+
+mod utils {
+    pub(crate) mod server_utils;
+}
 
 const INSTANCE_NAME: &str = "foo_instance_name";
 const HASH1: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
@@ -78,9 +81,12 @@ fn make_bytestream_server(
         cas_stores: hashmap! {
             "foo_instance_name".to_string() => "main_cas".to_string(),
         },
+        digest_functions: hashmap! {},
         persist_stream_on_disconnect_timeout: 0,
         max_bytes_per_stream: 1024,
         max_decoding_message_size: 0,
+        max_concurrent_writes_per_client: 0,
+        write_idle_timeout: 0,
     });
     ByteStreamServer::new(&config, store_manager)
 }
@@ -107,6 +113,20 @@ fn make_stream_and_writer_spawn(
     (tx, join_handle)
 }
 
+fn make_client_stream_and_writer_spawn(
+    bs_server: Arc<ByteStreamServer>,
+    client_addr: SocketAddr,
+) -> (mpsc::Sender<Frame<Bytes>>, JoinHandle) {
+    let (tx, stream) = make_stream(None);
+    let mut request = Request::new(stream);
+    request.extensions_mut().insert(ClientAddr(client_addr));
+    let join_handle = spawn!(
+        "bs_server_write",
+        async move { bs_server.write(request).await }
+    );
+    (tx, join_handle)
+}
+
 fn make_resource_name(data_len: impl std::fmt::Display) -> String {
     format!(
         "{}/uploads/{}/blobs/{}/{}",
@@ -117,72 +137,6 @@ fn make_resource_name(data_len: impl std::fmt::Display) -> String {
     )
 }
 
-async fn server_and_client_stub(
-    bs_server: ByteStreamServer,
-) -> (JoinHandleDropGuard<()>, ByteStreamClient<Channel>) {
-    #[derive(Clone)]
-    struct Executor;
-    impl<F> hyper::rt::Executor<F> for Executor
-    where
-        F: Future + Send + 'static,
-        F::Output: Send + 'static,
-    {
-        fn execute(&self, fut: F) {
-            background_spawn!("executor_spawn", fut);
-        }
-    }
-
-    let (tx, rx) = unbounded_channel::<Result<DuplexStream, Error>>();
-    let mut rx = UnboundedReceiverStream::new(rx);
-
-    let server_spawn = spawn!("grpc_server", async move {
-        let http = auto::Builder::new(Executor);
-        let grpc_service = tonic::service::Routes::new(bs_server.into_service());
-
-        let adapted_service = tower::ServiceBuilder::new()
-            .map_request(|req: hyper::Request<hyper::body::Incoming>| {
-                let (parts, body) = req.into_parts();
-                let body = body
-                    .map_err(|e| tonic::Status::internal(e.to_string()))
-                    .boxed_unsync();
-                hyper::Request::from_parts(parts, body)
-            })
-            .service(grpc_service);
-
-        let hyper_service = TowerToHyperService::new(adapted_service);
-
-        while let Some(stream) = rx.next().await {
-            http.serve_connection_with_upgrades(
-                TokioIo::new(stream.expect("Failed to get stream")),
-                hyper_service.clone(),
-            )
-            .await
-            .expect("Connection failed");
-        }
-    });
-
-    // Note: This is a dummy address, it will not actually connect to it,
-    // instead it will be connecting via mpsc.
-    let channel = Endpoint::try_from("http://[::]:50051")
-        .unwrap()
-        .executor(Executor)
-        .connect_with_connector(service_fn(move |_: Uri| {
-            let tx = tx.clone();
-            async move {
-                const MAX_BUFFER_SIZE: usize = 4096;
-                let (client, server) = tokio::io::duplex(MAX_BUFFER_SIZE);
-                tx.send(Ok(server)).unwrap();
-                Result::<_, Error>::Ok(TokioIo::new(client))
-            }
-        }))
-        .await
-        .unwrap();
-
-    let client = ByteStreamClient::new(channel);
-
-    (server_spawn, client)
-}
-
 #[nativelink_test]
 pub async fn chunked_stream_receives_all_data() -> Result<(), Box<dyn std::error::Error>> {
     let store_manager = make_store_manager().await?;
@@ -268,6 +222,85 @@ pub async fn chunked_stream_receives_all_data() -> Result<(), Box<dyn std::error
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn write_in_flight_is_drained_and_new_write_is_rejected(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager().await?;
+    let inflight_writes = InflightWrites::default();
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None)
+            .expect("Failed to make server")
+            .with_inflight_writes(inflight_writes.clone()),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    // Start a write, but do not finish it yet.
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    let mut write_request = WriteRequest {
+        resource_name: make_resource_name(WRITE_DATA.len()),
+        write_offset: 0,
+        finish_write: false,
+        data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+    };
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    while inflight_writes.count() == 0 {
+        yield_now().await;
+    }
+
+    // Start draining, which must wait for the write in flight.
+    let drain_fut = inflight_writes.drain(Duration::from_secs(10));
+    tokio::pin!(drain_fut);
+    assert!(poll!(&mut drain_fut).is_pending());
+
+    {
+        // A write started after draining began is rejected.
+        let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+        tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: format!(
+                "{INSTANCE_NAME}/uploads/{}/blobs/{HASH1}/{}",
+                "5ba8fd8d-2b4e-4a3f-9e17-0c3c1d0f9a6e", // Randomly generated.
+                WRITE_DATA.len(),
+            ),
+            write_offset: 0,
+            finish_write: true,
+            data: WRITE_DATA.into(),
+        })?))
+        .await?;
+        let status = join_handle
+            .await
+            .expect("Failed to join")
+            .expect_err("Expected write to be rejected");
+        assert_eq!(status.code(), tonic::Code::Unavailable, "{status:?}");
+    }
+
+    // The write in flight still completes.
+    write_request.write_offset = BYTE_SPLIT_OFFSET as i64;
+    write_request.data = WRITE_DATA[BYTE_SPLIT_OFFSET..].into();
+    write_request.finish_write = true;
+    tx.send(Frame::data(encode_stream_proto(&write_request)?))
+        .await?;
+    let committed_size = join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write")
+        .into_inner()
+        .committed_size;
+    assert_eq!(committed_size, WRITE_DATA.len() as i64);
+    assert_eq!(
+        store
+            .get_part_unchunked(DigestInfo::try_new(HASH1, WRITE_DATA.len())?, 0, None)
+            .await?,
+        WRITE_DATA.as_bytes()
+    );
+
+    drain_fut.await?;
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn resume_write_success() -> Result<(), Box<dyn std::error::Error>> {
     const WRITE_DATA: &str = "12456789abcdefghijk";
@@ -342,6 +375,411 @@ pub async fn resume_write_success() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn resume_write_from_query_write_status() -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+    let resource_name = make_resource_name(WRITE_DATA.len());
+
+    {
+        // Write the first chunk of data, then get interrupted.
+        let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+        tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: resource_name.clone(),
+            write_offset: 0,
+            finish_write: false,
+            data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+        })?))
+        .await?;
+        while bs_server
+            .query_write_status(Request::new(QueryWriteStatusRequest {
+                resource_name: resource_name.clone(),
+            }))
+            .await?
+            .into_inner()
+            .committed_size
+            == 0
+        {
+            yield_now().await;
+        }
+        drop(tx);
+        assert!(join_handle.await.expect("Failed to join").is_err());
+    }
+
+    // The client asks where to resume from.
+    let committed_size = {
+        let response = bs_server
+            .query_write_status(Request::new(QueryWriteStatusRequest {
+                resource_name: resource_name.clone(),
+            }))
+            .await?
+            .into_inner();
+        assert_eq!(
+            response,
+            QueryWriteStatusResponse {
+                committed_size: BYTE_SPLIT_OFFSET as i64,
+                complete: false,
+            }
+        );
+        response.committed_size
+    };
+
+    {
+        // Resume from the committed offset.
+        let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+        tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: resource_name.clone(),
+            write_offset: committed_size,
+            finish_write: true,
+            data: WRITE_DATA[committed_size as usize..].into(),
+        })?))
+        .await?;
+        let response = join_handle
+            .await
+            .expect("Failed to join")
+            .expect("Failed write")
+            .into_inner();
+        assert_eq!(response.committed_size, WRITE_DATA.len() as i64);
+    }
+
+    assert_eq!(
+        store
+            .get_part_unchunked(DigestInfo::try_new(HASH1, WRITE_DATA.len())?, 0, None)
+            .await?,
+        WRITE_DATA,
+        "Data written to store did not match expected data",
+    );
+    assert_eq!(
+        bs_server
+            .query_write_status(Request::new(QueryWriteStatusRequest { resource_name }))
+            .await?
+            .into_inner(),
+        QueryWriteStatusResponse {
+            committed_size: WRITE_DATA.len() as i64,
+            complete: true,
+        }
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn resume_write_with_different_digest_fails() -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+    const UUID: &str = "4dcec57e-1389-4ab5-b188-4a59f22ceb4b";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+
+    {
+        let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+        tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: make_resource_name(WRITE_DATA.len()),
+            write_offset: 0,
+            finish_write: false,
+            data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+        })?))
+        .await?;
+        drop(tx);
+        assert!(join_handle.await.expect("Failed to join").is_err());
+    }
+
+    // Same upload UUID, but a different blob.
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: format!(
+            "{INSTANCE_NAME}/uploads/{UUID}/blobs/{HASH1}/{}",
+            WRITE_DATA.len() + 1
+        ),
+        write_offset: BYTE_SPLIT_OFFSET as i64,
+        finish_write: true,
+        data: WRITE_DATA[BYTE_SPLIT_OFFSET..].into(),
+    })?))
+    .await?;
+    let status = join_handle
+        .await
+        .expect("Failed to join")
+        .expect_err("Expected resume to fail");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument, "{status:?}");
+    assert!(
+        status.message().contains("Cannot resume upload"),
+        "Unexpected error: {status:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn idle_upload_is_discarded_after_timeout() -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager().await?;
+    let timeout = Arc::new(Notify::new());
+    let bs_server = Arc::new(ByteStreamServer::new_with_sleep_fn(
+        &ByteStreamConfig {
+            cas_stores: hashmap! {
+                INSTANCE_NAME.to_string() => "main_cas".to_string(),
+            },
+            ..Default::default()
+        },
+        store_manager.as_ref(),
+        {
+            let timeout = timeout.clone();
+            Arc::new(move || {
+                let timeout = timeout.clone();
+                Box::pin(async move { timeout.notified().await })
+            })
+        },
+    )?);
+    let resource_name = make_resource_name(WRITE_DATA.len());
+    let query_committed_size = {
+        let bs_server = &bs_server;
+        let resource_name = &resource_name;
+        move || async move {
+            bs_server
+                .query_write_status(Request::new(QueryWriteStatusRequest {
+                    resource_name: resource_name.clone(),
+                }))
+                .await
+                .map(|response| response.into_inner().committed_size)
+        }
+    };
+
+    {
+        let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+        tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: resource_name.clone(),
+            write_offset: 0,
+            finish_write: false,
+            data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+        })?))
+        .await?;
+        while query_committed_size().await? == 0 {
+            yield_now().await;
+        }
+        drop(tx);
+        assert!(join_handle.await.expect("Failed to join").is_err());
+    }
+    assert_eq!(query_committed_size().await?, BYTE_SPLIT_OFFSET as i64);
+
+    // Once the idle timeout fires the partial upload is discarded.
+    timeout.notify_one();
+    while query_committed_size().await? != 0 {
+        yield_now().await;
+    }
+
+    // So resuming it is no longer possible.
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: BYTE_SPLIT_OFFSET as i64,
+        finish_write: true,
+        data: WRITE_DATA[BYTE_SPLIT_OFFSET..].into(),
+    })?))
+    .await?;
+    let status = join_handle
+        .await
+        .expect("Failed to join")
+        .expect_err("Expected resume to fail");
+    assert!(
+        status.message().contains("Received out of order data"),
+        "Unexpected error: {status:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn stalled_write_is_aborted_while_active_write_proceeds(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+    const CHUNK_DELAY: Duration = Duration::from_millis(300);
+
+    let store_manager = make_store_manager().await?;
+    let config = ByteStreamConfig {
+        cas_stores: hashmap! {
+            INSTANCE_NAME.to_string() => "main_cas".to_string(),
+        },
+        write_idle_timeout: 1,
+        ..Default::default()
+    };
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), Some(config))
+            .expect("Failed to make server"),
+    );
+
+    // This client sends its first chunk and then stalls.
+    let (stalled_tx, stalled_join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    stalled_tx
+        .send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name: make_resource_name(WRITE_DATA.len()),
+            write_offset: 0,
+            finish_write: false,
+            data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+        })?))
+        .await?;
+
+    // This client keeps sending a byte at a time, each well within the idle
+    // timeout, but takes longer than the idle timeout in total.
+    let (active_tx, active_join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    let resource_name = format!(
+        "{INSTANCE_NAME}/uploads/{}/blobs/{HASH1}/{}",
+        "5ba8fd8d-2b4e-4a3f-9e17-0c3c1d0f9a6e", // Randomly generated.
+        WRITE_DATA.len(),
+    );
+    for offset in 0..BYTE_SPLIT_OFFSET {
+        active_tx
+            .send(Frame::data(encode_stream_proto(&WriteRequest {
+                resource_name: resource_name.clone(),
+                write_offset: offset as i64,
+                finish_write: false,
+                data: WRITE_DATA[offset..=offset].into(),
+            })?))
+            .await?;
+        tokio::time::sleep(CHUNK_DELAY).await;
+    }
+    active_tx
+        .send(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name,
+            write_offset: BYTE_SPLIT_OFFSET as i64,
+            finish_write: true,
+            data: WRITE_DATA[BYTE_SPLIT_OFFSET..].into(),
+        })?))
+        .await?;
+
+    let committed_size = active_join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write")
+        .into_inner()
+        .committed_size;
+    assert_eq!(committed_size, WRITE_DATA.len() as i64);
+
+    let status = stalled_join_handle
+        .await
+        .expect("Failed to join")
+        .expect_err("Expected stalled write to be aborted");
+    assert_eq!(status.code(), tonic::Code::DeadlineExceeded, "{status:?}");
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn writes_per_client_are_limited() -> Result<(), Box<dyn std::error::Error>> {
+    const WRITE_DATA: &str = "12456789abcdefghijk";
+    const BYTE_SPLIT_OFFSET: usize = 8;
+
+    let store_manager = make_store_manager().await?;
+    let config = ByteStreamConfig {
+        cas_stores: hashmap! {
+            INSTANCE_NAME.to_string() => "main_cas".to_string(),
+        },
+        max_concurrent_writes_per_client: 1,
+        ..Default::default()
+    };
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), Some(config))
+            .expect("Failed to make server"),
+    );
+    let client_addr: SocketAddr = "127.0.0.1:40000".parse()?;
+    let same_client_addr: SocketAddr = "127.0.0.1:40001".parse()?;
+    let other_client_addr: SocketAddr = "127.0.0.2:40000".parse()?;
+    let other_resource_name = |uuid: &str| {
+        format!(
+            "{INSTANCE_NAME}/uploads/{uuid}/blobs/{HASH1}/{}",
+            WRITE_DATA.len()
+        )
+    };
+    let write_all = |resource_name: String| -> Result<Frame<Bytes>, Box<dyn std::error::Error>> {
+        Ok(Frame::data(encode_stream_proto(&WriteRequest {
+            resource_name,
+            write_offset: 0,
+            finish_write: true,
+            data: WRITE_DATA.into(),
+        })?))
+    };
+
+    // Start a write, but do not finish it yet.
+    let resource_name = make_resource_name(WRITE_DATA.len());
+    let (tx, join_handle) = make_client_stream_and_writer_spawn(bs_server.clone(), client_addr);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: 0,
+        finish_write: false,
+        data: WRITE_DATA[..BYTE_SPLIT_OFFSET].into(),
+    })?))
+    .await?;
+    while bs_server
+        .query_write_status(Request::new(QueryWriteStatusRequest {
+            resource_name: resource_name.clone(),
+        }))
+        .await?
+        .into_inner()
+        .committed_size
+        == 0
+    {
+        yield_now().await;
+    }
+
+    {
+        // A second write from the same client is rejected.
+        let (tx, join_handle) =
+            make_client_stream_and_writer_spawn(bs_server.clone(), same_client_addr);
+        tx.send(write_all(other_resource_name(
+            "5ba8fd8d-2b4e-4a3f-9e17-0c3c1d0f9a6e", // Randomly generated.
+        ))?)
+        .await?;
+        let status = join_handle
+            .await
+            .expect("Failed to join")
+            .expect_err("Expected write to be rejected");
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted, "{status:?}");
+    }
+    {
+        // Other clients are not affected.
+        let (tx, join_handle) =
+            make_client_stream_and_writer_spawn(bs_server.clone(), other_client_addr);
+        tx.send(write_all(other_resource_name(
+            "0b0e4a52-8d8f-4b43-9d1b-2f0c5a7e6c31", // Randomly generated.
+        ))?)
+        .await?;
+        join_handle
+            .await
+            .expect("Failed to join")
+            .expect("Failed write");
+    }
+
+    // Once the first write finishes the client may write again.
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name,
+        write_offset: BYTE_SPLIT_OFFSET as i64,
+        finish_write: true,
+        data: WRITE_DATA[BYTE_SPLIT_OFFSET..].into(),
+    })?))
+    .await?;
+    join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    let (tx, join_handle) = make_client_stream_and_writer_spawn(bs_server, same_client_addr);
+    tx.send(write_all(other_resource_name(
+        "9c6f1d3e-74a2-4e0b-8f55-3d2a6b1c9e07", // Randomly generated.
+    ))?)
+    .await?;
+    join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write");
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn restart_write_success() -> Result<(), Box<dyn std::error::Error>> {
     const WRITE_DATA: &str = "12456789abcdefghijk";
@@ -728,6 +1166,181 @@ pub async fn out_of_sequence_write() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn write_zstd_compressed_blob_and_read_back() -> Result<(), Box<dyn std::error::Error>> {
+    let raw_data = "12456789abcdefghijk".repeat(100);
+    let compressed_data = zstd::bulk::compress(raw_data.as_bytes(), 0)?;
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server.clone(), None);
+    let resource_name = format!(
+        "{}/uploads/{}/compressed-blobs/zstd/{}/{}",
+        INSTANCE_NAME,
+        "4dcec57e-1389-4ab5-b188-4a59f22ceb4b", // Randomly generated.
+        HASH1,
+        raw_data.len(),
+    );
+    // Split the compressed data, so a zstd frame spans two messages.
+    let (first_half, second_half) = compressed_data.split_at(compressed_data.len() / 2);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: resource_name.clone(),
+        write_offset: 0,
+        finish_write: false,
+        data: Bytes::copy_from_slice(first_half),
+    })?))
+    .await?;
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name,
+        write_offset: first_half.len() as i64,
+        finish_write: true,
+        data: Bytes::copy_from_slice(second_half),
+    })?))
+    .await?;
+    let response = join_handle
+        .await
+        .expect("Failed to join")
+        .expect("Failed write")
+        .into_inner();
+    assert_eq!(response.committed_size, compressed_data.len() as i64);
+
+    // The store only ever sees the uncompressed data.
+    let digest = DigestInfo::try_new(HASH1, raw_data.len())?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        raw_data.as_bytes()
+    );
+
+    let read_all = |resource_name: String| {
+        let bs_server = bs_server.clone();
+        async move {
+            let mut read_stream = bs_server
+                .read(Request::new(ReadRequest {
+                    resource_name,
+                    read_offset: 0,
+                    read_limit: 0,
+                }))
+                .await?
+                .into_inner();
+            let mut data = Vec::new();
+            while let Some(read_response) = read_stream.next().await {
+                data.extend_from_slice(&read_response?.data);
+            }
+            Ok::<_, tonic::Status>(data)
+        }
+    };
+    assert_eq!(
+        read_all(format!(
+            "{}/blobs/{}/{}",
+            INSTANCE_NAME,
+            HASH1,
+            raw_data.len()
+        ))
+        .await?,
+        raw_data.as_bytes()
+    );
+    let read_compressed_data = read_all(format!(
+        "{}/compressed-blobs/zstd/{}/{}",
+        INSTANCE_NAME,
+        HASH1,
+        raw_data.len()
+    ))
+    .await?;
+    assert_eq!(
+        zstd::stream::decode_all(read_compressed_data.as_slice())?,
+        raw_data.as_bytes()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn write_zstd_compressed_blob_with_wrong_size_fails(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "12456789abcdefghijk";
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: format!(
+            "{}/uploads/{}/compressed-blobs/zstd/{}/{}",
+            INSTANCE_NAME,
+            "4dcec57e-1389-4ab5-b188-4a59f22ceb4b", // Randomly generated.
+            HASH1,
+            VALUE.len() + 1,
+        ),
+        write_offset: 0,
+        finish_write: true,
+        data: zstd::bulk::compress(VALUE.as_bytes(), 0)?.into(),
+    })?))
+    .await?;
+    let result = join_handle.await.expect("Failed to join");
+    assert_eq!(
+        result.map_err(|status| status.code()).err(),
+        Some(tonic::Code::InvalidArgument)
+    );
+    assert_eq!(
+        store
+            .has(DigestInfo::try_new(HASH1, VALUE.len() + 1)?)
+            .await?,
+        None
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn write_zstd_compressed_blob_that_expands_past_size_fails(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const EXPECTED_SIZE: usize = 100;
+    let store_manager = make_store_manager().await?;
+    let bs_server = Arc::new(
+        make_bytestream_server(store_manager.as_ref(), None).expect("Failed to make server"),
+    );
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    // A few bytes of compressed data that decompress to 64mb.
+    let compressed_data = zstd::bulk::compress(&vec![0u8; 64 * 1024 * 1024], 0)?;
+    let (tx, join_handle) = make_stream_and_writer_spawn(bs_server, None);
+    tx.send(Frame::data(encode_stream_proto(&WriteRequest {
+        resource_name: format!(
+            "{}/uploads/{}/compressed-blobs/zstd/{}/{}",
+            INSTANCE_NAME,
+            "4dcec57e-1389-4ab5-b188-4a59f22ceb4b", // Randomly generated.
+            HASH1,
+            EXPECTED_SIZE,
+        ),
+        write_offset: 0,
+        finish_write: true,
+        data: compressed_data.into(),
+    })?))
+    .await?;
+    let status = join_handle
+        .await
+        .expect("Failed to join")
+        .expect_err("Expected write to fail");
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(
+        status
+            .message()
+            .contains(&format!("decompresses to more than {EXPECTED_SIZE} bytes")),
+        "Expected the write to be rejected for its size, got {status:?}"
+    );
+    assert_eq!(
+        store
+            .has(DigestInfo::try_new(HASH1, EXPECTED_SIZE)?)
+            .await?,
+        None
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn chunked_stream_reads_small_set_of_data() -> Result<(), Box<dyn std::error::Error>> {
     const VALUE1: &str = "12456789abcdefghijk";
@@ -812,6 +1425,66 @@ pub async fn chunked_stream_reads_10mb_of_data() -> Result<(), Box<dyn std::erro
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn read_validates_instance_digest_function() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "12456789abcdefghijk";
+
+    let store_manager = make_store_manager().await?;
+    let config = ByteStreamConfig {
+        cas_stores: hashmap! {
+            INSTANCE_NAME.to_string() => "main_cas".to_string(),
+        },
+        digest_functions: hashmap! {
+            INSTANCE_NAME.to_string() => ConfigDigestHashFunction::blake3,
+        },
+        ..Default::default()
+    };
+    let bs_server = make_bytestream_server(store_manager.as_ref(), Some(config))
+        .expect("Failed to make server");
+    let store = store_manager.get_store("main_cas").unwrap();
+    store
+        .update_oneshot(DigestInfo::try_new(HASH1, VALUE1.len())?, VALUE1.into())
+        .await?;
+
+    for resource_name in [
+        format!("{INSTANCE_NAME}/blobs/{HASH1}/{}", VALUE1.len()),
+        format!("{INSTANCE_NAME}/blobs/blake3/{HASH1}/{}", VALUE1.len()),
+    ] {
+        let mut read_stream = bs_server
+            .read(Request::new(ReadRequest {
+                resource_name,
+                read_offset: 0,
+                read_limit: VALUE1.len() as i64,
+            }))
+            .await?
+            .into_inner();
+        let mut roundtrip_data = Vec::with_capacity(VALUE1.len());
+        while let Some(result_read_response) = read_stream.next().await {
+            roundtrip_data.append(&mut result_read_response?.data.to_vec());
+        }
+        assert_eq!(roundtrip_data, VALUE1.as_bytes());
+    }
+
+    let Err(status) = bs_server
+        .read(Request::new(ReadRequest {
+            resource_name: format!("{INSTANCE_NAME}/blobs/sha256/{HASH1}/{}", VALUE1.len()),
+            read_offset: 0,
+            read_limit: VALUE1.len() as i64,
+        }))
+        .await
+    else {
+        panic!("Expected read with mismatched digest function to fail");
+    };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(
+        status
+            .message()
+            .contains("does not match the digest function BLAKE3"),
+        "Unexpected error: {status:?}"
+    );
+    Ok(())
+}
+
 /// A bug was found in early development where we could deadlock when reading a stream if the
 /// store backend resulted in an error. This was because we were not shutting down the stream
 /// when on the backend store error which caused the AsyncReader to block forever because the
@@ -970,7 +1643,9 @@ pub async fn max_decoding_message_size_test() -> Result<(), Box<dyn std::error::
     };
     let bs_server = make_bytestream_server(store_manager.as_ref(), Some(config))
         .expect("Failed to make server");
-    let (server_join_handle, mut bs_client) = server_and_client_stub(bs_server).await;
+    let (server_join_handle, channel) =
+        server_and_client_stub(Routes::new(bs_server.into_service())).await;
+    let mut bs_client = ByteStreamClient::new(channel);
 
     {
         // Test to ensure if we send exactly our max message size, it will succeed.
@@ -1025,3 +1700,54 @@ pub async fn max_decoding_message_size_test() -> Result<(), Box<dyn std::error::
 
     Ok(())
 }
+
+#[nativelink_test]
+pub async fn instance_name_alias_routes_to_aliased_instance(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "12456789abcdefghijk";
+
+    let store_manager = make_store_manager().await?;
+    let bs_server = make_bytestream_server(store_manager.as_ref(), None)
+        .expect("Failed to make server")
+        .with_instance_name_aliases(InstanceNameAliases::new(hashmap! {
+            "alias".to_string() => INSTANCE_NAME.to_string(),
+        }));
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+
+    let mut read_stream = bs_server
+        .read(Request::new(ReadRequest {
+            resource_name: format!("alias/blobs/{}/{}", HASH1, VALUE1.len()),
+            read_offset: 0,
+            read_limit: VALUE1.len() as i64,
+        }))
+        .await?
+        .into_inner();
+    let mut roundtrip_data = Vec::with_capacity(VALUE1.len());
+    while let Some(result_read_response) = read_stream.next().await {
+        roundtrip_data.append(&mut result_read_response?.data.to_vec());
+    }
+    assert_eq!(roundtrip_data, VALUE1.as_bytes());
+
+    let response = bs_server
+        .query_write_status(Request::new(QueryWriteStatusRequest {
+            resource_name: format!(
+                "alias/uploads/{}/blobs/{}/{}",
+                "4dcec57e-1389-4ab5-b188-4a59f22ceb4b", // Randomly generated.
+                HASH1,
+                VALUE1.len()
+            ),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(
+        response,
+        QueryWriteStatusResponse {
+            committed_size: VALUE1.len() as i64,
+            complete: true,
+        }
+    );
+    Ok(())
+}
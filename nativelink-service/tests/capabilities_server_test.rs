@@ -0,0 +1,132 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use maplit::hashmap;
+use nativelink_config::cas_server::{AcStoreConfig, CapabilitiesConfig, CasStoreConfig};
+use nativelink_config::stores::ConfigDigestHashFunction;
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::capabilities_server::Capabilities;
+use nativelink_proto::build::bazel::remote::execution::v2::digest_function::Value as DigestFunction;
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    CacheCapabilities, GetCapabilitiesRequest,
+};
+use nativelink_service::capabilities_server::CapabilitiesServer;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
+use pretty_assertions::assert_eq;
+use tonic::Request;
+
+const LIMITED_INSTANCE_NAME: &str = "limited_instance_name";
+const DEFAULT_INSTANCE_NAME: &str = "default_instance_name";
+
+async fn get_cache_capabilities(
+    server: &CapabilitiesServer,
+    instance_name: &str,
+) -> Result<CacheCapabilities, Error> {
+    Ok(server
+        .get_capabilities(Request::new(GetCapabilitiesRequest {
+            instance_name: instance_name.to_string(),
+        }))
+        .await?
+        .into_inner()
+        .cache_capabilities
+        .expect("Cache capabilities should always be set"))
+}
+
+#[nativelink_test]
+async fn reports_configured_cache_capabilities_test() -> Result<(), Error> {
+    const MAX_BYTES_PER_BATCH: u64 = 4 * 1024 * 1024;
+
+    let server = CapabilitiesServer::new(
+        &hashmap! {
+            LIMITED_INSTANCE_NAME.to_string() => CapabilitiesConfig::default(),
+            DEFAULT_INSTANCE_NAME.to_string() => CapabilitiesConfig::default(),
+        },
+        Some(&hashmap! {
+            LIMITED_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "main_cas".to_string(),
+                max_bytes_per_batch: MAX_BYTES_PER_BATCH,
+                digest_function: Some(ConfigDigestHashFunction::blake3),
+                ..Default::default()
+            },
+            DEFAULT_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "main_cas".to_string(),
+                ..Default::default()
+            },
+        }),
+        Some(&hashmap! {
+            LIMITED_INSTANCE_NAME.to_string() => AcStoreConfig {
+                ac_store: "main_ac".to_string(),
+                read_only: true,
+                verify_cas_store: None,
+                inline_output_cas_store: None,
+                max_inline_output_bytes: 0,
+            },
+        }),
+        &HashMap::new(),
+    )
+    .await?
+    .with_instance_name_aliases(InstanceNameAliases::new(hashmap! {
+        "alias".to_string() => LIMITED_INSTANCE_NAME.to_string(),
+    }));
+
+    {
+        // Limits of the instance are reported as configured.
+        let cache_capabilities = get_cache_capabilities(&server, LIMITED_INSTANCE_NAME).await?;
+        assert_eq!(
+            cache_capabilities.max_batch_total_size_bytes,
+            i64::try_from(MAX_BYTES_PER_BATCH).unwrap()
+        );
+        assert_eq!(
+            cache_capabilities.digest_functions,
+            vec![i32::from(DigestFunction::Blake3)]
+        );
+        assert_eq!(
+            cache_capabilities
+                .action_cache_update_capabilities
+                .map(|capabilities| capabilities.update_enabled),
+            Some(false)
+        );
+    }
+    {
+        // Unlimited instances keep the historical defaults.
+        let cache_capabilities = get_cache_capabilities(&server, DEFAULT_INSTANCE_NAME).await?;
+        assert_eq!(cache_capabilities.max_batch_total_size_bytes, 64 * 1024);
+        assert_eq!(
+            cache_capabilities.digest_functions,
+            vec![
+                i32::from(DigestFunction::Sha256),
+                i32::from(DigestFunction::Blake3),
+                i32::from(DigestFunction::Sha512)
+            ]
+        );
+        assert_eq!(
+            cache_capabilities
+                .action_cache_update_capabilities
+                .map(|capabilities| capabilities.update_enabled),
+            Some(true)
+        );
+    }
+    {
+        // Aliases report the capabilities of the instance they map to.
+        let cache_capabilities = get_cache_capabilities(&server, "alias").await?;
+        assert_eq!(
+            cache_capabilities,
+            get_cache_capabilities(&server, LIMITED_INSTANCE_NAME).await?
+        );
+    }
+    Ok(())
+}
@@ -12,14 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
+use async_trait::async_trait;
 use futures::StreamExt;
 use maplit::hashmap;
-use nativelink_config::stores::{MemorySpec, StoreSpec};
+use nativelink_config::cas_server::CasStoreConfig;
+use nativelink_config::stores::{
+    ConfigDigestHashFunction, FilesystemSpec, MemorySpec, StoreSpec, VerifySpec,
+};
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorage;
 use nativelink_proto::build::bazel::remote::execution::v2::{
     batch_read_blobs_response, batch_update_blobs_request, batch_update_blobs_response, compressor,
@@ -31,13 +39,29 @@ use nativelink_proto::google::rpc::Status as GrpcStatus;
 use nativelink_service::cas_server::CasServer;
 use nativelink_store::ac_utils::serialize_and_upload_message;
 use nativelink_store::default_store_factory::store_factory;
+use nativelink_store::memory_store::MemoryStore;
 use nativelink_store::store_manager::StoreManager;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
-use nativelink_util::store_trait::{StoreKey, StoreLike};
-use pretty_assertions::assert_eq;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::inflight_writes::InflightWrites;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
+use nativelink_util::json_log_layer;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use nativelink_util::task::JoinHandleDropGuard;
+use parking_lot::Mutex;
+use pretty_assertions::{assert_eq, assert_ne};
 use prost_types::Timestamp;
+use tonic::service::Routes;
+use tonic::transport::Channel;
 use tonic::{Code, Request};
+use tracing_subscriber::layer::SubscriberExt;
+use utils::server_utils::server_and_client_stub;
+
+mod utils {
+    pub(crate) mod server_utils;
+}
 
 const INSTANCE_NAME: &str = "foo_instance_name";
 const HASH1: &str = "0123456789abcdef000000000000000000000000000000000123456789abcdef";
@@ -64,6 +88,25 @@ fn make_cas_server(store_manager: &StoreManager) -> Result<CasServer, Error> {
         &hashmap! {
             "foo_instance_name".to_string() => nativelink_config::cas_server::CasStoreConfig{
                 cas_store: "main_cas".to_string(),
+                ..Default::default()
+            }
+        },
+        store_manager,
+    )
+}
+
+fn make_cas_server_with_limits(
+    store_manager: &StoreManager,
+    max_bytes_per_batch: u64,
+    max_blob_size: u64,
+) -> Result<CasServer, Error> {
+    CasServer::new(
+        &hashmap! {
+            "foo_instance_name".to_string() => nativelink_config::cas_server::CasStoreConfig{
+                cas_store: "main_cas".to_string(),
+                max_bytes_per_batch,
+                max_blob_size,
+                ..Default::default()
             }
         },
         store_manager,
@@ -151,8 +194,8 @@ async fn has_three_requests_one_bad_hash() -> Result<(), Box<dyn std::error::Err
         .await;
     let error = raw_response.unwrap_err();
     assert!(
-        error.to_string().contains("Invalid sha256 hash: BAD_HASH"),
-        "'Invalid sha256 hash: BAD_HASH' not found in: {error:?}"
+        error.to_string().contains("Invalid hash: BAD_HASH"),
+        "'Invalid hash: BAD_HASH' not found in: {error:?}"
     );
     Ok(())
 }
@@ -213,6 +256,40 @@ async fn update_existing_item() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn batch_update_blobs_rejected_while_draining() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "1";
+
+    let store_manager = make_store_manager().await?;
+    let inflight_writes = InflightWrites::default();
+    let cas_server = make_cas_server(&store_manager)?.with_inflight_writes(inflight_writes.clone());
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    inflight_writes.drain(Duration::from_secs(1)).await?;
+
+    let error = cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            requests: vec![batch_update_blobs_request::Request {
+                digest: Some(Digest {
+                    hash: HASH1.to_string(),
+                    size_bytes: VALUE.len() as i64,
+                }),
+                data: VALUE.into(),
+                compressor: compressor::Value::Identity.into(),
+            }],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Code::Unavailable, "{error:?}");
+    assert_eq!(
+        store.has(DigestInfo::try_new(HASH1, VALUE.len())?).await?,
+        None
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn batch_read_blobs_read_two_blobs_success_one_fail() -> Result<(), Box<dyn std::error::Error>>
 {
@@ -305,7 +382,6 @@ struct SetupDirectoryResult {
     root_directory: Directory,
     root_directory_digest_info: DigestInfo,
     sub_directories: Vec<Directory>,
-    sub_directory_digest_infos: Vec<DigestInfo>,
 }
 async fn setup_directory_structure(
     store_pinned: Pin<&impl StoreLike>,
@@ -314,7 +390,6 @@ async fn setup_directory_structure(
     const SUB_DIRECTORIES_LENGTH: i32 = 5;
     let mut sub_directory_nodes: Vec<DirectoryNode> = vec![];
     let mut sub_directories: Vec<Directory> = vec![];
-    let mut sub_directory_digest_infos: Vec<DigestInfo> = vec![];
 
     for i in 0..SUB_DIRECTORIES_LENGTH {
         let sub_directory: Directory = Directory {
@@ -336,7 +411,6 @@ async fn setup_directory_structure(
             &mut DigestHasherFunc::Sha256.hasher(),
         )
         .await?;
-        sub_directory_digest_infos.push(sub_directory_digest_info);
         sub_directory_nodes.push(DirectoryNode {
             name: format!("sub_directory_{i}"),
             digest: Some(sub_directory_digest_info.into()),
@@ -362,10 +436,31 @@ async fn setup_directory_structure(
         root_directory,
         root_directory_digest_info,
         sub_directories,
-        sub_directory_digest_infos,
     })
 }
 
+async fn get_tree_page(
+    cas_server: &CasServer,
+    root_digest: DigestInfo,
+    page_size: i32,
+    page_token: String,
+) -> Result<GetTreeResponse, Box<dyn std::error::Error>> {
+    let mut responses = cas_server
+        .get_tree(Request::new(GetTreeRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            page_size,
+            page_token,
+            root_digest: Some(root_digest.into()),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(responses.len(), 1, "Expected exactly one GetTreeResponse");
+    Ok(responses.remove(0)?)
+}
+
 #[nativelink_test]
 async fn get_tree_read_directories_without_paging() -> Result<(), Box<dyn std::error::Error>> {
     let store_manager = make_store_manager().await?;
@@ -377,7 +472,6 @@ async fn get_tree_read_directories_without_paging() -> Result<(), Box<dyn std::e
         root_directory,
         root_directory_digest_info,
         sub_directories,
-        sub_directory_digest_infos: _,
     } = setup_directory_structure(store.as_pin()).await?;
 
     // Must work when paging is disabled ( `page_size` is 0 ).
@@ -416,38 +510,6 @@ async fn get_tree_read_directories_without_paging() -> Result<(), Box<dyn std::e
         );
     }
 
-    // Also verify that sending the root digest returns the entire tree as well.
-    {
-        let raw_response = cas_server
-            .get_tree(Request::new(GetTreeRequest {
-                instance_name: INSTANCE_NAME.to_string(),
-                page_size: 0,
-                page_token: format!("{root_directory_digest_info}"),
-                root_digest: Some(root_directory_digest_info.into()),
-                digest_function: digest_function::Value::Sha256.into(),
-            }))
-            .await;
-        assert_eq!(
-            raw_response
-                .unwrap()
-                .into_inner()
-                .filter_map(|x| async move { Some(x.unwrap()) })
-                .collect::<Vec<_>>()
-                .await,
-            vec![GetTreeResponse {
-                directories: vec![
-                    root_directory.clone(),
-                    sub_directories[0].clone(),
-                    sub_directories[1].clone(),
-                    sub_directories[2].clone(),
-                    sub_directories[3].clone(),
-                    sub_directories[4].clone()
-                ],
-                next_page_token: String::new()
-            }]
-        );
-    }
-
     Ok(())
 }
 
@@ -462,112 +524,285 @@ async fn get_tree_read_directories_with_paging() -> Result<(), Box<dyn std::erro
         root_directory,
         root_directory_digest_info,
         sub_directories,
-        sub_directory_digest_infos,
     } = setup_directory_structure(store.as_pin()).await?;
 
     // Must work when paging is enabled ( `page_size` is 2 ).
     // First, it reads `root_directory` and `sub_directory[0]`.
     // Then, it reads `sub_directory[1]` and `sub_directory[2]`.
     // Finally, it reads `sub_directory[3]` and `sub_directory[4]`.
+    // Each request resumes from the opaque `next_page_token` of the previous one.
+    let first_page =
+        get_tree_page(&cas_server, root_directory_digest_info, 2, String::new()).await?;
+    assert_eq!(
+        first_page.directories,
+        vec![root_directory.clone(), sub_directories[0].clone()]
+    );
+    assert!(
+        !first_page.next_page_token.is_empty(),
+        "Expected a next_page_token after the first page"
+    );
 
-    // First, verify that an empty initial page token is treated as if the client had sent the
-    // root digest and respects the page size.
-    {
-        let raw_response = cas_server
+    let second_page = get_tree_page(
+        &cas_server,
+        root_directory_digest_info,
+        2,
+        first_page.next_page_token.clone(),
+    )
+    .await?;
+    assert_eq!(
+        second_page.directories,
+        vec![sub_directories[1].clone(), sub_directories[2].clone()]
+    );
+    assert!(
+        !second_page.next_page_token.is_empty(),
+        "Expected a next_page_token after the second page"
+    );
+    // Tokens hold a position in the tree rather than the directories left
+    // to visit, so they do not grow with the tree.
+    assert_eq!(
+        first_page.next_page_token.len(),
+        second_page.next_page_token.len()
+    );
+
+    let third_page = get_tree_page(
+        &cas_server,
+        root_directory_digest_info,
+        2,
+        second_page.next_page_token.clone(),
+    )
+    .await?;
+    assert_eq!(
+        third_page,
+        GetTreeResponse {
+            directories: vec![sub_directories[3].clone(), sub_directories[4].clone()],
+            next_page_token: String::new(),
+        }
+    );
+
+    // Page tokens carry all state needed to resume, so replaying one must
+    // return the same page again.
+    assert_eq!(
+        get_tree_page(
+            &cas_server,
+            root_directory_digest_info,
+            2,
+            first_page.next_page_token,
+        )
+        .await?,
+        second_page
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_tree_rejects_malformed_page_token() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let SetupDirectoryResult {
+        root_directory_digest_info,
+        ..
+    } = setup_directory_structure(store.as_pin()).await?;
+
+    for page_token in [
+        // Not valid base64.
+        "not a valid token!".to_string(),
+        // The legacy `{hash}-{size}` format is no longer accepted.
+        format!("{root_directory_digest_info}"),
+        // Valid base64, but not a whole number of digests.
+        "AAAA".to_string(),
+    ] {
+        let result = cas_server
             .get_tree(Request::new(GetTreeRequest {
                 instance_name: INSTANCE_NAME.to_string(),
                 page_size: 2,
-                page_token: String::new(),
+                page_token: page_token.clone(),
                 root_digest: Some(root_directory_digest_info.into()),
                 digest_function: digest_function::Value::Sha256.into(),
             }))
             .await;
-        assert_eq!(
-            raw_response
-                .unwrap()
-                .into_inner()
-                .filter_map(|x| async move { Some(x.unwrap()) })
-                .collect::<Vec<_>>()
-                .await,
-            vec![GetTreeResponse {
-                directories: vec![root_directory.clone(), sub_directories[0].clone()],
-                next_page_token: format!("{}", sub_directory_digest_infos[1]),
-            }]
+        let Err(status) = result else {
+            panic!("Expected malformed page_token '{page_token}' to be rejected");
+        };
+        assert_eq!(status.code(), Code::InvalidArgument, "{status:?}");
+        assert!(
+            !status.message().contains(&page_token),
+            "Expected page_token not to be echoed: {status:?}"
         );
     }
 
-    // Also verify that sending the root digest as the page token is treated as paging from the
-    // beginning and respects page size.
-    {
-        let raw_response = cas_server
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_tree_rejects_page_token_of_other_root_or_too_large(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let SetupDirectoryResult {
+        root_directory_digest_info,
+        ..
+    } = setup_directory_structure(store.as_pin()).await?;
+    let other_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let first_page =
+        get_tree_page(&cas_server, root_directory_digest_info, 2, String::new()).await?;
+    assert!(
+        !first_page.next_page_token.is_empty(),
+        "Expected a next_page_token after the first page"
+    );
+
+    for (root_digest, page_token) in [
+        // A token is only valid for the tree it was created for.
+        (other_root_digest, first_page.next_page_token),
+        // Tokens over the size limit are rejected before being decoded.
+        (root_directory_digest_info, "A".repeat(1024)),
+    ] {
+        let result = cas_server
             .get_tree(Request::new(GetTreeRequest {
                 instance_name: INSTANCE_NAME.to_string(),
                 page_size: 2,
-                page_token: format!("{root_directory_digest_info}"),
-                root_digest: Some(root_directory_digest_info.into()),
+                page_token,
+                root_digest: Some(root_digest.into()),
                 digest_function: digest_function::Value::Sha256.into(),
             }))
             .await;
-        assert_eq!(
-            raw_response
-                .unwrap()
-                .into_inner()
-                .filter_map(|x| async move { Some(x.unwrap()) })
-                .collect::<Vec<_>>()
-                .await,
-            vec![GetTreeResponse {
-                directories: vec![root_directory.clone(), sub_directories[0].clone()],
-                next_page_token: format!("{}", sub_directory_digest_infos[1]),
-            }]
-        );
+        let Err(status) = result else {
+            panic!("Expected page_token for root_digest {root_digest} to be rejected");
+        };
+        assert_eq!(status.code(), Code::InvalidArgument, "{status:?}");
     }
 
-    // Verify that paging from a non-initial page token will return the expected content.
-    {
-        let raw_response = cas_server
-            .get_tree(Request::new(GetTreeRequest {
-                instance_name: INSTANCE_NAME.to_string(),
-                page_size: 2,
-                page_token: format!("{}", sub_directory_digest_infos[1]),
-                root_digest: Some(root_directory_digest_info.into()),
-                digest_function: digest_function::Value::Sha256.into(),
-            }))
-            .await;
-        assert_eq!(
-            raw_response
-                .unwrap()
-                .into_inner()
-                .filter_map(|x| async move { Some(x.unwrap()) })
-                .collect::<Vec<_>>()
-                .await,
-            vec![GetTreeResponse {
-                directories: vec![sub_directories[1].clone(), sub_directories[2].clone()],
-                next_page_token: format!("{}", sub_directory_digest_infos[3]),
-            }]
-        );
+    Ok(())
+}
 
-        let raw_response = cas_server
+#[nativelink_test]
+async fn get_tree_rejects_trees_over_limits() -> Result<(), Box<dyn std::error::Error>> {
+    const TREE_DEPTH: usize = 100;
+
+    let store_manager = make_store_manager().await?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    // Build a chain of directories, each holding only the next one.
+    let mut directory = Directory::default();
+    let mut root_digest = serialize_and_upload_message(
+        &directory,
+        store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    for _ in 0..TREE_DEPTH {
+        directory = Directory {
+            directories: vec![DirectoryNode {
+                name: "nested".to_string(),
+                digest: Some(root_digest.into()),
+            }],
+            ..Default::default()
+        };
+        root_digest = serialize_and_upload_message(
+            &directory,
+            store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+    }
+
+    let make_server = |max_tree_depth, max_tree_nodes| {
+        CasServer::new(
+            &hashmap! {
+                INSTANCE_NAME.to_string() => CasStoreConfig {
+                    cas_store: "main_cas".to_string(),
+                    max_tree_depth,
+                    max_tree_nodes,
+                    ..Default::default()
+                }
+            },
+            &store_manager,
+        )
+    };
+
+    let response = get_tree_page(
+        &make_server(TREE_DEPTH, TREE_DEPTH + 1)?,
+        root_digest,
+        0,
+        String::new(),
+    )
+    .await?;
+    assert_eq!(response.directories.len(), TREE_DEPTH + 1);
+
+    for (max_tree_depth, max_tree_nodes) in [(TREE_DEPTH - 1, 0), (0, TREE_DEPTH)] {
+        let result = make_server(max_tree_depth, max_tree_nodes)?
             .get_tree(Request::new(GetTreeRequest {
                 instance_name: INSTANCE_NAME.to_string(),
-                page_size: 2,
-                page_token: format!("{}", sub_directory_digest_infos[3]),
-                root_digest: Some(root_directory_digest_info.into()),
+                page_size: 0,
+                page_token: String::new(),
+                root_digest: Some(root_digest.into()),
                 digest_function: digest_function::Value::Sha256.into(),
             }))
             .await;
-        assert_eq!(
-            raw_response
-                .unwrap()
-                .into_inner()
-                .filter_map(|x| async move { Some(x.unwrap()) })
-                .collect::<Vec<_>>()
-                .await,
-            vec![GetTreeResponse {
-                directories: vec![sub_directories[3].clone(), sub_directories[4].clone()],
-                next_page_token: String::new(),
-            }]
-        );
+        let Err(status) = result else {
+            panic!(
+                "Expected max_tree_depth {max_tree_depth} and max_tree_nodes {max_tree_nodes} to reject the tree"
+            );
+        };
+        assert_eq!(status.code(), Code::ResourceExhausted, "{status:?}");
+
+        // Paging through the tree must not reset the limits.
+        let cas_server = make_server(max_tree_depth, max_tree_nodes)?;
+        let mut page_token = String::new();
+        let status = loop {
+            let result = cas_server
+                .get_tree(Request::new(GetTreeRequest {
+                    instance_name: INSTANCE_NAME.to_string(),
+                    page_size: 1,
+                    page_token,
+                    root_digest: Some(root_digest.into()),
+                    digest_function: digest_function::Value::Sha256.into(),
+                }))
+                .await;
+            let page = match result {
+                Ok(response) => response.into_inner().next().await.unwrap()?,
+                Err(status) => break status,
+            };
+            assert!(
+                !page.next_page_token.is_empty(),
+                "Expected max_tree_depth {max_tree_depth} and max_tree_nodes {max_tree_nodes} to reject the paged tree"
+            );
+            page_token = page.next_page_token;
+        };
+        assert_eq!(status.code(), Code::ResourceExhausted, "{status:?}");
+    }
+
+    // Limits are counted by the server while walking from the root, so a
+    // token pointing far into the tree does not skip them.
+    let mut page_token = String::new();
+    for _ in 0..TREE_DEPTH / 2 {
+        page_token = get_tree_page(&make_server(0, 0)?, root_digest, 1, page_token)
+            .await?
+            .next_page_token;
     }
+    let result = make_server(TREE_DEPTH / 4, TREE_DEPTH / 4)?
+        .get_tree(Request::new(GetTreeRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            page_size: 1,
+            page_token,
+            root_digest: Some(root_digest.into()),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await;
+    let Err(status) = result else {
+        panic!("Expected a page_token from deep in the tree not to skip the limits");
+    };
+    assert_eq!(status.code(), Code::ResourceExhausted, "{status:?}");
 
     Ok(())
 }
@@ -665,3 +900,953 @@ async fn batch_update_blobs_two_items_existence_with_third_missing(
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn batch_read_blobs_rejects_oversized_blob() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server_with_limits(&store_manager, 0, 10)?;
+
+    // The blob does not exist in the store, so if the request was not
+    // rejected up front we would get a NotFound status for it instead.
+    let raw_response = cas_server
+        .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            digests: vec![Digest {
+                hash: HASH1.to_string(),
+                size_bytes: 11,
+            }],
+            acceptable_compressors: vec![compressor::Value::Identity.into()],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await;
+    assert_eq!(raw_response.unwrap_err().code(), Code::ResourceExhausted);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_read_blobs_rejects_oversized_batch() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "12345";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server_with_limits(&store_manager, 9, 0)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+    store
+        .update_oneshot(DigestInfo::try_new(HASH1, VALUE.len())?, VALUE.into())
+        .await?;
+    store
+        .update_oneshot(DigestInfo::try_new(HASH2, VALUE.len())?, VALUE.into())
+        .await?;
+
+    let make_request = |digests: Vec<Digest>| BatchReadBlobsRequest {
+        instance_name: INSTANCE_NAME.to_string(),
+        digests,
+        acceptable_compressors: vec![compressor::Value::Identity.into()],
+        digest_function: digest_function::Value::Sha256.into(),
+    };
+    let digest1 = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: VALUE.len() as i64,
+    };
+    let digest2 = Digest {
+        hash: HASH2.to_string(),
+        size_bytes: VALUE.len() as i64,
+    };
+
+    // Each blob fits on its own, but together they are over the limit.
+    let raw_response = cas_server
+        .batch_read_blobs(Request::new(make_request(vec![digest1.clone(), digest2])))
+        .await;
+    assert_eq!(raw_response.unwrap_err().code(), Code::ResourceExhausted);
+
+    let response = cas_server
+        .batch_read_blobs(Request::new(make_request(vec![digest1])))
+        .await?
+        .into_inner();
+    assert_eq!(response.responses.len(), 1);
+    assert_eq!(response.responses[0].data, VALUE);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_read_blobs_compresses_with_acceptable_compressor(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    store_manager
+        .get_store("main_cas")
+        .unwrap()
+        .update_oneshot(DigestInfo::try_new(HASH1, VALUE.len())?, VALUE.into())
+        .await?;
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: VALUE.len() as i64,
+    };
+    let make_request = |acceptable_compressors: Vec<compressor::Value>| BatchReadBlobsRequest {
+        instance_name: INSTANCE_NAME.to_string(),
+        digests: vec![digest.clone()],
+        acceptable_compressors: acceptable_compressors.into_iter().map(Into::into).collect(),
+        digest_function: digest_function::Value::Sha256.into(),
+    };
+
+    {
+        // Client accepts zstd.
+        let response = cas_server
+            .batch_read_blobs(Request::new(make_request(vec![
+                compressor::Value::Identity,
+                compressor::Value::Zstd,
+            ])))
+            .await?
+            .into_inner();
+        assert_eq!(response.responses.len(), 1);
+        let blob_response = &response.responses[0];
+        assert_eq!(blob_response.compressor, compressor::Value::Zstd as i32);
+        assert_eq!(blob_response.digest, Some(digest.clone()));
+        assert_ne!(blob_response.data, VALUE);
+        assert_eq!(
+            zstd::bulk::decompress(&blob_response.data, VALUE.len())?,
+            VALUE.as_bytes()
+        );
+    }
+    {
+        // Client only accepts identity.
+        let response = cas_server
+            .batch_read_blobs(Request::new(make_request(vec![
+                compressor::Value::Identity,
+            ])))
+            .await?
+            .into_inner();
+        assert_eq!(response.responses.len(), 1);
+        assert_eq!(
+            response.responses[0].compressor,
+            compressor::Value::Identity as i32
+        );
+        assert_eq!(response.responses[0].data, VALUE);
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_update_blobs_rejects_oversized_requests() -> Result<(), Box<dyn std::error::Error>> {
+    const VALUE1: &str = "1234";
+    const VALUE2: &str = "5678";
+
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server_with_limits(&store_manager, 6, 5)?;
+    let store = store_manager.get_store("main_cas").unwrap();
+
+    let make_request =
+        |hash: &str, value: &'static str, size_bytes: i64| batch_update_blobs_request::Request {
+            digest: Some(Digest {
+                hash: hash.to_string(),
+                size_bytes,
+            }),
+            data: value.into(),
+            compressor: compressor::Value::Identity.into(),
+        };
+
+    {
+        // The digest claims a size over `max_blob_size`.
+        let raw_response = cas_server
+            .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+                instance_name: INSTANCE_NAME.to_string(),
+                requests: vec![make_request(HASH1, VALUE1, 6)],
+                digest_function: digest_function::Value::Sha256.into(),
+            }))
+            .await;
+        assert_eq!(raw_response.unwrap_err().code(), Code::ResourceExhausted);
+    }
+    {
+        // The sum of the digests is over `max_bytes_per_batch`.
+        let raw_response = cas_server
+            .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+                instance_name: INSTANCE_NAME.to_string(),
+                requests: vec![
+                    make_request(HASH1, VALUE1, VALUE1.len() as i64),
+                    make_request(HASH2, VALUE2, VALUE2.len() as i64),
+                ],
+                digest_function: digest_function::Value::Sha256.into(),
+            }))
+            .await;
+        assert_eq!(raw_response.unwrap_err().code(), Code::ResourceExhausted);
+    }
+    // Nothing should have been written to the store.
+    assert_eq!(
+        store.has(DigestInfo::try_new(HASH1, VALUE1.len())?).await?,
+        None
+    );
+    assert_eq!(
+        store.has(DigestInfo::try_new(HASH2, VALUE2.len())?).await?,
+        None
+    );
+    Ok(())
+}
+
+const SHA256_INSTANCE_NAME: &str = "sha256_instance_name";
+const BLAKE3_INSTANCE_NAME: &str = "blake3_instance_name";
+const SHA512_INSTANCE_NAME: &str = "sha512_instance_name";
+
+/// Creates a server with one instance per digest function. All instances
+/// share a store that verifies the hash of uploaded data.
+async fn make_cas_server_with_digest_functions() -> Result<CasServer, Error> {
+    let store_manager = Arc::new(StoreManager::new());
+    store_manager.add_store(
+        "verify_cas",
+        store_factory(
+            &StoreSpec::verify(Box::new(VerifySpec {
+                backend: StoreSpec::memory(MemorySpec::default()),
+                verify_size: true,
+                verify_hash: true,
+            })),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    CasServer::new(
+        &hashmap! {
+            SHA256_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "verify_cas".to_string(),
+                digest_function: Some(ConfigDigestHashFunction::sha256),
+                ..Default::default()
+            },
+            BLAKE3_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "verify_cas".to_string(),
+                digest_function: Some(ConfigDigestHashFunction::blake3),
+                ..Default::default()
+            },
+            SHA512_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "verify_cas".to_string(),
+                digest_function: Some(ConfigDigestHashFunction::sha512),
+                ..Default::default()
+            },
+        },
+        &store_manager,
+    )
+}
+
+#[nativelink_test]
+async fn batch_update_blobs_uses_instance_digest_function() -> Result<(), Box<dyn std::error::Error>>
+{
+    const VALUE: &str = "123";
+    /// This value is sha256("123").
+    const SHA256_HASH: &str = "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3";
+    /// This value is blake3("123").
+    const BLAKE3_HASH: &str = "b3d4f8803f7e24b8f389b072e75477cdbcfbe074080fb5e500e53e26e054158e";
+    /// This value is sha512("123").
+    const SHA512_HASH: &str = concat!(
+        "3c9909afec25354d551dae21590bb26e38d53f2173b8d3dc3eee4c047e7ab1c1",
+        "eb8b85103e3be7ba613b31bb5c9c36214dc9f14a42fd7a2fdb84856bca5c44c2",
+    );
+
+    let cas_server = make_cas_server_with_digest_functions().await?;
+
+    let update_status_code = |instance_name: &str, hash: &str| {
+        let request = BatchUpdateBlobsRequest {
+            instance_name: instance_name.to_string(),
+            requests: vec![batch_update_blobs_request::Request {
+                digest: Some(Digest {
+                    hash: hash.to_string(),
+                    size_bytes: VALUE.len() as i64,
+                }),
+                data: VALUE.into(),
+                compressor: compressor::Value::Identity.into(),
+            }],
+            // Not set, so the instance's digest function is used.
+            digest_function: 0,
+        };
+        let cas_server = &cas_server;
+        async move {
+            let response = cas_server
+                .batch_update_blobs(Request::new(request))
+                .await?
+                .into_inner();
+            Ok::<_, tonic::Status>(response.responses[0].status.as_ref().unwrap().code)
+        }
+    };
+
+    // Each instance verifies uploads with its own digest function.
+    assert_eq!(
+        update_status_code(SHA256_INSTANCE_NAME, SHA256_HASH).await?,
+        Code::Ok as i32
+    );
+    assert_eq!(
+        update_status_code(BLAKE3_INSTANCE_NAME, BLAKE3_HASH).await?,
+        Code::Ok as i32
+    );
+    assert_eq!(
+        update_status_code(SHA512_INSTANCE_NAME, SHA512_HASH).await?,
+        Code::Ok as i32
+    );
+    assert_ne!(
+        update_status_code(SHA256_INSTANCE_NAME, BLAKE3_HASH).await?,
+        Code::Ok as i32
+    );
+    assert_ne!(
+        update_status_code(BLAKE3_INSTANCE_NAME, SHA256_HASH).await?,
+        Code::Ok as i32
+    );
+    assert_ne!(
+        update_status_code(SHA512_INSTANCE_NAME, SHA256_HASH).await?,
+        Code::Ok as i32
+    );
+
+    // The 512 bit hash is stored and read back as is.
+    let read_response = cas_server
+        .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: SHA512_INSTANCE_NAME.to_string(),
+            digests: vec![Digest {
+                hash: SHA512_HASH.to_string(),
+                size_bytes: VALUE.len() as i64,
+            }],
+            acceptable_compressors: vec![compressor::Value::Identity.into()],
+            digest_function: digest_function::Value::Sha512.into(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(read_response.responses[0].data, VALUE.as_bytes());
+    assert_eq!(
+        read_response.responses[0].digest.as_ref().unwrap().hash,
+        SHA512_HASH
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn request_with_mismatched_digest_function_is_rejected(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cas_server = make_cas_server_with_digest_functions().await?;
+
+    let find_missing_blobs = |digest_function: digest_function::Value| {
+        cas_server.find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: BLAKE3_INSTANCE_NAME.to_string(),
+            blob_digests: vec![Digest {
+                hash: HASH1.to_string(),
+                size_bytes: 0,
+            }],
+            digest_function: digest_function.into(),
+        }))
+    };
+
+    assert!(find_missing_blobs(digest_function::Value::Blake3)
+        .await
+        .is_ok());
+    let error = find_missing_blobs(digest_function::Value::Sha256)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Code::InvalidArgument);
+    assert!(
+        error
+            .message()
+            .contains("Digest function SHA256 does not match the digest function BLAKE3"),
+        "Unexpected error: {error:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn find_missing_blobs_rejects_wrong_length_hash() -> Result<(), Box<dyn std::error::Error>> {
+    /// Valid hex, but the length of a sha1 hash.
+    const SHORT_HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+    /// Valid hex, but the length of a sha512 hash.
+    const LONG_HASH: &str = concat!(
+        "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+    );
+
+    let cas_server = make_cas_server_with_digest_functions().await?;
+
+    for hash in [SHORT_HASH, LONG_HASH] {
+        let error = cas_server
+            .find_missing_blobs(Request::new(FindMissingBlobsRequest {
+                instance_name: SHA256_INSTANCE_NAME.to_string(),
+                blob_digests: vec![Digest {
+                    hash: hash.to_string(),
+                    size_bytes: 0,
+                }],
+                digest_function: digest_function::Value::Sha256.into(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), Code::InvalidArgument, "For hash {hash}");
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn find_missing_blobs_rejects_too_many_digests() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = CasServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "main_cas".to_string(),
+                max_digests_per_find_missing: 2,
+                ..Default::default()
+            }
+        },
+        &store_manager,
+    )?;
+    let find_missing_blobs = |hashes: &[&str]| {
+        cas_server.find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            blob_digests: hashes
+                .iter()
+                .map(|hash| Digest {
+                    hash: (*hash).to_string(),
+                    size_bytes: 0,
+                })
+                .collect(),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+    };
+
+    let response = find_missing_blobs(&[HASH1, HASH2]).await?.into_inner();
+    assert_eq!(response.missing_blob_digests.len(), 2);
+
+    let error = find_missing_blobs(&[HASH1, HASH2, HASH3])
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), Code::ResourceExhausted);
+    Ok(())
+}
+
+/// Store that records the number of digests in each existence check.
+#[derive(MetricsComponent)]
+struct RecordingStore {
+    inner: Store,
+    has_call_sizes: Mutex<Vec<usize>>,
+}
+
+#[async_trait]
+impl StoreDriver for RecordingStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        digests: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.has_call_sizes.lock().push(digests.len());
+        self.inner.has_with_results(digests, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(RecordingStore);
+
+#[nativelink_test]
+async fn find_missing_blobs_checks_large_requests_in_chunks(
+) -> Result<(), Box<dyn std::error::Error>> {
+    /// Must be kept in sync with the chunk size used by the `CasServer`.
+    const CHUNK_SIZE: usize = 10_000;
+    const DIGEST_COUNT: usize = 2 * CHUNK_SIZE + 5;
+
+    let recording_store = Arc::new(RecordingStore {
+        inner: Store::new(MemoryStore::new(&MemorySpec::default())),
+        has_call_sizes: Mutex::new(Vec::new()),
+    });
+    let store_manager = StoreManager::new();
+    store_manager.add_store("main_cas", Store::new(recording_store.clone()));
+    let cas_server = make_cas_server(&store_manager)?;
+
+    // Only the last digest exists in the store.
+    let blob_digests: Vec<Digest> = (0..DIGEST_COUNT)
+        .map(|i| Digest {
+            hash: format!("{i:064x}"),
+            size_bytes: 0,
+        })
+        .collect();
+    let last_digest = DigestInfo::try_from(blob_digests[DIGEST_COUNT - 1].clone())?;
+    recording_store
+        .inner
+        .update_oneshot(last_digest, "".into())
+        .await?;
+
+    let response = cas_server
+        .find_missing_blobs(Request::new(FindMissingBlobsRequest {
+            instance_name: INSTANCE_NAME.to_string(),
+            blob_digests: blob_digests.clone(),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+    assert_eq!(
+        response.missing_blob_digests,
+        &blob_digests[..DIGEST_COUNT - 1]
+    );
+    assert_eq!(
+        *recording_store.has_call_sizes.lock(),
+        vec![CHUNK_SIZE, CHUNK_SIZE, 5]
+    );
+    Ok(())
+}
+
+const SPILL_INSTANCE_NAME: &str = "spill_instance_name";
+
+/// Creates a server with two instances sharing a filesystem store. Batch
+/// updates to `SPILL_INSTANCE_NAME` are spilled to the returned directory,
+/// while updates to `INSTANCE_NAME` are uploaded from memory.
+async fn make_cas_server_with_filesystem_store(
+    test_name: &str,
+) -> Result<(CasServer, String), Error> {
+    let root = format!(
+        "{}/cas_server_test/{}/{test_name}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        std::process::id(),
+    );
+    let spill_path = format!("{root}/spill");
+    let store_manager = Arc::new(StoreManager::new());
+    store_manager.add_store(
+        "filesystem_cas",
+        store_factory(
+            &StoreSpec::filesystem(FilesystemSpec {
+                content_path: format!("{root}/content"),
+                temp_path: format!("{root}/temp"),
+                ..Default::default()
+            }),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    let cas_server = CasServer::new(
+        &hashmap! {
+            INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "filesystem_cas".to_string(),
+                ..Default::default()
+            },
+            SPILL_INSTANCE_NAME.to_string() => CasStoreConfig {
+                cas_store: "filesystem_cas".to_string(),
+                batch_update_temp_path: Some(spill_path.clone()),
+                batch_update_min_file_size: 2,
+                ..Default::default()
+            },
+        },
+        &store_manager,
+    )?;
+    Ok((cas_server, spill_path))
+}
+
+async fn batch_update(
+    cas_server: &CasServer,
+    instance_name: &str,
+    blobs: &[(Digest, Vec<u8>)],
+) -> Result<Vec<i32>, tonic::Status> {
+    let response = cas_server
+        .batch_update_blobs(Request::new(BatchUpdateBlobsRequest {
+            instance_name: instance_name.to_string(),
+            requests: blobs
+                .iter()
+                .map(|(digest, data)| batch_update_blobs_request::Request {
+                    digest: Some(digest.clone()),
+                    data: data.clone().into(),
+                    compressor: compressor::Value::Identity.into(),
+                })
+                .collect(),
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+    Ok(response
+        .responses
+        .into_iter()
+        .map(|response| response.status.unwrap_or_default().code)
+        .collect())
+}
+
+async fn batch_read(
+    cas_server: &CasServer,
+    instance_name: &str,
+    digests: Vec<Digest>,
+) -> Result<Vec<Vec<u8>>, tonic::Status> {
+    let mut response = cas_server
+        .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+            instance_name: instance_name.to_string(),
+            digests: digests.clone(),
+            acceptable_compressors: vec![compressor::Value::Identity.into()],
+            digest_function: digest_function::Value::Sha256.into(),
+        }))
+        .await?
+        .into_inner();
+    // Responses are sent in the order the reads complete, so return them in
+    // the order they were requested.
+    response.responses.sort_by_key(|response| {
+        digests
+            .iter()
+            .position(|digest| Some(digest) == response.digest.as_ref())
+    });
+    Ok(response
+        .responses
+        .into_iter()
+        .map(|response| response.data.to_vec())
+        .collect())
+}
+
+#[nativelink_test]
+async fn batch_update_blobs_file_and_memory_paths_store_same_bytes(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (cas_server, spill_path) = make_cas_server_with_filesystem_store("same_bytes").await?;
+
+    // The one byte blob is below `batch_update_min_file_size`, so it is
+    // uploaded from memory even on the spilling instance.
+    let memory_blobs = vec![
+        (digest_for(HASH1, 1), b"1".to_vec()),
+        (digest_for(HASH2, 5), b"hello".to_vec()),
+    ];
+    let spill_blobs = vec![
+        (digest_for(HASH3, 1), b"1".to_vec()),
+        (digest_for(&format!("{:064x}", 4), 5), b"hello".to_vec()),
+    ];
+    assert_eq!(
+        batch_update(&cas_server, INSTANCE_NAME, &memory_blobs).await?,
+        vec![0, 0]
+    );
+    assert_eq!(
+        batch_update(&cas_server, SPILL_INSTANCE_NAME, &spill_blobs).await?,
+        vec![0, 0]
+    );
+
+    // Both instances read from the same store, so read each blob through the
+    // other instance to make sure the paths are interchangeable.
+    let memory_data = batch_read(
+        &cas_server,
+        SPILL_INSTANCE_NAME,
+        memory_blobs
+            .iter()
+            .map(|(digest, _)| digest.clone())
+            .collect(),
+    )
+    .await?;
+    let spill_data = batch_read(
+        &cas_server,
+        INSTANCE_NAME,
+        spill_blobs
+            .iter()
+            .map(|(digest, _)| digest.clone())
+            .collect(),
+    )
+    .await?;
+    assert_eq!(memory_data, vec![b"1".to_vec(), b"hello".to_vec()]);
+    assert_eq!(spill_data, memory_data);
+
+    // The spilled file was moved into the store, not left behind.
+    assert_eq!(std::fs::read_dir(&spill_path)?.count(), 0);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_update_blobs_large_batch_does_not_accumulate_spill_files(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const BLOB_COUNT: usize = 64;
+    const BLOB_SIZE: usize = 256 * 1024;
+
+    let (cas_server, spill_path) = make_cas_server_with_filesystem_store("large_batch").await?;
+
+    let blobs: Vec<(Digest, Vec<u8>)> = (0..BLOB_COUNT)
+        .map(|i| {
+            let data = vec![u8::try_from(i % 256).unwrap(); BLOB_SIZE];
+            (digest_for(&format!("{:064x}", i + 1), BLOB_SIZE), data)
+        })
+        .collect();
+    assert_eq!(
+        batch_update(&cas_server, SPILL_INSTANCE_NAME, &blobs).await?,
+        vec![0; BLOB_COUNT]
+    );
+    // Every spilled file was moved into the store, none are left behind.
+    assert_eq!(std::fs::read_dir(&spill_path)?.count(), 0);
+
+    let data = batch_read(
+        &cas_server,
+        INSTANCE_NAME,
+        blobs.iter().map(|(digest, _)| digest.clone()).collect(),
+    )
+    .await?;
+    assert_eq!(data.len(), BLOB_COUNT);
+    for ((_, expected), actual) in blobs.iter().zip(data) {
+        assert!(expected == &actual, "Blob data differs after spilling");
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn instance_name_alias_routes_to_aliased_instance() -> Result<(), Box<dyn std::error::Error>>
+{
+    const VALUE: &[u8] = b"1";
+
+    let store_manager = make_store_manager().await?;
+    store_manager.add_store(
+        "other_cas",
+        store_factory(
+            &StoreSpec::memory(MemorySpec::default()),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    let cas_server = CasServer::new(
+        &hashmap! {
+            "bar".to_string() => CasStoreConfig {
+                cas_store: "main_cas".to_string(),
+                ..Default::default()
+            },
+            "baz".to_string() => CasStoreConfig {
+                cas_store: "other_cas".to_string(),
+                ..Default::default()
+            },
+        },
+        &store_manager,
+    )?
+    .with_instance_name_aliases(InstanceNameAliases::new(hashmap! {
+        "foo".to_string() => "bar".to_string(),
+        "unknown_alias".to_string() => "does_not_exist".to_string(),
+    }));
+
+    let digest = digest_for(HASH1, VALUE.len());
+    assert_eq!(
+        batch_update(&cas_server, "foo", &[(digest.clone(), VALUE.to_vec())]).await?,
+        vec![Code::Ok as i32]
+    );
+
+    // The blob was written to the store of "bar" and not of "baz".
+    let digest_info = DigestInfo::try_new(HASH1, VALUE.len())?;
+    assert_eq!(
+        store_manager
+            .get_store("main_cas")
+            .unwrap()
+            .has(digest_info)
+            .await?,
+        Some(VALUE.len() as u64)
+    );
+    assert_eq!(
+        store_manager
+            .get_store("other_cas")
+            .unwrap()
+            .has(digest_info)
+            .await?,
+        None
+    );
+    assert_eq!(
+        batch_read(&cas_server, "bar", vec![digest.clone()]).await?,
+        vec![VALUE.to_vec()]
+    );
+
+    let err = batch_read(&cas_server, "unknown_alias", vec![digest])
+        .await
+        .expect_err("Expected alias of an unknown instance to fail");
+    assert!(
+        err.message().contains("does_not_exist"),
+        "Expected the aliased instance in the error, got {err:?}"
+    );
+    Ok(())
+}
+
+fn digest_for(hash: &str, size_bytes: usize) -> Digest {
+    Digest {
+        hash: hash.to_string(),
+        size_bytes: size_bytes as i64,
+    }
+}
+
+/// Collects everything written by the JSON log layer.
+#[derive(Clone, Default)]
+struct SharedLogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedLogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[nativelink_test]
+async fn json_logs_include_request_span_fields() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+
+    let log_buffer = SharedLogBuffer::default();
+    let log_writer = log_buffer.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(json_log_layer(move || log_writer.clone()));
+    {
+        // Tests run on a single thread, so the subscriber sees the whole request.
+        let _guard = tracing::subscriber::set_default(subscriber);
+        cas_server
+            .find_missing_blobs(Request::new(FindMissingBlobsRequest {
+                instance_name: INSTANCE_NAME.to_string(),
+                blob_digests: vec![Digest {
+                    hash: HASH1.to_string(),
+                    size_bytes: 0,
+                }],
+                digest_function: digest_function::Value::Sha256.into(),
+            }))
+            .await?;
+    }
+
+    let logs = String::from_utf8(log_buffer.0.lock().clone())?;
+    let request_spans = logs
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter_map(|line| line.get("span").cloned())
+        .filter(|span| span["name"] == "find_missing_blobs")
+        .collect::<Vec<_>>();
+    assert!(
+        !request_spans.is_empty(),
+        "Expected a JSON log line inside the request span, got: {logs}"
+    );
+    for span in request_spans {
+        assert_eq!(span["instance_name"], INSTANCE_NAME);
+        assert!(span.get("request").is_some(), "Expected request in {span}");
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn batch_read_blobs_not_found_sends_terse_message_and_logs_context(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let cas_server = make_cas_server(&store_manager)?;
+    let digest = Digest {
+        hash: HASH1.to_string(),
+        size_bytes: 3,
+    };
+
+    let log_buffer = SharedLogBuffer::default();
+    let log_writer = log_buffer.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(json_log_layer(move || log_writer.clone()));
+    let response = {
+        let _guard = tracing::subscriber::set_default(subscriber);
+        cas_server
+            .batch_read_blobs(Request::new(BatchReadBlobsRequest {
+                instance_name: INSTANCE_NAME.to_string(),
+                digests: vec![digest.clone()],
+                acceptable_compressors: vec![compressor::Value::Identity.into()],
+                digest_function: digest_function::Value::Sha256.into(),
+            }))
+            .await?
+            .into_inner()
+    };
+
+    // The client only sees where the error came from.
+    let status = response.responses[0].status.clone().unwrap_or_default();
+    assert_eq!(status.code, Code::NotFound as i32);
+    assert_eq!(
+        status.message,
+        format!(
+            "Key {:?} not found",
+            StoreKey::from(DigestInfo::try_from(digest)?)
+        )
+    );
+
+    // The context added on the way up is still logged.
+    let logs = String::from_utf8(log_buffer.0.lock().clone())?;
+    assert!(
+        logs.contains("Error reading from store"),
+        "Expected the full error in the logs, got: {logs}"
+    );
+    Ok(())
+}
+
+async fn cas_server_and_client(
+    cas_server: CasServer,
+) -> (
+    JoinHandleDropGuard<()>,
+    ContentAddressableStorageClient<Channel>,
+) {
+    let (server_spawn, channel) =
+        server_and_client_stub(Routes::new(cas_server.into_service())).await;
+    // The client accepts messages of any size, so only the server limits apply.
+    let client = ContentAddressableStorageClient::new(channel)
+        .max_decoding_message_size(usize::MAX)
+        .max_encoding_message_size(usize::MAX);
+    (server_spawn, client)
+}
+
+#[nativelink_test]
+async fn batch_update_over_default_message_size_test() -> Result<(), Box<dyn std::error::Error>> {
+    // Just over the 4 MiB tonic decodes by default.
+    const BLOB_SIZE: usize = 4 * 1024 * 1024 + 1;
+    const MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+    let make_request = || BatchUpdateBlobsRequest {
+        instance_name: INSTANCE_NAME.to_string(),
+        requests: vec![batch_update_blobs_request::Request {
+            digest: Some(Digest {
+                hash: HASH1.to_string(),
+                size_bytes: BLOB_SIZE as i64,
+            }),
+            data: vec![0u8; BLOB_SIZE].into(),
+            compressor: compressor::Value::Identity.into(),
+        }],
+        digest_function: digest_function::Value::Sha256.into(),
+    };
+
+    {
+        // The tonic default still applies if no limit is configured.
+        let store_manager = make_store_manager().await?;
+        let (_server_spawn, mut client) =
+            cas_server_and_client(make_cas_server(&store_manager)?).await;
+        let error = client.batch_update_blobs(make_request()).await.unwrap_err();
+        assert_eq!(error.code(), Code::OutOfRange, "{error:?}");
+    }
+    {
+        let store_manager = make_store_manager().await?;
+        let cas_server = make_cas_server(&store_manager)?
+            .with_max_message_sizes(MAX_MESSAGE_SIZE, MAX_MESSAGE_SIZE);
+        let (_server_spawn, mut client) = cas_server_and_client(cas_server).await;
+        let response = client
+            .batch_update_blobs(make_request())
+            .await?
+            .into_inner();
+        assert_eq!(response.responses.len(), 1);
+        assert_eq!(
+            response.responses[0]
+                .status
+                .clone()
+                .unwrap_or_default()
+                .code,
+            Code::Ok as i32
+        );
+        let store = store_manager.get_store("main_cas").unwrap();
+        assert_eq!(
+            store.has(DigestInfo::try_new(HASH1, BLOB_SIZE)?).await?,
+            Some(BLOB_SIZE as u64)
+        );
+    }
+    Ok(())
+}
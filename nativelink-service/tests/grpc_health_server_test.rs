@@ -0,0 +1,145 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use nativelink_config::cas_server::ServicesConfig;
+use nativelink_config::stores::{MemorySpec, NoopSpec, StoreSpec};
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_service::grpc_health_server::GrpcHealthServer;
+use nativelink_store::default_store_factory::store_factory;
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::task::JoinHandleDropGuard;
+use pretty_assertions::assert_eq;
+use tonic::service::Routes;
+use tonic::transport::Channel;
+use tonic::Request;
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use utils::server_utils::server_and_client_stub;
+
+mod utils {
+    pub(crate) mod server_utils;
+}
+
+const CAS_SERVICE_NAME: &str = "build.bazel.remote.execution.v2.ContentAddressableStorage";
+const AC_SERVICE_NAME: &str = "build.bazel.remote.execution.v2.ActionCache";
+const CAPABILITIES_SERVICE_NAME: &str = "build.bazel.remote.execution.v2.Capabilities";
+
+async fn make_store_manager() -> Result<Arc<StoreManager>, Error> {
+    let store_manager = Arc::new(StoreManager::new());
+    store_manager.add_store(
+        "main_cas",
+        store_factory(
+            &StoreSpec::memory(MemorySpec::default()),
+            &store_manager,
+            None,
+        )
+        .await?,
+    );
+    // A noop store never has the data it was given, so its health check fails.
+    store_manager.add_store(
+        "broken_ac",
+        store_factory(&StoreSpec::noop(NoopSpec::default()), &store_manager, None).await?,
+    );
+    Ok(store_manager)
+}
+
+async fn grpc_health_server_and_client(
+    services: &ServicesConfig,
+    store_manager: &StoreManager,
+) -> Result<
+    (
+        GrpcHealthServer,
+        JoinHandleDropGuard<()>,
+        HealthClient<Channel>,
+    ),
+    Error,
+> {
+    let (grpc_health_server, health_service) = GrpcHealthServer::new(services, store_manager)?;
+    let (server_spawn, channel) = server_and_client_stub(Routes::new(health_service)).await;
+    Ok((grpc_health_server, server_spawn, HealthClient::new(channel)))
+}
+
+async fn serving_status(client: &mut HealthClient<Channel>, service_name: &str) -> ServingStatus {
+    let response = client
+        .check(Request::new(HealthCheckRequest {
+            service: service_name.to_string(),
+        }))
+        .await
+        .expect("Expected service to be registered");
+    response.into_inner().status()
+}
+
+#[nativelink_test]
+async fn services_are_reported_by_store_health() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let services: ServicesConfig = serde_json::from_value(serde_json::json!({
+        "cas": { "main": { "cas_store": "main_cas" } },
+        "ac": { "main": { "ac_store": "broken_ac" } },
+        "capabilities": { "main": {} },
+        "experimental_grpc_health": {},
+    }))?;
+
+    let (mut grpc_health_server, _server_spawn, mut client) =
+        grpc_health_server_and_client(&services, &store_manager).await?;
+    grpc_health_server.update_statuses().await;
+
+    assert_eq!(
+        serving_status(&mut client, CAS_SERVICE_NAME).await,
+        ServingStatus::Serving
+    );
+    assert_eq!(
+        serving_status(&mut client, AC_SERVICE_NAME).await,
+        ServingStatus::NotServing
+    );
+    // Services that don't use stores are always serving.
+    assert_eq!(
+        serving_status(&mut client, CAPABILITIES_SERVICE_NAME).await,
+        ServingStatus::Serving
+    );
+    // The server as a whole is not serving if any of its services is not.
+    assert_eq!(
+        serving_status(&mut client, "").await,
+        ServingStatus::NotServing
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn unconfigured_services_are_not_registered() -> Result<(), Box<dyn std::error::Error>> {
+    let store_manager = make_store_manager().await?;
+    let services: ServicesConfig = serde_json::from_value(serde_json::json!({
+        "cas": { "main": { "cas_store": "main_cas" } },
+    }))?;
+
+    let (mut grpc_health_server, _server_spawn, mut client) =
+        grpc_health_server_and_client(&services, &store_manager).await?;
+    grpc_health_server.update_statuses().await;
+
+    assert_eq!(
+        serving_status(&mut client, "").await,
+        ServingStatus::Serving
+    );
+    let status = client
+        .check(Request::new(HealthCheckRequest {
+            service: AC_SERVICE_NAME.to_string(),
+        }))
+        .await
+        .expect_err("Expected unconfigured service to be unknown");
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::cas_server::ServicesConfig;
+use nativelink_macro::nativelink_test;
+use nativelink_service::grpc_reflection_server::GrpcReflectionServer;
+use pretty_assertions::assert_eq;
+use tonic::service::Routes;
+use tonic::transport::Channel;
+use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+use tonic_reflection::pb::v1::ServerReflectionRequest;
+use utils::server_utils::server_and_client_stub;
+
+mod utils {
+    pub(crate) mod server_utils;
+}
+
+const CAS_SERVICE_NAME: &str = "build.bazel.remote.execution.v2.ContentAddressableStorage";
+
+async fn reflect(
+    client: &mut ServerReflectionClient<Channel>,
+    message_request: MessageRequest,
+) -> Result<MessageResponse, Box<dyn std::error::Error>> {
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(message_request),
+    };
+    let mut responses = client
+        .server_reflection_info(tokio_stream::once(request))
+        .await?
+        .into_inner();
+    let response = responses
+        .message()
+        .await?
+        .ok_or("Expected a reflection response")?;
+    Ok(response
+        .message_response
+        .ok_or("Expected response to have a message")?)
+}
+
+#[nativelink_test]
+async fn lists_configured_services_and_their_protos() -> Result<(), Box<dyn std::error::Error>> {
+    let services: ServicesConfig = serde_json::from_value(serde_json::json!({
+        "cas": { "main": { "cas_store": "main_cas" } },
+        "experimental_grpc_health": {},
+        "experimental_grpc_reflection": {},
+    }))?;
+    let grpc_reflection_server = GrpcReflectionServer::new(&services);
+    let (_server_spawn, channel) =
+        server_and_client_stub(Routes::new(grpc_reflection_server.v1_service()?)).await;
+    let mut client = ServerReflectionClient::new(channel);
+
+    let MessageResponse::ListServicesResponse(list_services_response) =
+        reflect(&mut client, MessageRequest::ListServices(String::new())).await?
+    else {
+        panic!("Expected a list services response");
+    };
+    let mut service_names: Vec<_> = list_services_response
+        .service
+        .into_iter()
+        .map(|service| service.name)
+        .collect();
+    service_names.sort();
+    assert_eq!(
+        service_names,
+        vec![
+            CAS_SERVICE_NAME,
+            "grpc.health.v1.Health",
+            "grpc.reflection.v1.ServerReflection",
+            "grpc.reflection.v1alpha.ServerReflection",
+        ]
+    );
+
+    let MessageResponse::FileDescriptorResponse(file_descriptor_response) = reflect(
+        &mut client,
+        MessageRequest::FileContainingSymbol(CAS_SERVICE_NAME.to_string()),
+    )
+    .await?
+    else {
+        panic!("Expected a file descriptor response");
+    };
+    assert!(!file_descriptor_response.file_descriptor_proto.is_empty());
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::Future;
+use http_body_util::BodyExt;
+use hyper::Uri;
+use hyper_util::rt::TokioIo;
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use nativelink_error::Error;
+use nativelink_util::task::JoinHandleDropGuard;
+use nativelink_util::{background_spawn, spawn};
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::service::Routes;
+use tonic::transport::{Channel, Endpoint};
+use tower::service_fn;
+
+/// Serves `routes` over an in-memory connection and returns a channel
+/// connected to it. The server stops when the returned guard is dropped.
+pub(crate) async fn server_and_client_stub(routes: Routes) -> (JoinHandleDropGuard<()>, Channel) {
+    #[derive(Clone)]
+    struct Executor;
+    impl<F> hyper::rt::Executor<F> for Executor
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        fn execute(&self, fut: F) {
+            background_spawn!("executor_spawn", fut);
+        }
+    }
+
+    let (tx, rx) = unbounded_channel::<Result<DuplexStream, Error>>();
+    let mut rx = UnboundedReceiverStream::new(rx);
+
+    let server_spawn = spawn!("grpc_server", async move {
+        let http = auto::Builder::new(Executor);
+
+        let adapted_service = tower::ServiceBuilder::new()
+            .map_request(|req: hyper::Request<hyper::body::Incoming>| {
+                let (parts, body) = req.into_parts();
+                let body = body
+                    .map_err(|e| tonic::Status::internal(e.to_string()))
+                    .boxed_unsync();
+                hyper::Request::from_parts(parts, body)
+            })
+            .service(routes);
+
+        let hyper_service = TowerToHyperService::new(adapted_service);
+
+        while let Some(stream) = rx.next().await {
+            http.serve_connection_with_upgrades(
+                TokioIo::new(stream.expect("Failed to get stream")),
+                hyper_service.clone(),
+            )
+            .await
+            .expect("Connection failed");
+        }
+    });
+
+    // Note: This is a dummy address, it will not actually connect to it,
+    // instead it will be connecting via mpsc.
+    let channel = Endpoint::try_from("http://[::]:50051")
+        .unwrap()
+        .executor(Executor)
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let tx = tx.clone();
+            async move {
+                const MAX_BUFFER_SIZE: usize = 4096;
+                let (client, server) = tokio::io::duplex(MAX_BUFFER_SIZE);
+                tx.send(Ok(server)).unwrap();
+                Result::<_, Error>::Ok(TokioIo::new(client))
+            }
+        }))
+        .await
+        .unwrap();
+
+    (server_spawn, channel)
+}
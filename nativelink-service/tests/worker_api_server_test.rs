@@ -21,9 +21,10 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use nativelink_config::cas_server::WorkerApiConfig;
 use nativelink_config::schedulers::WorkerAllocationStrategy;
-use nativelink_error::{Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_metric::MetricsComponent;
+use nativelink_proto::build::bazel::remote::execution::v2::platform::Property;
 use nativelink_proto::build::bazel::remote::execution::v2::{
     ActionResult as ProtoActionResult, ExecuteResponse, ExecutedActionMetadata, LogFile,
     OutputDirectory, OutputFile, OutputSymlink,
@@ -39,19 +40,23 @@ use nativelink_scheduler::worker::ActionInfoWithProps;
 use nativelink_scheduler::worker_scheduler::WorkerScheduler;
 use nativelink_service::worker_api_server::{ConnectWorkerStream, NowFn, WorkerApiServer};
 use nativelink_util::action_messages::{
-    ActionInfo, ActionUniqueKey, ActionUniqueQualifier, OperationId, WorkerId,
+    ActionInfo, ActionStage, ActionUniqueKey, ActionUniqueQualifier, ExecutionMetadata,
+    OperationId, WorkerId,
 };
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
+use nativelink_util::json_log_layer;
 use nativelink_util::operation_state_manager::{UpdateOperationType, WorkerStateManager};
 use pretty_assertions::assert_eq;
 use tokio::join;
 use tokio::sync::{mpsc, Notify};
 use tokio_stream::StreamExt;
 use tonic::Request;
+use tracing_subscriber::layer::SubscriberExt;
 
 const BASE_NOW_S: u64 = 10;
 const BASE_WORKER_TIMEOUT_S: u64 = 100;
+const SCHEDULER_NAME: &str = "DUMMY_SCHEDULE_NAME";
 
 #[derive(Debug)]
 enum WorkerStateManagerCalls {
@@ -138,8 +143,32 @@ fn static_now_fn() -> Result<Duration, Error> {
 }
 
 async fn setup_api_server(worker_timeout: u64, now_fn: NowFn) -> Result<TestContext, Error> {
-    const SCHEDULER_NAME: &str = "DUMMY_SCHEDULE_NAME";
+    setup_api_server_with_keep_alive_interval(worker_timeout, now_fn, 0).await
+}
+
+async fn setup_api_server_with_keep_alive_interval(
+    worker_timeout: u64,
+    now_fn: NowFn,
+    keep_alive_interval: usize,
+) -> Result<TestContext, Error> {
+    setup_api_server_with_config(
+        worker_timeout,
+        now_fn,
+        &WorkerApiConfig {
+            scheduler: SCHEDULER_NAME.to_string(),
+            keep_alive_interval,
+            max_action_result_size: 0,
+            ignore_unknown_worker_properties: false,
+        },
+    )
+    .await
+}
 
+async fn setup_api_server_with_config(
+    worker_timeout: u64,
+    now_fn: NowFn,
+    config: &WorkerApiConfig,
+) -> Result<TestContext, Error> {
     const UUID_SIZE: usize = 36;
 
     let platform_property_manager = Arc::new(PlatformPropertyManager::new(HashMap::new()));
@@ -151,18 +180,13 @@ async fn setup_api_server(worker_timeout: u64, now_fn: NowFn) -> Result<TestCont
         WorkerAllocationStrategy::default(),
         tasks_or_worker_change_notify,
         worker_timeout,
+        None,
     );
 
     let mut schedulers: HashMap<String, Arc<dyn WorkerScheduler>> = HashMap::new();
     schedulers.insert(SCHEDULER_NAME.to_string(), scheduler.clone());
-    let worker_api_server = WorkerApiServer::new_with_now_fn(
-        &WorkerApiConfig {
-            scheduler: SCHEDULER_NAME.to_string(),
-        },
-        &schedulers,
-        now_fn,
-    )
-    .err_tip(|| "Error creating WorkerApiServer")?;
+    let worker_api_server = WorkerApiServer::new_with_now_fn(config, &schedulers, now_fn)
+        .err_tip(|| "Error creating WorkerApiServer")?;
 
     let supported_properties = SupportedProperties::default();
     let mut connection_worker_stream = worker_api_server
@@ -216,6 +240,79 @@ pub async fn connect_worker_adds_worker_to_scheduler_test() -> Result<(), Box<dy
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn connect_worker_with_unknown_property_is_rejected_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
+
+    let Err(status) = test_context
+        .worker_api_server
+        .connect_worker(Request::new(SupportedProperties {
+            properties: vec![Property {
+                name: "unknown_key".to_string(),
+                value: "foo".to_string(),
+            }],
+        }))
+        .await
+    else {
+        panic!("Expected worker with unknown property to be rejected");
+    };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(
+        status.message().contains("unknown_key"),
+        "Unexpected error: {status:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn connect_worker_with_unknown_property_is_accepted_when_ignored_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let test_context = setup_api_server_with_config(
+        BASE_WORKER_TIMEOUT_S,
+        Box::new(static_now_fn),
+        &WorkerApiConfig {
+            scheduler: SCHEDULER_NAME.to_string(),
+            keep_alive_interval: 0,
+            max_action_result_size: 0,
+            ignore_unknown_worker_properties: true,
+        },
+    )
+    .await?;
+
+    let mut connection_worker_stream = test_context
+        .worker_api_server
+        .connect_worker(Request::new(SupportedProperties {
+            properties: vec![Property {
+                name: "unknown_key".to_string(),
+                value: "foo".to_string(),
+            }],
+        }))
+        .await?
+        .into_inner();
+    let update = connection_worker_stream
+        .next()
+        .await
+        .err_tip(|| "Expected first message from stream")?
+        .err_tip(|| "Expected success result")?
+        .update
+        .err_tip(|| "Expected update field to be populated")?;
+    let worker_id: WorkerId = match update {
+        update_for_worker::Update::ConnectionResult(connection_result) => {
+            connection_result.worker_id.try_into()?
+        }
+        other => unreachable!("Expected ConnectionResult, got {:?}", other),
+    };
+    assert!(
+        test_context
+            .scheduler
+            .contains_worker_for_test(&worker_id)
+            .await,
+        "Expected worker to exist in worker map"
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn server_times_out_workers_test() -> Result<(), Box<dyn std::error::Error>> {
     let test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
@@ -286,6 +383,7 @@ pub async fn server_does_not_timeout_if_keep_alive_test() -> Result<(), Box<dyn
             .worker_api_server
             .keep_alive(Request::new(KeepAliveRequest {
                 worker_id: test_context.worker_id.to_string(),
+                paused: false,
             }))
             .await
             .err_tip(|| "Error sending keep alive")?;
@@ -340,6 +438,71 @@ pub async fn worker_receives_keep_alive_request_test() -> Result<(), Box<dyn std
     Ok(())
 }
 
+#[nativelink_test]
+pub async fn idle_worker_receives_keep_alive_test() -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_api_server_with_keep_alive_interval(
+        BASE_WORKER_TIMEOUT_S,
+        Box::new(static_now_fn),
+        1,
+    )
+    .await?;
+
+    // Nothing else is sent to the worker, so a keep alive must arrive after
+    // `keep_alive_interval`.
+    let update_message = tokio::time::timeout(
+        Duration::from_secs(10),
+        test_context.connection_worker_stream.next(),
+    )
+    .await
+    .map_err(|_| make_err!(Code::DeadlineExceeded, "Timed out waiting for keep alive"))?
+    .err_tip(|| "Expected next message in stream to exist")?
+    .err_tip(|| "Expected success result")?
+    .update
+    .err_tip(|| "Expected update field to be populated")?;
+    assert_eq!(update_message, update_for_worker::Update::KeepAlive(()));
+
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn missed_keep_alive_evicts_worker_and_requeues_action_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
+    let expected_operation_id = start_action_on_worker(&mut test_context).await?;
+
+    // The worker never sends a keep alive, so once `worker_timeout_s` passed
+    // it is evicted and its action handed back to the state manager.
+    let (remove_result, (operation_id, worker_id, update)) = join!(
+        test_context
+            .scheduler
+            .remove_timedout_workers(BASE_NOW_S + BASE_WORKER_TIMEOUT_S),
+        test_context.state_manager.expect_update_operation(Ok(())),
+    );
+    remove_result?;
+    assert_eq!(operation_id, expected_operation_id);
+    assert_eq!(worker_id, test_context.worker_id);
+    let UpdateOperationType::UpdateWithError(err) = update else {
+        panic!("Expected UpdateWithError, got {update:?}");
+    };
+    assert_eq!(err.code, Code::Internal, "{err:?}");
+
+    let worker_exists = test_context
+        .scheduler
+        .contains_worker_for_test(&test_context.worker_id)
+        .await;
+    assert!(!worker_exists, "Expected worker to not exist in map");
+    let update_message = test_context
+        .connection_worker_stream
+        .next()
+        .await
+        .err_tip(|| "Worker stream ended early")??
+        .update
+        .err_tip(|| "Expected update field to be populated")?;
+    assert_eq!(update_message, update_for_worker::Update::Disconnect(()));
+
+    Ok(())
+}
+
 #[nativelink_test]
 pub async fn going_away_removes_worker_test() -> Result<(), Box<dyn std::error::Error>> {
     let test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
@@ -393,6 +556,7 @@ pub async fn execution_response_success_test() -> Result<(), Box<dyn std::error:
         load_timestamp: make_system_time(0),
         insert_timestamp: make_system_time(0),
         unique_qualifier,
+        do_not_cache: false,
     });
     let expected_operation_id = OperationId::default();
 
@@ -526,3 +690,240 @@ pub async fn execution_response_success_test() -> Result<(), Box<dyn std::error:
     }
     Ok(())
 }
+
+/// Runs a new action on the test worker and returns its operation id.
+async fn start_action_on_worker(test_context: &mut TestContext) -> Result<OperationId, Error> {
+    let action_info = Arc::new(ActionInfo {
+        command_digest: DigestInfo::new([0u8; 32], 0),
+        input_root_digest: DigestInfo::new([0u8; 32], 0),
+        timeout: Duration::MAX,
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: make_system_time(0),
+        insert_timestamp: make_system_time(0),
+        unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+            instance_name: "instance_name".to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: DigestInfo::new([7u8; 32], 123),
+        }),
+        do_not_cache: false,
+    });
+    let platform_properties = test_context
+        .scheduler
+        .get_platform_property_manager()
+        .make_platform_properties(action_info.platform_properties.clone())?;
+    let operation_id = OperationId::default();
+    test_context
+        .scheduler
+        .worker_notify_run_action(
+            test_context.worker_id,
+            operation_id.clone(),
+            ActionInfoWithProps {
+                inner: action_info,
+                platform_properties,
+            },
+        )
+        .await?;
+    let update_for_worker = test_context
+        .connection_worker_stream
+        .next()
+        .await
+        .err_tip(|| "Worker stream ended early")?
+        .err_tip(|| "Expected success result")?
+        .update
+        .err_tip(|| "Expected update field to be populated")?;
+    let update_for_worker::Update::StartAction(start_execute) = update_for_worker else {
+        panic!("Expected StartAction message");
+    };
+    assert_eq!(operation_id.to_string(), start_execute.operation_id);
+    Ok(operation_id)
+}
+
+fn make_execute_response_with_output_files(output_file_count: usize) -> ExecuteResponse {
+    ExecuteResponse {
+        result: Some(ProtoActionResult {
+            output_files: (0..output_file_count)
+                .map(|i| OutputFile {
+                    path: format!("some/output/path{i}"),
+                    digest: Some(DigestInfo::new([8u8; 32], 124).into()),
+                    is_executable: false,
+                    contents: Bytes::default(),
+                    node_properties: None,
+                })
+                .collect(),
+            stdout_digest: Some(DigestInfo::new([10u8; 32], 124).into()),
+            stderr_digest: Some(DigestInfo::new([11u8; 32], 124).into()),
+            execution_metadata: Some(ExecutionMetadata::default().into()),
+            ..Default::default()
+        }),
+        status: Some(ProtoStatus::default()),
+        ..Default::default()
+    }
+}
+
+#[nativelink_test]
+pub async fn execution_response_within_max_action_result_size_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_api_server_with_config(
+        BASE_WORKER_TIMEOUT_S,
+        Box::new(static_now_fn),
+        &WorkerApiConfig {
+            scheduler: SCHEDULER_NAME.to_string(),
+            keep_alive_interval: 0,
+            max_action_result_size: 4096,
+            ignore_unknown_worker_properties: false,
+        },
+    )
+    .await?;
+    let operation_id = start_action_on_worker(&mut test_context).await?;
+
+    let execute_response = make_execute_response_with_output_files(2);
+    let result = ExecuteResult {
+        instance_name: "instance_name".to_string(),
+        worker_id: test_context.worker_id.to_string(),
+        operation_id: operation_id.to_string(),
+        result: Some(execute_result::Result::ExecuteResponse(
+            execute_response.clone(),
+        )),
+    };
+    let (execution_response_result, (_, _, client_given_update)) = join!(
+        test_context
+            .worker_api_server
+            .execution_response(Request::new(result)),
+        test_context.state_manager.expect_update_operation(Ok(())),
+    );
+    execution_response_result?;
+    assert_eq!(
+        client_given_update,
+        UpdateOperationType::UpdateWithActionStage(execute_response.try_into()?)
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+pub async fn execution_response_over_max_action_result_size_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_api_server_with_config(
+        BASE_WORKER_TIMEOUT_S,
+        Box::new(static_now_fn),
+        &WorkerApiConfig {
+            scheduler: SCHEDULER_NAME.to_string(),
+            keep_alive_interval: 0,
+            max_action_result_size: 4096,
+            ignore_unknown_worker_properties: false,
+        },
+    )
+    .await?;
+    let operation_id = start_action_on_worker(&mut test_context).await?;
+
+    let result = ExecuteResult {
+        instance_name: "instance_name".to_string(),
+        worker_id: test_context.worker_id.to_string(),
+        operation_id: operation_id.to_string(),
+        result: Some(execute_result::Result::ExecuteResponse(
+            make_execute_response_with_output_files(1000),
+        )),
+    };
+    let (execution_response_result, (update_operation_id, _, client_given_update)) = join!(
+        test_context
+            .worker_api_server
+            .execution_response(Request::new(result)),
+        test_context.state_manager.expect_update_operation(Ok(())),
+    );
+    execution_response_result?;
+    assert_eq!(update_operation_id, operation_id);
+
+    // The action completes with an error instead of the oversized result.
+    let UpdateOperationType::UpdateWithActionStage(ActionStage::Completed(action_result)) =
+        client_given_update
+    else {
+        panic!("Expected action to be completed, got {client_given_update:?}");
+    };
+    assert!(action_result.output_files.is_empty());
+    let err = action_result
+        .error
+        .expect("Expected an error in the result");
+    assert_eq!(err.code, Code::ResourceExhausted);
+    assert!(
+        err.to_string().contains("max_action_result_size"),
+        "Unexpected error: {err:?}"
+    );
+    Ok(())
+}
+
+/// Collects everything written by the JSON log layer.
+#[derive(Clone, Default)]
+struct SharedLogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedLogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[nativelink_test]
+pub async fn trace_id_is_logged_by_scheduler_and_worker_api_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_api_server(BASE_WORKER_TIMEOUT_S, Box::new(static_now_fn)).await?;
+
+    let log_buffer = SharedLogBuffer::default();
+    let log_writer = log_buffer.clone();
+    let subscriber =
+        tracing_subscriber::registry().with(json_log_layer(move || log_writer.clone()));
+    let operation_id = {
+        // Tests run on a single thread, so the subscriber sees both sides.
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let operation_id = start_action_on_worker(&mut test_context).await?;
+
+        let (execution_response_result, _) = join!(
+            test_context
+                .worker_api_server
+                .execution_response(Request::new(ExecuteResult {
+                    instance_name: "instance_name".to_string(),
+                    worker_id: test_context.worker_id.to_string(),
+                    operation_id: operation_id.to_string(),
+                    result: Some(execute_result::Result::InternalError(ProtoStatus {
+                        code: 13,
+                        message: "foo".to_string(),
+                        details: Vec::default(),
+                    })),
+                })),
+            test_context.state_manager.expect_update_operation(Ok(())),
+        );
+        execution_response_result?;
+        operation_id
+    };
+
+    let logs = String::from_utf8(log_buffer.0.lock().unwrap().clone())?;
+    let trace_ids_of_span = |span_name: &str| -> Result<Vec<String>, serde_json::Error> {
+        Ok(logs
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|line| {
+                line.get("spans")
+                    .and_then(|spans| spans.as_array().cloned())
+            })
+            .flatten()
+            .filter(|span| span["name"] == span_name)
+            .map(|span| span["trace_id"].as_str().unwrap_or_default().to_string())
+            .collect())
+    };
+    for span_name in ["run_action", "execution_response"] {
+        let trace_ids = trace_ids_of_span(span_name)?;
+        assert!(
+            !trace_ids.is_empty(),
+            "Expected a log line inside the {span_name} span, got: {logs}"
+        );
+        for trace_id in trace_ids {
+            assert_eq!(trace_id, operation_id.to_string());
+        }
+    }
+    Ok(())
+}
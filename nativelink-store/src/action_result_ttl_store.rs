@@ -0,0 +1,237 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use nativelink_config::stores::ActionResultTtlSpec;
+use nativelink_error::{error_if, make_err, Code, Error, ErrorReason, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
+use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::metrics_utils::CounterWithTime;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+
+/// Every stored entry starts with `HEADER_MAGIC`, `HEADER_VERSION` and the
+/// big-endian unix timestamp in seconds of when it was stored. The first byte
+/// of the magic is an invalid protobuf tag, so an encoded `ActionResult`
+/// never starts with it. Entries without the magic were stored before this
+/// store was added and are passed through as-is without ever expiring.
+const HEADER_MAGIC: [u8; 4] = *b"NLTT";
+const HEADER_VERSION: u8 = 1;
+const HEADER_SIZE: usize = HEADER_MAGIC.len() + size_of::<u8>() + size_of::<u64>();
+
+#[derive(MetricsComponent)]
+pub struct ActionResultTtlStore<NowFn> {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    now_fn: NowFn,
+    #[metric(help = "Action results stored longer ago than this are missing, zero is never")]
+    ttl_s: u64,
+    #[metric(help = "Number of action results treated as missing because they expired")]
+    expired_entries: CounterWithTime,
+}
+
+impl<I, NowFn> ActionResultTtlStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    pub fn new(spec: &ActionResultTtlSpec, inner_store: Store, now_fn: NowFn) -> Arc<Self> {
+        Arc::new(Self {
+            inner_store,
+            now_fn,
+            ttl_s: spec.ttl_s,
+            expired_entries: CounterWithTime::default(),
+        })
+    }
+
+    /// Returns true if an entry stored at `stored_at` (unix seconds) has
+    /// outlived the TTL.
+    fn is_expired(&self, stored_at: u64) -> bool {
+        if self.ttl_s == 0 {
+            return false;
+        }
+        let expired = (self.now_fn)().unix_timestamp().saturating_sub(stored_at) > self.ttl_s;
+        if expired {
+            self.expired_entries.inc();
+        }
+        expired
+    }
+
+    /// Reads the header of a stored entry and returns when it was stored,
+    /// or `None` for legacy entries without a header.
+    fn decode_header(key: &StoreKey<'_>, data: &[u8]) -> Result<Option<u64>, Error> {
+        let Some(rest) = data.strip_prefix(&HEADER_MAGIC) else {
+            return Ok(None);
+        };
+        error_if!(
+            data.len() < HEADER_SIZE,
+            "Entry {key:?} is too short to contain a header in ActionResultTtlStore"
+        );
+        error_if!(
+            rest[0] != HEADER_VERSION,
+            "Entry {key:?} has unknown header version {} in ActionResultTtlStore",
+            rest[0]
+        );
+        let mut stored_at = [0u8; size_of::<u64>()];
+        stored_at.copy_from_slice(&rest[1..1 + size_of::<u64>()]);
+        Ok(Some(u64::from_be_bytes(stored_at)))
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> StoreDriver for ActionResultTtlStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner_store
+            .has_with_results(keys, results)
+            .await
+            .err_tip(|| "In ActionResultTtlStore::has_with_results")?;
+        for (key, result) in keys.iter().zip(results.iter_mut()) {
+            let Some(size) = *result else {
+                continue;
+            };
+            let header = self
+                .inner_store
+                .get_part_unchunked(key.borrow(), 0, Some(HEADER_SIZE as u64))
+                .await
+                .err_tip(|| "In ActionResultTtlStore::has_with_results")?;
+            *result = match Self::decode_header(key, &header)? {
+                None => Some(size),
+                Some(stored_at) if self.is_expired(stored_at) => None,
+                Some(_) => Some(size.saturating_sub(HEADER_SIZE as u64)),
+            };
+        }
+        Ok(())
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let data = reader
+            .consume(None)
+            .await
+            .err_tip(|| "Failed to read action result in ActionResultTtlStore::update")?;
+        let mut stamped = BytesMut::with_capacity(HEADER_SIZE + data.len());
+        stamped.put_slice(&HEADER_MAGIC);
+        stamped.put_u8(HEADER_VERSION);
+        stamped.put_u64((self.now_fn)().unix_timestamp());
+        stamped.put(data);
+        self.inner_store
+            .update_oneshot(key, stamped.freeze())
+            .await
+            .err_tip(|| "In ActionResultTtlStore::update")
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let data = self
+            .inner_store
+            .get_part_unchunked(key.borrow(), 0, None)
+            .await
+            .err_tip(|| "In ActionResultTtlStore::get_part")?;
+        let data: Bytes = match Self::decode_header(&key, &data)? {
+            None => data,
+            Some(stored_at) if self.is_expired(stored_at) => {
+                return Err(make_err!(
+                    Code::NotFound,
+                    "Action result {key:?} expired in ActionResultTtlStore"
+                )
+                .with_reason(ErrorReason::NotFound));
+            }
+            Some(_) => data.slice(HEADER_SIZE..),
+        };
+        let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
+        error_if!(
+            offset > data.len(),
+            "Offset {offset} is past the end of {key:?} which is {} bytes long",
+            data.len()
+        );
+        let remaining = data.len() - offset;
+        let length = match length {
+            Some(length) => usize::try_from(length)
+                .err_tip(|| "Could not convert length to usize")?
+                .min(remaining),
+            None => remaining,
+        };
+        if length > 0 {
+            writer
+                .send(data.slice(offset..(offset + length)))
+                .await
+                .err_tip(|| "Failed to write data in ActionResultTtlStore::get_part")?;
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in ActionResultTtlStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> HealthStatusIndicator for ActionResultTtlStore<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    fn get_name(&self) -> &'static str {
+        "ActionResultTtlStore"
+    }
+
+    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        StoreDriver::check_health(Pin::new(self), namespace).await
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use futures::{join, StreamExt};
+use nativelink_config::stores::AsyncMirrorSpec;
+use nativelink_error::{Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::metrics_utils::DurationHistogram;
+use nativelink_util::spawn;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use nativelink_util::task::JoinHandleDropGuard;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{event, Level};
+
+// NOTE: If these change update the comments in `stores.rs` to reflect
+// the new defaults.
+const DEFAULT_MAX_PENDING_MIRRORS: usize = 1000;
+const DEFAULT_MAX_CONCURRENT_MIRRORS: usize = 10;
+
+/// A blob written to the primary store that still has to be copied to the
+/// secondary store.
+struct MirrorRequest {
+    key: StoreKey<'static>,
+    queued_at: Instant,
+}
+
+/// State shared between the store and the task copying blobs to the
+/// secondary store.
+#[derive(MetricsComponent)]
+struct MirrorState {
+    #[metric(group = "primary_store")]
+    primary_store: Store,
+    #[metric(group = "secondary_store")]
+    secondary_store: Store,
+    #[metric(help = "Number of blobs waiting to be copied to the secondary store")]
+    pending_mirrors: AtomicU64,
+    #[metric(help = "Time from a blob being written to it being copied to the secondary store")]
+    mirror_lag: DurationHistogram,
+    #[metric(help = "Number of blobs copied to the secondary store")]
+    mirrored_blobs: AtomicU64,
+    #[metric(help = "Number of blobs that failed to be copied to the secondary store")]
+    failed_mirrors: AtomicU64,
+    #[metric(help = "Number of blobs not copied because too many copies were pending")]
+    dropped_mirrors: AtomicU64,
+}
+
+impl MirrorState {
+    /// Copies the blob stored under `key` from the primary store to the
+    /// secondary store.
+    async fn copy_to_secondary(&self, key: StoreKey<'_>) -> Result<(), Error> {
+        let size = self
+            .primary_store
+            .has(key.borrow())
+            .await
+            .err_tip(|| "Failed to check primary store in AsyncMirrorStore")?
+            .err_tip(|| "Blob was removed from primary store before it was mirrored")?;
+        let (tx, rx) = make_buf_channel_pair();
+        let (get_res, update_res) = join!(
+            self.primary_store.get(key.borrow(), tx),
+            self.secondary_store
+                .update(key.borrow(), rx, UploadSizeInfo::ExactSize(size)),
+        );
+        get_res
+            .err_tip(|| "Failed to read from primary store in AsyncMirrorStore")
+            .merge(update_res.err_tip(|| "Failed to write to secondary store in AsyncMirrorStore"))
+    }
+
+    async fn mirror(&self, request: MirrorRequest) {
+        match self.copy_to_secondary(request.key.borrow()).await {
+            Ok(()) => {
+                self.mirrored_blobs.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => {
+                self.failed_mirrors.fetch_add(1, Ordering::Relaxed);
+                event!(
+                    Level::WARN,
+                    key = ?request.key,
+                    ?err,
+                    "Failed to mirror blob to secondary store",
+                );
+            }
+        }
+        self.mirror_lag.observe(request.queued_at.elapsed());
+        self.pending_mirrors.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes to a primary store and copies every written blob to a secondary
+/// store in the background, without waiting for the copy to finish.
+#[derive(MetricsComponent)]
+pub struct AsyncMirrorStore {
+    #[metric(group = "mirror")]
+    state: Arc<MirrorState>,
+    #[metric(help = "If reads of blobs missing in the primary store try the secondary store")]
+    read_fallback_to_secondary: bool,
+    mirror_tx: mpsc::Sender<MirrorRequest>,
+    _mirror_spawn: JoinHandleDropGuard<()>,
+}
+
+impl AsyncMirrorStore {
+    pub fn new(spec: &AsyncMirrorSpec, primary_store: Store, secondary_store: Store) -> Arc<Self> {
+        let max_pending_mirrors = if spec.max_pending_mirrors == 0 {
+            DEFAULT_MAX_PENDING_MIRRORS
+        } else {
+            spec.max_pending_mirrors
+        };
+        let max_concurrent_mirrors = if spec.max_concurrent_mirrors == 0 {
+            DEFAULT_MAX_CONCURRENT_MIRRORS
+        } else {
+            spec.max_concurrent_mirrors
+        };
+        let state = Arc::new(MirrorState {
+            primary_store,
+            secondary_store,
+            pending_mirrors: AtomicU64::new(0),
+            mirror_lag: DurationHistogram::default(),
+            mirrored_blobs: AtomicU64::new(0),
+            failed_mirrors: AtomicU64::new(0),
+            dropped_mirrors: AtomicU64::new(0),
+        });
+        let (mirror_tx, mirror_rx) = mpsc::channel(max_pending_mirrors);
+        let mirror_state = state.clone();
+        Arc::new(Self {
+            state,
+            read_fallback_to_secondary: spec.read_fallback_to_secondary,
+            mirror_tx,
+            _mirror_spawn: spawn!("async_mirror_store_mirror", async move {
+                ReceiverStream::new(mirror_rx)
+                    .for_each_concurrent(max_concurrent_mirrors, |request| {
+                        mirror_state.mirror(request)
+                    })
+                    .await;
+            }),
+        })
+    }
+
+    /// Number of blobs that were written but not yet copied to the
+    /// secondary store.
+    pub fn pending_mirrors(&self) -> u64 {
+        self.state.pending_mirrors.load(Ordering::Relaxed)
+    }
+
+    /// Queues a copy of the blob stored under `key` to the secondary store.
+    fn enqueue_mirror(&self, key: StoreKey<'static>) {
+        self.state.pending_mirrors.fetch_add(1, Ordering::Relaxed);
+        let request = MirrorRequest {
+            key,
+            queued_at: Instant::now(),
+        };
+        if let Err(err) = self.mirror_tx.try_send(request) {
+            self.state.pending_mirrors.fetch_sub(1, Ordering::Relaxed);
+            self.state.dropped_mirrors.fetch_add(1, Ordering::Relaxed);
+            let (mpsc::error::TrySendError::Full(request)
+            | mpsc::error::TrySendError::Closed(request)) = err;
+            event!(
+                Level::WARN,
+                key = ?request.key,
+                "Too many pending mirrors in AsyncMirrorStore, blob will not be mirrored",
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDriver for AsyncMirrorStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.state
+            .primary_store
+            .has_with_results(keys, results)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::has_with_results")?;
+        if !self.read_fallback_to_secondary {
+            return Ok(());
+        }
+        let (missing_keys, missing_indexes): (Vec<_>, Vec<_>) = keys
+            .iter()
+            .zip(results.iter())
+            .enumerate()
+            .filter(|(_, (_, result))| result.is_none())
+            .map(|(index, (key, _))| (key.borrow(), index))
+            .unzip();
+        if missing_keys.is_empty() {
+            return Ok(());
+        }
+        let secondary_results = self
+            .state
+            .secondary_store
+            .has_many(&missing_keys)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::has_with_results secondary")?;
+        for (index, secondary_result) in missing_indexes.into_iter().zip(secondary_results) {
+            results[index] = secondary_result;
+        }
+        Ok(())
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        // Blobs are kept in the secondary store, so it can be used to
+        // recover blobs that were removed by accident.
+        self.state
+            .primary_store
+            .remove(key)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::remove")
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        // The primary store serves reads, so it is the one whose entries
+        // must not be evicted.
+        self.state
+            .primary_store
+            .touch(keys)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::touch")
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        // Like `remove`, only the primary store is purged.
+        self.state
+            .primary_store
+            .remove_prefix(prefix)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::remove_prefix")
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.state
+            .primary_store
+            .update(key.borrow(), reader, size_info)
+            .await
+            .err_tip(|| "In AsyncMirrorStore::update")?;
+        self.enqueue_mirror(key.into_owned());
+        Ok(())
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        if self.read_fallback_to_secondary
+            && self
+                .state
+                .primary_store
+                .has(key.borrow())
+                .await
+                .err_tip(|| "In AsyncMirrorStore::get_part")?
+                .is_none()
+        {
+            return self
+                .state
+                .secondary_store
+                .get_part(key, writer, offset, length)
+                .await
+                .err_tip(|| "In AsyncMirrorStore::get_part secondary");
+        }
+        self.state
+            .primary_store
+            .get_part(key, writer, offset, length)
+            .await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(AsyncMirrorStore);
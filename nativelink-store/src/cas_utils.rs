@@ -12,34 +12,4 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use nativelink_util::common::DigestInfo;
-use nativelink_util::store_trait::StoreKey;
-
-pub const ZERO_BYTE_DIGESTS: [DigestInfo; 2] = [
-    // Sha256 hash of zero bytes.
-    DigestInfo::new(
-        [
-            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
-            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
-            0x78, 0x52, 0xb8, 0x55,
-        ],
-        0,
-    ),
-    // Blake3 hash of zero bytes.
-    DigestInfo::new(
-        [
-            0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6, 0xa0, 0x40, 0x4d, 0xea, 0x36, 0xdc,
-            0xc9, 0x49, 0x9b, 0xcb, 0x25, 0xc9, 0xad, 0xc1, 0x12, 0xb7, 0xcc, 0x9a, 0x93, 0xca,
-            0xe4, 0x1f, 0x32, 0x62,
-        ],
-        0,
-    ),
-];
-
-#[inline]
-pub fn is_zero_digest<'a>(digest: impl Into<StoreKey<'a>>) -> bool {
-    match digest.into() {
-        StoreKey::Digest(digest) => digest.size_bytes() == 0 && ZERO_BYTE_DIGESTS.contains(&digest),
-        StoreKey::Str(_) => false,
-    }
-}
+pub use nativelink_util::store_trait::{is_zero_digest, ZERO_BYTE_DIGESTS};
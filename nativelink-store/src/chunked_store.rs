@@ -0,0 +1,519 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bincode::config::{FixintEncoding, WithOtherIntEncoding};
+use bincode::{DefaultOptions, Options};
+use futures::stream::{self, FuturesOrdered, StreamExt, TryStreamExt};
+use nativelink_config::stores::ChunkedSpec;
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use serde::{Deserialize, Serialize};
+use tokio::join;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+// NOTE: If these change update the comments in `stores.rs` to reflect
+// the new defaults.
+const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_CONCURRENT_FETCH_PER_GET: usize = 4;
+
+/// A single chunk of an entry, stored in the backend under `key`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct ChunkedManifestEntry {
+    pub key: String,
+    pub size_bytes: u64,
+}
+
+/// Stored in the backend under the key of the entry and lists its chunks
+/// in order.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
+pub struct ChunkedManifest {
+    pub chunks: Vec<ChunkedManifestEntry>,
+}
+
+#[derive(MetricsComponent)]
+pub struct ChunkedStore {
+    #[metric(group = "backend")]
+    backend: Store,
+    #[metric(help = "Maximum size of a single chunk in the backend")]
+    chunk_size: u64,
+    #[metric(help = "Maximum number of concurrent fetches per get")]
+    max_concurrent_fetch_per_get: usize,
+    bincode_options: WithOtherIntEncoding<DefaultOptions, FixintEncoding>,
+}
+
+impl ChunkedStore {
+    pub fn new(spec: &ChunkedSpec, backend: Store) -> Result<Arc<Self>, Error> {
+        let chunk_size = if spec.chunk_size == 0 {
+            DEFAULT_CHUNK_SIZE
+        } else {
+            spec.chunk_size
+        };
+        // Chunks are streamed through memory, so they must be addressable.
+        usize::try_from(chunk_size).err_tip(|| "Could not convert chunk_size to usize")?;
+        let max_concurrent_fetch_per_get = if spec.max_concurrent_fetch_per_get == 0 {
+            DEFAULT_MAX_CONCURRENT_FETCH_PER_GET
+        } else {
+            spec.max_concurrent_fetch_per_get as usize
+        };
+        Ok(Arc::new(Self {
+            backend,
+            chunk_size,
+            max_concurrent_fetch_per_get,
+            bincode_options: DefaultOptions::new().with_fixint_encoding(),
+        }))
+    }
+
+    /// Digests that fit in a single chunk are stored in the backend as-is.
+    /// The size of string keys is unknown, so they always get a manifest.
+    fn is_passthrough(&self, key: &StoreKey<'_>) -> bool {
+        match key {
+            StoreKey::Digest(digest) => digest.size_bytes() <= self.chunk_size,
+            StoreKey::Str(_) => false,
+        }
+    }
+
+    async fn get_manifest(&self, key: StoreKey<'_>) -> Result<ChunkedManifest, Error> {
+        let data = self
+            .backend
+            .get_part_unchunked(key, 0, None)
+            .await
+            .err_tip(|| "Failed to read manifest in chunked store")?;
+        self.bincode_options
+            .deserialize::<ChunkedManifest>(&data)
+            .map_err(|e| {
+                make_err!(
+                    Code::Internal,
+                    "Failed to deserialize manifest in chunked store : {:?}",
+                    e
+                )
+            })
+    }
+
+    async fn has(self: Pin<&Self>, key: StoreKey<'_>) -> Result<Option<u64>, Error> {
+        if self.is_passthrough(&key) {
+            return self.backend.has(key).await;
+        }
+        let manifest = match self.get_manifest(key.borrow()).await {
+            Ok(manifest) => manifest,
+            Err(err) if err.code == Code::NotFound => return Ok(None),
+            Err(err) => {
+                event!(
+                    Level::WARN,
+                    ?key,
+                    ?err,
+                    "Failed to load manifest in chunked store",
+                );
+                // We return the equivalent of NotFound here so the client is happy.
+                return Ok(None);
+            }
+        };
+
+        let chunk_keys: Vec<_> = manifest
+            .chunks
+            .into_iter()
+            .map(|chunk| StoreKey::Str(Cow::Owned(chunk.key)))
+            .collect();
+        let mut sum = 0;
+        for size in self.backend.has_many(&chunk_keys).await? {
+            let Some(size) = size else {
+                // A chunk is missing so the entry can not be read back.
+                return Ok(None);
+            };
+            sum += size;
+        }
+        Ok(Some(sum))
+    }
+
+    /// Forwards at most `max_len` bytes of `reader` to the backend under
+    /// `chunk_key` and returns the number of bytes forwarded.
+    async fn update_chunk(
+        self: Pin<&Self>,
+        chunk_key: StoreKey<'_>,
+        reader: &mut DropCloserReadHalf,
+        max_len: u64,
+        size_info: UploadSizeInfo,
+    ) -> Result<u64, Error> {
+        let (mut tx, rx) = make_buf_channel_pair();
+        let forward_fut = async move {
+            let mut remaining = max_len;
+            while remaining > 0 {
+                let next_len = reader
+                    .peek()
+                    .await
+                    .err_tip(|| "Failed to read data in chunked store")?
+                    .len() as u64;
+                if next_len == 0 {
+                    break; // EOF.
+                }
+                let data = if next_len <= remaining {
+                    reader.recv().await
+                } else {
+                    // Splits the buffered data without copying it.
+                    reader
+                        .consume(Some(usize::try_from(remaining).err_tip(|| {
+                            "Could not convert remaining to usize in chunked store"
+                        })?))
+                        .await
+                }
+                .err_tip(|| "Failed to read data in chunked store")?;
+                remaining -= data.len() as u64;
+                tx.send(data)
+                    .await
+                    .err_tip(|| "Failed to forward data to backend in chunked store")?;
+            }
+            tx.send_eof()
+                .err_tip(|| "Failed to send EOF to backend in chunked store")?;
+            Result::<_, Error>::Ok(max_len - remaining)
+        };
+        let (forward_res, update_res) =
+            join!(forward_fut, self.backend.update(chunk_key, rx, size_info));
+        match (forward_res, update_res) {
+            (Ok(size), Ok(())) => Ok(size),
+            (Err(err), Ok(())) | (Ok(_), Err(err)) => Err(err),
+            (Err(forward_err), Err(update_err)) => Err(forward_err.merge(update_err)),
+        }
+    }
+
+    /// Uploads the data of `reader` in chunks and appends each of them to
+    /// `chunks` before it is uploaded, so a failed upload knows what to
+    /// clean up.
+    async fn update_chunks(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: &mut DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+        chunks: &mut Vec<ChunkedManifestEntry>,
+    ) -> Result<(), Error> {
+        // Every upload writes its chunks under new keys, so a reader of the
+        // previous manifest of this key never sees data of this upload.
+        let upload_id = Uuid::new_v4().simple().to_string();
+        let mut total_size = 0;
+        loop {
+            if reader
+                .peek()
+                .await
+                .err_tip(|| "Failed to read data in chunked store")?
+                .is_empty()
+            {
+                return Ok(()); // EOF.
+            }
+            let chunk_size_info = match size_info {
+                UploadSizeInfo::ExactSize(size) => UploadSizeInfo::ExactSize(cmp::min(
+                    self.chunk_size,
+                    size.saturating_sub(total_size),
+                )),
+                UploadSizeInfo::MaxSize(_) => UploadSizeInfo::MaxSize(self.chunk_size),
+            };
+            let chunk_index = chunks.len();
+            let chunk_key = format!("{}-chunk-{upload_id}-{chunk_index}", key.as_str());
+            chunks.push(ChunkedManifestEntry {
+                key: chunk_key.clone(),
+                size_bytes: 0,
+            });
+            let size_bytes = self
+                .update_chunk(
+                    StoreKey::new_str(&chunk_key),
+                    reader,
+                    self.chunk_size,
+                    chunk_size_info,
+                )
+                .await
+                .err_tip(|| format!("Failed to upload chunk {chunk_key} in chunked store"))?;
+            total_size += size_bytes;
+            chunks[chunk_index].size_bytes = size_bytes;
+        }
+    }
+
+    /// Removes chunks no manifest refers to anymore. Failures are only
+    /// logged, because nothing can read these chunks back.
+    async fn remove_unreferenced_chunks(self: Pin<&Self>, chunks: Vec<ChunkedManifestEntry>) {
+        for chunk in chunks {
+            if let Err(err) = self.backend.remove(StoreKey::new_str(&chunk.key)).await {
+                event!(
+                    Level::WARN,
+                    chunk_key = chunk.key,
+                    ?err,
+                    "Failed to remove unreferenced chunk in chunked store",
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDriver for ChunkedStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        keys.iter()
+            .zip(results.iter_mut())
+            .map(|(key, result)| async move {
+                *result = self.has(key.borrow()).await?;
+                Result::<_, Error>::Ok(())
+            })
+            .collect::<FuturesOrdered<_>>()
+            .try_collect()
+            .await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        if self.is_passthrough(&key) {
+            return self.backend.remove(key).await;
+        }
+        let manifest = match self.get_manifest(key.borrow()).await {
+            Ok(manifest) => manifest,
+            Err(err) if err.code == Code::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        // Remove the manifest first so readers never see missing chunks.
+        let removed = self
+            .backend
+            .remove(key)
+            .await
+            .err_tip(|| "Failed to remove manifest in chunked store")?;
+        for chunk in manifest.chunks {
+            self.backend
+                .remove(StoreKey::Str(Cow::Owned(chunk.key)))
+                .await
+                .err_tip(|| "Failed to remove chunk in chunked store")?;
+        }
+        Ok(removed)
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let mut passthrough_keys = Vec::new();
+        let mut chunked_keys = Vec::new();
+        let mut touched_manifests = 0;
+        for key in keys {
+            if self.is_passthrough(key) {
+                passthrough_keys.push(key.borrow());
+                continue;
+            }
+            let manifest = match self.get_manifest(key.borrow()).await {
+                Ok(manifest) => manifest,
+                Err(err) if err.code == Code::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            // The chunks are touched along with the manifest, so the entry
+            // stays readable.
+            touched_manifests += 1;
+            chunked_keys.push(key.borrow());
+            chunked_keys.extend(
+                manifest
+                    .chunks
+                    .into_iter()
+                    .map(|chunk| StoreKey::Str(Cow::Owned(chunk.key))),
+            );
+        }
+        let (passthrough_res, chunked_res) = join!(
+            self.backend.touch(&passthrough_keys),
+            self.backend.touch(&chunked_keys)
+        );
+        chunked_res.err_tip(|| "Failed to touch chunks in chunked store")?;
+        Ok(
+            passthrough_res.err_tip(|| "Failed to touch entries in chunked store")?
+                + touched_manifests,
+        )
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        if self.is_passthrough(&key) {
+            return self.backend.update(key, reader, size_info).await;
+        }
+
+        let mut chunks = Vec::new();
+        if let Err(err) = self
+            .update_chunks(key.borrow(), &mut reader, size_info, &mut chunks)
+            .await
+        {
+            self.remove_unreferenced_chunks(chunks).await;
+            return Err(err);
+        }
+
+        let serialized_manifest = match self.bincode_options.serialize(&ChunkedManifest {
+            chunks: chunks.clone(),
+        }) {
+            Ok(serialized_manifest) => serialized_manifest,
+            Err(e) => {
+                self.remove_unreferenced_chunks(chunks).await;
+                return Err(make_err!(
+                    Code::Internal,
+                    "Failed to serialize manifest in chunked store : {:?}",
+                    e
+                ));
+            }
+        };
+        // The chunks of the previous manifest are removed once the new
+        // manifest is in place. A reader that is still fetching them fails
+        // instead of mixing the data of both uploads.
+        let previous_manifest = match self.get_manifest(key.borrow()).await {
+            Ok(manifest) => Some(manifest),
+            Err(err) if err.code == Code::NotFound => None,
+            Err(err) => {
+                event!(
+                    Level::WARN,
+                    ?key,
+                    ?err,
+                    "Failed to load previous manifest in chunked store, its chunks will not be removed",
+                );
+                None
+            }
+        };
+        if let Err(err) = self
+            .backend
+            .update_oneshot(key, serialized_manifest.into())
+            .await
+        {
+            self.remove_unreferenced_chunks(chunks).await;
+            return Err(err).err_tip(|| "Failed to insert manifest in chunked store");
+        }
+        if let Some(previous_manifest) = previous_manifest {
+            self.remove_unreferenced_chunks(previous_manifest.chunks)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        if self.is_passthrough(&key) {
+            return self.backend.get_part(key, writer, offset, length).await;
+        }
+        // Special case for if a client tries to read zero bytes.
+        if length == Some(0) {
+            writer
+                .send_eof()
+                .err_tip(|| "Failed to write EOF out from get_part chunked")?;
+            return Ok(());
+        }
+        let manifest = self.get_manifest(key.borrow()).await?;
+
+        let total_size: u64 = manifest.chunks.iter().map(|chunk| chunk.size_bytes).sum();
+        error_if!(
+            offset > total_size,
+            "Offset {offset} is past the end of {key:?} which is {total_size} bytes long"
+        );
+        let end = length.map_or(total_size, |length| {
+            cmp::min(offset.saturating_add(length), total_size)
+        });
+
+        // Only the chunks covering `offset..end` are fetched, and only the
+        // part of each chunk that falls inside of the range.
+        let mut ranges = Vec::new();
+        let mut first_byte = 0;
+        for chunk in manifest.chunks {
+            let last_byte = first_byte + chunk.size_bytes;
+            if first_byte >= end {
+                break;
+            }
+            if last_byte > offset {
+                let start_in_chunk = offset.saturating_sub(first_byte);
+                let end_in_chunk = cmp::min(end, last_byte) - first_byte;
+                ranges.push((chunk.key, start_in_chunk, end_in_chunk - start_in_chunk));
+            }
+            first_byte = last_byte;
+        }
+
+        // The chunks are streamed out in the same order they are in the
+        // manifest. Up to `max_concurrent_fetch_per_get` chunks are fetched
+        // at the same time, but each of them only buffers what fits in its
+        // channel until the chunks before it are sent.
+        let (fetch_futs, readers): (Vec<_>, Vec<_>) = ranges
+            .into_iter()
+            .map(|(chunk_key, start, len)| {
+                let (mut tx, rx) = make_buf_channel_pair();
+                let fetch_fut = async move {
+                    self.backend
+                        .get_part(
+                            StoreKey::Str(Cow::Owned(chunk_key)),
+                            &mut tx,
+                            start,
+                            Some(len),
+                        )
+                        .await
+                        .err_tip(|| "Failed to get_part in backend in chunked store")
+                };
+                (fetch_fut, rx)
+            })
+            .unzip();
+        let fetch_fut = stream::iter(fetch_futs)
+            .buffered(self.max_concurrent_fetch_per_get)
+            .try_collect::<()>();
+        // The readers are dropped as soon as forwarding fails, so the
+        // fetches that are still running stop too.
+        let forward_fut = async move {
+            for mut reader in readers {
+                loop {
+                    let data = reader
+                        .recv()
+                        .await
+                        .err_tip(|| "Failed to read chunk in chunked store")?;
+                    if data.is_empty() {
+                        break; // EOF.
+                    }
+                    writer
+                        .send(data)
+                        .await
+                        .err_tip(|| "Failed to write data to get_part chunked")?;
+                }
+            }
+            writer
+                .send_eof()
+                .err_tip(|| "Failed to write EOF out from get_part chunked")
+        };
+        match join!(fetch_fut, forward_fut) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(err), Ok(())) | (Ok(()), Err(err)) => Err(err),
+            (Err(fetch_err), Err(forward_err)) => Err(fetch_err.merge(forward_err)),
+        }
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(ChunkedStore);
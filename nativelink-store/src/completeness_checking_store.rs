@@ -19,7 +19,7 @@ use std::{iter, mem};
 use async_trait::async_trait;
 use futures::stream::{FuturesUnordered, StreamExt};
 use futures::{select, FutureExt, TryFutureExt};
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::{
     ActionResult as ProtoActionResult, OutputDirectory as ProtoOutputDirectory, Tree as ProtoTree,
@@ -30,6 +30,8 @@ use nativelink_util::health_utils::{default_health_status_indicator, HealthStatu
 use nativelink_util::metrics_utils::CounterWithTime;
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use rand::Rng;
 use tokio::sync::Notify;
 use tracing::{event, Level};
 
@@ -113,18 +115,50 @@ pub struct CompletenessCheckingStore {
     incomplete_entries_counter: CounterWithTime,
     #[metric(help = "Complete entries hit in CompletenessCheckingStore")]
     complete_entries_counter: CounterWithTime,
+    #[metric(help = "Percentage of output digests verified to exist in the CAS")]
+    sample_percent: u32,
 }
 
 impl CompletenessCheckingStore {
     pub fn new(ac_store: Store, cas_store: Store) -> Arc<Self> {
+        Self::new_with_sample_percent(ac_store, cas_store, 100)
+    }
+
+    /// Only verifies that `sample_percent` percent of the outputs of each
+    /// action result exist, chosen at random. Values of 0 or 100 verify
+    /// every output.
+    pub fn new_with_sample_percent(
+        ac_store: Store,
+        cas_store: Store,
+        sample_percent: u8,
+    ) -> Arc<Self> {
+        let sample_percent = match sample_percent {
+            0 => 100,
+            sample_percent => u32::from(sample_percent.min(100)),
+        };
         Arc::new(CompletenessCheckingStore {
             cas_store,
             ac_store,
             incomplete_entries_counter: CounterWithTime::default(),
             complete_entries_counter: CounterWithTime::default(),
+            sample_percent,
         })
     }
 
+    /// Returns the digests that should be verified to exist in the CAS.
+    fn sample_digests<'a>(&self, mut digests: Vec<StoreKey<'a>>) -> Vec<StoreKey<'a>> {
+        if self.sample_percent < 100 {
+            digests.retain(|_| OsRng.gen_ratio(self.sample_percent, 100));
+        }
+        digests
+    }
+
+    /// The store holding the action results, which does not check that
+    /// their outputs exist in the CAS.
+    pub fn ac_store(&self) -> &Store {
+        &self.ac_store
+    }
+
     /// Check that all files and directories in action results
     /// exist in the CAS. Does this by decoding digests and
     /// checking their existence in two separate sets of futures that
@@ -169,8 +203,9 @@ impl CompletenessCheckingStore {
                     )
                     .await?;
 
-                    let (mut digest_infos, output_directories) =
+                    let (digest_infos, output_directories) =
                         get_digests_and_output_dirs(action_result)?;
+                    let mut digest_infos = self.sample_digests(digest_infos);
 
                     {
                         let mut state = state_mux.lock();
@@ -204,6 +239,7 @@ impl CompletenessCheckingStore {
                         &self.cas_store,
                         output_directories,
                         &move |digest_infos| {
+                            let digest_infos = self.sample_digests(digest_infos);
                             let mut state = state_mux.lock();
                             let rep_len = digest_infos.len();
                             state.digests_to_check.extend(digest_infos);
@@ -347,6 +383,18 @@ impl StoreDriver for CompletenessCheckingStore {
         self.inner_has_with_results(keys, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.ac_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.ac_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.ac_store.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -371,7 +419,8 @@ impl StoreDriver for CompletenessCheckingStore {
             return Err(make_err!(
                 Code::NotFound,
                 "Digest found, but not all parts were found in CompletenessCheckingStore::get_part"
-            ));
+            )
+            .with_reason(ErrorReason::NotFound));
         }
         self.ac_store.get_part(key, writer, offset, length).await
     }
@@ -20,7 +20,7 @@ use async_trait::async_trait;
 use bincode::config::{FixintEncoding, WithOtherIntEncoding};
 use bincode::{DefaultOptions, Options};
 use byteorder::{ByteOrder, LittleEndian};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::future::FutureExt;
 use lz4_flex::block::{compress_into, decompress_into, get_maximum_output_size};
 use nativelink_config::stores::CompressionSpec;
@@ -150,6 +150,27 @@ fn lz4_compress_bound(input_size: u64) -> u64 {
     input_size + (input_size / 255) + 16
 }
 
+/// Reads exactly `size` bytes from `rx`. Fails with `DataLoss` if the data
+/// ends early, which means the stored data was truncated.
+async fn consume_exact(
+    rx: &mut DropCloserReadHalf,
+    size: usize,
+    what: &str,
+) -> Result<Bytes, Error> {
+    let chunk = rx
+        .consume(Some(size))
+        .await
+        .err_tip(|| format!("Failed to read {what} in compression store get_part"))?;
+    if chunk.len() != size {
+        return Err(make_err!(
+            Code::DataLoss,
+            "Got EOF while reading {what} in compression store get_part, expected {size} bytes but got {}. The data may be truncated or not compressed",
+            chunk.len()
+        ));
+    }
+    Ok(chunk)
+}
+
 struct UploadState {
     header: Header,
     footer: Footer,
@@ -249,6 +270,18 @@ impl StoreDriver for CompressionStore {
         self.inner_store.has_with_results(digests, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -395,6 +428,10 @@ impl StoreDriver for CompressionStore {
         length: Option<u64>,
     ) -> Result<(), Error> {
         if is_zero_digest(key.borrow()) {
+            error_if!(
+                offset != 0,
+                "Offset {offset} is past the end of {key:?} which is 0 bytes long"
+            );
             writer
                 .send_eof()
                 .err_tip(|| "Failed to send zero EOF in filesystem store get_part")?;
@@ -428,16 +465,7 @@ impl StoreDriver for CompressionStore {
                     upload_size: UploadSizeInfo::ExactSize(0),
                 };
                 let header_size = self.bincode_options.serialized_size(&EMPTY_HEADER).unwrap();
-                let chunk = rx
-                    .consume(Some(header_size as usize))
-                    .await
-                    .err_tip(|| "Failed to read header in get_part compression store")?;
-                error_if!(
-                    chunk.len() as u64 != header_size,
-                    "Expected inner store to return the proper amount of data in compression store {} != {}",
-                    chunk.len(),
-                    header_size,
-                );
+                let chunk = consume_exact(&mut rx, header_size as usize, "header").await?;
 
                 self.bincode_options
                     .deserialize::<Header>(&chunk)
@@ -459,14 +487,10 @@ impl StoreDriver for CompressionStore {
                 self.config.max_decode_block_size
             );
 
-            let mut chunk = rx
-                .consume(Some(1 + 4))
-                .await
-                .err_tip(|| "Failed to read init frame info in compression store")?;
-            error_if!(
-                chunk.len() < 1 + 4,
-                "Received EOF too early while reading init frame info in compression store"
-            );
+            // Compressed blocks are never larger than this, so a larger frame
+            // means the data is corrupt.
+            let max_frame_size = get_maximum_output_size(header.config.block_size as usize);
+            let mut chunk = consume_exact(&mut rx, 1 + 4, "init frame info").await?;
 
             let mut frame_type = chunk.get_u8();
             let mut frame_sz = chunk.get_u32_le();
@@ -482,16 +506,14 @@ impl StoreDriver for CompressionStore {
                     chunks_count
                 );
 
-                let chunk = rx
-                    .consume(Some(frame_sz as usize))
-                    .await
-                    .err_tip(|| "Failed to read chunk in get_part compression store")?;
-                if chunk.len() < frame_sz as usize {
+                if frame_sz as usize > max_frame_size {
                     return Err(make_err!(
-                        Code::Internal,
-                        "Got EOF earlier than expected. Maybe the data is not compressed or different format?"
+                        Code::DataLoss,
+                        "Frame {chunks_count} is {frame_sz} bytes, which is larger than the {max_frame_size} bytes a block of {} bytes compresses to in compression store get_part",
+                        header.config.block_size
                     ));
                 }
+                let chunk = consume_exact(&mut rx, frame_sz as usize, "chunk").await?;
                 {
                     let max_output_size =
                         get_maximum_output_size(header.config.block_size as usize);
@@ -514,7 +536,7 @@ impl StoreDriver for CompressionStore {
                     if new_uncompressed_data_sz >= offset && remaining_bytes_to_send > 0 {
                         let start_pos = offset.saturating_sub(uncompressed_data_sz) as usize;
                         let end_pos = cmp::min(
-                            start_pos + remaining_bytes_to_send as usize,
+                            start_pos.saturating_add(remaining_bytes_to_send as usize),
                             uncompressed_chunk_sz,
                         );
                         if end_pos != start_pos {
@@ -530,14 +552,7 @@ impl StoreDriver for CompressionStore {
                 }
                 chunks_count += 1;
 
-                let mut chunk = rx
-                    .consume(Some(1 + 4))
-                    .await
-                    .err_tip(|| "Failed to read frame info in compression store")?;
-                error_if!(
-                    chunk.len() < 1 + 4,
-                    "Received EOF too early while reading frame info in compression store"
-                );
+                let mut chunk = consume_exact(&mut rx, 1 + 4, "frame info").await?;
 
                 frame_type = chunk.get_u8();
                 frame_sz = chunk.get_u32_le();
@@ -546,14 +561,7 @@ impl StoreDriver for CompressionStore {
             chunks_count = chunks_count.saturating_sub(1);
             {
                 // Read and validate footer.
-                let chunk = rx
-                    .consume(Some(frame_sz as usize))
-                    .await
-                    .err_tip(|| "Failed to read chunk in get_part compression store")?;
-                error_if!(
-                    chunk.len() < frame_sz as usize,
-                    "Unexpected EOF when reading footer in compression store get_part"
-                );
+                let chunk = consume_exact(&mut rx, frame_sz as usize, "footer").await?;
 
                 let footer = self
                     .bincode_options
@@ -590,6 +598,12 @@ impl StoreDriver for CompressionStore {
                     uncompressed_data_sz
                 );
             }
+            // Nothing was sent if the offset is past the end of the data, so
+            // the client gets a clean error instead of an empty blob.
+            error_if!(
+                offset > uncompressed_data_sz,
+                "Offset {offset} is past the end of the data which is {uncompressed_data_sz} bytes long"
+            );
 
             writer
                 .send_eof()
@@ -19,17 +19,19 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use bincode::config::{FixintEncoding, WithOtherIntEncoding};
 use bincode::{DefaultOptions, Options};
+use bytes::{Bytes, BytesMut};
 use futures::stream::{self, FuturesOrdered, StreamExt, TryStreamExt};
-use nativelink_config::stores::DedupSpec;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_config::stores::{DedupChunkingAlgorithm, DedupSpec};
+use nativelink_error::{error_if, make_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
 use nativelink_util::common::DigestInfo;
 use nativelink_util::fastcdc::FastCDC;
+use nativelink_util::gearcdc::GearCDC;
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 use serde::{Deserialize, Serialize};
-use tokio_util::codec::FramedRead;
+use tokio_util::codec::{Decoder, FramedRead};
 use tokio_util::io::StreamReader;
 use tracing::{event, Level};
 
@@ -47,13 +49,39 @@ pub struct DedupIndex {
     pub entries: Vec<DigestInfo>,
 }
 
+/// Content defined chunker selected by `DedupSpec::chunking_algorithm`.
+#[derive(Clone)]
+enum ChunkDecoder {
+    FastCdc(FastCDC),
+    Gear(GearCDC),
+}
+
+impl Decoder for ChunkDecoder {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            Self::FastCdc(decoder) => decoder.decode(buf),
+            Self::Gear(decoder) => decoder.decode(buf),
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            Self::FastCdc(decoder) => decoder.decode_eof(buf),
+            Self::Gear(decoder) => decoder.decode_eof(buf),
+        }
+    }
+}
+
 #[derive(MetricsComponent)]
 pub struct DedupStore {
     #[metric(group = "index_store")]
     index_store: Store,
     #[metric(group = "content_store")]
     content_store: Store,
-    fast_cdc_decoder: FastCDC,
+    chunk_decoder: ChunkDecoder,
     #[metric(help = "Maximum number of concurrent fetches per get")]
     max_concurrent_fetch_per_get: usize,
     bincode_options: WithOtherIntEncoding<DefaultOptions, FixintEncoding>,
@@ -85,15 +113,24 @@ impl DedupStore {
         } else {
             spec.max_concurrent_fetch_per_get as usize
         };
+        let min_size =
+            usize::try_from(min_size).err_tip(|| "Could not convert min_size to usize")?;
+        let normal_size =
+            usize::try_from(normal_size).err_tip(|| "Could not convert normal_size to usize")?;
+        let max_size =
+            usize::try_from(max_size).err_tip(|| "Could not convert max_size to usize")?;
+        let chunk_decoder = match spec.chunking_algorithm {
+            DedupChunkingAlgorithm::fastcdc => {
+                ChunkDecoder::FastCdc(FastCDC::new(min_size, normal_size, max_size))
+            }
+            DedupChunkingAlgorithm::gear => {
+                ChunkDecoder::Gear(GearCDC::new(min_size, normal_size, max_size))
+            }
+        };
         Ok(Arc::new(Self {
             index_store,
             content_store,
-            fast_cdc_decoder: FastCDC::new(
-                usize::try_from(min_size).err_tip(|| "Could not convert min_size to usize")?,
-                usize::try_from(normal_size)
-                    .err_tip(|| "Could not convert normal_size to usize")?,
-                usize::try_from(max_size).err_tip(|| "Could not convert max_size to usize")?,
-            ),
+            chunk_decoder,
             max_concurrent_fetch_per_get,
             bincode_options: DefaultOptions::new().with_fixint_encoding(),
         }))
@@ -180,6 +217,22 @@ impl StoreDriver for DedupStore {
             .await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        // Only the index is removed, the chunks may be shared with other
+        // entries in the content store.
+        self.index_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        // Chunks can only be found by reading the index, so only the index
+        // entries are touched.
+        self.index_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.index_store.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -187,9 +240,9 @@ impl StoreDriver for DedupStore {
         _size_info: UploadSizeInfo,
     ) -> Result<(), Error> {
         let mut bytes_reader = StreamReader::new(reader);
-        let frame_reader = FramedRead::new(&mut bytes_reader, self.fast_cdc_decoder.clone());
+        let frame_reader = FramedRead::new(&mut bytes_reader, self.chunk_decoder.clone());
         let index_entries = frame_reader
-            .map(|r| r.err_tip(|| "Failed to decode frame from fast_cdc"))
+            .map(|r| r.err_tip(|| "Failed to decode frame from chunk decoder"))
             .map_ok(|frame| async move {
                 let hash = blake3::hash(&frame[..]).into();
                 let index_entry = DigestInfo::new(hash, frame.len() as u64);
@@ -253,7 +306,7 @@ impl StoreDriver for DedupStore {
         let index_entries = {
             let data = self
                 .index_store
-                .get_part_unchunked(key, 0, None)
+                .get_part_unchunked(key.borrow(), 0, None)
                 .await
                 .err_tip(|| "Failed to read index store in dedup store")?;
 
@@ -268,6 +321,16 @@ impl StoreDriver for DedupStore {
                 })?
         };
 
+        let total_size: u64 = index_entries
+            .entries
+            .iter()
+            .map(DigestInfo::size_bytes)
+            .sum();
+        error_if!(
+            offset > total_size,
+            "Offset {offset} is past the end of {key:?} which is {total_size} bytes long"
+        );
+
         let mut start_byte_in_stream: u64 = 0;
         let entries = {
             if offset == 0 && length.is_none() {
@@ -286,7 +349,7 @@ impl StoreDriver for DedupStore {
                     }
                     // If we are not going to read any bytes past the length we are done.
                     if let Some(length) = length {
-                        if first_byte >= offset + length {
+                        if first_byte >= offset.saturating_add(length) {
                             break;
                         }
                     }
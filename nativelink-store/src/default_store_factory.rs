@@ -12,32 +12,42 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::SystemTime;
 
 use futures::stream::FuturesOrdered;
 use futures::{Future, TryStreamExt};
-use nativelink_config::stores::StoreSpec;
-use nativelink_error::Error;
+use nativelink_config::stores::{FilesystemSpec, StoreRefName, StoreSpec};
+use nativelink_error::{make_input_err, Error};
 use nativelink_util::health_utils::HealthRegistryBuilder;
 use nativelink_util::store_trait::{Store, StoreDriver};
 
+use crate::action_result_ttl_store::ActionResultTtlStore;
+use crate::async_mirror_store::AsyncMirrorStore;
+use crate::chunked_store::ChunkedStore;
 use crate::completeness_checking_store::CompletenessCheckingStore;
 use crate::compression_store::CompressionStore;
 use crate::dedup_store::DedupStore;
 use crate::existence_cache_store::ExistenceCacheStore;
 use crate::fast_slow_store::FastSlowStore;
+#[cfg(feature = "fault_injection")]
+use crate::fault_injection_store::FaultInjectionStore;
 use crate::filesystem_store::FilesystemStore;
 use crate::grpc_store::GrpcStore;
 use crate::memory_store::MemoryStore;
 use crate::noop_store::NoopStore;
+use crate::rate_limit_store::RateLimitStore;
+use crate::read_cache_store::ReadCacheStore;
 use crate::redis_store::RedisStore;
 use crate::ref_store::RefStore;
 use crate::s3_store::S3Store;
 use crate::shard_store::ShardStore;
+use crate::singleflight_store::SingleflightStore;
 use crate::size_partitioning_store::SizePartitioningStore;
 use crate::store_manager::StoreManager;
+use crate::timeout_store::TimeoutStore;
 use crate::verify_store::VerifyStore;
 
 type FutureMaybeStore<'a> = Box<dyn Future<Output = Result<Store, Error>> + 'a>;
@@ -49,7 +59,13 @@ pub fn store_factory<'a>(
 ) -> Pin<FutureMaybeStore<'a>> {
     Box::pin(async move {
         let store: Arc<dyn StoreDriver> = match backend {
-            StoreSpec::memory(spec) => MemoryStore::new(spec),
+            StoreSpec::memory(spec) => {
+                let spill_store = match &spec.spill_store {
+                    Some(spill_spec) => Some(Store::new(<FilesystemStore>::new(spill_spec).await?)),
+                    None => None,
+                };
+                MemoryStore::new_with_spill_store(spec, spill_store)
+            }
             StoreSpec::experimental_s3_store(spec) => S3Store::new(spec, SystemTime::now).await?,
             StoreSpec::redis_store(spec) => RedisStore::new(spec.clone())?,
             StoreSpec::verify(spec) => VerifyStore::new(
@@ -65,13 +81,53 @@ pub fn store_factory<'a>(
                 store_factory(&spec.index_store, store_manager, None).await?,
                 store_factory(&spec.content_store, store_manager, None).await?,
             )?,
+            StoreSpec::chunked(spec) => ChunkedStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            )?,
             StoreSpec::existence_cache(spec) => ExistenceCacheStore::new(
                 spec,
                 store_factory(&spec.backend, store_manager, None).await?,
             ),
-            StoreSpec::completeness_checking(spec) => CompletenessCheckingStore::new(
+            StoreSpec::singleflight(spec) => SingleflightStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::read_cache(spec) => ReadCacheStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::timeout(spec) => TimeoutStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            ),
+            StoreSpec::rate_limit(spec) => RateLimitStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            )?,
+            #[cfg(feature = "fault_injection")]
+            StoreSpec::fault_injection(spec) => FaultInjectionStore::new(
+                spec,
+                store_factory(&spec.backend, store_manager, None).await?,
+            )?,
+            #[cfg(not(feature = "fault_injection"))]
+            StoreSpec::fault_injection(_) => return Err(fault_injection_disabled_err()),
+            StoreSpec::async_mirror(spec) => AsyncMirrorStore::new(
+                spec,
+                store_factory(&spec.primary, store_manager, None).await?,
+                store_factory(&spec.secondary, store_manager, None).await?,
+            ),
+            StoreSpec::completeness_checking(spec) => {
+                CompletenessCheckingStore::new_with_sample_percent(
+                    store_factory(&spec.backend, store_manager, None).await?,
+                    store_factory(&spec.cas_store, store_manager, None).await?,
+                    spec.verify_outputs_sample_percent,
+                )
+            }
+            StoreSpec::action_result_ttl(spec) => ActionResultTtlStore::new(
+                spec,
                 store_factory(&spec.backend, store_manager, None).await?,
-                store_factory(&spec.cas_store, store_manager, None).await?,
+                SystemTime::now,
             ),
             StoreSpec::fast_slow(spec) => FastSlowStore::new(
                 spec,
@@ -84,7 +140,7 @@ pub fn store_factory<'a>(
                 spec,
                 store_factory(&spec.lower_store, store_manager, None).await?,
                 store_factory(&spec.upper_store, store_manager, None).await?,
-            ),
+            )?,
             StoreSpec::grpc(spec) => GrpcStore::new(spec).await?,
             StoreSpec::noop(_) => NoopStore::new(),
             StoreSpec::shard(spec) => {
@@ -106,3 +162,181 @@ pub fn store_factory<'a>(
         Ok(Store::new(store))
     })
 }
+
+/// Checks the stores of a config without constructing them, so no sockets
+/// are opened and nothing is written to disk. Every problem found is
+/// returned instead of stopping at the first one.
+pub fn validate_store_specs(stores: &HashMap<StoreRefName, StoreSpec>) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let references = store_references(stores);
+    for (name, referenced_names) in &references {
+        validate_store_spec(name, &stores[*name], &mut errors);
+        for referenced_name in referenced_names {
+            if !stores.contains_key(*referenced_name) {
+                errors.push(make_input_err!(
+                    "Store '{name}' references store '{referenced_name}' in a 'ref_store', but no store with that name is configured"
+                ));
+            }
+        }
+    }
+    find_store_cycles(&references, &mut errors);
+    errors
+}
+
+/// Returns an error naming every cycle of stores referencing each other
+/// through `ref_store`s. A `RefStore` only looks up the store it references
+/// on first access, so this must be checked before the stores are wired
+/// together.
+pub fn check_ref_store_cycles(stores: &HashMap<StoreRefName, StoreSpec>) -> Result<(), Error> {
+    let mut errors = Vec::new();
+    find_store_cycles(&store_references(stores), &mut errors);
+    match errors.into_iter().reduce(Error::merge) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Maps the name of every store to the names referenced by the
+/// `ref_store`s nested in it. Sorted so that errors are reported in a
+/// stable order.
+fn store_references(stores: &HashMap<StoreRefName, StoreSpec>) -> BTreeMap<&str, Vec<&str>> {
+    stores
+        .iter()
+        .map(|(name, spec)| {
+            let mut referenced_names = Vec::new();
+            collect_ref_store_names(spec, &mut referenced_names);
+            (name.as_str(), referenced_names)
+        })
+        .collect()
+}
+
+fn collect_ref_store_names<'a>(spec: &'a StoreSpec, referenced_names: &mut Vec<&'a str>) {
+    if let StoreSpec::ref_store(spec) = spec {
+        referenced_names.push(&spec.name);
+    }
+    for nested_spec in nested_store_specs(spec) {
+        collect_ref_store_names(nested_spec, referenced_names);
+    }
+}
+
+/// Returns the stores directly wrapped by `spec`.
+fn nested_store_specs(spec: &StoreSpec) -> Vec<&StoreSpec> {
+    match spec {
+        StoreSpec::fast_slow(spec) => vec![&spec.fast, &spec.slow],
+        StoreSpec::shard(spec) => spec.stores.iter().map(|shard| &shard.store).collect(),
+        StoreSpec::verify(spec) => vec![&spec.backend],
+        StoreSpec::compression(spec) => vec![&spec.backend],
+        StoreSpec::existence_cache(spec) => vec![&spec.backend],
+        StoreSpec::singleflight(spec) => vec![&spec.backend],
+        StoreSpec::read_cache(spec) => vec![&spec.backend],
+        StoreSpec::timeout(spec) => vec![&spec.backend],
+        StoreSpec::rate_limit(spec) => vec![&spec.backend],
+        StoreSpec::fault_injection(spec) => vec![&spec.backend],
+        StoreSpec::async_mirror(spec) => vec![&spec.primary, &spec.secondary],
+        StoreSpec::dedup(spec) => vec![&spec.index_store, &spec.content_store],
+        StoreSpec::chunked(spec) => vec![&spec.backend],
+        StoreSpec::completeness_checking(spec) => vec![&spec.backend, &spec.cas_store],
+        StoreSpec::action_result_ttl(spec) => vec![&spec.backend],
+        StoreSpec::size_partitioning(spec) => vec![&spec.lower_store, &spec.upper_store],
+        StoreSpec::memory(_)
+        | StoreSpec::filesystem(_)
+        | StoreSpec::ref_store(_)
+        | StoreSpec::experimental_s3_store(_)
+        | StoreSpec::redis_store(_)
+        | StoreSpec::grpc(_)
+        | StoreSpec::noop(_) => Vec::new(),
+    }
+}
+
+/// Validates `spec` and its nested stores, which are part of the store
+/// called `name`.
+fn validate_store_spec(name: &str, spec: &StoreSpec, errors: &mut Vec<Error>) {
+    match spec {
+        StoreSpec::memory(spec) => {
+            if let Some(spill_spec) = &spec.spill_store {
+                validate_filesystem_spec(name, spill_spec, errors);
+            }
+        }
+        StoreSpec::filesystem(spec) => validate_filesystem_spec(name, spec, errors),
+        StoreSpec::fast_slow(spec) => {
+            if let (StoreSpec::ref_store(fast), StoreSpec::ref_store(slow)) =
+                (&spec.fast, &spec.slow)
+            {
+                if fast.name == slow.name {
+                    errors.push(make_input_err!(
+                        "The 'fast' and 'slow' stores of 'fast_slow' store '{name}' are both '{}'",
+                        fast.name
+                    ));
+                }
+            }
+        }
+        StoreSpec::shard(spec) if spec.stores.is_empty() => {
+            errors.push(make_input_err!(
+                "'shard' store '{name}' must have at least one store"
+            ));
+        }
+        StoreSpec::fault_injection(_) if !cfg!(feature = "fault_injection") => {
+            errors.push(fault_injection_disabled_err().append(format!("In store '{name}'")));
+        }
+        _ => {}
+    }
+    for nested_spec in nested_store_specs(spec) {
+        validate_store_spec(name, nested_spec, errors);
+    }
+}
+
+fn fault_injection_disabled_err() -> Error {
+    make_input_err!(
+        "'fault_injection' store requires nativelink to be built with the 'fault_injection' feature"
+    )
+}
+
+fn validate_filesystem_spec(name: &str, spec: &FilesystemSpec, errors: &mut Vec<Error>) {
+    if spec.content_path.is_empty() || spec.temp_path.is_empty() {
+        errors.push(make_input_err!(
+            "'filesystem' store in '{name}' must set both 'content_path' and 'temp_path'"
+        ));
+    } else if spec.content_path == spec.temp_path {
+        errors.push(make_input_err!(
+            "'filesystem' store in '{name}' must use different 'content_path' and 'temp_path', both are '{}'",
+            spec.content_path
+        ));
+    }
+}
+
+/// Reports every cycle of stores referencing each other through
+/// `ref_store`s, which would otherwise only be noticed when reading from
+/// one of the stores never returns.
+fn find_store_cycles(references: &BTreeMap<&str, Vec<&str>>, errors: &mut Vec<Error>) {
+    fn visit<'a>(
+        name: &'a str,
+        references: &BTreeMap<&'a str, Vec<&'a str>>,
+        path: &mut Vec<&'a str>,
+        visited: &mut HashSet<&'a str>,
+        errors: &mut Vec<Error>,
+    ) {
+        if let Some(cycle_start) = path.iter().position(|path_name| *path_name == name) {
+            let mut cycle = path[cycle_start..].to_vec();
+            cycle.push(name);
+            errors.push(make_input_err!(
+                "Stores reference each other in a cycle: {}",
+                cycle.join(" -> ")
+            ));
+            return;
+        }
+        if visited.contains(name) {
+            return;
+        }
+        path.push(name);
+        for referenced_name in references.get(name).into_iter().flatten() {
+            visit(referenced_name, references, path, visited, errors);
+        }
+        path.pop();
+        visited.insert(name);
+    }
+
+    let mut visited = HashSet::new();
+    for name in references.keys() {
+        visit(name, references, &mut Vec::new(), &mut visited, errors);
+    }
+}
@@ -164,6 +164,23 @@ impl<I: InstantWrapper> StoreDriver for ExistenceCacheStore<I> {
         self.inner_has_with_results(&digests, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        let digest = key.into_digest();
+        self.existence_cache.remove(&digest).await;
+        self.inner_store.remove(digest).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.existence_cache
+            .remove_matching(|digest| format!("{digest}").starts_with(prefix))
+            .await;
+        self.inner_store.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
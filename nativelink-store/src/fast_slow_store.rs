@@ -22,7 +22,7 @@ use std::sync::{Arc, Weak};
 use async_trait::async_trait;
 use futures::{join, FutureExt};
 use nativelink_config::stores::FastSlowSpec;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
@@ -33,6 +33,7 @@ use nativelink_util::store_trait::{
     slow_update_store_with_file, Store, StoreDriver, StoreKey, StoreLike, StoreOptimizations,
     UploadSizeInfo,
 };
+use tokio::sync::Semaphore;
 
 // TODO(blaise.bruer) This store needs to be evaluated for more efficient memory usage,
 // there are many copies happening internally.
@@ -48,16 +49,50 @@ pub struct FastSlowStore {
     #[metric(group = "slow_store")]
     slow_store: Store,
     weak_self: Weak<Self>,
+    /// Limits the number of concurrent copies from the slow store into the
+    /// fast store during reads. `None` if unlimited.
+    population_semaphore: Option<Semaphore>,
+    #[metric(help = "Uploads larger than this are only written to the slow store, 0 is unlimited")]
+    fast_store_max_write_size: u64,
     #[metric]
     metrics: FastSlowStoreMetrics,
 }
 
 impl FastSlowStore {
-    pub fn new(_spec: &FastSlowSpec, fast_store: Store, slow_store: Store) -> Arc<Self> {
+    pub fn new(spec: &FastSlowSpec, fast_store: Store, slow_store: Store) -> Arc<Self> {
+        Self::new_with_limits(
+            spec.max_concurrent_populations,
+            spec.fast_store_max_write_size,
+            fast_store,
+            slow_store,
+        )
+    }
+
+    pub fn new_with_max_concurrent_populations(
+        max_concurrent_populations: usize,
+        fast_store: Store,
+        slow_store: Store,
+    ) -> Arc<Self> {
+        Self::new_with_limits(max_concurrent_populations, 0, fast_store, slow_store)
+    }
+
+    fn new_with_limits(
+        max_concurrent_populations: usize,
+        fast_store_max_write_size: u64,
+        fast_store: Store,
+        slow_store: Store,
+    ) -> Arc<Self> {
+        let population_semaphore = if max_concurrent_populations == 0 {
+            None
+        } else {
+            Some(Semaphore::new(max_concurrent_populations))
+        };
         Arc::new_cyclic(|weak_self| Self {
             fast_store,
             slow_store,
             weak_self: weak_self.clone(),
+            population_semaphore,
+            fast_store_max_write_size,
             metrics: FastSlowStoreMetrics::default(),
         })
     }
@@ -74,6 +109,16 @@ impl FastSlowStore {
         self.weak_self.upgrade()
     }
 
+    /// If an upload is too large to be mirrored into the fast store.
+    fn skips_fast_store(&self, size_info: UploadSizeInfo) -> bool {
+        match size_info {
+            UploadSizeInfo::ExactSize(size) => {
+                self.fast_store_max_write_size != 0 && size > self.fast_store_max_write_size
+            }
+            UploadSizeInfo::MaxSize(_) => false,
+        }
+    }
+
     /// Ensure our fast store is populated. This should be kept as a low
     /// cost function. Since the data itself is shared and not copied it should be fairly
     /// low cost to just discard the data, but does cost a few mutex locks while
@@ -90,15 +135,154 @@ impl FastSlowStore {
         // TODO(blaise.bruer) This is extremely inefficient, since we are just trying
         // to send the stream to /dev/null. Maybe we could instead make a version of
         // the stream that can send to the drain more efficiently?
-        let (tx, mut rx) = make_buf_channel_pair();
+        let (mut tx, mut rx) = make_buf_channel_pair();
         let drain_fut = async move {
             while !rx.recv().await?.is_empty() {}
             Ok(())
         };
-        let (drain_res, get_res) = join!(drain_fut, StoreDriver::get(Pin::new(self), key, tx));
+        let (drain_res, get_res) = join!(
+            drain_fut,
+            self.get_part_inner(key, &mut tx, 0, None, true /* wait_for_population_permit */)
+        );
         get_res.err_tip(|| "Failed to populate()").merge(drain_res)
     }
 
+    /// Implementation of `get_part`. If `wait_for_population_permit` is set
+    /// the read waits for the fast store population limit instead of skipping
+    /// the population, which callers that require the data to end up in the
+    /// fast store rely on.
+    async fn get_part_inner(
+        &self,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+        wait_for_population_permit: bool,
+    ) -> Result<(), Error> {
+        // TODO(blaise.bruer) Investigate if we should maybe ignore errors here instead of
+        // forwarding the up.
+        if self.fast_store.has(key.borrow()).await?.is_some() {
+            self.metrics
+                .fast_store_hit_count
+                .fetch_add(1, Ordering::Acquire);
+            self.fast_store
+                .get_part(key, writer.borrow_mut(), offset, length)
+                .await?;
+            self.metrics
+                .fast_store_downloaded_bytes
+                .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
+            return Ok(());
+        }
+
+        let sz = self
+            .slow_store
+            .has(key.borrow())
+            .await
+            .err_tip(|| "Failed to run has() on slow store")?
+            .ok_or_else(|| {
+                make_err!(
+                    Code::NotFound,
+                    "Object {} not found in either fast or slow store",
+                    key.as_str()
+                )
+                .with_reason(ErrorReason::NotFound)
+            })?;
+        self.metrics
+            .slow_store_hit_count
+            .fetch_add(1, Ordering::Acquire);
+
+        // The permit is held until the fast store has been populated.
+        let _population_permit = match &self.population_semaphore {
+            Some(semaphore) if wait_for_population_permit => Some(
+                semaphore
+                    .acquire()
+                    .await
+                    .map_err(|e| make_err!(Code::Internal, "Population semaphore closed {e:?}"))?,
+            ),
+            Some(semaphore) => {
+                if let Ok(permit) = semaphore.try_acquire() {
+                    Some(permit)
+                } else {
+                    // Too many populations are in flight, so serve the read
+                    // directly from the slow store instead of adding more IO
+                    // to the fast store.
+                    self.metrics
+                        .unpopulated_slow_store_reads
+                        .fetch_add(1, Ordering::Acquire);
+                    self.slow_store
+                        .get_part(key, writer.borrow_mut(), offset, length)
+                        .await?;
+                    self.metrics
+                        .slow_store_downloaded_bytes
+                        .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
+                    return Ok(());
+                }
+            }
+            None => None,
+        };
+
+        let send_range = offset..length.map_or(u64::MAX, |length| length + offset);
+        let mut bytes_received: u64 = 0;
+
+        let (mut fast_tx, fast_rx) = make_buf_channel_pair();
+        let (slow_tx, mut slow_rx) = make_buf_channel_pair();
+        let data_stream_fut = async move {
+            let mut writer_pin = Pin::new(writer);
+            loop {
+                let output_buf = slow_rx
+                    .recv()
+                    .await
+                    .err_tip(|| "Failed to read data data buffer from slow store")?;
+                if output_buf.is_empty() {
+                    // Write out our EOF.
+                    // We are dropped as soon as we send_eof to writer_pin, so
+                    // we wait until we've finished all of our joins to do that.
+                    let fast_res = fast_tx.send_eof();
+                    return Ok::<_, Error>((fast_res, writer_pin));
+                }
+                let output_buf_len = u64::try_from(output_buf.len())
+                    .err_tip(|| "Could not output_buf.len() to u64")?;
+                self.metrics
+                    .slow_store_downloaded_bytes
+                    .fetch_add(output_buf_len, Ordering::Acquire);
+
+                let writer_fut = if let Some(range) = Self::calculate_range(
+                    &(bytes_received..bytes_received + output_buf_len),
+                    &send_range,
+                )? {
+                    writer_pin.send(output_buf.slice(range)).right_future()
+                } else {
+                    futures::future::ready(Ok(())).left_future()
+                };
+                bytes_received += output_buf_len;
+
+                let (fast_tx_res, writer_res) = join!(fast_tx.send(output_buf), writer_fut);
+                fast_tx_res.err_tip(|| "Failed to write to fast store in fast_slow store")?;
+                writer_res.err_tip(|| "Failed to write result to writer in fast_slow store")?;
+            }
+        };
+
+        let slow_store_fut = self.slow_store.get(key.borrow(), slow_tx);
+        let fast_store_fut =
+            self.fast_store
+                .update(key.borrow(), fast_rx, UploadSizeInfo::ExactSize(sz));
+
+        let (data_stream_res, slow_res, fast_res) =
+            join!(data_stream_fut, slow_store_fut, fast_store_fut);
+        match data_stream_res {
+            Ok((fast_eof_res, mut writer_pin)) =>
+            // Sending the EOF will drop us almost immediately in bytestream_server
+            // so we perform it as the very last action in this method.
+            {
+                fast_eof_res
+                    .merge(fast_res)
+                    .merge(slow_res)
+                    .merge(writer_pin.send_eof())
+            }
+            Err(err) => fast_res.merge(slow_res).merge(Err(err)),
+        }
+    }
+
     /// Returns the range of bytes that should be sent given a slice bounds
     /// offset so the output range maps the `received_range.start` to 0.
     // TODO(allada) This should be put into utils, as this logic is used
@@ -147,6 +331,38 @@ impl StoreDriver for FastSlowStore {
         self.slow_store.has_with_results(key, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        let (fast_res, slow_res) = join!(
+            self.fast_store.remove(key.borrow()),
+            self.slow_store.remove(key.borrow())
+        );
+        let fast_removed = fast_res.err_tip(|| "Failed to remove from fast store")?;
+        let slow_removed = slow_res.err_tip(|| "Failed to remove from slow store")?;
+        Ok(fast_removed || slow_removed)
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let (fast_res, slow_res) = join!(self.fast_store.touch(keys), self.slow_store.touch(keys));
+        let fast_touched = fast_res.err_tip(|| "Failed to touch fast store")?;
+        // Slow stores without eviction of their own, like S3, do not
+        // implement touch and have nothing to keep alive.
+        let slow_touched = match slow_res {
+            Err(err) if err.code == Code::Unimplemented => 0,
+            slow_res => slow_res.err_tip(|| "Failed to touch slow store")?,
+        };
+        Ok(fast_touched.max(slow_touched))
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        let (fast_res, slow_res) = join!(
+            self.fast_store.remove_prefix(prefix),
+            self.slow_store.remove_prefix(prefix)
+        );
+        let fast_removed = fast_res.err_tip(|| "Failed to remove prefix from fast store")?;
+        let slow_removed = slow_res.err_tip(|| "Failed to remove prefix from slow store")?;
+        Ok(fast_removed.max(slow_removed))
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -163,6 +379,12 @@ impl StoreDriver for FastSlowStore {
         if fast_store.optimized_for(StoreOptimizations::NoopUpdates) {
             return self.slow_store.update(key, reader, size_info).await;
         }
+        if self.skips_fast_store(size_info) {
+            self.metrics
+                .slow_store_only_writes
+                .fetch_add(1, Ordering::Acquire);
+            return self.slow_store.update(key, reader, size_info).await;
+        }
 
         let (mut fast_tx, fast_rx) = make_buf_channel_pair();
         let (mut slow_tx, slow_rx) = make_buf_channel_pair();
@@ -227,6 +449,15 @@ impl StoreDriver for FastSlowStore {
         mut file: fs::ResumeableFileSlot,
         upload_size: UploadSizeInfo,
     ) -> Result<Option<fs::ResumeableFileSlot>, Error> {
+        if self.skips_fast_store(upload_size) {
+            self.metrics
+                .slow_store_only_writes
+                .fetch_add(1, Ordering::Acquire);
+            return self
+                .slow_store
+                .update_with_whole_file(key, file, upload_size)
+                .await;
+        }
         if self
             .fast_store
             .optimized_for(StoreOptimizations::FileUpdates)
@@ -286,97 +517,8 @@ impl StoreDriver for FastSlowStore {
         offset: u64,
         length: Option<u64>,
     ) -> Result<(), Error> {
-        // TODO(blaise.bruer) Investigate if we should maybe ignore errors here instead of
-        // forwarding the up.
-        if self.fast_store.has(key.borrow()).await?.is_some() {
-            self.metrics
-                .fast_store_hit_count
-                .fetch_add(1, Ordering::Acquire);
-            self.fast_store
-                .get_part(key, writer.borrow_mut(), offset, length)
-                .await?;
-            self.metrics
-                .fast_store_downloaded_bytes
-                .fetch_add(writer.get_bytes_written(), Ordering::Acquire);
-            return Ok(());
-        }
-
-        let sz = self
-            .slow_store
-            .has(key.borrow())
+        self.get_part_inner(key, writer, offset, length, false)
             .await
-            .err_tip(|| "Failed to run has() on slow store")?
-            .ok_or_else(|| {
-                make_err!(
-                    Code::NotFound,
-                    "Object {} not found in either fast or slow store",
-                    key.as_str()
-                )
-            })?;
-        self.metrics
-            .slow_store_hit_count
-            .fetch_add(1, Ordering::Acquire);
-
-        let send_range = offset..length.map_or(u64::MAX, |length| length + offset);
-        let mut bytes_received: u64 = 0;
-
-        let (mut fast_tx, fast_rx) = make_buf_channel_pair();
-        let (slow_tx, mut slow_rx) = make_buf_channel_pair();
-        let data_stream_fut = async move {
-            let mut writer_pin = Pin::new(writer);
-            loop {
-                let output_buf = slow_rx
-                    .recv()
-                    .await
-                    .err_tip(|| "Failed to read data data buffer from slow store")?;
-                if output_buf.is_empty() {
-                    // Write out our EOF.
-                    // We are dropped as soon as we send_eof to writer_pin, so
-                    // we wait until we've finished all of our joins to do that.
-                    let fast_res = fast_tx.send_eof();
-                    return Ok::<_, Error>((fast_res, writer_pin));
-                }
-                let output_buf_len = u64::try_from(output_buf.len())
-                    .err_tip(|| "Could not output_buf.len() to u64")?;
-                self.metrics
-                    .slow_store_downloaded_bytes
-                    .fetch_add(output_buf_len, Ordering::Acquire);
-
-                let writer_fut = if let Some(range) = Self::calculate_range(
-                    &(bytes_received..bytes_received + output_buf_len),
-                    &send_range,
-                )? {
-                    writer_pin.send(output_buf.slice(range)).right_future()
-                } else {
-                    futures::future::ready(Ok(())).left_future()
-                };
-                bytes_received += output_buf_len;
-
-                let (fast_tx_res, writer_res) = join!(fast_tx.send(output_buf), writer_fut);
-                fast_tx_res.err_tip(|| "Failed to write to fast store in fast_slow store")?;
-                writer_res.err_tip(|| "Failed to write result to writer in fast_slow store")?;
-            }
-        };
-
-        let slow_store_fut = self.slow_store.get(key.borrow(), slow_tx);
-        let fast_store_fut =
-            self.fast_store
-                .update(key.borrow(), fast_rx, UploadSizeInfo::ExactSize(sz));
-
-        let (data_stream_res, slow_res, fast_res) =
-            join!(data_stream_fut, slow_store_fut, fast_store_fut);
-        match data_stream_res {
-            Ok((fast_eof_res, mut writer_pin)) =>
-            // Sending the EOF will drop us almost immediately in bytestream_server
-            // so we perform it as the very last action in this method.
-            {
-                fast_eof_res
-                    .merge(fast_res)
-                    .merge(slow_res)
-                    .merge(writer_pin.send_eof())
-            }
-            Err(err) => fast_res.merge(slow_res).merge(Err(err)),
-        }
     }
 
     fn inner_store(&self, _key: Option<StoreKey>) -> &dyn StoreDriver {
@@ -402,6 +544,10 @@ struct FastSlowStoreMetrics {
     slow_store_hit_count: AtomicU64,
     #[metric(help = "Downloaded bytes from the slow store")]
     slow_store_downloaded_bytes: AtomicU64,
+    #[metric(help = "Slow store reads that skipped populating the fast store")]
+    unpopulated_slow_store_reads: AtomicU64,
+    #[metric(help = "Uploads that were too large to be written to the fast store")]
+    slow_store_only_writes: AtomicU64,
 }
 
 default_health_status_indicator!(FastSlowStore);
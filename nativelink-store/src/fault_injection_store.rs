@@ -0,0 +1,219 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::join;
+use nativelink_config::stores::FaultInjectionSpec;
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+use rand::rngs::{OsRng, StdRng};
+use rand::{Rng, SeedableRng};
+use tokio::time::sleep;
+
+#[derive(MetricsComponent)]
+pub struct FaultInjectionStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Fraction of operations that fail without reaching the inner store")]
+    error_rate: f64,
+    #[metric(help = "Fraction of reads that return truncated data")]
+    truncated_read_rate: f64,
+    #[metric(help = "Minimum latency added to every operation")]
+    min_latency: Duration,
+    #[metric(help = "Maximum latency added to every operation")]
+    max_latency: Duration,
+    rng: Mutex<StdRng>,
+
+    // Metrics.
+    #[metric(help = "Number of operations failed by the fault injection store")]
+    injected_errors: AtomicU64,
+    #[metric(help = "Number of reads truncated by the fault injection store")]
+    truncated_reads: AtomicU64,
+}
+
+impl FaultInjectionStore {
+    pub fn new(spec: &FaultInjectionSpec, inner_store: Store) -> Result<Arc<Self>, Error> {
+        if !(0.0..=1.0).contains(&spec.error_rate) {
+            return Err(make_input_err!(
+                "'error_rate' must be between 0.0 and 1.0, got {}",
+                spec.error_rate
+            ));
+        }
+        if !(0.0..=1.0).contains(&spec.truncated_read_rate) {
+            return Err(make_input_err!(
+                "'truncated_read_rate' must be between 0.0 and 1.0, got {}",
+                spec.truncated_read_rate
+            ));
+        }
+        if spec.min_latency_ms > spec.max_latency_ms {
+            return Err(make_input_err!(
+                "'min_latency_ms' ({}) must not be larger than 'max_latency_ms' ({})",
+                spec.min_latency_ms,
+                spec.max_latency_ms
+            ));
+        }
+        let seed = spec.seed.unwrap_or_else(|| OsRng.gen());
+        Ok(Arc::new(Self {
+            inner_store,
+            error_rate: spec.error_rate,
+            truncated_read_rate: spec.truncated_read_rate,
+            min_latency: Duration::from_millis(spec.min_latency_ms),
+            max_latency: Duration::from_millis(spec.max_latency_ms),
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            injected_errors: AtomicU64::new(0),
+            truncated_reads: AtomicU64::new(0),
+        }))
+    }
+
+    /// Returns true with a probability of `rate`.
+    fn roll(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.lock().gen::<f64>() < rate
+    }
+
+    /// Sleeps for the configured latency, then fails `operation` with a
+    /// probability of `error_rate`.
+    async fn inject_faults(&self, operation: &str) -> Result<(), Error> {
+        let latency = if self.max_latency > self.min_latency {
+            self.rng
+                .lock()
+                .gen_range(self.min_latency..=self.max_latency)
+        } else {
+            self.min_latency
+        };
+        if !latency.is_zero() {
+            sleep(latency).await;
+        }
+        if self.roll(self.error_rate) {
+            self.injected_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(make_err!(
+                Code::Unavailable,
+                "Injected error in FaultInjectionStore::{operation}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Forwards the first half of the first chunk read from the inner store,
+    /// then fails as if the connection to the backend was lost.
+    async fn truncated_get_part(
+        &self,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.truncated_reads.fetch_add(1, Ordering::Relaxed);
+        let (mut tx, mut rx) = make_buf_channel_pair();
+        let forward_fut = async move {
+            let chunk = rx
+                .recv()
+                .await
+                .err_tip(|| "In FaultInjectionStore::get_part")?;
+            let truncated_chunk = chunk.slice(..chunk.len() / 2);
+            if !truncated_chunk.is_empty() {
+                writer
+                    .send(truncated_chunk)
+                    .await
+                    .err_tip(|| "Failed to write data in FaultInjectionStore::get_part")?;
+            }
+            // Dropping `rx` stops the inner store from reading further.
+            Ok::<_, Error>(())
+        };
+        let (_, forward_result) = join!(
+            self.inner_store.get_part(key, &mut tx, offset, length),
+            forward_fut
+        );
+        forward_result?;
+        Err(make_err!(
+            Code::Unavailable,
+            "Injected truncated read in FaultInjectionStore::get_part"
+        ))
+    }
+}
+
+#[async_trait]
+impl StoreDriver for FaultInjectionStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inject_faults("has_with_results").await?;
+        self.inner_store.has_with_results(keys, results).await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inject_faults("remove").await?;
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inject_faults("touch").await?;
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inject_faults("remove_prefix").await?;
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inject_faults("update").await?;
+        self.inner_store.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.inject_faults("get_part").await?;
+        if self.roll(self.truncated_read_rate) {
+            return self.truncated_get_part(key, writer, offset, length).await;
+        }
+        self.inner_store.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(FaultInjectionStore);
@@ -12,37 +12,45 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::borrow::Cow;
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Debug, Formatter};
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
-use async_lock::RwLock;
+use async_lock::{Mutex as AsyncMutex, RwLock};
 use async_trait::async_trait;
-use bytes::BytesMut;
+use bytes::{BufMut, BytesMut};
 use filetime::{set_file_atime, FileTime};
 use futures::stream::{StreamExt, TryStreamExt};
 use futures::{Future, TryFutureExt};
 use nativelink_config::stores::FilesystemSpec;
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
 };
 use nativelink_util::common::{fs, DigestInfo};
-use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::digest_hasher::{
+    default_digest_hasher_func, DigestHasher, ACTIVE_HASHER_FUNC,
+};
+use nativelink_util::evicting_map::{with_default_evict_bytes, EvictingMap, LenEntry};
 use nativelink_util::health_utils::{HealthRegistryBuilder, HealthStatus, HealthStatusIndicator};
+use nativelink_util::origin_context::ActiveOriginContext;
 use nativelink_util::store_trait::{
     StoreDriver, StoreKey, StoreKeyBorrow, StoreOptimizations, UploadSizeInfo,
 };
 use nativelink_util::{background_spawn, spawn_blocking};
+use parking_lot::Mutex;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
 use tokio::time::{sleep, timeout, Sleep};
 use tokio_stream::wrappers::ReadDirStream;
 use tracing::{event, Level};
+use uuid::Uuid;
 
 use crate::cas_utils::is_zero_digest;
 
@@ -50,10 +58,16 @@ use crate::cas_utils::is_zero_digest;
 const DEFAULT_BUFF_SIZE: usize = 32 * 1024;
 // Default block size of all major filesystems is 4KB
 const DEFAULT_BLOCK_SIZE: u64 = 4 * 1024;
+// Default time after which a partial upload no range was written to is deleted.
+// NOTE: If this changes update the comments in `stores.rs`.
+const DEFAULT_PARTIAL_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
 
 pub const STR_FOLDER: &str = "s";
 pub const DIGEST_FOLDER: &str = "d";
 
+/// If this value changes update the documentation in the config definition.
+const MAX_CONTENT_SHARD_DEPTH: usize = 4;
+
 #[derive(Clone, Copy, Debug)]
 pub enum FileType {
     Digest,
@@ -68,10 +82,18 @@ pub struct SharedContext {
     // deleted (similar to how it is done in tests).
     #[metric(help = "Number of active drop spawns")]
     pub active_drop_spawns: AtomicU64,
+    // Only evictions are counted, files that are replaced by a new upload
+    // or removed explicitly are not.
+    #[metric(help = "Number of files evicted from the content path")]
+    pub evicted_files: AtomicU64,
+    #[metric(help = "Number of bytes on disk evicted from the content path")]
+    pub evicted_bytes: AtomicU64,
     #[metric(help = "Path to the configured temp path")]
     temp_path: String,
     #[metric(help = "Path to the configured content path")]
     content_path: String,
+    #[metric(help = "Number of subdirectory levels digest files are sharded into")]
+    content_shard_depth: usize,
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -105,12 +127,15 @@ fn get_file_path_raw<'a>(
     shared_context: &SharedContext,
     key: &StoreKey<'a>,
 ) -> Cow<'a, OsStr> {
-    let folder = match path_type {
-        PathType::Content => &shared_context.content_path,
-        PathType::Temp => &shared_context.temp_path,
+    let (folder, shard_depth) = match path_type {
+        PathType::Content => (
+            &shared_context.content_path,
+            shared_context.content_shard_depth,
+        ),
+        PathType::Temp => (&shared_context.temp_path, 0),
         PathType::Custom(path) => return Cow::Borrowed(path),
     };
-    Cow::Owned(to_full_path_from_key(folder, key))
+    Cow::Owned(to_full_path_from_key(folder, key, shard_depth))
 }
 
 impl Drop for EncodedFilePath {
@@ -151,15 +176,35 @@ impl Drop for EncodedFilePath {
 ///
 /// Previously, only the string representation of the [`DigestInfo`] was
 /// used with no prefix
+///
+/// Digests are placed `shard_depth` subdirectories deep, see
+/// [`to_digest_path_from_file_name`].
 #[inline]
-fn to_full_path_from_key(folder: &str, key: &StoreKey<'_>) -> OsString {
+fn to_full_path_from_key(folder: &str, key: &StoreKey<'_>, shard_depth: usize) -> OsString {
     match key {
         StoreKey::Str(str) => format!("{folder}/{STR_FOLDER}/{str}"),
-        StoreKey::Digest(digest_info) => format!("{folder}/{DIGEST_FOLDER}/{digest_info}"),
+        StoreKey::Digest(digest_info) => {
+            to_digest_path_from_file_name(folder, &digest_info.to_string(), shard_depth)
+        }
     }
     .into()
 }
 
+/// Path of the digest file `file_name` below `folder`. Each level of
+/// `shard_depth` adds a subdirectory named after the next two hex chars of
+/// the hash, eg: `{folder}/d/ab/cd/abcd...-5` for a depth of 2.
+/// `file_name` must be a valid digest file name.
+#[inline]
+fn to_digest_path_from_file_name(folder: &str, file_name: &str, shard_depth: usize) -> String {
+    let mut path = format!("{folder}/{DIGEST_FOLDER}/");
+    for level in 0..shard_depth {
+        path.push_str(&file_name[level * 2..level * 2 + 2]);
+        path.push('/');
+    }
+    path.push_str(file_name);
+    path
+}
+
 pub trait FileEntry: LenEntry + Send + Sync + Debug + 'static {
     /// Responsible for creating the underlying `FileEntry`.
     fn create(data_size: u64, block_size: u64, encoded_file_path: RwLock<EncodedFilePath>) -> Self;
@@ -172,6 +217,9 @@ pub trait FileEntry: LenEntry + Send + Sync + Debug + 'static {
     where
         Self: Sized;
 
+    /// Returns the size of the data in bytes.
+    fn data_size(&self) -> u64;
+
     /// Returns the underlying reference to the size of the data in bytes
     fn data_size_mut(&mut self) -> &mut u64;
 
@@ -264,6 +312,10 @@ impl FileEntry for FileEntryImpl {
         ))
     }
 
+    fn data_size(&self) -> u64 {
+        self.data_size
+    }
+
     fn data_size_mut(&mut self) -> &mut u64 {
         &mut self.data_size
     }
@@ -327,12 +379,13 @@ impl Debug for FileEntryImpl {
 fn make_temp_digest(mut digest: DigestInfo) -> DigestInfo {
     static DELETE_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
     let mut hash = *digest.packed_hash();
-    hash[24..].clone_from_slice(
+    let counter_start = hash.len() - size_of::<u64>();
+    hash[counter_start..].clone_from_slice(
         &DELETE_FILE_COUNTER
             .fetch_add(1, Ordering::Relaxed)
             .to_le_bytes(),
     );
-    digest.set_packed_hash(*hash);
+    digest.set_packed_hash(hash);
     digest
 }
 
@@ -394,7 +447,7 @@ impl LenEntry for FileEntryImpl {
             let new_key = make_temp_key(&encoded_file_path.key);
 
             let to_path =
-                to_full_path_from_key(&encoded_file_path.shared_context.temp_path, &new_key);
+                to_full_path_from_key(&encoded_file_path.shared_context.temp_path, &new_key, 0);
 
             if let Err(err) = fs::rename(&from_path, &to_path).await {
                 event!(
@@ -411,6 +464,7 @@ impl LenEntry for FileEntryImpl {
                     key = ?encoded_file_path.key,
                     ?from_path,
                     ?to_path,
+                    size_on_disk = self.size_on_disk(),
                     "Renamed file",
                 );
                 encoded_file_path.path_type = PathType::Temp;
@@ -418,6 +472,17 @@ impl LenEntry for FileEntryImpl {
             }
         }
     }
+
+    async fn evict(&self) {
+        {
+            let shared_context = &self.encoded_file_path.read().await.shared_context;
+            shared_context.evicted_files.fetch_add(1, Ordering::Relaxed);
+            shared_context
+                .evicted_bytes
+                .fetch_add(self.size_on_disk(), Ordering::Relaxed);
+        }
+        self.unref().await;
+    }
 }
 
 #[inline]
@@ -508,9 +573,9 @@ async fn add_files_to_cache<Fe: FileEntry>(
                     .metadata()
                     .await
                     .err_tip(|| "Failed to get metadata in filesystem store")?;
-                // We need to filter out folders - we do not want to try to cache the s and d folders.
-                let is_file =
-                    metadata.is_file() || !(file_name == STR_FOLDER || file_name == DIGEST_FOLDER);
+                // We need to filter out folders - we do not want to try to cache the s and d
+                // folders or the shard folders of digests.
+                let is_file = !metadata.is_dir();
                 let atime = match metadata.accessed() {
                     Ok(atime) => atime,
                     Err(err) => {
@@ -569,22 +634,83 @@ async fn add_files_to_cache<Fe: FileEntry>(
         Ok(())
     }
 
+    /// Walks all shard directories below [`DIGEST_FOLDER`] and moves the
+    /// digest files that are not in the directory `content_shard_depth`
+    /// expects into place, so the layout can be changed between restarts.
+    /// Files that are not valid digests are deleted. Returns the digest files,
+    /// all of which are now in place.
+    async fn reshard_digest_files(
+        shared_context: &Arc<SharedContext>,
+        rename_fn: fn(&OsStr, &OsStr) -> Result<(), std::io::Error>,
+    ) -> Result<Vec<(String, SystemTime, u64, bool)>, Error> {
+        let content_path = &shared_context.content_path;
+        let shard_depth = shared_context.content_shard_depth;
+        // The whole tree is listed before anything is moved, so files moved
+        // into a directory that is yet to be walked are not seen twice.
+        let mut found_files = Vec::new();
+        let mut dirs = vec![DIGEST_FOLDER.to_string()];
+        while let Some(dir) = dirs.pop() {
+            for file_info in read_files(Some(&dir), shared_context).await? {
+                if file_info.3 {
+                    found_files.push((dir.clone(), file_info));
+                } else {
+                    dirs.push(format!("{dir}/{}", file_info.0));
+                }
+            }
+        }
+        let mut digest_files = Vec::with_capacity(found_files.len());
+        for (dir, file_info) in found_files {
+            let file_name = &file_info.0;
+            let from_file = format!("{content_path}/{dir}/{file_name}");
+            if let Err(err) = digest_from_filename(file_name) {
+                event!(
+                    Level::WARN,
+                    ?from_file,
+                    ?err,
+                    "Failed to add file to eviction cache",
+                );
+                // Ignore result.
+                let _ = fs::remove_file(&from_file).await;
+                continue;
+            }
+            let to_file = to_digest_path_from_file_name(content_path, file_name, shard_depth);
+            if from_file != to_file {
+                if let Some(parent) = Path::new(&to_file).parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .err_tip(|| format!("Failed to create directory {parent:?}"))?;
+                }
+                if let Err(err) = rename_fn(from_file.as_ref(), to_file.as_ref()) {
+                    event!(
+                        Level::WARN,
+                        ?from_file,
+                        ?to_file,
+                        ?err,
+                        "Failed to rename file",
+                    );
+                    continue;
+                }
+                event!(Level::INFO, ?from_file, ?to_file, "Renamed file",);
+            }
+            digest_files.push(file_info);
+        }
+        Ok(digest_files)
+    }
+
     async fn add_files_to_cache<Fe: FileEntry>(
         evicting_map: &EvictingMap<StoreKeyBorrow, Arc<Fe>, SystemTime>,
         anchor_time: &SystemTime,
         shared_context: &Arc<SharedContext>,
         block_size: u64,
         folder: &str,
+        file_infos: Vec<(String, SystemTime, u64, bool)>,
     ) -> Result<(), Error> {
-        let file_infos = read_files(Some(folder), shared_context).await?;
         let file_type = match folder {
             STR_FOLDER => FileType::String,
             DIGEST_FOLDER => FileType::Digest,
             _ => panic!("Invalid folder type"),
         };
 
-        let path_root = format!("{}/{folder}", shared_context.content_path);
-
         for (file_name, atime, data_size, _) in file_infos.into_iter().filter(|x| x.3) {
             let result = process_entry(
                 evicting_map,
@@ -604,8 +730,18 @@ async fn add_files_to_cache<Fe: FileEntry>(
                     ?err,
                     "Failed to add file to eviction cache",
                 );
+                let file_path = match file_type {
+                    FileType::String => {
+                        format!("{}/{STR_FOLDER}/{file_name}", shared_context.content_path)
+                    }
+                    FileType::Digest => to_digest_path_from_file_name(
+                        &shared_context.content_path,
+                        &file_name,
+                        shared_context.content_shard_depth,
+                    ),
+                };
                 // Ignore result.
-                let _ = fs::remove_file(format!("{path_root}/{file_name}")).await;
+                let _ = fs::remove_file(file_path).await;
             }
         }
         Ok(())
@@ -619,6 +755,7 @@ async fn add_files_to_cache<Fe: FileEntry>(
         shared_context,
         block_size,
         DIGEST_FOLDER,
+        reshard_digest_files(shared_context, rename_fn).await?,
     )
     .await?;
 
@@ -628,11 +765,50 @@ async fn add_files_to_cache<Fe: FileEntry>(
         shared_context,
         block_size,
         STR_FOLDER,
+        read_files(Some(STR_FOLDER), shared_context).await?,
     )
     .await?;
     Ok(())
 }
 
+/// Uploads are finished by renaming files from `temp_path` into
+/// `content_path`, which is only possible if both are on the same device.
+#[cfg(target_family = "unix")]
+async fn verify_same_device(temp_path: &str, content_path: &str) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+    let temp_device = fs::metadata(temp_path)
+        .await
+        .err_tip(|| format!("Failed to read metadata of temp_path {temp_path}"))?
+        .dev();
+    let content_device = fs::metadata(content_path)
+        .await
+        .err_tip(|| format!("Failed to read metadata of content_path {content_path}"))?
+        .dev();
+    if temp_device != content_device {
+        return Err(make_input_err!(
+            "temp_path {temp_path} and content_path {content_path} of a filesystem store must be on the same device so files can be moved atomically, but they are on devices {temp_device} and {content_device}"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_family = "unix"))]
+async fn verify_same_device(_temp_path: &str, _content_path: &str) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Syncs the file or directory at `path` to disk on a blocking thread.
+async fn sync_path(path: &Path) -> Result<(), Error> {
+    let path = path.to_path_buf();
+    spawn_blocking!("filesystem_store_sync_path", move || {
+        std::fs::File::open(&path)
+            .and_then(|file| file.sync_all())
+            .err_tip(|| format!("Failed to sync {path:?} in filesystem store"))
+    })
+    .await
+    .err_tip(|| "Failed to spawn sync in filesystem store")?
+}
+
 async fn prune_temp_path(temp_path: &str) -> Result<(), Error> {
     async fn prune_temp_inner(temp_path: &str, subpath: &str) -> Result<(), Error> {
         let (_permit, dir_handle) = fs::read_dir(format!("{temp_path}/{subpath}"))
@@ -657,6 +833,81 @@ async fn prune_temp_path(temp_path: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Writes the data from `reader` to `resumeable_temp_file` at its current
+/// position until EOF and returns the number of bytes written.
+async fn write_to_file(
+    resumeable_temp_file: &mut fs::ResumeableFileSlot,
+    reader: &mut DropCloserReadHalf,
+) -> Result<u64, Error> {
+    let mut data_size = 0;
+    loop {
+        let Ok(data_result) = timeout(fs::idle_file_descriptor_timeout(), reader.recv()).await
+        else {
+            // In the event we timeout, we want to close the writing file, to prevent
+            // the file descriptor left open for long periods of time.
+            // This is needed because we wrap `fs` so only a fixed number of file
+            // descriptors may be open at any given time. If we are streaming from
+            // File -> File, it can cause a deadlock if the Write file is not sending
+            // data because it is waiting for a file descriotor to open before sending data.
+            resumeable_temp_file.close_file().await.err_tip(|| {
+                "Could not close file due to timeout in FileSystemStore::update_file"
+            })?;
+            continue;
+        };
+        let mut data = data_result.err_tip(|| "Failed to receive data in filesystem store")?;
+        let data_len = data.len();
+        if data_len == 0 {
+            break; // EOF.
+        }
+        resumeable_temp_file
+            .as_writer()
+            .await
+            .err_tip(|| "in filesystem_store::update_file")?
+            .write_all_buf(&mut data)
+            .await
+            .err_tip(|| "Failed to write data into filesystem store")?;
+        data_size += data_len as u64;
+    }
+    Ok(data_size)
+}
+
+/// A blob `update_at_offset` is assembling in a temp file. It is not in the
+/// store until every byte of it was written.
+struct PartialUpload<Fe> {
+    entry: Fe,
+    temp_file: fs::ResumeableFileSlot,
+    temp_full_path: OsString,
+    // Start to end of the ranges written so far, touching ranges are merged.
+    written_ranges: BTreeMap<u64, u64>,
+    // When a range was last written, used to find abandoned uploads.
+    last_write: Instant,
+}
+
+impl<Fe> PartialUpload<Fe> {
+    fn add_written_range(&mut self, mut start: u64, mut end: u64) {
+        if start == end {
+            return;
+        }
+        let merged_starts: Vec<u64> = self
+            .written_ranges
+            .range(..=end)
+            .filter(|(_, range_end)| **range_end >= start)
+            .map(|(range_start, _)| *range_start)
+            .collect();
+        for range_start in merged_starts {
+            if let Some(range_end) = self.written_ranges.remove(&range_start) {
+                start = start.min(range_start);
+                end = end.max(range_end);
+            }
+        }
+        self.written_ranges.insert(start, end);
+    }
+
+    fn is_complete(&self, size: u64) -> bool {
+        size == 0 || self.written_ranges.get(&0) == Some(&size)
+    }
+}
+
 #[derive(MetricsComponent)]
 pub struct FilesystemStore<Fe: FileEntry = FileEntryImpl> {
     #[metric]
@@ -667,6 +918,21 @@ pub struct FilesystemStore<Fe: FileEntry = FileEntryImpl> {
     block_size: u64,
     #[metric(help = "Size of the configured read buffer size")]
     read_buffer_size: usize,
+    #[metric(help = "If reads advise the kernel that files are read sequentially")]
+    sequential_read_advice: bool,
+    #[metric(help = "If renamed files and their directories are synced to disk")]
+    sync_renames: bool,
+    #[metric(help = "If whole file reads are verified against their digest")]
+    verify_on_read: bool,
+    #[metric(help = "If entries failing read verification are evicted")]
+    evict_on_verify_failure: bool,
+    #[metric(help = "Number of reads that failed digest verification")]
+    verify_on_read_failures: AtomicU64,
+    // Blobs being assembled by `update_at_offset`. Each is locked while a
+    // range is written to it.
+    partial_uploads: Mutex<HashMap<DigestInfo, Arc<AsyncMutex<Option<PartialUpload<Fe>>>>>>,
+    #[metric(help = "Time after which a partial upload no range was written to is deleted")]
+    partial_upload_timeout: Duration,
     weak_self: Weak<Self>,
     sleep_fn: fn(Duration) -> Sleep,
     rename_fn: fn(&OsStr, &OsStr) -> Result<(), std::io::Error>,
@@ -695,18 +961,29 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         let now = SystemTime::now();
 
         let empty_policy = nativelink_config::stores::EvictionPolicy::default();
-        let eviction_policy = spec.eviction_policy.as_ref().unwrap_or(&empty_policy);
-        let evicting_map = Arc::new(EvictingMap::new(eviction_policy, now));
+        let eviction_policy =
+            with_default_evict_bytes(spec.eviction_policy.as_ref().unwrap_or(&empty_policy));
+        let evicting_map = Arc::new(EvictingMap::new(&eviction_policy, now));
+
+        error_if!(
+            spec.content_shard_depth > MAX_CONTENT_SHARD_DEPTH,
+            "content_shard_depth of a filesystem store must be at most {MAX_CONTENT_SHARD_DEPTH}, got {}",
+            spec.content_shard_depth
+        );
 
         // Create temp and content directories and the s and d subdirectories.
 
         create_subdirs(&spec.temp_path).await?;
         create_subdirs(&spec.content_path).await?;
+        verify_same_device(&spec.temp_path, &spec.content_path).await?;
 
         let shared_context = Arc::new(SharedContext {
             active_drop_spawns: AtomicU64::new(0),
+            evicted_files: AtomicU64::new(0),
+            evicted_bytes: AtomicU64::new(0),
             temp_path: spec.temp_path.clone(),
             content_path: spec.content_path.clone(),
+            content_shard_depth: spec.content_shard_depth,
         });
 
         let block_size = if spec.block_size == 0 {
@@ -729,15 +1006,106 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         } else {
             spec.read_buffer_size as usize
         };
-        Ok(Arc::new_cyclic(|weak_self| Self {
+        let partial_upload_timeout = if spec.partial_upload_timeout_s == 0 {
+            DEFAULT_PARTIAL_UPLOAD_TIMEOUT
+        } else {
+            Duration::from_secs(spec.partial_upload_timeout_s)
+        };
+        let store = Arc::new_cyclic(|weak_self| Self {
             shared_context,
             evicting_map,
             block_size,
             read_buffer_size,
+            sequential_read_advice: spec.sequential_read_advice,
+            sync_renames: spec.sync_renames,
+            verify_on_read: spec.verify_on_read,
+            evict_on_verify_failure: spec.evict_on_verify_failure,
+            verify_on_read_failures: AtomicU64::new(0),
+            partial_uploads: Mutex::new(HashMap::new()),
+            partial_upload_timeout,
             weak_self: weak_self.clone(),
             sleep_fn,
             rename_fn,
-        }))
+        });
+
+        // Uploads are also swept when a range is written, this covers stores
+        // that stop receiving ranges altogether.
+        let weak_store = Arc::downgrade(&store);
+        background_spawn!(
+            "filesystem_store_remove_abandoned_partial_uploads",
+            async move {
+                loop {
+                    sleep(partial_upload_timeout).await;
+                    let Some(store) = weak_store.upgrade() else {
+                        return;
+                    };
+                    store.remove_abandoned_partial_uploads();
+                }
+            }
+        );
+        Ok(store)
+    }
+
+    /// Deletes the partial uploads no range was written to for
+    /// `partial_upload_timeout`. Uploads a range is being written to are
+    /// locked and kept.
+    fn remove_abandoned_partial_uploads(&self) {
+        self.partial_uploads
+            .lock()
+            .retain(|digest, partial_upload_slot| {
+                let Some(mut partial_upload_guard) = partial_upload_slot.try_lock() else {
+                    return true;
+                };
+                let Some(partial_upload) = &*partial_upload_guard else {
+                    return true;
+                };
+                if partial_upload.last_write.elapsed() < self.partial_upload_timeout {
+                    return true;
+                }
+                event!(
+                    Level::WARN,
+                    ?digest,
+                    written_ranges = ?partial_upload.written_ranges,
+                    "Deleting abandoned partial upload in filesystem store",
+                );
+                // Dropping the entry deletes the file.
+                partial_upload_guard.take();
+                false
+            });
+    }
+
+    /// Creates a file in `temp_path` and moves it into `content_path`. Uploads
+    /// are finished with a rename between these paths, which requires both to
+    /// be writable and on the same filesystem.
+    async fn check_temp_to_content_rename(&self) -> Result<(), Error> {
+        let file_name = format!("health_check-{}", Uuid::new_v4());
+        let temp_file: OsString = format!("{}/{file_name}", self.shared_context.temp_path).into();
+        let content_file: OsString =
+            format!("{}/{file_name}", self.shared_context.content_path).into();
+
+        drop(fs::create_file(&temp_file).await.err_tip(|| {
+            format!(
+                "Failed to create file in temp_path {}",
+                self.shared_context.temp_path
+            )
+        })?);
+        if let Err(err) = (self.rename_fn)(&temp_file, &content_file) {
+            // Ignore result, the rename error is more useful.
+            let _ = fs::remove_file(&temp_file).await;
+            return Err(make_err!(
+                Code::FailedPrecondition,
+                "Failed to move a file from temp_path {} to content_path {}, they must be writable and on the same filesystem: {err:?}",
+                self.shared_context.temp_path,
+                self.shared_context.content_path,
+            ));
+        }
+        fs::remove_file(&content_file)
+            .await
+            .err_tip(|| format!("Failed to remove health check file {content_file:?}"))
+    }
+
+    pub fn get_shared_context_for_test(&self) -> Arc<SharedContext> {
+        self.shared_context.clone()
     }
 
     pub fn get_arc(&self) -> Option<Arc<Self>> {
@@ -748,7 +1116,10 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         self.evicting_map
             .get::<StoreKey<'static>>(&digest.into())
             .await
-            .ok_or_else(|| make_err!(Code::NotFound, "{digest} not found in filesystem store"))
+            .ok_or_else(|| {
+                make_err!(Code::NotFound, "{digest} not found in filesystem store")
+                    .with_reason(ErrorReason::NotFound)
+            })
     }
 
     async fn update_file<'a>(
@@ -758,35 +1129,7 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         final_key: StoreKey<'static>,
         mut reader: DropCloserReadHalf,
     ) -> Result<(), Error> {
-        let mut data_size = 0;
-        loop {
-            let Ok(data_result) = timeout(fs::idle_file_descriptor_timeout(), reader.recv()).await
-            else {
-                // In the event we timeout, we want to close the writing file, to prevent
-                // the file descriptor left open for long periods of time.
-                // This is needed because we wrap `fs` so only a fixed number of file
-                // descriptors may be open at any given time. If we are streaming from
-                // File -> File, it can cause a deadlock if the Write file is not sending
-                // data because it is waiting for a file descriotor to open before sending data.
-                resumeable_temp_file.close_file().await.err_tip(|| {
-                    "Could not close file due to timeout in FileSystemStore::update_file"
-                })?;
-                continue;
-            };
-            let mut data = data_result.err_tip(|| "Failed to receive data in filesystem store")?;
-            let data_len = data.len();
-            if data_len == 0 {
-                break; // EOF.
-            }
-            resumeable_temp_file
-                .as_writer()
-                .await
-                .err_tip(|| "in filesystem_store::update_file")?
-                .write_all_buf(&mut data)
-                .await
-                .err_tip(|| "Failed to write data into filesystem store")?;
-            data_size += data_len as u64;
-        }
+        let data_size = write_to_file(&mut resumeable_temp_file, &mut reader).await?;
 
         resumeable_temp_file
             .as_writer()
@@ -803,6 +1146,133 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         self.emplace_file(final_key, Arc::new(entry)).await
     }
 
+    /// Writes the data from `reader` at `offset` of the partial upload of
+    /// `digest`. Once every byte of the digest was written the assembled file
+    /// is verified and moved into place.
+    async fn update_partial_upload(
+        self: Pin<&Self>,
+        digest: DigestInfo,
+        mut reader: DropCloserReadHalf,
+        offset: u64,
+    ) -> Result<(), Error> {
+        self.remove_abandoned_partial_uploads();
+        let partial_upload_slot = self
+            .partial_uploads
+            .lock()
+            .entry(digest)
+            .or_default()
+            .clone();
+        // Held until the range is written, so ranges of the same digest are
+        // written one at a time.
+        let mut partial_upload_guard = partial_upload_slot.lock().await;
+        let partial_upload = match &mut *partial_upload_guard {
+            Some(partial_upload) => partial_upload,
+            None => {
+                let (entry, temp_file, temp_full_path) = Fe::make_and_open_file(
+                    self.block_size,
+                    EncodedFilePath {
+                        shared_context: self.shared_context.clone(),
+                        path_type: PathType::Temp,
+                        key: make_temp_key(&digest.into()),
+                    },
+                )
+                .await?;
+                partial_upload_guard.insert(PartialUpload {
+                    entry,
+                    temp_file,
+                    temp_full_path,
+                    written_ranges: BTreeMap::new(),
+                    last_write: Instant::now(),
+                })
+            }
+        };
+
+        let write_result = async {
+            partial_upload
+                .temp_file
+                .as_writer()
+                .await
+                .err_tip(|| "In filesystem_store::update_partial_upload")?
+                .seek(SeekFrom::Start(offset))
+                .await
+                .err_tip(|| format!("Failed to seek partial upload of {digest} to {offset}"))?;
+            write_to_file(&mut partial_upload.temp_file, &mut reader).await
+        }
+        .await;
+        // Do not hold a file descriptor until the next range arrives.
+        partial_upload
+            .temp_file
+            .close_file()
+            .await
+            .err_tip(|| "In filesystem_store::update_partial_upload")?;
+        partial_upload.last_write = Instant::now();
+        // A range that failed to write is not recorded, so it can be retried.
+        let end = offset + write_result?;
+        if end <= digest.size_bytes() {
+            partial_upload.add_written_range(offset, end);
+            if !partial_upload.is_complete(digest.size_bytes()) {
+                return Ok(());
+            }
+        }
+        // Either every byte was written or the file grew past the end of the
+        // digest, in both cases it is not assembled any further. Dropping the
+        // entry deletes the file.
+        let partial_upload = partial_upload_guard
+            .take()
+            .err_tip(|| "Partial upload vanished in filesystem store")?;
+        {
+            let mut partial_uploads = self.partial_uploads.lock();
+            if partial_uploads
+                .get(&digest)
+                .is_some_and(|slot| Arc::ptr_eq(slot, &partial_upload_slot))
+            {
+                partial_uploads.remove(&digest);
+            }
+        }
+        drop(partial_upload_guard);
+        error_if!(
+            end > digest.size_bytes(),
+            "Range {offset}..{end} written to {digest} ends past the end of the digest"
+        );
+
+        let PartialUpload {
+            mut entry,
+            temp_file,
+            temp_full_path,
+            ..
+        } = partial_upload;
+        drop(temp_file);
+        let temp_file = fs::open_file(&temp_full_path, u64::MAX)
+            .await
+            .err_tip(|| "In filesystem_store::update_partial_upload")?;
+        let hasher = ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+            .err_tip(|| "In filesystem_store::update_partial_upload")?
+            .map_or_else(default_digest_hasher_func, |v| *v)
+            .hasher();
+        let (actual_digest, mut temp_file) = hasher
+            .digest_for_file(temp_file, Some(digest.size_bytes()))
+            .await
+            .err_tip(|| format!("Failed to hash assembled upload of {digest}"))?;
+        if actual_digest != digest {
+            return Err(make_input_err!(
+                "Ranges written to {digest} hashed to {actual_digest}"
+            ));
+        }
+        temp_file
+            .as_reader()
+            .await
+            .err_tip(|| "In filesystem_store::update_partial_upload")?
+            .get_ref()
+            .as_ref()
+            .sync_all()
+            .await
+            .err_tip(|| "Failed to sync_data in filesystem store")?;
+        drop(temp_file);
+
+        *entry.data_size_mut() = digest.size_bytes();
+        self.emplace_file(digest.into(), Arc::new(entry)).await
+    }
+
     async fn emplace_file(&self, key: StoreKey<'static>, entry: Arc<Fe>) -> Result<(), Error> {
         // This sequence of events is quite ticky to understand due to the amount of triggers that
         // happen, async'ness of it and the locking. So here is a breakdown of what happens:
@@ -823,10 +1293,23 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
         //    contents until we relese the lock.
         let evicting_map = self.evicting_map.clone();
         let rename_fn = self.rename_fn;
+        let sync_renames = self.sync_renames;
+        let sharded = self.shared_context.content_shard_depth > 0;
 
         // We need to guarantee that this will get to the end even if the parent future is dropped.
         // See: https://github.com/TraceMachina/nativelink/issues/495
         background_spawn!("filesystem_store_emplace_file", async move {
+            // The file is synced before it is renamed and inserted, so no
+            // lock is held while waiting for the disk.
+            if sync_renames {
+                let temp_path = entry
+                    .get_encoded_file_path()
+                    .read()
+                    .await
+                    .get_file_path()
+                    .to_os_string();
+                sync_path(Path::new(&temp_path)).await?;
+            }
             let mut encoded_file_path = entry.get_encoded_file_path().write().await;
             let final_path = get_file_path_raw(
                 &PathType::Content,
@@ -842,8 +1325,16 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
             // Internally tokio spawns fs commands onto a blocking thread anyways.
             // Since we are already on a blocking thread, we just need the `fs` wrapper to manage
             // an open-file permit (ensure we don't open too many files at once).
-            let result = (rename_fn)(&from_path, &final_path)
-                .err_tip(|| format!("Failed to rename temp file to final path {final_path:?}"));
+            let result = match Path::new(&final_path).parent() {
+                // Shard directories are created the first time a file is placed in them.
+                Some(parent) if sharded => std::fs::create_dir_all(parent)
+                    .err_tip(|| format!("Failed to create shard directory {parent:?}")),
+                _ => Ok(()),
+            }
+            .and_then(|()| {
+                (rename_fn)(&from_path, &final_path)
+                    .err_tip(|| format!("Failed to rename temp file to final path {final_path:?}"))
+            });
 
             // In the event our move from temp file to final file fails we need to ensure we remove
             // the entry from our map.
@@ -869,7 +1360,19 @@ impl<Fe: FileEntry> FilesystemStore<Fe> {
                 return Err(err);
             }
             encoded_file_path.path_type = PathType::Content;
-            encoded_file_path.key = key;
+            encoded_file_path.key = key.borrow().into_owned();
+            drop(encoded_file_path);
+            // The rename is only durable once the directory it happened in
+            // is synced as well.
+            let Some(parent) = Path::new(&final_path).parent().filter(|_| sync_renames) else {
+                return Ok(());
+            };
+            if let Err(err) = sync_path(parent).await {
+                evicting_map
+                    .remove_if(&key, |map_entry| Arc::<Fe>::ptr_eq(map_entry, &entry))
+                    .await;
+                return Err(err);
+            }
             Ok(())
         })
         .await
@@ -884,13 +1387,32 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
         keys: &[StoreKey<'_>],
         results: &mut [Option<u64>],
     ) -> Result<(), Error> {
+        // Answered from the index alone, so large `has_many` calls do not
+        // wait for any I/O. The found files are touched afterwards in one
+        // batch, which keeps their atime fresh and drops files deleted from
+        // disk from the index.
         self.evicting_map
-            .sizes_for_keys::<_, StoreKey<'_>, &StoreKey<'_>>(
-                keys.iter(),
-                results,
-                false, /* peek */
-            )
+            .sizes_for_keys_without_touch::<_, StoreKey<'_>, &StoreKey<'_>>(keys.iter(), results)
             .await;
+        let found_keys: Vec<StoreKey<'static>> = keys
+            .iter()
+            .zip(results.iter())
+            .filter(|(_, result)| result.is_some())
+            .map(|(key, _)| key.borrow().into_owned())
+            .collect();
+        if !found_keys.is_empty() {
+            let evicting_map = self.evicting_map.clone();
+            background_spawn!("filesystem_store_touch_found_keys", async move {
+                let mut results = vec![None; found_keys.len()];
+                evicting_map
+                    .sizes_for_keys::<_, StoreKey<'_>, &StoreKey<'_>>(
+                        found_keys.iter(),
+                        &mut results,
+                        false, /* peek */
+                    )
+                    .await;
+            });
+        }
         // We need to do a special pass to ensure our zero files exist.
         // If our results failed and the result was a zero file, we need to
         // create the file by spec.
@@ -913,6 +1435,33 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
         Ok(())
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        // The file is deleted once no reader holds it anymore.
+        Ok(self.evicting_map.remove(&key).await)
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let mut results = vec![None; keys.len()];
+        self.evicting_map
+            .sizes_for_keys::<_, StoreKey<'_>, &StoreKey<'_>>(
+                keys.iter(),
+                &mut results,
+                false, /* peek */
+            )
+            .await;
+        Ok(results.iter().filter(|result| result.is_some()).count() as u64)
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        Ok(self
+            .evicting_map
+            .remove_matching(|key| {
+                let key: &StoreKey = key.borrow();
+                key.as_str().starts_with(prefix)
+            })
+            .await)
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -935,6 +1484,25 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
             .err_tip(|| format!("While processing with temp file {temp_full_path:?}"))
     }
 
+    async fn update_at_offset(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        offset: u64,
+        upload_size: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        match key {
+            StoreKey::Digest(digest) => self.update_partial_upload(digest, reader, offset).await,
+            // Only digests tell when every range of an entry was written.
+            StoreKey::Str(_) if offset == 0 => self.update(key, reader, upload_size).await,
+            StoreKey::Str(_) => Err(make_err!(
+                Code::Unimplemented,
+                "Filesystem store can only write ranges at a non-zero offset of digests, got {}",
+                key.as_str()
+            )),
+        }
+    }
+
     fn optimized_for(&self, optimization: StoreOptimizations) -> bool {
         optimization == StoreOptimizations::FileUpdates
     }
@@ -987,6 +1555,10 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
         length: Option<u64>,
     ) -> Result<(), Error> {
         if is_zero_digest(key.borrow()) {
+            error_if!(
+                offset != 0,
+                "Offset {offset} is past the end of {key:?} which is 0 bytes long"
+            );
             self.has(key.borrow())
                 .await
                 .err_tip(|| "Failed to check if zero digest exists in filesystem store")?;
@@ -1002,17 +1574,48 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
                 "{} not found in filesystem store here",
                 key.as_str()
             )
+            .with_reason(ErrorReason::NotFound)
         })?;
+        let entry_len = entry.data_size();
+        error_if!(
+            offset > entry_len,
+            "Offset {offset} is past the end of {key:?} which is {entry_len} bytes long"
+        );
         let read_limit = length.unwrap_or(u64::MAX);
+        // Only reads of the whole entry can be checked against the digest.
+        let mut maybe_verify = match key.borrow() {
+            StoreKey::Digest(digest)
+                if self.verify_on_read && offset == 0 && read_limit >= entry_len =>
+            {
+                let hasher = ActiveOriginContext::get_value(&ACTIVE_HASHER_FUNC)
+                    .err_tip(|| "In FilesystemStore::get_part")?
+                    .map_or_else(default_digest_hasher_func, |v| *v)
+                    .hasher();
+                Some((digest, hasher))
+            }
+            _ => None,
+        };
         let mut resumeable_temp_file = entry.read_file_part(offset, read_limit).await?;
+        if self.sequential_read_advice && length != Some(0) {
+            // A length of zero advises everything up to the end of the file.
+            let advice_len = length.unwrap_or(0);
+            if let Err(err) = resumeable_temp_file
+                .advise_sequential(offset, advice_len)
+                .await
+            {
+                event!(Level::WARN, ?err, "Failed to advise sequential read");
+            }
+        }
 
         loop {
             let mut buf = BytesMut::with_capacity(self.read_buffer_size);
+            // Limit the read so no chunk is ever larger than the configured
+            // buffer size, even if the buffer allocated more capacity.
             resumeable_temp_file
                 .as_reader()
                 .await
                 .err_tip(|| "In FileSystemStore::get_part()")?
-                .read_buf(&mut buf)
+                .read_buf(&mut (&mut buf).limit(self.read_buffer_size))
                 .await
                 .err_tip(|| "Failed to read data in filesystem store")?;
             if buf.is_empty() {
@@ -1025,6 +1628,9 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
             // Using `ResumeableFileSlot` will re-open the file in the event it gets closed on the
             // next iteration.
             let buf_content = buf.freeze();
+            if let Some((_, hasher)) = maybe_verify.as_mut() {
+                hasher.update(&buf_content);
+            }
             loop {
                 let sleep_fn = (self.sleep_fn)(fs::idle_file_descriptor_timeout());
                 tokio::pin!(sleep_fn);
@@ -1046,6 +1652,28 @@ impl<Fe: FileEntry> StoreDriver for FilesystemStore<Fe> {
                 }
             }
         }
+        if let Some((digest, mut hasher)) = maybe_verify {
+            let actual_digest = hasher.finalize_digest();
+            if actual_digest != digest {
+                self.verify_on_read_failures.fetch_add(1, Ordering::Relaxed);
+                let evicted = self.evict_on_verify_failure
+                    && self
+                        .evicting_map
+                        .remove_if(&key, |e| Arc::ptr_eq(e, &entry))
+                        .await;
+                event!(
+                    Level::WARN,
+                    ?digest,
+                    ?actual_digest,
+                    evicted,
+                    "Filesystem store entry failed verification on read",
+                );
+                return Err(make_err!(
+                    Code::DataLoss,
+                    "Digest mismatch reading {digest:?} from filesystem store, content hashed to {actual_digest:?}"
+                ));
+            }
+        }
         writer
             .send_eof()
             .err_tip(|| "Filed to send EOF in filesystem store get_part")?;
@@ -1077,6 +1705,9 @@ impl<Fe: FileEntry> HealthStatusIndicator for FilesystemStore<Fe> {
     }
 
     async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
+        if let Err(e) = self.check_temp_to_content_rename().await {
+            return HealthStatus::new_failed(self, e.message_string().into());
+        }
         StoreDriver::check_health(Pin::new(self), namespace).await
     }
 }
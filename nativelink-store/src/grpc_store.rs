@@ -18,11 +18,11 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use bytes::BytesMut;
-use futures::stream::{unfold, FuturesUnordered};
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, unfold};
 use futures::{future, Future, Stream, StreamExt, TryFutureExt, TryStreamExt};
 use nativelink_config::stores::GrpcSpec;
-use nativelink_error::{error_if, make_input_err, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::action_cache_client::ActionCacheClient;
 use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
@@ -41,7 +41,7 @@ use nativelink_util::common::DigestInfo;
 use nativelink_util::connection_manager::ConnectionManager;
 use nativelink_util::digest_hasher::{default_digest_hasher_func, ACTIVE_HASHER_FUNC};
 use nativelink_util::health_utils::HealthStatusIndicator;
-use nativelink_util::origin_context::ActiveOriginContext;
+use nativelink_util::origin_context::{make_traced_request, ActiveOriginContext};
 use nativelink_util::proto_stream_utils::{
     FirstStream, WriteRequestStreamWrapper, WriteState, WriteStateWrapper,
 };
@@ -58,6 +58,18 @@ use tonic::{IntoRequest, Request, Response, Status, Streaming};
 use tracing::{event, Level};
 use uuid::Uuid;
 
+/// Default for `GrpcSpec::max_concurrent_has_requests`.
+const DEFAULT_MAX_CONCURRENT_HAS_REQUESTS: usize = 32;
+
+/// Marks a `NotFound` from upstream as the requested blob or action result
+/// not existing, so clients are only sent the upstream message.
+fn with_not_found_reason(err: Error) -> Error {
+    if err.code == Code::NotFound {
+        return err.with_reason(ErrorReason::NotFound);
+    }
+    err
+}
+
 // This store is usually a pass-through store, but can also be used as a CAS store. Using it as an
 // AC store has one major side-effect... The has() function may not give the proper size of the
 // underlying data. This might cause issues if embedded in certain stores.
@@ -67,7 +79,13 @@ pub struct GrpcStore {
     instance_name: String,
     store_type: nativelink_config::stores::StoreType,
     retrier: Retrier,
-    connection_manager: ConnectionManager,
+    connection_manager: Arc<ConnectionManager>,
+    #[metric(help = "Maximum size of content inlined in GetActionResult responses")]
+    max_inline_size: u64,
+    #[metric(help = "Maximum number of GetActionResult requests sent at once by has_many")]
+    max_concurrent_has_requests: usize,
+    #[metric(help = "If uploads are sent as zstd compressed blobs")]
+    compress_uploads: bool,
 }
 
 impl GrpcStore {
@@ -111,16 +129,37 @@ impl GrpcStore {
                 jitter_fn.clone(),
                 spec.retry.clone(),
             ),
-            connection_manager: ConnectionManager::new(
+            connection_manager: Arc::new(ConnectionManager::new(
                 endpoints.into_iter(),
                 spec.connections_per_endpoint,
                 spec.max_concurrent_requests,
                 spec.retry.clone(),
                 jitter_fn,
-            ),
+            )),
+            max_inline_size: spec.max_inline_size,
+            max_concurrent_has_requests: if spec.max_concurrent_has_requests == 0 {
+                DEFAULT_MAX_CONCURRENT_HAS_REQUESTS
+            } else {
+                spec.max_concurrent_has_requests
+            },
+            compress_uploads: spec.compress_uploads,
         }))
     }
 
+    /// Returns a store that shares the connections of this one, but uploads
+    /// blobs as zstd `compressed-blobs` resources.
+    pub fn with_compressed_uploads(&self) -> Arc<Self> {
+        Arc::new(GrpcStore {
+            instance_name: self.instance_name.clone(),
+            store_type: self.store_type,
+            retrier: self.retrier.clone(),
+            connection_manager: self.connection_manager.clone(),
+            max_inline_size: self.max_inline_size,
+            max_concurrent_has_requests: self.max_concurrent_has_requests,
+            compress_uploads: true,
+        })
+    }
+
     async fn perform_request<F, Fut, R, I>(&self, input: I, mut request: F) -> Result<R, Error>
     where
         F: FnMut(I) -> Fut + Send + Copy,
@@ -159,7 +198,7 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in find_missing_blobs")?;
             ContentAddressableStorageClient::new(channel)
-                .find_missing_blobs(Request::new(request))
+                .find_missing_blobs(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::find_missing_blobs")
         })
@@ -184,7 +223,7 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in batch_update_blobs")?;
             ContentAddressableStorageClient::new(channel)
-                .batch_update_blobs(Request::new(request))
+                .batch_update_blobs(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::batch_update_blobs")
         })
@@ -209,7 +248,7 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in batch_read_blobs")?;
             ContentAddressableStorageClient::new(channel)
-                .batch_read_blobs(Request::new(request))
+                .batch_read_blobs(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::batch_read_blobs")
         })
@@ -234,7 +273,7 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in get_tree")?;
             ContentAddressableStorageClient::new(channel)
-                .get_tree(Request::new(request))
+                .get_tree(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::get_tree")
         })
@@ -261,7 +300,7 @@ impl GrpcStore {
             .await
             .err_tip(|| "in read_internal")?;
         let mut response = ByteStreamClient::new(channel)
-            .read(Request::new(request))
+            .read(make_traced_request(request))
             .await
             .err_tip(|| "in GrpcStore::read")?
             .into_inner();
@@ -319,7 +358,9 @@ impl GrpcStore {
                     .connection()
                     .and_then(|channel| async {
                         ByteStreamClient::new(channel)
-                            .write(WriteStateWrapper::new(local_state.clone()))
+                            .write(make_traced_request(WriteStateWrapper::new(
+                                local_state.clone(),
+                            )))
                             .await
                             .err_tip(|| "in GrpcStore::write")
                     })
@@ -380,7 +421,7 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in query_write_status")?;
             ByteStreamClient::new(channel)
-                .query_write_status(Request::new(request))
+                .query_write_status(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::query_write_status")
         })
@@ -400,11 +441,36 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in get_action_result")?;
             ActionCacheClient::new(channel)
-                .get_action_result(Request::new(request))
+                .get_action_result(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::get_action_result")
         })
         .await
+        .map(|mut response| {
+            self.strip_oversized_inline_content(response.get_mut());
+            response
+        })
+    }
+
+    /// Removes any content the upstream inlined into `action_result` that is
+    /// larger than `max_inline_size`. Content is only removed if its digest
+    /// is set, so the client is always able to fetch it from the CAS.
+    fn strip_oversized_inline_content(&self, action_result: &mut ActionResult) {
+        if self.max_inline_size == 0 {
+            return;
+        }
+        let is_oversized = |data: &Bytes| data.len() as u64 > self.max_inline_size;
+        if action_result.stdout_digest.is_some() && is_oversized(&action_result.stdout_raw) {
+            action_result.stdout_raw = Bytes::new();
+        }
+        if action_result.stderr_digest.is_some() && is_oversized(&action_result.stderr_raw) {
+            action_result.stderr_raw = Bytes::new();
+        }
+        for output_file in &mut action_result.output_files {
+            if output_file.digest.is_some() && is_oversized(&output_file.contents) {
+                output_file.contents = Bytes::new();
+            }
+        }
     }
 
     pub async fn update_action_result(
@@ -420,13 +486,17 @@ impl GrpcStore {
                 .await
                 .err_tip(|| "in update_action_result")?;
             ActionCacheClient::new(channel)
-                .update_action_result(Request::new(request))
+                .update_action_result(make_traced_request(request))
                 .await
                 .err_tip(|| "in GrpcStore::update_action_result")
         })
         .await
     }
 
+    /// Fetches an action result for use as store data. Inline content is never
+    /// requested here, since the encoded result is what gets cached by the
+    /// stores above this one. Proxied client requests go through
+    /// `get_action_result` instead, which forwards the client's inline flags.
     async fn get_action_result_from_digest(
         &self,
         digest: DigestInfo,
@@ -458,6 +528,7 @@ impl GrpcStore {
             .get_action_result_from_digest(digest)
             .await
             .map(Response::into_inner)
+            .map_err(with_not_found_reason)
             .err_tip(|| "Action result not found")?;
         // TODO: Would be better to avoid all the encoding and decoding in this
         //       file, however there's no way to currently get raw bytes from a
@@ -467,6 +538,11 @@ impl GrpcStore {
             .encode(&mut value)
             .err_tip(|| "Could not encode upstream action result")?;
 
+        error_if!(
+            offset > value.len(),
+            "Offset {offset} is past the end of action result {digest} which is {} bytes long",
+            value.len()
+        );
         let default_len = value.len() - offset;
         let length = length.unwrap_or(default_len).min(default_len);
         if length > 0 {
@@ -507,26 +583,44 @@ impl GrpcStore {
 
 #[async_trait]
 impl StoreDriver for GrpcStore {
-    // NOTE: This function can only be safely used on CAS stores. AC stores may return a size that
-    // is incorrect.
+    // NOTE: For CAS stores the sizes in the digests are trusted and returned
+    // as is. For AC stores the size in the digest is the size of the action,
+    // not of the stored `ActionResult`, so the entry is fetched and the size
+    // of the encoded `ActionResult` is returned instead.
     async fn has_with_results(
         self: Pin<&Self>,
         keys: &[StoreKey<'_>],
         results: &mut [Option<u64>],
     ) -> Result<(), Error> {
         if matches!(self.store_type, nativelink_config::stores::StoreType::ac) {
-            keys.iter()
+            // Each result is written to the slot of its key, so the order
+            // the requests finish in does not matter. The requests are
+            // collected first, as mapping the stream directly makes the
+            // future not `Send` for every lifetime of `keys`.
+            let requests: Vec<_> = keys
+                .iter()
                 .zip(results.iter_mut())
                 .map(|(key, result)| async move {
-                    // The length of an AC is incorrect, so we don't figure out the
-                    // length, instead the biggest possible result is returned in the
-                    // hope that we detect incorrect usage.
-                    self.get_action_result_from_digest(key.borrow().into_digest())
-                        .await?;
-                    *result = Some(u64::MAX);
+                    match self
+                        .get_action_result_from_digest(key.borrow().into_digest())
+                        .await
+                    {
+                        Ok(response) => {
+                            // This is the number of bytes `get_part` returns.
+                            let size = response.into_inner().encoded_len();
+                            *result = Some(
+                                u64::try_from(size)
+                                    .err_tip(|| "ActionResult size does not fit in u64")?,
+                            );
+                        }
+                        Err(err) if err.code == Code::NotFound => *result = None,
+                        Err(err) => return Err(err),
+                    }
                     Ok::<_, Error>(())
                 })
-                .collect::<FuturesUnordered<_>>()
+                .collect();
+            stream::iter(requests)
+                .buffer_unordered(self.max_concurrent_has_requests)
                 .try_for_each(|()| future::ready(Ok(())))
                 .await
                 .err_tip(|| "Getting upstream action cache entry")?;
@@ -586,10 +680,17 @@ impl StoreDriver for GrpcStore {
         }
 
         let mut buf = Uuid::encode_buffer();
+        // The digest is always of the uncompressed data, the upstream
+        // decompresses it on ingest.
         let resource_name = format!(
-            "{}/uploads/{}/blobs/{}/{}",
+            "{}/uploads/{}/{}/{}/{}",
             &self.instance_name,
             Uuid::new_v4().hyphenated().encode_lower(&mut buf),
+            if self.compress_uploads {
+                "compressed-blobs/zstd"
+            } else {
+                "blobs"
+            },
             digest.packed_hash(),
             digest.size_bytes(),
         );
@@ -599,12 +700,14 @@ impl StoreDriver for GrpcStore {
             reader: DropCloserReadHalf,
             did_error: bool,
             bytes_received: i64,
+            compress: bool,
         }
         let local_state = LocalState {
             resource_name,
             reader,
             did_error: false,
             bytes_received: 0,
+            compress: self.compress_uploads,
         };
 
         let stream = Box::pin(unfold(local_state, |mut local_state| async move {
@@ -621,6 +724,23 @@ impl StoreDriver for GrpcStore {
                 .await
                 .err_tip(|| "In GrpcStore::update()")
             {
+                // Every chunk is compressed into its own zstd frame, a
+                // sequence of frames decompresses to the concatenated data.
+                Ok(data) if local_state.compress && !data.is_empty() => {
+                    match zstd::bulk::compress(&data, 0) {
+                        Ok(compressed) => Bytes::from(compressed),
+                        Err(err) => {
+                            local_state.did_error = true;
+                            return Some((
+                                Err(make_err!(
+                                    Code::Internal,
+                                    "Failed to zstd compress data in GrpcStore::update(): {err}"
+                                )),
+                                local_state,
+                            ));
+                        }
+                    }
+                }
                 Ok(data) => data,
                 Err(err) => {
                     local_state.did_error = true;
@@ -672,6 +792,11 @@ impl StoreDriver for GrpcStore {
                 .await;
         }
 
+        error_if!(
+            offset > digest.size_bytes(),
+            "Offset {offset} is past the end of {digest} which is {} bytes long",
+            digest.size_bytes()
+        );
         // Shortcut for empty blobs.
         if digest.size_bytes() == 0 {
             return writer.send_eof();
@@ -753,6 +878,7 @@ impl StoreDriver for GrpcStore {
                 }
             }))
             .await
+            .map_err(with_not_found_reason)
     }
 
     fn inner_store(&self, _digest: Option<StoreKey>) -> &dyn StoreDriver {
@@ -13,22 +13,31 @@
 // limitations under the License.
 
 pub mod ac_utils;
+pub mod action_result_ttl_store;
+pub mod async_mirror_store;
 pub mod cas_utils;
+pub mod chunked_store;
 pub mod completeness_checking_store;
 pub mod compression_store;
 pub mod dedup_store;
 pub mod default_store_factory;
 pub mod existence_cache_store;
 pub mod fast_slow_store;
+#[cfg(feature = "fault_injection")]
+pub mod fault_injection_store;
 pub mod filesystem_store;
 pub mod grpc_store;
 pub mod memory_store;
 pub mod noop_store;
+pub mod rate_limit_store;
+pub mod read_cache_store;
 pub mod redis_store;
 mod redis_utils;
 pub mod ref_store;
 pub mod s3_store;
 pub mod shard_store;
+pub mod singleflight_store;
 pub mod size_partitioning_store;
 pub mod store_manager;
+pub mod timeout_store;
 pub mod verify_store;
@@ -22,12 +22,14 @@ use std::time::SystemTime;
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use nativelink_config::stores::MemorySpec;
-use nativelink_error::{Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
-use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::evicting_map::{with_default_evict_bytes, EvictingMap, LenEntry};
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
-use nativelink_util::store_trait::{StoreDriver, StoreKey, StoreKeyBorrow, UploadSizeInfo};
+use nativelink_util::store_trait::{
+    Store, StoreDriver, StoreKey, StoreKeyBorrow, StoreLike, UploadSizeInfo,
+};
 
 use crate::cas_utils::is_zero_digest;
 
@@ -56,17 +58,37 @@ impl LenEntry for BytesWrapper {
 pub struct MemoryStore {
     #[metric(group = "evicting_map")]
     evicting_map: EvictingMap<StoreKeyBorrow, BytesWrapper, SystemTime>,
+    #[metric(help = "Blobs of at least this size are written to the spill store")]
+    spill_threshold: u64,
+    #[metric(group = "spill_store")]
+    spill_store: Option<Store>,
 }
 
 impl MemoryStore {
     pub fn new(spec: &MemorySpec) -> Arc<Self> {
+        Self::new_with_spill_store(spec, None)
+    }
+
+    /// Creates a `MemoryStore` that writes blobs of at least
+    /// `spec.spill_threshold` bytes to `spill_store` instead of memory.
+    pub fn new_with_spill_store(spec: &MemorySpec, spill_store: Option<Store>) -> Arc<Self> {
         let empty_policy = nativelink_config::stores::EvictionPolicy::default();
-        let eviction_policy = spec.eviction_policy.as_ref().unwrap_or(&empty_policy);
+        let eviction_policy =
+            with_default_evict_bytes(spec.eviction_policy.as_ref().unwrap_or(&empty_policy));
         Arc::new(Self {
-            evicting_map: EvictingMap::new(eviction_policy, SystemTime::now()),
+            evicting_map: EvictingMap::new(&eviction_policy, SystemTime::now()),
+            spill_threshold: spec.spill_threshold,
+            spill_store: spill_store.filter(|_| spec.spill_threshold > 0),
         })
     }
 
+    /// Returns the spill store if a blob of `size` bytes belongs in it.
+    fn spill_store_for_size(&self, size: u64) -> Option<&Store> {
+        self.spill_store
+            .as_ref()
+            .filter(|_| size >= self.spill_threshold)
+    }
+
     /// Returns the number of key-value pairs that are currently in the the cache.
     /// Function is not for production code paths.
     pub async fn len_for_test(&self) -> usize {
@@ -76,6 +98,45 @@ impl MemoryStore {
     pub async fn remove_entry(&self, key: StoreKey<'_>) -> bool {
         self.evicting_map.remove(&key).await
     }
+
+    /// Returns the stored bytes of `key` starting at `offset` and at most
+    /// `length` bytes long. The returned `Bytes` shares the underlying buffer
+    /// with the stored value, so no data is copied.
+    async fn get_slice(
+        &self,
+        key: StoreKey<'_>,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<Bytes, Error> {
+        let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
+        let length = length
+            .map(|v| usize::try_from(v).err_tip(|| "Could not convert length to usize"))
+            .transpose()?;
+
+        if is_zero_digest(key.borrow()) {
+            error_if!(
+                offset != 0,
+                "Offset {offset} is past the end of {key:?} which is 0 bytes long"
+            );
+            return Ok(Bytes::new());
+        }
+
+        let value = self.evicting_map.get(&key).await.ok_or_else(|| {
+            make_err!(Code::NotFound, "Key {key:?} not found").with_reason(ErrorReason::NotFound)
+        })?;
+        let value_len =
+            usize::try_from(value.len()).err_tip(|| "Could not convert value.len() to usize")?;
+        error_if!(
+            offset > value_len,
+            "Offset {offset} is past the end of {key:?} which is {value_len} bytes long"
+        );
+        let default_len = value_len - offset;
+        let length = length.unwrap_or(default_len).min(default_len);
+        if length == 0 {
+            return Ok(Bytes::new());
+        }
+        Ok(value.0.slice(offset..(offset + length)))
+    }
 }
 
 #[async_trait]
@@ -100,6 +161,32 @@ impl StoreDriver for MemoryStore {
                     *result = Some(0);
                 }
             });
+        let Some(spill_store) = &self.spill_store else {
+            return Ok(());
+        };
+        let (missing_keys, missing_results): (Vec<_>, Vec<_>) = keys
+            .iter()
+            .zip(results.iter_mut())
+            .filter(|(_, result)| result.is_none())
+            .map(|(key, result)| (key.borrow(), result))
+            .unzip();
+        if missing_keys.is_empty() {
+            return Ok(());
+        }
+        let spill_results = spill_store
+            .has_many(&missing_keys)
+            .await
+            .err_tip(|| "In MemoryStore::has_with_results")?;
+        for ((key, result), spill_result) in
+            missing_keys.iter().zip(missing_results).zip(spill_results)
+        {
+            // Some spill stores, like the filesystem store, report the size
+            // a blob takes up on disk, so report the size of the data itself.
+            *result = match key {
+                StoreKey::Digest(digest) => spill_result.map(|_| digest.size_bytes()),
+                StoreKey::Str(_) => spill_result,
+            };
+        }
         Ok(())
     }
 
@@ -119,12 +206,65 @@ impl StoreDriver for MemoryStore {
         Ok(iterations)
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        let removed = self.evicting_map.remove(&key).await;
+        let Some(spill_store) = &self.spill_store else {
+            return Ok(removed);
+        };
+        let spill_removed = spill_store
+            .remove(key)
+            .await
+            .err_tip(|| "In MemoryStore::remove")?;
+        Ok(removed || spill_removed)
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let mut results = vec![None; keys.len()];
+        self.evicting_map
+            .sizes_for_keys::<_, StoreKey<'_>, &StoreKey<'_>>(
+                keys.iter(),
+                &mut results,
+                false, /* peek */
+            )
+            .await;
+        Ok(results.iter().filter(|result| result.is_some()).count() as u64)
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        let removed = self
+            .evicting_map
+            .remove_matching(|key| {
+                let key: &StoreKey = key.borrow();
+                key.as_str().starts_with(prefix)
+            })
+            .await;
+        let Some(spill_store) = &self.spill_store else {
+            return Ok(removed);
+        };
+        let spill_removed = spill_store
+            .remove_prefix(prefix)
+            .await
+            .err_tip(|| "In MemoryStore::remove_prefix")?;
+        Ok(removed + spill_removed)
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
         mut reader: DropCloserReadHalf,
-        _size_info: UploadSizeInfo,
+        size_info: UploadSizeInfo,
     ) -> Result<(), Error> {
+        if let UploadSizeInfo::ExactSize(size) = size_info {
+            if let Some(spill_store) = self.spill_store_for_size(size) {
+                // Drop any in-memory copy so reads don't serve stale data.
+                self.evicting_map.remove(&key).await;
+                return spill_store
+                    .update(key, reader, size_info)
+                    .await
+                    .err_tip(|| "Failed to write to spill store in memory_store::update");
+            }
+        }
+
         // Internally Bytes might hold a reference to more data than just our data. To prevent
         // this potential case, we make a full copy of our data for long-term storage.
         let final_buffer = {
@@ -137,6 +277,15 @@ impl StoreDriver for MemoryStore {
             new_buffer.freeze()
         };
 
+        // The size was not known up front, so check it now that it is.
+        if let Some(spill_store) = self.spill_store_for_size(final_buffer.len() as u64) {
+            self.evicting_map.remove(&key).await;
+            return spill_store
+                .update_oneshot(key, final_buffer)
+                .await
+                .err_tip(|| "Failed to write to spill store in memory_store::update");
+        }
+
         self.evicting_map
             .insert(key.into_owned().into(), BytesWrapper(final_buffer))
             .await;
@@ -150,30 +299,21 @@ impl StoreDriver for MemoryStore {
         offset: u64,
         length: Option<u64>,
     ) -> Result<(), Error> {
-        let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
-        let length = length
-            .map(|v| usize::try_from(v).err_tip(|| "Could not convert length to usize"))
-            .transpose()?;
-
-        if is_zero_digest(key.borrow()) {
-            writer
-                .send_eof()
-                .err_tip(|| "Failed to send zero EOF in filesystem store get_part")?;
-            return Ok(());
-        }
-
-        let value = self
-            .evicting_map
-            .get(&key)
-            .await
-            .err_tip_with_code(|_| (Code::NotFound, format!("Key {key:?} not found")))?;
-        let default_len = usize::try_from(value.len())
-            .err_tip(|| "Could not convert value.len() to usize")?
-            .saturating_sub(offset);
-        let length = length.unwrap_or(default_len).min(default_len);
-        if length > 0 {
+        let data = match self.get_slice(key.borrow(), offset, length).await {
+            Err(err) if err.code == Code::NotFound => match &self.spill_store {
+                Some(spill_store) => {
+                    return spill_store
+                        .get_part(key, writer, offset, length)
+                        .await
+                        .err_tip(|| "Failed to read from spill store in memory store get_part");
+                }
+                None => return Err(err),
+            },
+            result => result?,
+        };
+        if !data.is_empty() {
             writer
-                .send(value.0.slice(offset..(offset + length)))
+                .send(data)
                 .await
                 .err_tip(|| "Failed to write data in memory store")?;
         }
@@ -183,6 +323,24 @@ impl StoreDriver for MemoryStore {
         Ok(())
     }
 
+    // Since the data is already held in memory, slice it directly instead of
+    // round-tripping through a buf channel like the default implementation.
+    async fn get_part_unchunked(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<Bytes, Error> {
+        match self.get_slice(key.borrow(), offset, length).await {
+            Err(err) if err.code == Code::NotFound => match &self.spill_store {
+                Some(spill_store) => spill_store.get_part_unchunked(key, offset, length).await,
+                None => Err(err),
+            },
+            result => result,
+        }
+        .err_tip(|| "Failed to get_part in get_part_unchunked")
+    }
+
     fn inner_store(&self, _digest: Option<StoreKey>) -> &dyn StoreDriver {
         self
     }
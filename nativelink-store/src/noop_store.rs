@@ -16,7 +16,7 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
 };
@@ -54,6 +54,18 @@ impl StoreDriver for NoopStore {
         Ok(())
     }
 
+    async fn remove(self: Pin<&Self>, _key: StoreKey<'_>) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    async fn touch(self: Pin<&Self>, _keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, _prefix: &str) -> Result<u64, Error> {
+        Ok(0)
+    }
+
     async fn update(
         self: Pin<&Self>,
         _key: StoreKey<'_>,
@@ -78,7 +90,7 @@ impl StoreDriver for NoopStore {
         _offset: u64,
         _length: Option<u64>,
     ) -> Result<(), Error> {
-        Err(make_err!(Code::NotFound, "Not found in noop store"))
+        Err(make_err!(Code::NotFound, "Not found in noop store").with_reason(ErrorReason::NotFound))
     }
 
     fn inner_store(&self, _key: Option<StoreKey>) -> &dyn StoreDriver {
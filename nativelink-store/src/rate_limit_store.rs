@@ -0,0 +1,173 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nativelink_config::stores::RateLimitSpec;
+use nativelink_error::{error_if, make_err, Code, Error};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+use tokio::time::{sleep, Instant};
+
+/// Token bucket that refills at `requests_per_second` up to `max_burst`.
+/// Tokens may go negative; that is how calls reserve a future token while
+/// they wait, so waiting calls are served in the order they arrived.
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(MetricsComponent)]
+pub struct RateLimitStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Number of calls per second allowed to reach the backend")]
+    requests_per_second: u64,
+    #[metric(help = "Number of calls allowed to reach the backend at once")]
+    max_burst: u64,
+    #[metric(help = "Time a call over the limit may wait for its turn")]
+    max_wait: Duration,
+    #[metric(help = "Number of calls that waited for their turn")]
+    delayed_requests: AtomicU64,
+    #[metric(help = "Number of calls that failed because they were over the limit")]
+    rejected_requests: AtomicU64,
+    /// Tokens left for calls to reach the backend.
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimitStore {
+    pub fn new(spec: &RateLimitSpec, inner_store: Store) -> Result<Arc<Self>, Error> {
+        error_if!(
+            spec.requests_per_second == 0,
+            "requests_per_second must be greater than 0 in RateLimitStore"
+        );
+        let max_burst = if spec.max_burst == 0 {
+            spec.requests_per_second
+        } else {
+            spec.max_burst
+        };
+        Ok(Arc::new(Self {
+            inner_store,
+            requests_per_second: spec.requests_per_second,
+            max_burst,
+            max_wait: Duration::from_millis(spec.max_wait_ms),
+            delayed_requests: AtomicU64::new(0),
+            rejected_requests: AtomicU64::new(0),
+            bucket: Mutex::new(TokenBucket {
+                tokens: max_burst as f64,
+                last_refill: Instant::now(),
+            }),
+        }))
+    }
+
+    /// Takes a token for `operation_name`, waiting for one to be refilled
+    /// if the bucket is empty. Fails with `ResourceExhausted` if that would
+    /// take longer than `max_wait`.
+    async fn acquire(&self, operation_name: &str) -> Result<(), Error> {
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            let now = Instant::now();
+            let refilled = now.duration_since(bucket.last_refill).as_secs_f64()
+                * self.requests_per_second as f64;
+            bucket.tokens = (bucket.tokens + refilled).min(self.max_burst as f64);
+            bucket.last_refill = now;
+            let wait = if bucket.tokens >= 1.0 {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.requests_per_second as f64)
+            };
+            if wait > self.max_wait {
+                self.rejected_requests.fetch_add(1, Ordering::Relaxed);
+                return Err(make_err!(
+                    Code::ResourceExhausted,
+                    "RateLimitStore::{operation_name} is over the limit of {} requests per second",
+                    self.requests_per_second
+                ));
+            }
+            bucket.tokens -= 1.0;
+            wait
+        };
+        if !wait.is_zero() {
+            self.delayed_requests.fetch_add(1, Ordering::Relaxed);
+            sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StoreDriver for RateLimitStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.acquire("has").await?;
+        self.inner_store.has_with_results(keys, results).await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.acquire("update").await?;
+        self.inner_store.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.acquire("get_part").await?;
+        self.inner_store.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(RateLimitStore);
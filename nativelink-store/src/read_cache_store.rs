@@ -0,0 +1,210 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use nativelink_config::stores::ReadCacheSpec;
+use nativelink_error::{error_if, Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+
+// NOTE: If these change update the comments in `stores.rs` to reflect
+// the new defaults.
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024; // 1mib.
+const DEFAULT_MAX_CACHE_BYTES: usize = 256 * 1024 * 1024; // 256mib.
+
+#[derive(Clone)]
+struct CachedBlob(Bytes);
+
+impl Debug for CachedBlob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CachedBlob { -- Binary data -- }")
+    }
+}
+
+impl LenEntry for CachedBlob {
+    #[inline]
+    fn len(&self) -> u64 {
+        Bytes::len(&self.0) as u64
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        Bytes::is_empty(&self.0)
+    }
+}
+
+/// Keeps recently read blobs in memory in front of another store. Only reads
+/// populate the cache; writes go straight to the inner store.
+#[derive(MetricsComponent)]
+pub struct ReadCacheStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Blobs larger than this are not cached")]
+    max_size: u64,
+    #[metric(group = "cache")]
+    cache: EvictingMap<DigestInfo, CachedBlob, SystemTime>,
+    #[metric(help = "Number of reads served from the cache")]
+    cache_hits: AtomicU64,
+    #[metric(help = "Number of reads of cacheable blobs sent to the inner store")]
+    cache_misses: AtomicU64,
+}
+
+impl ReadCacheStore {
+    pub fn new(spec: &ReadCacheSpec, inner_store: Store) -> Arc<Self> {
+        let default_policy = nativelink_config::stores::EvictionPolicy {
+            max_bytes: DEFAULT_MAX_CACHE_BYTES,
+            ..Default::default()
+        };
+        let eviction_policy = spec.eviction_policy.as_ref().unwrap_or(&default_policy);
+        let max_size = if spec.max_size == 0 {
+            DEFAULT_MAX_SIZE
+        } else {
+            spec.max_size
+        };
+        Arc::new(Self {
+            inner_store,
+            max_size,
+            cache: EvictingMap::new(eviction_policy, SystemTime::now()),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the digest of `key` if the whole blob may be held in memory.
+    /// Reads of other keys are streamed straight from the inner store.
+    fn cacheable_digest(&self, key: &StoreKey<'_>) -> Option<DigestInfo> {
+        match key {
+            StoreKey::Digest(digest) if digest.size_bytes() <= self.max_size => Some(*digest),
+            _ => None,
+        }
+    }
+
+    /// Returns the whole blob for `digest`, reading it from the inner store
+    /// and caching it if it is not already cached.
+    async fn get_blob(&self, digest: DigestInfo) -> Result<Bytes, Error> {
+        if let Some(blob) = self.cache.get(&digest).await {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(blob.0);
+        }
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        let data = self
+            .inner_store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .err_tip(|| "In ReadCacheStore::get_part")?;
+        self.cache.insert(digest, CachedBlob(data.clone())).await;
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl StoreDriver for ReadCacheStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner_store.has_with_results(keys, results).await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        if let Some(digest) = self.cacheable_digest(&key) {
+            self.cache.remove(&digest).await;
+        }
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.cache
+            .remove_matching(|digest| format!("{digest}").starts_with(prefix))
+            .await;
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        if let Some(digest) = self.cacheable_digest(&key) {
+            self.cache.remove(&digest).await;
+        }
+        self.inner_store.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        let Some(digest) = self.cacheable_digest(&key) else {
+            return self.inner_store.get_part(key, writer, offset, length).await;
+        };
+        let blob = self.get_blob(digest).await?;
+        let offset = usize::try_from(offset).err_tip(|| "Could not convert offset to usize")?;
+        error_if!(
+            offset > blob.len(),
+            "Offset {offset} is past the end of {digest} which is {} bytes long",
+            blob.len()
+        );
+        let remaining = blob.len() - offset;
+        let length = match length {
+            Some(length) => usize::try_from(length)
+                .err_tip(|| "Could not convert length to usize")?
+                .min(remaining),
+            None => remaining,
+        };
+        if length > 0 {
+            writer
+                .send(blob.slice(offset..(offset + length)))
+                .await
+                .err_tip(|| "Failed to write data in ReadCacheStore::get_part")?;
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in ReadCacheStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(ReadCacheStore);
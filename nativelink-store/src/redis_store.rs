@@ -36,7 +36,7 @@ use fred::types::{Builder, Key as RedisKey, Map as RedisMap, SortOrder, Value as
 use futures::stream::FuturesUnordered;
 use futures::{future, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
 use nativelink_config::stores::{RedisMode, RedisSpec};
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, make_input_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
 use nativelink_util::health_utils::{HealthRegistryBuilder, HealthStatus, HealthStatusIndicator};
@@ -359,6 +359,17 @@ impl StoreDriver for RedisStore {
             .await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        let encoded_key = self.encode_key(&key);
+        let removed_count = self
+            .client_pool
+            .next()
+            .del::<u64, _>(encoded_key.as_ref())
+            .await
+            .err_tip(|| format!("In RedisStore::remove for {encoded_key}"))?;
+        Ok(removed_count > 0)
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -555,7 +566,8 @@ impl StoreDriver for RedisStore {
                 return Err(make_err!(
                     Code::NotFound,
                     "Data not found in Redis store for digest: {key:?}"
-                ));
+                )
+                .with_reason(ErrorReason::NotFound));
             }
         }
 
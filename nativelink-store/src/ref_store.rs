@@ -108,6 +108,18 @@ impl StoreDriver for RefStore {
         self.get_store()?.has_with_results(keys, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.get_store()?.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.get_store()?.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.get_store()?.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -117,6 +129,18 @@ impl StoreDriver for RefStore {
         self.get_store()?.update(key, reader, size_info).await
     }
 
+    async fn update_at_offset(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        offset: u64,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.get_store()?
+            .update_at_offset(key, reader, offset, size_info)
+            .await
+    }
+
     async fn get_part(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -13,25 +13,33 @@
 // limitations under the License.
 
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::future::Future;
+use std::io::Write;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{cmp, env};
 
 use async_trait::async_trait;
 use aws_config::default_provider::credentials;
 use aws_config::{AppName, BehaviorVersion};
-use aws_sdk_s3::config::Region;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::{IdentityCache, Region, SharedCredentialsProvider};
+use aws_sdk_s3::error::SdkError;
 use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadOutput;
-use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::operation::get_object::{GetObjectError, GetObjectOutput};
 use aws_sdk_s3::operation::head_object::HeadObjectError;
 use aws_sdk_s3::primitives::{ByteStream, SdkBody};
 use aws_sdk_s3::types::builders::{CompletedMultipartUploadBuilder, CompletedPartBuilder};
+use aws_sdk_s3::types::{ServerSideEncryption, StorageClass};
 use aws_sdk_s3::Client;
 use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
 use bytes::Bytes;
+use flate2::write::{GzDecoder, GzEncoder};
+use flate2::Compression;
 use futures::future::FusedFuture;
 use futures::stream::{unfold, FuturesUnordered};
 use futures::{FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt};
@@ -40,12 +48,12 @@ use hyper::client::connect::{Connected, Connection, HttpConnector};
 use hyper::service::Service;
 use hyper::Uri;
 use hyper_rustls::{HttpsConnector, MaybeHttpsStream};
-use nativelink_config::stores::S3Spec;
+use nativelink_config::stores::{S3ContentEncoding, S3ServerSideEncryption, S3Spec};
 // Note: S3 store should be very careful about the error codes it returns
 // when in a retryable wrapper. Always prefer Code::Aborted or another
 // retryable code over Code::InvalidArgument or make_input_err!().
 // ie: Don't import make_input_err!() to help prevent this.
-use nativelink_error::{make_err, Code, Error, ResultExt};
+use nativelink_error::{make_err, Code, Error, ErrorReason, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{
     make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
@@ -53,13 +61,14 @@ use nativelink_util::buf_channel::{
 use nativelink_util::fs;
 use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::instant_wrapper::InstantWrapper;
+use nativelink_util::metrics_utils::DurationHistogram;
 use nativelink_util::retry::{Retrier, RetryResult};
 use nativelink_util::store_trait::{StoreDriver, StoreKey, UploadSizeInfo};
 use rand::rngs::OsRng;
 use rand::Rng;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, SemaphorePermit};
+use tokio::sync::{mpsc, Semaphore, SemaphorePermit};
 use tokio::time::sleep;
 use tracing::{event, Level};
 
@@ -77,6 +86,13 @@ const MAX_MULTIPART_SIZE: u64 = 5 * 1024 * 1024 * 1024; // 5GB.
 // https://docs.aws.amazon.com/AmazonS3/latest/userguide/qfacts.html
 const MAX_UPLOAD_PARTS: usize = 10_000;
 
+// Header S3 reports the region of a bucket in, even on redirects and
+// access denied errors.
+const BUCKET_REGION_HEADER: &str = "x-amz-bucket-region";
+
+// Region used to ask S3 where a bucket is when no region is known at all.
+const BUCKET_REGION_PROBE_REGION: &str = "us-east-1";
+
 // Default max buffer size for retrying upload requests.
 // Note: If you change this, adjust the docs in the config.
 const DEFAULT_MAX_RETRY_BUFFER_PER_REQUEST: usize = 5 * 1024 * 1024; // 5MB.
@@ -85,6 +101,98 @@ const DEFAULT_MAX_RETRY_BUFFER_PER_REQUEST: usize = 5 * 1024 * 1024; // 5MB.
 // Note: If you change this, adjust the docs in the config.
 const DEFAULT_MULTIPART_MAX_CONCURRENT_UPLOADS: usize = 10;
 
+// User metadata key holding the size of an object before it was compressed,
+// so `has()` can report the real size of compressed objects.
+const UNCOMPRESSED_SIZE_METADATA_KEY: &str = "nativelink-uncompressed-size";
+
+// Status S3 responds with to ranges starting past the end of an object.
+const RANGE_NOT_SATISFIABLE_STATUS: u16 = 416;
+
+/// Limits the number of requests made to S3 at the same time. All
+/// `S3Store`s created from the config share the limiter returned by
+/// `global_s3_request_limiter()`.
+#[derive(MetricsComponent)]
+pub struct S3RequestLimiter {
+    semaphore: Option<Semaphore>,
+    #[metric(help = "The maximum number of concurrent S3 requests, 0 if unlimited")]
+    max_concurrent_requests: usize,
+    #[metric(help = "The number of S3 requests currently holding a permit")]
+    in_use: AtomicU64,
+    #[metric(help = "The time spent waiting for a permit to make a S3 request")]
+    acquire_wait: DurationHistogram,
+}
+
+impl S3RequestLimiter {
+    /// Creates a limiter allowing `max_concurrent_requests` requests at the
+    /// same time. A value of zero is treated as unlimited.
+    #[must_use]
+    pub fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            semaphore: (max_concurrent_requests != 0)
+                .then(|| Semaphore::new(max_concurrent_requests)),
+            max_concurrent_requests,
+            in_use: AtomicU64::new(0),
+            acquire_wait: DurationHistogram::default(),
+        }
+    }
+
+    /// Waits until a request may be made. The returned permit must be held
+    /// until the request has completed.
+    pub async fn acquire(&self) -> S3RequestPermit<'_> {
+        let start = Instant::now();
+        let permit = match &self.semaphore {
+            Some(semaphore) => Some(
+                semaphore
+                    .acquire()
+                    .await
+                    .expect("S3RequestLimiter never closes its semaphore"),
+            ),
+            None => None,
+        };
+        self.acquire_wait.observe(start.elapsed());
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        S3RequestPermit {
+            limiter: self,
+            _permit: permit,
+        }
+    }
+
+    /// The number of requests currently holding a permit.
+    pub fn in_use(&self) -> u64 {
+        self.in_use.load(Ordering::Relaxed)
+    }
+}
+
+/// Allows a request to be made to S3 until it is dropped.
+pub struct S3RequestPermit<'a> {
+    limiter: &'a S3RequestLimiter,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for S3RequestPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+static GLOBAL_S3_REQUEST_LIMITER: OnceLock<Arc<S3RequestLimiter>> = OnceLock::new();
+
+/// The limiter shared by all `S3Store`s. Unlimited unless
+/// `set_max_concurrent_s3_requests()` was called first.
+pub fn global_s3_request_limiter() -> Arc<S3RequestLimiter> {
+    GLOBAL_S3_REQUEST_LIMITER
+        .get_or_init(|| Arc::new(S3RequestLimiter::new(0)))
+        .clone()
+}
+
+/// Set the maximum number of requests all `S3Store`s may make at the same
+/// time, this should be called once before any `S3Store` is created.
+pub fn set_max_concurrent_s3_requests(limit: usize) -> Result<(), Error> {
+    GLOBAL_S3_REQUEST_LIMITER
+        .set(Arc::new(S3RequestLimiter::new(limit)))
+        .map_err(|_| make_err!(Code::Internal, "set_max_concurrent_s3_requests already set"))
+}
+
 pub struct ConnectionWithPermit<T: Connection + AsyncRead + AsyncWrite + Unpin> {
     connection: T,
     _permit: SemaphorePermit<'static>,
@@ -236,6 +344,186 @@ impl http_body::Body for BodyWrapper {
     }
 }
 
+/// Configures `builder` to get credentials from `credentials_provider` and
+/// cache them until shortly before they expire. The next request after that
+/// fetches new credentials, so temporary credentials like the ones from STS
+/// or IRSA are refreshed for as long as the process runs.
+pub fn with_refreshing_credentials(
+    builder: aws_sdk_s3::config::Builder,
+    credentials_provider: SharedCredentialsProvider,
+) -> aws_sdk_s3::config::Builder {
+    builder
+        .credentials_provider(credentials_provider)
+        .identity_cache(IdentityCache::lazy().build())
+}
+
+/// Asks S3 which region `bucket` is in. S3 reports the region in the
+/// `x-amz-bucket-region` header of `HeadBucket` responses, including
+/// redirects from the wrong region and access denied errors.
+pub async fn detect_bucket_region(s3_client: &Client, bucket: &str) -> Result<Region, Error> {
+    let bucket_region = match s3_client.head_bucket().bucket(bucket).send().await {
+        Ok(head_bucket_output) => head_bucket_output.bucket_region().map(str::to_string),
+        Err(err) => err
+            .raw_response()
+            .and_then(|response| response.headers().get(BUCKET_REGION_HEADER))
+            .map(str::to_string),
+    };
+    bucket_region.map(Region::new).ok_or_else(|| {
+        make_err!(
+            Code::FailedPrecondition,
+            "S3 did not report the region of bucket {bucket}"
+        )
+    })
+}
+
+/// Returns the region of `bucket`, preferring what S3 reports over
+/// `default_region` from the default region provider chain.
+async fn resolve_bucket_region(
+    s3_config: &aws_sdk_s3::config::Builder,
+    bucket: &str,
+    default_region: Option<&Region>,
+) -> Result<Region, Error> {
+    let probe_region = default_region
+        .cloned()
+        .unwrap_or_else(|| Region::from_static(BUCKET_REGION_PROBE_REGION));
+    let probe_client = Client::from_conf(s3_config.clone().region(probe_region).build());
+    match detect_bucket_region(&probe_client, bucket).await {
+        Ok(region) => Ok(region),
+        Err(err) => {
+            let Some(default_region) = default_region else {
+                return Err(err).err_tip(|| {
+                    "No 'region' configured for S3 store and none found in the environment"
+                });
+            };
+            event!(
+                Level::WARN,
+                ?err,
+                ?default_region,
+                bucket,
+                "Could not detect S3 bucket region, using the default region",
+            );
+            Ok(default_region.clone())
+        }
+    }
+}
+
+/// User metadata recording the size of an object before it was compressed.
+fn uncompressed_size_metadata(uncompressed_size: Option<u64>) -> Option<HashMap<String, String>> {
+    uncompressed_size
+        .map(|size| HashMap::from([(UNCOMPRESSED_SIZE_METADATA_KEY.to_string(), size.to_string())]))
+}
+
+fn gzip_compress(data: &[u8]) -> Result<Bytes, Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .err_tip(|| "Failed to gzip data in S3Store")?;
+    Ok(encoder
+        .finish()
+        .err_tip(|| "Failed to finish gzip data in S3Store")?
+        .into())
+}
+
+/// Compresses everything from `reader` with gzip into `writer` one chunk
+/// at a time.
+async fn gzip_compress_stream(
+    reader: &mut DropCloserReadHalf,
+    writer: &mut DropCloserWriteHalf,
+) -> Result<(), Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    loop {
+        let chunk = reader
+            .recv()
+            .await
+            .err_tip(|| "Failed to read chunk in S3Store gzip_compress_stream")?;
+        if chunk.is_empty() {
+            break; // EOF.
+        }
+        encoder
+            .write_all(&chunk)
+            .err_tip(|| "Failed to gzip chunk in S3Store")?;
+        let compressed = std::mem::take(encoder.get_mut());
+        if !compressed.is_empty() {
+            writer
+                .send(compressed.into())
+                .await
+                .err_tip(|| "Failed to send gzip chunk in S3Store")?;
+        }
+    }
+    let compressed = encoder
+        .finish()
+        .err_tip(|| "Failed to finish gzip data in S3Store")?;
+    writer
+        .send(compressed.into())
+        .await
+        .err_tip(|| "Failed to send gzip trailer in S3Store")?;
+    writer
+        .send_eof()
+        .err_tip(|| "Failed to send EOF in S3Store gzip_compress_stream")
+}
+
+/// Yields the bytes inside the requested range of an object that is read
+/// from the start, decompressing it first if it is gzip encoded.
+struct RangeDecoder {
+    /// None if the object is stored without compression.
+    decoder: Option<GzDecoder<Vec<u8>>>,
+    /// Decompressed bytes to drop before the range starts.
+    skip: u64,
+    /// Decompressed bytes left in the range, or None to read until the end.
+    remaining: Option<u64>,
+}
+
+impl RangeDecoder {
+    fn new(gzip: bool, skip: u64, remaining: Option<u64>) -> Self {
+        Self {
+            decoder: gzip.then(|| GzDecoder::new(Vec::new())),
+            skip,
+            remaining,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    fn decode(&mut self, data: Bytes) -> Result<Bytes, Error> {
+        let Some(decoder) = &mut self.decoder else {
+            return Ok(self.take_range(data));
+        };
+        decoder
+            .write_all(&data)
+            .map_err(|e| make_err!(Code::Aborted, "Failed to gunzip data from S3: {e:?}"))?;
+        let data = Bytes::from(std::mem::take(decoder.get_mut()));
+        Ok(self.take_range(data))
+    }
+
+    fn finish(&mut self) -> Result<Bytes, Error> {
+        let Some(decoder) = &mut self.decoder else {
+            return Ok(Bytes::new());
+        };
+        decoder.try_finish().map_err(|e| {
+            make_err!(
+                Code::Aborted,
+                "Failed to finish gunzip of data from S3: {e:?}"
+            )
+        })?;
+        let data = Bytes::from(std::mem::take(decoder.get_mut()));
+        Ok(self.take_range(data))
+    }
+
+    fn take_range(&mut self, mut data: Bytes) -> Bytes {
+        let skip = usize::try_from(self.skip).map_or(data.len(), |skip| skip.min(data.len()));
+        data = data.slice(skip..);
+        self.skip -= skip as u64;
+        if let Some(remaining) = &mut self.remaining {
+            let len = usize::try_from(*remaining).map_or(data.len(), |len| len.min(data.len()));
+            data.truncate(len);
+            *remaining -= len as u64;
+        }
+        data
+    }
+}
+
 #[derive(MetricsComponent)]
 pub struct S3Store<NowFn> {
     s3_client: Arc<Client>,
@@ -251,6 +539,18 @@ pub struct S3Store<NowFn> {
     max_retry_buffer_per_request: usize,
     #[metric(help = "The number of concurrent uploads allowed for multipart uploads")]
     multipart_max_concurrent_uploads: usize,
+    #[metric(help = "The size of multipart upload parts, smaller uploads use a single request")]
+    multipart_part_size: u64,
+    /// Server side encryption set on uploads, if any.
+    server_side_encryption: Option<ServerSideEncryption>,
+    /// KMS key set on uploads encrypted with `ServerSideEncryption::AwsKms`.
+    kms_key_id: Option<String>,
+    /// Storage class set on uploads, if any.
+    storage_class: Option<StorageClass>,
+    /// Content encoding applied to uploads and removed on downloads, if any.
+    content_encoding: Option<S3ContentEncoding>,
+    #[metric(group = "request_limiter")]
+    request_limiter: Arc<S3RequestLimiter>,
 }
 
 impl<I, NowFn> S3Store<NowFn>
@@ -271,24 +571,42 @@ where
         let s3_client = {
             let http_client =
                 HyperClientBuilder::new().build(TlsConnector::new(spec, jitter_fn.clone()));
-            let credential_provider = credentials::default_provider().await;
             let mut config_builder = aws_config::defaults(BehaviorVersion::v2024_03_28())
-                .credentials_provider(credential_provider)
                 .app_name(AppName::new("nativelink").expect("valid app name"))
                 .timeout_config(
                     aws_config::timeout::TimeoutConfig::builder()
                         .connect_timeout(Duration::from_secs(15))
                         .build(),
                 )
-                .region(Region::new(Cow::Owned(spec.region.clone())))
                 .http_client(http_client);
+            // If no region is configured the default region provider chain
+            // is used, which is then checked against the bucket below.
+            if !spec.region.is_empty() {
+                config_builder =
+                    config_builder.region(Region::new(Cow::Owned(spec.region.clone())));
+            }
             // TODO(allada) When aws-sdk supports this env variable we should be able
             // to remove this.
             // See: https://github.com/awslabs/aws-sdk-rust/issues/932
             if let Ok(endpoint_url) = env::var("AWS_ENDPOINT_URL") {
                 config_builder = config_builder.endpoint_url(endpoint_url);
             }
-            aws_sdk_s3::Client::new(&config_builder.load().await)
+            let sdk_config = config_builder.load().await;
+            // Credentials are not resolved until the first request, so this
+            // does not fail if they are not available yet (eg: IRSA tokens
+            // or instance metadata that show up after startup).
+            let credentials_provider =
+                SharedCredentialsProvider::new(credentials::default_provider().await);
+            let mut s3_config = with_refreshing_credentials(
+                aws_sdk_s3::config::Builder::from(&sdk_config),
+                credentials_provider,
+            );
+            if spec.region.is_empty() {
+                let region =
+                    resolve_bucket_region(&s3_config, &spec.bucket, sdk_config.region()).await?;
+                s3_config = s3_config.region(region);
+            }
+            Client::from_conf(s3_config.build())
         };
         Self::new_with_client_and_jitter(spec, s3_client, jitter_fn, now_fn)
     }
@@ -299,6 +617,45 @@ where
         jitter_fn: Arc<dyn Fn(Duration) -> Duration + Send + Sync>,
         now_fn: NowFn,
     ) -> Result<Arc<Self>, Error> {
+        Self::new_with_request_limiter(
+            spec,
+            s3_client,
+            jitter_fn,
+            now_fn,
+            global_s3_request_limiter(),
+        )
+    }
+
+    /// Like `new_with_client_and_jitter()`, but limits the requests made to
+    /// S3 with `request_limiter` instead of the global limiter.
+    pub fn new_with_request_limiter(
+        spec: &S3Spec,
+        s3_client: Client,
+        jitter_fn: Arc<dyn Fn(Duration) -> Duration + Send + Sync>,
+        now_fn: NowFn,
+        request_limiter: Arc<S3RequestLimiter>,
+    ) -> Result<Arc<Self>, Error> {
+        if spec.kms_key_id.is_some() && spec.sse != Some(S3ServerSideEncryption::aws_kms) {
+            return Err(make_err!(
+                Code::InvalidArgument,
+                "S3 store 'kms_key_id' requires 'sse' to be 'aws_kms'"
+            ));
+        }
+        let storage_class = spec
+            .storage_class
+            .as_deref()
+            .map(|storage_class| {
+                if StorageClass::values().contains(&storage_class) {
+                    Ok(StorageClass::from(storage_class))
+                } else {
+                    Err(make_err!(
+                        Code::InvalidArgument,
+                        "Unknown S3 'storage_class' {storage_class}, expected one of {:?}",
+                        StorageClass::values()
+                    ))
+                }
+            })
+            .transpose()?;
         Ok(Arc::new(Self {
             s3_client: Arc::new(s3_client),
             now_fn,
@@ -316,6 +673,18 @@ where
             multipart_max_concurrent_uploads: spec
                 .multipart_max_concurrent_uploads
                 .map_or(DEFAULT_MULTIPART_MAX_CONCURRENT_UPLOADS, |v| v),
+            multipart_part_size: spec
+                .multipart_part_size
+                .unwrap_or(MIN_MULTIPART_SIZE)
+                .clamp(MIN_MULTIPART_SIZE, MAX_MULTIPART_SIZE),
+            server_side_encryption: spec.sse.map(|sse| match sse {
+                S3ServerSideEncryption::aes256 => ServerSideEncryption::Aes256,
+                S3ServerSideEncryption::aws_kms => ServerSideEncryption::AwsKms,
+            }),
+            kms_key_id: spec.kms_key_id.clone(),
+            storage_class,
+            content_encoding: spec.content_encoding,
+            request_limiter,
         }))
     }
 
@@ -323,91 +692,45 @@ where
         format!("{}{}", self.key_prefix, key.as_str(),)
     }
 
-    async fn has(self: Pin<&Self>, digest: &StoreKey<'_>) -> Result<Option<u64>, Error> {
-        self.retrier
-            .retry(unfold((), move |state| async move {
-                let result = self
-                    .s3_client
-                    .head_object()
-                    .bucket(&self.bucket)
-                    .key(self.make_s3_path(&digest.borrow()))
-                    .send()
-                    .await;
-
-                match result {
-                    Ok(head_object_output) => {
-                        if self.consider_expired_after_s != 0 {
-                            if let Some(last_modified) = head_object_output.last_modified {
-                                let now_s = (self.now_fn)().unix_timestamp() as i64;
-                                if last_modified.secs() + self.consider_expired_after_s <= now_s {
-                                    return Some((RetryResult::Ok(None), state));
-                                }
-                            }
-                        }
-                        let Some(length) = head_object_output.content_length else {
-                            return Some((RetryResult::Ok(None), state));
-                        };
-                        if length >= 0 {
-                            return Some((RetryResult::Ok(Some(length as u64)), state));
-                        }
-                        Some((
-                            RetryResult::Err(make_err!(
-                                Code::InvalidArgument,
-                                "Negative content length in S3: {length:?}",
-                            )),
-                            state,
-                        ))
-                    }
-                    Err(sdk_error) => match sdk_error.into_service_error() {
-                        HeadObjectError::NotFound(_) => Some((RetryResult::Ok(None), state)),
-                        other => Some((
-                            RetryResult::Retry(make_err!(
-                                Code::Unavailable,
-                                "Unhandled HeadObjectError in S3: {other:?}"
-                            )),
-                            state,
-                        )),
-                    },
-                }
-            }))
+    /// Requests `range` of the object at `s3_path`, or all of it if None.
+    async fn get_object(
+        &self,
+        s3_path: &str,
+        range: Option<String>,
+    ) -> Result<GetObjectOutput, SdkError<GetObjectError, HttpResponse>> {
+        // The permit is released once the response headers arrived.
+        // The body is read at the pace of the consumer, which may
+        // itself need a permit, eg: to upload the data to S3.
+        let _permit = self.request_limiter.acquire().await;
+        self.s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(s3_path)
+            .set_range(range)
+            .send()
             .await
     }
-}
 
-#[async_trait]
-impl<I, NowFn> StoreDriver for S3Store<NowFn>
-where
-    I: InstantWrapper,
-    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
-{
-    async fn has_with_results(
-        self: Pin<&Self>,
-        keys: &[StoreKey<'_>],
-        results: &mut [Option<u64>],
-    ) -> Result<(), Error> {
-        keys.iter()
-            .zip(results.iter_mut())
-            .map(|(key, result)| async move {
-                // We need to do a special pass to ensure our zero key exist.
-                if is_zero_digest(key.borrow()) {
-                    *result = Some(0);
-                    return Ok::<_, Error>(());
-                }
-                *result = self.has(key).await?;
-                Ok::<_, Error>(())
+    /// Value of the `Content-Encoding` header set on uploaded objects.
+    fn content_encoding_header(&self) -> Option<String> {
+        self.content_encoding
+            .map(|content_encoding| match content_encoding {
+                S3ContentEncoding::gzip => "gzip".to_string(),
             })
-            .collect::<FuturesUnordered<_>>()
-            .try_collect()
-            .await
     }
 
-    async fn update(
+    /// Uploads the data in `reader` as-is. `uncompressed_size` is only set
+    /// when the data was gzipped before the upload, in which case it is
+    /// recorded on the object along with the `Content-Encoding` header.
+    async fn upload(
         self: Pin<&Self>,
         digest: StoreKey<'_>,
         mut reader: DropCloserReadHalf,
         upload_size: UploadSizeInfo,
+        uncompressed_size: Option<u64>,
     ) -> Result<(), Error> {
         let s3_path = &self.make_s3_path(&digest.borrow());
+        let content_encoding = &uncompressed_size.and_then(|_| self.content_encoding_header());
 
         let max_size = match upload_size {
             UploadSizeInfo::ExactSize(sz) | UploadSizeInfo::MaxSize(sz) => sz,
@@ -421,7 +744,9 @@ where
         //
         // Note(allada) If the upload size is not known, we go down the multipart upload path.
         // This is not very efficient, but it greatly reduces the complexity of the code.
-        if max_size < MIN_MULTIPART_SIZE && matches!(upload_size, UploadSizeInfo::ExactSize(_)) {
+        if max_size < self.multipart_part_size
+            && matches!(upload_size, UploadSizeInfo::ExactSize(_))
+        {
             let UploadSizeInfo::ExactSize(sz) = upload_size else {
                 unreachable!("upload_size must be UploadSizeInfo::ExactSize here");
             };
@@ -437,6 +762,7 @@ where
                     let (mut tx, rx) = make_buf_channel_pair();
 
                     // Upload the data to the S3 backend.
+                    let _permit = self.request_limiter.acquire().await;
                     let result = {
                         let reader_ref = &mut reader;
                         let (upload_res, bind_res) = tokio::join!(
@@ -445,6 +771,11 @@ where
                                 .bucket(&self.bucket)
                                 .key(s3_path.clone())
                                 .content_length(sz as i64)
+                                .set_server_side_encryption(self.server_side_encryption.clone())
+                                .set_ssekms_key_id(self.kms_key_id.clone())
+                                .set_storage_class(self.storage_class.clone())
+                                .set_content_encoding(content_encoding.clone())
+                                .set_metadata(uncompressed_size_metadata(uncompressed_size))
                                 .body(ByteStream::from_body_1_x(BodyWrapper {
                                     reader: rx,
                                     size: sz,
@@ -492,11 +823,17 @@ where
         let upload_id = &self
             .retrier
             .retry(unfold((), move |()| async move {
+                let _permit = self.request_limiter.acquire().await;
                 let retry_result = self
                     .s3_client
                     .create_multipart_upload()
                     .bucket(&self.bucket)
                     .key(s3_path)
+                    .set_server_side_encryption(self.server_side_encryption.clone())
+                    .set_ssekms_key_id(self.kms_key_id.clone())
+                    .set_storage_class(self.storage_class.clone())
+                    .set_content_encoding(content_encoding.clone())
+                    .set_metadata(uncompressed_size_metadata(uncompressed_size))
                     .send()
                     .await
                     .map_or_else(
@@ -524,8 +861,10 @@ where
 
         // S3 requires us to upload in parts if the size is greater than 5GB. The part size must be at least
         // 5mb (except last part) and can have up to 10,000 parts.
-        let bytes_per_upload_part =
-            (max_size / (MIN_MULTIPART_SIZE - 1)).clamp(MIN_MULTIPART_SIZE, MAX_MULTIPART_SIZE);
+        let bytes_per_upload_part = max_size
+            .div_ceil(MAX_UPLOAD_PARTS as u64)
+            .max(self.multipart_part_size)
+            .clamp(MIN_MULTIPART_SIZE, MAX_MULTIPART_SIZE);
 
         let upload_parts = move || async move {
             // This will ensure we only have `multipart_max_concurrent_uploads` * `bytes_per_upload_part`
@@ -548,6 +887,7 @@ where
                         write_buf,
                         move |write_buf| {
                             async move {
+                                let _permit = self.request_limiter.acquire().await;
                                 let retry_result = self
                                     .s3_client
                                     .upload_part()
@@ -612,6 +952,7 @@ where
 
             self.retrier
                 .retry(unfold(completed_parts, move |completed_parts| async move {
+                    let _permit = self.request_limiter.acquire().await;
                     Some((
                         self.s3_client
                             .complete_multipart_upload()
@@ -643,6 +984,7 @@ where
         // If we fail attempt to abort the multipart upload (cleanup).
         upload_parts()
             .or_else(move |e| async move {
+                let _permit = self.request_limiter.acquire().await;
                 Result::<(), _>::Err(e).merge(
                     // Note: We don't retry here because this is just a best attempt.
                     self.s3_client
@@ -668,6 +1010,265 @@ where
             .await
     }
 
+    async fn delete_object(&self, s3_path: &str) -> Result<(), Error> {
+        self.retrier
+            .retry(unfold((), move |state| async move {
+                let _permit = self.request_limiter.acquire().await;
+                let result = self
+                    .s3_client
+                    .delete_object()
+                    .bucket(&self.bucket)
+                    .key(s3_path)
+                    .send()
+                    .await;
+                match result {
+                    Ok(_) => Some((RetryResult::Ok(()), state)),
+                    Err(sdk_error) => Some((
+                        RetryResult::Retry(make_err!(
+                            Code::Unavailable,
+                            "Unhandled DeleteObjectError in S3: {:?}",
+                            sdk_error.into_service_error()
+                        )),
+                        state,
+                    )),
+                }
+            }))
+            .await
+    }
+
+    /// Lists one page of object paths starting with `s3_prefix` and returns
+    /// them with the continuation token of the next page, if any.
+    async fn list_objects_page(
+        &self,
+        s3_prefix: &str,
+        continuation_token: Option<String>,
+    ) -> Result<(Vec<String>, Option<String>), Error> {
+        let continuation_token = &continuation_token;
+        self.retrier
+            .retry(unfold((), move |state| async move {
+                let _permit = self.request_limiter.acquire().await;
+                let result = self
+                    .s3_client
+                    .list_objects_v2()
+                    .bucket(&self.bucket)
+                    .prefix(s3_prefix)
+                    .set_continuation_token(continuation_token.clone())
+                    .send()
+                    .await;
+                match result {
+                    Ok(output) => {
+                        let s3_paths = output
+                            .contents
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter_map(|object| object.key)
+                            .collect();
+                        let next_continuation_token = if output.is_truncated == Some(true) {
+                            output.next_continuation_token
+                        } else {
+                            None
+                        };
+                        Some((RetryResult::Ok((s3_paths, next_continuation_token)), state))
+                    }
+                    Err(sdk_error) => Some((
+                        RetryResult::Retry(make_err!(
+                            Code::Unavailable,
+                            "Unhandled ListObjectsV2Error in S3: {:?}",
+                            sdk_error.into_service_error()
+                        )),
+                        state,
+                    )),
+                }
+            }))
+            .await
+    }
+
+    async fn has(self: Pin<&Self>, digest: &StoreKey<'_>) -> Result<Option<u64>, Error> {
+        self.retrier
+            .retry(unfold((), move |state| async move {
+                let _permit = self.request_limiter.acquire().await;
+                let result = self
+                    .s3_client
+                    .head_object()
+                    .bucket(&self.bucket)
+                    .key(self.make_s3_path(&digest.borrow()))
+                    .send()
+                    .await;
+
+                match result {
+                    Ok(head_object_output) => {
+                        if self.consider_expired_after_s != 0 {
+                            if let Some(last_modified) = head_object_output.last_modified {
+                                let now_s = (self.now_fn)().unix_timestamp() as i64;
+                                if last_modified.secs() + self.consider_expired_after_s <= now_s {
+                                    return Some((RetryResult::Ok(None), state));
+                                }
+                            }
+                        }
+                        if let Some(uncompressed_size) = head_object_output
+                            .metadata
+                            .as_ref()
+                            .and_then(|metadata| metadata.get(UNCOMPRESSED_SIZE_METADATA_KEY))
+                        {
+                            return Some((
+                                uncompressed_size.parse().map_or_else(
+                                    |e| {
+                                        RetryResult::Err(make_err!(
+                                            Code::Internal,
+                                            "Invalid {UNCOMPRESSED_SIZE_METADATA_KEY} metadata in S3: {uncompressed_size:?} - {e:?}",
+                                        ))
+                                    },
+                                    |size| RetryResult::Ok(Some(size)),
+                                ),
+                                state,
+                            ));
+                        }
+                        let Some(length) = head_object_output.content_length else {
+                            return Some((RetryResult::Ok(None), state));
+                        };
+                        if length >= 0 {
+                            return Some((RetryResult::Ok(Some(length as u64)), state));
+                        }
+                        Some((
+                            RetryResult::Err(make_err!(
+                                Code::InvalidArgument,
+                                "Negative content length in S3: {length:?}",
+                            )),
+                            state,
+                        ))
+                    }
+                    Err(sdk_error) => match sdk_error.into_service_error() {
+                        HeadObjectError::NotFound(_) => Some((RetryResult::Ok(None), state)),
+                        other => Some((
+                            RetryResult::Retry(make_err!(
+                                Code::Unavailable,
+                                "Unhandled HeadObjectError in S3: {other:?}"
+                            )),
+                            state,
+                        )),
+                    },
+                }
+            }))
+            .await
+    }
+}
+
+#[async_trait]
+impl<I, NowFn> StoreDriver for S3Store<NowFn>
+where
+    I: InstantWrapper,
+    NowFn: Fn() -> I + Send + Sync + Unpin + 'static,
+{
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        keys.iter()
+            .zip(results.iter_mut())
+            .map(|(key, result)| async move {
+                // We need to do a special pass to ensure our zero key exist.
+                if is_zero_digest(key.borrow()) {
+                    *result = Some(0);
+                    return Ok::<_, Error>(());
+                }
+                *result = self.has(key).await?;
+                Ok::<_, Error>(())
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_collect()
+            .await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        if is_zero_digest(key.borrow()) {
+            return Ok(false);
+        }
+        // S3 does not tell us if the deleted object existed, so check first.
+        let existed = self
+            .has(&key)
+            .await
+            .err_tip(|| "In S3Store::remove")?
+            .is_some();
+        self.delete_object(&self.make_s3_path(&key))
+            .await
+            .err_tip(|| "In S3Store::remove")?;
+        Ok(existed)
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        let s3_prefix = format!("{}{prefix}", self.key_prefix);
+        let mut removed = 0;
+        let mut continuation_token = None;
+        loop {
+            let (s3_paths, next_continuation_token) = self
+                .list_objects_page(&s3_prefix, continuation_token)
+                .await
+                .err_tip(|| "In S3Store::remove_prefix")?;
+            for s3_path in &s3_paths {
+                self.delete_object(s3_path)
+                    .await
+                    .err_tip(|| "In S3Store::remove_prefix")?;
+                removed += 1;
+            }
+            continuation_token = next_continuation_token;
+            if continuation_token.is_none() {
+                return Ok(removed);
+            }
+        }
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        digest: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        upload_size: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        let (Some(S3ContentEncoding::gzip), UploadSizeInfo::ExactSize(sz)) =
+            (self.content_encoding, upload_size)
+        else {
+            // With an unknown size the uncompressed size is only known once
+            // the data was streamed, but object metadata has to be set when
+            // the upload is created, so these are stored without compression.
+            // Reads decide per object whether to decompress.
+            return self.upload(digest, reader, upload_size, None).await;
+        };
+        let (mut tx, rx) = make_buf_channel_pair();
+        // Small objects are compressed up front so they can still be
+        // uploaded in a single request, which needs an exact size. This
+        // buffers the whole object, so it is limited to the smallest part
+        // size regardless of the configured one.
+        if sz < MIN_MULTIPART_SIZE {
+            let data = reader
+                .consume(None)
+                .await
+                .err_tip(|| "Failed to read data in S3Store::update")?;
+            let compressed = gzip_compress(&data)?;
+            let compressed_size = compressed.len() as u64;
+            let (send_res, upload_res) = tokio::join!(
+                async move {
+                    tx.send(compressed).await?;
+                    tx.send_eof()
+                },
+                self.upload(
+                    digest,
+                    rx,
+                    UploadSizeInfo::ExactSize(compressed_size),
+                    Some(sz),
+                ),
+            );
+            upload_res.merge(send_res)
+        } else {
+            // Gzip only grows incompressible data by a few bytes per
+            // block, which the multipart part sizing has ample room for.
+            let (compress_res, upload_res) = tokio::join!(
+                gzip_compress_stream(&mut reader, &mut tx),
+                self.upload(digest, rx, UploadSizeInfo::MaxSize(sz), Some(sz)),
+            );
+            upload_res.merge(compress_res)
+        }
+    }
+
     async fn get_part(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -689,28 +1290,57 @@ where
 
         self.retrier
             .retry(unfold(writer, move |writer| async move {
-                let result = self
-                    .s3_client
-                    .get_object()
-                    .bucket(&self.bucket)
-                    .key(s3_path)
-                    .range(format!(
-                        "bytes={}-{}",
-                        offset + writer.get_bytes_written(),
-                        end_read_byte.map_or_else(String::new, |v| v.to_string())
-                    ))
-                    .send()
-                    .await;
+                let read_offset = offset + writer.get_bytes_written();
+                let range = format!(
+                    "bytes={read_offset}-{}",
+                    end_read_byte.map_or_else(String::new, |v| v.to_string())
+                );
+                let reads_whole_object = read_offset == 0 && end_read_byte.is_none();
+                // Objects written before compression was enabled, or without
+                // a known size, are stored as-is and not decompressed.
+                let is_gzip = |get_object_output: &GetObjectOutput| {
+                    self.content_encoding.is_some()
+                        && get_object_output.content_encoding.as_deref() == Some("gzip")
+                };
+                // Byte ranges of gzip encoded objects are ranges of the
+                // compressed data, so those objects are read again from the
+                // start and the range is applied after decoding. S3 rejects
+                // ranges starting past the end of the compressed data, which
+                // are read from the start too.
+                let (result, is_ranged) = match self.get_object(s3_path, Some(range)).await {
+                    Ok(get_object_output) if is_gzip(&get_object_output) && !reads_whole_object => {
+                        (self.get_object(s3_path, None).await, false)
+                    }
+                    Err(sdk_error)
+                        if self.content_encoding.is_some()
+                            && sdk_error.raw_response().is_some_and(|response| {
+                                response.status().as_u16() == RANGE_NOT_SATISFIABLE_STATUS
+                            }) =>
+                    {
+                        (self.get_object(s3_path, None).await, false)
+                    }
+                    result => (result, true),
+                };
 
-                let mut s3_in_stream = match result {
-                    Ok(head_object_output) => head_object_output.body,
+                let (mut s3_in_stream, mut maybe_decoder) = match result {
+                    Ok(get_object_output) => {
+                        let is_gzip = is_gzip(&get_object_output);
+                        let maybe_decoder = (is_gzip || !is_ranged).then(|| {
+                            RangeDecoder::new(
+                                is_gzip,
+                                read_offset,
+                                length.map(|length| length - writer.get_bytes_written()),
+                            )
+                        });
+                        (get_object_output.body, maybe_decoder)
+                    }
                     Err(sdk_error) => match sdk_error.into_service_error() {
                         GetObjectError::NoSuchKey(e) => {
                             return Some((
-                                RetryResult::Err(make_err!(
-                                    Code::NotFound,
-                                    "No such key in S3: {e}"
-                                )),
+                                RetryResult::Err(
+                                    make_err!(Code::NotFound, "No such key in S3: {e}")
+                                        .with_reason(ErrorReason::NotFound),
+                                ),
                                 writer,
                             ));
                         }
@@ -735,14 +1365,24 @@ where
                                 // send EOF this way.
                                 continue;
                             }
-                            if let Err(e) = writer.send(bytes).await {
-                                return Some((
-                                    RetryResult::Err(make_err!(
-                                        Code::Aborted,
-                                        "Error sending bytes to consumer in S3: {e}"
-                                    )),
-                                    writer,
-                                ));
+                            let bytes = match maybe_decoder.as_mut().map(|d| d.decode(bytes)) {
+                                None => bytes,
+                                Some(Ok(bytes)) => bytes,
+                                Some(Err(err)) => return Some((RetryResult::Retry(err), writer)),
+                            };
+                            if !bytes.is_empty() {
+                                if let Err(e) = writer.send(bytes).await {
+                                    return Some((
+                                        RetryResult::Err(make_err!(
+                                            Code::Aborted,
+                                            "Error sending bytes to consumer in S3: {e}"
+                                        )),
+                                        writer,
+                                    ));
+                                }
+                            }
+                            if maybe_decoder.as_ref().is_some_and(RangeDecoder::is_done) {
+                                break;
                             }
                         }
                         Err(e) => {
@@ -756,6 +1396,23 @@ where
                         }
                     }
                 }
+                if let Some(decoder) = maybe_decoder.as_mut().filter(|d| !d.is_done()) {
+                    let bytes = match decoder.finish() {
+                        Ok(bytes) => bytes,
+                        Err(err) => return Some((RetryResult::Retry(err), writer)),
+                    };
+                    if !bytes.is_empty() {
+                        if let Err(e) = writer.send(bytes).await {
+                            return Some((
+                                RetryResult::Err(make_err!(
+                                    Code::Aborted,
+                                    "Error sending bytes to consumer in S3: {e}"
+                                )),
+                                writer,
+                            ));
+                        }
+                    }
+                }
                 if let Err(e) = writer.send_eof() {
                     return Some((
                         RetryResult::Err(make_err!(
@@ -793,7 +1450,21 @@ where
         "S3Store"
     }
 
-    async fn check_health(&self, namespace: Cow<'static, str>) -> HealthStatus {
-        StoreDriver::check_health(Pin::new(self), namespace).await
+    /// Only checks that the bucket is reachable with HeadBucket, so health
+    /// checks don't write objects to the bucket.
+    async fn check_health(&self, _namespace: Cow<'static, str>) -> HealthStatus {
+        match self
+            .s3_client
+            .head_bucket()
+            .bucket(&self.bucket)
+            .send()
+            .await
+        {
+            Ok(_) => HealthStatus::new_ok(self, "HeadBucket succeeded".into()),
+            Err(e) => HealthStatus::new_failed(
+                self,
+                format!("HeadBucket on bucket {} failed: {e:?}", self.bucket).into(),
+            ),
+        }
     }
 }
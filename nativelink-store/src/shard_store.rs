@@ -86,39 +86,18 @@ impl ShardStore {
     fn get_store_index(&self, store_key: &StoreKey) -> usize {
         let key = match store_key {
             StoreKey::Digest(digest) => {
-                // Quote from std primitive array documentation:
-                //     Array’s try_from(slice) implementations (and the corresponding slice.try_into()
-                //     array implementations) succeed if the input slice length is the same as the result
-                //     array length. They optimize especially well when the optimizer can easily determine
-                //     the slice length, e.g. <[u8; 4]>::try_from(&slice[4..8]).unwrap(). Array implements
-                //     TryFrom returning.
+                // Xor every 4 bytes of the hash and size together. A hash
+                // length that is not a multiple of 4 is padded with zeros.
                 let size_bytes = digest.size_bytes().to_le_bytes();
-                0.bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[0..4].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[4..8].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[8..12].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[12..16].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[16..20].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[20..24].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[24..28].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(
-                    digest.packed_hash()[28..32].try_into().unwrap(),
-                ))
-                .bitxor(u32::from_le_bytes(size_bytes[0..4].try_into().unwrap()))
-                .bitxor(u32::from_le_bytes(size_bytes[4..8].try_into().unwrap()))
+                digest
+                    .packed_hash()
+                    .chunks(4)
+                    .chain(size_bytes.chunks(4))
+                    .fold(0u32, |key, chunk| {
+                        let mut word = [0u8; 4];
+                        word[..chunk.len()].copy_from_slice(chunk);
+                        key.bitxor(u32::from_le_bytes(word))
+                    })
             }
             StoreKey::Str(s) => {
                 let mut hasher = DefaultHasher::new();
@@ -194,6 +173,46 @@ impl StoreDriver for ShardStore {
         Ok(())
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.get_store(&key).remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let mut keys_for_store: Vec<Vec<StoreKey>> =
+            self.weights_and_stores.iter().map(|_| Vec::new()).collect();
+        for key in keys {
+            keys_for_store[self.get_store_index(key)].push(key.borrow());
+        }
+        keys_for_store
+            .iter()
+            .enumerate()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(store_idx, keys)| async move {
+                self.weights_and_stores[store_idx]
+                    .store
+                    .touch(keys)
+                    .await
+                    .err_tip(|| format!("In ShardStore::touch() for store {store_idx}"))
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_fold(0, |total, touched| async move {
+                Ok::<_, Error>(total + touched)
+            })
+            .await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        // Keys with the same prefix may live in any shard.
+        self.weights_and_stores
+            .iter()
+            .map(|item| item.store.remove_prefix(prefix))
+            .collect::<FuturesUnordered<_>>()
+            .try_fold(0, |total, removed| async move {
+                Ok::<_, Error>(total + removed)
+            })
+            .await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -0,0 +1,207 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use nativelink_config::stores::SingleflightSpec;
+use nativelink_error::{Error, ResultExt};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use parking_lot::Mutex;
+
+// NOTE: If this changes update the comments in `stores.rs` to reflect
+// the new default.
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024; // 16mib.
+
+/// Identifies a read that can be shared: the key and the exact range.
+type ReadKey = (StoreKey<'static>, u64, Option<u64>);
+
+/// A backend read that any number of readers may await.
+type SharedRead = Shared<BoxFuture<'static, Result<Bytes, Error>>>;
+
+#[derive(MetricsComponent)]
+pub struct SingleflightStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Reads larger than this are not coalesced")]
+    max_size: u64,
+    /// Backend reads in progress, keyed by what they read.
+    in_flight_reads: Mutex<HashMap<ReadKey, SharedRead>>,
+    #[metric(help = "Number of reads sent to the inner store")]
+    backend_reads: AtomicU64,
+    #[metric(help = "Number of reads that joined a read already in progress")]
+    coalesced_reads: AtomicU64,
+}
+
+impl SingleflightStore {
+    pub fn new(spec: &SingleflightSpec, inner_store: Store) -> Arc<Self> {
+        let max_size = if spec.max_size == 0 {
+            DEFAULT_MAX_SIZE
+        } else {
+            spec.max_size
+        };
+        Arc::new(Self {
+            inner_store,
+            max_size,
+            in_flight_reads: Mutex::new(HashMap::new()),
+            backend_reads: AtomicU64::new(0),
+            coalesced_reads: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns true if a read of `key` at `offset` and `length` is small
+    /// enough to be buffered in memory and shared.
+    fn should_coalesce(&self, key: &StoreKey<'_>, offset: u64, length: Option<u64>) -> bool {
+        let read_size = match (key, length) {
+            (StoreKey::Digest(digest), length) => {
+                let remaining = digest.size_bytes().saturating_sub(offset);
+                length.map_or(remaining, |length| length.min(remaining))
+            }
+            (StoreKey::Str(_), Some(length)) => length,
+            // The size is unknown, so it may be arbitrarily large.
+            (StoreKey::Str(_), None) => return false,
+        };
+        read_size <= self.max_size
+    }
+
+    /// Returns the backend read for `read_key`, starting it if no read of the
+    /// same range is in progress.
+    fn join_or_start_read(&self, read_key: &ReadKey) -> SharedRead {
+        let mut in_flight_reads = self.in_flight_reads.lock();
+        if let Some(shared_read) = in_flight_reads.get(read_key) {
+            self.coalesced_reads.fetch_add(1, Ordering::Relaxed);
+            return shared_read.clone();
+        }
+        self.backend_reads.fetch_add(1, Ordering::Relaxed);
+        let inner_store = self.inner_store.clone();
+        let (key, offset, length) = read_key.clone();
+        let shared_read = async move {
+            inner_store
+                .get_part_unchunked(key, offset, length)
+                .await
+                .err_tip(|| "In SingleflightStore::get_part")
+        }
+        .boxed()
+        .shared();
+        in_flight_reads.insert(read_key.clone(), shared_read.clone());
+        shared_read
+    }
+}
+
+/// Removes a read from `in_flight_reads` once a reader is done with it, so
+/// later reads go to the backend again instead of getting stale results.
+struct InFlightReadGuard<'a> {
+    in_flight_reads: &'a Mutex<HashMap<ReadKey, SharedRead>>,
+    read_key: ReadKey,
+    shared_read: SharedRead,
+}
+
+impl Drop for InFlightReadGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight_reads = self.in_flight_reads.lock();
+        // A newer read of the same range may have replaced ours.
+        if in_flight_reads
+            .get(&self.read_key)
+            .is_some_and(|shared_read| shared_read.ptr_eq(&self.shared_read))
+        {
+            in_flight_reads.remove(&self.read_key);
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDriver for SingleflightStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner_store.has_with_results(keys, results).await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inner_store.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        if !self.should_coalesce(&key, offset, length) {
+            return self.inner_store.get_part(key, writer, offset, length).await;
+        }
+        let read_key = (key.into_owned(), offset, length);
+        let shared_read = self.join_or_start_read(&read_key);
+        let guard = InFlightReadGuard {
+            in_flight_reads: &self.in_flight_reads,
+            read_key,
+            shared_read: shared_read.clone(),
+        };
+        let data = shared_read.await;
+        drop(guard);
+        let data = data?;
+        if !data.is_empty() {
+            writer
+                .send(data)
+                .await
+                .err_tip(|| "Failed to write data in SingleflightStore::get_part")?;
+        }
+        writer
+            .send_eof()
+            .err_tip(|| "Failed to write EOF in SingleflightStore::get_part")
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(SingleflightStore);
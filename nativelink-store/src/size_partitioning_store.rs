@@ -12,22 +12,62 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp::Ordering;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use nativelink_config::stores::SizePartitioningSpec;
+use nativelink_config::stores::{PartitionPredicate, SizePartitioningSpec};
 use nativelink_error::{make_input_err, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
 use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 use tokio::join;
 
+/// A range of hex hash prefixes stored as nibbles, both ends inclusive.
+struct NibbleRange {
+    start: Vec<u8>,
+    end: Vec<u8>,
+}
+
+impl NibbleRange {
+    fn contains(&self, digest: &DigestInfo) -> bool {
+        let nibbles = || {
+            digest
+                .packed_hash()
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0xf])
+        };
+        nibbles()
+            .take(self.start.len())
+            .cmp(self.start.iter().copied())
+            != Ordering::Less
+            && nibbles().take(self.end.len()).cmp(self.end.iter().copied()) != Ordering::Greater
+    }
+}
+
+fn hex_to_nibbles(hex: &str) -> Result<Vec<u8>, Error> {
+    hex.chars()
+        .map(|c| {
+            c.to_digit(16).map(|nibble| nibble as u8).ok_or_else(|| {
+                make_input_err!("Invalid hex character {c:?} in hash prefix {hex:?}")
+            })
+        })
+        .collect()
+}
+
+enum Predicate {
+    Size(u64),
+    HashPrefix(Vec<NibbleRange>),
+}
+
 #[derive(MetricsComponent)]
 pub struct SizePartitioningStore {
     #[metric(help = "Size to partition our data")]
     partition_size: u64,
+    predicate: Predicate,
     #[metric(group = "lower_store")]
     lower_store: Store,
     #[metric(group = "upper_store")]
@@ -35,12 +75,40 @@ pub struct SizePartitioningStore {
 }
 
 impl SizePartitioningStore {
-    pub fn new(spec: &SizePartitioningSpec, lower_store: Store, upper_store: Store) -> Arc<Self> {
-        Arc::new(SizePartitioningStore {
+    pub fn new(
+        spec: &SizePartitioningSpec,
+        lower_store: Store,
+        upper_store: Store,
+    ) -> Result<Arc<Self>, Error> {
+        let predicate = match &spec.predicate {
+            PartitionPredicate::size => Predicate::Size(spec.size),
+            PartitionPredicate::hash_prefix(ranges) => Predicate::HashPrefix(
+                ranges
+                    .iter()
+                    .map(|range| {
+                        Ok(NibbleRange {
+                            start: hex_to_nibbles(&range.start)?,
+                            end: hex_to_nibbles(&range.end)?,
+                        })
+                    })
+                    .collect::<Result<_, Error>>()
+                    .err_tip(|| "In SizePartitioningStore::new")?,
+            ),
+        };
+        Ok(Arc::new(SizePartitioningStore {
             partition_size: spec.size,
+            predicate,
             lower_store,
             upper_store,
-        })
+        }))
+    }
+
+    /// Returns true if the digest should be routed to the lower store.
+    fn is_lower(&self, digest: &DigestInfo) -> bool {
+        match &self.predicate {
+            Predicate::Size(partition_size) => digest.size_bytes() < *partition_size,
+            Predicate::HashPrefix(ranges) => !ranges.iter().any(|range| range.contains(digest)),
+        }
     }
 }
 
@@ -58,7 +126,7 @@ impl StoreDriver for SizePartitioningStore {
                     non_digest_sample = Some(k.borrow().into_owned());
                     return false;
                 };
-                digest.size_bytes() < self.partition_size
+                self.is_lower(digest)
             });
         if let Some(non_digest) = non_digest_sample {
             return Err(make_input_err!(
@@ -93,6 +161,55 @@ impl StoreDriver for SizePartitioningStore {
         Ok(())
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        let digest = match key {
+            StoreKey::Digest(digest) => digest,
+            other @ StoreKey::Str(_) => {
+                return Err(make_input_err!(
+                    "SizePartitioningStore only supports Digest keys, got {other:?}"
+                ))
+            }
+        };
+        if self.is_lower(&digest) {
+            return self.lower_store.remove(digest).await;
+        }
+        self.upper_store.remove(digest).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        let mut lower_keys = Vec::new();
+        let mut upper_keys = Vec::new();
+        for key in keys {
+            let StoreKey::Digest(digest) = key else {
+                return Err(make_input_err!(
+                    "SizePartitioningStore only supports Digest keys, got {key:?}"
+                ));
+            };
+            if self.is_lower(digest) {
+                lower_keys.push(key.borrow());
+            } else {
+                upper_keys.push(key.borrow());
+            }
+        }
+        let (lower_res, upper_res) = join!(
+            self.lower_store.touch(&lower_keys),
+            self.upper_store.touch(&upper_keys)
+        );
+        let lower_touched = lower_res.err_tip(|| "Failed to touch lower store")?;
+        let upper_touched = upper_res.err_tip(|| "Failed to touch upper store")?;
+        Ok(lower_touched + upper_touched)
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        let (lower_res, upper_res) = join!(
+            self.lower_store.remove_prefix(prefix),
+            self.upper_store.remove_prefix(prefix)
+        );
+        let lower_removed = lower_res.err_tip(|| "Failed to remove prefix from lower store")?;
+        let upper_removed = upper_res.err_tip(|| "Failed to remove prefix from upper store")?;
+        Ok(lower_removed + upper_removed)
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -107,7 +224,7 @@ impl StoreDriver for SizePartitioningStore {
                 ))
             }
         };
-        if digest.size_bytes() < self.partition_size {
+        if self.is_lower(&digest) {
             return self.lower_store.update(digest, reader, size_info).await;
         }
         self.upper_store.update(digest, reader, size_info).await
@@ -128,7 +245,7 @@ impl StoreDriver for SizePartitioningStore {
                 ))
             }
         };
-        if digest.size_bytes() < self.partition_size {
+        if self.is_lower(&digest) {
             return self
                 .lower_store
                 .get_part(digest, writer, offset, length)
@@ -146,7 +263,7 @@ impl StoreDriver for SizePartitioningStore {
         let StoreKey::Digest(digest) = key else {
             return self;
         };
-        if digest.size_bytes() < self.partition_size {
+        if self.is_lower(&digest) {
             return self.lower_store.inner_store(Some(digest));
         }
         self.upper_store.inner_store(Some(digest))
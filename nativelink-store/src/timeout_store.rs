@@ -0,0 +1,188 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::{pin_mut, select, Future, FutureExt};
+use nativelink_config::stores::TimeoutSpec;
+use nativelink_error::{make_err, Code, Error};
+use nativelink_metric::MetricsComponent;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+
+/// Default for `TimeoutSpec::timeout_ms`.
+/// If this changes, remember to change the documentation in the config.
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+
+#[derive(MetricsComponent)]
+pub struct TimeoutStore {
+    #[metric(group = "inner_store")]
+    inner_store: Store,
+    #[metric(help = "Time an operation may go without progress before it fails")]
+    timeout: Duration,
+    #[metric(help = "Number of operations that failed because they timed out")]
+    timeouts: AtomicU64,
+}
+
+impl TimeoutStore {
+    pub fn new(spec: &TimeoutSpec, inner_store: Store) -> Arc<Self> {
+        let timeout_ms = if spec.timeout_ms == 0 {
+            DEFAULT_TIMEOUT_MS
+        } else {
+            spec.timeout_ms
+        };
+        Arc::new(Self {
+            inner_store,
+            timeout: Duration::from_millis(timeout_ms),
+            timeouts: AtomicU64::new(0),
+        })
+    }
+
+    /// Runs `operation`, failing with `DeadlineExceeded` if it does not
+    /// finish within the timeout. The operation is dropped on timeout.
+    async fn with_timeout<T>(
+        &self,
+        operation_name: &str,
+        operation: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        tokio::time::timeout(self.timeout, operation)
+            .await
+            .unwrap_or_else(|_| {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(make_err!(
+                    Code::DeadlineExceeded,
+                    "TimeoutStore::{operation_name} did not make progress within {:?}",
+                    self.timeout
+                ))
+            })
+    }
+
+    /// Moves all data from `reader` to `writer`, failing if receiving or
+    /// sending a single chunk takes longer than the timeout.
+    async fn forward_with_timeout(
+        &self,
+        operation_name: &str,
+        reader: &mut DropCloserReadHalf,
+        writer: &mut DropCloserWriteHalf,
+    ) -> Result<(), Error> {
+        loop {
+            let chunk = self.with_timeout(operation_name, reader.recv()).await?;
+            if chunk.is_empty() {
+                return writer.send_eof();
+            }
+            self.with_timeout(operation_name, writer.send(chunk))
+                .await?;
+        }
+    }
+}
+
+#[async_trait]
+impl StoreDriver for TimeoutStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.with_timeout("has", self.inner_store.has_with_results(keys, results))
+            .await
+    }
+
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        // The timeout restarts whenever a chunk is forwarded, so large
+        // uploads that keep making progress are not cut off.
+        let (mut tx, rx) = make_buf_channel_pair();
+        let update_fut = self.inner_store.update(key, rx, size_info).fuse();
+        let forward_fut = self
+            .forward_with_timeout("update", &mut reader, &mut tx)
+            .fuse();
+        pin_mut!(update_fut, forward_fut);
+        select! {
+            result = update_fut => result,
+            result = forward_fut => {
+                result?;
+                self.with_timeout("update", update_fut).await
+            }
+        }
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        // The timeout restarts whenever a chunk is forwarded, so large
+        // downloads that keep making progress are not cut off.
+        let (mut tx, mut rx) = make_buf_channel_pair();
+        let get_part_fut = async move {
+            self.inner_store
+                .get_part(key, &mut tx, offset, length)
+                .await
+        }
+        .fuse();
+        let forward_fut = self
+            .forward_with_timeout("get_part", &mut rx, writer)
+            .fuse();
+        pin_mut!(get_part_fut, forward_fut);
+        select! {
+            result = get_part_fut => {
+                result?;
+                forward_fut.await
+            }
+            result = forward_fut => {
+                result?;
+                self.with_timeout("get_part", get_part_fut).await
+            }
+        }
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(TimeoutStore);
@@ -58,6 +58,10 @@ impl VerifyStore {
         })
     }
 
+    /// Forwards `rx` to `tx` while counting and hashing the data as it
+    /// streams. The EOF is only forwarded once every check has passed, so any
+    /// failure drops `tx` without an EOF and the inner store update fails
+    /// instead of committing the data.
     async fn inner_check_update<D: DigestHasher>(
         &self,
         mut tx: DropCloserWriteHalf,
@@ -120,6 +124,8 @@ impl VerifyStore {
                     let hash_result = digest.packed_hash();
                     if original_hash != hash_result {
                         self.hash_verification_failures.inc();
+                        // Returning drops `tx` without an EOF, which aborts the
+                        // inner store update so the bad data is never committed.
                         return Err(make_input_err!(
                             "Hashes do not match, got: {original_hash} but digest hash was {hash_result}",
                         ));
@@ -154,6 +160,18 @@ impl StoreDriver for VerifyStore {
         self.inner_store.has_with_results(digests, results).await
     }
 
+    async fn remove(self: Pin<&Self>, key: StoreKey<'_>) -> Result<bool, Error> {
+        self.inner_store.remove(key).await
+    }
+
+    async fn touch(self: Pin<&Self>, keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        self.inner_store.touch(keys).await
+    }
+
+    async fn remove_prefix(self: Pin<&Self>, prefix: &str) -> Result<u64, Error> {
+        self.inner_store.remove_prefix(prefix).await
+    }
+
     async fn update(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -166,14 +184,28 @@ impl StoreDriver for VerifyStore {
             ));
         };
         let digest_size = digest.size_bytes();
-        if let UploadSizeInfo::ExactSize(expected_size) = size_info {
-            if self.verify_size && expected_size != digest_size {
-                self.size_verification_failures.inc();
-                return Err(make_input_err!(
-                    "Expected size to match. Got {} but digest says {} on update",
-                    expected_size,
-                    digest_size
-                ));
+        if self.verify_size {
+            match size_info {
+                UploadSizeInfo::ExactSize(expected_size) if expected_size != digest_size => {
+                    self.size_verification_failures.inc();
+                    return Err(make_input_err!(
+                        "Expected size to match. Got {} but digest says {} on update",
+                        expected_size,
+                        digest_size
+                    ));
+                }
+                // When the exact size is unknown the streamed bytes are counted
+                // in `inner_check_update`, but we can still reject uploads that
+                // can never reach the digest size.
+                UploadSizeInfo::MaxSize(max_size) if max_size < digest_size => {
+                    self.size_verification_failures.inc();
+                    return Err(make_input_err!(
+                        "Max upload size {} is smaller than digest size {} on update",
+                        max_size,
+                        digest_size
+                    ));
+                }
+                _ => {}
             }
         }
 
@@ -0,0 +1,118 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use mock_instant::thread_local::MockClock;
+use nativelink_config::stores::{ActionResultTtlSpec, MemorySpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::ActionResult;
+use nativelink_store::ac_utils::get_and_decode_digest;
+use nativelink_store::action_result_ttl_store::ActionResultTtlStore;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::instant_wrapper::MockInstantWrapped;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+use prost::Message;
+
+const ACTION_DIGEST: DigestInfo = DigestInfo::new([1u8; 32], 100);
+const TTL_S: u64 = 60;
+
+fn make_ttl_store() -> Store {
+    make_ttl_store_with_inner(Store::new(MemoryStore::new(&MemorySpec::default())))
+}
+
+fn make_ttl_store_with_inner(inner_store: Store) -> Store {
+    Store::new(ActionResultTtlStore::new(
+        &ActionResultTtlSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            ttl_s: TTL_S,
+        },
+        inner_store,
+        MockInstantWrapped::default,
+    ))
+}
+
+fn make_action_result() -> ActionResult {
+    ActionResult {
+        exit_code: 1,
+        stdout_raw: "foo".into(),
+        ..Default::default()
+    }
+}
+
+#[nativelink_test]
+async fn fresh_action_result_reads_normally() -> Result<(), Error> {
+    let store = make_ttl_store();
+    let action_result = make_action_result();
+    let encoded = action_result.encode_to_vec();
+    store
+        .update_oneshot(ACTION_DIGEST, encoded.clone().into())
+        .await?;
+
+    MockClock::advance(Duration::from_secs(TTL_S));
+    assert_eq!(store.has(ACTION_DIGEST).await?, Some(encoded.len() as u64));
+    assert_eq!(
+        get_and_decode_digest::<ActionResult>(&store, ACTION_DIGEST.into()).await?,
+        action_result
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn expired_action_result_is_not_found() -> Result<(), Error> {
+    let store = make_ttl_store();
+    store
+        .update_oneshot(ACTION_DIGEST, make_action_result().encode_to_vec().into())
+        .await?;
+
+    MockClock::advance(Duration::from_secs(TTL_S + 1));
+    assert_eq!(store.has(ACTION_DIGEST).await?, None);
+    let err = get_and_decode_digest::<ActionResult>(&store, ACTION_DIGEST.into())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code, Code::NotFound, "{err:?}");
+
+    // Writing the action result again makes it fresh.
+    store
+        .update_oneshot(ACTION_DIGEST, make_action_result().encode_to_vec().into())
+        .await?;
+    assert_eq!(
+        get_and_decode_digest::<ActionResult>(&store, ACTION_DIGEST.into()).await?,
+        make_action_result()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn legacy_action_result_without_header_never_expires() -> Result<(), Error> {
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = make_ttl_store_with_inner(inner_store.clone());
+    // Written before the TTL store was added, so it has no header.
+    let action_result = make_action_result();
+    let encoded = action_result.encode_to_vec();
+    inner_store
+        .update_oneshot(ACTION_DIGEST, encoded.clone().into())
+        .await?;
+
+    MockClock::advance(Duration::from_secs(TTL_S + 1));
+    assert_eq!(store.has(ACTION_DIGEST).await?, Some(encoded.len() as u64));
+    assert_eq!(
+        get_and_decode_digest::<ActionResult>(&store, ACTION_DIGEST.into()).await?,
+        action_result
+    );
+    Ok(())
+}
@@ -0,0 +1,211 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nativelink_config::stores::{AsyncMirrorSpec, MemorySpec, StoreSpec};
+use nativelink_error::{make_err, Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_store::async_mirror_store::AsyncMirrorStore;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALID_HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+const VALUE2: &str = "9876543210";
+
+/// Store that holds each write until `gate` has a permit.
+#[derive(MetricsComponent)]
+struct GatedWriteStore {
+    inner: Store,
+    gate: Semaphore,
+}
+
+#[async_trait]
+impl StoreDriver for GatedWriteStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner.has_with_results(keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.gate
+            .acquire()
+            .await
+            .map_err(|e| make_err!(Code::Internal, "{:?}", e))?
+            .forget();
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _key: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any<'a>(&'a self) -> &'a (dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(GatedWriteStore);
+
+fn make_spec() -> AsyncMirrorSpec {
+    AsyncMirrorSpec {
+        primary: StoreSpec::memory(MemorySpec::default()),
+        secondary: StoreSpec::memory(MemorySpec::default()),
+        max_pending_mirrors: 0,
+        max_concurrent_mirrors: 0,
+        read_fallback_to_secondary: false,
+    }
+}
+
+/// Waits until every queued blob was copied to the secondary store.
+async fn wait_for_mirrors(store: &AsyncMirrorStore) -> Result<(), Error> {
+    timeout(Duration::from_secs(10), async {
+        while store.pending_mirrors() != 0 {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .map_err(|_| make_err!(Code::DeadlineExceeded, "Mirrors did not finish in time"))
+}
+
+#[nativelink_test]
+async fn primary_write_returns_before_mirror_completes() -> Result<(), Error> {
+    let primary_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let secondary_inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let secondary_store = Arc::new(GatedWriteStore {
+        inner: secondary_inner_store.clone(),
+        gate: Semaphore::new(0),
+    });
+    let mirror_store = AsyncMirrorStore::new(
+        &make_spec(),
+        primary_store.clone(),
+        Store::new(secondary_store.clone()),
+    );
+    let store = Store::new(mirror_store.clone());
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    // The secondary store blocks all writes, so this only returns if the
+    // write does not wait for the mirror.
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(primary_store.has(digest).await?, Some(VALUE1.len() as u64));
+    assert_eq!(secondary_inner_store.has(digest).await?, None);
+    assert_eq!(mirror_store.pending_mirrors(), 1);
+
+    secondary_store.gate.add_permits(1);
+    wait_for_mirrors(&mirror_store).await?;
+    assert_eq!(
+        secondary_inner_store
+            .get_part_unchunked(digest, 0, None)
+            .await?,
+        VALUE1.as_bytes()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn mirror_eventually_has_all_written_data() -> Result<(), Error> {
+    let secondary_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let mirror_store = AsyncMirrorStore::new(
+        &make_spec(),
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+        secondary_store.clone(),
+    );
+    let store = Store::new(mirror_store.clone());
+    let digest1 = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, VALUE2.len())?;
+
+    store.update_oneshot(digest1, VALUE1.into()).await?;
+    store.update_oneshot(digest2, VALUE2.into()).await?;
+    wait_for_mirrors(&mirror_store).await?;
+
+    assert_eq!(
+        secondary_store.get_part_unchunked(digest1, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    assert_eq!(
+        secondary_store.get_part_unchunked(digest2, 0, None).await?,
+        VALUE2.as_bytes()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn reads_fall_back_to_secondary_when_enabled() -> Result<(), Error> {
+    let secondary_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    secondary_store
+        .update_oneshot(digest, VALUE1.into())
+        .await?;
+
+    let store_without_fallback = Store::new(AsyncMirrorStore::new(
+        &make_spec(),
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+        secondary_store.clone(),
+    ));
+    assert_eq!(store_without_fallback.has(digest).await?, None);
+
+    let store_with_fallback = Store::new(AsyncMirrorStore::new(
+        &AsyncMirrorSpec {
+            read_fallback_to_secondary: true,
+            ..make_spec()
+        },
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+        secondary_store,
+    ));
+    assert_eq!(
+        store_with_fallback.has(digest).await?,
+        Some(VALUE1.len() as u64)
+    );
+    assert_eq!(
+        store_with_fallback
+            .get_part_unchunked(digest, 0, None)
+            .await?,
+        VALUE1.as_bytes()
+    );
+    Ok(())
+}
@@ -0,0 +1,281 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bincode::{DefaultOptions, Options};
+use futures::join;
+use nativelink_config::stores::{ChunkedSpec, MemorySpec, StoreSpec};
+use nativelink_error::{Error, ResultExt};
+use nativelink_macro::nativelink_test;
+use nativelink_store::chunked_store::{ChunkedManifest, ChunkedStore};
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::buf_channel::make_buf_channel_pair;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+const CHUNK_SIZE: usize = 16 * 1024;
+
+fn make_default_config() -> ChunkedSpec {
+    ChunkedSpec {
+        backend: StoreSpec::memory(MemorySpec::default()),
+        chunk_size: CHUNK_SIZE as u64,
+        max_concurrent_fetch_per_get: 2,
+    }
+}
+
+fn make_random_data(sz: usize) -> Vec<u8> {
+    let mut value = vec![0u8; sz];
+    let mut rng = SmallRng::seed_from_u64(1);
+    rng.fill(&mut value[..]);
+    value
+}
+
+async fn get_manifest(backend: &Store, digest: DigestInfo) -> Result<ChunkedManifest, Error> {
+    let data = backend
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to read manifest from backend")?;
+    Ok(DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize::<ChunkedManifest>(&data)
+        .unwrap())
+}
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const DATA_SIZE: usize = 100_000;
+
+#[nativelink_test]
+async fn round_trip_larger_than_chunk_size_test() -> Result<(), Error> {
+    let backend = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = ChunkedStore::new(&make_default_config(), backend.clone())?;
+
+    let original_data = make_random_data(DATA_SIZE);
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE).unwrap();
+
+    store
+        .update_oneshot(digest, original_data.clone().into())
+        .await
+        .err_tip(|| "Failed to write data to chunked store")?;
+
+    let manifest = get_manifest(&backend, digest).await?;
+    let chunk_sizes: Vec<u64> = manifest
+        .chunks
+        .iter()
+        .map(|chunk| chunk.size_bytes)
+        .collect();
+    assert_eq!(
+        chunk_sizes,
+        vec![16384, 16384, 16384, 16384, 16384, 16384, 1696],
+        "Expected the data to be split at chunk_size"
+    );
+
+    let rt_data = store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to get_part from chunked store")?;
+    assert_eq!(rt_data, original_data, "Expected round trip data to match");
+
+    assert_eq!(
+        store.has(digest).await,
+        Ok(Some(DATA_SIZE as u64)),
+        "Expected the entry to exist"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn range_read_fetches_only_covering_chunks_test() -> Result<(), Error> {
+    const OFFSET: usize = 30_000;
+    const LENGTH: usize = 10_000;
+
+    let backend = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = ChunkedStore::new(&make_default_config(), backend.clone())?;
+
+    let original_data = make_random_data(DATA_SIZE);
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE).unwrap();
+
+    store
+        .update_oneshot(digest, original_data.clone().into())
+        .await
+        .err_tip(|| "Failed to write data to chunked store")?;
+
+    // The range only covers the second and third chunks, so every other
+    // chunk is removed from the backend to prove it is never fetched.
+    let manifest = get_manifest(&backend, digest).await?;
+    for (i, chunk) in manifest.chunks.iter().enumerate() {
+        if i != 1 && i != 2 {
+            assert!(backend.remove(StoreKey::new_str(&chunk.key)).await?);
+        }
+    }
+
+    let rt_data = store
+        .get_part_unchunked(digest, OFFSET as u64, Some(LENGTH as u64))
+        .await
+        .err_tip(|| "Failed to get_part from chunked store")?;
+    assert_eq!(
+        rt_data,
+        &original_data[OFFSET..OFFSET + LENGTH],
+        "Expected partial data to match"
+    );
+
+    let rt_data = store.get_part_unchunked(digest, 0, None).await;
+    assert!(
+        rt_data.is_err(),
+        "Expected reading the removed chunks to fail, got {rt_data:?}"
+    );
+    assert_eq!(
+        store.has(digest).await,
+        Ok(None),
+        "Expected an entry with missing chunks to not exist"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn digest_within_chunk_size_is_stored_as_is_test() -> Result<(), Error> {
+    const SMALL_DATA_SIZE: usize = 1000;
+
+    let backend = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = ChunkedStore::new(&make_default_config(), backend.clone())?;
+
+    let original_data = make_random_data(SMALL_DATA_SIZE);
+    let digest = DigestInfo::try_new(VALID_HASH1, SMALL_DATA_SIZE).unwrap();
+
+    store
+        .update_oneshot(digest, original_data.clone().into())
+        .await
+        .err_tip(|| "Failed to write data to chunked store")?;
+
+    let backend_data = backend
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to get_part from backend")?;
+    assert_eq!(
+        backend_data, original_data,
+        "Expected the data to be stored without a manifest"
+    );
+
+    let rt_data = store
+        .get_part_unchunked(digest, 100, Some(200))
+        .await
+        .err_tip(|| "Failed to get_part from chunked store")?;
+    assert_eq!(
+        rt_data,
+        &original_data[100..300],
+        "Expected partial data to match"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn remove_deletes_manifest_and_chunks_test() -> Result<(), Error> {
+    let backend = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = ChunkedStore::new(&make_default_config(), backend.clone())?;
+
+    let original_data = make_random_data(DATA_SIZE);
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE).unwrap();
+
+    store
+        .update_oneshot(digest, original_data.into())
+        .await
+        .err_tip(|| "Failed to write data to chunked store")?;
+    let manifest = get_manifest(&backend, digest).await?;
+
+    assert!(
+        store.remove(digest).await?,
+        "Expected the entry to be removed"
+    );
+
+    assert_eq!(backend.has(digest).await, Ok(None));
+    for chunk in manifest.chunks {
+        assert_eq!(
+            backend.has(StoreKey::new_str(&chunk.key)).await,
+            Ok(None),
+            "Expected chunk {} to be removed",
+            chunk.key
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_removes_chunks_of_previous_upload_test() -> Result<(), Error> {
+    let memory_store = MemoryStore::new(&MemorySpec::default());
+    let backend = Store::new(memory_store.clone());
+    let store = ChunkedStore::new(&make_default_config(), backend.clone())?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE).unwrap();
+    store
+        .update_oneshot(digest, make_random_data(DATA_SIZE).into())
+        .await
+        .err_tip(|| "Failed to write data to chunked store")?;
+    let previous_manifest = get_manifest(&backend, digest).await?;
+
+    let original_data = make_random_data(DATA_SIZE);
+    store
+        .update_oneshot(digest, original_data.clone().into())
+        .await
+        .err_tip(|| "Failed to overwrite data in chunked store")?;
+
+    for chunk in previous_manifest.chunks {
+        assert_eq!(
+            backend.has(StoreKey::new_str(&chunk.key)).await,
+            Ok(None),
+            "Expected chunk {} of the previous upload to be removed",
+            chunk.key
+        );
+    }
+    let manifest = get_manifest(&backend, digest).await?;
+    assert_eq!(
+        memory_store.len_for_test().await,
+        manifest.chunks.len() + 1,
+        "Expected only the new manifest and its chunks in the backend"
+    );
+
+    let rt_data = store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to get_part from chunked store")?;
+    assert_eq!(rt_data, original_data, "Expected round trip data to match");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn failed_update_removes_uploaded_chunks_test() -> Result<(), Error> {
+    let memory_store = MemoryStore::new(&MemorySpec::default());
+    let store = ChunkedStore::new(&make_default_config(), Store::new(memory_store.clone()))?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, DATA_SIZE).unwrap();
+    let (mut tx, rx) = make_buf_channel_pair();
+    // Sends more than two chunks of data and then drops the stream without
+    // an EOF.
+    let send_fut = async move { tx.send(make_random_data(2 * CHUNK_SIZE + 100).into()).await };
+    let (send_result, update_result) = join!(
+        send_fut,
+        store.update(digest, rx, UploadSizeInfo::ExactSize(DATA_SIZE as u64))
+    );
+    send_result?;
+    assert!(
+        update_result.is_err(),
+        "Expected update to fail, got {update_result:?}"
+    );
+    assert_eq!(
+        memory_store.len_for_test().await,
+        0,
+        "Expected the uploaded chunks to be removed"
+    );
+    Ok(())
+}
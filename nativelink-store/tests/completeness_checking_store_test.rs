@@ -12,11 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+use async_trait::async_trait;
 use nativelink_config::stores::MemorySpec;
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
 use nativelink_proto::build::bazel::remote::execution::v2::{
     ActionResult as ProtoActionResult, Directory, DirectoryNode, FileNode, OutputDirectory,
     OutputFile, Tree,
@@ -24,9 +28,11 @@ use nativelink_proto::build::bazel::remote::execution::v2::{
 use nativelink_store::ac_utils::serialize_and_upload_message;
 use nativelink_store::completeness_checking_store::CompletenessCheckingStore;
 use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
 use nativelink_util::common::DigestInfo;
 use nativelink_util::digest_hasher::DigestHasherFunc;
-use nativelink_util::store_trait::{Store, StoreLike};
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
 
 const ROOT_FILE: DigestInfo = DigestInfo::new([0u8; 32], 0);
 const ROOT_DIRECTORY: DigestInfo = DigestInfo::new([1u8; 32], 0);
@@ -245,3 +251,120 @@ async fn verify_completeness_get() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// CAS store that counts how many digests are checked for existence.
+#[derive(MetricsComponent)]
+struct CountingStore {
+    inner: Store,
+    checked_digests: AtomicUsize,
+}
+
+#[async_trait]
+impl StoreDriver for CountingStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        digests: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.checked_digests
+            .fetch_add(digests.len(), Ordering::Relaxed);
+        self.inner.has_with_results(digests, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(CountingStore);
+
+const OUTPUT_FILE_COUNT: usize = 1000;
+
+/// Stores an action result with `OUTPUT_FILE_COUNT` output files, which
+/// all exist in the CAS, and returns how many of them were checked.
+async fn count_checked_outputs(sample_percent: u8) -> Result<usize, Error> {
+    let cas_store = Arc::new(CountingStore {
+        inner: Store::new(MemoryStore::new(&MemorySpec::default())),
+        checked_digests: AtomicUsize::new(0),
+    });
+    let backend_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let ac_store = CompletenessCheckingStore::new_with_sample_percent(
+        backend_store.clone(),
+        Store::new(cas_store.clone()),
+        sample_percent,
+    );
+
+    let mut output_files = Vec::with_capacity(OUTPUT_FILE_COUNT);
+    for i in 0..OUTPUT_FILE_COUNT {
+        let mut hash = [0u8; 32];
+        hash[..8].copy_from_slice(&i.to_le_bytes());
+        let digest = DigestInfo::new(hash, 0);
+        cas_store.inner.update_oneshot(digest, "".into()).await?;
+        output_files.push(OutputFile {
+            digest: Some(digest.into()),
+            ..Default::default()
+        });
+    }
+    let action_result_digest = serialize_and_upload_message(
+        &ProtoActionResult {
+            output_files,
+            ..Default::default()
+        },
+        backend_store.as_pin(),
+        &mut DigestHasherFunc::Blake3.hasher(),
+    )
+    .await?;
+
+    let res = ac_store.has_many(&[action_result_digest.into()]).await?;
+    assert!(
+        res[0].is_some(),
+        "Results should be some with all items in CAS."
+    );
+    Ok(cas_store.checked_digests.load(Ordering::Relaxed))
+}
+
+#[nativelink_test]
+async fn verify_all_outputs_by_default() -> Result<(), Error> {
+    assert_eq!(count_checked_outputs(0).await?, OUTPUT_FILE_COUNT);
+    assert_eq!(count_checked_outputs(100).await?, OUTPUT_FILE_COUNT);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn verify_sample_of_outputs() -> Result<(), Error> {
+    // 10% of 1000 outputs is 100 on average, with a standard deviation
+    // of about 10.
+    let checked_outputs = count_checked_outputs(10).await?;
+    assert!(
+        (50..=150).contains(&checked_outputs),
+        "Expected about 100 outputs to be checked, got {checked_outputs}"
+    );
+    Ok(())
+}
@@ -134,7 +134,7 @@ async fn partial_reads_test() -> Result<(), Error> {
     // and different window sizes. This will ensure we get most edge cases for when
     // we go across block boundaries inclusive, on the fence and exclusive.
     for read_slice_size in 0..(RAW_DATA.len() + 5) {
-        for offset in 0..(RAW_DATA.len() + 5) {
+        for offset in 0..=RAW_DATA.len() {
             let store_data = store
                 .get_part_unchunked(digest, offset as u64, Some(read_slice_size as u64))
                 .await
@@ -142,7 +142,7 @@ async fn partial_reads_test() -> Result<(), Error> {
                     format!("Failed to get from inner store at {offset} - {read_slice_size}")
                 })?;
 
-            let start_pos = cmp::min(RAW_DATA.len(), offset);
+            let start_pos = offset;
             let end_pos = cmp::min(RAW_DATA.len(), offset + read_slice_size);
             assert_eq!(
                 &store_data,
@@ -154,6 +154,15 @@ async fn partial_reads_test() -> Result<(), Error> {
         }
     }
 
+    // Offsets past the end of the data are rejected.
+    for offset in [RAW_DATA.len() + 1, RAW_DATA.len() + 5] {
+        let err = store
+            .get_part_unchunked(digest, offset as u64, None)
+            .await
+            .expect_err("Expected offset past the end to fail");
+        assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    }
+
     Ok(())
 }
 
@@ -514,3 +523,140 @@ async fn get_part_is_zero_digest() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn get_part_detects_truncated_data_test() -> Result<(), Error> {
+    const BLOCK_SIZE: u32 = 1024;
+    // Header is: version(u8), block_size(u32), upload_size(u32 + u64).
+    const HEADER_SIZE: usize = 1 + 4 + 4 + 8;
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: nativelink_config::stores::CompressionAlgorithm::lz4(
+                nativelink_config::stores::Lz4Config {
+                    block_size: BLOCK_SIZE,
+                    ..Default::default()
+                },
+            ),
+        },
+        Store::new(inner_store.clone()),
+    )
+    .err_tip(|| "Failed to create compression store")?;
+
+    let mut value = vec![0u8; BLOCK_SIZE as usize * 4];
+    let mut rng = SmallRng::seed_from_u64(1);
+    rng.fill(&mut value[..]);
+    let digest = DigestInfo::try_new(VALID_HASH, DUMMY_DATA_SIZE).unwrap();
+    store.update_oneshot(digest, value.into()).await?;
+    let compressed_data = inner_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to get from inner store")?;
+
+    // Cut the data in the header, in a frame's info, in a chunk and in the
+    // footer.
+    for truncated_size in [
+        HEADER_SIZE - 1,
+        HEADER_SIZE + 2,
+        HEADER_SIZE + 1 + 4 + 100,
+        compressed_data.len() - 1,
+    ] {
+        inner_store
+            .update_oneshot(digest, compressed_data.slice(..truncated_size))
+            .await?;
+        let err = store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .expect_err("Expected truncated data to fail");
+        assert_eq!(
+            err.code,
+            Code::DataLoss,
+            "Unexpected error for data truncated to {truncated_size} bytes: {err:?}"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_rejects_oversized_frame_test() -> Result<(), Error> {
+    const BLOCK_SIZE: u32 = 1024;
+    // Header is: version(u8), block_size(u32), upload_size(u32 + u64).
+    const HEADER_SIZE: usize = 1 + 4 + 4 + 8;
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = CompressionStore::new(
+        &CompressionSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            compression_algorithm: nativelink_config::stores::CompressionAlgorithm::lz4(
+                nativelink_config::stores::Lz4Config {
+                    block_size: BLOCK_SIZE,
+                    ..Default::default()
+                },
+            ),
+        },
+        Store::new(inner_store.clone()),
+    )
+    .err_tip(|| "Failed to create compression store")?;
+
+    let digest = DigestInfo::try_new(VALID_HASH, DUMMY_DATA_SIZE).unwrap();
+    store
+        .update_oneshot(digest, vec![1u8; BLOCK_SIZE as usize * 2].into())
+        .await?;
+    let mut compressed_data = inner_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to get from inner store")?
+        .to_vec();
+
+    // Claim the first chunk is far larger than any compressed block could be.
+    compressed_data[HEADER_SIZE + 1..HEADER_SIZE + 1 + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    inner_store
+        .update_oneshot(digest, compressed_data.into())
+        .await?;
+    let err = store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .expect_err("Expected oversized frame to fail");
+    assert_eq!(err.code, Code::DataLoss, "Unexpected error: {err:?}");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_rejects_block_size_over_max_decode_block_size_test() -> Result<(), Error> {
+    const BLOCK_SIZE: u32 = 64 * 1024;
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let make_store = |block_size, max_decode_block_size| {
+        CompressionStore::new(
+            &CompressionSpec {
+                backend: StoreSpec::memory(MemorySpec::default()),
+                compression_algorithm: nativelink_config::stores::CompressionAlgorithm::lz4(
+                    nativelink_config::stores::Lz4Config {
+                        block_size,
+                        max_decode_block_size,
+                    },
+                ),
+            },
+            Store::new(inner_store.clone()),
+        )
+    };
+    let writer_store = make_store(BLOCK_SIZE, 0)?;
+    let reader_store = make_store(BLOCK_SIZE / 2, BLOCK_SIZE / 2)?;
+
+    let digest = DigestInfo::try_new(VALID_HASH, DUMMY_DATA_SIZE).unwrap();
+    writer_store
+        .update_oneshot(digest, vec![1u8; 100].into())
+        .await?;
+    let err = reader_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .expect_err("Expected block size over max_decode_block_size to fail");
+    assert_eq!(err.code, Code::InvalidArgument, "Unexpected error: {err:?}");
+    assert!(
+        err.to_string().contains("Block size is too large"),
+        "Unexpected error: {err:?}"
+    );
+    Ok(())
+}
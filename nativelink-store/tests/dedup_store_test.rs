@@ -12,11 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use nativelink_config::stores::{DedupSpec, MemorySpec, StoreSpec};
-use nativelink_error::{Code, Error, ResultExt};
+use bincode::{DefaultOptions, Options};
+use nativelink_config::stores::{DedupChunkingAlgorithm, DedupSpec, MemorySpec, StoreSpec};
+use nativelink_error::{make_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_store::cas_utils::ZERO_BYTE_DIGESTS;
-use nativelink_store::dedup_store::DedupStore;
+use nativelink_store::dedup_store::{DedupIndex, DedupStore};
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::store_trait::{Store, StoreLike};
@@ -32,6 +33,7 @@ fn make_default_config() -> DedupSpec {
         normal_size: 32 * 1024,
         max_size: 128 * 1024,
         max_concurrent_fetch_per_get: 10,
+        chunking_algorithm: DedupChunkingAlgorithm::default(),
     }
 }
 
@@ -167,6 +169,7 @@ async fn check_length_not_set_with_chunk_read_beyond_first_chunk_regression_test
             normal_size: 6,
             max_size: 7,
             max_concurrent_fetch_per_get: 10,
+            chunking_algorithm: DedupChunkingAlgorithm::default(),
         },
         Store::new(MemoryStore::new(&MemorySpec::default())), // Index store.
         Store::new(MemoryStore::new(&MemorySpec::default())), // Content store.
@@ -212,6 +215,7 @@ async fn check_chunk_boundary_reads_test() -> Result<(), Error> {
             normal_size: 6,
             max_size: 7,
             max_concurrent_fetch_per_get: 10,
+            chunking_algorithm: DedupChunkingAlgorithm::default(),
         },
         Store::new(MemoryStore::new(&MemorySpec::default())), // Index store.
         Store::new(MemoryStore::new(&MemorySpec::default())), // Content store.
@@ -284,6 +288,7 @@ async fn has_checks_content_store() -> Result<(), Error> {
             max_count: 10,
             ..Default::default()
         }),
+        ..Default::default()
     });
 
     let store = DedupStore::new(
@@ -350,6 +355,7 @@ async fn has_with_no_existing_index_returns_none_test() -> Result<(), Error> {
             max_count: 10,
             ..Default::default()
         }),
+        ..Default::default()
     });
 
     let store = DedupStore::new(
@@ -381,6 +387,7 @@ async fn has_with_zero_digest_returns_some_test() -> Result<(), Error> {
             max_count: 10,
             ..Default::default()
         }),
+        ..Default::default()
     });
 
     let store = DedupStore::new(
@@ -402,3 +409,129 @@ async fn has_with_zero_digest_returns_some_test() -> Result<(), Error> {
     }
     Ok(())
 }
+
+/// Ensure a range read from the middle of a file only fetches the chunks that overlap the
+/// requested range. Every other chunk is removed from the content store, so fetching any of
+/// them would fail the read.
+#[nativelink_test]
+async fn mid_file_range_read_only_fetches_covering_chunks_test() -> Result<(), Error> {
+    let index_store = MemoryStore::new(&MemorySpec::default());
+    let content_store = MemoryStore::new(&MemorySpec::default());
+    let store = DedupStore::new(
+        &make_default_config(),
+        Store::new(index_store.clone()),
+        Store::new(content_store.clone()),
+    )?;
+
+    let original_data = make_random_data(MEGABYTE_SZ);
+    let digest = DigestInfo::try_new(VALID_HASH1, MEGABYTE_SZ).unwrap();
+    store
+        .update_oneshot(digest, original_data.clone().into())
+        .await
+        .err_tip(|| "Failed to write data to dedup store")?;
+
+    let index_data = index_store
+        .get_part_unchunked(digest, 0, None)
+        .await
+        .err_tip(|| "Failed to read index from index store")?;
+    let index: DedupIndex = DefaultOptions::new()
+        .with_fixint_encoding()
+        .deserialize(&index_data)
+        .map_err(|e| make_err!(Code::Internal, "Failed to deserialize index: {e:?}"))?;
+    assert!(
+        index.entries.len() > 4,
+        "Expected data to be split into several chunks"
+    );
+
+    // Read from just inside the second chunk to just inside the third chunk.
+    let second_chunk_start = index.entries[0].size_bytes();
+    let third_chunk_start = second_chunk_start + index.entries[1].size_bytes();
+    let read_offset = second_chunk_start + 1;
+    let read_length = third_chunk_start + 1 - read_offset;
+
+    for (i, entry) in index.entries.iter().enumerate() {
+        if i == 1 || i == 2 {
+            continue;
+        }
+        assert!(
+            content_store.remove_entry(entry.into()).await,
+            "Expected chunk {i} to exist in content store"
+        );
+    }
+
+    let rt_data = store
+        .get_part_unchunked(digest, read_offset, Some(read_length))
+        .await
+        .err_tip(|| "Failed to get_part from dedup store")?;
+    assert_eq!(
+        rt_data,
+        original_data[read_offset as usize..(read_offset + read_length) as usize],
+        "Expected round trip data to match"
+    );
+
+    // Starting two bytes earlier reaches into the removed first chunk and must fail.
+    let result = store
+        .get_part_unchunked(digest, read_offset - 2, Some(read_length))
+        .await;
+    assert!(result.is_err(), "Expected read of a removed chunk to fail");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn chunking_algorithms_round_trip_within_bounds_test() -> Result<(), Error> {
+    for chunking_algorithm in [
+        DedupChunkingAlgorithm::fastcdc,
+        DedupChunkingAlgorithm::gear,
+    ] {
+        let spec = DedupSpec {
+            chunking_algorithm,
+            ..make_default_config()
+        };
+        let index_store = MemoryStore::new(&MemorySpec::default());
+        let store = DedupStore::new(
+            &spec,
+            Store::new(index_store.clone()),
+            Store::new(MemoryStore::new(&MemorySpec::default())),
+        )?;
+
+        let original_data = make_random_data(MEGABYTE_SZ);
+        let digest = DigestInfo::try_new(VALID_HASH1, MEGABYTE_SZ).unwrap();
+        store
+            .update_oneshot(digest, original_data.clone().into())
+            .await
+            .err_tip(|| "Failed to write data to dedup store")?;
+
+        let rt_data = store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .err_tip(|| "Failed to get_part from dedup store")?;
+        assert_eq!(
+            rt_data, original_data,
+            "Expected round trip data to match for {chunking_algorithm:?}"
+        );
+
+        let index_data = index_store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .err_tip(|| "Failed to read index from index store")?;
+        let index: DedupIndex = DefaultOptions::new()
+            .with_fixint_encoding()
+            .deserialize(&index_data)
+            .map_err(|e| make_err!(Code::Internal, "Failed to deserialize index: {e:?}"))?;
+        assert!(
+            index.entries.len() > 1,
+            "Expected data to be split into several chunks for {chunking_algorithm:?}"
+        );
+        // The last chunk holds whatever is left, so it may be below `min_size`.
+        let (last_entry, entries) = index.entries.split_last().unwrap();
+        for entry in entries {
+            assert!(
+                (u64::from(spec.min_size)..=u64::from(spec.max_size)).contains(&entry.size_bytes()),
+                "Chunk of {} bytes out of bounds for {chunking_algorithm:?}",
+                entry.size_bytes()
+            );
+        }
+        assert!(last_entry.size_bytes() <= u64::from(spec.max_size));
+    }
+    Ok(())
+}
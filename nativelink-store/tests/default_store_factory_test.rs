@@ -0,0 +1,238 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use nativelink_config::stores::{StoreRefName, StoreSpec};
+use nativelink_error::{Code, Error, ResultExt};
+use nativelink_macro::nativelink_test;
+use nativelink_store::default_store_factory::{
+    check_ref_store_cycles, store_factory, validate_store_specs,
+};
+use nativelink_store::store_manager::StoreManager;
+use nativelink_util::buf_channel::make_buf_channel_pair;
+use nativelink_util::store_trait::{StoreLike, ZERO_BYTE_DIGESTS};
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+use serde_json::json;
+
+fn parse_stores(json: &str) -> HashMap<StoreRefName, StoreSpec> {
+    serde_json::from_str(json).expect("Failed to parse stores")
+}
+
+#[nativelink_test]
+async fn valid_stores_have_no_errors() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "cas": { "fast_slow": {
+                "fast": { "memory": {} },
+                "slow": { "ref_store": { "name": "slow" } }
+            } },
+            "slow": { "memory": {} }
+        }"#,
+    );
+    assert_eq!(validate_store_specs(&stores), Vec::<Error>::new());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn dangling_ref_store_is_reported() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "main": { "fast_slow": {
+                "fast": { "memory": {} },
+                "slow": { "ref_store": { "name": "missing" } }
+            } }
+        }"#,
+    );
+    let errors = validate_store_specs(&stores);
+    assert_eq!(errors.len(), 1, "Expected one error, got {errors:?}");
+    assert_eq!(errors[0].code, Code::InvalidArgument);
+    assert!(
+        errors[0].message_string().contains(
+            "Store 'main' references store 'missing' in a 'ref_store', but no store with that name is configured"
+        ),
+        "Unexpected error: {:?}",
+        errors[0]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn store_cycle_is_reported() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "a": { "verify": {
+                "backend": { "ref_store": { "name": "b" } }
+            } },
+            "b": { "ref_store": { "name": "a" } }
+        }"#,
+    );
+    let errors = validate_store_specs(&stores);
+    assert_eq!(errors.len(), 1, "Expected one error, got {errors:?}");
+    assert_eq!(errors[0].code, Code::InvalidArgument);
+    assert!(
+        errors[0]
+            .message_string()
+            .contains("Stores reference each other in a cycle: a -> b -> a"),
+        "Unexpected error: {:?}",
+        errors[0]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn all_problems_are_reported() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "a": { "ref_store": { "name": "a" } },
+            "b": { "ref_store": { "name": "missing" } }
+        }"#,
+    );
+    assert_eq!(validate_store_specs(&stores).len(), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn ref_store_cycle_is_rejected() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "a": { "ref_store": { "name": "b" } },
+            "b": { "ref_store": { "name": "a" } },
+            "c": { "memory": {} }
+        }"#,
+    );
+    let err = check_ref_store_cycles(&stores).expect_err("Expected cycle to be rejected");
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert_eq!(
+        err.message_string(),
+        "Stores reference each other in a cycle: a -> b -> a"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn ref_store_chain_is_accepted() -> Result<(), Error> {
+    let stores = parse_stores(
+        r#"{
+            "a": { "ref_store": { "name": "b" } },
+            "b": { "ref_store": { "name": "c" } },
+            "c": { "memory": {} }
+        }"#,
+    );
+    check_ref_store_cycles(&stores)
+}
+
+#[nativelink_test]
+async fn zero_digest_exists_in_every_store_test() -> Result<(), Error> {
+    let temp_dir = format!(
+        "{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    );
+    let stores: HashMap<StoreRefName, StoreSpec> = serde_json::from_value(json!({
+        "memory": { "memory": {} },
+        "noop": { "noop": {} },
+        "filesystem": { "filesystem": {
+            "content_path": format!("{temp_dir}/content_path"),
+            "temp_path": format!("{temp_dir}/temp_path"),
+        } },
+        "ref_store": { "ref_store": { "name": "memory" } },
+        "verify": { "verify": { "backend": { "memory": {} }, "verify_size": true } },
+        "compression": { "compression": {
+            "compression_algorithm": { "lz4": {} },
+            "backend": { "memory": {} },
+        } },
+        "dedup": { "dedup": {
+            "index_store": { "memory": {} },
+            "content_store": { "memory": {} },
+        } },
+        "existence_cache": { "existence_cache": { "backend": { "memory": {} } } },
+        "singleflight": { "singleflight": { "backend": { "memory": {} } } },
+        "read_cache": { "read_cache": { "backend": { "memory": {} } } },
+        "timeout": { "timeout": { "backend": { "memory": {} } } },
+        "rate_limit": { "rate_limit": {
+            "backend": { "memory": {} },
+            "requests_per_second": 1000,
+        } },
+        "fast_slow": { "fast_slow": {
+            "fast": { "memory": {} },
+            "slow": { "noop": {} },
+        } },
+        "shard": { "shard": { "stores": [
+            { "store": { "memory": {} } },
+            { "store": { "memory": {} } },
+        ] } },
+        "size_partitioning": { "size_partitioning": {
+            "size": 1024,
+            "lower_store": { "memory": {} },
+            "upper_store": { "memory": {} },
+        } },
+    }))
+    .expect("Failed to parse stores");
+
+    let store_manager = Arc::new(StoreManager::new());
+    for (name, spec) in &stores {
+        let store = store_factory(spec, &store_manager, None)
+            .await
+            .err_tip(|| format!("Failed to create store '{name}'"))?;
+        store_manager.add_store(name, store);
+    }
+
+    // Nothing was ever uploaded, but every store must still have the empty blob.
+    for name in stores.keys() {
+        let store = store_manager.get_store(name).unwrap();
+        for digest in ZERO_BYTE_DIGESTS {
+            assert_eq!(
+                store.has(digest).await.err_tip(|| format!("In '{name}'"))?,
+                Some(0),
+                "has() in '{name}'"
+            );
+            assert_eq!(
+                store
+                    .has_many(&[digest.into()])
+                    .await
+                    .err_tip(|| format!("In '{name}'"))?,
+                vec![Some(0)],
+                "has_many() in '{name}'"
+            );
+            assert_eq!(
+                store
+                    .get_part_unchunked(digest, 0, None)
+                    .await
+                    .err_tip(|| format!("In '{name}'"))?,
+                Bytes::new(),
+                "get_part_unchunked() in '{name}'"
+            );
+
+            let (mut writer, mut reader) = make_buf_channel_pair();
+            let (get_part_result, data) = tokio::join!(
+                store.get_part(digest, &mut writer, 0, None),
+                reader.consume(None),
+            );
+            get_part_result.err_tip(|| format!("In '{name}'"))?;
+            assert_eq!(data?, Bytes::new(), "get_part() in '{name}'");
+
+            let err = store
+                .get_part_unchunked(digest, 1, None)
+                .await
+                .expect_err("Expected offset past the end to be rejected");
+            assert_eq!(err.code, Code::InvalidArgument, "In '{name}'");
+        }
+    }
+    Ok(())
+}
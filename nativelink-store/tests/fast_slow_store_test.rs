@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
@@ -28,6 +28,7 @@ use nativelink_store::noop_store::NoopStore;
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::spawn;
 use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike};
 use pretty_assertions::assert_eq;
 use rand::rngs::SmallRng;
@@ -42,6 +43,8 @@ fn make_stores() -> (Store, Store, Store) {
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         fast_store.clone(),
         slow_store.clone(),
@@ -332,6 +335,8 @@ async fn drop_on_eof_completes_store_futures() -> Result<(), Error> {
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         fast_store,
         slow_store,
@@ -373,6 +378,8 @@ async fn ignore_value_in_fast_store() -> Result<(), Error> {
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         fast_store.clone(),
         slow_store,
@@ -396,6 +403,8 @@ async fn has_checks_fast_store_when_noop() -> Result<(), Error> {
     let fast_slow_store_config = FastSlowSpec {
         fast: StoreSpec::memory(MemorySpec::default()),
         slow: StoreSpec::noop(NoopSpec::default()),
+        max_concurrent_populations: 0,
+        fast_store_max_write_size: 0,
     };
     let fast_slow_store = Arc::new(FastSlowStore::new(
         &fast_slow_store_config,
@@ -430,3 +439,216 @@ async fn has_checks_fast_store_when_noop() -> Result<(), Error> {
     );
     Ok(())
 }
+
+#[nativelink_test]
+async fn populations_are_limited_by_max_concurrent_populations() -> Result<(), Error> {
+    const HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+    const HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+
+    /// Fast store that blocks updates until `gate` has permits and tracks
+    /// how many updates run at the same time.
+    #[derive(MetricsComponent)]
+    struct GatedStore {
+        inner: Store,
+        gate: tokio::sync::Semaphore,
+        active_updates: AtomicUsize,
+        max_active_updates: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StoreDriver for GatedStore {
+        async fn has_with_results(
+            self: Pin<&Self>,
+            digests: &[StoreKey<'_>],
+            results: &mut [Option<u64>],
+        ) -> Result<(), Error> {
+            self.inner.has_with_results(digests, results).await
+        }
+
+        async fn update(
+            self: Pin<&Self>,
+            key: StoreKey<'_>,
+            reader: nativelink_util::buf_channel::DropCloserReadHalf,
+            size_info: nativelink_util::store_trait::UploadSizeInfo,
+        ) -> Result<(), Error> {
+            let active_updates = self.active_updates.fetch_add(1, Ordering::AcqRel) + 1;
+            self.max_active_updates
+                .fetch_max(active_updates, Ordering::AcqRel);
+            let _permit = self
+                .gate
+                .acquire()
+                .await
+                .map_err(|e| make_err!(Code::Internal, "{:?}", e))?;
+            let result = self.inner.update(key, reader, size_info).await;
+            self.active_updates.fetch_sub(1, Ordering::AcqRel);
+            result
+        }
+
+        async fn get_part(
+            self: Pin<&Self>,
+            key: StoreKey<'_>,
+            writer: &mut nativelink_util::buf_channel::DropCloserWriteHalf,
+            offset: u64,
+            length: Option<u64>,
+        ) -> Result<(), Error> {
+            self.inner.get_part(key, writer, offset, length).await
+        }
+
+        fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+            self
+        }
+
+        fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+            self
+        }
+
+        fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+            self
+        }
+    }
+
+    default_health_status_indicator!(GatedStore);
+
+    let gated_store = Arc::new(GatedStore {
+        inner: Store::new(MemoryStore::new(&MemorySpec::default())),
+        gate: tokio::sync::Semaphore::new(0),
+        active_updates: AtomicUsize::new(0),
+        max_active_updates: AtomicUsize::new(0),
+    });
+    let fast_store = Store::new(gated_store.clone());
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = Store::new(FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 1,
+            fast_store_max_write_size: 0,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    ));
+
+    let original_data1 = make_random_data(MEGABYTE_SZ);
+    let digest1 = DigestInfo::try_new(HASH1, original_data1.len())?;
+    slow_store
+        .update_oneshot(digest1, original_data1.clone().into())
+        .await?;
+    let original_data2 = make_random_data(100);
+    let digest2 = DigestInfo::try_new(HASH2, original_data2.len())?;
+    slow_store
+        .update_oneshot(digest2, original_data2.clone().into())
+        .await?;
+
+    // The first read takes the only permit and blocks while populating.
+    let first_read = spawn!("first_read", {
+        let fast_slow_store = fast_slow_store.clone();
+        async move { fast_slow_store.get_part_unchunked(digest1, 0, None).await }
+    });
+    while gated_store.active_updates.load(Ordering::Acquire) == 0 {
+        tokio::task::yield_now().await;
+    }
+
+    // While the limit is reached, reads are still served from the slow store
+    // but do not populate the fast store.
+    assert_eq!(
+        fast_slow_store.get_part_unchunked(digest2, 0, None).await?,
+        original_data2
+    );
+    assert_eq!(
+        fast_slow_store
+            .get_part_unchunked(digest2, 10, Some(50))
+            .await?,
+        original_data2[10..60]
+    );
+    assert_eq!(fast_store.has(digest2).await, Ok(None));
+
+    // Once the first population finishes, its data must be correct and the
+    // permit must be available again.
+    gated_store.gate.add_permits(1);
+    assert_eq!(
+        first_read
+            .await
+            .map_err(|e| make_err!(Code::Internal, "{:?}", e))??,
+        original_data1
+    );
+    check_data(&fast_store, digest1, &original_data1, "fast_store").await?;
+
+    fast_slow_store.get_part_unchunked(digest2, 0, None).await?;
+    check_data(&fast_store, digest2, &original_data2, "fast_store").await?;
+
+    assert_eq!(gated_store.max_active_updates.load(Ordering::Acquire), 1);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn remove_deletes_from_fast_and_slow_store() -> Result<(), Error> {
+    let (fast_slow_store, fast_store, slow_store) = make_stores();
+    let original_data = make_random_data(100);
+    let digest = DigestInfo::try_new(VALID_HASH, original_data.len())?;
+    fast_slow_store
+        .update_oneshot(digest, original_data.into())
+        .await?;
+
+    assert_eq!(fast_slow_store.remove(digest).await, Ok(true));
+    assert_eq!(fast_slow_store.has(digest).await, Ok(None));
+    assert_eq!(fast_store.has(digest).await, Ok(None));
+    assert_eq!(slow_store.has(digest).await, Ok(None));
+    assert_eq!(fast_slow_store.remove(digest).await, Ok(false));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn touch_reaches_slow_store() -> Result<(), Error> {
+    let (fast_slow_store, fast_store, slow_store) = make_stores();
+    let original_data = make_random_data(100);
+    let digest = DigestInfo::try_new(VALID_HASH, original_data.len())?;
+    // Only the slow store has the entry, so it is the one touched.
+    slow_store
+        .update_oneshot(digest, original_data.into())
+        .await?;
+
+    assert_eq!(fast_slow_store.touch(&[digest.into()]).await, Ok(1));
+    assert_eq!(fast_store.has(digest).await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn large_writes_skip_fast_store_test() -> Result<(), Error> {
+    const MAX_WRITE_SIZE: usize = 100;
+
+    let fast_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let slow_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fast_slow_store = Store::new(FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::memory(MemorySpec::default()),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: MAX_WRITE_SIZE as u64,
+        },
+        fast_store.clone(),
+        slow_store.clone(),
+    ));
+
+    // Blobs up to the limit are written to both stores.
+    let small_data = make_random_data(MAX_WRITE_SIZE);
+    let small_digest = DigestInfo::try_new(VALID_HASH, small_data.len())?;
+    fast_slow_store
+        .update_oneshot(small_digest, small_data.clone().into())
+        .await?;
+    check_data(&fast_store, small_digest, &small_data, "fast").await?;
+    check_data(&slow_store, small_digest, &small_data, "slow").await?;
+
+    // Larger blobs are only written to the slow store.
+    let large_data = make_random_data(MAX_WRITE_SIZE + 1);
+    let large_digest = DigestInfo::try_new(VALID_HASH, large_data.len())?;
+    fast_slow_store
+        .update_oneshot(large_digest, large_data.clone().into())
+        .await?;
+    assert_eq!(fast_store.has(large_digest).await, Ok(None));
+    check_data(&slow_store, large_digest, &large_data, "slow").await?;
+
+    // But reading them still populates the fast store.
+    check_data(&fast_slow_store, large_digest, &large_data, "fast_slow").await?;
+    check_data(&fast_store, large_digest, &large_data, "fast").await?;
+    Ok(())
+}
@@ -0,0 +1,163 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Run with `cargo test -p nativelink-store --features fault_injection`.
+#![cfg(feature = "fault_injection")]
+
+use std::time::{Duration, Instant};
+
+use nativelink_config::stores::{FaultInjectionSpec, MemorySpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::fault_injection_store::FaultInjectionStore;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+
+fn make_spec() -> FaultInjectionSpec {
+    FaultInjectionSpec {
+        backend: StoreSpec::memory(MemorySpec::default()),
+        error_rate: 0.0,
+        truncated_read_rate: 0.0,
+        min_latency_ms: 0,
+        max_latency_ms: 0,
+        seed: Some(0),
+    }
+}
+
+fn make_stores(spec: &FaultInjectionSpec) -> Result<(Store, Store), Error> {
+    let inner_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let fault_injection_store = Store::new(FaultInjectionStore::new(spec, inner_store.clone())?);
+    Ok((inner_store, fault_injection_store))
+}
+
+#[nativelink_test]
+async fn all_operations_fail_with_full_error_rate() -> Result<(), Error> {
+    let (inner_store, store) = make_stores(&FaultInjectionSpec {
+        error_rate: 1.0,
+        ..make_spec()
+    })?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    inner_store.update_oneshot(digest, VALUE1.into()).await?;
+
+    assert_eq!(store.has(digest).await.unwrap_err().code, Code::Unavailable);
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .unwrap_err()
+            .code,
+        Code::Unavailable
+    );
+    assert_eq!(
+        store.remove(digest).await.unwrap_err().code,
+        Code::Unavailable
+    );
+    assert!(store.update_oneshot(digest, "new".into()).await.is_err());
+
+    // None of the operations reached the inner store.
+    assert_eq!(
+        inner_store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn operations_pass_through_without_faults() -> Result<(), Error> {
+    let (inner_store, store) = make_stores(&make_spec())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(inner_store.has(digest).await?, Some(VALUE1.len() as u64));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn latency_is_added_to_operations() -> Result<(), Error> {
+    const LATENCY_MS: u64 = 50;
+    let (_inner_store, store) = make_stores(&FaultInjectionSpec {
+        min_latency_ms: LATENCY_MS,
+        max_latency_ms: LATENCY_MS,
+        ..make_spec()
+    })?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    let start = Instant::now();
+    assert_eq!(store.has(digest).await?, None);
+    assert!(
+        start.elapsed() >= Duration::from_millis(LATENCY_MS),
+        "Expected at least {LATENCY_MS}ms of latency, took {:?}",
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn truncated_read_returns_partial_data_then_fails() -> Result<(), Error> {
+    let (inner_store, store) = make_stores(&FaultInjectionSpec {
+        truncated_read_rate: 1.0,
+        ..make_spec()
+    })?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    inner_store.update_oneshot(digest, VALUE1.into()).await?;
+
+    let err = store.get_part_unchunked(digest, 0, None).await.unwrap_err();
+    assert_eq!(err.code, Code::Unavailable, "{err:?}");
+    // Existence checks are not affected.
+    assert_eq!(store.has(digest).await?, Some(VALUE1.len() as u64));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn same_seed_injects_same_errors() -> Result<(), Error> {
+    let spec = FaultInjectionSpec {
+        error_rate: 0.5,
+        seed: Some(42),
+        ..make_spec()
+    };
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    let mut outcomes = Vec::new();
+    for _ in 0..2 {
+        let (_inner_store, store) = make_stores(&spec)?;
+        let mut store_outcomes = Vec::new();
+        for _ in 0..32 {
+            store_outcomes.push(store.has(digest).await.is_ok());
+        }
+        outcomes.push(store_outcomes);
+    }
+    assert_eq!(outcomes[0], outcomes[1]);
+    assert!(outcomes[0].contains(&true) && outcomes[0].contains(&false));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn invalid_error_rate_is_rejected() -> Result<(), Error> {
+    let Err(err) = make_stores(&FaultInjectionSpec {
+        error_rate: 1.5,
+        ..make_spec()
+    }) else {
+        panic!("Expected an error for an error_rate above 1.0");
+    };
+    assert_eq!(err.code, Code::InvalidArgument);
+    Ok(())
+}
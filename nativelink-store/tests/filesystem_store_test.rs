@@ -38,6 +38,7 @@ use nativelink_store::filesystem_store::{
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::{fs, DigestInfo};
 use nativelink_util::evicting_map::LenEntry;
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::origin_context::ContextAwareFuture;
 use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
 use nativelink_util::{background_spawn, spawn};
@@ -56,6 +57,7 @@ use tracing::Instrument;
 trait FileEntryHooks {
     fn on_unref<Fe: FileEntry>(_entry: &Fe) {}
     fn on_drop<Fe: FileEntry>(_entry: &Fe) {}
+    fn on_touch<Fe: FileEntry>(_entry: &Fe) {}
 }
 
 struct TestFileEntry<Hooks: FileEntryHooks + 'static + Sync + Send> {
@@ -99,6 +101,10 @@ impl<Hooks: FileEntryHooks + 'static + Sync + Send> FileEntry for TestFileEntry<
         ))
     }
 
+    fn data_size(&self) -> u64 {
+        self.inner.as_ref().unwrap().data_size()
+    }
+
     fn data_size_mut(&mut self) -> &mut u64 {
         self.inner.as_mut().unwrap().data_size_mut()
     }
@@ -149,6 +155,7 @@ impl<Hooks: FileEntryHooks + 'static + Sync + Send> LenEntry for TestFileEntry<H
     }
 
     async fn touch(&self) -> bool {
+        Hooks::on_touch(self);
         self.inner.as_ref().unwrap().touch().await
     }
 
@@ -156,6 +163,11 @@ impl<Hooks: FileEntryHooks + 'static + Sync + Send> LenEntry for TestFileEntry<H
         Hooks::on_unref(self);
         self.inner.as_ref().unwrap().unref().await;
     }
+
+    async fn evict(&self) {
+        Hooks::on_unref(self);
+        self.inner.as_ref().unwrap().evict().await;
+    }
 }
 
 impl<Hooks: FileEntryHooks + 'static + Sync + Send> Drop for TestFileEntry<Hooks> {
@@ -263,6 +275,10 @@ const HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789a
 const HASH2: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
 const VALUE1: &str = "0123456789";
 const VALUE2: &str = "9876543210";
+/// Sha256 of `VALUE1`.
+const VALUE1_HASH: &str = "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882";
+/// Sha256 of `VALUE2`.
+const VALUE2_HASH: &str = "7619ee8cea49187f309616e30ecf54be072259b43760f1f550a644945d5572f2";
 const STRING_NAME: &str = "String_Filename";
 
 #[serial]
@@ -406,6 +422,7 @@ async fn file_continues_to_stream_on_content_replace_test() -> Result<(), Error>
             }),
             block_size: 1,
             read_buffer_size: 1,
+            ..Default::default()
         })
         .await?,
     );
@@ -515,6 +532,7 @@ async fn file_gets_cleans_up_on_cache_eviction() -> Result<(), Error> {
             }),
             block_size: 1,
             read_buffer_size: 1,
+            ..Default::default()
         })
         .await?,
     );
@@ -788,6 +806,83 @@ async fn eviction_on_insert_calls_unref_once() -> Result<(), Error> {
     Ok(())
 }
 
+#[serial]
+#[nativelink_test]
+async fn eviction_increments_evicted_counters_and_removes_oldest() -> Result<(), Error> {
+    const HASH3: &str = "0123456789abcdef000000000000000000030000000000000123456789abcdef";
+    const HASH4: &str = "0123456789abcdef000000000000000000040000000000000123456789abcdef";
+    const VALUE: &str = "0123";
+
+    let digest1 = DigestInfo::try_new(HASH1, VALUE.len())?;
+    let digest2 = DigestInfo::try_new(HASH2, VALUE.len())?;
+    let digest3 = DigestInfo::try_new(HASH3, VALUE.len())?;
+    let digest4 = DigestInfo::try_new(HASH4, VALUE.len())?;
+
+    let store = Box::pin(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: make_temp_path("temp_path"),
+            eviction_policy: Some(nativelink_config::stores::EvictionPolicy {
+                // Room for two values, the third insert triggers an eviction.
+                max_bytes: VALUE.len() * 3 - 1,
+                ..Default::default()
+            }),
+            block_size: 1,
+            ..Default::default()
+        })
+        .await?,
+    );
+    let shared_context = store.get_shared_context_for_test();
+
+    store.update_oneshot(digest1, VALUE.into()).await?;
+    store.update_oneshot(digest2, VALUE.into()).await?;
+    assert_eq!(shared_context.evicted_files.load(Ordering::Acquire), 0);
+    assert_eq!(shared_context.evicted_bytes.load(Ordering::Acquire), 0);
+
+    // Reading `digest1` makes `digest2` the least recently used entry.
+    store.get_part_unchunked(digest1, 0, None).await?;
+
+    store.update_oneshot(digest3, VALUE.into()).await?;
+    assert_eq!(shared_context.evicted_files.load(Ordering::Acquire), 1);
+    assert_eq!(
+        shared_context.evicted_bytes.load(Ordering::Acquire),
+        VALUE.len() as u64
+    );
+    assert_eq!(
+        store
+            .has_many(&[digest1.into(), digest2.into(), digest3.into()])
+            .await?,
+        vec![Some(VALUE.len() as u64), None, Some(VALUE.len() as u64)],
+        "Expected the least recently used entry to be evicted"
+    );
+
+    // `digest1` is now the least recently used entry.
+    store.update_oneshot(digest4, VALUE.into()).await?;
+    assert_eq!(shared_context.evicted_files.load(Ordering::Acquire), 2);
+    assert_eq!(
+        shared_context.evicted_bytes.load(Ordering::Acquire),
+        (VALUE.len() * 2) as u64
+    );
+    assert_eq!(
+        store
+            .has_many(&[digest1.into(), digest3.into(), digest4.into()])
+            .await?,
+        vec![None, Some(VALUE.len() as u64), Some(VALUE.len() as u64)],
+        "Expected the least recently used entry to be evicted"
+    );
+
+    // Replacing and removing entries are not evictions.
+    store.update_oneshot(digest3, VALUE.into()).await?;
+    assert!(store.remove(digest4).await?);
+    assert_eq!(shared_context.evicted_files.load(Ordering::Acquire), 2);
+    assert_eq!(
+        shared_context.evicted_bytes.load(Ordering::Acquire),
+        (VALUE.len() * 2) as u64
+    );
+
+    Ok(())
+}
+
 #[serial]
 #[nativelink_test]
 #[allow(clippy::await_holding_refcell_ref)]
@@ -1193,6 +1288,13 @@ async fn deleted_file_removed_from_store() -> Result<(), Error> {
     let stored_file_path = OsString::from(format!("{content_path}/{DIGEST_FOLDER}/{digest}"));
     std::fs::remove_file(stored_file_path)?;
 
+    // `has()` only consults the index, the deletion is noticed on read.
+    let get_result = store.get_part_unchunked(digest, 0, None).await;
+    assert_eq!(
+        get_result.map_err(|err| err.code),
+        Err(Code::NotFound),
+        "Expected reading a deleted file to fail"
+    );
     let digest_result = store
         .has(digest)
         .await
@@ -1210,6 +1312,10 @@ async fn deleted_file_removed_from_store() -> Result<(), Error> {
     let stored_file_path = OsString::from(format!("{content_path}/{STR_FOLDER}/{STRING_NAME}"));
     std::fs::remove_file(stored_file_path)?;
 
+    assert!(store
+        .get_part_unchunked(string_key.borrow(), 0, None)
+        .await
+        .is_err());
     let string_result = store
         .has(string_key)
         .await
@@ -1219,6 +1325,127 @@ async fn deleted_file_removed_from_store() -> Result<(), Error> {
     Ok(())
 }
 
+#[serial]
+#[nativelink_test]
+async fn sharded_layout_stores_and_moves_files_test() -> Result<(), Error> {
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let string_key = StoreKey::new_str(STRING_NAME);
+    let content_path = make_temp_path("content_path");
+    let temp_path = make_temp_path("temp_path");
+    let make_store = |content_shard_depth| {
+        let spec = FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: temp_path.clone(),
+            content_shard_depth,
+            ..Default::default()
+        };
+        async move { FilesystemStore::<FileEntryImpl>::new(&spec).await }
+    };
+    let digest_path = |shards: &str| format!("{content_path}/{DIGEST_FOLDER}/{shards}{digest}");
+    let string_path = format!("{content_path}/{STR_FOLDER}/{STRING_NAME}");
+
+    {
+        // Files are placed under one directory per two hex chars of the hash.
+        let store = Box::pin(make_store(2).await?);
+        store.update_oneshot(digest, VALUE1.into()).await?;
+        store
+            .update_oneshot(string_key.borrow(), VALUE2.into())
+            .await?;
+
+        assert!(Path::new(&digest_path("01/23/")).is_file());
+        assert!(!Path::new(&digest_path("")).exists());
+        // Strings are never sharded.
+        assert!(Path::new(&string_path).is_file());
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await?,
+            VALUE1.as_bytes()
+        );
+    }
+    {
+        // Reducing the depth moves the files back on startup.
+        let store = Box::pin(make_store(0).await?);
+        assert!(Path::new(&digest_path("")).is_file());
+        assert!(!Path::new(&digest_path("01/23/")).exists());
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await?,
+            VALUE1.as_bytes()
+        );
+        assert_eq!(
+            store
+                .get_part_unchunked(string_key.borrow(), 0, None)
+                .await?,
+            VALUE2.as_bytes()
+        );
+    }
+    {
+        // A flat layout is migrated into shards on startup.
+        let store = Box::pin(make_store(1).await?);
+        assert!(Path::new(&digest_path("01/")).is_file());
+        assert!(!Path::new(&digest_path("")).exists());
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await?,
+            VALUE1.as_bytes()
+        );
+    }
+
+    // Depths above the maximum are rejected.
+    assert!(make_store(5).await.is_err());
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn has_many_answers_from_index_test() -> Result<(), Error> {
+    const NUM_DIGESTS: usize = 100;
+    static TOUCHES: AtomicU32 = AtomicU32::new(0);
+    struct LocalHooks {}
+    impl FileEntryHooks for LocalHooks {
+        fn on_touch<Fe: FileEntry>(_file_entry: &Fe) {
+            TOUCHES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let store = Box::pin(
+        FilesystemStore::<TestFileEntry<LocalHooks>>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: make_temp_path("temp_path"),
+            block_size: 1,
+            ..Default::default()
+        })
+        .await?,
+    );
+
+    let mut keys = Vec::with_capacity(NUM_DIGESTS + 1);
+    let mut expected_results = Vec::with_capacity(NUM_DIGESTS + 1);
+    for i in 1..=NUM_DIGESTS {
+        let digest = DigestInfo::new(
+            Sha256::new()
+                .chain_update(i.to_le_bytes())
+                .finalize()
+                .into(),
+            i as u64,
+        );
+        store.update_oneshot(digest, vec![b'x'; i].into()).await?;
+        keys.push(StoreKey::from(digest));
+        expected_results.push(Some(i as u64));
+    }
+    keys.push(DigestInfo::try_new(HASH1, VALUE1.len())?.into());
+    expected_results.push(None);
+
+    TOUCHES.store(0, Ordering::Relaxed);
+    assert_eq!(store.has_many(&keys).await?, expected_results);
+    assert_eq!(
+        TOUCHES.load(Ordering::Relaxed),
+        0,
+        "Expected has_many to not touch any files"
+    );
+    // The found files are touched in the background once it returned.
+    while TOUCHES.load(Ordering::Relaxed) < NUM_DIGESTS as u32 {
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
 // Ensure that get_file_size() returns the correct number
 // ceil(content length / block_size) * block_size
 // assume block size 4K
@@ -1334,6 +1561,8 @@ async fn update_with_whole_file_slow_path_when_low_file_descriptors() -> Result<
         &FastSlowSpec {
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         Store::new(
             FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
@@ -1434,3 +1663,430 @@ async fn update_with_whole_file_uses_same_inode() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[serial]
+#[nativelink_test]
+async fn health_check_passes_when_temp_and_content_path_share_filesystem() -> Result<(), Error> {
+    let store = FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: make_temp_path("temp_path"),
+        ..Default::default()
+    })
+    .await?;
+
+    let status = HealthStatusIndicator::check_health(store.as_ref(), "".into()).await;
+    assert!(
+        matches!(status, HealthStatus::Ok { .. }),
+        "Expected healthy store, got {status:?}"
+    );
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn health_check_fails_when_temp_and_content_path_are_cross_device() -> Result<(), Error> {
+    let content_path = make_temp_path("content_path");
+    let temp_path = make_temp_path("temp_path");
+    let store = FilesystemStore::<FileEntryImpl>::new_with_timeout_and_rename_fn(
+        &FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: temp_path.clone(),
+            ..Default::default()
+        },
+        |_| sleep(Duration::ZERO),
+        // Simulate `temp_path` and `content_path` being on different devices.
+        |_from, _to| Err(std::io::Error::other("Invalid cross-device link")),
+    )
+    .await?;
+
+    let status = HealthStatusIndicator::check_health(store.as_ref(), "".into()).await;
+    let HealthStatus::Failed { message, .. } = status else {
+        panic!("Expected failed health status, got {status:?}");
+    };
+    assert!(
+        message.contains("same filesystem") && message.contains(&content_path),
+        "Expected message to explain the cross-device paths, got {message}"
+    );
+    // Only the `STR_FOLDER` and `DIGEST_FOLDER` folders are left in the temp path.
+    assert_eq!(std::fs::read_dir(&temp_path)?.count(), 2);
+    Ok(())
+}
+
+// `/dev/shm` is a tmpfs on Linux, so it is on a different device than the
+// test's temporary directory.
+#[cfg(target_os = "linux")]
+#[serial]
+#[nativelink_test]
+async fn new_fails_when_temp_and_content_path_are_on_different_devices() -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+    let content_path = format!("/dev/shm/{}/content_path", thread_rng().gen::<u64>());
+    let temp_path = make_temp_path("temp_path");
+    std::fs::create_dir_all(&content_path)?;
+    std::fs::create_dir_all(&temp_path)?;
+    if std::fs::metadata(&content_path)?.dev() == std::fs::metadata(&temp_path)?.dev() {
+        // The test's temporary directory is itself on `/dev/shm`.
+        return Ok(());
+    }
+
+    let result = FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+        content_path: content_path.clone(),
+        temp_path: temp_path.clone(),
+        ..Default::default()
+    })
+    .await;
+    std::fs::remove_dir_all(Path::new(&content_path).parent().unwrap())?;
+
+    let err = result.err().expect("Expected construction to fail");
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string().contains("same device"),
+        "Expected error to explain the cross-device paths, got {err:?}"
+    );
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn sync_renames_stores_and_reads_file() -> Result<(), Error> {
+    const VALUE: &str = "0123456789";
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: make_temp_path("temp_path"),
+            sync_renames: true,
+            ..Default::default()
+        })
+        .await?,
+    );
+
+    let digest = DigestInfo::try_new(HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE.as_bytes()
+    );
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn get_part_past_end_is_rejected_test() -> Result<(), Error> {
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: make_temp_path("temp_path"),
+            ..Default::default()
+        })
+        .await?,
+    );
+
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, VALUE1.len() as u64, None)
+            .await?,
+        ""
+    );
+    let err = store
+        .get_part_unchunked(digest, VALUE1.len() as u64 + 1, None)
+        .await
+        .expect_err("Expected offset past the end to fail");
+    assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn get_part_with_sequential_read_advice_respects_read_buffer_size() -> Result<(), Error> {
+    const READ_BUFFER_SIZE: usize = 7;
+    let value: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: make_temp_path("temp_path"),
+            read_buffer_size: READ_BUFFER_SIZE as u32,
+            sequential_read_advice: true,
+            ..Default::default()
+        })
+        .await?,
+    );
+    let digest = DigestInfo::try_new(HASH1, value.len())?;
+    store.update_oneshot(digest, value.clone().into()).await?;
+
+    for (offset, length) in [(0, None), (13, Some(500)), (990, None), (100, Some(1))] {
+        let (writer, mut reader) = make_buf_channel_pair();
+        let read_chunks = async move {
+            let mut chunks = Vec::new();
+            loop {
+                let chunk = reader.recv().await?;
+                if chunk.is_empty() {
+                    return Ok::<_, Error>(chunks);
+                }
+                chunks.push(chunk);
+            }
+        };
+        let (get_res, chunks_res) =
+            tokio::join!(store.get_part(digest, writer, offset, length), read_chunks);
+        get_res?;
+        let chunks = chunks_res?;
+
+        assert!(
+            chunks.iter().all(|chunk| chunk.len() <= READ_BUFFER_SIZE),
+            "Expected no chunk larger than {READ_BUFFER_SIZE} bytes for offset {offset}",
+        );
+        let start = usize::try_from(offset)?;
+        let end = length.map_or(value.len(), |length| start + length as usize);
+        assert_eq!(chunks.concat(), &value[start..end]);
+    }
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn remove_deletes_file_from_content_path() -> Result<(), Error> {
+    let content_path = make_temp_path("content_path");
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: make_temp_path("temp_path"),
+            ..Default::default()
+        })
+        .await?,
+    );
+    let digest = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    let content_file_path = format!("{content_path}/{DIGEST_FOLDER}/{digest}");
+    assert!(Path::new(&content_file_path).exists());
+
+    assert_eq!(store.remove(digest).await, Ok(true));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert!(!Path::new(&content_file_path).exists());
+    assert_eq!(store.remove(digest).await, Ok(false));
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn purge_by_digest_and_by_prefix_removes_only_matching_files() -> Result<(), Error> {
+    let content_path = make_temp_path("content_path");
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: make_temp_path("temp_path"),
+            ..Default::default()
+        })
+        .await?,
+    );
+    // Both entries of HASH1 match the prefix, the entry of HASH2 does not.
+    let hash1_prefix = &HASH1[..36];
+    let digest1 = DigestInfo::try_new(HASH1, VALUE1.len())?;
+    let digest1_short = DigestInfo::try_new(HASH1, 3)?;
+    let digest2 = DigestInfo::try_new(HASH2, VALUE2.len())?;
+    store.update_oneshot(digest1, VALUE1.into()).await?;
+    store.update_oneshot(digest1_short, "abc".into()).await?;
+    store.update_oneshot(digest2, VALUE2.into()).await?;
+
+    assert_eq!(store.remove(digest1_short).await, Ok(true));
+    assert_eq!(store.has(digest1_short).await, Ok(None));
+    assert!(store.has(digest1).await?.is_some());
+
+    store.update_oneshot(digest1_short, "abc".into()).await?;
+    assert_eq!(store.remove_prefix(hash1_prefix).await, Ok(2));
+    assert_eq!(store.has(digest1).await, Ok(None));
+    assert_eq!(store.has(digest1_short).await, Ok(None));
+    assert!(store.has(digest2).await?.is_some());
+    assert!(Path::new(&format!("{content_path}/{DIGEST_FOLDER}/{digest2}")).exists());
+    assert_eq!(store.remove_prefix(hash1_prefix).await, Ok(0));
+    Ok(())
+}
+
+#[serial]
+#[nativelink_test]
+async fn verify_on_read_detects_and_evicts_corrupted_file() -> Result<(), Error> {
+    let content_path = make_temp_path("content_path");
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: content_path.clone(),
+            temp_path: make_temp_path("temp_path"),
+            verify_on_read: true,
+            evict_on_verify_failure: true,
+            ..Default::default()
+        })
+        .await?,
+    );
+    let digest = DigestInfo::new(
+        Sha256::new().chain_update(VALUE1).finalize().into(),
+        VALUE1.len() as u64,
+    );
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(Bytes::from(VALUE1)),
+        "Expected intact content to pass verification"
+    );
+
+    // Corrupt the file on disk without changing its length.
+    let content_file_path = format!("{content_path}/{DIGEST_FOLDER}/{digest}");
+    let corrupted_value = "X".repeat(VALUE1.len());
+    {
+        let mut file = fs::create_file(&content_file_path).await?;
+        let writer = file.as_writer().await?;
+        writer.write_all(corrupted_value.as_bytes()).await?;
+        writer.as_mut().sync_all().await?;
+    }
+
+    // Range reads can not be verified and are served as is.
+    assert_eq!(
+        store.get_part_unchunked(digest, 1, Some(2)).await,
+        Ok(Bytes::copy_from_slice(&corrupted_value.as_bytes()[1..3])),
+    );
+
+    let result = store.get_part_unchunked(digest, 0, None).await;
+    assert_eq!(
+        result.map_err(|e| e.code),
+        Err(Code::DataLoss),
+        "Expected corrupted content to fail verification"
+    );
+    assert_eq!(store.has(digest).await, Ok(None));
+    // The file is deleted on a background task once the entry is dropped.
+    for _ in 0..100 {
+        if !Path::new(&content_file_path).exists() {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert!(!Path::new(&content_file_path).exists());
+    Ok(())
+}
+
+/// Writes `data` at `offset` of `key` with `update_at_offset`.
+async fn write_range(
+    store: &Store,
+    key: StoreKey<'_>,
+    offset: u64,
+    data: &str,
+) -> Result<(), Error> {
+    let (mut tx, rx) = make_buf_channel_pair();
+    let data = Bytes::copy_from_slice(data.as_bytes());
+    let data_len = data.len() as u64;
+    let send_fut = async move {
+        tx.send(data).await?;
+        tx.send_eof()
+    };
+    let (update_res, send_res) = tokio::join!(
+        store.update_at_offset(key, rx, offset, UploadSizeInfo::ExactSize(data_len)),
+        send_fut
+    );
+    update_res.merge(send_res)
+}
+
+#[serial]
+#[nativelink_test]
+async fn update_at_offset_assembles_non_contiguous_ranges() -> Result<(), Error> {
+    let temp_path = make_temp_path("temp_path");
+    let store = Store::new(
+        FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+            content_path: make_temp_path("content_path"),
+            temp_path: temp_path.clone(),
+            // Makes `has()` report the size of the data instead of the
+            // size it takes up on disk.
+            block_size: 1,
+            ..Default::default()
+        })
+        .await?,
+    );
+    let digest = DigestInfo::try_new(VALUE1_HASH, VALUE1.len())?;
+
+    // Write the second half first, the entry is not visible until every
+    // range of it was written.
+    write_range(&store, digest.into(), 6, &VALUE1[6..]).await?;
+    assert_eq!(store.has(digest).await?, None);
+    // Then write the start, leaving a gap that is filled by the last write.
+    write_range(&store, digest.into(), 0, &VALUE1[..3]).await?;
+    assert_eq!(store.has(digest).await?, None);
+    write_range(&store, digest.into(), 3, &VALUE1[3..6]).await?;
+
+    assert_eq!(store.has(digest).await?, Some(VALUE1.len() as u64));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    check_temp_empty(&temp_path).await
+}
+
+#[serial]
+#[nativelink_test]
+async fn update_at_offset_keeps_concurrent_ranges_and_rejects_wrong_content() -> Result<(), Error> {
+    let temp_path = make_temp_path("temp_path");
+    let filesystem_store = FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: temp_path.clone(),
+        ..Default::default()
+    })
+    .await?;
+    let store = Store::new(filesystem_store.clone());
+    let digest = DigestInfo::try_new(VALUE1_HASH, VALUE1.len())?;
+
+    // Ranges of the same digest written at the same time are all kept.
+    let (first_res, second_res) = tokio::join!(
+        write_range(&store, digest.into(), 0, &VALUE1[..5]),
+        write_range(&store, digest.into(), 5, &VALUE1[5..]),
+    );
+    first_res.merge(second_res)?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+
+    // Content that does not hash to the digest is never added.
+    let digest = DigestInfo::try_new(VALUE2_HASH, VALUE2.len())?;
+    write_range(&store, digest.into(), 0, &VALUE2[..5]).await?;
+    assert_eq!(
+        write_range(&store, digest.into(), 5, &VALUE1[5..])
+            .await
+            .map_err(|e| e.code),
+        Err(Code::InvalidArgument),
+        "Expected content not matching the digest to be rejected"
+    );
+    assert_eq!(store.has(digest).await?, None);
+    // The rejected file is deleted on a background task.
+    let shared_context = filesystem_store.get_shared_context_for_test();
+    while shared_context.active_drop_spawns.load(Ordering::Acquire) > 0 {
+        tokio::task::yield_now().await;
+    }
+    check_temp_empty(&temp_path).await
+}
+
+#[serial]
+#[nativelink_test]
+async fn update_at_offset_deletes_abandoned_partial_uploads() -> Result<(), Error> {
+    let temp_path = make_temp_path("temp_path");
+    let filesystem_store = FilesystemStore::<FileEntryImpl>::new(&FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: temp_path.clone(),
+        partial_upload_timeout_s: 1,
+        ..Default::default()
+    })
+    .await?;
+    let store = Store::new(filesystem_store.clone());
+    let abandoned_digest = DigestInfo::try_new(VALUE1_HASH, VALUE1.len())?;
+    let digest = DigestInfo::try_new(VALUE2_HASH, VALUE2.len())?;
+
+    write_range(&store, abandoned_digest.into(), 0, &VALUE1[..5]).await?;
+    sleep(Duration::from_millis(1100)).await;
+    // Writing any range deletes uploads no range was written to for longer
+    // than the timeout.
+    write_range(&store, digest.into(), 0, VALUE2).await?;
+    let shared_context = filesystem_store.get_shared_context_for_test();
+    while shared_context.active_drop_spawns.load(Ordering::Acquire) > 0 {
+        tokio::task::yield_now().await;
+    }
+    check_temp_empty(&temp_path).await?;
+
+    // The rest of the abandoned upload starts a new one instead of
+    // completing it.
+    write_range(&store, abandoned_digest.into(), 5, &VALUE1[5..]).await?;
+    assert_eq!(store.has(abandoned_digest).await?, None);
+    Ok(())
+}
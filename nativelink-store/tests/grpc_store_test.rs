@@ -0,0 +1,582 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::{unfold, Stream};
+use nativelink_config::stores::{GrpcEndpoint, GrpcSpec, Retry, StoreType};
+use nativelink_error::{Code, Error, ErrorReason};
+use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::action_cache_server::{
+    ActionCache, ActionCacheServer,
+};
+use nativelink_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::{
+    ContentAddressableStorage, ContentAddressableStorageServer,
+};
+use nativelink_proto::build::bazel::remote::execution::v2::{
+    digest_function, ActionResult, BatchReadBlobsRequest, BatchReadBlobsResponse,
+    BatchUpdateBlobsRequest, BatchUpdateBlobsResponse, Digest, FindMissingBlobsRequest,
+    FindMissingBlobsResponse, GetActionResultRequest, GetTreeRequest, GetTreeResponse, OutputFile,
+    UpdateActionResultRequest,
+};
+use nativelink_store::grpc_store::GrpcStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::spawn;
+use nativelink_util::store_trait::{StoreKey, StoreLike};
+use nativelink_util::task::JoinHandleDropGuard;
+use pretty_assertions::{assert_eq, assert_ne};
+use prost::Message;
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+const INSTANCE_NAME: &str = "foo_instance_name";
+const ACTION_HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const STDOUT_HASH: &str = "0123456789abcdef000000000000000000020000000000000123456789abcdef";
+const STDERR_HASH: &str = "0123456789abcdef000000000000000000030000000000000123456789abcdef";
+const OUTPUT_FILE_HASH: &str = "0123456789abcdef000000000000000000040000000000000123456789abcdef";
+const OUTPUT_FILE_PATH: &str = "some/output_file";
+/// Action the upstream action cache has no result for.
+const MISSING_ACTION_HASH: &str =
+    "0123456789abcdef000000000000000000050000000000000123456789abcdef";
+
+/// Upstream action cache that inlines content exactly as requested.
+struct InliningActionCache {
+    stdout: Bytes,
+    stderr: Bytes,
+    output_file: Bytes,
+}
+
+#[tonic::async_trait]
+impl ActionCache for InliningActionCache {
+    async fn get_action_result(
+        &self,
+        request: Request<GetActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        let request = request.into_inner();
+        if request
+            .action_digest
+            .as_ref()
+            .is_some_and(|digest| digest.hash == MISSING_ACTION_HASH)
+        {
+            return Err(Status::not_found("Action not in upstream cache"));
+        }
+        let inline_or_empty = |inline: bool, data: &Bytes| {
+            if inline {
+                data.clone()
+            } else {
+                Bytes::new()
+            }
+        };
+        Ok(Response::new(ActionResult {
+            stdout_digest: Some(DigestInfo::try_new(STDOUT_HASH, self.stdout.len())?.into()),
+            stdout_raw: inline_or_empty(request.inline_stdout, &self.stdout),
+            stderr_digest: Some(DigestInfo::try_new(STDERR_HASH, self.stderr.len())?.into()),
+            stderr_raw: inline_or_empty(request.inline_stderr, &self.stderr),
+            output_files: vec![OutputFile {
+                path: OUTPUT_FILE_PATH.to_string(),
+                digest: Some(DigestInfo::try_new(OUTPUT_FILE_HASH, self.output_file.len())?.into()),
+                contents: inline_or_empty(
+                    request
+                        .inline_output_files
+                        .iter()
+                        .any(|path| path == OUTPUT_FILE_PATH),
+                    &self.output_file,
+                ),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }))
+    }
+
+    async fn update_action_result(
+        &self,
+        _request: Request<UpdateActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        Err(Status::unimplemented("Not used in tests"))
+    }
+}
+
+/// Upstream action cache that counts how many requests it serves at once.
+/// Actions with an odd size are not in the cache.
+#[derive(Default)]
+struct ConcurrencyTrackingActionCache {
+    in_flight: AtomicUsize,
+    max_in_flight: Arc<AtomicUsize>,
+}
+
+#[tonic::async_trait]
+impl ActionCache for ConcurrencyTrackingActionCache {
+    async fn get_action_result(
+        &self,
+        request: Request<GetActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        sleep(Duration::from_millis(10)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        let size_bytes = request
+            .into_inner()
+            .action_digest
+            .map_or(0, |digest| digest.size_bytes);
+        if size_bytes % 2 == 1 {
+            return Err(Status::not_found("Action not in upstream cache"));
+        }
+        Ok(Response::new(ActionResult {
+            exit_code: 1,
+            ..Default::default()
+        }))
+    }
+
+    async fn update_action_result(
+        &self,
+        _request: Request<UpdateActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        Err(Status::unimplemented("Not used in tests"))
+    }
+}
+
+async fn make_upstream_and_store(
+    upstream: impl ActionCache,
+    max_inline_size: u64,
+    make_endpoint: fn(String) -> GrpcEndpoint,
+) -> Result<(JoinHandleDropGuard<()>, Arc<GrpcStore>), Error> {
+    make_upstream_and_store_with_spec(upstream, |address| GrpcSpec {
+        instance_name: INSTANCE_NAME.to_string(),
+        endpoints: vec![make_endpoint(address)],
+        store_type: StoreType::ac,
+        retry: Retry::default(),
+        max_concurrent_requests: 0,
+        connections_per_endpoint: 0,
+        max_inline_size,
+        max_concurrent_has_requests: 0,
+        compress_uploads: false,
+    })
+    .await
+}
+
+async fn make_upstream_and_store_with_spec(
+    upstream: impl ActionCache,
+    make_spec: impl FnOnce(String) -> GrpcSpec,
+) -> Result<(JoinHandleDropGuard<()>, Arc<GrpcStore>), Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let server_spawn = spawn!("upstream_action_cache", async move {
+        let incoming = unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        Server::builder()
+            .add_service(ActionCacheServer::new(upstream))
+            .serve_with_incoming(incoming)
+            .await
+            .expect("Upstream action cache failed");
+    });
+    let store = GrpcStore::new(&make_spec(format!("grpc://{address}"))).await?;
+    Ok((server_spawn, store))
+}
+
+/// Upstream CAS that only answers `FindMissingBlobs`.
+struct FindMissingBlobsCas {
+    missing: Vec<Digest>,
+}
+
+#[tonic::async_trait]
+impl ContentAddressableStorage for FindMissingBlobsCas {
+    async fn find_missing_blobs(
+        &self,
+        request: Request<FindMissingBlobsRequest>,
+    ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        Ok(Response::new(FindMissingBlobsResponse {
+            missing_blob_digests: request
+                .into_inner()
+                .blob_digests
+                .into_iter()
+                .filter(|digest| self.missing.contains(digest))
+                .collect(),
+        }))
+    }
+
+    async fn batch_update_blobs(
+        &self,
+        _request: Request<BatchUpdateBlobsRequest>,
+    ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        Err(Status::unimplemented("Not used in tests"))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        _request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        Err(Status::unimplemented("Not used in tests"))
+    }
+
+    type GetTreeStream =
+        Pin<Box<dyn Stream<Item = Result<GetTreeResponse, Status>> + Send + 'static>>;
+
+    async fn get_tree(
+        &self,
+        _request: Request<GetTreeRequest>,
+    ) -> Result<Response<Self::GetTreeStream>, Status> {
+        Err(Status::unimplemented("Not used in tests"))
+    }
+}
+
+async fn make_cas_upstream_and_store(
+    upstream: FindMissingBlobsCas,
+) -> Result<(JoinHandleDropGuard<()>, Arc<GrpcStore>), Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let server_spawn = spawn!("upstream_cas", async move {
+        let incoming = unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        Server::builder()
+            .add_service(ContentAddressableStorageServer::new(upstream))
+            .serve_with_incoming(incoming)
+            .await
+            .expect("Upstream CAS failed");
+    });
+    let store = GrpcStore::new(&GrpcSpec {
+        instance_name: INSTANCE_NAME.to_string(),
+        endpoints: vec![default_endpoint(format!("grpc://{address}"))],
+        store_type: StoreType::cas,
+        retry: Retry::default(),
+        max_concurrent_requests: 0,
+        connections_per_endpoint: 0,
+        max_inline_size: 0,
+        max_concurrent_has_requests: 0,
+        compress_uploads: false,
+    })
+    .await?;
+    Ok((server_spawn, store))
+}
+
+fn default_endpoint(address: String) -> GrpcEndpoint {
+    GrpcEndpoint {
+        address,
+        ..Default::default()
+    }
+}
+
+fn make_request(inline: bool) -> Result<GetActionResultRequest, Error> {
+    Ok(GetActionResultRequest {
+        instance_name: INSTANCE_NAME.to_string(),
+        action_digest: Some(DigestInfo::try_new(ACTION_HASH, 100)?.into()),
+        inline_stdout: inline,
+        inline_stderr: inline,
+        inline_output_files: if inline {
+            vec![OUTPUT_FILE_PATH.to_string()]
+        } else {
+            Vec::new()
+        },
+        digest_function: digest_function::Value::Sha256.into(),
+    })
+}
+
+#[nativelink_test]
+async fn get_action_result_forwards_inline_flags() -> Result<(), Error> {
+    const STDOUT: &str = "stdout data";
+    const STDERR: &str = "stderr data";
+    const OUTPUT_FILE: &str = "output file data";
+
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(STDOUT.as_bytes()),
+            stderr: Bytes::from_static(STDERR.as_bytes()),
+            output_file: Bytes::from_static(OUTPUT_FILE.as_bytes()),
+        },
+        0, /* max_inline_size */
+        default_endpoint,
+    )
+    .await?;
+
+    {
+        // Content is inlined when the client asks for it.
+        let action_result = store
+            .get_action_result(Request::new(make_request(true)?))
+            .await?
+            .into_inner();
+        assert_eq!(action_result.stdout_raw, STDOUT.as_bytes());
+        assert_eq!(action_result.stderr_raw, STDERR.as_bytes());
+        assert_eq!(
+            action_result.output_files[0].contents,
+            OUTPUT_FILE.as_bytes()
+        );
+    }
+    {
+        // Content is not inlined when the client does not ask for it.
+        let action_result = store
+            .get_action_result(Request::new(make_request(false)?))
+            .await?
+            .into_inner();
+        assert_eq!(action_result.stdout_raw, Bytes::new());
+        assert_eq!(action_result.stderr_raw, Bytes::new());
+        assert_eq!(action_result.output_files[0].contents, Bytes::new());
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_action_result_strips_inline_content_over_max_size() -> Result<(), Error> {
+    const MAX_INLINE_SIZE: u64 = 10;
+    const SMALL_STDOUT: &str = "small";
+    const EXACT_STDERR: &str = "0123456789";
+    const LARGE_OUTPUT_FILE: &str = "this is larger than the max inline size";
+
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(SMALL_STDOUT.as_bytes()),
+            stderr: Bytes::from_static(EXACT_STDERR.as_bytes()),
+            output_file: Bytes::from_static(LARGE_OUTPUT_FILE.as_bytes()),
+        },
+        MAX_INLINE_SIZE,
+        default_endpoint,
+    )
+    .await?;
+
+    let action_result = store
+        .get_action_result(Request::new(make_request(true)?))
+        .await?
+        .into_inner();
+    // Content within the limit is passed through.
+    assert_eq!(action_result.stdout_raw, SMALL_STDOUT.as_bytes());
+    assert_eq!(action_result.stderr_raw, EXACT_STDERR.as_bytes());
+    // Content over the limit is dropped, but can still be fetched by digest.
+    assert_eq!(action_result.output_files[0].contents, Bytes::new());
+    assert_eq!(
+        action_result.output_files[0].digest,
+        Some(DigestInfo::try_new(OUTPUT_FILE_HASH, LARGE_OUTPUT_FILE.len())?.into())
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_action_result_with_tuned_endpoint() -> Result<(), Error> {
+    const STDOUT: &str = "stdout data";
+
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(STDOUT.as_bytes()),
+            stderr: Bytes::new(),
+            output_file: Bytes::new(),
+        },
+        0, /* max_inline_size */
+        |address| GrpcEndpoint {
+            address,
+            concurrency_limit: Some(1),
+            http2_keep_alive_interval: Some(30),
+            http2_keep_alive_timeout: Some(5),
+            http2_keep_alive_while_idle: Some(true),
+            tcp_nodelay: Some(true),
+            http2_initial_stream_window_size: Some(1024 * 1024),
+            http2_initial_connection_window_size: Some(4 * 1024 * 1024),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let action_result = store
+        .get_action_result(Request::new(make_request(true)?))
+        .await?
+        .into_inner();
+    assert_eq!(action_result.stdout_raw, STDOUT.as_bytes());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn has_on_ac_store_returns_action_result_size() -> Result<(), Error> {
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(b"stdout data"),
+            stderr: Bytes::from_static(b"stderr data"),
+            output_file: Bytes::from_static(b"output file data"),
+        },
+        0, /* max_inline_size */
+        default_endpoint,
+    )
+    .await?;
+
+    let expected_size = store
+        .get_action_result(Request::new(make_request(false)?))
+        .await?
+        .into_inner()
+        .encoded_len();
+    // The size in the action digest is not the size of the stored entry, so
+    // it must not be echoed back.
+    let action_digest = DigestInfo::try_new(ACTION_HASH, 100)?;
+    assert_ne!(expected_size, 100);
+    assert_eq!(
+        store.has(action_digest).await?,
+        Some(u64::try_from(expected_size).unwrap())
+    );
+    assert_eq!(
+        store
+            .has(DigestInfo::try_new(MISSING_ACTION_HASH, 100)?)
+            .await?,
+        None
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn has_many_on_ac_store_bounds_concurrency_and_keeps_order() -> Result<(), Error> {
+    const MAX_CONCURRENT_HAS_REQUESTS: usize = 4;
+    const NUM_DIGESTS: u64 = 20;
+    let upstream = ConcurrencyTrackingActionCache::default();
+    let max_in_flight = upstream.max_in_flight.clone();
+    let (_server_spawn, store) = make_upstream_and_store_with_spec(upstream, |address| GrpcSpec {
+        instance_name: INSTANCE_NAME.to_string(),
+        endpoints: vec![default_endpoint(address)],
+        store_type: StoreType::ac,
+        retry: Retry::default(),
+        max_concurrent_requests: 0,
+        connections_per_endpoint: 0,
+        max_inline_size: 0,
+        max_concurrent_has_requests: MAX_CONCURRENT_HAS_REQUESTS,
+        compress_uploads: false,
+    })
+    .await?;
+
+    let digests = (0..NUM_DIGESTS)
+        .map(|size| DigestInfo::try_new(ACTION_HASH, size))
+        .collect::<Result<Vec<_>, _>>()?;
+    let keys: Vec<StoreKey> = digests.iter().map(|digest| (*digest).into()).collect();
+    let results = store.has_many(&keys).await?;
+
+    let found_size = u64::try_from(
+        ActionResult {
+            exit_code: 1,
+            ..Default::default()
+        }
+        .encoded_len(),
+    )
+    .unwrap();
+    let expected_results: Vec<Option<u64>> = (0..NUM_DIGESTS)
+        .map(|size| (size % 2 == 0).then_some(found_size))
+        .collect();
+    assert_eq!(results, expected_results);
+    let max_in_flight = max_in_flight.load(Ordering::SeqCst);
+    assert!(
+        max_in_flight <= MAX_CONCURRENT_HAS_REQUESTS,
+        "Expected at most {MAX_CONCURRENT_HAS_REQUESTS} requests at once, got {max_in_flight}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn has_on_cas_store_returns_digest_size() -> Result<(), Error> {
+    const PRESENT_HASH: &str = STDOUT_HASH;
+    const MISSING_HASH: &str = STDERR_HASH;
+
+    let missing_digest = DigestInfo::try_new(MISSING_HASH, 7)?;
+    let (_server_spawn, store) = make_cas_upstream_and_store(FindMissingBlobsCas {
+        missing: vec![missing_digest.into()],
+    })
+    .await?;
+
+    // Sizes of CAS digests are trusted, so they are returned as is.
+    let present_digest = DigestInfo::try_new(PRESENT_HASH, 42)?;
+    let mut results = [None, None];
+    store
+        .has_with_results(
+            &[present_digest.into(), missing_digest.into()],
+            &mut results,
+        )
+        .await?;
+    assert_eq!(results, [Some(42), None]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn get_part_past_end_is_rejected_test() -> Result<(), Error> {
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(b"stdout data"),
+            stderr: Bytes::from_static(b"stderr data"),
+            output_file: Bytes::from_static(b"output file data"),
+        },
+        0, /* max_inline_size */
+        default_endpoint,
+    )
+    .await?;
+
+    let action_digest = DigestInfo::try_new(ACTION_HASH, 100)?;
+    let encoded_len = u64::try_from(
+        store
+            .get_action_result(Request::new(make_request(false)?))
+            .await?
+            .into_inner()
+            .encoded_len(),
+    )
+    .unwrap();
+    assert_eq!(
+        store
+            .get_part_unchunked(action_digest, encoded_len, None)
+            .await?,
+        Bytes::new()
+    );
+    let err = store
+        .get_part_unchunked(action_digest, encoded_len + 1, None)
+        .await
+        .expect_err("Expected offset past the end to be rejected");
+    assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+
+    // CAS digests carry their size, so the check happens before any request.
+    let (_cas_server_spawn, cas_store) =
+        make_cas_upstream_and_store(FindMissingBlobsCas { missing: vec![] }).await?;
+    let err = cas_store
+        .get_part_unchunked(DigestInfo::try_new(STDOUT_HASH, 7)?, 8, None)
+        .await
+        .expect_err("Expected offset past the end to be rejected");
+    assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn not_found_from_upstream_hides_context_from_clients() -> Result<(), Error> {
+    let (_server_spawn, store) = make_upstream_and_store(
+        InliningActionCache {
+            stdout: Bytes::from_static(b"stdout data"),
+            stderr: Bytes::from_static(b"stderr data"),
+            output_file: Bytes::from_static(b"output file data"),
+        },
+        0, /* max_inline_size */
+        default_endpoint,
+    )
+    .await?;
+
+    let err = store
+        .get_part_unchunked(DigestInfo::try_new(MISSING_ACTION_HASH, 100)?, 0, None)
+        .await
+        .expect_err("Expected missing action to fail");
+    assert_eq!(err.code, Code::NotFound, "{err:?}");
+    assert_eq!(err.reason, Some(ErrorReason::NotFound), "{err:?}");
+    // Clients only see the message of the upstream status, not the context
+    // added while it propagated through the store.
+    assert!(err.messages.len() > 1, "{err:?}");
+    assert_eq!(err.client_message(), err.messages[0]);
+    assert!(
+        err.client_message()
+            .contains("Action not in upstream cache"),
+        "{err:?}"
+    );
+    Ok(())
+}
@@ -12,20 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::env;
 use std::ops::RangeBounds;
 use std::pin::Pin;
 
 use bytes::{BufMut, Bytes, BytesMut};
 use memory_stats::memory_stats;
-use nativelink_config::stores::MemorySpec;
-use nativelink_error::{Error, ResultExt};
+use nativelink_config::stores::{EvictionPolicy, FilesystemSpec, MemorySpec};
+use nativelink_error::{Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
+use nativelink_store::filesystem_store::FilesystemStore;
 use nativelink_store::memory_store::MemoryStore;
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::DigestInfo;
 use nativelink_util::spawn;
-use nativelink_util::store_trait::{StoreKey, StoreLike};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
 use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
 use sha2::{Digest, Sha256};
 
 const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
@@ -149,6 +152,26 @@ async fn read_partial() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn read_past_end_is_rejected_test() -> Result<(), Error> {
+    const VALUE1: &str = "1234";
+    let store_owned = MemoryStore::new(&MemorySpec::default());
+    let store = Pin::new(&store_owned);
+
+    let digest = DigestInfo::try_new(VALID_HASH1, 4).unwrap();
+    store.update_oneshot(digest, VALUE1.into()).await?;
+
+    // Reading from the very end is an empty read, but past it is an error.
+    assert_eq!(store.get_part_unchunked(digest, 4, None).await?, "");
+    assert_eq!(store.get_part_unchunked(digest, 2, Some(10)).await?, "34");
+    let err = store
+        .get_part_unchunked(digest, 5, None)
+        .await
+        .expect_err("Expected offset past the end to fail");
+    assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    Ok(())
+}
+
 // A bug was found where reading an empty value from memory store would result in an error
 // due to internal EOF handling. This is an edge case test.
 #[nativelink_test]
@@ -171,6 +194,86 @@ async fn read_zero_size_item_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn get_part_unchunked_matches_get_part() -> Result<(), Error> {
+    const VALUE: &str = "0123456789";
+    const LEN: u64 = VALUE.len() as u64;
+    let store_owned = MemoryStore::new(&MemorySpec::default());
+    let store = Pin::new(store_owned.as_ref());
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    // Reads the data through the buf channel, like the default
+    // `get_part_unchunked` implementation does.
+    let get_part_via_channel = |offset: u64, length: Option<u64>| async move {
+        let (mut writer, mut reader) = make_buf_channel_pair();
+        // The writer is moved in, so it is dropped if `get_part` fails
+        // without sending EOF and the reader stops waiting for more data.
+        let (data_res, get_part_res) = tokio::join!(reader.consume(None), async move {
+            store.get_part(digest, &mut writer, offset, length).await
+        });
+        get_part_res.merge(data_res)
+    };
+
+    for (offset, length) in [
+        (0, None),
+        (0, Some(0)),
+        (0, Some(LEN)),
+        (0, Some(LEN + 1)),
+        (1, Some(2)),
+        (3, None),
+        (LEN - 1, None),
+        (LEN - 1, Some(5)),
+        (LEN, None),
+        (LEN, Some(1)),
+    ] {
+        let unchunked = store.get_part_unchunked(digest, offset, length).await?;
+        let via_channel = get_part_via_channel(offset, length).await?;
+        assert_eq!(
+            unchunked, via_channel,
+            "Mismatch for offset {offset} and length {length:?}"
+        );
+    }
+
+    // Both reject offsets past the end of the data.
+    for length in [None, Some(1)] {
+        assert_eq!(
+            store
+                .get_part_unchunked(digest, LEN + 5, length)
+                .await
+                .map_err(|e| e.code),
+            Err(Code::InvalidArgument),
+            "Expected get_part_unchunked to fail for length {length:?}"
+        );
+        assert_eq!(
+            get_part_via_channel(LEN + 5, length)
+                .await
+                .map_err(|e| e.code),
+            Err(Code::InvalidArgument),
+            "Expected get_part to fail for length {length:?}"
+        );
+    }
+
+    // Zero digests must return empty data without being stored.
+    let zero_digest = DigestInfo::new(Sha256::new().finalize().into(), 0);
+    assert_eq!(
+        store.get_part_unchunked(zero_digest, 0, None).await,
+        Ok(Bytes::new()),
+    );
+
+    // Missing keys must be reported as NotFound.
+    let missing_digest = DigestInfo::try_new(VALID_HASH2, VALUE.len())?;
+    assert_eq!(
+        store
+            .get_part_unchunked(missing_digest, 0, None)
+            .await
+            .map_err(|e| e.code),
+        Err(Code::NotFound),
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn errors_with_invalid_inputs() -> Result<(), Error> {
     const VALUE1: &str = "123";
@@ -238,6 +341,26 @@ async fn errors_with_invalid_inputs() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn insert_and_get_64_byte_hash() -> Result<(), Error> {
+    const SHA512_HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef0123456789abcdef000000000000000000010000000000000123456789abcdef";
+    const VALUE1: &str = "13";
+    let store = MemoryStore::new(&MemorySpec::default());
+    let digest = DigestInfo::try_new(SHA512_HASH, VALUE1.len())?;
+
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE1.len() as u64)));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+
+    // The 256 bit prefix of the hash is a different key.
+    let prefix_digest = DigestInfo::try_new(&SHA512_HASH[..64], VALUE1.len())?;
+    assert_eq!(store.has(prefix_digest).await, Ok(None));
+    Ok(())
+}
+
 #[nativelink_test]
 async fn get_part_is_zero_digest() -> Result<(), Error> {
     let digest = DigestInfo::new(Sha256::new().finalize().into(), 0);
@@ -348,3 +471,158 @@ async fn list_test() -> Result<(), Error> {
 
     Ok(())
 }
+
+fn make_temp_path(data: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+        data
+    )
+}
+
+#[nativelink_test]
+async fn blobs_over_spill_threshold_are_served_from_spill_store() -> Result<(), Error> {
+    const SMALL_VALUE: &str = "123";
+    const LARGE_VALUE: &str = "0123456789abcdef";
+
+    let spill_store = <FilesystemStore>::new(&FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: make_temp_path("temp_path"),
+        ..Default::default()
+    })
+    .await?;
+    let store = MemoryStore::new_with_spill_store(
+        &MemorySpec {
+            spill_threshold: 10,
+            ..Default::default()
+        },
+        Some(Store::new(spill_store.clone())),
+    );
+
+    let small_digest = DigestInfo::try_new(VALID_HASH1, SMALL_VALUE.len() as u64)?;
+    let large_digest = DigestInfo::try_new(VALID_HASH2, LARGE_VALUE.len() as u64)?;
+    store
+        .update_oneshot(small_digest, SMALL_VALUE.into())
+        .await?;
+    store
+        .update_oneshot(large_digest, LARGE_VALUE.into())
+        .await?;
+
+    // Only the small blob is held in memory.
+    assert_eq!(store.len_for_test().await, 1);
+    assert_eq!(spill_store.has(small_digest).await?, None);
+    // The filesystem store reports the size the file takes on disk.
+    assert!(spill_store.has(large_digest).await?.is_some());
+
+    // Where a blob lives is transparent to readers.
+    assert_eq!(
+        store
+            .has_many(&[small_digest.into(), large_digest.into()])
+            .await?,
+        vec![
+            Some(SMALL_VALUE.len() as u64),
+            Some(LARGE_VALUE.len() as u64)
+        ]
+    );
+    assert_eq!(
+        store.get_part_unchunked(small_digest, 0, None).await?,
+        SMALL_VALUE.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(large_digest, 2, Some(4)).await?,
+        &LARGE_VALUE.as_bytes()[2..6]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn remove_deletes_entry() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let store = MemoryStore::new(&MemorySpec::default());
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    assert_eq!(store.remove(digest).await, Ok(true));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .map_err(|e| e.code),
+        Err(Code::NotFound)
+    );
+    // Removing an entry that does not exist is not an error.
+    assert_eq!(store.remove(digest).await, Ok(false));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn remove_prefix_deletes_only_matching_entries() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let store = MemoryStore::new(&MemorySpec::default());
+    let digest1 = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let digest1_short = DigestInfo::try_new(VALID_HASH1, 2)?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, VALUE.len())?;
+    store.update_oneshot(digest1, VALUE.into()).await?;
+    store.update_oneshot(digest1_short, "12".into()).await?;
+    store.update_oneshot(digest2, VALUE.into()).await?;
+
+    // The prefix covers VALID_HASH1 up to its first differing character.
+    assert_eq!(store.remove_prefix(&VALID_HASH1[..36]).await, Ok(2));
+    assert_eq!(store.has(digest1).await, Ok(None));
+    assert_eq!(store.has(digest1_short).await, Ok(None));
+    assert_eq!(store.has(digest2).await, Ok(Some(VALUE.len() as u64)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn touch_protects_entry_from_eviction() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let store = MemoryStore::new(&MemorySpec {
+        eviction_policy: Some(EvictionPolicy {
+            max_count: 3,
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    let digest1 = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let digest2 = DigestInfo::try_new(VALID_HASH2, VALUE.len())?;
+    let digest3 = DigestInfo::try_new(VALID_HASH3, VALUE.len())?;
+    let digest4 = DigestInfo::try_new(VALID_HASH4, VALUE.len())?;
+    store.update_oneshot(digest1, VALUE.into()).await?;
+    store.update_oneshot(digest2, VALUE.into()).await?;
+    store.update_oneshot(digest3, VALUE.into()).await?;
+
+    // Touching moves `digest1` from least to most recently used. Keys that
+    // are not in the store are not counted.
+    assert_eq!(
+        store
+            .touch(&[digest1.into(), DigestInfo::try_new(VALID_HASH4, 1)?.into()])
+            .await,
+        Ok(1)
+    );
+    store.update_oneshot(digest4, VALUE.into()).await?;
+
+    assert_eq!(store.has(digest1).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(store.has(digest2).await, Ok(None));
+    assert_eq!(store.has(digest3).await, Ok(Some(VALUE.len() as u64)));
+    assert_eq!(store.has(digest4).await, Ok(Some(VALUE.len() as u64)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_at_non_zero_offset_is_unimplemented() -> Result<(), Error> {
+    let store = MemoryStore::new(&MemorySpec::default());
+    let digest = DigestInfo::try_new(VALID_HASH1, 3)?;
+    let (_tx, rx) = make_buf_channel_pair();
+    assert_eq!(
+        store
+            .update_at_offset(digest, rx, 1, UploadSizeInfo::ExactSize(2))
+            .await
+            .map_err(|e| e.code),
+        Err(Code::Unimplemented)
+    );
+    assert_eq!(store.has(digest).await, Ok(None));
+    Ok(())
+}
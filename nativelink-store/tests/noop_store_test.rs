@@ -0,0 +1,99 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::env;
+use std::ffi::OsString;
+
+use nativelink_error::{Code, Error, ResultExt};
+use nativelink_macro::nativelink_test;
+use nativelink_store::noop_store::NoopStore;
+use nativelink_util::common::{fs, DigestInfo};
+use nativelink_util::store_trait::{StoreLike, StoreOptimizations, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+use rand::{thread_rng, Rng};
+use tokio::io::AsyncWriteExt;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+
+/// Get temporary path from either `TEST_TMPDIR` or best effort temp directory if
+/// not set.
+async fn make_temp_path(data: &str) -> OsString {
+    let dir = format!(
+        "{}/{}",
+        env::var("TEST_TMPDIR").unwrap_or(env::temp_dir().to_str().unwrap().to_string()),
+        thread_rng().gen::<u64>(),
+    );
+    fs::create_dir_all(&dir).await.unwrap();
+    OsString::from(format!("{dir}/{data}"))
+}
+
+#[nativelink_test]
+async fn noop_store_advertises_noop_optimizations() -> Result<(), Error> {
+    let store = NoopStore::new();
+    assert!(store.optimized_for(StoreOptimizations::NoopUpdates));
+    assert!(store.optimized_for(StoreOptimizations::NoopDownloads));
+    assert!(!store.optimized_for(StoreOptimizations::FileUpdates));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn noop_store_discards_updates() -> Result<(), Error> {
+    const VALUE: &str = "123";
+    let store = NoopStore::new();
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+
+    store.update_oneshot(digest, VALUE.into()).await?;
+
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .map_err(|e| e.code),
+        Err(Code::NotFound)
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_with_whole_file_skips_reading_file() -> Result<(), Error> {
+    const VALUE: &str = "some file contents";
+    let filepath = make_temp_path("test.txt").await;
+    {
+        let mut file = tokio::fs::File::create(&filepath)
+            .await
+            .err_tip(|| "Could not open file")?;
+        file.write_all(VALUE.as_bytes())
+            .await
+            .err_tip(|| "Could not write to file")?;
+        file.sync_all().await.err_tip(|| "Could not sync file")?;
+    }
+
+    let store = NoopStore::new();
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE.len())?;
+    let resumeable_file = fs::open_file(filepath, u64::MAX).await?;
+    let mut resumeable_file = store
+        .update_with_whole_file(
+            digest,
+            resumeable_file,
+            UploadSizeInfo::ExactSize(VALUE.len() as u64),
+        )
+        .await?
+        .err_tip(|| "Expected file to be handed back by NoopStore")?;
+
+    // The file must be untouched, since the noop optimization short-circuits
+    // before any data is read.
+    assert_eq!(resumeable_file.stream_position().await?, 0);
+    Ok(())
+}
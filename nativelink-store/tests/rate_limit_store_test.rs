@@ -0,0 +1,101 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use nativelink_config::stores::{MemorySpec, RateLimitSpec, StoreSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::rate_limit_store::RateLimitStore;
+use nativelink_util::common::DigestInfo;
+use nativelink_util::store_trait::{Store, StoreLike};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+
+fn make_rate_limit_store(
+    requests_per_second: u64,
+    max_burst: u64,
+    max_wait_ms: u64,
+) -> Result<Store, Error> {
+    Ok(Store::new(RateLimitStore::new(
+        &RateLimitSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            requests_per_second,
+            max_burst,
+            max_wait_ms,
+        },
+        Store::new(MemoryStore::new(&MemorySpec::default())),
+    )?))
+}
+
+#[nativelink_test]
+async fn calls_over_the_limit_wait_for_their_turn() -> Result<(), Error> {
+    const REQUESTS_PER_SECOND: u64 = 20;
+    let store = make_rate_limit_store(REQUESTS_PER_SECOND, 1, 10_000)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    let start = Instant::now();
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    for _ in 0..4 {
+        assert_eq!(store.has(digest).await, Ok(Some(VALUE1.len() as u64)));
+    }
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(VALUE1.into())
+    );
+
+    // The burst covers the first call, every other call waits for a token.
+    let min_elapsed = Duration::from_secs(5) / u32::try_from(REQUESTS_PER_SECOND).unwrap();
+    assert!(
+        start.elapsed() >= min_elapsed,
+        "Expected calls to take at least {min_elapsed:?}, took {:?}",
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn calls_over_the_limit_fail_without_wait() -> Result<(), Error> {
+    let store = make_rate_limit_store(1, 2, 0)?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE1.len() as u64)));
+    assert_eq!(
+        store.has(digest).await.map_err(|e| e.code),
+        Err(Code::ResourceExhausted)
+    );
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .map_err(|e| e.code),
+        Err(Code::ResourceExhausted)
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn zero_requests_per_second_is_rejected() -> Result<(), Error> {
+    assert_eq!(
+        make_rate_limit_store(0, 0, 0)
+            .map(|_| ())
+            .map_err(|e| e.code),
+        Err(Code::InvalidArgument)
+    );
+    Ok(())
+}
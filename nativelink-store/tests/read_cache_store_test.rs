@@ -0,0 +1,192 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use nativelink_config::stores::{MemorySpec, ReadCacheSpec, StoreSpec};
+use nativelink_error::Error;
+use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::read_cache_store::ReadCacheStore;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+
+/// Store that counts the reads that reach it.
+#[derive(MetricsComponent)]
+struct CountingStore {
+    inner: Store,
+    get_part_calls: AtomicUsize,
+}
+
+#[async_trait]
+impl StoreDriver for CountingStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner.has_with_results(keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.get_part_calls.fetch_add(1, Ordering::AcqRel);
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(CountingStore);
+
+fn make_stores(max_size: u64) -> (Arc<CountingStore>, Store) {
+    let counting_store = Arc::new(CountingStore {
+        inner: Store::new(MemoryStore::new(&MemorySpec::default())),
+        get_part_calls: AtomicUsize::new(0),
+    });
+    let read_cache_store = Store::new(ReadCacheStore::new(
+        &ReadCacheSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            eviction_policy: None,
+            max_size,
+        },
+        Store::new(counting_store.clone()),
+    ));
+    (counting_store, read_cache_store)
+}
+
+#[nativelink_test]
+async fn repeated_reads_are_served_from_cache() -> Result<(), Error> {
+    let (counting_store, store) = make_stores(0);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    counting_store
+        .inner
+        .update_oneshot(digest, VALUE1.into())
+        .await?;
+
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    // Ranges are sliced out of the cached blob.
+    assert_eq!(
+        store.get_part_unchunked(digest, 2, Some(3)).await?,
+        &VALUE1.as_bytes()[2..5]
+    );
+    assert_eq!(counting_store.get_part_calls.load(Ordering::Acquire), 1);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn writes_pass_through_without_populating_cache() -> Result<(), Error> {
+    let (counting_store, store) = make_stores(0);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(
+        counting_store
+            .inner
+            .get_part_unchunked(digest, 0, None)
+            .await?,
+        VALUE1.as_bytes()
+    );
+
+    // The first read after the write must still go to the inner store.
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    assert_eq!(counting_store.get_part_calls.load(Ordering::Acquire), 1);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn blobs_larger_than_max_size_are_not_cached() -> Result<(), Error> {
+    let (counting_store, store) = make_stores(5);
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    counting_store
+        .inner
+        .update_oneshot(digest, VALUE1.into())
+        .await?;
+
+    for _ in 0..2 {
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await?,
+            VALUE1.as_bytes()
+        );
+    }
+    assert_eq!(counting_store.get_part_calls.load(Ordering::Acquire), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn large_blobs_are_streamed_by_default() -> Result<(), Error> {
+    // Same as in read_cache_store.
+    const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+    let (counting_store, store) = make_stores(0);
+    let value = vec![7u8; DEFAULT_MAX_SIZE + 1];
+    let digest = DigestInfo::try_new(VALID_HASH1, value.len())?;
+    counting_store
+        .inner
+        .update_oneshot(digest, value.clone().into())
+        .await?;
+
+    for _ in 0..2 {
+        assert_eq!(
+            store.get_part_unchunked(digest, 1, Some(3)).await?,
+            value[1..4]
+        );
+    }
+    assert_eq!(counting_store.get_part_calls.load(Ordering::Acquire), 2);
+    Ok(())
+}
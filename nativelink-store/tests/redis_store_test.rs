@@ -923,3 +923,69 @@ struct RootMetricsTest {
 }
 
 impl RootMetricsComponent for RootMetricsTest {}
+
+#[nativelink_test]
+async fn remove_deletes_key() -> Result<(), Error> {
+    let digest = DigestInfo::try_new(VALID_HASH1, 2)?;
+    let real_key = RedisValue::Bytes(format!("{digest}").into());
+
+    let mocks = Arc::new(MockRedisBackend::new());
+    mocks
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("DEL"),
+                subcommand: None,
+                args: vec![real_key.clone()],
+            },
+            Ok(RedisValue::Integer(1)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("STRLEN"),
+                subcommand: None,
+                args: vec![real_key.clone()],
+            },
+            Ok(RedisValue::Integer(0)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("EXISTS"),
+                subcommand: None,
+                args: vec![real_key.clone()],
+            },
+            Ok(RedisValue::Integer(0)),
+        )
+        .expect(
+            MockCommand {
+                cmd: Str::from_static("DEL"),
+                subcommand: None,
+                args: vec![real_key],
+            },
+            Ok(RedisValue::Integer(0)),
+        );
+
+    let store = {
+        let mut builder = Builder::default_centralized();
+        builder.set_config(RedisConfig {
+            mocks: Some(Arc::clone(&mocks) as Arc<dyn Mocks>),
+            ..Default::default()
+        });
+
+        let (client_pool, subscriber_client) = make_clients(builder);
+        RedisStore::new_from_builder_and_parts(
+            client_pool,
+            subscriber_client,
+            None,
+            mock_uuid_generator,
+            String::new(),
+            DEFAULT_READ_CHUNK_SIZE,
+            DEFAULT_MAX_CHUNK_UPLOADS_PER_UPDATE,
+        )
+        .unwrap()
+    };
+
+    assert_eq!(store.remove(digest).await, Ok(true));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(store.remove(digest).await, Ok(false));
+    Ok(())
+}
@@ -135,6 +135,30 @@ async fn update_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn remove_prefix_test() -> Result<(), Error> {
+    const VALUE1: &str = "13";
+    const OTHER_HASH: &str = "fedcba9876543210000000000000000000010000000000000123456789abcdef";
+
+    let (_store_manager, memory_store, ref_store) = setup_stores();
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    let other_digest = DigestInfo::try_new(OTHER_HASH, VALUE1.len())?;
+    memory_store.update_oneshot(digest, VALUE1.into()).await?;
+    memory_store
+        .update_oneshot(other_digest, VALUE1.into())
+        .await?;
+
+    // Removing through the ref_store removes only the matching entry from memory_store.
+    assert_eq!(ref_store.remove_prefix(&VALID_HASH1[..8]).await?, 1);
+    assert_eq!(memory_store.has(digest).await?, None);
+    assert_eq!(
+        memory_store.has(other_digest).await?,
+        Some(VALUE1.len() as u64)
+    );
+    Ok(())
+}
+
 #[nativelink_test]
 async fn inner_store_test() -> Result<(), Error> {
     let store_manager = Arc::new(StoreManager::new());
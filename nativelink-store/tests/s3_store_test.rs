@@ -12,26 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use aws_sdk_s3::config::{BehaviorVersion, Builder, Region};
+use aws_credential_types::provider::future::ProvideCredentials as ProvideCredentialsFuture;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sdk_s3::config::{
+    BehaviorVersion, Builder, Credentials, Region, SharedCredentialsProvider,
+};
 use aws_sdk_s3::primitives::ByteStream;
 use aws_smithy_runtime::client::http::test_util::{ReplayEvent, StaticReplayClient};
 use aws_smithy_types::body::SdkBody;
 use bytes::{BufMut, Bytes, BytesMut};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures::join;
 use futures::task::Poll;
 use http::header;
 use http::status::StatusCode;
 use hyper::Body;
 use mock_instant::thread_local::MockClock;
-use nativelink_config::stores::S3Spec;
+use nativelink_config::stores::{S3ContentEncoding, S3ServerSideEncryption, S3Spec};
 use nativelink_error::{make_input_err, Error, ResultExt};
 use nativelink_macro::nativelink_test;
-use nativelink_store::s3_store::S3Store;
+use nativelink_store::s3_store::{
+    detect_bucket_region, with_refreshing_credentials, S3RequestLimiter, S3Store,
+};
 use nativelink_util::buf_channel::make_buf_channel_pair;
 use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{HealthStatus, HealthStatusIndicator};
 use nativelink_util::instant_wrapper::MockInstantWrapped;
 use nativelink_util::spawn;
 use nativelink_util::store_trait::{StoreLike, UploadSizeInfo};
@@ -547,6 +559,183 @@ async fn multipart_update_large_cas() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn update_below_configured_part_size_uses_single_put() -> Result<(), Error> {
+    const MIN_MULTIPART_SIZE: usize = 5 * 1024 * 1024; // 5mb.
+
+    // Larger than the default part size, but smaller than the configured one.
+    const AC_ENTRY_SIZE: usize = MIN_MULTIPART_SIZE + 50;
+
+    let mut send_data = BytesMut::new();
+    for i in 0..AC_ENTRY_SIZE {
+        send_data.put_u8(((i * 3) % 256) as u8);
+    }
+    let send_data = send_data.freeze();
+
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(Some(
+            aws_smithy_runtime_api::http::Response::new(StatusCode::OK.into(), SdkBody::empty())
+                .try_into_http02x()
+                .unwrap(),
+        ));
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            multipart_part_size: Some(MIN_MULTIPART_SIZE as u64 * 2),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+    let (mut tx, rx) = make_buf_channel_pair();
+    let mut update_fut = Box::pin(async move {
+        store
+            .update(
+                DigestInfo::try_new(VALID_HASH1, AC_ENTRY_SIZE)?,
+                rx,
+                UploadSizeInfo::ExactSize(AC_ENTRY_SIZE as u64),
+            )
+            .await
+    });
+
+    // A single `PutObject` request is sent instead of a multipart upload.
+    let body_stream = {
+        assert_eq!(Poll::Pending, futures::poll!(&mut update_fut));
+        let sent_request = request_receiver.expect_request();
+        assert_eq!(sent_request.method(), "PUT");
+        assert_eq!(sent_request.uri(), format!("https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{AC_ENTRY_SIZE}?x-id=PutObject"));
+        ByteStream::from_body_0_4(sent_request.into_body())
+    };
+
+    let send_data_copy = send_data.clone();
+    let spawn_fut = spawn!("update_below_configured_part_size", async move {
+        tokio::try_join!(update_fut, async move {
+            tx.send(send_data_copy).await?;
+            tx.send_eof()
+        })
+    });
+
+    let data_sent_to_s3 = body_stream
+        .collect()
+        .await
+        .map_err(|e| make_input_err!("{e:?}"))?;
+    assert_eq!(
+        send_data,
+        data_sent_to_s3.into_bytes(),
+        "Expected data to match"
+    );
+    spawn_fut
+        .await
+        .err_tip(|| "Failed to launch spawn")?
+        .err_tip(|| "In spawn")?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn multipart_update_uses_configured_part_size() -> Result<(), Error> {
+    const MIN_MULTIPART_SIZE: usize = 5 * 1024 * 1024; // 5mb.
+    const PART_SIZE: usize = MIN_MULTIPART_SIZE + 1024;
+    const AC_ENTRY_SIZE: usize = PART_SIZE * 2 + 50;
+
+    let mut send_data = Vec::with_capacity(AC_ENTRY_SIZE);
+    for i in 0..send_data.capacity() {
+        send_data.push(((i * 3) % 256) as u8);
+    }
+    let digest = DigestInfo::try_new(VALID_HASH1, send_data.len())?;
+    let upload_part = |part_number: usize, range: std::ops::Range<usize>| {
+        ReplayEvent::new(
+            http::Request::builder()
+                .uri(format!(
+                    "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{AC_ENTRY_SIZE}?x-id=UploadPart&partNumber={part_number}&uploadId=Dummy-uploadid",
+                ))
+                .method("PUT")
+                .header("content-type", "application/octet-stream")
+                .header("content-length", range.len().to_string())
+                .body(SdkBody::from(&send_data[range]))
+                .unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::empty())
+                .unwrap(),
+        )
+    };
+
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder()
+                .uri(format!(
+                    "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{AC_ENTRY_SIZE}?uploads",
+                ))
+                .method("POST")
+                .body(SdkBody::empty())
+                .unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(
+                    r#"
+                    <InitiateMultipartUploadResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+                      <UploadId>Dummy-uploadid</UploadId>
+                    </InitiateMultipartUploadResult>"#
+                        .as_bytes(),
+                ))
+                .unwrap(),
+        ),
+        upload_part(1, 0..PART_SIZE),
+        upload_part(2, PART_SIZE..PART_SIZE * 2),
+        upload_part(3, PART_SIZE * 2..AC_ENTRY_SIZE),
+        ReplayEvent::new(
+            http::Request::builder()
+                .uri(format!(
+                    "https://{BUCKET_NAME}.s3.{REGION}.amazonaws.com/{VALID_HASH1}-{AC_ENTRY_SIZE}?uploadId=Dummy-uploadid",
+                ))
+                .method("POST")
+                .header("content-length", "216")
+                .body(SdkBody::from(concat!(
+                    r#"<CompleteMultipartUpload xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+                    "<Part><PartNumber>1</PartNumber></Part>",
+                    "<Part><PartNumber>2</PartNumber></Part>",
+                    "<Part><PartNumber>3</PartNumber></Part>",
+                    "</CompleteMultipartUpload>",
+                )))
+                .unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(concat!(
+                    "<CompleteMultipartUploadResult>",
+                    "</CompleteMultipartUploadResult>",
+                )))
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            multipart_part_size: Some(PART_SIZE as u64),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+    store
+        .update_oneshot(digest, send_data.clone().into())
+        .await?;
+    mock_client.assert_requests_match(&[]);
+    Ok(())
+}
+
 #[nativelink_test]
 async fn ensure_empty_string_in_stream_works_test() -> Result<(), Error> {
     const CAS_ENTRY_SIZE: usize = 10; // Length of "helloworld".
@@ -735,3 +924,718 @@ async fn has_with_expired_result() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn detect_bucket_region_from_head_bucket() -> Result<(), Error> {
+    const BUCKET_REGION: &str = "eu-west-1";
+
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .header("x-amz-bucket-region", BUCKET_REGION)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        // S3 redirects requests sent to the wrong region, but still
+        // reports the region of the bucket.
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header("x-amz-bucket-region", BUCKET_REGION)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+
+    assert_eq!(
+        detect_bucket_region(&s3_client, BUCKET_NAME).await?,
+        Region::from_static(BUCKET_REGION)
+    );
+    assert_eq!(
+        detect_bucket_region(&s3_client, BUCKET_NAME).await?,
+        Region::from_static(BUCKET_REGION)
+    );
+    // Without the header there is nothing to go on.
+    assert!(detect_bucket_region(&s3_client, BUCKET_NAME).await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn expired_credentials_are_refreshed() -> Result<(), Error> {
+    /// Hands out a new set of credentials on every call, each of which is
+    /// already expired.
+    #[derive(Debug)]
+    struct ExpiringCredentialsProvider {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl ProvideCredentials for ExpiringCredentialsProvider {
+        fn provide_credentials<'a>(&'a self) -> ProvideCredentialsFuture<'a>
+        where
+            Self: 'a,
+        {
+            let call = self.calls.fetch_add(1, Ordering::AcqRel);
+            ProvideCredentialsFuture::ready(Ok(Credentials::new(
+                format!("access_key_{call}"),
+                "secret_key",
+                None,
+                Some(SystemTime::now()),
+                "test",
+            )))
+        }
+    }
+
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .header(header::CONTENT_LENGTH, "512")
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .header(header::CONTENT_LENGTH, "512")
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+    ]);
+    let provider_calls = Arc::new(AtomicUsize::new(0));
+    let test_config = with_refreshing_credentials(
+        Builder::new()
+            .behavior_version(BehaviorVersion::v2024_03_28())
+            .region(Region::from_static(REGION))
+            .http_client(mock_client.clone()),
+        SharedCredentialsProvider::new(ExpiringCredentialsProvider {
+            calls: provider_calls.clone(),
+        }),
+    )
+    .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, 100)?;
+    // The second request finds the cached credentials expired and fetches
+    // new ones instead of failing.
+    assert_eq!(store.has(digest).await, Ok(Some(512)));
+    assert_eq!(store.has(digest).await, Ok(Some(512)));
+    assert_eq!(provider_calls.load(Ordering::Acquire), 2);
+
+    let access_keys: Vec<bool> = mock_client
+        .actual_requests()
+        .zip(["access_key_0", "access_key_1"])
+        .map(|(request, access_key)| {
+            request
+                .headers()
+                .get("authorization")
+                .is_some_and(|authorization| authorization.contains(access_key))
+        })
+        .collect();
+    assert_eq!(access_keys, vec![true, true]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_sets_encryption_and_storage_class_headers() -> Result<(), Error> {
+    const KMS_KEY_ID: &str = "arn:aws:kms:testregion:123456789012:key/test-key";
+    const CONTENT: &str = "some content";
+
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(Some(
+            aws_smithy_runtime_api::http::Response::new(StatusCode::OK.into(), SdkBody::empty())
+                .try_into_http02x()
+                .unwrap(),
+        ));
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            sse: Some(S3ServerSideEncryption::aws_kms),
+            kms_key_id: Some(KMS_KEY_ID.to_string()),
+            storage_class: Some("INTELLIGENT_TIERING".to_string()),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, CONTENT.len())?;
+    let (update_result, sent_request) =
+        join!(store.update_oneshot(digest, CONTENT.into()), async {
+            request_receiver.expect_request()
+        },);
+    update_result?;
+    assert_eq!(sent_request.method(), "PUT");
+    let headers = sent_request.headers();
+    assert_eq!(headers.get("x-amz-server-side-encryption"), Some("aws:kms"));
+    assert_eq!(
+        headers.get("x-amz-server-side-encryption-aws-kms-key-id"),
+        Some(KMS_KEY_ID)
+    );
+    assert_eq!(
+        headers.get("x-amz-storage-class"),
+        Some("INTELLIGENT_TIERING")
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn update_without_encryption_config_sends_no_headers() -> Result<(), Error> {
+    const CONTENT: &str = "some content";
+
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(Some(
+            aws_smithy_runtime_api::http::Response::new(StatusCode::OK.into(), SdkBody::empty())
+                .try_into_http02x()
+                .unwrap(),
+        ));
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, CONTENT.len())?;
+    let (update_result, sent_request) =
+        join!(store.update_oneshot(digest, CONTENT.into()), async {
+            request_receiver.expect_request()
+        },);
+    update_result?;
+    let headers = sent_request.headers();
+    assert_eq!(headers.get("x-amz-server-side-encryption"), None);
+    assert_eq!(
+        headers.get("x-amz-server-side-encryption-aws-kms-key-id"),
+        None
+    );
+    assert_eq!(headers.get("x-amz-storage-class"), None);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn invalid_encryption_and_storage_class_config_is_rejected() -> Result<(), Error> {
+    let make_store = |spec: &S3Spec| {
+        let test_config = Builder::new()
+            .behavior_version(BehaviorVersion::v2024_03_28())
+            .region(Region::from_static(REGION))
+            .http_client(StaticReplayClient::new(vec![]))
+            .build();
+        S3Store::new_with_client_and_jitter(
+            spec,
+            aws_sdk_s3::Client::from_conf(test_config),
+            Arc::new(move |_delay| Duration::from_secs(0)),
+            MockInstantWrapped::default,
+        )
+    };
+
+    // A KMS key only makes sense with KMS encryption.
+    assert!(make_store(&S3Spec {
+        bucket: BUCKET_NAME.to_string(),
+        sse: Some(S3ServerSideEncryption::aes256),
+        kms_key_id: Some("some-key".to_string()),
+        ..Default::default()
+    })
+    .is_err());
+    assert!(make_store(&S3Spec {
+        bucket: BUCKET_NAME.to_string(),
+        storage_class: Some("NOT_A_STORAGE_CLASS".to_string()),
+        ..Default::default()
+    })
+    .is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn health_check_uses_head_bucket() -> Result<(), Error> {
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let status = HealthStatusIndicator::check_health(store.as_ref(), "".into()).await;
+    assert!(
+        matches!(status, HealthStatus::Ok { .. }),
+        "Expected healthy store, got {status:?}"
+    );
+    let status = HealthStatusIndicator::check_health(store.as_ref(), "".into()).await;
+    assert!(
+        matches!(status, HealthStatus::Failed { .. }),
+        "Expected failed health status, got {status:?}"
+    );
+
+    // Only HEAD requests were made, nothing was written to the bucket.
+    let requests = mock_client.actual_requests().collect::<Vec<_>>();
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        assert_eq!(request.method(), "HEAD");
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn remove_deletes_object() -> Result<(), Error> {
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .header(header::CONTENT_LENGTH, "512")
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let s3_client = aws_sdk_s3::Client::from_conf(test_config);
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            ..Default::default()
+        },
+        s3_client,
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, 512)?;
+    assert_eq!(store.remove(digest).await, Ok(true));
+    assert_eq!(store.has(digest).await, Ok(None));
+    assert_eq!(
+        mock_client
+            .actual_requests()
+            .map(|request| request.method().to_string())
+            .collect::<Vec<_>>(),
+        vec!["HEAD", "DELETE", "HEAD"]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn gzip_content_encoding_round_trip() -> Result<(), Error> {
+    const CONTENT: &str = "line one of a build log\nline two of a build log\n";
+
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(Some(
+            aws_smithy_runtime_api::http::Response::new(StatusCode::OK.into(), SdkBody::empty())
+                .try_into_http02x()
+                .unwrap(),
+        ));
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, CONTENT.len())?;
+    let (update_result, sent_request) =
+        join!(store.update_oneshot(digest, CONTENT.into()), async {
+            request_receiver.expect_request()
+        },);
+    update_result?;
+    assert_eq!(sent_request.method(), "PUT");
+    let headers = sent_request.headers();
+    assert_eq!(headers.get(header::CONTENT_ENCODING), Some("gzip"));
+    assert_eq!(
+        headers.get("x-amz-meta-nativelink-uncompressed-size"),
+        Some(CONTENT.len().to_string().as_str())
+    );
+    let compressed = ByteStream::from_body_0_4(sent_request.into_body())
+        .collect()
+        .await
+        .map_err(|e| make_input_err!("{e:?}"))?
+        .into_bytes();
+    let mut decoder = GzDecoder::new(&compressed[..]);
+    let mut uploaded = String::new();
+    decoder.read_to_string(&mut uploaded)?;
+    assert_eq!(uploaded, CONTENT);
+
+    // Serve the uploaded object back for a full read and a ranged read, which
+    // reads the object again from the start once it is known to be gzipped.
+    let get_event = || {
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(SdkBody::from(compressed.clone()))
+                .unwrap(),
+        )
+    };
+    let mock_client = StaticReplayClient::new(vec![get_event(), get_event(), get_event()]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        CONTENT.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest, 5, Some(3)).await?,
+        CONTENT[5..8].as_bytes()
+    );
+    let ranges: Vec<Option<String>> = mock_client
+        .actual_requests()
+        .map(|request| request.headers().get(header::RANGE).map(str::to_string))
+        .collect();
+    assert_eq!(
+        ranges,
+        vec![
+            Some("bytes=0-".to_string()),
+            Some("bytes=5-8".to_string()),
+            None
+        ]
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn gzip_content_encoding_reads_ranges_past_compressed_size() -> Result<(), Error> {
+    const CONTENT: &str =
+        "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaab";
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(CONTENT.as_bytes())?;
+    let compressed = encoder.finish()?;
+    assert!(compressed.len() < CONTENT.len() - 1);
+
+    // S3 rejects a range starting past the end of the compressed object, so
+    // it is read from the start instead.
+    let mock_client = StaticReplayClient::new(vec![
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .body(SdkBody::empty())
+                .unwrap(),
+        ),
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(SdkBody::from(compressed))
+                .unwrap(),
+        ),
+    ]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, CONTENT.len())?;
+    let offset = CONTENT.len() - 1;
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, offset as u64, None)
+            .await?,
+        CONTENT[offset..].as_bytes()
+    );
+    let ranges: Vec<Option<String>> = mock_client
+        .actual_requests()
+        .map(|request| request.headers().get(header::RANGE).map(str::to_string))
+        .collect();
+    assert_eq!(ranges, vec![Some(format!("bytes={offset}-")), None]);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn gzip_content_encoding_streams_uploads_above_min_part_size() -> Result<(), Error> {
+    const MIN_MULTIPART_SIZE: u64 = 5 * 1024 * 1024; // 5mb.
+
+    // Smaller than the configured part size, but too large to be buffered
+    // for compression.
+    const CONTENT_SIZE: u64 = MIN_MULTIPART_SIZE + 50;
+
+    let (mock_client, request_receiver) =
+        aws_smithy_runtime::client::http::test_util::capture_request(None);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            multipart_part_size: Some(MIN_MULTIPART_SIZE * 2),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    // No data is sent, a buffered upload would wait for all of it before
+    // sending any request.
+    let (_tx, rx) = make_buf_channel_pair();
+    let mut update_fut = Box::pin(store.update(
+        DigestInfo::try_new(VALID_HASH1, CONTENT_SIZE)?,
+        rx,
+        UploadSizeInfo::ExactSize(CONTENT_SIZE),
+    ));
+    assert_eq!(Poll::Pending, futures::poll!(&mut update_fut));
+    let sent_request = request_receiver.expect_request();
+    assert_eq!(sent_request.method(), "POST");
+    assert!(sent_request.uri().contains("?uploads"));
+    assert_eq!(
+        sent_request.headers().get(header::CONTENT_ENCODING),
+        Some("gzip")
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn gzip_content_encoding_has_reports_uncompressed_size() -> Result<(), Error> {
+    let mock_client = StaticReplayClient::new(vec![ReplayEvent::new(
+        http::Request::builder().body(SdkBody::empty()).unwrap(),
+        http::Response::builder()
+            .header(header::CONTENT_LENGTH, "40")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .header("x-amz-meta-nativelink-uncompressed-size", "512")
+            .body(SdkBody::empty())
+            .unwrap(),
+    )]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client)
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, 512)?;
+    assert_eq!(store.has(digest).await, Ok(Some(512)));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn gzip_content_encoding_reads_uncompressed_objects_as_is() -> Result<(), Error> {
+    const CONTENT: &str = "stored before compression was enabled";
+
+    // Objects without a `Content-Encoding` header must not be decompressed,
+    // and ranges of them are read from S3 directly.
+    let get_event = |data: &'static str| {
+        ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .status(StatusCode::OK)
+                .body(SdkBody::from(data))
+                .unwrap(),
+        )
+    };
+    let mock_client = StaticReplayClient::new(vec![get_event(CONTENT), get_event(&CONTENT[7..13])]);
+    let test_config = Builder::new()
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .region(Region::from_static(REGION))
+        .http_client(mock_client.clone())
+        .build();
+    let store = S3Store::new_with_client_and_jitter(
+        &S3Spec {
+            bucket: BUCKET_NAME.to_string(),
+            content_encoding: Some(S3ContentEncoding::gzip),
+            ..Default::default()
+        },
+        aws_sdk_s3::Client::from_conf(test_config),
+        Arc::new(move |_delay| Duration::from_secs(0)),
+        MockInstantWrapped::default,
+    )?;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, CONTENT.len())?;
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        CONTENT.as_bytes()
+    );
+    assert_eq!(
+        store.get_part_unchunked(digest, 7, Some(6)).await?,
+        CONTENT[7..13].as_bytes()
+    );
+    assert_eq!(
+        mock_client
+            .actual_requests()
+            .last()
+            .and_then(|request| request.headers().get(header::RANGE)),
+        Some("bytes=7-13")
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn stores_sharing_request_limiter_block_when_saturated() -> Result<(), Error> {
+    type TestS3Store = S3Store<fn() -> MockInstantWrapped>;
+
+    fn make_store(request_limiter: Arc<S3RequestLimiter>) -> Result<Arc<TestS3Store>, Error> {
+        let mock_client = StaticReplayClient::new(vec![ReplayEvent::new(
+            http::Request::builder().body(SdkBody::empty()).unwrap(),
+            http::Response::builder()
+                .header(header::CONTENT_LENGTH, "512")
+                .body(SdkBody::empty())
+                .unwrap(),
+        )]);
+        let test_config = Builder::new()
+            .behavior_version(BehaviorVersion::v2024_03_28())
+            .region(Region::from_static(REGION))
+            .http_client(mock_client)
+            .build();
+        S3Store::new_with_request_limiter(
+            &S3Spec {
+                bucket: BUCKET_NAME.to_string(),
+                ..Default::default()
+            },
+            aws_sdk_s3::Client::from_conf(test_config),
+            Arc::new(move |_delay| Duration::from_secs(0)),
+            MockInstantWrapped::default,
+            request_limiter,
+        )
+    }
+
+    let request_limiter = Arc::new(S3RequestLimiter::new(1));
+    let store1 = make_store(request_limiter.clone())?;
+    let store2 = make_store(request_limiter.clone())?;
+    let digest = DigestInfo::try_new(VALID_HASH1, 100)?;
+
+    // Saturate the limiter so neither store can make a request.
+    let permit = request_limiter.acquire().await;
+    assert_eq!(request_limiter.in_use(), 1);
+
+    let mut has_fut1 = Box::pin(store1.has(digest));
+    let mut has_fut2 = Box::pin(store2.has(digest));
+    for _ in 0..10 {
+        assert_eq!(Poll::Pending, futures::poll!(&mut has_fut1));
+        assert_eq!(Poll::Pending, futures::poll!(&mut has_fut2));
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(request_limiter.in_use(), 1);
+
+    // Once the permit is released both stores make their requests, one
+    // after the other.
+    drop(permit);
+    let (result1, result2) = join!(has_fut1, has_fut2);
+    assert_eq!(result1, Ok(Some(512)));
+    assert_eq!(result2, Ok(Some(512)));
+    assert_eq!(request_limiter.in_use(), 0);
+    Ok(())
+}
@@ -0,0 +1,217 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use futures::join;
+use nativelink_config::stores::{MemorySpec, SingleflightSpec, StoreSpec};
+use nativelink_error::{make_err, Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::singleflight_store::SingleflightStore;
+use nativelink_util::buf_channel::{DropCloserReadHalf, DropCloserWriteHalf};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+const CONCURRENT_READS: usize = 10;
+
+/// Store that counts reads and holds each read until `gate` has a permit.
+#[derive(MetricsComponent)]
+struct GatedReadStore {
+    inner: Store,
+    gate: tokio::sync::Semaphore,
+    get_part_calls: AtomicUsize,
+}
+
+#[async_trait]
+impl StoreDriver for GatedReadStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        self.inner.has_with_results(keys, results).await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        self.inner.update(key, reader, size_info).await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<(), Error> {
+        self.get_part_calls.fetch_add(1, Ordering::AcqRel);
+        self.gate
+            .acquire()
+            .await
+            .map_err(|e| make_err!(Code::Internal, "{:?}", e))?
+            .forget();
+        self.inner.get_part(key, writer, offset, length).await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(GatedReadStore);
+
+async fn make_stores(max_size: u64) -> Result<(Arc<GatedReadStore>, Store), Error> {
+    let gated_store = Arc::new(GatedReadStore {
+        inner: Store::new(MemoryStore::new(&MemorySpec::default())),
+        gate: tokio::sync::Semaphore::new(0),
+        get_part_calls: AtomicUsize::new(0),
+    });
+    gated_store
+        .inner
+        .update_oneshot(
+            DigestInfo::try_new(VALID_HASH1, VALUE1.len())?,
+            VALUE1.into(),
+        )
+        .await?;
+    let singleflight_store = Store::new(SingleflightStore::new(
+        &SingleflightSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            max_size,
+        },
+        Store::new(gated_store.clone()),
+    ));
+    Ok((gated_store, singleflight_store))
+}
+
+#[nativelink_test]
+async fn concurrent_identical_reads_share_one_backend_read() -> Result<(), Error> {
+    let (gated_store, store) = make_stores(1024).await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    // All reads are polled before the backend is allowed to answer, so they
+    // are all in flight at the same time.
+    let (results, ()) = join!(
+        join_all((0..CONCURRENT_READS).map(|_| store.get_part_unchunked(digest, 0, None))),
+        async { gated_store.gate.add_permits(CONCURRENT_READS) },
+    );
+    for result in results {
+        assert_eq!(result?, VALUE1.as_bytes());
+    }
+    assert_eq!(gated_store.get_part_calls.load(Ordering::Acquire), 1);
+
+    // Once the shared read is done, a new read goes to the backend again.
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await?,
+        VALUE1.as_bytes()
+    );
+    assert_eq!(gated_store.get_part_calls.load(Ordering::Acquire), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn concurrent_reads_of_different_ranges_are_not_shared() -> Result<(), Error> {
+    let (gated_store, store) = make_stores(1024).await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    let (results, ()) = join!(
+        join_all([
+            store.get_part_unchunked(digest, 0, None),
+            store.get_part_unchunked(digest, 2, Some(3)),
+            store.get_part_unchunked(digest, 0, None),
+            store.get_part_unchunked(digest, 2, Some(3)),
+        ]),
+        async { gated_store.gate.add_permits(4) },
+    );
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        results,
+        vec![
+            VALUE1.as_bytes(),
+            &VALUE1.as_bytes()[2..5],
+            VALUE1.as_bytes(),
+            &VALUE1.as_bytes()[2..5],
+        ]
+    );
+    // One backend read per distinct range.
+    assert_eq!(gated_store.get_part_calls.load(Ordering::Acquire), 2);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn reads_larger_than_max_size_are_not_shared() -> Result<(), Error> {
+    let (gated_store, store) = make_stores(5).await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    let (results, ()) = join!(
+        join_all([
+            // Larger than `max_size`, so each read goes to the backend.
+            store.get_part_unchunked(digest, 0, None),
+            store.get_part_unchunked(digest, 0, None),
+            // Within `max_size`, so these are shared.
+            store.get_part_unchunked(digest, 5, None),
+            store.get_part_unchunked(digest, 5, None),
+        ]),
+        async { gated_store.gate.add_permits(4) },
+    );
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        results,
+        vec![
+            VALUE1.as_bytes(),
+            VALUE1.as_bytes(),
+            &VALUE1.as_bytes()[5..],
+            &VALUE1.as_bytes()[5..],
+        ]
+    );
+    assert_eq!(gated_store.get_part_calls.load(Ordering::Acquire), 3);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn zero_max_size_uses_default_max_size() -> Result<(), Error> {
+    let (gated_store, store) = make_stores(0).await?;
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    let (results, ()) = join!(
+        join_all((0..CONCURRENT_READS).map(|_| store.get_part_unchunked(digest, 0, None))),
+        async { gated_store.gate.add_permits(CONCURRENT_READS) },
+    );
+    for result in results {
+        assert_eq!(result?, VALUE1.as_bytes());
+    }
+    assert_eq!(gated_store.get_part_calls.load(Ordering::Acquire), 1);
+    Ok(())
+}
@@ -14,7 +14,9 @@
 
 use std::sync::Arc;
 
-use nativelink_config::stores::{MemorySpec, SizePartitioningSpec, StoreSpec};
+use nativelink_config::stores::{
+    HashPrefixRange, MemorySpec, PartitionPredicate, SizePartitioningSpec, StoreSpec,
+};
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
 use nativelink_store::memory_store::MemoryStore;
@@ -37,6 +39,17 @@ fn setup_stores(
     Arc<SizePartitioningStore>,
     Arc<MemoryStore>,
     Arc<MemoryStore>,
+) {
+    setup_stores_with_predicate(size, PartitionPredicate::size)
+}
+
+fn setup_stores_with_predicate(
+    size: u64,
+    predicate: PartitionPredicate,
+) -> (
+    Arc<SizePartitioningStore>,
+    Arc<MemoryStore>,
+    Arc<MemoryStore>,
 ) {
     let lower_memory_store = MemoryStore::new(&MemorySpec::default());
     let upper_memory_store = MemoryStore::new(&MemorySpec::default());
@@ -44,12 +57,14 @@ fn setup_stores(
     let size_part_store = SizePartitioningStore::new(
         &SizePartitioningSpec {
             size,
+            predicate,
             lower_store: StoreSpec::memory(MemorySpec::default()),
             upper_store: StoreSpec::memory(MemorySpec::default()),
         },
         Store::new(lower_memory_store.clone()),
         Store::new(upper_memory_store.clone()),
-    );
+    )
+    .expect("Failed to create SizePartitioningStore");
     (size_part_store, lower_memory_store, upper_memory_store)
 }
 
@@ -200,3 +215,100 @@ async fn update_test() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn size_predicate_ignores_hash_test() -> Result<(), Error> {
+    const LOW_PREFIX_HASH: &str =
+        "00000000000000000000000000000000000000000000000000000000000000aa";
+    const HIGH_PREFIX_HASH: &str =
+        "ff000000000000000000000000000000000000000000000000000000000000aa";
+    let (size_part_store, lower_memory_store, upper_memory_store) = setup_stores(BASE_SIZE_PART);
+
+    let small_digest = DigestInfo::try_new(HIGH_PREFIX_HASH, SMALL_VALUE.len())?;
+    let big_digest = DigestInfo::try_new(LOW_PREFIX_HASH, BIG_VALUE.len())?;
+    size_part_store
+        .update_oneshot(small_digest, SMALL_VALUE.into())
+        .await?;
+    size_part_store
+        .update_oneshot(big_digest, BIG_VALUE.into())
+        .await?;
+
+    assert_eq!(
+        lower_memory_store.has(small_digest).await,
+        Ok(Some(SMALL_VALUE.len() as u64))
+    );
+    assert_eq!(upper_memory_store.has(small_digest).await, Ok(None));
+    assert_eq!(
+        upper_memory_store.has(big_digest).await,
+        Ok(Some(BIG_VALUE.len() as u64))
+    );
+    assert_eq!(lower_memory_store.has(big_digest).await, Ok(None));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn hash_prefix_predicate_routes_by_hash_test() -> Result<(), Error> {
+    const IN_RANGE_HASH: &str = "3a00000000000000000000000000000000000000000000000000000000000000";
+    const RANGE_END_HASH: &str = "4fff000000000000000000000000000000000000000000000000000000000000";
+    const OUT_OF_RANGE_HASH: &str =
+        "5000000000000000000000000000000000000000000000000000000000000000";
+    const BELOW_RANGE_HASH: &str =
+        "0fff000000000000000000000000000000000000000000000000000000000000";
+    let (size_part_store, lower_memory_store, upper_memory_store) = setup_stores_with_predicate(
+        // The size must not influence the routing of this predicate.
+        0,
+        PartitionPredicate::hash_prefix(vec![HashPrefixRange {
+            start: "1".to_string(),
+            end: "4f".to_string(),
+        }]),
+    );
+
+    let upper_digests = [
+        DigestInfo::try_new(IN_RANGE_HASH, SMALL_VALUE.len())?,
+        DigestInfo::try_new(RANGE_END_HASH, SMALL_VALUE.len())?,
+    ];
+    let lower_digests = [
+        DigestInfo::try_new(OUT_OF_RANGE_HASH, SMALL_VALUE.len())?,
+        DigestInfo::try_new(BELOW_RANGE_HASH, SMALL_VALUE.len())?,
+    ];
+    for digest in upper_digests.iter().chain(lower_digests.iter()) {
+        size_part_store
+            .update_oneshot(*digest, SMALL_VALUE.into())
+            .await?;
+    }
+
+    for digest in upper_digests {
+        assert_eq!(
+            upper_memory_store.has(digest).await,
+            Ok(Some(SMALL_VALUE.len() as u64)),
+            "Expected {digest} in upper store"
+        );
+        assert_eq!(lower_memory_store.has(digest).await, Ok(None));
+    }
+    for digest in lower_digests {
+        assert_eq!(
+            lower_memory_store.has(digest).await,
+            Ok(Some(SMALL_VALUE.len() as u64)),
+            "Expected {digest} in lower store"
+        );
+        assert_eq!(upper_memory_store.has(digest).await, Ok(None));
+    }
+
+    // Reads are routed the same way as writes.
+    let keys: Vec<_> = upper_digests
+        .iter()
+        .chain(lower_digests.iter())
+        .map(|digest| (*digest).into())
+        .collect();
+    assert_eq!(
+        size_part_store.has_many(&keys).await?,
+        vec![Some(SMALL_VALUE.len() as u64); 4]
+    );
+    assert_eq!(
+        size_part_store
+            .get_part_unchunked(lower_digests[0], 0, None)
+            .await?,
+        SMALL_VALUE.as_bytes()
+    );
+    Ok(())
+}
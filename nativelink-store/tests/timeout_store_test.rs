@@ -0,0 +1,240 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nativelink_config::stores::{MemorySpec, StoreSpec, TimeoutSpec};
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_metric::MetricsComponent;
+use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::timeout_store::TimeoutStore;
+use nativelink_util::buf_channel::{
+    make_buf_channel_pair, DropCloserReadHalf, DropCloserWriteHalf,
+};
+use nativelink_util::common::DigestInfo;
+use nativelink_util::health_utils::{default_health_status_indicator, HealthStatusIndicator};
+use nativelink_util::spawn;
+use nativelink_util::store_trait::{Store, StoreDriver, StoreKey, StoreLike, UploadSizeInfo};
+use pretty_assertions::assert_eq;
+
+const VALID_HASH1: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef";
+const VALUE1: &str = "0123456789";
+const TIMEOUT_MS: u64 = 10;
+const TRICKLE_DELAY: Duration = Duration::from_millis(20);
+
+/// Store whose operations never finish, like a backend with a hung
+/// connection.
+#[derive(MetricsComponent)]
+struct StallingStore {}
+
+#[async_trait]
+impl StoreDriver for StallingStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        _keys: &[StoreKey<'_>],
+        _results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        _key: StoreKey<'_>,
+        _reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        _key: StoreKey<'_>,
+        _writer: &mut DropCloserWriteHalf,
+        _offset: u64,
+        _length: Option<u64>,
+    ) -> Result<(), Error> {
+        std::future::pending().await
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(StallingStore);
+
+/// Store that sends `VALUE1` one byte at a time with a pause of
+/// `TRICKLE_DELAY` before each byte.
+#[derive(MetricsComponent)]
+struct TricklingStore {}
+
+#[async_trait]
+impl StoreDriver for TricklingStore {
+    async fn has_with_results(
+        self: Pin<&Self>,
+        _keys: &[StoreKey<'_>],
+        results: &mut [Option<u64>],
+    ) -> Result<(), Error> {
+        results.fill(Some(VALUE1.len() as u64));
+        Ok(())
+    }
+
+    async fn update(
+        self: Pin<&Self>,
+        _key: StoreKey<'_>,
+        mut reader: DropCloserReadHalf,
+        _size_info: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        reader.drain().await
+    }
+
+    async fn get_part(
+        self: Pin<&Self>,
+        _key: StoreKey<'_>,
+        writer: &mut DropCloserWriteHalf,
+        _offset: u64,
+        _length: Option<u64>,
+    ) -> Result<(), Error> {
+        for i in 0..VALUE1.len() {
+            tokio::time::sleep(TRICKLE_DELAY).await;
+            writer.send(VALUE1[i..=i].into()).await?;
+        }
+        writer.send_eof()
+    }
+
+    fn inner_store(&self, _digest: Option<StoreKey>) -> &'_ dyn StoreDriver {
+        self
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Sync + Send + 'static) {
+        self
+    }
+
+    fn as_any_arc(self: Arc<Self>) -> Arc<dyn std::any::Any + Sync + Send + 'static> {
+        self
+    }
+}
+
+default_health_status_indicator!(TricklingStore);
+
+fn make_timeout_store(inner_store: Store) -> Store {
+    make_timeout_store_with_timeout(inner_store, TIMEOUT_MS)
+}
+
+fn make_timeout_store_with_timeout(inner_store: Store, timeout_ms: u64) -> Store {
+    Store::new(TimeoutStore::new(
+        &TimeoutSpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            timeout_ms,
+        },
+        inner_store,
+    ))
+}
+
+#[nativelink_test]
+async fn operations_on_stalled_store_time_out() -> Result<(), Error> {
+    let store = make_timeout_store(Store::new(Arc::new(StallingStore {})));
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    assert_eq!(
+        store.has(digest).await.map_err(|e| e.code),
+        Err(Code::DeadlineExceeded)
+    );
+    assert_eq!(
+        store
+            .get_part_unchunked(digest, 0, None)
+            .await
+            .map_err(|e| e.code),
+        Err(Code::DeadlineExceeded)
+    );
+    assert_eq!(
+        store
+            .update_oneshot(digest, VALUE1.into())
+            .await
+            .map_err(|e| e.code),
+        Err(Code::DeadlineExceeded)
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn operations_that_finish_in_time_pass_through() -> Result<(), Error> {
+    let memory_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+    let store = make_timeout_store(memory_store.clone());
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+
+    store.update_oneshot(digest, VALUE1.into()).await?;
+    assert_eq!(
+        memory_store.has(digest).await,
+        Ok(Some(VALUE1.len() as u64))
+    );
+    assert_eq!(store.has(digest).await, Ok(Some(VALUE1.len() as u64)));
+    assert_eq!(
+        store.get_part_unchunked(digest, 0, None).await,
+        Ok(VALUE1.into())
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn slow_transfers_that_keep_making_progress_pass_through() -> Result<(), Error> {
+    // Each chunk arrives well within the timeout, but the whole transfer
+    // takes several times longer than it.
+    const IDLE_TIMEOUT_MS: u64 = 100;
+
+    let digest = DigestInfo::try_new(VALID_HASH1, VALUE1.len())?;
+    {
+        let store = make_timeout_store_with_timeout(
+            Store::new(Arc::new(TricklingStore {})),
+            IDLE_TIMEOUT_MS,
+        );
+        assert_eq!(
+            store.get_part_unchunked(digest, 0, None).await,
+            Ok(VALUE1.into())
+        );
+    }
+    {
+        let memory_store = Store::new(MemoryStore::new(&MemorySpec::default()));
+        let store = make_timeout_store_with_timeout(memory_store.clone(), IDLE_TIMEOUT_MS);
+        let (mut tx, rx) = make_buf_channel_pair();
+        let send_fut = spawn!("timeout_store_test_trickle_send", async move {
+            for i in 0..VALUE1.len() {
+                tokio::time::sleep(TRICKLE_DELAY).await;
+                tx.send(VALUE1[i..=i].into()).await?;
+            }
+            tx.send_eof()
+        });
+        store
+            .update(digest, rx, UploadSizeInfo::ExactSize(VALUE1.len() as u64))
+            .await?;
+        send_fut.await.unwrap()?;
+        assert_eq!(
+            memory_store.get_part_unchunked(digest, 0, None).await,
+            Ok(VALUE1.into())
+        );
+    }
+    Ok(())
+}
@@ -297,6 +297,52 @@ async fn verify_blake3_hash_true_fails_on_update() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn verify_hash_true_aborts_backend_update_on_multi_chunk_mismatch() -> Result<(), Error> {
+    /// This value is sha256("12").
+    const HASH: &str = "6b51d431df5d7f141cbececcf79edf3dd861c3b4069f0b11661a3eefacbba918";
+    const EXPECTED_ERR: &str = "Hashes do not match";
+    const EXPECTED_BACKEND_ERR: &str = "Sender dropped before sending EOF";
+
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = VerifyStore::new(
+        &VerifySpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            verify_size: false,
+            verify_hash: true,
+        },
+        Store::new(inner_store.clone()),
+    );
+
+    let digest = DigestInfo::try_new(HASH, 6).unwrap();
+    let (mut tx, rx) = make_buf_channel_pair();
+    let send_fut = async move {
+        tx.send("123".into()).await?;
+        tx.send("456".into()).await?;
+        tx.send_eof()
+    };
+    let result = try_join!(
+        send_fut,
+        store.update(digest, rx, UploadSizeInfo::ExactSize(6))
+    );
+    let err = result.unwrap_err().to_string();
+    assert!(
+        err.contains(EXPECTED_ERR),
+        "Error should contain '{EXPECTED_ERR}', got: {err:?}"
+    );
+    // The backend must have seen the stream end without an EOF.
+    assert!(
+        err.contains(EXPECTED_BACKEND_ERR),
+        "Error should contain '{EXPECTED_BACKEND_ERR}', got: {err:?}"
+    );
+    assert_eq!(
+        inner_store.has(digest).await,
+        Ok(None),
+        "Expected data to not exist in store after update"
+    );
+    Ok(())
+}
+
 // A potential bug could happen if the down stream component ignores the EOF but will
 // stop receiving data when the expected size is reached. We should ensure this edge
 // case is double protected.
@@ -369,3 +415,114 @@ async fn verify_size_and_hash_suceeds_on_small_data() -> Result<(), Error> {
     );
     Ok(())
 }
+
+/// Streams `chunks` into a size verifying store for a digest of `digest_size`
+/// bytes and returns the resulting error message. If `hang_after_send` is set
+/// the sender never sends an EOF, so the store must fail on its own.
+async fn verify_size_update_error(
+    digest_size: u64,
+    chunks: &'static [&'static str],
+    size_info: UploadSizeInfo,
+    hang_after_send: bool,
+) -> Result<String, Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = VerifyStore::new(
+        &VerifySpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            verify_size: true,
+            verify_hash: false,
+        },
+        Store::new(inner_store.clone()),
+    );
+
+    let digest = DigestInfo::try_new(VALID_HASH1, digest_size).unwrap();
+    let (mut tx, rx) = make_buf_channel_pair();
+    let send_fut = async move {
+        for chunk in chunks {
+            tx.send((*chunk).into()).await?;
+        }
+        if hang_after_send {
+            pending::<()>().await;
+        }
+        tx.send_eof()
+    };
+    let result = try_join!(send_fut, store.update(digest, rx, size_info));
+    assert!(result.is_err(), "Expected error, got: {:?}", &result);
+    assert_eq!(
+        inner_store.has(digest).await,
+        Ok(None),
+        "Expected data to not exist in store after update"
+    );
+    Ok(result.unwrap_err().to_string())
+}
+
+#[nativelink_test]
+async fn verify_size_true_fails_on_too_few_bytes() -> Result<(), Error> {
+    const EXPECTED_ERR: &str = "Expected size 6 but got size 3 on insert";
+
+    for size_info in [UploadSizeInfo::ExactSize(6), UploadSizeInfo::MaxSize(6)] {
+        let err = verify_size_update_error(6, &["123"], size_info, false).await?;
+        assert!(
+            err.contains(EXPECTED_ERR),
+            "Error should contain '{EXPECTED_ERR}' for {size_info:?}, got: {err:?}"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn verify_size_true_fails_on_too_many_bytes() -> Result<(), Error> {
+    const EXPECTED_ERR: &str = "Expected size 4 but already received 6 on insert";
+
+    for size_info in [UploadSizeInfo::ExactSize(4), UploadSizeInfo::MaxSize(100)] {
+        let err = verify_size_update_error(4, &["123", "456"], size_info, true).await?;
+        assert!(
+            err.contains(EXPECTED_ERR),
+            "Error should contain '{EXPECTED_ERR}' for {size_info:?}, got: {err:?}"
+        );
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn verify_size_true_fails_on_max_size_smaller_than_digest() -> Result<(), Error> {
+    const EXPECTED_ERR: &str = "Max upload size 2 is smaller than digest size 3 on update";
+
+    let err = verify_size_update_error(3, &[], UploadSizeInfo::MaxSize(2), true).await?;
+    assert!(
+        err.contains(EXPECTED_ERR),
+        "Error should contain '{EXPECTED_ERR}', got: {err:?}"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn verify_size_true_suceeds_on_max_size_update() -> Result<(), Error> {
+    let inner_store = MemoryStore::new(&MemorySpec::default());
+    let store = VerifyStore::new(
+        &VerifySpec {
+            backend: StoreSpec::memory(MemorySpec::default()),
+            verify_size: true,
+            verify_hash: false,
+        },
+        Store::new(inner_store.clone()),
+    );
+
+    let digest = DigestInfo::try_new(VALID_HASH1, 6).unwrap();
+    let (mut tx, rx) = make_buf_channel_pair();
+    let send_fut = async move {
+        tx.send("123".into()).await?;
+        tx.send("456".into()).await?;
+        tx.send_eof()
+    };
+    try_join!(
+        send_fut,
+        store.update(digest, rx, UploadSizeInfo::MaxSize(100))
+    )?;
+    assert_eq!(
+        inner_store.has(digest).await,
+        Ok(Some(6)),
+        "Expected data to exist in store after update"
+    );
+    Ok(())
+}
@@ -23,9 +23,9 @@ use nativelink_metric::{
     publish, MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent,
 };
 use nativelink_proto::build::bazel::remote::execution::v2::{
-    execution_stage, Action, ActionResult as ProtoActionResult, ExecuteOperationMetadata,
-    ExecuteRequest, ExecuteResponse, ExecutedActionMetadata, FileNode, LogFile, OutputDirectory,
-    OutputFile, OutputSymlink, SymlinkNode,
+    execution_stage, Action, ActionResult as ProtoActionResult, Digest, ExecuteOperationMetadata,
+    ExecuteRequest, ExecuteResponse, ExecutedActionMetadata, FileNode, LogFile, NodeProperties,
+    OutputDirectory, OutputFile, OutputSymlink, SymlinkNode,
 };
 use nativelink_proto::google::longrunning::operation::Result as LongRunningResult;
 use nativelink_proto::google::longrunning::Operation;
@@ -309,6 +309,12 @@ pub struct ActionInfo {
     /// This is primarily used to join actions/operations together using this key.
     #[metric(help = "Info used to uniquely identify this ActionInfo and if it is cachable.")]
     pub unique_qualifier: ActionUniqueQualifier,
+    /// Whether the `Action` has `do_not_cache` set. Its result must then never
+    /// be written to the action cache, but cached results may still be looked
+    /// up unless `unique_qualifier` is `Uncachable`.
+    #[metric(help = "Whether the result of this action must not be cached.")]
+    #[serde(default)]
+    pub do_not_cache: bool,
 }
 
 impl ActionInfo {
@@ -372,6 +378,7 @@ impl ActionInfo {
             load_timestamp,
             insert_timestamp: queued_timestamp,
             unique_qualifier,
+            do_not_cache: action.do_not_cache,
         })
     }
 }
@@ -433,11 +440,24 @@ pub struct FileInfo {
     pub name_or_path: NameOrPath,
     pub digest: DigestInfo,
     pub is_executable: bool,
+    /// The full unix mode of the file, sent as `NodeProperties::unix_mode`.
+    #[serde(default)]
+    pub unix_mode: Option<u32>,
+}
+
+impl FileInfo {
+    fn node_properties(&self) -> Option<NodeProperties> {
+        self.unix_mode.map(|unix_mode| NodeProperties {
+            unix_mode: Some(unix_mode),
+            ..Default::default()
+        })
+    }
 }
 
 //TODO: Make this TryFrom.
 impl From<FileInfo> for FileNode {
     fn from(val: FileInfo) -> Self {
+        let node_properties = val.node_properties();
         let NameOrPath::Name(name) = val.name_or_path else {
             panic!("Cannot return a FileInfo that uses a NameOrPath::Path(), it must be a NameOrPath::Name()");
         };
@@ -445,7 +465,7 @@ impl From<FileInfo> for FileNode {
             name,
             digest: Some((&val.digest).into()),
             is_executable: val.is_executable,
-            node_properties: Option::default(), // Not supported.
+            node_properties,
         }
     }
 }
@@ -461,6 +481,9 @@ impl TryFrom<OutputFile> for FileInfo {
                 .err_tip(|| "Expected digest to exist on OutputFile")?
                 .try_into()?,
             is_executable: output_file.is_executable,
+            unix_mode: output_file
+                .node_properties
+                .and_then(|node_properties| node_properties.unix_mode),
         })
     }
 }
@@ -468,6 +491,7 @@ impl TryFrom<OutputFile> for FileInfo {
 //TODO: Make this TryFrom.
 impl From<FileInfo> for OutputFile {
     fn from(val: FileInfo) -> Self {
+        let node_properties = val.node_properties();
         let NameOrPath::Path(path) = val.name_or_path else {
             panic!("Cannot return a FileInfo that uses a NameOrPath::Name(), it must be a NameOrPath::Path()");
         };
@@ -476,7 +500,7 @@ impl From<FileInfo> for OutputFile {
             digest: Some((&val.digest).into()),
             is_executable: val.is_executable,
             contents: Bytes::default(),
-            node_properties: Option::default(), // Not supported.
+            node_properties,
         }
     }
 }
@@ -696,8 +720,16 @@ pub struct ActionResult {
     pub output_directory_symlinks: Vec<SymlinkInfo>,
     pub output_file_symlinks: Vec<SymlinkInfo>,
     pub exit_code: i32,
-    pub stdout_digest: DigestInfo,
-    pub stderr_digest: DigestInfo,
+    /// Digest of the standard output in the CAS. Only `None` if it is
+    /// inlined in `stdout_raw` instead.
+    pub stdout_digest: Option<DigestInfo>,
+    /// Digest of the standard error in the CAS. Only `None` if it is
+    /// inlined in `stderr_raw` instead.
+    pub stderr_digest: Option<DigestInfo>,
+    /// Standard output inlined in the result instead of uploaded to the CAS.
+    pub stdout_raw: Vec<u8>,
+    /// Standard error inlined in the result instead of uploaded to the CAS.
+    pub stderr_raw: Vec<u8>,
     pub execution_metadata: ExecutionMetadata,
     pub server_logs: HashMap<String, DigestInfo>,
     pub error: Option<Error>,
@@ -712,8 +744,10 @@ impl Default for ActionResult {
             output_directory_symlinks: Vec::default(),
             output_file_symlinks: Vec::default(),
             exit_code: INTERNAL_ERROR_EXIT_CODE,
-            stdout_digest: DigestInfo::new([0u8; 32], 0),
-            stderr_digest: DigestInfo::new([0u8; 32], 0),
+            stdout_digest: Some(DigestInfo::new([0u8; 32], 0)),
+            stderr_digest: Some(DigestInfo::new([0u8; 32], 0)),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
             execution_metadata: ExecutionMetadata {
                 worker: String::new(),
                 queued_timestamp: SystemTime::UNIX_EPOCH,
@@ -905,15 +939,25 @@ impl From<ActionResult> for ProtoActionResult {
                 .map(Into::into)
                 .collect(),
             exit_code: val.exit_code,
-            stdout_raw: Bytes::default(),
-            stdout_digest: Some(val.stdout_digest.into()),
-            stderr_raw: Bytes::default(),
-            stderr_digest: Some(val.stderr_digest.into()),
+            stdout_digest: val.stdout_digest.map(Into::into),
+            stdout_raw: val.stdout_raw.into(),
+            stderr_digest: val.stderr_digest.map(Into::into),
+            stderr_raw: val.stderr_raw.into(),
             execution_metadata: Some(val.execution_metadata.into()),
         }
     }
 }
 
+/// Returns the digest of the stdout or stderr of a `ProtoActionResult`.
+/// The digest may only be omitted if the stream is inlined.
+fn output_stream_digest(digest: Option<Digest>, raw: &[u8]) -> Result<Option<DigestInfo>, Error> {
+    error_if!(
+        digest.is_none() && raw.is_empty(),
+        "Expected digest or inlined data to be set"
+    );
+    digest.map(DigestInfo::try_from).transpose()
+}
+
 impl TryFrom<ProtoActionResult> for ActionResult {
     type Error = Error;
 
@@ -962,14 +1006,12 @@ impl TryFrom<ProtoActionResult> for ActionResult {
             output_file_symlinks,
             output_directory_symlinks,
             exit_code: val.exit_code,
-            stdout_digest: val
-                .stdout_digest
-                .err_tip(|| "Expected stdout_digest to be set on ExecuteResponse msg")?
-                .try_into()?,
-            stderr_digest: val
-                .stderr_digest
-                .err_tip(|| "Expected stderr_digest to be set on ExecuteResponse msg")?
-                .try_into()?,
+            stdout_digest: output_stream_digest(val.stdout_digest, &val.stdout_raw)
+                .err_tip(|| "Expected stdout_digest to be set on ExecuteResponse msg")?,
+            stderr_digest: output_stream_digest(val.stderr_digest, &val.stderr_raw)
+                .err_tip(|| "Expected stderr_digest to be set on ExecuteResponse msg")?,
+            stdout_raw: val.stdout_raw.to_vec(),
+            stderr_raw: val.stderr_raw.to_vec(),
             execution_metadata: val
                 .execution_metadata
                 .err_tip(|| "Expected execution_metadata to be set on ExecuteResponse msg")?
@@ -1003,14 +1045,18 @@ impl TryFrom<ExecuteResponse> for ActionStage {
                 .try_map(TryInto::try_into)?,
             exit_code: proto_action_result.exit_code,
 
-            stdout_digest: proto_action_result
-                .stdout_digest
-                .err_tip(|| "Expected stdout_digest to be set on ExecuteResponse msg")?
-                .try_into()?,
-            stderr_digest: proto_action_result
-                .stderr_digest
-                .err_tip(|| "Expected stderr_digest to be set on ExecuteResponse msg")?
-                .try_into()?,
+            stdout_digest: output_stream_digest(
+                proto_action_result.stdout_digest,
+                &proto_action_result.stdout_raw,
+            )
+            .err_tip(|| "Expected stdout_digest to be set on ExecuteResponse msg")?,
+            stderr_digest: output_stream_digest(
+                proto_action_result.stderr_digest,
+                &proto_action_result.stderr_raw,
+            )
+            .err_tip(|| "Expected stderr_digest to be set on ExecuteResponse msg")?,
+            stdout_raw: proto_action_result.stdout_raw.to_vec(),
+            stderr_raw: proto_action_result.stderr_raw.to_vec(),
             execution_metadata: proto_action_result
                 .execution_metadata
                 .err_tip(|| "Expected execution_metadata to be set on ExecuteResponse msg")?
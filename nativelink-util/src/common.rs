@@ -17,6 +17,7 @@ use std::collections::HashMap;
 use std::fmt;
 use std::hash::{BuildHasher, Hash};
 use std::io::{Cursor, Write};
+use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
@@ -54,19 +55,38 @@ impl MetricsComponent for DigestInfo {
 }
 
 impl DigestInfo {
-    pub const fn new(packed_hash: [u8; 32], size_bytes: u64) -> Self {
+    pub const fn new(packed_hash: [u8; SIZE_OF_PACKED_HASH], size_bytes: u64) -> Self {
         DigestInfo {
             size_bytes,
-            packed_hash: PackedHash(packed_hash),
+            packed_hash: PackedHash::from_array(packed_hash),
         }
     }
 
+    /// Creates a `DigestInfo` from a 512 bit hash, such as those of SHA512.
+    pub const fn new_512(packed_hash: [u8; MAX_SIZE_OF_PACKED_HASH], size_bytes: u64) -> Self {
+        DigestInfo {
+            size_bytes,
+            packed_hash: PackedHash {
+                bytes: packed_hash,
+                len: MAX_SIZE_OF_PACKED_HASH as u8,
+            },
+        }
+    }
+
+    /// Creates a `DigestInfo` from a hash of any supported length, such as
+    /// the 64 byte hashes of SHA512.
+    pub fn try_new_from_packed_hash(packed_hash: &[u8], size_bytes: u64) -> Result<Self, Error> {
+        Ok(DigestInfo {
+            packed_hash: PackedHash::try_from(packed_hash)?,
+            size_bytes,
+        })
+    }
+
     pub fn try_new<T>(hash: &str, size_bytes: T) -> Result<Self, Error>
     where
         T: TryInto<u64> + std::fmt::Display + Copy,
     {
-        let packed_hash =
-            PackedHash::from_hex(hash).err_tip(|| format!("Invalid sha256 hash: {hash}"))?;
+        let packed_hash = PackedHash::from_hex(hash).err_tip(|| format!("Invalid hash: {hash}"))?;
         let size_bytes = size_bytes
             .try_into()
             .map_err(|_| make_input_err!("Could not convert {} into u64", size_bytes))?;
@@ -96,8 +116,8 @@ impl DigestInfo {
         &self.packed_hash
     }
 
-    pub fn set_packed_hash(&mut self, packed_hash: [u8; 32]) {
-        self.packed_hash = PackedHash(packed_hash);
+    pub fn set_packed_hash(&mut self, packed_hash: PackedHash) {
+        self.packed_hash = packed_hash;
     }
 
     pub const fn size_bytes(&self) -> u64 {
@@ -126,17 +146,17 @@ const fn count_digits(mut num: u64) -> usize {
 struct DigestStackStringifier<'a> {
     digest: &'a DigestInfo,
     /// Buffer that can hold the string representation of the `DigestInfo`.
-    /// - Hex is '2 * sizeof(PackedHash)'.
+    /// - Hex is at most '2 * MAX_SIZE_OF_PACKED_HASH'.
     /// - Digits can be at most `count_digits(u64::MAX)`.
     /// - We also have a hyphen separator.
-    buf: [u8; std::mem::size_of::<PackedHash>() * 2 + count_digits(u64::MAX) + 1],
+    buf: [u8; MAX_SIZE_OF_PACKED_HASH * 2 + count_digits(u64::MAX) + 1],
 }
 
 impl<'a> DigestStackStringifier<'a> {
     const fn new(digest: &'a DigestInfo) -> Self {
         DigestStackStringifier {
             digest,
-            buf: [b'-'; std::mem::size_of::<PackedHash>() * 2 + count_digits(u64::MAX) + 1],
+            buf: [b'-'; MAX_SIZE_OF_PACKED_HASH * 2 + count_digits(u64::MAX) + 1],
         }
     }
 
@@ -145,14 +165,15 @@ impl<'a> DigestStackStringifier<'a> {
         // to the buffer.
         let len = {
             let mut cursor = Cursor::new(&mut self.buf[..]);
-            let hex = self.digest.packed_hash.to_hex().map_err(|e| {
+            let (hex, hex_len) = self.digest.packed_hash.to_hex().map_err(|e| {
                 make_input_err!(
                     "Could not convert PackedHash to hex - {e:?} - {:?}",
                     self.digest
                 )
             })?;
+            let hex = &hex[..hex_len];
             cursor
-                .write_all(&hex)
+                .write_all(hex)
                 .err_tip(|| format!("Could not write hex to buffer - {hex:?} - {hex:?}",))?;
             // Note: We already have a hyphen at this point because we
             // initialized the buffer with hyphens.
@@ -279,7 +300,7 @@ impl TryFrom<Digest> for DigestInfo {
 
     fn try_from(digest: Digest) -> Result<Self, Self::Error> {
         let packed_hash = PackedHash::from_hex(&digest.hash)
-            .err_tip(|| format!("Invalid sha256 hash: {}", digest.hash))?;
+            .err_tip(|| format!("Invalid hash: {}", digest.hash))?;
         let size_bytes = digest
             .size_bytes
             .try_into()
@@ -296,7 +317,7 @@ impl TryFrom<&Digest> for DigestInfo {
 
     fn try_from(digest: &Digest) -> Result<Self, Self::Error> {
         let packed_hash = PackedHash::from_hex(&digest.hash)
-            .err_tip(|| format!("Invalid sha256 hash: {}", digest.hash))?;
+            .err_tip(|| format!("Invalid hash: {}", digest.hash))?;
         let size_bytes = digest
             .size_bytes
             .try_into()
@@ -344,63 +365,166 @@ impl From<&DigestInfo> for Digest {
     }
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
-pub struct PackedHash([u8; 32]);
-
+/// Size in bytes of the 256 bit hashes used by most digest functions.
 const SIZE_OF_PACKED_HASH: usize = 32;
+
+/// Largest hash in bytes a `PackedHash` can hold, enough for 512 bit
+/// digest functions like SHA512.
+pub const MAX_SIZE_OF_PACKED_HASH: usize = 64;
+
+/// Hash sizes in bytes that a `PackedHash` accepts.
+const SUPPORTED_SIZES_OF_PACKED_HASH: [usize; 2] = [SIZE_OF_PACKED_HASH, MAX_SIZE_OF_PACKED_HASH];
+
+/// Raw hash of a digest. The hash is stored inline in a fixed-capacity
+/// buffer so digests of any supported length stay `Copy` and never allocate.
+#[derive(Clone, Copy)]
+pub struct PackedHash {
+    bytes: [u8; MAX_SIZE_OF_PACKED_HASH],
+    /// Number of bytes of `bytes` in use. Unused bytes are always zero.
+    len: u8,
+}
+
 impl PackedHash {
     const fn new() -> Self {
-        PackedHash([0; SIZE_OF_PACKED_HASH])
+        Self::from_array([0; SIZE_OF_PACKED_HASH])
+    }
+
+    const fn from_array(packed_hash: [u8; SIZE_OF_PACKED_HASH]) -> Self {
+        let mut bytes = [0u8; MAX_SIZE_OF_PACKED_HASH];
+        let mut i = 0;
+        while i < SIZE_OF_PACKED_HASH {
+            bytes[i] = packed_hash[i];
+            i += 1;
+        }
+        PackedHash {
+            bytes,
+            len: SIZE_OF_PACKED_HASH as u8,
+        }
     }
 
     fn from_hex(hash: &str) -> Result<Self, Error> {
-        let mut packed_hash = [0u8; 32];
-        hex::decode_to_slice(hash, &mut packed_hash)
-            .map_err(|e| make_input_err!("Invalid sha256 hash: {hash} - {e:?}"))?;
-        Ok(PackedHash(packed_hash))
+        let len = hash.len() / 2;
+        if hash.len() % 2 != 0 || !SUPPORTED_SIZES_OF_PACKED_HASH.contains(&len) {
+            return Err(make_input_err!(
+                "Invalid hash length: {hash} - expected one of {SUPPORTED_SIZES_OF_PACKED_HASH:?} hex encoded bytes"
+            ));
+        }
+        let mut bytes = [0u8; MAX_SIZE_OF_PACKED_HASH];
+        hex::decode_to_slice(hash, &mut bytes[..len])
+            .map_err(|e| make_input_err!("Invalid hash: {hash} - {e:?}"))?;
+        Ok(PackedHash {
+            bytes,
+            len: len as u8,
+        })
     }
 
-    /// Converts the packed hash into a hex string.
+    /// Converts the packed hash into hex, returning the buffer and the
+    /// number of bytes of it in use.
     #[inline]
-    fn to_hex(self) -> Result<[u8; SIZE_OF_PACKED_HASH * 2], fmt::Error> {
-        let mut hash = [0u8; SIZE_OF_PACKED_HASH * 2];
-        hex::encode_to_slice(self.0, &mut hash).map_err(|e| {
+    fn to_hex(self) -> Result<([u8; MAX_SIZE_OF_PACKED_HASH * 2], usize), fmt::Error> {
+        let mut hash = [0u8; MAX_SIZE_OF_PACKED_HASH * 2];
+        let hex_len = self.len() * 2;
+        hex::encode_to_slice(&*self, &mut hash[..hex_len]).map_err(|e| {
             event!(
                 Level::ERROR,
                 "Could not convert PackedHash to hex - {e:?} - {:?}",
-                self.0
+                &*self
             );
             fmt::Error
         })?;
-        Ok(hash)
+        Ok((hash, hex_len))
+    }
+}
+
+impl Default for PackedHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TryFrom<&[u8]> for PackedHash {
+    type Error = Error;
+
+    fn try_from(packed_hash: &[u8]) -> Result<Self, Self::Error> {
+        if !SUPPORTED_SIZES_OF_PACKED_HASH.contains(&packed_hash.len()) {
+            return Err(make_input_err!(
+                "Invalid hash length: {} - expected one of {SUPPORTED_SIZES_OF_PACKED_HASH:?} bytes",
+                packed_hash.len()
+            ));
+        }
+        let mut bytes = [0u8; MAX_SIZE_OF_PACKED_HASH];
+        bytes[..packed_hash.len()].copy_from_slice(packed_hash);
+        Ok(PackedHash {
+            bytes,
+            len: packed_hash.len() as u8,
+        })
+    }
+}
+
+impl PartialEq for PackedHash {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for PackedHash {}
+
+impl Hash for PackedHash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl Ord for PackedHash {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl PartialOrd for PackedHash {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl fmt::Display for PackedHash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let hash = self.to_hex()?;
-        match std::str::from_utf8(&hash) {
+        let (hash, hex_len) = self.to_hex()?;
+        match std::str::from_utf8(&hash[..hex_len]) {
             Ok(hash) => f.write_str(hash)?,
-            Err(_) => f.write_str(&format!("Could not convert hash to utf8 {:?}", self.0))?,
+            Err(_) => f.write_str(&format!("Could not convert hash to utf8 {:?}", &**self))?,
         }
         Ok(())
     }
 }
 
+impl fmt::Debug for PackedHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PackedHash")
+            .field(&format!("{self}"))
+            .finish()
+    }
+}
+
 impl Deref for PackedHash {
-    type Target = [u8; 32];
+    type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.bytes[..self.len as usize]
     }
 }
 
 impl DerefMut for PackedHash {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.bytes[..self.len as usize]
     }
 }
 
+/// Address of the client a request was received from. Attached by the server
+/// to the extensions of every request on a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ClientAddr(pub SocketAddr);
+
 // Simple utility trait that makes it easier to apply `.try_map` to Vec.
 // This will convert one vector into another vector with a different type.
 pub trait VecExt<T> {
@@ -24,10 +24,10 @@ use nativelink_metric::{
 };
 use nativelink_proto::build::bazel::remote::execution::v2::digest_function::Value as ProtoDigestFunction;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::{Digest, Sha256, Sha512};
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::common::DigestInfo;
+use crate::common::{DigestInfo, MAX_SIZE_OF_PACKED_HASH};
 use crate::origin_context::{ActiveOriginContext, OriginContext};
 use crate::{fs, make_symbol, spawn_blocking};
 
@@ -52,6 +52,23 @@ where
     Ok(Arc::new(new_ctx))
 }
 
+/// Resolves the digest function used to serve a request for an instance.
+/// If the instance is configured with a digest function, requests must either
+/// not specify one or specify the same one. Otherwise the requested digest
+/// function is used, falling back to the global default.
+pub fn resolve_instance_digest_function(
+    requested: Option<DigestHasherFunc>,
+    instance_digest_function: Option<DigestHasherFunc>,
+) -> Result<DigestHasherFunc, Error> {
+    match (requested, instance_digest_function) {
+        (Some(requested), Some(configured)) if requested != configured => Err(make_input_err!(
+            "Digest function {requested} does not match the digest function {configured} configured for this instance"
+        )),
+        (Some(digest_function), _) | (None, Some(digest_function)) => Ok(digest_function),
+        (None, None) => Ok(default_digest_hasher_func()),
+    }
+}
+
 /// Get the default hasher.
 pub fn default_digest_hasher_func() -> DigestHasherFunc {
     *DEFAULT_DIGEST_HASHER_FUNC.get_or_init(|| DigestHasherFunc::Sha256)
@@ -69,6 +86,7 @@ pub fn set_default_digest_hasher_func(hasher: DigestHasherFunc) -> Result<(), Er
 pub enum DigestHasherFunc {
     Sha256,
     Blake3,
+    Sha512,
 }
 
 impl MetricsComponent for DigestHasherFunc {
@@ -86,11 +104,33 @@ impl DigestHasherFunc {
         self.into()
     }
 
+    /// Number of bytes in the hashes produced by this digest function.
+    #[must_use]
+    pub const fn hash_size(&self) -> usize {
+        match self {
+            Self::Sha256 | Self::Blake3 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+
+    /// Checks that `digest` has a hash this digest function could produce.
+    pub fn check_digest(&self, digest: &DigestInfo) -> Result<(), Error> {
+        let hash_size = digest.packed_hash().len();
+        if hash_size != self.hash_size() {
+            return Err(make_input_err!(
+                "Digest {digest} has a {hash_size} byte hash, but {self} hashes are {} bytes",
+                self.hash_size()
+            ));
+        }
+        Ok(())
+    }
+
     #[must_use]
     pub const fn proto_digest_func(&self) -> ProtoDigestFunction {
         match self {
             Self::Sha256 => ProtoDigestFunction::Sha256,
             Self::Blake3 => ProtoDigestFunction::Blake3,
+            Self::Sha512 => ProtoDigestFunction::Sha512,
         }
     }
 }
@@ -100,6 +140,7 @@ impl From<ConfigDigestHashFunction> for DigestHasherFunc {
         match value {
             ConfigDigestHashFunction::sha256 => Self::Sha256,
             ConfigDigestHashFunction::blake3 => Self::Blake3,
+            ConfigDigestHashFunction::sha512 => Self::Sha512,
         }
     }
 }
@@ -111,6 +152,7 @@ impl TryFrom<ProtoDigestFunction> for DigestHasherFunc {
         match value {
             ProtoDigestFunction::Sha256 => Ok(Self::Sha256),
             ProtoDigestFunction::Blake3 => Ok(Self::Blake3),
+            ProtoDigestFunction::Sha512 => Ok(Self::Sha512),
             v => Err(make_input_err!(
                 "Unknown or unsupported digest function for proto conversion {v:?}"
             )),
@@ -125,6 +167,7 @@ impl TryFrom<&str> for DigestHasherFunc {
         match value.to_uppercase().as_str() {
             "SHA256" => Ok(Self::Sha256),
             "BLAKE3" => Ok(Self::Blake3),
+            "SHA512" => Ok(Self::Sha512),
             v => Err(make_input_err!(
                 "Unknown or unsupported digest function for string conversion: {v:?}"
             )),
@@ -137,6 +180,7 @@ impl std::fmt::Display for DigestHasherFunc {
         match self {
             DigestHasherFunc::Sha256 => write!(f, "SHA256"),
             DigestHasherFunc::Blake3 => write!(f, "BLAKE3"),
+            DigestHasherFunc::Sha512 => write!(f, "SHA512"),
         }
     }
 }
@@ -152,6 +196,7 @@ impl TryFrom<i32> for DigestHasherFunc {
         match ProtoDigestFunction::try_from(value) {
             Ok(ProtoDigestFunction::Sha256) => Ok(Self::Sha256),
             Ok(ProtoDigestFunction::Blake3) => Ok(Self::Blake3),
+            Ok(ProtoDigestFunction::Sha512) => Ok(Self::Sha512),
             value => Err(make_input_err!(
                 "Unknown or unsupported digest function for int conversion: {:?}",
                 value.map(|v| v.as_str_name())
@@ -165,6 +210,7 @@ impl From<&DigestHasherFunc> for DigestHasherImpl {
         let hash_func_impl = match value {
             DigestHasherFunc::Sha256 => DigestHasherFuncImpl::Sha256(Sha256::new()),
             DigestHasherFunc::Blake3 => DigestHasherFuncImpl::Blake3(Box::new(Blake3Hasher::new())),
+            DigestHasherFunc::Sha512 => DigestHasherFuncImpl::Sha512(Sha512::new()),
         };
         Self {
             hashed_size: 0,
@@ -217,6 +263,7 @@ pub trait DigestHasher {
 pub enum DigestHasherFuncImpl {
     Sha256(Sha256),
     Blake3(Box<Blake3Hasher>), // Box because Blake3Hasher is 1.3kb in size.
+    Sha512(Sha512),
 }
 
 /// The individual implementation of the hash function.
@@ -249,6 +296,7 @@ impl DigestHasher for DigestHasherImpl {
             DigestHasherFuncImpl::Blake3(h) => {
                 Blake3Hasher::update(h, input);
             }
+            DigestHasherFuncImpl::Sha512(h) => sha2::digest::Update::update(h, input),
         }
     }
 
@@ -257,6 +305,11 @@ impl DigestHasher for DigestHasherImpl {
         let hash = match &mut self.hash_func_impl {
             DigestHasherFuncImpl::Sha256(h) => h.finalize_reset().into(),
             DigestHasherFuncImpl::Blake3(h) => h.finalize().into(),
+            DigestHasherFuncImpl::Sha512(h) => {
+                let mut hash = [0u8; MAX_SIZE_OF_PACKED_HASH];
+                hash.copy_from_slice(&h.finalize_reset());
+                return DigestInfo::new_512(hash, self.hashed_size);
+            }
         };
         DigestInfo::new(hash, self.hashed_size)
     }
@@ -281,7 +334,9 @@ impl DigestHasher for DigestHasherImpl {
             }
         }
         match self.hash_func_impl {
-            DigestHasherFuncImpl::Sha256(_) => self.hash_file(file).await,
+            DigestHasherFuncImpl::Sha256(_) | DigestHasherFuncImpl::Sha512(_) => {
+                self.hash_file(file).await
+            }
             DigestHasherFuncImpl::Blake3(mut hasher) => {
                 spawn_blocking!("digest_for_file", move || {
                     hasher.update_mmap(file.get_path()).map_err(|e| {
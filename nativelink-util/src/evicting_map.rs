@@ -31,6 +31,22 @@ use tracing::{event, Level};
 use crate::instant_wrapper::InstantWrapper;
 use crate::metrics_utils::{Counter, CounterWithTime};
 
+/// Percentage of `max_bytes` used as `evict_bytes` by
+/// [`with_default_evict_bytes`].
+pub const DEFAULT_EVICT_BYTES_PERCENT: usize = 10;
+
+/// Returns a copy of `policy` whose `evict_bytes` is
+/// `DEFAULT_EVICT_BYTES_PERCENT` of `max_bytes` if `max_bytes` is set and
+/// `evict_bytes` is not, so eviction frees some room at once instead of a
+/// single entry per insert once the map is full.
+pub fn with_default_evict_bytes(policy: &EvictionPolicy) -> EvictionPolicy {
+    let mut policy = policy.clone();
+    if policy.max_bytes != 0 && policy.evict_bytes == 0 {
+        policy.evict_bytes = policy.max_bytes / 100 * DEFAULT_EVICT_BYTES_PERCENT;
+    }
+    policy
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct SerializedLRU<K> {
     pub data: Vec<(K, i32)>,
@@ -73,6 +89,14 @@ pub trait LenEntry: 'static {
     fn unref(&self) -> impl Future<Output = ()> + Send {
         std::future::ready(())
     }
+
+    /// Called instead of `unref()` when the entry is removed from the map
+    /// because it was evicted, rather than replaced or explicitly removed.
+    /// The same rules as for `unref()` apply.
+    #[inline]
+    fn evict(&self) -> impl Future<Output = ()> + Send {
+        self.unref()
+    }
 }
 
 impl<T: LenEntry + Send + Sync> LenEntry for Arc<T> {
@@ -95,6 +119,22 @@ impl<T: LenEntry + Send + Sync> LenEntry for Arc<T> {
     async fn unref(&self) {
         self.as_ref().unref().await;
     }
+
+    #[inline]
+    async fn evict(&self) {
+        self.as_ref().evict().await;
+    }
+}
+
+/// Why an item is removed from the map.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RemovalReason {
+    /// The item was expired or the map was full.
+    Evicted,
+    /// A new item was inserted with the same key.
+    Replaced,
+    /// The item was removed explicitly or could no longer be touched.
+    Removed,
 }
 
 #[derive(MetricsComponent)]
@@ -112,13 +152,17 @@ struct State<K: Ord + Hash + Eq + Clone + Debug, T: LenEntry + Debug> {
     replaced_bytes: Counter,
     #[metric(help = "Number of items replaced in the store")]
     replaced_items: CounterWithTime,
+    #[metric(help = "Number of bytes explicitly removed from the store")]
+    removed_bytes: Counter,
+    #[metric(help = "Number of items explicitly removed from the store")]
+    removed_items: CounterWithTime,
     #[metric(help = "Number of bytes inserted into the store since it was created")]
     lifetime_inserted_bytes: Counter,
 }
 
 impl<K: Ord + Hash + Eq + Clone + Debug, T: LenEntry + Debug + Sync> State<K, T> {
     /// Removes an item from the cache.
-    async fn remove<Q>(&mut self, key: &Q, eviction_item: &EvictionItem<T>, replaced: bool)
+    async fn remove<Q>(&mut self, key: &Q, eviction_item: &EvictionItem<T>, reason: RemovalReason)
     where
         K: Borrow<Q>,
         Q: Ord + Hash + Eq + Debug,
@@ -127,15 +171,19 @@ impl<K: Ord + Hash + Eq + Clone + Debug, T: LenEntry + Debug + Sync> State<K, T>
             btree.remove(key.borrow());
         }
         self.sum_store_size -= eviction_item.data.len();
-        if replaced {
-            self.replaced_items.inc();
-            self.replaced_bytes.add(eviction_item.data.len());
+        let (items, bytes) = match reason {
+            RemovalReason::Evicted => (&self.evicted_items, &self.evicted_bytes),
+            RemovalReason::Replaced => (&self.replaced_items, &self.replaced_bytes),
+            RemovalReason::Removed => (&self.removed_items, &self.removed_bytes),
+        };
+        items.inc();
+        bytes.add(eviction_item.data.len());
+        // Note: See comment in `unref()` requring global lock of insert/remove.
+        if reason == RemovalReason::Evicted {
+            eviction_item.data.evict().await;
         } else {
-            self.evicted_items.inc();
-            self.evicted_bytes.add(eviction_item.data.len());
+            eviction_item.data.unref().await;
         }
-        // Note: See comment in `unref()` requring global lock of insert/remove.
-        eviction_item.data.unref().await;
     }
 
     /// Inserts a new item into the cache. If the key already exists, the old item is returned.
@@ -145,7 +193,7 @@ impl<K: Ord + Hash + Eq + Clone + Debug, T: LenEntry + Debug + Sync> State<K, T>
             btree.insert(key.clone());
         }
         if let Some(old_item) = self.lru.put(key.clone(), eviction_item) {
-            self.remove(&key, &old_item, true).await;
+            self.remove(&key, &old_item, RemovalReason::Replaced).await;
             return Some(old_item.data);
         }
         None
@@ -185,6 +233,8 @@ where
                 evicted_items: CounterWithTime::default(),
                 replaced_bytes: Counter::default(),
                 replaced_items: CounterWithTime::default(),
+                removed_bytes: Counter::default(),
+                removed_items: CounterWithTime::default(),
                 lifetime_inserted_bytes: Counter::default(),
             }),
             anchor_time,
@@ -287,8 +337,18 @@ where
                 .lru
                 .pop_lru()
                 .expect("Tried to peek() then pop() but failed");
-            event!(Level::INFO, ?key, "Evicting",);
-            state.remove(&key, &eviction_item, false).await;
+            let age_secs = (self.anchor_time.elapsed().as_secs() as i32)
+                .saturating_sub(eviction_item.seconds_since_anchor);
+            event!(
+                Level::INFO,
+                ?key,
+                size = eviction_item.data.len(),
+                age_secs,
+                "Evicting",
+            );
+            state
+                .remove(&key, &eviction_item, RemovalReason::Evicted)
+                .await;
 
             peek_entry = if let Some((_, entry)) = state.lru.peek_lru() {
                 entry
@@ -326,6 +386,38 @@ where
         K: Borrow<Q>,
         R: Borrow<Q>,
         Q: Ord + Hash + Eq + Debug,
+    {
+        self.inner_sizes_for_keys(keys, results, peek, true).await;
+    }
+
+    /// Same as `sizes_for_keys()` without `peek`, but found items are only
+    /// promoted in memory and never `touch()`ed. Answering from the map alone
+    /// avoids any I/O `touch()` may do, at the cost of not noticing entries
+    /// whose backing data disappeared until they are read with `get()`.
+    pub async fn sizes_for_keys_without_touch<It, Q, R>(
+        &self,
+        keys: It,
+        results: &mut [Option<u64>],
+    ) where
+        It: IntoIterator<Item = R>,
+        K: Borrow<Q>,
+        R: Borrow<Q>,
+        Q: Ord + Hash + Eq + Debug,
+    {
+        self.inner_sizes_for_keys(keys, results, false, false).await;
+    }
+
+    async fn inner_sizes_for_keys<It, Q, R>(
+        &self,
+        keys: It,
+        results: &mut [Option<u64>],
+        peek: bool,
+        touch: bool,
+    ) where
+        It: IntoIterator<Item = R>,
+        K: Borrow<Q>,
+        R: Borrow<Q>,
+        Q: Ord + Hash + Eq + Debug,
     {
         let mut state = self.state.lock().await;
 
@@ -346,18 +438,20 @@ where
                     let should_evict = self.should_evict(lru_len, entry, 0, u64::MAX);
                     if !should_evict && peek {
                         *result = Some(entry.data.len());
-                    } else if !should_evict && entry.data.touch().await {
+                    } else if !should_evict && (!touch || entry.data.touch().await) {
                         entry.seconds_since_anchor = self.anchor_time.elapsed().as_secs() as i32;
                         *result = Some(entry.data.len());
                     } else {
                         *result = None;
                         if let Some((key, eviction_item)) = state.lru.pop_entry(key.borrow()) {
-                            if should_evict {
+                            let reason = if should_evict {
                                 event!(Level::INFO, ?key, "Item expired, evicting");
+                                RemovalReason::Evicted
                             } else {
                                 event!(Level::INFO, ?key, "Touch failed, evicting");
-                            }
-                            state.remove(key.borrow(), &eviction_item, false).await;
+                                RemovalReason::Removed
+                            };
+                            state.remove(key.borrow(), &eviction_item, reason).await;
                         }
                     }
                 }
@@ -383,7 +477,9 @@ where
 
         let (key, eviction_item) = state.lru.pop_entry(key.borrow())?;
         event!(Level::INFO, ?key, "Touch failed, evicting");
-        state.remove(key.borrow(), &eviction_item, false).await;
+        state
+            .remove(key.borrow(), &eviction_item, RemovalReason::Removed)
+            .await;
         None
     }
 
@@ -455,12 +551,31 @@ where
     {
         self.evict_items(state).await;
         if let Some(entry) = state.lru.pop(key.borrow()) {
-            state.remove(key, &entry, false).await;
+            state.remove(key, &entry, RemovalReason::Removed).await;
             return true;
         }
         false
     }
 
+    /// Removes every item whose key matches `predicate` and returns the
+    /// number of items removed.
+    pub async fn remove_matching(&self, mut predicate: impl FnMut(&K) -> bool) -> u64 {
+        let mut state = self.state.lock().await;
+        let keys: Vec<K> = state
+            .lru
+            .iter()
+            .filter(|(key, _)| predicate(key))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut removed = 0;
+        for key in keys {
+            if self.inner_remove(&mut state, &key).await {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
     /// Same as `remove()`, but allows for a conditional to be applied to the
     /// entry before removal in an atomic fashion.
     pub async fn remove_if<Q, F: FnOnce(&T) -> bool>(&self, key: &Q, cond: F) -> bool
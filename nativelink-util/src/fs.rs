@@ -159,6 +159,44 @@ impl ResumeableFileSlot {
         Ok(self.as_reader().await?.get_mut())
     }
 
+    /// Advises the kernel that `len` bytes starting at `offset` will be read
+    /// sequentially, so it reads ahead more aggressively. A `len` of zero
+    /// covers everything to the end of the file. This is only a hint, so it
+    /// does nothing on platforms without `posix_fadvise`.
+    /// Note: The advice is lost if the file gets closed and resumed.
+    pub async fn advise_sequential(&mut self, offset: u64, len: u64) -> Result<(), Error> {
+        let file_slot = self
+            .as_reader()
+            .await
+            .err_tip(|| "Could not get reader from file slot in advise_sequential")?;
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            let offset = libc::off_t::try_from(offset)
+                .err_tip(|| "Could not convert offset to off_t in advise_sequential")?;
+            let len = libc::off_t::try_from(len)
+                .err_tip(|| "Could not convert len to off_t in advise_sequential")?;
+            // SAFETY: The file descriptor is owned by `file_slot` which
+            // outlives this call.
+            let ret = unsafe {
+                libc::posix_fadvise(
+                    file_slot.get_ref().inner.as_raw_fd(),
+                    offset,
+                    len,
+                    libc::POSIX_FADV_SEQUENTIAL,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::from(std::io::Error::from_raw_os_error(ret)))
+                    .err_tip(|| format!("posix_fadvise failed on {:?}", self.path));
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (file_slot, offset, len);
+        Ok(())
+    }
+
     /// Utility function to read data from a handler and handles file descriptor
     /// timeouts. Chunk size is based on the `buf`'s capacity.
     /// Note: If the `handler` changes `buf`s capacity, it is responsible for reserving
@@ -504,3 +542,37 @@ pub async fn remove_dir_all(path: impl AsRef<Path>) -> Result<(), Error> {
     let path = path.as_ref().to_owned();
     call_with_permit(move |_| std::fs::remove_dir_all(path).map_err(Into::<Error>::into)).await
 }
+
+/// Returns the number of bytes available to unprivileged users on the
+/// filesystem containing `path`.
+pub async fn available_space(path: impl AsRef<Path>) -> Result<u64, Error> {
+    let path = path.as_ref().to_owned();
+    call_with_permit(move |_| {
+        #[cfg(target_family = "unix")]
+        {
+            use std::ffi::CString;
+            use std::os::unix::ffi::OsStrExt;
+
+            let c_path = CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| make_err!(Code::InvalidArgument, "Invalid path {path:?}: {e:?}"))?;
+            let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+            // SAFETY: `c_path` is a valid nul terminated string and `stat` is
+            // only read after `statvfs` succeeded and initialized it.
+            let stat = unsafe {
+                if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+                    return Err(Error::from(std::io::Error::last_os_error()))
+                        .err_tip(|| format!("statvfs failed on {path:?}"));
+                }
+                stat.assume_init()
+            };
+            #[allow(clippy::useless_conversion)]
+            Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+        }
+        #[cfg(not(target_family = "unix"))]
+        Err(make_err!(
+            Code::Unimplemented,
+            "available_space is not supported on this platform for {path:?}"
+        ))
+    })
+    .await
+}
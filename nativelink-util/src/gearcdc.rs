@@ -0,0 +1,125 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::Decoder;
+
+use crate::fastcdc::TABLE;
+
+/// Content defined chunker based on a plain Gear rolling hash, as used by
+/// `FastCDC` before normalized chunking was added.
+/// see: <https://www.usenix.org/system/files/conference/atc16/atc16-paper-xia.pdf>
+///
+/// A boundary is placed after the first byte past `min_size` where the top
+/// bits of the hash are all zero, or at `max_size` if no such byte is found.
+/// Unlike [`FastCDC`](crate::fastcdc::FastCDC) a single mask is used for the
+/// whole chunk, so chunk sizes are more spread out around `avg_size`.
+pub struct GearCDC {
+    min_size: usize,
+    max_size: usize,
+
+    mask: u32,
+
+    hash: u32,
+    position: usize,
+}
+
+impl GearCDC {
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        assert!(min_size < avg_size, "Expected {min_size} < {avg_size}");
+        assert!(avg_size < max_size, "Expected {avg_size} < {max_size}");
+        // Bytes before `min_size` are skipped, so only the remainder needs
+        // to be covered by the mask.
+        let bits = ((avg_size - min_size) as f64)
+            .log2()
+            .round()
+            .clamp(1.0, 31.0) as u32;
+        Self {
+            min_size,
+            max_size,
+
+            // The high bits of a gear hash are influenced by the most bytes,
+            // so those are the ones we check.
+            mask: (2u32.pow(bits) - 1) << (32 - bits),
+
+            hash: 0,
+            position: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.hash = 0;
+        self.position = 0;
+    }
+}
+
+impl Decoder for GearCDC {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if buf.len() <= self.min_size {
+            return Ok(None);
+        }
+        let start_point = std::cmp::max(self.position, self.min_size);
+        let end_point = std::cmp::min(buf.len(), self.max_size);
+
+        let mut split_point = None;
+        for (i, byte) in buf[start_point..end_point].iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(TABLE[*byte as usize]);
+            if (self.hash & self.mask) == 0 {
+                split_point = Some(start_point + i + 1);
+                break;
+            }
+        }
+        if split_point.is_none() && end_point == self.max_size {
+            split_point = Some(self.max_size);
+        }
+
+        if let Some(split_point) = split_point {
+            self.reset();
+            return Ok(Some(buf.split_to(split_point).freeze()));
+        }
+        self.position = end_point;
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(frame) = self.decode(buf)? {
+            Ok(Some(frame))
+        } else {
+            self.reset();
+            if buf.is_empty() {
+                // If our buffer is empty we don't have any more data.
+                return Ok(None);
+            }
+            Ok(Some(buf.split().freeze()))
+        }
+    }
+}
+
+impl Clone for GearCDC {
+    /// Clone configuration but with new state, the same as `FastCDC`.
+    fn clone(&self) -> Self {
+        Self {
+            min_size: self.min_size,
+            max_size: self.max_size,
+
+            mask: self.mask,
+
+            hash: 0,
+            position: 0,
+        }
+    }
+}
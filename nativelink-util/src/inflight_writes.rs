@@ -0,0 +1,98 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nativelink_error::{make_err, Code, Error};
+use tokio::sync::watch;
+
+struct InflightWritesInner {
+    draining: AtomicBool,
+    count: watch::Sender<usize>,
+}
+
+/// Tracks writes that are in flight so they can be drained before the
+/// process shuts down. Once draining has started new writes are rejected.
+#[derive(Clone)]
+pub struct InflightWrites {
+    inner: Arc<InflightWritesInner>,
+}
+
+impl Default for InflightWrites {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(InflightWritesInner {
+                draining: AtomicBool::new(false),
+                count: watch::channel(0).0,
+            }),
+        }
+    }
+}
+
+impl InflightWrites {
+    /// Registers a new write. The write is considered in flight until the
+    /// returned guard is dropped. Fails with `Unavailable` if draining has
+    /// started.
+    pub fn try_start(&self) -> Result<InflightWriteGuard, Error> {
+        // Register before checking the flag, so `drain()` either sees this
+        // write or this write sees the flag.
+        self.inner.count.send_modify(|count| *count += 1);
+        let guard = InflightWriteGuard {
+            inner: self.inner.clone(),
+        };
+        if self.inner.draining.load(Ordering::SeqCst) {
+            return Err(make_err!(
+                Code::Unavailable,
+                "Server is shutting down and no longer accepts writes"
+            ));
+        }
+        Ok(guard)
+    }
+
+    /// Number of writes that are currently in flight.
+    pub fn count(&self) -> usize {
+        *self.inner.count.borrow()
+    }
+
+    /// Stops accepting new writes and waits up to `timeout` for the writes
+    /// in flight to finish.
+    pub async fn drain(&self, timeout: Duration) -> Result<(), Error> {
+        self.inner.draining.store(true, Ordering::SeqCst);
+        let mut rx = self.inner.count.subscribe();
+        if tokio::time::timeout(timeout, rx.wait_for(|count| *count == 0))
+            .await
+            .is_err()
+        {
+            return Err(make_err!(
+                Code::DeadlineExceeded,
+                "{} writes were still in flight after {timeout:?}",
+                *rx.borrow()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Marks a write as in flight until dropped.
+pub struct InflightWriteGuard {
+    inner: Arc<InflightWritesInner>,
+}
+
+impl Drop for InflightWriteGuard {
+    fn drop(&mut self) {
+        self.inner.count.send_modify(|count| *count -= 1);
+    }
+}
@@ -0,0 +1,44 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use nativelink_config::cas_server::InstanceName;
+
+/// Client facing instance names and the instance they are served as. Shared
+/// by every service, so aliases resolve the same way everywhere.
+#[derive(Clone, Debug, Default)]
+pub struct InstanceNameAliases {
+    aliases: HashMap<InstanceName, InstanceName>,
+}
+
+impl InstanceNameAliases {
+    pub fn new(aliases: HashMap<InstanceName, InstanceName>) -> Self {
+        Self { aliases }
+    }
+
+    /// Returns the instance requests for `instance_name` are served as.
+    pub fn resolve<'a>(&'a self, instance_name: &'a str) -> &'a str {
+        self.aliases
+            .get(instance_name)
+            .map_or(instance_name, String::as_str)
+    }
+
+    /// Replaces `instance_name` with the instance it is served as.
+    pub fn resolve_in_place(&self, instance_name: &mut String) {
+        if let Some(aliased_instance_name) = self.aliases.get(instance_name.as_str()) {
+            instance_name.clone_from(aliased_instance_name);
+        }
+    }
+}
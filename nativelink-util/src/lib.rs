@@ -22,7 +22,10 @@ pub mod digest_hasher;
 pub mod evicting_map;
 pub mod fastcdc;
 pub mod fs;
+pub mod gearcdc;
 pub mod health_utils;
+pub mod inflight_writes;
+pub mod instance_name_aliases;
 pub mod instant_wrapper;
 pub mod known_platform_property_provider;
 pub mod metrics_utils;
@@ -44,6 +47,23 @@ pub mod write_counter;
 // Re-export tracing mostly for use in macros.
 pub use tracing as __tracing;
 
+/// Layer used when `NL_LOG=json` that writes one JSON object per line to
+/// `make_writer`. Every line includes the current span and the list of its
+/// parents with their fields, so fields like `instance_name` recorded on a
+/// request span are attached to everything logged while serving the request.
+pub fn json_log_layer<S, W>(make_writer: W) -> impl tracing_subscriber::Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_timer(tracing_subscriber::fmt::time::time())
+        .with_writer(make_writer)
+}
+
 /// Initialize tracing.
 pub fn init_tracing() -> Result<(), nativelink_error::Error> {
     use tracing_subscriber::prelude::*;
@@ -78,9 +98,7 @@ pub fn init_tracing() -> Result<(), nativelink_error::Error> {
                 .boxed(),
         ),
         "json" => layers.push(
-            tracing_subscriber::fmt::layer()
-                .json()
-                .with_timer(tracing_subscriber::fmt::time::time())
+            json_log_layer(std::io::stdout)
                 .with_filter(env_filter)
                 .boxed(),
         ),
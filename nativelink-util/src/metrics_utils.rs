@@ -15,7 +15,7 @@
 use std::mem::forget;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread_local;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::Future;
 use nativelink_metric::{
@@ -367,3 +367,84 @@ impl MetricsComponent for CounterWithTime {
         Ok(MetricPublishKnownKindData::Component)
     }
 }
+
+/// Upper bounds of the buckets of a `DurationHistogram`.
+const DURATION_HISTOGRAM_BUCKETS: [(&str, Duration); 5] = [
+    ("le_1ms", Duration::from_millis(1)),
+    ("le_10ms", Duration::from_millis(10)),
+    ("le_100ms", Duration::from_millis(100)),
+    ("le_1s", Duration::from_secs(1)),
+    ("le_10s", Duration::from_secs(10)),
+];
+
+/// Tracks the distribution of durations in a fixed set of cumulative
+/// buckets, like a prometheus histogram.
+#[derive(Default)]
+pub struct DurationHistogram {
+    buckets: [AtomicU64; DURATION_HISTOGRAM_BUCKETS.len()],
+    count: AtomicU64,
+    // 64 bit address space gives ~584 years of nanoseconds.
+    sum_ns: AtomicU64,
+}
+
+impl DurationHistogram {
+    #[inline]
+    pub fn observe(&self, duration: Duration) {
+        if !metrics_enabled() {
+            return;
+        }
+        for (bucket, (_, upper_bound)) in self.buckets.iter().zip(DURATION_HISTOGRAM_BUCKETS) {
+            if duration <= upper_bound {
+                bucket.fetch_add(1, Ordering::Acquire);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Acquire);
+        self.sum_ns.fetch_add(
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Acquire,
+        );
+    }
+}
+
+// Derive-macros have no way to tell the collector that the parent
+// is now a group with the name of the group as the field so we
+// can attach multiple values on the same group, so we need to
+// manually implement the `MetricsComponent` trait to do so.
+impl MetricsComponent for DurationHistogram {
+    fn publish(
+        &self,
+        _kind: MetricKind,
+        field_metadata: MetricFieldData,
+    ) -> Result<MetricPublishKnownKindData, nativelink_metric::Error> {
+        let _enter = group!(field_metadata.name).entered();
+
+        for (bucket, (name, upper_bound)) in self.buckets.iter().zip(DURATION_HISTOGRAM_BUCKETS) {
+            publish!(
+                name,
+                bucket,
+                MetricKind::Counter,
+                format!(
+                    "The number of {} that took at most {upper_bound:?}.",
+                    field_metadata.name
+                )
+            );
+        }
+        publish!(
+            "count",
+            &self.count,
+            MetricKind::Counter,
+            format!("The number of {} observed.", field_metadata.name)
+        );
+        publish!(
+            "sum_ns",
+            &self.sum_ns,
+            MetricKind::Counter,
+            format!(
+                "The sum of the time spent in nanoseconds in {}.",
+                field_metadata.name
+            )
+        );
+
+        Ok(MetricPublishKnownKindData::Component)
+    }
+}
@@ -26,6 +26,8 @@ use std::task::{Context, Poll};
 use futures::Future;
 use nativelink_error::{make_err, Code, Error};
 use pin_project_lite::pin_project;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::Request;
 use tracing::instrument::Instrumented;
 use tracing::{Instrument, Span};
 
@@ -56,6 +58,38 @@ macro_rules! make_symbol {
 // See: IdentityHeaderSpec for details.
 make_symbol!(ORIGIN_IDENTITY, String);
 
+// Symbol that represents the trace id of the action a request is made on
+// behalf of. The scheduler's operation id is used as the trace id, since it
+// is created when the action is added and is known to both the scheduler
+// and the worker.
+make_symbol!(ACTION_TRACE_ID, String);
+
+/// gRPC metadata key used to forward the `ACTION_TRACE_ID` to other services.
+pub const TRACE_ID_HEADER: &str = "x-nativelink-trace-id";
+
+/// Wraps `message` in a [`Request`] that carries the `ACTION_TRACE_ID` of the
+/// active context in its metadata, if one is set.
+pub fn make_traced_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    let maybe_trace_id = ActiveOriginContext::get_value(&ACTION_TRACE_ID)
+        .ok()
+        .flatten()
+        .and_then(|trace_id| MetadataValue::try_from(trace_id.as_str()).ok());
+    if let Some(trace_id) = maybe_trace_id {
+        request.metadata_mut().insert(TRACE_ID_HEADER, trace_id);
+    }
+    request
+}
+
+/// Returns the trace id a client sent in the [`TRACE_ID_HEADER`] metadata,
+/// or an empty string if it did not send one.
+pub fn trace_id_from_metadata(metadata: &MetadataMap) -> &str {
+    metadata
+        .get(TRACE_ID_HEADER)
+        .and_then(|trace_id| trace_id.to_str().ok())
+        .unwrap_or_default()
+}
+
 pub struct NLSymbol<T: Send + Sync + 'static> {
     pub name: &'static str,
     pub _phantom: std::marker::PhantomData<T>,
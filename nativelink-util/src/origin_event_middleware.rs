@@ -28,7 +28,9 @@ use tower::layer::Layer;
 use tower::Service;
 use tracing::trace_span;
 
-use crate::origin_context::{ActiveOriginContext, ORIGIN_IDENTITY};
+use crate::origin_context::{
+    ActiveOriginContext, ACTION_TRACE_ID, ORIGIN_IDENTITY, TRACE_ID_HEADER,
+};
 use crate::origin_event::{OriginEventCollector, ORIGIN_EVENT_COLLECTOR};
 
 /// Default identity header name.
@@ -128,6 +130,14 @@ where
             context.set_value(&ORIGIN_IDENTITY, Arc::new(identity.clone()));
             identity
         };
+        // Forward the trace id to any upstream requests made on behalf of this one.
+        if let Some(trace_id) = req
+            .headers()
+            .get(TRACE_ID_HEADER)
+            .and_then(|header| header.to_str().ok())
+        {
+            context.set_value(&ACTION_TRACE_ID, Arc::new(trace_id.to_string()));
+        }
         if let Some(origin_event_tx) = &self.maybe_origin_event_tx {
             let bazel_metadata = req
                 .headers()
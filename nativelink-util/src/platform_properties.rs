@@ -54,6 +54,29 @@ impl PlatformProperties {
         }
         true
     }
+
+    /// Returns the number of `Priority` properties in this struct that have
+    /// the exact same value on the worker. This does not restrict which
+    /// workers may be selected, but is used to prefer workers that better
+    /// match the requested priority properties.
+    #[must_use]
+    pub fn priority_match_count(&self, worker_properties: &Self) -> usize {
+        self.properties
+            .iter()
+            .filter(|(property, check_value)| {
+                matches!(check_value, PlatformPropertyValue::Priority(_))
+                    && worker_properties.properties.get(*property) == Some(*check_value)
+            })
+            .count()
+    }
+
+    /// Returns true if any of the properties are `Priority` properties.
+    #[must_use]
+    pub fn has_priority_properties(&self) -> bool {
+        self.properties
+            .values()
+            .any(|value| matches!(value, PlatformPropertyValue::Priority(_)))
+    }
 }
 
 impl From<ProtoPlatform> for PlatformProperties {
@@ -69,6 +92,22 @@ impl From<ProtoPlatform> for PlatformProperties {
     }
 }
 
+/// Merges the platform of an `Action` with the platform of its `Command`
+/// into a single map of property names to values. REAPI allows both to be
+/// set; if they share a property name, the `Command`'s value is used.
+#[must_use]
+pub fn merge_proto_platforms(
+    action_platform: Option<ProtoPlatform>,
+    command_platform: Option<ProtoPlatform>,
+) -> HashMap<String, String> {
+    action_platform
+        .into_iter()
+        .chain(command_platform)
+        .flat_map(|platform| platform.properties)
+        .map(|property| (property.name, property.value))
+        .collect()
+}
+
 /// Holds the associated value of the key and type.
 ///
 /// Exact    - Means the worker must have this exact value.
@@ -77,10 +116,9 @@ impl From<ProtoPlatform> for PlatformProperties {
 ///            this value subtracted from the available resources of the worker.
 /// Priority - Means the worker is given this information, but does not restrict
 ///            what workers can take this value. However, the worker must have the
-///            associated key present to be matched.
-///            TODO(allada) In the future this will be used by the scheduler and
-///            worker to cause the scheduler to prefer certain workers over others,
-///            but not restrict them based on these values.
+///            associated key present to be matched. When multiple workers can
+///            take the action, the scheduler prefers the workers that have the
+///            most priority values matching exactly.
 #[derive(Eq, PartialEq, Hash, Clone, Ord, PartialOrd, Debug, Serialize, Deserialize)]
 pub enum PlatformPropertyValue {
     Exact(String),
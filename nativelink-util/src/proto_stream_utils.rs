@@ -94,9 +94,11 @@ where
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         // If the stream said that the previous message was the last one, then
         // return a stream EOF (i.e. None).
+        // The size of compressed data is only known once decompressed.
         if self.write_finished {
             error_if!(
-                self.bytes_received != self.resource_info.expected_size,
+                !self.resource_info.is_compressed()
+                    && self.bytes_received != self.resource_info.expected_size,
                 "Did not send enough data. Expected {}, but so far received {}",
                 self.resource_info.expected_size,
                 self.bytes_received
@@ -124,7 +126,9 @@ where
             self.bytes_received += message.data.len();
 
             // Check that we haven't read past the expected end.
-            if self.bytes_received > self.resource_info.expected_size {
+            if !self.resource_info.is_compressed()
+                && self.bytes_received > self.resource_info.expected_size
+            {
                 Err(make_input_err!(
                     "Sent too much data. Expected {}, but so far received {}",
                     self.resource_info.expected_size,
@@ -149,6 +149,14 @@ impl<'a> ResourceInfo<'a> {
         Ok(output)
     }
 
+    /// Returns true if the data of the resource is compressed, in which case
+    /// `expected_size` is the size of the uncompressed data.
+    pub fn is_compressed(&self) -> bool {
+        self.compressor
+            .as_deref()
+            .is_some_and(|compressor| compressor != "identity")
+    }
+
     /// Returns a new `ResourceInfo` with all fields owned.
     pub fn to_owned(&self) -> ResourceInfo<'static> {
         ResourceInfo {
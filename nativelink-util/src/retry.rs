@@ -18,10 +18,16 @@ use std::time::Duration;
 
 use futures::future::Future;
 use futures::stream::StreamExt;
-use nativelink_config::stores::{ErrorCode, Retry};
+use nativelink_config::stores::{ErrorCode, Retry, RetryBudget};
 use nativelink_error::{make_err, Code, Error};
+use parking_lot::Mutex;
 use tracing::{event, Level};
 
+// NOTE: If these change update the comments in `stores.rs` to reflect
+// the new defaults.
+const DEFAULT_RETRY_BUDGET_MAX_TOKENS: u32 = 100;
+const DEFAULT_RETRY_BUDGET_TOKEN_RATIO: f32 = 0.1;
+
 struct ExponentialBackoff {
     current: Duration,
 }
@@ -41,6 +47,48 @@ impl Iterator for ExponentialBackoff {
     }
 }
 
+/// Token bucket shared by every clone of a `Retrier`, see `RetryBudget`.
+struct RetryBudgetState {
+    tokens: Mutex<f64>,
+    max_tokens: f64,
+    token_ratio: f64,
+}
+
+impl RetryBudgetState {
+    fn new(config: &RetryBudget) -> Self {
+        let max_tokens = if config.max_tokens == 0 {
+            DEFAULT_RETRY_BUDGET_MAX_TOKENS
+        } else {
+            config.max_tokens
+        };
+        let token_ratio = if config.token_ratio == 0. {
+            DEFAULT_RETRY_BUDGET_TOKEN_RATIO
+        } else {
+            config.token_ratio
+        };
+        Self {
+            tokens: Mutex::new(f64::from(max_tokens)),
+            max_tokens: f64::from(max_tokens),
+            token_ratio: f64::from(token_ratio),
+        }
+    }
+
+    /// Takes the token for a single retry, returns false if there is none.
+    fn try_take(&self) -> bool {
+        let mut tokens = self.tokens.lock();
+        if *tokens < 1. {
+            return false;
+        }
+        *tokens -= 1.;
+        true
+    }
+
+    fn on_success(&self) {
+        let mut tokens = self.tokens.lock();
+        *tokens = (*tokens + self.token_ratio).min(self.max_tokens);
+    }
+}
+
 type SleepFn = Arc<dyn Fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> + Sync + Send>;
 pub(crate) type JitterFn = Arc<dyn Fn(Duration) -> Duration + Send + Sync>;
 
@@ -52,11 +100,13 @@ pub enum RetryResult<T> {
 }
 
 /// Class used to retry a job with a sleep function in between each retry.
+/// Clones share the retry budget of the original.
 #[derive(Clone)]
 pub struct Retrier {
     sleep_fn: SleepFn,
     jitter_fn: JitterFn,
     config: Retry,
+    budget: Option<Arc<RetryBudgetState>>,
 }
 
 fn to_error_code(code: Code) -> ErrorCode {
@@ -82,10 +132,15 @@ fn to_error_code(code: Code) -> ErrorCode {
 
 impl Retrier {
     pub fn new(sleep_fn: SleepFn, jitter_fn: JitterFn, config: Retry) -> Self {
+        let budget = config
+            .retry_budget
+            .as_ref()
+            .map(|budget| Arc::new(RetryBudgetState::new(budget)));
         Retrier {
             sleep_fn,
             jitter_fn,
             config,
+            budget,
         }
     }
 
@@ -151,7 +206,12 @@ impl Retrier {
                             "Retry stream ended abruptly on attempt {attempt}",
                         ))
                     }
-                    Some(RetryResult::Ok(value)) => return Ok(value),
+                    Some(RetryResult::Ok(value)) => {
+                        if let Some(budget) = &self.budget {
+                            budget.on_success();
+                        }
+                        return Ok(value);
+                    }
                     Some(RetryResult::Err(e)) => {
                         return Err(e.append(format!("On attempt {attempt}")));
                     }
@@ -160,11 +220,23 @@ impl Retrier {
                             event!(Level::ERROR, ?attempt, ?err, "Not retrying permanent error");
                             return Err(err);
                         }
-                        (self.sleep_fn)(
-                            iter.next()
-                                .ok_or(err.append(format!("On attempt {attempt}")))?,
-                        )
-                        .await;
+                        let Some(delay) = iter.next() else {
+                            return Err(err.append(format!("On attempt {attempt}")));
+                        };
+                        if let Some(budget) = &self.budget {
+                            if !budget.try_take() {
+                                event!(
+                                    Level::WARN,
+                                    ?attempt,
+                                    ?err,
+                                    "Not retrying because the retry budget is exhausted"
+                                );
+                                return Err(err.append(format!(
+                                    "Retry budget exhausted on attempt {attempt}"
+                                )));
+                            }
+                        }
+                        (self.sleep_fn)(delay).await;
                     }
                 }
             }
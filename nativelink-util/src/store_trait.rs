@@ -329,6 +329,49 @@ impl From<&DigestInfo> for StoreKey<'_> {
     }
 }
 
+pub const ZERO_BYTE_DIGESTS: [DigestInfo; 3] = [
+    // Sha256 hash of zero bytes.
+    DigestInfo::new(
+        [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ],
+        0,
+    ),
+    // Blake3 hash of zero bytes.
+    DigestInfo::new(
+        [
+            0xaf, 0x13, 0x49, 0xb9, 0xf5, 0xf9, 0xa1, 0xa6, 0xa0, 0x40, 0x4d, 0xea, 0x36, 0xdc,
+            0xc9, 0x49, 0x9b, 0xcb, 0x25, 0xc9, 0xad, 0xc1, 0x12, 0xb7, 0xcc, 0x9a, 0x93, 0xca,
+            0xe4, 0x1f, 0x32, 0x62,
+        ],
+        0,
+    ),
+    // Sha512 hash of zero bytes.
+    DigestInfo::new_512(
+        [
+            0xcf, 0x83, 0xe1, 0x35, 0x7e, 0xef, 0xb8, 0xbd, 0xf1, 0x54, 0x28, 0x50, 0xd6, 0x6d,
+            0x80, 0x07, 0xd6, 0x20, 0xe4, 0x05, 0x0b, 0x57, 0x15, 0xdc, 0x83, 0xf4, 0xa9, 0x21,
+            0xd3, 0x6c, 0xe9, 0xce, 0x47, 0xd0, 0xd1, 0x3c, 0x5d, 0x85, 0xf2, 0xb0, 0xff, 0x83,
+            0x18, 0xd2, 0x87, 0x7e, 0xec, 0x2f, 0x63, 0xb9, 0x31, 0xbd, 0x47, 0x41, 0x7a, 0x81,
+            0xa5, 0x38, 0x32, 0x7a, 0xf9, 0x27, 0xda, 0x3e,
+        ],
+        0,
+    ),
+];
+
+/// Returns true if `digest` is the digest of the empty blob. The empty blob
+/// always exists, so `StoreLike` reports it as present and reads it without
+/// it ever being uploaded.
+#[inline]
+pub fn is_zero_digest<'a>(digest: impl Into<StoreKey<'a>>) -> bool {
+    match digest.into() {
+        StoreKey::Digest(digest) => digest.size_bytes() == 0 && ZERO_BYTE_DIGESTS.contains(&digest),
+        StoreKey::Str(_) => false,
+    }
+}
+
 #[derive(Clone, MetricsComponent)]
 #[repr(transparent)]
 pub struct Store {
@@ -413,13 +456,19 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
 
     /// Look up a digest in the store and return None if it does not exist in
     /// the store, or Some(size) if it does.
+    /// The empty blob always exists, even if it was never uploaded.
     /// Note: On an AC store the size will be incorrect and should not be used!
     #[inline]
     fn has<'a>(
         &'a self,
         digest: impl Into<StoreKey<'a>>,
     ) -> impl Future<Output = Result<Option<u64>, Error>> + 'a {
-        self.as_store_driver_pin().has(digest.into())
+        let key = digest.into();
+        async move {
+            let zero_digest = is_zero_digest(key.borrow());
+            let result = self.as_store_driver_pin().has(key).await?;
+            Ok(if zero_digest { Some(0) } else { result })
+        }
     }
 
     /// Look up a list of digests in the store and return a result for each in
@@ -431,7 +480,11 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         &'a self,
         digests: &'a [StoreKey<'a>],
     ) -> impl Future<Output = Result<Vec<Option<u64>>, Error>> + Send + 'a {
-        self.as_store_driver_pin().has_many(digests)
+        async move {
+            let mut results = vec![None; digests.len()];
+            self.has_with_results(digests, &mut results).await?;
+            Ok(results)
+        }
     }
 
     /// The implementation of the above has and `has_many` functions.  See their
@@ -442,8 +495,19 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         digests: &'a [StoreKey<'a>],
         results: &'a mut [Option<u64>],
     ) -> impl Future<Output = Result<(), Error>> + Send + 'a {
-        self.as_store_driver_pin()
-            .has_with_results(digests, results)
+        async move {
+            // Stores are still asked about the empty blob, since some of them,
+            // like the filesystem store, create it on demand.
+            self.as_store_driver_pin()
+                .has_with_results(digests, results)
+                .await?;
+            for (digest, result) in digests.iter().zip(results.iter_mut()) {
+                if is_zero_digest(digest.borrow()) {
+                    *result = Some(0);
+                }
+            }
+            Ok(())
+        }
     }
 
     /// List all the keys in the store that are within the given range.
@@ -476,6 +540,44 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         }
     }
 
+    /// Removes the entry for `digest` from the store. Returns true if an
+    /// entry was removed or false if it did not exist.
+    /// Note: Not every store supports removal, those that don't return
+    /// `Code::Unimplemented`.
+    #[inline]
+    fn remove<'a>(
+        &'a self,
+        digest: impl Into<StoreKey<'a>>,
+    ) -> impl Future<Output = Result<bool, Error>> + Send + 'a {
+        self.as_store_driver_pin().remove(digest.into())
+    }
+
+    /// Marks the entries of `keys` as most recently used without reading
+    /// their data, so they are the last ones to be evicted. Returns the
+    /// number of keys that exist in the store.
+    /// Note: Not every store supports touching, those that don't return
+    /// `Code::Unimplemented`.
+    #[inline]
+    fn touch<'a>(
+        &'a self,
+        keys: &'a [StoreKey<'a>],
+    ) -> impl Future<Output = Result<u64, Error>> + Send + 'a {
+        self.as_store_driver_pin().touch(keys)
+    }
+
+    /// Removes every entry whose key starts with `prefix` from the store,
+    /// where digest keys are matched by their `{hash}-{size}` form. Returns
+    /// the number of entries removed.
+    /// Note: Not every store supports removal by prefix, those that don't
+    /// return `Code::Unimplemented`.
+    #[inline]
+    fn remove_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Future<Output = Result<u64, Error>> + Send + 'a {
+        self.as_store_driver_pin().remove_prefix(prefix)
+    }
+
     /// Sends the data to the store.
     #[inline]
     fn update<'a>(
@@ -488,6 +590,30 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
             .update(digest.into(), reader, upload_size)
     }
 
+    /// Writes the data to the store starting at `offset` of the entry, so an
+    /// entry can be assembled from ranges written in any order. Stores that
+    /// support it only add the entry once every range of it was written, so
+    /// readers never see a partly written entry.
+    /// Note: Not every store supports writing at an offset, those that don't
+    /// return `Code::Unimplemented` for a non-zero `offset` and replace the
+    /// whole entry like `.update()` for an `offset` of zero. Currently only
+    /// the filesystem store supports it for digest keys, and `RefStore` is
+    /// the only wrapper store that forwards it. S3 objects can not be
+    /// modified in place and other stores would have to buffer every range.
+    /// No service calls this yet, ByteStream writes still have to send a blob
+    /// in order on a single stream.
+    #[inline]
+    fn update_at_offset<'a>(
+        &'a self,
+        digest: impl Into<StoreKey<'a>>,
+        reader: DropCloserReadHalf,
+        offset: u64,
+        upload_size: UploadSizeInfo,
+    ) -> impl Future<Output = Result<(), Error>> + Send + 'a {
+        self.as_store_driver_pin()
+            .update_at_offset(digest.into(), reader, offset, upload_size)
+    }
+
     /// Any optimizations the store might want to expose to the callers.
     /// By default, no optimizations are exposed.
     #[inline]
@@ -535,6 +661,16 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         // is done due to the complex interaction between the DropCloserWriteHalf
         // and the DropCloserReadHalf during drop().
         async move {
+            if is_zero_digest(key.borrow()) {
+                error_if!(
+                    offset != 0,
+                    "Offset {offset} is past the end of {key:?} which is 0 bytes long"
+                );
+                return writer
+                    .borrow_mut()
+                    .send_eof()
+                    .err_tip(|| "Failed to send zero EOF in StoreLike::get_part");
+            }
             self.as_store_driver_pin()
                 .get_part(key, writer.borrow_mut(), offset, length)
                 .await
@@ -548,7 +684,7 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         key: impl Into<StoreKey<'a>>,
         writer: DropCloserWriteHalf,
     ) -> impl Future<Output = Result<(), Error>> + Send + 'a {
-        self.as_store_driver_pin().get(key.into(), writer)
+        self.get_part(key, writer, 0, None)
     }
 
     /// Utility that will return all the bytes at once instead of in a streaming manner.
@@ -559,8 +695,19 @@ pub trait StoreLike: Send + Sync + Sized + Unpin + 'static {
         offset: u64,
         length: Option<u64>,
     ) -> impl Future<Output = Result<Bytes, Error>> + Send + 'a {
-        self.as_store_driver_pin()
-            .get_part_unchunked(key.into(), offset, length)
+        let key = key.into();
+        async move {
+            if is_zero_digest(key.borrow()) {
+                error_if!(
+                    offset != 0,
+                    "Offset {offset} is past the end of {key:?} which is 0 bytes long"
+                );
+                return Ok(Bytes::new());
+            }
+            self.as_store_driver_pin()
+                .get_part_unchunked(key, offset, length)
+                .await
+        }
     }
 
     /// Default implementation of the health check. Some stores may want to override this
@@ -618,6 +765,30 @@ pub trait StoreDriver:
         ))
     }
 
+    /// See: [`StoreLike::remove`] for details.
+    async fn remove(self: Pin<&Self>, _key: StoreKey<'_>) -> Result<bool, Error> {
+        Err(make_err!(
+            Code::Unimplemented,
+            "Store::remove() not implemented for this store"
+        ))
+    }
+
+    /// See: [`StoreLike::touch`] for details.
+    async fn touch(self: Pin<&Self>, _keys: &[StoreKey<'_>]) -> Result<u64, Error> {
+        Err(make_err!(
+            Code::Unimplemented,
+            "Store::touch() not implemented for this store"
+        ))
+    }
+
+    /// See: [`StoreLike::remove_prefix`] for details.
+    async fn remove_prefix(self: Pin<&Self>, _prefix: &str) -> Result<u64, Error> {
+        Err(make_err!(
+            Code::Unimplemented,
+            "Store::remove_prefix() not implemented for this store"
+        ))
+    }
+
     /// See: [`StoreLike::update`] for details.
     async fn update(
         self: Pin<&Self>,
@@ -626,6 +797,23 @@ pub trait StoreDriver:
         upload_size: UploadSizeInfo,
     ) -> Result<(), Error>;
 
+    /// See: [`StoreLike::update_at_offset`] for details.
+    async fn update_at_offset(
+        self: Pin<&Self>,
+        key: StoreKey<'_>,
+        reader: DropCloserReadHalf,
+        offset: u64,
+        upload_size: UploadSizeInfo,
+    ) -> Result<(), Error> {
+        if offset != 0 {
+            return Err(make_err!(
+                Code::Unimplemented,
+                "Store::update_at_offset() with a non-zero offset is not implemented for this store"
+            ));
+        }
+        self.update(key, reader, upload_size).await
+    }
+
     /// See: [`StoreLike::optimized_for`] for details.
     fn optimized_for(&self, _optimization: StoreOptimizations) -> bool {
         false
@@ -639,6 +827,10 @@ pub trait StoreDriver:
         upload_size: UploadSizeInfo,
     ) -> Result<Option<fs::ResumeableFileSlot>, Error> {
         let inner_store = self.inner_store(Some(key.borrow()));
+        // The data would be discarded anyway, so don't bother reading the file.
+        if inner_store.optimized_for(StoreOptimizations::NoopUpdates) {
+            return Ok(Some(file));
+        }
         if inner_store.optimized_for(StoreOptimizations::FileUpdates) {
             error_if!(
                 addr_eq(inner_store, &*self),
@@ -699,6 +891,8 @@ pub trait StoreDriver:
     }
 
     /// See: [`StoreLike::get_part_unchunked`] for details.
+    /// Stores that already hold the data contiguously in memory should
+    /// override this to avoid the buf channel round trip.
     async fn get_part_unchunked(
         self: Pin<&Self>,
         key: StoreKey<'_>,
@@ -709,9 +903,8 @@ pub trait StoreDriver:
             .map(|v| usize::try_from(v).err_tip(|| "Could not convert length to usize"))
             .transpose()?;
 
-        // TODO(blaise.bruer) This is extremely inefficient, since we have exactly
-        // what we need here. Maybe we could instead make a version of the stream
-        // that can take objects already fully in memory instead?
+        // Note: This is inefficient for stores that already have the data in
+        // memory, those stores override this function instead.
         let (mut tx, mut rx) = make_buf_channel_pair();
 
         let (data_res, get_part_res) = join!(
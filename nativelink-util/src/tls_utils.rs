@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::Duration;
+
 use nativelink_config::stores::{ClientTlsConfig, GrpcEndpoint};
 use nativelink_error::{make_err, make_input_err, Code, Error};
 use tonic::transport::Uri;
@@ -100,13 +102,37 @@ pub fn endpoint_from(
 }
 
 pub fn endpoint(endpoint_config: &GrpcEndpoint) -> Result<tonic::transport::Endpoint, Error> {
-    let endpoint = endpoint_from(
+    let mut endpoint = endpoint_from(
         &endpoint_config.address,
         load_client_config(&endpoint_config.tls_config)?,
     )?;
     if let Some(concurrency_limit) = endpoint_config.concurrency_limit {
-        Ok(endpoint.concurrency_limit(concurrency_limit))
-    } else {
-        Ok(endpoint)
+        endpoint = endpoint.concurrency_limit(concurrency_limit);
+    }
+    if let Some(interval) = endpoint_config.http2_keep_alive_interval {
+        endpoint = endpoint.http2_keep_alive_interval(Duration::from_secs(u64::from(interval)));
+    } else if endpoint_config.http2_keep_alive_timeout.is_some()
+        || endpoint_config.http2_keep_alive_while_idle.is_some()
+    {
+        return Err(make_input_err!(
+            "http2_keep_alive_timeout and http2_keep_alive_while_idle require http2_keep_alive_interval for endpoint {}",
+            endpoint_config.address
+        ));
+    }
+    if let Some(timeout) = endpoint_config.http2_keep_alive_timeout {
+        endpoint = endpoint.keep_alive_timeout(Duration::from_secs(u64::from(timeout)));
+    }
+    if let Some(while_idle) = endpoint_config.http2_keep_alive_while_idle {
+        endpoint = endpoint.keep_alive_while_idle(while_idle);
+    }
+    if let Some(tcp_nodelay) = endpoint_config.tcp_nodelay {
+        endpoint = endpoint.tcp_nodelay(tcp_nodelay);
+    }
+    if let Some(window_size) = endpoint_config.http2_initial_stream_window_size {
+        endpoint = endpoint.initial_stream_window_size(window_size);
+    }
+    if let Some(window_size) = endpoint_config.http2_initial_connection_window_size {
+        endpoint = endpoint.initial_connection_window_size(window_size);
     }
+    Ok(endpoint)
 }
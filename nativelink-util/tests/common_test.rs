@@ -14,7 +14,9 @@
 
 use nativelink_error::{make_input_err, Error};
 use nativelink_macro::nativelink_test;
+use nativelink_proto::build::bazel::remote::execution::v2::Digest;
 use nativelink_util::common::DigestInfo;
+use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
 use pretty_assertions::assert_eq;
 
 const MIN_DIGEST: &str = "0000000000000000000000000000000000000000000000000000000000000000-0";
@@ -22,6 +24,7 @@ const MAX_SAFE_DIGEST: &str =
     "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff-9223372036854775807";
 const MAX_UNSAFE_DIGEST: &str =
     "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff-18446744073709551615";
+const SHA512_HASH: &str = "0123456789abcdef000000000000000000010000000000000123456789abcdef0123456789abcdef000000000000000000010000000000000123456789abcdef";
 
 #[nativelink_test]
 async fn digest_info_min_max_test() -> Result<(), Error> {
@@ -125,8 +128,64 @@ async fn digest_info_deserialize_test() -> Result<(), Error> {
         let digest_res = serde_json::from_str::<DigestInfo>(&format!("\"{MAX_UNSAFE_DIGEST}\""));
         assert_eq!(
             format!("{}", digest_res.err().unwrap()),
-            "Could not create DigestInfo: Error { code: InvalidArgument, messages: [\"Size bytes is too large: 18446744073709551615 - max: 9223372036854775807\"] } at line 1 column 87",
+            "Could not create DigestInfo: Error { code: InvalidArgument, messages: [\"Size bytes is too large: 18446744073709551615 - max: 9223372036854775807\"], reason: None } at line 1 column 87",
         );
     }
     Ok(())
 }
+
+#[nativelink_test]
+async fn digest_info_64_byte_hash_test() -> Result<(), Error> {
+    let digest = DigestInfo::try_new(SHA512_HASH, 123u64)?;
+    assert_eq!(digest.packed_hash().len(), 64);
+    assert_eq!(format!("{digest}"), format!("{SHA512_HASH}-123"));
+    assert_eq!(
+        serde_json::from_str::<DigestInfo>(&serde_json::to_string(&digest).unwrap()).unwrap(),
+        digest
+    );
+
+    let proto_digest: Digest = digest.into();
+    assert_eq!(proto_digest.hash, SHA512_HASH);
+    assert_eq!(DigestInfo::try_from(proto_digest)?, digest);
+
+    assert_eq!(
+        DigestInfo::try_new_from_packed_hash(digest.packed_hash(), 123)?,
+        digest
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn digest_info_from_sha512_hasher_test() -> Result<(), Error> {
+    /// This value is sha512("123").
+    const SHA512_OF_VALUE: &str = concat!(
+        "3c9909afec25354d551dae21590bb26e38d53f2173b8d3dc3eee4c047e7ab1c1",
+        "eb8b85103e3be7ba613b31bb5c9c36214dc9f14a42fd7a2fdb84856bca5c44c2",
+    );
+    let mut hasher = DigestHasherFunc::Sha512.hasher();
+    hasher.update(b"123");
+    let digest = hasher.finalize_digest();
+    assert_eq!(digest, DigestInfo::try_new(SHA512_OF_VALUE, 3u64)?);
+    DigestHasherFunc::Sha512.check_digest(&digest)?;
+    assert!(DigestHasherFunc::Sha256.check_digest(&digest).is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn digest_info_hash_length_is_part_of_identity_test() -> Result<(), Error> {
+    // A 256 bit hash that is a prefix of a 512 bit hash is a different digest
+    // and sorts before it.
+    let short_digest = DigestInfo::try_new(&SHA512_HASH[..64], 123u64)?;
+    let long_digest = DigestInfo::try_new(SHA512_HASH, 123u64)?;
+    assert!(short_digest != long_digest);
+    assert!(short_digest < long_digest);
+    Ok(())
+}
+
+#[nativelink_test]
+async fn digest_info_rejects_unsupported_hash_length_test() -> Result<(), Error> {
+    // 48 bytes is not a supported hash length.
+    assert!(DigestInfo::try_new(&SHA512_HASH[..96], 123u64).is_err());
+    assert!(DigestInfo::try_new_from_packed_hash(&[0u8; 48], 123).is_err());
+    Ok(())
+}
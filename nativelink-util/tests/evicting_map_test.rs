@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -22,7 +22,7 @@ use nativelink_config::stores::EvictionPolicy;
 use nativelink_error::Error;
 use nativelink_macro::nativelink_test;
 use nativelink_util::common::DigestInfo;
-use nativelink_util::evicting_map::{EvictingMap, LenEntry};
+use nativelink_util::evicting_map::{with_default_evict_bytes, EvictingMap, LenEntry};
 use nativelink_util::instant_wrapper::MockInstantWrapped;
 use pretty_assertions::assert_eq;
 
@@ -223,6 +223,71 @@ async fn insert_purges_to_low_watermark_at_max_bytes() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn default_evict_bytes_reduces_eviction_passes() -> Result<(), Error> {
+    #[derive(Debug, Clone)]
+    struct CountingEntry(Arc<AtomicU64>);
+
+    impl LenEntry for CountingEntry {
+        fn len(&self) -> u64 {
+            1
+        }
+
+        fn is_empty(&self) -> bool {
+            false
+        }
+
+        async fn unref(&self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Inserts one byte entries and returns how many inserts evicted
+    /// anything.
+    async fn count_eviction_passes(policy: &EvictionPolicy) -> u64 {
+        let evicted = Arc::new(AtomicU64::new(0));
+        let evicting_map = EvictingMap::<u64, CountingEntry, MockInstantWrapped>::new(
+            policy,
+            MockInstantWrapped::default(),
+        );
+        let mut eviction_passes = 0;
+        for key in 0..1000 {
+            let evicted_before = evicted.load(Ordering::Relaxed);
+            evicting_map
+                .insert(key, CountingEntry(evicted.clone()))
+                .await;
+            if evicted.load(Ordering::Relaxed) != evicted_before {
+                eviction_passes += 1;
+            }
+        }
+        eviction_passes
+    }
+
+    let policy = EvictionPolicy {
+        max_bytes: 100,
+        ..Default::default()
+    };
+    let derived_policy = with_default_evict_bytes(&policy);
+    assert_eq!(derived_policy.evict_bytes, 10);
+    let passes_without_watermark = count_eviction_passes(&policy).await;
+    let passes_with_watermark = count_eviction_passes(&derived_policy).await;
+    // Every insert on a full map evicts without a low watermark, with one
+    // only about every eleventh insert does.
+    assert!(
+        passes_with_watermark * 10 < passes_without_watermark,
+        "Expected fewer eviction passes with the derived low watermark, got {passes_with_watermark} with and {passes_without_watermark} without"
+    );
+
+    // An explicit `evict_bytes` is kept.
+    let explicit_policy = with_default_evict_bytes(&EvictionPolicy {
+        max_bytes: 100,
+        evict_bytes: 1,
+        ..Default::default()
+    });
+    assert_eq!(explicit_policy.evict_bytes, 1);
+    Ok(())
+}
+
 #[nativelink_test]
 async fn insert_purges_at_max_seconds() -> Result<(), Error> {
     const DATA: &str = "12345678";
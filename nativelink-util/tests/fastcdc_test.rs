@@ -19,6 +19,7 @@ use bytes::Bytes;
 use futures::stream::StreamExt;
 use nativelink_macro::nativelink_test;
 use nativelink_util::fastcdc::FastCDC;
+use nativelink_util::gearcdc::GearCDC;
 use pretty_assertions::assert_eq;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
@@ -202,3 +203,42 @@ async fn insert_garbage_check_boundaries_recover_test() -> Result<(), std::io::E
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn chunks_respect_min_max_bounds_test() -> Result<(), std::io::Error> {
+    const MIN_SIZE: usize = 1024;
+    const AVG_SIZE: usize = 2048;
+    const MAX_SIZE: usize = 4096;
+    let data = {
+        let mut data = vec![0u8; MEGABYTE_SZ];
+        let mut rng = SmallRng::seed_from_u64(1);
+        rng.fill(&mut data[..]);
+        data
+    };
+
+    let fast_cdc_frames = get_frames(&mut FramedRead::new(
+        Cursor::new(&data),
+        FastCDC::new(MIN_SIZE, AVG_SIZE, MAX_SIZE),
+    ))
+    .await?;
+    let gear_cdc_frames = get_frames(&mut FramedRead::new(
+        Cursor::new(&data),
+        GearCDC::new(MIN_SIZE, AVG_SIZE, MAX_SIZE),
+    ))
+    .await?;
+
+    for frames in [fast_cdc_frames, gear_cdc_frames] {
+        assert_eq!(frames.iter().map(Bytes::len).sum::<usize>(), data.len());
+        // The last frame holds whatever is left, so it may be below `MIN_SIZE`.
+        let (last_frame, frames) = frames.split_last().unwrap();
+        for frame in frames {
+            assert!(
+                (MIN_SIZE..=MAX_SIZE).contains(&frame.len()),
+                "Frame of {} bytes out of bounds",
+                frame.len()
+            );
+        }
+        assert!(last_frame.len() <= MAX_SIZE);
+    }
+    Ok(())
+}
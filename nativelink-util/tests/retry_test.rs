@@ -18,7 +18,7 @@ use std::sync::Arc;
 
 use futures::future::ready;
 use futures::stream::repeat_with;
-use nativelink_config::stores::Retry;
+use nativelink_config::stores::{Retry, RetryBudget};
 use nativelink_error::{make_err, Code, Error};
 use nativelink_macro::nativelink_test;
 use nativelink_util::retry::{Retrier, RetryResult};
@@ -178,3 +178,55 @@ async fn retry_calls_sleep_fn() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn retry_fails_fast_when_budget_exhausted() -> Result<(), Error> {
+    let retrier = Retrier::new(
+        Arc::new(|_duration| Box::pin(ready(()))),
+        Arc::new(move |_delay| Duration::from_millis(1)),
+        Retry {
+            max_retries: 10,
+            retry_budget: Some(RetryBudget {
+                max_tokens: 2,
+                token_ratio: 0.5,
+            }),
+            ..Default::default()
+        },
+    );
+    let count_failing_runs = |retrier: Retrier| async move {
+        let run_count = Arc::new(AtomicI32::new(0));
+        let result = Pin::new(&retrier)
+            .retry(repeat_with(|| {
+                run_count.fetch_add(1, Ordering::Relaxed);
+                RetryResult::<bool>::Retry(make_err!(Code::Unavailable, "Dummy failure",))
+            }))
+            .await;
+        assert_eq!(result.is_err(), true, "Expected result to error");
+        run_count.load(Ordering::Relaxed)
+    };
+
+    // The two tokens in the budget allow two retries.
+    assert_eq!(count_failing_runs(retrier.clone()).await, 3);
+    // The budget is shared, so every further request fails fast.
+    assert_eq!(count_failing_runs(retrier.clone()).await, 1);
+    let result = Pin::new(&retrier)
+        .retry(repeat_with(|| {
+            RetryResult::<bool>::Retry(make_err!(Code::Unavailable, "Dummy failure",))
+        }))
+        .await;
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Error { code: Unavailable, messages: [\"Dummy failure\", \"Retry budget exhausted on attempt 1\"] }"
+    );
+
+    // Two successful requests earn back the token for a single retry.
+    for _ in 0..2 {
+        let result = Pin::new(&retrier)
+            .retry(repeat_with(|| RetryResult::Ok(true)))
+            .await?;
+        assert_eq!(result, true, "Expected result to succeed");
+    }
+    assert_eq!(count_failing_runs(retrier.clone()).await, 2);
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nativelink_config::stores::GrpcEndpoint;
+use nativelink_error::{Code, Error};
+use nativelink_macro::nativelink_test;
+use nativelink_util::tls_utils;
+use pretty_assertions::assert_eq;
+
+const ADDRESS: &str = "grpc://localhost:50051";
+
+// `tonic::transport::Endpoint` does not expose the options it was configured
+// with, so this only checks that a fully configured endpoint is accepted.
+#[nativelink_test]
+async fn endpoint_accepts_connection_options() -> Result<(), Error> {
+    let endpoint = tls_utils::endpoint(&GrpcEndpoint {
+        address: ADDRESS.to_string(),
+        concurrency_limit: Some(10),
+        http2_keep_alive_interval: Some(30),
+        http2_keep_alive_timeout: Some(5),
+        http2_keep_alive_while_idle: Some(true),
+        tcp_nodelay: Some(false),
+        http2_initial_stream_window_size: Some(1024 * 1024),
+        http2_initial_connection_window_size: Some(4 * 1024 * 1024),
+        ..Default::default()
+    })?;
+    assert_eq!(endpoint.uri().to_string(), format!("{ADDRESS}/"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn endpoint_without_options_uses_defaults() -> Result<(), Error> {
+    let endpoint = tls_utils::endpoint(&GrpcEndpoint {
+        address: ADDRESS.to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(endpoint.uri().to_string(), format!("{ADDRESS}/"));
+    Ok(())
+}
+
+#[nativelink_test]
+async fn endpoint_rejects_keep_alive_options_without_interval() -> Result<(), Error> {
+    for endpoint_config in [
+        GrpcEndpoint {
+            address: ADDRESS.to_string(),
+            http2_keep_alive_timeout: Some(5),
+            ..Default::default()
+        },
+        GrpcEndpoint {
+            address: ADDRESS.to_string(),
+            http2_keep_alive_while_idle: Some(true),
+            ..Default::default()
+        },
+    ] {
+        let Err(err) = tls_utils::endpoint(&endpoint_config) else {
+            panic!("Expected error for {endpoint_config:?}");
+        };
+        assert_eq!(err.code, Code::InvalidArgument, "{err:?}");
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+// Copyright 2024 The NativeLink Authors. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//    http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use nativelink_error::{make_err, Code, Error, ResultExt};
+
+/// Period of the CPU bandwidth limit in `cpu.max`, in microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Number of times removing the cgroup is tried while its killed processes
+/// exit.
+const REMOVE_ATTEMPTS: u32 = 50;
+
+/// Delay between attempts to remove the cgroup.
+const REMOVE_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// A cgroup v2 that confines the processes of a single action to a number
+/// of CPUs and an amount of memory.
+#[derive(Debug, Clone)]
+pub struct ActionCgroup {
+    path: String,
+}
+
+impl ActionCgroup {
+    /// Creates a cgroup named `name` below `parent_directory`, limited to
+    /// `cpu_count` CPUs and `memory_kb` KiB of memory if they are set.
+    /// `parent_directory` must have the `cpu` and `memory` controllers
+    /// enabled in its `cgroup.subtree_control`. Only supported on Linux.
+    pub async fn create(
+        parent_directory: &str,
+        name: &str,
+        cpu_count: Option<u64>,
+        memory_kb: Option<u64>,
+    ) -> Result<Self, Error> {
+        if cfg!(not(target_os = "linux")) {
+            return Err(make_err!(
+                Code::Unimplemented,
+                "Confining actions to a cgroup is only supported on Linux"
+            ));
+        }
+        let cgroup = Self {
+            path: format!("{parent_directory}/{name}"),
+        };
+        tokio::fs::create_dir(&cgroup.path)
+            .await
+            .err_tip(|| format!("Could not create cgroup {}", cgroup.path))?;
+        if let Err(err) = cgroup.set_limits(cpu_count, memory_kb).await {
+            return Err(match cgroup.remove().await {
+                Ok(()) => err,
+                Err(remove_err) => err.merge(remove_err),
+            });
+        }
+        Ok(cgroup)
+    }
+
+    async fn set_limits(
+        &self,
+        cpu_count: Option<u64>,
+        memory_kb: Option<u64>,
+    ) -> Result<(), Error> {
+        if let Some(cpu_count) = cpu_count {
+            self.write(
+                "cpu.max",
+                &format!("{} {CPU_PERIOD_US}", cpu_count * CPU_PERIOD_US),
+            )
+            .await?;
+        }
+        if let Some(memory_kb) = memory_kb {
+            self.write("memory.max", &(memory_kb * 1024).to_string())
+                .await?;
+            // Without this the action would swap instead of being killed
+            // once it reaches its limit. The file does not exist if swap
+            // accounting is disabled, in which case nothing can swap.
+            match tokio::fs::write(format!("{}/memory.swap.max", self.path), "0").await {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    return Err(err)
+                        .err_tip(|| format!("Could not write memory.swap.max of {}", self.path));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&self, file_name: &str, value: &str) -> Result<(), Error> {
+        tokio::fs::write(format!("{}/{file_name}", self.path), value)
+            .await
+            .err_tip(|| format!("Could not write {value} to {file_name} of {}", self.path))
+    }
+
+    /// Opens the `cgroup.procs` file of the cgroup. Writing `0` to it moves
+    /// the writing process into the cgroup, which a child can do between
+    /// `fork()` and `exec()` so it never runs outside of the cgroup.
+    /// Processes it spawns afterwards are in the cgroup too.
+    pub async fn open_procs_file(&self) -> Result<std::fs::File, Error> {
+        let procs_file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(format!("{}/cgroup.procs", self.path))
+            .await
+            .err_tip(|| format!("Could not open cgroup.procs of {}", self.path))?;
+        Ok(procs_file.into_std().await)
+    }
+
+    /// Returns true if the kernel killed a process in the cgroup because
+    /// the cgroup reached its memory limit.
+    pub async fn oom_killed(&self) -> Result<bool, Error> {
+        let events = tokio::fs::read_to_string(format!("{}/memory.events", self.path))
+            .await
+            .err_tip(|| format!("Could not read memory.events of {}", self.path))?;
+        Ok(events
+            .lines()
+            .find_map(|line| line.strip_prefix("oom_kill "))
+            .and_then(|count| count.trim().parse::<u64>().ok())
+            .is_some_and(|count| count > 0))
+    }
+
+    /// Kills every process left in the cgroup and removes it.
+    pub async fn remove(&self) -> Result<(), Error> {
+        // `cgroup.kill` only exists since Linux 5.14. On older kernels the
+        // cgroup can only be removed once its processes exited by themselves.
+        let _ = tokio::fs::write(format!("{}/cgroup.kill", self.path), "1").await;
+        let mut attempt = 1;
+        loop {
+            match tokio::fs::remove_dir(&self.path).await {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(err) if attempt >= REMOVE_ATTEMPTS => {
+                    return Err(err).err_tip(|| format!("Could not remove cgroup {}", self.path));
+                }
+                Err(_) => {
+                    attempt += 1;
+                    tokio::time::sleep(REMOVE_RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod action_cgroup;
 pub mod local_worker;
 pub mod running_actions_manager;
 pub mod worker_api_client_wrapper;
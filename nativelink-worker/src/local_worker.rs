@@ -15,7 +15,7 @@
 use std::pin::Pin;
 use std::process::Stdio;
 use std::str;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Weak};
 use std::time::Duration;
 
@@ -23,6 +23,7 @@ use futures::future::BoxFuture;
 use futures::stream::FuturesUnordered;
 use futures::{select, Future, FutureExt, StreamExt, TryFutureExt};
 use nativelink_config::cas_server::LocalWorkerConfig;
+use nativelink_config::stores::Retry;
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{MetricsComponent, RootMetricsComponent};
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::update_for_worker::Update;
@@ -35,14 +36,15 @@ use nativelink_util::action_messages::{ActionResult, ActionStage, OperationId};
 use nativelink_util::common::fs;
 use nativelink_util::digest_hasher::{DigestHasherFunc, ACTIVE_HASHER_FUNC};
 use nativelink_util::metrics_utils::{AsyncCounterWrapper, CounterWithTime};
-use nativelink_util::origin_context::ActiveOriginContext;
+use nativelink_util::origin_context::{ActiveOriginContext, ACTION_TRACE_ID};
 use nativelink_util::shutdown_guard::ShutdownGuard;
 use nativelink_util::store_trait::Store;
-use nativelink_util::{spawn, tls_utils};
+use nativelink_util::{background_spawn, tls_utils};
+use rand::rngs::OsRng;
+use rand::Rng;
 use tokio::process;
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::sleep;
-use tokio_stream::wrappers::UnboundedReceiverStream;
 use tonic::Streaming;
 use tracing::{event, info_span, instrument, Level};
 
@@ -51,14 +53,10 @@ use crate::running_actions_manager::{
     RunningActionsManager, RunningActionsManagerArgs, RunningActionsManagerImpl,
 };
 use crate::worker_api_client_wrapper::{WorkerApiClientTrait, WorkerApiClientWrapper};
-use crate::worker_utils::make_supported_properties;
-
-/// Amount of time to wait if we have actions in transit before we try to
-/// consider an error to have occurred.
-const ACTIONS_IN_TRANSIT_TIMEOUT_S: f32 = 10.;
+use crate::worker_utils::{make_host_resource_properties, make_supported_properties};
 
 /// If we lose connection to the worker api server we will wait this many seconds
-/// before trying to connect.
+/// before trying to connect, unless `connection_retry` configures a delay.
 const CONNECTION_RETRY_DELAY_S: f32 = 0.5;
 
 /// Default endpoint timeout. If this value gets modified the documentation in
@@ -69,20 +67,81 @@ const DEFAULT_ENDPOINT_TIMEOUT_S: f32 = 5.;
 /// If this value gets modified the documentation in `cas_server.rs` must also be updated.
 const DEFAULT_MAX_ACTION_TIMEOUT: Duration = Duration::from_secs(1200); // 20 mins.
 
+/// Default interval for pruning the input staging directory.
+/// If this value gets modified the documentation in `cas_server.rs` must also be updated.
+const DEFAULT_INPUT_STAGING_PRUNE_INTERVAL: Duration = Duration::from_secs(300); // 5 mins.
+
+/// How often the free disk space of the work_directory is checked when
+/// `min_free_disk_space` is set.
+const FREE_DISK_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns the number of bytes free on the filesystem containing a path.
+pub type AvailableSpaceFn =
+    Arc<dyn Fn(&str) -> BoxFuture<'static, Result<u64, Error>> + Send + Sync>;
+
 struct LocalWorkerImpl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> {
     config: &'a LocalWorkerConfig,
     // According to the tonic documentation it is a cheap operation to clone this.
     grpc_client: T,
     worker_id: String,
     running_actions_manager: Arc<U>,
-    // Number of actions that have been received in `Update::StartAction`, but
-    // not yet processed by running_actions_manager's spawn. This number should
-    // always be zero if there are no actions running and no actions being waited
-    // on by the scheduler.
-    actions_in_transit: Arc<AtomicU64>,
+    // Results of finished actions that still have to be sent to the scheduler.
+    // The receiving end outlives the connection, so actions keep running while
+    // the worker is disconnected and their results are sent once reconnected.
+    finished_actions_tx: mpsc::UnboundedSender<FinishedAction>,
+    // Every action started on this connection holds a clone of this sender
+    // until its result was sent, so `running_actions_rx` closes once all of
+    // them are done.
+    running_actions_tx: mpsc::Sender<()>,
+    running_actions_rx: Option<mpsc::Receiver<()>>,
+    available_space_fn: AvailableSpaceFn,
+    // Whether the work_directory is low on disk space.
+    low_on_disk_space: AtomicBool,
+    // Whether actions started on earlier connections are still running.
+    running_previous_actions: AtomicBool,
     metrics: Arc<Metrics>,
 }
 
+/// The result of an action that still has to be sent to the scheduler.
+struct FinishedAction {
+    execute_result: ExecuteResult,
+    // Keeps the `running_actions_rx` of the connection the action was
+    // started on open until the result was sent.
+    _running_action_guard: mpsc::Sender<()>,
+}
+
+/// Fails with `ResourceExhausted` if less than `min_free_disk_space` bytes are
+/// free on the filesystem of `work_directory`.
+async fn free_disk_space_met(
+    available_space_fn: AvailableSpaceFn,
+    work_directory: String,
+    min_free_disk_space: u64,
+) -> Result<(), Error> {
+    if min_free_disk_space == 0 {
+        return Ok(());
+    }
+    let available_space = match available_space_fn(&work_directory).await {
+        Ok(available_space) => available_space,
+        Err(err) => {
+            // Not being able to check is no reason to stop running actions.
+            event!(
+                Level::WARN,
+                ?err,
+                %work_directory,
+                "Could not check free disk space of work_directory"
+            );
+            return Ok(());
+        }
+    };
+    if available_space < min_free_disk_space {
+        return Err(make_err!(
+            Code::ResourceExhausted,
+            "Only {available_space} bytes are free on the filesystem of {work_directory}, below min_free_disk_space of {min_free_disk_space} bytes"
+        ));
+    }
+    Ok(())
+}
+
 async fn preconditions_met(precondition_script: Option<String>) -> Result<(), Error> {
     let Some(precondition_script) = &precondition_script else {
         // No script means we are always ok to proceed.
@@ -121,28 +180,58 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
         grpc_client: T,
         worker_id: String,
         running_actions_manager: Arc<U>,
+        finished_actions_tx: mpsc::UnboundedSender<FinishedAction>,
+        available_space_fn: AvailableSpaceFn,
         metrics: Arc<Metrics>,
     ) -> Self {
+        let (running_actions_tx, running_actions_rx) = mpsc::channel(1);
         Self {
             config,
             grpc_client,
             worker_id,
             running_actions_manager,
-            // Number of actions that have been received in `Update::StartAction`, but
-            // not yet processed by running_actions_manager's spawn. This number should
-            // always be zero if there are no actions running and no actions being waited
-            // on by the scheduler.
-            actions_in_transit: Arc::new(AtomicU64::new(0)),
+            finished_actions_tx,
+            running_actions_tx,
+            running_actions_rx: Some(running_actions_rx),
+            available_space_fn,
+            low_on_disk_space: AtomicBool::new(false),
+            running_previous_actions: AtomicBool::new(false),
             metrics,
         }
     }
 
-    /// Starts a background spawn/thread that will send a message to the server every `timeout / 2`.
+    /// Whether the scheduler should not send new actions to this worker.
+    fn is_intake_paused(&self) -> bool {
+        self.low_on_disk_space.load(Ordering::Acquire)
+            || self.running_previous_actions.load(Ordering::Acquire)
+    }
+
+    /// Tells the scheduler whether it may send new actions to this worker.
+    async fn send_intake_paused(&self) -> Result<(), Error> {
+        self.grpc_client
+            .clone()
+            .keep_alive(KeepAliveRequest {
+                worker_id: self.worker_id.clone(),
+                paused: self.is_intake_paused(),
+            })
+            .await
+            .map_err(|e| {
+                make_err!(
+                    Code::Internal,
+                    "Failed to send KeepAlive in LocalWorker : {:?}",
+                    e
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Starts a background spawn/thread that will send a message to the server every
+    /// `keep_alive_interval`, or every `timeout / 2` if it is not set.
     async fn start_keep_alive(&self) -> Result<(), Error> {
         // According to tonic's documentation this call should be cheap and is the same stream.
         let mut grpc_client = self.grpc_client.clone();
 
-        loop {
+        let keep_alive_interval = if self.config.keep_alive_interval == 0 {
             let timeout = self
                 .config
                 .worker_api_endpoint
@@ -150,10 +239,16 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
                 .unwrap_or(DEFAULT_ENDPOINT_TIMEOUT_S);
             // We always send 2 keep alive requests per timeout. Http2 should manage most of our
             // timeout issues, this is a secondary check to ensure we can still send data.
-            sleep(Duration::from_secs_f32(timeout / 2.)).await;
+            Duration::from_secs_f32(timeout / 2.)
+        } else {
+            Duration::from_secs(self.config.keep_alive_interval as u64)
+        };
+        loop {
+            sleep(keep_alive_interval).await;
             if let Err(e) = grpc_client
                 .keep_alive(KeepAliveRequest {
                     worker_id: self.worker_id.clone(),
+                    paused: self.is_intake_paused(),
                 })
                 .await
             {
@@ -166,10 +261,60 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
         }
     }
 
+    /// Checks the free disk space of the work_directory every
+    /// `FREE_DISK_SPACE_CHECK_INTERVAL` and asks the scheduler to pause or
+    /// resume sending actions whenever it falls below or recovers above
+    /// `min_free_disk_space`.
+    async fn watch_free_disk_space(&self) -> Result<(), Error> {
+        loop {
+            let paused = free_disk_space_met(
+                self.available_space_fn.clone(),
+                self.config.work_directory.clone(),
+                self.config.min_free_disk_space,
+            )
+            .await
+            .is_err();
+            if self.low_on_disk_space.swap(paused, Ordering::AcqRel) != paused {
+                event!(
+                    Level::INFO,
+                    paused,
+                    work_directory = %self.config.work_directory,
+                    "Free disk space changed, updating intake of actions"
+                );
+                self.send_intake_paused().await?;
+            }
+            sleep(FREE_DISK_SPACE_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Actions started on earlier connections were put back in the queue of
+    /// the scheduler when those connections were lost, so the scheduler
+    /// considers their resources free. Asks the scheduler not to send new
+    /// actions until all of them finished and their results were sent.
+    async fn pause_intake_for_previous_actions(
+        &self,
+        previous_actions_rx: &mut Vec<mpsc::Receiver<()>>,
+    ) -> Result<(), Error> {
+        self.running_previous_actions.store(true, Ordering::Release);
+        self.send_intake_paused().await?;
+        for running_actions_rx in previous_actions_rx.iter_mut() {
+            // Nothing is ever sent, this only returns once all senders are
+            // dropped.
+            running_actions_rx.recv().await;
+        }
+        previous_actions_rx.clear();
+        self.running_previous_actions
+            .store(false, Ordering::Release);
+        self.send_intake_paused().await
+    }
+
     async fn run(
         &mut self,
         update_for_worker_stream: Streaming<UpdateForWorker>,
+        finished_actions_rx: &mut mpsc::UnboundedReceiver<FinishedAction>,
+        previous_actions_rx: &mut Vec<mpsc::Receiver<()>>,
         shutdown_rx: &mut broadcast::Receiver<ShutdownGuard>,
+        sleep_fn: &SleepFn,
     ) -> Result<(), Error> {
         // This big block of logic is designed to help simplify upstream components. Upstream
         // components can write standard futures that return a `Result<(), Error>` and this block
@@ -177,23 +322,42 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
         // It is a common use case that an item sent through update_for_worker_stream will always
         // have a response but the response will be triggered through a callback to the scheduler.
         // This can be quite tricky to manage, so what we have done here is given access to a
-        // `futures` variable which because this is in a single thread as well as a channel that you
-        // send a future into that makes it into the `futures` variable.
-        // This means that if you want to perform an action based on the result of the future
-        // you use the `.map()` method and the new action will always come to live in this spawn,
-        // giving mutable access to stuff in this struct.
+        // `futures` variable which because this is in a single thread. Actions are not part of
+        // it, they run in their own spawn and hand their results to `finished_actions_rx`, so
+        // they are not affected if this function returns.
         // NOTE: If you ever return from this function it will disconnect from the scheduler.
         let mut futures = FuturesUnordered::new();
         futures.push(self.start_keep_alive().boxed());
-
-        let (add_future_channel, add_future_rx) = mpsc::unbounded_channel();
-        let mut add_future_rx = UnboundedReceiverStream::new(add_future_rx).fuse();
+        if self.config.min_free_disk_space != 0 {
+            futures.push(self.watch_free_disk_space().boxed());
+        }
+        if !previous_actions_rx.is_empty() {
+            futures.push(
+                self.pause_intake_for_previous_actions(previous_actions_rx)
+                    .boxed(),
+            );
+        }
 
         let mut update_for_worker_stream = update_for_worker_stream.fuse();
 
+        // If the scheduler sends nothing for `scheduler_keep_alive_timeout`
+        // the connection is assumed to be dead.
+        let scheduler_keep_alive_timeout =
+            Duration::from_secs(self.config.scheduler_keep_alive_timeout as u64);
+        let make_scheduler_timeout_fut = || {
+            let timeout_fut = if scheduler_keep_alive_timeout.is_zero() {
+                futures::future::pending().boxed()
+            } else {
+                sleep_fn(scheduler_keep_alive_timeout)
+            };
+            timeout_fut.fuse()
+        };
+        let mut scheduler_timeout_fut = make_scheduler_timeout_fut();
+
         loop {
             select! {
                 maybe_update = update_for_worker_stream.next() => {
+                    scheduler_timeout_fut = make_scheduler_timeout_fut();
                     match maybe_update
                         .err_tip(|| "UpdateForWorker stream closed early")?
                         .err_tip(|| "Got error in UpdateForWorker stream")?
@@ -228,6 +392,7 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
 
                             let execute_request = start_execute.execute_request.as_ref();
                             let operation_id = start_execute.operation_id.clone();
+                            let trace_id = operation_id.clone();
                             let maybe_instance_name = execute_request.map(|v| v.instance_name.clone());
                             let action_digest = execute_request.and_then(|v| v.action_digest.clone());
                             let digest_hasher = execute_request
@@ -237,24 +402,23 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
 
                             let start_action_fut = {
                                 let precondition_script_cfg = self.config.experimental_precondition_script.clone();
-                                let actions_in_transit = self.actions_in_transit.clone();
+                                let work_directory = self.config.work_directory.clone();
+                                let min_free_disk_space = self.config.min_free_disk_space;
+                                let available_space_fn = self.available_space_fn.clone();
                                 let worker_id = self.worker_id.clone();
                                 let running_actions_manager = self.running_actions_manager.clone();
                                 self.metrics.clone().wrap(move |metrics| async move {
-                                    metrics.preconditions.wrap(preconditions_met(precondition_script_cfg))
+                                    free_disk_space_met(available_space_fn, work_directory, min_free_disk_space)
+                                    .inspect_err(|_| metrics.low_disk_space_rejections.inc())
+                                    .and_then(|()| metrics.preconditions.wrap(preconditions_met(precondition_script_cfg)))
                                     .and_then(|()| running_actions_manager.create_and_add_action(worker_id, start_execute))
-                                    .map(move |r| {
-                                        // Now that we either failed or registered our action, we can
-                                        // consider the action to no longer be in transit.
-                                        actions_in_transit.fetch_sub(1, Ordering::Release);
-                                        r
-                                    })
                                     .and_then(|action| {
                                         event!(
                                             Level::INFO,
                                             operation_id = ?action.get_operation_id(),
                                             "Received request to run action"
                                         );
+                                        let do_not_cache = action.do_not_cache();
                                         action
                                             .clone()
                                             .prepare_action()
@@ -268,22 +432,26 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
                                                 }
                                                 result
                                             })
+                                            .map_ok(move |action_result| (action_result, do_not_cache))
                                     }).await
                                 })
                             };
 
-                            let make_publish_future = {
-                                let mut grpc_client = self.grpc_client.clone();
-
-                                let worker_id = self.worker_id.clone();
+                            // The result is not sent to the scheduler from here, but handed to
+                            // `finished_actions_tx`, so it is sent on whichever connection is
+                            // alive once the action has finished.
+                            let make_finished_action = {
                                 let running_actions_manager = self.running_actions_manager.clone();
-                                move |res: Result<ActionResult, Error>| async move {
+                                let worker_id = self.worker_id.clone();
+                                let running_action_guard = self.running_actions_tx.clone();
+                                move |res: Result<(ActionResult, bool), Error>| async move {
                                     let instance_name = maybe_instance_name
                                         .err_tip(|| "`instance_name` could not be resolved; this is likely an internal error in local_worker.")?;
-                                    match res {
-                                        Ok(mut action_result) => {
+                                    let result = match res {
+                                        Ok((mut action_result, do_not_cache)) => {
                                             // Save in the action cache before notifying the scheduler that we've completed.
-                                            if let Some(digest_info) = action_digest.clone().and_then(|action_digest| action_digest.try_into().ok()) {
+                                            // Results of actions marked `do_not_cache` must never be cached.
+                                            if let Some(digest_info) = action_digest.clone().filter(|_| !do_not_cache).and_then(|action_digest| action_digest.try_into().ok()) {
                                                 if let Err(err) = running_actions_manager.cache_action_result(digest_info, &mut action_result, digest_hasher).await {
                                                     event!(
                                                         Level::ERROR,
@@ -294,61 +462,81 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
                                                 }
                                             }
                                             let action_stage = ActionStage::Completed(action_result);
-                                            grpc_client.execution_response(
-                                                ExecuteResult{
-                                                    worker_id,
-                                                    instance_name,
-                                                    operation_id,
-                                                    result: Some(execute_result::Result::ExecuteResponse(action_stage.into())),
-                                                }
-                                            )
-                                            .await
-                                            .err_tip(|| "Error while calling execution_response")?;
+                                            execute_result::Result::ExecuteResponse(action_stage.into())
                                         },
-                                        Err(e) => {
-                                            grpc_client.execution_response(ExecuteResult{
-                                                worker_id,
-                                                instance_name,
-                                                operation_id,
-                                                result: Some(execute_result::Result::InternalError(e.into())),
-                                            }).await.err_tip(|| "Error calling execution_response with error")?;
+                                        Err(e) => execute_result::Result::InternalError(e.into()),
+                                    };
+                                    // The result is reported under the worker id the action was
+                                    // started on, even if the worker reconnected in the meantime,
+                                    // as the scheduler only accepts it from that worker.
+                                    Ok::<_, Error>(FinishedAction {
+                                        execute_result: ExecuteResult {
+                                            worker_id,
+                                            instance_name,
+                                            operation_id,
+                                            result: Some(result),
                                         },
-                                    }
-                                    Ok(())
+                                        _running_action_guard: running_action_guard,
+                                    })
                                 }
                             };
 
-                            self.actions_in_transit.fetch_add(1, Ordering::Release);
-                            let futures_ref = &futures;
-
-                            let add_future_channel = add_future_channel.clone();
+                            let finished_actions_tx = self.finished_actions_tx.clone();
                             let mut ctx = ActiveOriginContext::fork().err_tip(|| "Expected ActiveOriginContext to be set in local_worker::run")?;
                             ctx.set_value(&ACTIVE_HASHER_FUNC, Arc::new(digest_hasher));
-                            ctx.run(info_span!("worker_start_action_ctx"), move || {
-                                futures_ref.push(
-                                    spawn!("worker_start_action", start_action_fut).map(move |res| {
-                                        let res = res.err_tip(|| "Failed to launch spawn")?;
-                                        if let Err(err) = &res {
-                                            event!(
-                                                Level::ERROR,
-                                                ?err,
-                                                "Error executing action",
-                                            );
+                            // The operation id is the trace id assigned by the scheduler, it is
+                            // forwarded to the stores so their logs can be correlated.
+                            let span = info_span!("worker_start_action_ctx", %trace_id);
+                            ctx.set_value(&ACTION_TRACE_ID, Arc::new(trace_id));
+                            // The action is not tied to the connection it was received on, it
+                            // keeps running if the worker loses its connection to the scheduler.
+                            ctx.run(span, move || {
+                                background_spawn!("worker_start_action", async move {
+                                    let res = start_action_fut.await;
+                                    if let Err(err) = &res {
+                                        event!(
+                                            Level::ERROR,
+                                            ?err,
+                                            "Error executing action",
+                                        );
+                                    }
+                                    match make_finished_action(res).await {
+                                        Ok(finished_action) => {
+                                            if finished_actions_tx.send(finished_action).is_err() {
+                                                event!(Level::ERROR, "LocalWorker could not send finished action");
+                                            }
                                         }
-                                        add_future_channel
-                                            .send(make_publish_future(res).boxed())
-                                            .map_err(|_| make_err!(Code::Internal, "LocalWorker could not send future"))?;
-                                        Ok(())
-                                    })
-                                    .boxed()
-                                );
+                                        Err(err) => {
+                                            event!(Level::ERROR, ?err, "Could not make result of finished action");
+                                        }
+                                    }
+                                });
                             });
                         }
                     };
                 },
-                res = add_future_rx.next() => {
-                    let fut = res.err_tip(|| "New future stream receives should never be closed")?;
-                    futures.push(fut);
+                () = scheduler_timeout_fut => {
+                    return Err(make_err!(
+                        Code::Unavailable,
+                        "Received nothing from the scheduler for {scheduler_keep_alive_timeout:?}"
+                    ));
+                },
+                maybe_finished_action = finished_actions_rx.recv().fuse() => {
+                    let finished_action = maybe_finished_action
+                        .err_tip(|| "Finished actions channel should never be closed")?;
+                    let mut grpc_client = self.grpc_client.clone();
+                    let finished_actions_tx = self.finished_actions_tx.clone();
+                    futures.push(async move {
+                        if let Err(status) = grpc_client.execution_response(finished_action.execute_result.clone()).await {
+                            // Keep the result around to send it again once reconnected,
+                            // unless the scheduler itself rejected it.
+                            if status.code() == tonic::Code::Unavailable {
+                                let _ = finished_actions_tx.send(finished_action);
+                            }
+                            return Err(Error::from(status)).err_tip(|| "Error while calling execution_response");
+                        }
+                        Ok(())
+                    }.boxed());
                 },
                 res = futures.next() => res.err_tip(|| "Keep-alive should always pending. Likely unable to send data to scheduler")??,
                 complete_msg = shutdown_rx.recv().fuse() => {
@@ -373,13 +561,35 @@ impl<'a, T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorkerImpl<'a,
     }
 }
 
+/// Returns how long to wait before reconnecting to the scheduler after
+/// `connection_failures` consecutive failed attempts.
+fn connection_retry_delay(connection_retry: &Retry, connection_failures: usize) -> Duration {
+    let base_delay = if connection_retry.delay == 0. {
+        CONNECTION_RETRY_DELAY_S
+    } else {
+        connection_retry.delay
+    };
+    let doublings = connection_failures.min(connection_retry.max_retries);
+    let delay = Duration::from_secs_f32(base_delay)
+        .saturating_mul(2_u32.saturating_pow(u32::try_from(doublings).unwrap_or(u32::MAX)));
+    if connection_retry.jitter == 0. {
+        return delay;
+    }
+    let min = 1. - (connection_retry.jitter / 2.);
+    let max = 1. + (connection_retry.jitter / 2.);
+    delay.mul_f32(OsRng.gen_range(min..max))
+}
+
 type ConnectionFactory<T> = Box<dyn Fn() -> BoxFuture<'static, Result<T, Error>> + Send + Sync>;
 
+type SleepFn = Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>;
+
 pub struct LocalWorker<T: WorkerApiClientTrait, U: RunningActionsManager> {
     config: Arc<LocalWorkerConfig>,
     running_actions_manager: Arc<U>,
     connection_factory: ConnectionFactory<T>,
-    sleep_fn: Option<Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>>,
+    sleep_fn: Option<SleepFn>,
+    available_space_fn: AvailableSpaceFn,
     metrics: Arc<Metrics>,
 }
 
@@ -403,12 +613,6 @@ pub async fn new_local_worker(
         .get_arc()
         .err_tip(|| "FastSlowStore's Arc doesn't exist")?;
 
-    if let Ok(path) = fs::canonicalize(&config.work_directory).await {
-        fs::remove_dir_all(path)
-            .await
-            .err_tip(|| "Could not remove work_directory in LocalWorker")?;
-    }
-
     fs::create_dir_all(&config.work_directory)
         .await
         .err_tip(|| format!("Could not make work_directory : {}", config.work_directory))?;
@@ -428,6 +632,18 @@ pub async fn new_local_worker(
             execution_configuration: ExecutionConfiguration {
                 entrypoint,
                 additional_environment: config.additional_environment.clone(),
+                allow_absolute_symlink_targets: config.allow_absolute_symlink_targets,
+                check_inputs_exist_before_download: config.check_inputs_exist_before_download,
+                stuck_action_threshold: Duration::from_secs(
+                    config.stuck_action_warning_threshold as u64,
+                ),
+                max_tree_depth: config.max_tree_depth,
+                max_tree_nodes: config.max_tree_nodes,
+                input_staging_directory: (!config.input_staging_directory.is_empty())
+                    .then(|| config.input_staging_directory.clone()),
+                action_cgroup_directory: (!config.action_cgroup_directory.is_empty())
+                    .then(|| config.action_cgroup_directory.clone()),
+                max_inline_output_bytes: config.max_inline_output_bytes,
             },
             cas_store: fast_slow_store,
             ac_store,
@@ -435,7 +651,68 @@ pub async fn new_local_worker(
             upload_action_result_config: &config.upload_action_result,
             max_action_timeout,
             timeout_handled_externally: config.timeout_handled_externally,
+            max_concurrent_actions: config.max_concurrent_actions,
+            queue_actions_over_limit: config.queue_actions_over_limit,
         })?);
+    // Nothing is running yet, so anything in the work directory is left over
+    // from a previous run.
+    running_actions_manager
+        .remove_orphaned_action_directories(Duration::ZERO)
+        .await
+        .err_tip(|| "Could not clean work_directory in LocalWorker")?;
+    running_actions_manager
+        .prune_input_staging_directory()
+        .await
+        .err_tip(|| "Could not prune input_staging_directory in LocalWorker")?;
+    if config.stale_action_directory_timeout != 0 {
+        let stale_action_directory_timeout =
+            Duration::from_secs(config.stale_action_directory_timeout as u64);
+        let weak_running_actions_manager = Arc::downgrade(&running_actions_manager);
+        background_spawn!("local_worker_remove_stale_action_directories", async move {
+            loop {
+                sleep(stale_action_directory_timeout).await;
+                let Some(running_actions_manager) = weak_running_actions_manager.upgrade() else {
+                    return;
+                };
+                if let Err(err) = running_actions_manager
+                    .remove_orphaned_action_directories(stale_action_directory_timeout)
+                    .await
+                {
+                    event!(
+                        Level::ERROR,
+                        ?err,
+                        "Failed to remove stale action directories"
+                    );
+                }
+            }
+        });
+    }
+    if !config.input_staging_directory.is_empty() {
+        let input_staging_prune_interval = if config.input_staging_prune_interval == 0 {
+            DEFAULT_INPUT_STAGING_PRUNE_INTERVAL
+        } else {
+            Duration::from_secs(config.input_staging_prune_interval as u64)
+        };
+        let weak_running_actions_manager = Arc::downgrade(&running_actions_manager);
+        background_spawn!("local_worker_prune_input_staging_directory", async move {
+            loop {
+                sleep(input_staging_prune_interval).await;
+                let Some(running_actions_manager) = weak_running_actions_manager.upgrade() else {
+                    return;
+                };
+                if let Err(err) = running_actions_manager
+                    .prune_input_staging_directory()
+                    .await
+                {
+                    event!(
+                        Level::ERROR,
+                        ?err,
+                        "Failed to prune input_staging_directory"
+                    );
+                }
+            }
+        });
+    }
     let local_worker = LocalWorker::new_with_connection_factory_and_actions_manager(
         config.clone(),
         running_actions_manager,
@@ -467,6 +744,10 @@ pub async fn new_local_worker(
             })
         }),
         Box::new(move |d| Box::pin(sleep(d))),
+        Arc::new(|path: &str| {
+            let path = path.to_string();
+            Box::pin(async move { fs::available_space(path).await })
+        }),
     );
     let metrics = local_worker.metrics.clone();
     Ok((local_worker, metrics))
@@ -478,6 +759,7 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
         running_actions_manager: Arc<U>,
         connection_factory: ConnectionFactory<T>,
         sleep_fn: Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>,
+        available_space_fn: AvailableSpaceFn,
     ) -> Self {
         let metrics = Arc::new(Metrics::new(Arc::downgrade(
             running_actions_manager.metrics(),
@@ -487,6 +769,7 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             running_actions_manager,
             connection_factory,
             sleep_fn: Some(sleep_fn),
+            available_space_fn,
             metrics,
         }
     }
@@ -499,8 +782,15 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
         &self,
         client: &mut T,
     ) -> Result<(String, Streaming<UpdateForWorker>), Error> {
-        let supported_properties =
+        let mut supported_properties =
             make_supported_properties(&self.config.platform_properties).await?;
+        if self.config.advertise_host_resources {
+            supported_properties.properties.extend(
+                make_host_resource_properties(&self.config.platform_properties)
+                    .await
+                    .err_tip(|| "In LocalWorker::register_worker")?,
+            );
+        }
         let mut update_for_worker_stream = client
             .connect_worker(supported_properties)
             .await
@@ -536,17 +826,32 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             .take()
             .err_tip(|| "Could not unwrap sleep_fn in LocalWorker::run")?;
         let sleep_fn_pin = Pin::new(&sleep_fn);
-        let error_handler = Box::pin(move |err| async move {
-            event!(Level::ERROR, ?err, "Error");
-            (sleep_fn_pin)(Duration::from_secs_f32(CONNECTION_RETRY_DELAY_S)).await;
+        let connection_retry = self.config.connection_retry.clone();
+        let error_handler = Box::pin(move |err, connection_failures| {
+            let delay = connection_retry_delay(&connection_retry, connection_failures);
+            async move {
+                event!(
+                    Level::ERROR,
+                    ?err,
+                    ?delay,
+                    "Error, reconnecting to scheduler"
+                );
+                (sleep_fn_pin)(delay).await;
+            }
         });
+        // Number of consecutive attempts to connect to the scheduler that failed.
+        let mut connection_failures = 0;
+        let (finished_actions_tx, mut finished_actions_rx) = mpsc::unbounded_channel();
+        // Closed once the actions started on a lost connection are done.
+        let mut previous_actions_rx = Vec::new();
 
         loop {
             // First connect to our endpoint.
             let mut client = match (self.connection_factory)().await {
                 Ok(client) => client,
                 Err(e) => {
-                    (error_handler)(e).await;
+                    (error_handler)(e, connection_failures).await;
+                    connection_failures += 1;
                     continue; // Try to connect again.
                 }
             };
@@ -555,7 +860,8 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
             let (mut inner, update_for_worker_stream) =
                 match self.register_worker(&mut client).await {
                     Err(e) => {
-                        (error_handler)(e).await;
+                        (error_handler)(e, connection_failures).await;
+                        connection_failures += 1;
                         continue; // Try to connect again.
                     }
                     Ok((worker_id, update_for_worker_stream)) => (
@@ -564,6 +870,8 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
                             client,
                             worker_id,
                             self.running_actions_manager.clone(),
+                            finished_actions_tx.clone(),
+                            self.available_space_fn.clone(),
                             self.metrics.clone(),
                         ),
                         update_for_worker_stream,
@@ -574,32 +882,25 @@ impl<T: WorkerApiClientTrait, U: RunningActionsManager> LocalWorker<T, U> {
                 worker_id = %inner.worker_id,
                 "Worker registered with scheduler"
             );
+            connection_failures = 0;
 
             // Now listen for connections and run all other services.
-            if let Err(err) = inner.run(update_for_worker_stream, &mut shutdown_rx).await {
-                'no_more_actions: {
-                    // Ensure there are no actions in transit before we try to kill
-                    // all our actions.
-                    const ITERATIONS: usize = 1_000;
-
-                    const ERROR_MSG: &str = "Actions in transit did not reach zero before we disconnected from the scheduler";
-
-                    let sleep_duration = ACTIONS_IN_TRANSIT_TIMEOUT_S / ITERATIONS as f32;
-                    for _ in 0..ITERATIONS {
-                        if inner.actions_in_transit.load(Ordering::Acquire) == 0 {
-                            break 'no_more_actions;
-                        }
-                        (sleep_fn_pin)(Duration::from_secs_f32(sleep_duration)).await;
-                    }
-                    event!(Level::ERROR, ERROR_MSG);
-                    return Err(err.append(ERROR_MSG));
-                }
+            if let Err(err) = inner
+                .run(
+                    update_for_worker_stream,
+                    &mut finished_actions_rx,
+                    &mut previous_actions_rx,
+                    &mut shutdown_rx,
+                    &sleep_fn,
+                )
+                .await
+            {
+                // Running actions are not killed. Their results are sent to the
+                // scheduler once the worker has reconnected.
                 event!(Level::ERROR, ?err, "Worker disconnected from scheduler");
-                // Kill off any existing actions because if we re-connect, we'll
-                // get some more and it might resource lock us.
-                self.running_actions_manager.kill_all().await;
-
-                (error_handler)(err).await; // Try to connect again.
+                previous_actions_rx.extend(inner.running_actions_rx.take());
+                (error_handler)(err, connection_failures).await; // Try to connect again.
+                connection_failures += 1;
             }
         }
         // Unreachable.
@@ -620,6 +921,10 @@ pub struct Metrics {
         help = "Stats about the calls to check if an action satisfies the config supplied script."
     )]
     preconditions: AsyncCounterWrapper,
+    #[metric(
+        help = "Total number of actions rejected because the work_directory had less than min_free_disk_space free."
+    )]
+    low_disk_space_rejections: CounterWithTime,
     #[metric]
     running_actions_manager_metrics: Weak<RunningActionManagerMetrics>,
 }
@@ -633,6 +938,7 @@ impl Metrics {
             disconnects_received: CounterWithTime::default(),
             keep_alives_received: CounterWithTime::default(),
             preconditions: AsyncCounterWrapper::default(),
+            low_disk_space_rejections: CounterWithTime::default(),
             running_actions_manager_metrics,
         }
     }
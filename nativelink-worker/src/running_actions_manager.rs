@@ -15,18 +15,20 @@
 use std::borrow::Cow;
 use std::cmp::min;
 use std::collections::vec_deque::VecDeque;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::Into;
 use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
 #[cfg(target_family = "unix")]
 use std::fs::Permissions;
 #[cfg(target_family = "unix")]
+use std::io::Write;
+#[cfg(target_family = "unix")]
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::process::Stdio;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Weak};
 use std::time::{Duration, SystemTime};
 
@@ -38,7 +40,7 @@ use futures::future::{
 };
 use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
 use nativelink_config::cas_server::{
-    EnvironmentSource, UploadActionResultConfig, UploadCacheResultsStrategy,
+    EnvironmentSource, OutputCompressor, UploadActionResultConfig, UploadCacheResultsStrategy,
 };
 use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::MetricsComponent;
@@ -62,9 +64,9 @@ use nativelink_util::action_messages::{
 };
 use nativelink_util::common::{fs, DigestInfo};
 use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
-use nativelink_util::metrics_utils::{AsyncCounterWrapper, CounterWithTime};
+use nativelink_util::metrics_utils::{AsyncCounterWrapper, Counter, CounterWithTime};
 use nativelink_util::shutdown_guard::ShutdownGuard;
-use nativelink_util::store_trait::{Store, StoreLike, UploadSizeInfo};
+use nativelink_util::store_trait::{Store, StoreKey, StoreLike, UploadSizeInfo};
 use nativelink_util::{background_spawn, spawn, spawn_blocking};
 use parking_lot::Mutex;
 use prost::Message;
@@ -73,16 +75,24 @@ use scopeguard::{guard, ScopeGuard};
 use serde::Deserialize;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::process;
-use tokio::sync::{oneshot, watch};
+use tokio::sync::{oneshot, watch, OwnedSemaphorePermit, Semaphore};
 use tokio_stream::wrappers::ReadDirStream;
 use tonic::Request;
 use tracing::{enabled, event, Level};
 use uuid::Uuid;
 
+use crate::action_cgroup::ActionCgroup;
+use crate::worker_utils::{CPU_COUNT_PROPERTY_NAME, MEMORY_KB_PROPERTY_NAME};
+
 /// For simplicity we use a fixed exit code for cases when our program is terminated
 /// due to a signal.
 const EXIT_CODE_FOR_SIGNAL: i32 = 9;
 
+/// Maximum number of symlinks followed when resolving the target of an
+/// output symlink before it is considered to be part of a loop. Matches
+/// the limit of the Linux kernel.
+const MAX_SYMLINK_HOPS: usize = 40;
+
 /// Default strategy for uploading historical results.
 /// Note: If this value changes the config documentation
 /// should reflect it.
@@ -115,14 +125,46 @@ struct SideChannelInfo {
 /// efficiency reasons. We will request the `FastSlowStore` to populate the entry then we will
 /// assume the `FilesystemStore` has the file available immediately after and hardlink the file
 /// to a new location.
+///
+/// Symlinks must point inside of `current_directory` unless
+/// `allow_absolute_symlink_targets` is set, in which case they may also
+/// point to an absolute path.
+///
+/// If `input_staging_directory` is set, files already hardlinked there under
+/// their digest are linked from it without populating the fast store, and
+/// newly downloaded files are added to it.
+pub fn download_to_directory<'a>(
+    cas_store: &'a FastSlowStore,
+    filesystem_store: Pin<&'a FilesystemStore>,
+    digest: &'a DigestInfo,
+    current_directory: &'a str,
+    allow_absolute_symlink_targets: bool,
+    input_staging_directory: Option<&'a str>,
+) -> BoxFuture<'a, Result<(), Error>> {
+    download_directory(
+        cas_store,
+        filesystem_store,
+        digest,
+        current_directory,
+        0,
+        allow_absolute_symlink_targets,
+        input_staging_directory,
+    )
+}
+
+/// Downloads the directory `digest` into `current_directory`, which is
+/// `depth` directories below the root being downloaded into.
 // Sadly we cannot use `async fn` here because the rust compiler cannot determine the auto traits
 // of the future. So we need to force this function to return a dynamic future instead.
 // see: https://github.com/rust-lang/rust/issues/78649
-pub fn download_to_directory<'a>(
+fn download_directory<'a>(
     cas_store: &'a FastSlowStore,
     filesystem_store: Pin<&'a FilesystemStore>,
     digest: &'a DigestInfo,
     current_directory: &'a str,
+    depth: usize,
+    allow_absolute_symlink_targets: bool,
+    input_staging_directory: Option<&'a str>,
 ) -> BoxFuture<'a, Result<(), Error>> {
     async move {
         let directory = get_and_decode_digest::<ProtoDirectory>(cas_store, digest.into())
@@ -141,14 +183,35 @@ pub fn download_to_directory<'a>(
                 Some(properties) => (properties.mtime, properties.unix_mode),
                 None => (None, None),
             };
+            // A mode that already has an executable bit is kept as is, so it
+            // round trips exactly.
             #[cfg_attr(target_family = "windows", allow(unused_assignments))]
             if file.is_executable {
-                unix_mode = Some(unix_mode.unwrap_or(0o444) | 0o111);
+                unix_mode = Some(match unix_mode {
+                    Some(mode) if mode & 0o111 != 0 => mode,
+                    mode => mode.unwrap_or(0o444) | 0o111,
+                });
             }
+            let staged_path = input_staging_directory
+                .map(|staging_directory| format!("{staging_directory}/{digest}"));
             futures.push(
-                cas_store
-                    .populate_fast_store(digest.into())
-                    .and_then(move |()| async move {
+                async move {
+                    let is_staged = match &staged_path {
+                        // A staged file is only reused while the filesystem
+                        // store still has the digest. Looking it up touches
+                        // the entry, so it is not evicted while actions keep
+                        // using the staged file.
+                        Some(staged_path) => {
+                            filesystem_store
+                                .get_file_entry_for_digest(&digest)
+                                .await
+                                .is_ok()
+                                && fs::hard_link(staged_path, &dest).await.is_ok()
+                        }
+                        None => false,
+                    };
+                    if !is_staged {
+                        cas_store.populate_fast_store(digest.into()).await?;
                         let file_entry = filesystem_store
                             .get_file_entry_for_digest(&digest)
                             .await
@@ -159,35 +222,49 @@ pub fn download_to_directory<'a>(
                             .map_err(|e| {
                                 make_err!(Code::Internal, "Could not make hardlink, {e:?} : {dest}")
                             })?;
-                        #[cfg(target_family = "unix")]
-                        if let Some(unix_mode) = unix_mode {
-                            fs::set_permissions(&dest, Permissions::from_mode(unix_mode))
-                                .await
-                                .err_tip(|| {
-                                    format!(
-                                        "Could not set unix mode in download_to_directory {dest}"
-                                    )
-                                })?;
+                        if let Some(staged_path) = &staged_path {
+                            // A staged file left from an evicted entry is
+                            // replaced by the new one. Another download may
+                            // have staged the same digest in the meantime,
+                            // which is fine.
+                            let _ = fs::remove_file(staged_path).await;
+                            if let Err(err) = fs::hard_link(&dest, staged_path).await {
+                                if err.code != Code::AlreadyExists {
+                                    event!(
+                                        Level::WARN,
+                                        ?err,
+                                        ?staged_path,
+                                        "Could not add input file to input_staging_directory"
+                                    );
+                                }
+                            }
                         }
-                        if let Some(mtime) = mtime {
-                            spawn_blocking!("download_to_directory_set_mtime", move || {
-                                set_file_mtime(
-                                    &dest,
-                                    FileTime::from_unix_time(mtime.seconds, mtime.nanos as u32),
-                                )
-                                .err_tip(|| {
-                                    format!("Failed to set mtime in download_to_directory {dest}")
-                                })
-                            })
+                    }
+                    #[cfg(target_family = "unix")]
+                    if let Some(unix_mode) = unix_mode {
+                        fs::set_permissions(&dest, Permissions::from_mode(unix_mode))
                             .await
                             .err_tip(|| {
-                                "Failed to launch spawn_blocking in download_to_directory"
-                            })??;
-                        }
-                        Ok(())
-                    })
-                    .map_err(move |e| e.append(format!("for digest {digest}")))
-                    .boxed(),
+                                format!("Could not set unix mode in download_to_directory {dest}")
+                            })?;
+                    }
+                    if let Some(mtime) = mtime {
+                        spawn_blocking!("download_to_directory_set_mtime", move || {
+                            set_file_mtime(
+                                &dest,
+                                FileTime::from_unix_time(mtime.seconds, mtime.nanos as u32),
+                            )
+                            .err_tip(|| {
+                                format!("Failed to set mtime in download_to_directory {dest}")
+                            })
+                        })
+                        .await
+                        .err_tip(|| "Failed to launch spawn_blocking in download_to_directory")??;
+                    }
+                    Ok(())
+                }
+                .map_err(move |e: Error| e.append(format!("for digest {digest}")))
+                .boxed(),
             );
         }
 
@@ -203,11 +280,14 @@ pub fn download_to_directory<'a>(
                     fs::create_dir(&new_directory_path)
                         .await
                         .err_tip(|| format!("Could not create directory {new_directory_path}"))?;
-                    download_to_directory(
+                    download_directory(
                         cas_store,
                         filesystem_store,
                         &digest,
                         &new_directory_path,
+                        depth + 1,
+                        allow_absolute_symlink_targets,
+                        input_staging_directory,
                     )
                     .await
                     .err_tip(|| format!("in download_to_directory : {new_directory_path}"))?;
@@ -220,6 +300,8 @@ pub fn download_to_directory<'a>(
         #[cfg(target_family = "unix")]
         for symlink_node in directory.symlinks {
             let dest = format!("{}/{}", current_directory, symlink_node.name);
+            verify_symlink_target(&symlink_node.target, depth, allow_absolute_symlink_targets)
+                .err_tip(|| format!("For symlink {dest}"))?;
             futures.push(
                 async move {
                     fs::symlink(&symlink_node.target, &dest).await.err_tip(|| {
@@ -240,6 +322,71 @@ pub fn download_to_directory<'a>(
     .boxed()
 }
 
+/// Checks that every file in the input root `digest` exists in `cas_store`
+/// with a single existence check, without downloading any of the files.
+/// Fails with `Code::FailedPrecondition` listing all missing digests, so an
+/// action with missing inputs fails before its work directory is populated.
+pub async fn check_input_digests_exist(
+    cas_store: &FastSlowStore,
+    digest: &DigestInfo,
+) -> Result<(), Error> {
+    let mut file_digests = Vec::new();
+    let mut seen_file_digests = HashSet::new();
+    let mut seen_directory_digests = HashSet::from([*digest]);
+    let mut directory_digests = vec![*digest];
+    // Walk the tree one level at a time, fetching each level concurrently.
+    while !directory_digests.is_empty() {
+        let directories = try_join_all(directory_digests.iter().map(|directory_digest| {
+            get_and_decode_digest::<ProtoDirectory>(cas_store, directory_digest.into())
+        }))
+        .await
+        .err_tip(|| "Converting digest to Directory in check_input_digests_exist")?;
+        directory_digests = Vec::new();
+        for directory in directories {
+            for file in directory.files {
+                let file_digest: DigestInfo = file
+                    .digest
+                    .err_tip(|| "Expected Digest to exist in Directory::file::digest")?
+                    .try_into()
+                    .err_tip(|| "In Directory::file::digest")?;
+                if seen_file_digests.insert(file_digest) {
+                    file_digests.push(file_digest);
+                }
+            }
+            for directory_node in directory.directories {
+                let directory_digest: DigestInfo = directory_node
+                    .digest
+                    .err_tip(|| "Expected Digest to exist in Directory::directories::digest")?
+                    .try_into()
+                    .err_tip(|| "In Directory::directories::digest")?;
+                if seen_directory_digests.insert(directory_digest) {
+                    directory_digests.push(directory_digest);
+                }
+            }
+        }
+    }
+
+    let keys: Vec<StoreKey> = file_digests.iter().map(StoreKey::from).collect();
+    let results = cas_store
+        .has_many(&keys)
+        .await
+        .err_tip(|| "Checking input digests in check_input_digests_exist")?;
+    let missing_digests: Vec<String> = file_digests
+        .iter()
+        .zip(results)
+        .filter(|(_, result)| result.is_none())
+        .map(|(file_digest, _)| file_digest.to_string())
+        .collect();
+    if missing_digests.is_empty() {
+        return Ok(());
+    }
+    Err(make_err!(
+        Code::FailedPrecondition,
+        "Input digests missing from CAS: {}",
+        missing_digests.join(", ")
+    ))
+}
+
 #[cfg(target_family = "windows")]
 fn is_executable(_metadata: &std::fs::Metadata, full_path: &impl AsRef<Path>) -> bool {
     static EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "bat", "com"];
@@ -253,13 +400,31 @@ fn is_executable(metadata: &std::fs::Metadata, _full_path: &impl AsRef<Path>) ->
     (metadata.mode() & 0o111) != 0
 }
 
+#[cfg(target_family = "windows")]
+const fn file_unix_mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(target_family = "unix")]
+fn file_unix_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    Some(metadata.mode() & 0o7777)
+}
+
+/// Uploads the file at `full_path`. If `capture_unix_mode` is set the unix
+/// mode of the file is recorded, so it is restored when downloaded.
 async fn upload_file(
     cas_store: Pin<&impl StoreLike>,
     full_path: impl AsRef<Path> + Debug,
     hasher: DigestHasherFunc,
     metadata: std::fs::Metadata,
+    capture_unix_mode: bool,
 ) -> Result<FileInfo, Error> {
     let is_executable = is_executable(&metadata, &full_path);
+    let unix_mode = if capture_unix_mode {
+        file_unix_mode(&metadata)
+    } else {
+        None
+    };
     let file_size = metadata.len();
     let resumeable_file = fs::open_file(&full_path, u64::MAX)
         .await
@@ -312,9 +477,54 @@ async fn upload_file(
         name_or_path: NameOrPath::Name(name),
         digest,
         is_executable,
+        unix_mode,
     })
 }
 
+/// Ensures a symlink in a directory `depth` directories below the work
+/// directory cannot resolve to a path outside of the work directory. `..`
+/// is only allowed at the start of `target`, otherwise a `..` following a
+/// symlink to a deeper directory could climb out of the work directory
+/// even though the path looks like it stays inside.
+fn verify_symlink_target(
+    target: &str,
+    mut depth: usize,
+    allow_absolute_symlink_targets: bool,
+) -> Result<(), Error> {
+    let target_path = Path::new(target);
+    if target_path.has_root() {
+        if allow_absolute_symlink_targets {
+            return Ok(());
+        }
+        return Err(make_input_err!(
+            "Symlink target '{target}' is absolute, which is not allowed"
+        ));
+    }
+    let mut seen_name = false;
+    for component in target_path.components() {
+        match component {
+            Component::Normal(_) => seen_name = true,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if seen_name {
+                    return Err(make_input_err!(
+                        "Symlink target '{target}' must not contain '..' after a directory name"
+                    ));
+                }
+                depth = depth.checked_sub(1).ok_or_else(|| {
+                    make_input_err!("Symlink target '{target}' escapes the work directory")
+                })?;
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(make_input_err!(
+                    "Symlink target '{target}' is absolute, which is not allowed"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn upload_symlink(
     full_path: impl AsRef<Path> + Debug,
     full_work_directory_path: impl AsRef<Path>,
@@ -324,13 +534,23 @@ async fn upload_symlink(
         .err_tip(|| format!("Could not get read_link path of {full_path:?}"))?;
 
     // Detect if our symlink is inside our work directory, if it is find the
-    // relative path otherwise use the absolute path.
-    let target = if full_target_path.starts_with(full_work_directory_path.as_ref()) {
-        let full_target_path = RelativePath::from_path(&full_target_path)
+    // path relative to the directory holding the symlink, otherwise use the
+    // absolute path. The target is never followed, so it does not need to
+    // exist. `RelativePath` rejects absolute paths, so both paths are made
+    // relative to the work directory first.
+    let target = if let Ok(target_path) =
+        full_target_path.strip_prefix(full_work_directory_path.as_ref())
+    {
+        let target_path = RelativePath::from_path(target_path)
             .map_err(|v| make_err!(Code::Internal, "Could not convert {} to RelativePath", v))?;
-        RelativePath::from_path(full_work_directory_path.as_ref())
+        let symlink_directory = full_path
+            .as_ref()
+            .parent()
+            .and_then(|parent| parent.strip_prefix(full_work_directory_path.as_ref()).ok())
+            .err_tip(|| format!("Expected {full_path:?} to be inside of the work directory"))?;
+        RelativePath::from_path(symlink_directory)
             .map_err(|v| make_err!(Code::Internal, "Could not convert {} to RelativePath", v))?
-            .relative(full_target_path)
+            .relative(target_path)
             .normalize()
             .into_string()
     } else {
@@ -366,13 +586,144 @@ async fn upload_symlink(
     })
 }
 
+/// Follows the symlinks starting at `full_path` and returns the metadata of
+/// the file they resolve to. Returns `None` if that file does not exist or
+/// the symlinks form a loop, which is detected after `MAX_SYMLINK_HOPS`.
+async fn resolve_symlink_metadata(
+    full_path: impl AsRef<Path>,
+) -> Result<Option<std::fs::Metadata>, Error> {
+    let mut path = full_path.as_ref().to_path_buf();
+    for _ in 0..MAX_SYMLINK_HOPS {
+        let metadata = match fs::symlink_metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(e) if e.code == Code::NotFound => return Ok(None),
+            Err(e) => return Err(e).err_tip(|| format!("Could not stat {path:?}")),
+        };
+        if !metadata.is_symlink() {
+            return Ok(Some(metadata));
+        }
+        let target = fs::read_link(&path)
+            .await
+            .err_tip(|| format!("Could not get read_link path of {path:?}"))?;
+        path = match path.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+    }
+    Ok(None)
+}
+
+/// Joins `output_path` (relative to `working_directory`) onto `work_directory`
+/// and rejects any path that would resolve outside of `work_directory`. The
+/// resolution is purely lexical, so it is safe to call before the output exists.
+fn resolve_output_path(
+    work_directory: &str,
+    working_directory: &str,
+    output_path: &str,
+) -> Result<String, Error> {
+    let mut relative_path = PathBuf::new();
+    for component in Path::new(working_directory).join(output_path).components() {
+        match component {
+            Component::Normal(name) => relative_path.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !relative_path.pop() {
+                    return Err(make_input_err!(
+                        "Output path '{output_path}' escapes the work directory"
+                    ));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(make_input_err!(
+                    "Output path '{output_path}' must be relative to the work directory"
+                ));
+            }
+        }
+    }
+    Ok(format!("{}/{}", work_directory, relative_path.display()))
+}
+
+/// Ensures the closest existing ancestor of `full_path` is still inside
+/// `work_directory` once symlinks are resolved. This catches outputs whose
+/// parent directory is a symlink pointing outside of the work directory.
+async fn verify_output_path_ancestors(
+    work_directory: &str,
+    full_path: impl AsRef<Path> + Debug,
+) -> Result<(), Error> {
+    let canonical_work_directory = fs::canonicalize(work_directory)
+        .await
+        .err_tip(|| format!("Could not canonicalize work directory {work_directory}"))?;
+    let mut maybe_ancestor = full_path.as_ref().parent();
+    while let Some(ancestor) = maybe_ancestor {
+        match fs::canonicalize(ancestor).await {
+            Ok(canonical_ancestor) => {
+                if !canonical_ancestor.starts_with(&canonical_work_directory) {
+                    return Err(make_input_err!(
+                        "Output path {full_path:?} resolves outside of the work directory"
+                    ));
+                }
+                return Ok(());
+            }
+            Err(e) if e.code == Code::NotFound => maybe_ancestor = ancestor.parent(),
+            Err(e) => {
+                return Err(e).err_tip(|| format!("Could not canonicalize {ancestor:?}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Limits on the output directory trees `upload_directory` will walk.
+/// Zero disables a limit.
+struct UploadTreeLimits {
+    max_depth: usize,
+    max_nodes: usize,
+    /// Number of directories visited so far in the current tree.
+    nodes: AtomicUsize,
+}
+
+impl UploadTreeLimits {
+    const fn new(max_depth: usize, max_nodes: usize) -> Self {
+        Self {
+            max_depth,
+            max_nodes,
+            nodes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts a directory `depth` levels below the output directory and
+    /// errors if either limit is exceeded.
+    fn visit(&self, full_dir_path: &impl Debug, depth: usize) -> Result<(), Error> {
+        if self.max_depth != 0 && depth > self.max_depth {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Directory {full_dir_path:?} is nested deeper than the max_tree_depth of {}",
+                self.max_depth
+            ));
+        }
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_nodes != 0 && nodes > self.max_nodes {
+            return Err(make_err!(
+                Code::ResourceExhausted,
+                "Output directory has more than the max_tree_nodes of {} directories at {full_dir_path:?}",
+                self.max_nodes
+            ));
+        }
+        Ok(())
+    }
+}
+
 fn upload_directory<'a, P: AsRef<Path> + Debug + Send + Sync + Clone + 'a>(
     cas_store: Pin<&'a impl StoreLike>,
     full_dir_path: P,
     full_work_directory: &'a str,
     hasher: DigestHasherFunc,
+    capture_unix_mode: bool,
+    limits: &'a UploadTreeLimits,
+    depth: usize,
 ) -> BoxFuture<'a, Result<(Directory, VecDeque<ProtoDirectory>), Error>> {
     Box::pin(async move {
+        limits.visit(&full_dir_path, depth)?;
         let file_futures = FuturesUnordered::new();
         let dir_futures = FuturesUnordered::new();
         let symlink_futures = FuturesUnordered::new();
@@ -396,47 +747,52 @@ fn upload_directory<'a, P: AsRef<Path> + Debug + Send + Sync + Clone + 'a>(
                 if file_type.is_dir() {
                     let full_dir_path = full_dir_path.clone();
                     dir_futures.push(
-                        upload_directory(cas_store, full_path.clone(), full_work_directory, hasher)
-                            .and_then(|(dir, all_dirs)| async move {
-                                let directory_name = full_path
-                                    .file_name()
-                                    .err_tip(|| {
-                                        format!("Expected file_name to exist on {full_dir_path:?}")
-                                    })?
-                                    .to_str()
-                                    .err_tip(|| {
-                                        make_err!(
-                                            Code::Internal,
-                                            "Could not convert {:?} to string",
-                                            full_dir_path
-                                        )
-                                    })?
-                                    .to_string();
-
-                                let digest = serialize_and_upload_message(
-                                    &dir,
-                                    cas_store,
-                                    &mut hasher.hasher(),
-                                )
-                                .await
-                                .err_tip(|| format!("for {full_path:?}"))?;
-
-                                Result::<(DirectoryNode, VecDeque<Directory>), Error>::Ok((
-                                    DirectoryNode {
-                                        name: directory_name,
-                                        digest: Some(digest.into()),
-                                    },
-                                    all_dirs,
-                                ))
-                            })
-                            .boxed(),
+                        upload_directory(
+                            cas_store,
+                            full_path.clone(),
+                            full_work_directory,
+                            hasher,
+                            capture_unix_mode,
+                            limits,
+                            depth + 1,
+                        )
+                        .and_then(|(dir, all_dirs)| async move {
+                            let directory_name = full_path
+                                .file_name()
+                                .err_tip(|| {
+                                    format!("Expected file_name to exist on {full_dir_path:?}")
+                                })?
+                                .to_str()
+                                .err_tip(|| {
+                                    make_err!(
+                                        Code::Internal,
+                                        "Could not convert {:?} to string",
+                                        full_dir_path
+                                    )
+                                })?
+                                .to_string();
+
+                            let digest =
+                                serialize_and_upload_message(&dir, cas_store, &mut hasher.hasher())
+                                    .await
+                                    .err_tip(|| format!("for {full_path:?}"))?;
+
+                            Result::<(DirectoryNode, VecDeque<Directory>), Error>::Ok((
+                                DirectoryNode {
+                                    name: directory_name,
+                                    digest: Some(digest.into()),
+                                },
+                                all_dirs,
+                            ))
+                        })
+                        .boxed(),
                     );
                 } else if file_type.is_file() {
                     file_futures.push(async move {
                         let metadata = fs::metadata(&full_path)
                             .await
                             .err_tip(|| format!("Could not open file {full_path:?}"))?;
-                        upload_file(cas_store, &full_path, hasher, metadata)
+                        upload_file(cas_store, &full_path, hasher, metadata, capture_unix_mode)
                             .map_ok(Into::into)
                             .await
                     });
@@ -584,6 +940,10 @@ pub trait RunningAction: Sync + Send + Sized + Unpin + 'static {
 
     /// Returns the work directory of the action.
     fn get_work_directory(&self) -> &String;
+
+    /// Returns true if the `Action` has `do_not_cache` set, meaning its
+    /// results must not be written to the action cache.
+    fn do_not_cache(&self) -> bool;
 }
 
 struct RunningActionImplExecutionResult {
@@ -607,6 +967,8 @@ struct RunningActionImplState {
     // that prevented the action from running, upload failures, timeouts, exc...
     // but we have (or could have) the action results (like stderr/stdout).
     error: Option<Error>,
+    // The cgroup the action runs in, if any. Removed in cleanup().
+    cgroup: Option<ActionCgroup>,
 }
 
 pub struct RunningActionImpl {
@@ -618,6 +980,8 @@ pub struct RunningActionImpl {
     running_actions_manager: Arc<RunningActionsManagerImpl>,
     state: Mutex<RunningActionImplState>,
     did_cleanup: AtomicBool,
+    // Released when the action is dropped.
+    _action_slot: ActionSlot,
 }
 
 impl RunningActionImpl {
@@ -628,6 +992,7 @@ impl RunningActionImpl {
         action_info: ActionInfo,
         timeout: Duration,
         running_actions_manager: Arc<RunningActionsManagerImpl>,
+        action_slot: ActionSlot,
     ) -> Self {
         let work_directory = format!("{}/{}", action_directory, "work");
         let (kill_channel_tx, kill_channel_rx) = oneshot::channel();
@@ -646,8 +1011,10 @@ impl RunningActionImpl {
                 action_result: None,
                 execution_metadata,
                 error: None,
+                cgroup: None,
             }),
             did_cleanup: AtomicBool::new(false),
+            _action_slot: action_slot,
         }
     }
 
@@ -655,6 +1022,19 @@ impl RunningActionImpl {
         &self.running_actions_manager.metrics
     }
 
+    /// Parses the platform property `name` of the action as a number.
+    fn platform_property_u64(&self, name: &str) -> Result<Option<u64>, Error> {
+        self.action_info
+            .platform_properties
+            .get(name)
+            .map(|value| {
+                value.parse::<u64>().map_err(|e| {
+                    make_input_err!("Could not parse platform property {name}={value}: {e:?}")
+                })
+            })
+            .transpose()
+    }
+
     /// Prepares any actions needed to execution this action. This action will do the following:
     ///
     /// * Download any files needed to execute the action
@@ -681,6 +1061,19 @@ impl RunningActionImpl {
             let filesystem_store_pin =
                 Pin::new(self.running_actions_manager.filesystem_store.as_ref());
             let (command, ()) = try_join(command_fut, async {
+                if self
+                    .running_actions_manager
+                    .execution_configuration
+                    .check_inputs_exist_before_download
+                {
+                    self.metrics()
+                        .check_input_digests_exist
+                        .wrap(check_input_digests_exist(
+                            &self.running_actions_manager.cas_store,
+                            &self.action_info.input_root_digest,
+                        ))
+                        .await?;
+                }
                 fs::create_dir(&self.work_directory)
                     .await
                     .err_tip(|| format!("Error creating work directory {}", self.work_directory))?;
@@ -692,6 +1085,13 @@ impl RunningActionImpl {
                         filesystem_store_pin,
                         &self.action_info.input_root_digest,
                         &self.work_directory,
+                        self.running_actions_manager
+                            .execution_configuration
+                            .allow_absolute_symlink_targets,
+                        self.running_actions_manager
+                            .execution_configuration
+                            .input_staging_directory
+                            .as_deref(),
                     ))
                     .await
             })
@@ -700,16 +1100,13 @@ impl RunningActionImpl {
         };
         {
             // Create all directories needed for our output paths. This is required by the bazel spec.
-            let prepare_output_directories = |output_file| {
-                let full_output_path = if command.working_directory.is_empty() {
-                    format!("{}/{}", self.work_directory, output_file)
-                } else {
-                    format!(
-                        "{}/{}/{}",
-                        self.work_directory, command.working_directory, output_file
-                    )
-                };
+            let work_directory = &self.work_directory;
+            let prepare_output_directories = |output_file: &String| {
+                let full_output_path =
+                    resolve_output_path(work_directory, &command.working_directory, output_file);
                 async move {
+                    let full_output_path = full_output_path?;
+                    verify_output_path_ancestors(work_directory, &full_output_path).await?;
                     let full_parent_path = Path::new(&full_output_path)
                         .parent()
                         .err_tip(|| format!("Parent path for {full_output_path} has no parent"))?;
@@ -722,18 +1119,28 @@ impl RunningActionImpl {
                     Result::<(), Error>::Ok(())
                 }
             };
-            self.metrics()
-                .prepare_output_files
-                .wrap(try_join_all(
-                    command.output_files.iter().map(prepare_output_directories),
-                ))
-                .await?;
-            self.metrics()
-                .prepare_output_paths
-                .wrap(try_join_all(
-                    command.output_paths.iter().map(prepare_output_directories),
-                ))
-                .await?;
+            if command.output_paths.is_empty() {
+                // Older clients only set the deprecated `output_files` and
+                // `output_directories`, which `upload_results` falls back to
+                // as well.
+                self.metrics()
+                    .prepare_output_files
+                    .wrap(try_join_all(
+                        command
+                            .output_files
+                            .iter()
+                            .chain(&command.output_directories)
+                            .map(prepare_output_directories),
+                    ))
+                    .await?;
+            } else {
+                self.metrics()
+                    .prepare_output_paths
+                    .wrap(try_join_all(
+                        command.output_paths.iter().map(prepare_output_directories),
+                    ))
+                    .await?;
+            }
         }
         event!(Level::INFO, ?command, "Worker received command",);
         {
@@ -857,6 +1264,44 @@ impl RunningActionImpl {
             command_builder.env(&environment_variable.name, &environment_variable.value);
         }
 
+        let maybe_cgroup = match &self
+            .running_actions_manager
+            .execution_configuration
+            .action_cgroup_directory
+        {
+            Some(action_cgroup_directory) => {
+                let cgroup = ActionCgroup::create(
+                    action_cgroup_directory,
+                    &Uuid::new_v4().simple().to_string(),
+                    self.platform_property_u64(CPU_COUNT_PROPERTY_NAME)?,
+                    self.platform_property_u64(MEMORY_KB_PROPERTY_NAME)?,
+                )
+                .await
+                .err_tip(|| "Could not create cgroup for action")?;
+                self.state.lock().cgroup = Some(cgroup.clone());
+                Some(cgroup)
+            }
+            None => None,
+        };
+        // Cgroups are only supported on Linux, so there is never one here
+        // on other platforms.
+        #[cfg(target_family = "unix")]
+        if let Some(cgroup) = &maybe_cgroup {
+            let procs_file = cgroup
+                .open_procs_file()
+                .await
+                .err_tip(|| "Could not move action into its cgroup")?;
+            // SAFETY: The closure runs in the child between `fork()` and
+            // `exec()`, where it only makes a single `write()` call on a file
+            // opened before the fork and does not allocate.
+            unsafe {
+                command_builder.pre_exec(move || {
+                    // `0` stands for the process that writes it.
+                    (&procs_file).write_all(b"0")
+                });
+            }
+        }
+
         let mut child_process = command_builder
             .spawn()
             .err_tip(|| format!("Could not execute command {args:?}"))?;
@@ -879,6 +1324,10 @@ impl RunningActionImpl {
             });
         });
 
+        // When the child process last wrote to stdout or stderr.
+        let now_fn = self.running_actions_manager.callbacks.now_fn;
+        let last_progress = Arc::new(Mutex::new(now_fn()));
+        let stdout_last_progress = last_progress.clone();
         let all_stdout_fut = spawn!("stdout_reader", async move {
             let mut all_stdout = BytesMut::new();
             loop {
@@ -889,9 +1338,11 @@ impl RunningActionImpl {
                 if sz == 0 {
                     break; // EOF.
                 }
+                *stdout_last_progress.lock() = now_fn();
             }
             Result::<Bytes, Error>::Ok(all_stdout.freeze())
         });
+        let stderr_last_progress = last_progress.clone();
         let all_stderr_fut = spawn!("stderr_reader", async move {
             let mut all_stderr = BytesMut::new();
             loop {
@@ -902,15 +1353,62 @@ impl RunningActionImpl {
                 if sz == 0 {
                     break; // EOF.
                 }
+                *stderr_last_progress.lock() = now_fn();
             }
             Result::<Bytes, Error>::Ok(all_stderr.freeze())
         });
         let mut killed_action = false;
 
+        let stuck_action_threshold = self
+            .running_actions_manager
+            .execution_configuration
+            .stuck_action_threshold;
+        // A zero threshold disables the check, so no timer is started for it.
+        let mut stuck_check_fut = if stuck_action_threshold.is_zero() {
+            futures::future::pending().boxed()
+        } else {
+            (self.running_actions_manager.callbacks.sleep_fn)(stuck_action_threshold)
+        }
+        .fuse();
+        // Set while the action is counted in the `stuck_actions` metric.
+        let metrics = self.metrics().clone();
+        let mut is_stuck = guard(false, move |is_stuck| {
+            if is_stuck {
+                metrics.stuck_actions.sub(1);
+            }
+        });
+
         let timer = self.metrics().child_process.begin_timer();
         let mut sleep_fut = (self.running_actions_manager.callbacks.sleep_fn)(self.timeout).fuse();
         loop {
             tokio::select! {
+                () = &mut stuck_check_fut => {
+                    let idle_time = now_fn()
+                        .duration_since(*last_progress.lock())
+                        .unwrap_or_default();
+                    let next_check = if idle_time >= stuck_action_threshold {
+                        // Only warn once until the action writes output again.
+                        if !*is_stuck {
+                            *is_stuck = true;
+                            self.metrics().stuck_actions.inc();
+                            self.metrics().stuck_action_warnings.inc();
+                            event!(
+                                Level::WARN,
+                                operation_id = ?self.operation_id,
+                                ?idle_time,
+                                "Action has not written to stdout or stderr for longer than the stuck action threshold",
+                            );
+                        }
+                        stuck_action_threshold
+                    } else {
+                        if *is_stuck {
+                            *is_stuck = false;
+                            self.metrics().stuck_actions.sub(1);
+                        }
+                        stuck_action_threshold - idle_time
+                    };
+                    stuck_check_fut = (self.running_actions_manager.callbacks.sleep_fn)(next_check).fuse();
+                },
                 () = &mut sleep_fut => {
                     self.running_actions_manager.metrics.task_timeouts.inc();
                     killed_action = true;
@@ -969,9 +1467,20 @@ impl RunningActionImpl {
                     } else {
                         None
                     };
+                    let maybe_oom_error = match &maybe_cgroup {
+                        Some(cgroup) => cgroup.oom_killed().await
+                            .err_tip(|| "Could not check if action ran out of memory")?
+                            .then(|| make_err!(
+                                Code::ResourceExhausted,
+                                "Command '{}' was killed because it exceeded its memory limit",
+                                args.join(OsStr::new(" ")).to_string_lossy()
+                            )),
+                        None => None,
+                    };
                     {
                         let mut state = self.state.lock();
                         state.error = Error::merge_option(state.error.take(), maybe_error_override);
+                        state.error = Error::merge_option(state.error.take(), maybe_oom_error);
 
                         state.command_proto = Some(command_proto);
                         state.execution_result = Some(RunningActionImplExecutionResult{
@@ -1041,8 +1550,18 @@ impl RunningActionImpl {
                 state.execution_metadata.clone(),
             )
         };
-        let cas_store = self.running_actions_manager.cas_store.as_ref();
+        let cas_store = self
+            .running_actions_manager
+            .output_upload_store(self.action_info.instance_name());
         let hasher = self.action_info.unique_qualifier.digest_function();
+        // Only record the unix mode of outputs if the client asked for it.
+        let capture_unix_mode = command_proto
+            .output_node_properties
+            .iter()
+            .any(|property| property == "unix_mode");
+        let execution_configuration = &self.running_actions_manager.execution_configuration;
+        let max_tree_depth = execution_configuration.max_tree_depth;
+        let max_tree_nodes = execution_configuration.max_tree_nodes;
 
         let mut output_path_futures = FuturesUnordered::new();
         let mut output_paths = command_proto.output_paths;
@@ -1053,16 +1572,14 @@ impl RunningActionImpl {
             output_paths.append(&mut command_proto.output_directories);
         }
         for entry in output_paths {
-            let full_path = OsString::from(if command_proto.working_directory.is_empty() {
-                format!("{}/{}", self.work_directory, entry)
-            } else {
-                format!(
-                    "{}/{}/{}",
-                    self.work_directory, command_proto.working_directory, entry
-                )
-            });
+            let full_path = OsString::from(resolve_output_path(
+                &self.work_directory,
+                &command_proto.working_directory,
+                &entry,
+            )?);
             let work_directory = &self.work_directory;
             output_path_futures.push(async move {
+                verify_output_path_ancestors(work_directory, &full_path).await?;
                 let metadata = {
                     let metadata = match fs::symlink_metadata(&full_path).await {
                         Ok(file) => file,
@@ -1078,39 +1595,54 @@ impl RunningActionImpl {
 
                     if metadata.is_file() {
                         return Ok(OutputType::File(
-                            upload_file(cas_store.as_pin(), &full_path, hasher, metadata)
-                                .await
-                                .map(|mut file_info| {
-                                    file_info.name_or_path = NameOrPath::Path(entry);
-                                    file_info
-                                })
-                                .err_tip(|| format!("Uploading file {full_path:?}"))?,
+                            upload_file(
+                                cas_store.as_pin(),
+                                &full_path,
+                                hasher,
+                                metadata,
+                                capture_unix_mode,
+                            )
+                            .await
+                            .map(|mut file_info| {
+                                file_info.name_or_path = NameOrPath::Path(entry);
+                                file_info
+                            })
+                            .err_tip(|| format!("Uploading file {full_path:?}"))?,
                         ));
                     }
                     metadata
                 };
                 if metadata.is_dir() {
+                    let limits = UploadTreeLimits::new(max_tree_depth, max_tree_nodes);
                     Ok(OutputType::Directory(
-                        upload_directory(cas_store.as_pin(), &full_path, work_directory, hasher)
-                            .and_then(|(root_dir, children)| async move {
-                                let tree = ProtoTree {
-                                    root: Some(root_dir),
-                                    children: children.into(),
-                                };
-                                let tree_digest = serialize_and_upload_message(
-                                    &tree,
-                                    cas_store.as_pin(),
-                                    &mut hasher.hasher(),
-                                )
-                                .await
-                                .err_tip(|| format!("While processing {entry}"))?;
-                                Ok(DirectoryInfo {
-                                    path: entry,
-                                    tree_digest,
-                                })
-                            })
+                        upload_directory(
+                            cas_store.as_pin(),
+                            &full_path,
+                            work_directory,
+                            hasher,
+                            capture_unix_mode,
+                            &limits,
+                            0,
+                        )
+                        .and_then(|(root_dir, children)| async move {
+                            let tree = ProtoTree {
+                                root: Some(root_dir),
+                                children: children.into(),
+                            };
+                            let tree_digest = serialize_and_upload_message(
+                                &tree,
+                                cas_store.as_pin(),
+                                &mut hasher.hasher(),
+                            )
                             .await
-                            .err_tip(|| format!("Uploading directory {full_path:?}"))?,
+                            .err_tip(|| format!("While processing {entry}"))?;
+                            Ok(DirectoryInfo {
+                                path: entry,
+                                tree_digest,
+                            })
+                        })
+                        .await
+                        .err_tip(|| format!("Uploading directory {full_path:?}"))?,
                     ))
                 } else if metadata.is_symlink() {
                     let output_symlink = upload_symlink(&full_path, work_directory)
@@ -1120,26 +1652,19 @@ impl RunningActionImpl {
                             symlink_info
                         })
                         .err_tip(|| format!("Uploading symlink {full_path:?}"))?;
-                    match fs::metadata(&full_path).await {
-                        Ok(metadata) => {
-                            if metadata.is_dir() {
-                                return Ok(OutputType::DirectorySymlink(output_symlink));
-                            }
-                            // Note: If it's anything but directory we put it as a file symlink.
-                            return Ok(OutputType::FileSymlink(output_symlink));
-                        }
-                        Err(e) => {
-                            if e.code != Code::NotFound {
-                                return Err(e).err_tip(|| {
-                                    format!(
-                                        "While querying target symlink metadata for {full_path:?}"
-                                    )
-                                });
-                            }
-                            // If the file doesn't exist, we consider it a file. Even though the
-                            // file doesn't exist we still need to populate an entry.
-                            return Ok(OutputType::FileSymlink(output_symlink));
+                    let maybe_target_metadata =
+                        resolve_symlink_metadata(&full_path).await.err_tip(|| {
+                            format!("While querying target symlink metadata for {full_path:?}")
+                        })?;
+                    match maybe_target_metadata {
+                        Some(metadata) if metadata.is_dir() => {
+                            Ok(OutputType::DirectorySymlink(output_symlink))
                         }
+                        // Note: If it's anything but directory we put it as a file symlink.
+                        // If the target doesn't exist or can't be resolved because of a loop,
+                        // we consider it a file. Even though there is no target we still need
+                        // to populate an entry.
+                        _ => Ok(OutputType::FileSymlink(output_symlink)),
                     }
                 } else {
                     Err(make_err!(
@@ -1169,26 +1694,39 @@ impl RunningActionImpl {
             }
         }
 
-        let stdout_digest_fut = self.metrics().upload_stdout.wrap(async {
+        // Small outputs are inlined in the result instead of uploaded.
+        let max_inline_output_bytes = self
+            .running_actions_manager
+            .execution_configuration
+            .max_inline_output_bytes;
+        let should_inline =
+            |data: &Bytes| !data.is_empty() && data.len() <= max_inline_output_bytes;
+        let stdout_fut = self.metrics().upload_stdout.wrap(async {
             let data = execution_result.stdout;
+            if should_inline(&data) {
+                return Result::<(Option<DigestInfo>, Vec<u8>), Error>::Ok((None, data.to_vec()));
+            }
             let digest = compute_buf_digest(&data, &mut hasher.hasher());
             cas_store
                 .update_oneshot(digest, data)
                 .await
                 .err_tip(|| "Uploading stdout")?;
-            Result::<DigestInfo, Error>::Ok(digest)
+            Ok((Some(digest), Vec::new()))
         });
-        let stderr_digest_fut = self.metrics().upload_stderr.wrap(async {
+        let stderr_fut = self.metrics().upload_stderr.wrap(async {
             let data = execution_result.stderr;
+            if should_inline(&data) {
+                return Result::<(Option<DigestInfo>, Vec<u8>), Error>::Ok((None, data.to_vec()));
+            }
             let digest = compute_buf_digest(&data, &mut hasher.hasher());
             cas_store
                 .update_oneshot(digest, data)
                 .await
                 .err_tip(|| "Uploading stdout")?;
-            Result::<DigestInfo, Error>::Ok(digest)
+            Ok((Some(digest), Vec::new()))
         });
 
-        let upload_result = futures::try_join!(stdout_digest_fut, stderr_digest_fut, async {
+        let upload_result = futures::try_join!(stdout_fut, stderr_fut, async {
             while let Some(output_type) = output_path_futures.try_next().await? {
                 match output_type {
                     OutputType::File(output_file) => output_files.push(output_file),
@@ -1205,8 +1743,8 @@ impl RunningActionImpl {
             Ok(())
         });
         drop(output_path_futures);
-        let (stdout_digest, stderr_digest) = match upload_result {
-            Ok((stdout_digest, stderr_digest, ())) => (stdout_digest, stderr_digest),
+        let ((stdout_digest, stdout_raw), (stderr_digest, stderr_raw)) = match upload_result {
+            Ok((stdout, stderr, ())) => (stdout, stderr),
             Err(e) => return Err(e).err_tip(|| "Error while uploading results"),
         };
 
@@ -1228,6 +1766,8 @@ impl RunningActionImpl {
                 exit_code: execution_result.exit_code,
                 stdout_digest,
                 stderr_digest,
+                stdout_raw,
+                stderr_raw,
                 execution_metadata,
                 server_logs: HashMap::default(), // TODO(allada) Not implemented.
                 error: state.error.clone(),
@@ -1310,12 +1850,21 @@ impl RunningAction for RunningActionImpl {
             .clone()
             .cleanup
             .wrap(async move {
-                let result = do_cleanup(
+                let maybe_cgroup = self.state.lock().cgroup.take();
+                let mut result = do_cleanup(
                     &self.running_actions_manager,
                     &self.operation_id,
                     &self.action_directory,
                 )
                 .await;
+                if let Some(cgroup) = maybe_cgroup {
+                    result = result.merge(
+                        cgroup
+                            .remove()
+                            .await
+                            .err_tip(|| "Could not remove cgroup of action"),
+                    );
+                }
                 self.did_cleanup.store(true, Ordering::Release);
                 result.map(move |()| self)
             })
@@ -1333,6 +1882,10 @@ impl RunningAction for RunningActionImpl {
     fn get_work_directory(&self) -> &String {
         &self.work_directory
     }
+
+    fn do_not_cache(&self) -> bool {
+        self.action_info.do_not_cache
+    }
 }
 
 pub trait RunningActionsManager: Sync + Send + Sized + Unpin + 'static {
@@ -1392,12 +1945,38 @@ pub struct ExecutionConfiguration {
     /// executes other than those in the `ActionInfo`.  On Windows, `SystemRoot`
     /// and PATH are also assigned (see `inner_execute`).
     pub additional_environment: Option<HashMap<String, EnvironmentSource>>,
+    /// If symlinks in the input root may point to absolute paths.
+    pub allow_absolute_symlink_targets: bool,
+    /// If every input file is checked to exist in the CAS before any of
+    /// them are downloaded.
+    pub check_inputs_exist_before_download: bool,
+    /// How long an action may run without writing to stdout or stderr before
+    /// a warning is logged and it is counted as stuck. Zero disables this.
+    pub stuck_action_threshold: Duration,
+    /// Maximum number of directory levels below an output directory that
+    /// will be uploaded. Zero is unlimited.
+    pub max_tree_depth: usize,
+    /// Maximum number of directories in an output directory tree that will
+    /// be uploaded. Zero is unlimited.
+    pub max_tree_nodes: usize,
+    /// Directory input files are hardlinked into under their digest, so they
+    /// can be reused by later actions without populating the fast store.
+    pub input_staging_directory: Option<String>,
+    /// Directory each action gets its own cgroup in, limited to the CPUs
+    /// and memory in the action's platform properties.
+    pub action_cgroup_directory: Option<String>,
+    /// Stdout and stderr of at most this many bytes are inlined in the
+    /// `ActionResult` instead of uploaded to the CAS. Zero disables this.
+    pub max_inline_output_bytes: usize,
 }
 
 struct UploadActionResults {
     upload_ac_results_strategy: UploadCacheResultsStrategy,
     upload_historical_results_strategy: UploadCacheResultsStrategy,
     ac_store: Option<Store>,
+    // Results whose encoded `ExecuteResponse` is larger than this are not
+    // published to the `ac_store`. Zero means no limit.
+    max_action_result_size: usize,
     historical_store: Store,
     success_message_template: Template,
     failure_message_template: Template,
@@ -1425,6 +2004,7 @@ impl UploadActionResults {
             upload_ac_results_strategy: config.upload_ac_results_strategy,
             upload_historical_results_strategy,
             ac_store,
+            max_action_result_size: config.max_action_result_size,
             historical_store,
             success_message_template: Template::new(&config.success_message_template).map_err(
                 |e| {
@@ -1465,18 +2045,29 @@ impl UploadActionResults {
         }
     }
 
+    /// Describes why an action finished, eg: that it timed out or the exit
+    /// code of the command.
+    fn exit_reason(action_result: &ActionResult) -> String {
+        match &action_result.error {
+            Some(error) => error.client_message(),
+            None => format!("Exited with code {}", action_result.exit_code),
+        }
+    }
+
     /// Formats the message field in `ExecuteResponse` from the `success_message_template`
     /// or `failure_message_template` config templates.
     fn format_execute_response_message(
         mut template_str: Template,
         action_digest_info: DigestInfo,
         maybe_historical_digest_info: Option<DigestInfo>,
+        exit_reason: &str,
         hasher: DigestHasherFunc,
     ) -> Result<String, Error> {
         template_str.replace(
             "digest_function",
             hasher.proto_digest_func().as_str_name().to_lowercase(),
         );
+        template_str.replace("exit_reason", exit_reason);
         template_str.replace(
             "action_digest_hash",
             action_digest_info.packed_hash().to_string(),
@@ -1542,6 +2133,7 @@ impl UploadActionResults {
         action_digest: DigestInfo,
         execute_response: ExecuteResponse,
         message_template: Template,
+        exit_reason: &str,
         hasher: DigestHasherFunc,
     ) -> Result<String, Error> {
         let historical_digest_info = serialize_and_upload_message(
@@ -1559,6 +2151,7 @@ impl UploadActionResults {
             message_template,
             action_digest,
             Some(historical_digest_info),
+            exit_reason,
             hasher,
         )
         .err_tip(|| "Could not format message in upload_historical_results_with_message")
@@ -1574,12 +2167,6 @@ impl UploadActionResults {
             Self::should_cache_result(self.upload_historical_results_strategy, action_result, true);
         let should_upload_ac_results =
             Self::should_cache_result(self.upload_ac_results_strategy, action_result, false);
-        // Shortcut so we don't need to convert to proto if not needed.
-        if !should_upload_ac_results && !should_upload_historical_results {
-            return Ok(());
-        }
-
-        let mut execute_response = to_execute_response(action_result.clone());
 
         // In theory exit code should always be != 0 if there's an error, but for safety we
         // catch both.
@@ -1588,6 +2175,22 @@ impl UploadActionResults {
         } else {
             self.failure_message_template.clone()
         };
+        let exit_reason = Self::exit_reason(action_result);
+
+        // Shortcut so we don't need to convert to proto if not needed.
+        if !should_upload_ac_results && !should_upload_historical_results {
+            action_result.message = Self::format_execute_response_message(
+                message_template,
+                action_info,
+                None,
+                &exit_reason,
+                hasher,
+            )
+            .err_tip(|| "Could not format message in cache_action_result")?;
+            return Ok(());
+        }
+
+        let mut execute_response = to_execute_response(action_result.clone());
 
         let upload_historical_results_with_message_result = if should_upload_historical_results {
             let maybe_message = self
@@ -1595,6 +2198,7 @@ impl UploadActionResults {
                     action_info,
                     execute_response.clone(),
                     message_template,
+                    &exit_reason,
                     hasher,
                 )
                 .await;
@@ -1607,8 +2211,13 @@ impl UploadActionResults {
                 Err(e) => Result::<(), Error>::Err(e),
             }
         } else {
-            match Self::format_execute_response_message(message_template, action_info, None, hasher)
-            {
+            match Self::format_execute_response_message(
+                message_template,
+                action_info,
+                None,
+                &exit_reason,
+                hasher,
+            ) {
                 Ok(message) => {
                     action_result.message.clone_from(&message);
                     execute_response.message = message;
@@ -1621,7 +2230,22 @@ impl UploadActionResults {
         // Note: Done in this order because we assume most results will succed and most configs will
         // either always upload upload historical results or only upload on filure. In which case
         // we can avoid an extra clone of the protos by doing this last with the above assumption.
-        let ac_upload_results = if should_upload_ac_results {
+        let result_size = execute_response.encoded_len();
+        let ac_upload_results = if should_upload_ac_results
+            && self.max_action_result_size != 0
+            && result_size > self.max_action_result_size
+        {
+            // The scheduler rejects results this large, so caching it would
+            // only serve a result no client could have received.
+            event!(
+                Level::WARN,
+                action_digest = ?action_info,
+                result_size,
+                max_action_result_size = self.max_action_result_size,
+                "Not caching action result larger than max_action_result_size",
+            );
+            Ok(())
+        } else if should_upload_ac_results {
             self.upload_ac_results(
                 action_info,
                 execute_response
@@ -1646,6 +2270,22 @@ pub struct RunningActionsManagerArgs<'a> {
     pub upload_action_result_config: &'a UploadActionResultConfig,
     pub max_action_timeout: Duration,
     pub timeout_handled_externally: bool,
+    pub max_concurrent_actions: usize,
+    pub queue_actions_over_limit: bool,
+}
+
+/// One of the slots limiting how many actions run at once. It is taken when
+/// an action is created and given back when the action is dropped.
+struct ActionSlot {
+    // None if the number of concurrent actions is not limited.
+    _permit: Option<OwnedSemaphorePermit>,
+    metrics: Arc<Metrics>,
+}
+
+impl Drop for ActionSlot {
+    fn drop(&mut self) {
+        self.metrics.running_actions.sub(1);
+    }
 }
 
 /// Holds state info about what is being executed and the interface for interacting
@@ -1655,9 +2295,16 @@ pub struct RunningActionsManagerImpl {
     execution_configuration: ExecutionConfiguration,
     cas_store: Arc<FastSlowStore>,
     filesystem_store: Arc<FilesystemStore>,
+    // Stores that upload outputs to the slow store as compressed blobs, keyed
+    // by instance name. Other instances upload outputs to `cas_store`.
+    compressed_output_stores: HashMap<String, Arc<FastSlowStore>>,
     upload_action_results: UploadActionResults,
     max_action_timeout: Duration,
     timeout_handled_externally: bool,
+    max_concurrent_actions: usize,
+    queue_actions_over_limit: bool,
+    // None if `max_concurrent_actions` is zero (unlimited).
+    action_slots: Option<Arc<Semaphore>>,
     running_actions: Mutex<HashMap<OperationId, Weak<RunningActionImpl>>>,
     // Note: We don't use Notify because we need to support a .wait_for()-like function, which
     // Notify does not support.
@@ -1681,12 +2328,35 @@ impl RunningActionsManagerImpl {
             })?
             .get_arc()
             .err_tip(|| "FilesystemStore's internal Arc was lost")?;
+        let compressed_output_stores = args
+            .upload_action_result_config
+            .output_compression
+            .iter()
+            .map(|(instance_name, OutputCompressor::zstd)| {
+                let grpc_store = args
+                    .cas_store
+                    .slow_store()
+                    .downcast_ref::<GrpcStore>(None)
+                    .err_tip(|| {
+                        format!(
+                            "Expected GrpcStore store for .slow_store() to compress outputs of instance {instance_name}"
+                        )
+                    })?;
+                let output_store = FastSlowStore::new_with_max_concurrent_populations(
+                    0,
+                    args.cas_store.fast_store().clone(),
+                    Store::new(grpc_store.with_compressed_uploads()),
+                );
+                Ok((instance_name.clone(), output_store))
+            })
+            .collect::<Result<HashMap<_, _>, Error>>()?;
         let (action_done_tx, _) = watch::channel(());
         Ok(Self {
             root_action_directory: args.root_action_directory,
             execution_configuration: args.execution_configuration,
             cas_store: args.cas_store,
             filesystem_store,
+            compressed_output_stores,
             upload_action_results: UploadActionResults::new(
                 args.upload_action_result_config,
                 args.ac_store,
@@ -1695,13 +2365,27 @@ impl RunningActionsManagerImpl {
             .err_tip(|| "During RunningActionsManagerImpl construction")?,
             max_action_timeout: args.max_action_timeout,
             timeout_handled_externally: args.timeout_handled_externally,
+            max_concurrent_actions: args.max_concurrent_actions,
+            queue_actions_over_limit: args.queue_actions_over_limit,
+            action_slots: (args.max_concurrent_actions != 0)
+                .then(|| Arc::new(Semaphore::new(args.max_concurrent_actions))),
             running_actions: Mutex::new(HashMap::new()),
             action_done_tx,
             callbacks,
-            metrics: Arc::new(Metrics::default()),
+            metrics: Arc::new(Metrics {
+                max_concurrent_actions: args.max_concurrent_actions as u64,
+                ..Default::default()
+            }),
         })
     }
 
+    /// The store outputs of actions of `instance_name` are uploaded to.
+    fn output_upload_store(&self, instance_name: &str) -> &FastSlowStore {
+        self.compressed_output_stores
+            .get(instance_name)
+            .unwrap_or(&self.cas_store)
+    }
+
     pub fn new(args: RunningActionsManagerArgs<'_>) -> Result<Self, Error> {
         Self::new_with_callbacks(
             args,
@@ -1712,6 +2396,143 @@ impl RunningActionsManagerImpl {
         )
     }
 
+    /// Takes a slot for a new action. If `max_concurrent_actions` actions are
+    /// already running, this waits for one to finish when
+    /// `queue_actions_over_limit` is set, otherwise it fails with
+    /// `ResourceExhausted`.
+    async fn acquire_action_slot(&self) -> Result<ActionSlot, Error> {
+        let permit = match &self.action_slots {
+            None => None,
+            Some(action_slots) if self.queue_actions_over_limit => Some(
+                action_slots
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|e| make_err!(Code::Internal, "Action slots closed: {e:?}"))?,
+            ),
+            Some(action_slots) => Some(action_slots.clone().try_acquire_owned().map_err(|_| {
+                make_err!(
+                    Code::ResourceExhausted,
+                    "Worker is already running the maximum of {} concurrent actions",
+                    self.max_concurrent_actions
+                )
+            })?),
+        };
+        self.metrics.running_actions.inc();
+        Ok(ActionSlot {
+            _permit: permit,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    /// Removes entries in `root_action_directory` that don't belong to a
+    /// running action and were last modified at least `min_age` ago. These
+    /// are left behind if the worker stops before an action is cleaned up.
+    pub async fn remove_orphaned_action_directories(&self, min_age: Duration) -> Result<(), Error> {
+        let (_permit, mut dir_handle) = fs::read_dir(&self.root_action_directory)
+            .await
+            .err_tip(|| "Failed opening work directory to remove orphaned action directories")?
+            .into_inner();
+        let now = (self.callbacks.now_fn)();
+        while let Some(dir_entry) = dir_handle
+            .next_entry()
+            .await
+            .err_tip(|| "Failed reading work directory in remove_orphaned_action_directories")?
+        {
+            let operation_id = OperationId::from(dir_entry.file_name().to_string_lossy().as_ref());
+            if self.running_actions.lock().contains_key(&operation_id) {
+                continue;
+            }
+            let metadata = dir_entry
+                .metadata()
+                .await
+                .err_tip(|| "Failed to get metadata in remove_orphaned_action_directories")?;
+            let age = metadata
+                .modified()
+                .map(|modified| now.duration_since(modified).unwrap_or_default())
+                .unwrap_or_default();
+            if age < min_age {
+                continue;
+            }
+            let path = dir_entry.path();
+            let remove_result = if metadata.is_dir() {
+                fs::remove_dir_all(&path).await
+            } else {
+                fs::remove_file(&path).await
+            };
+            match remove_result {
+                Ok(()) => self.metrics.orphaned_action_directories_removed.inc(),
+                Err(err) => event!(
+                    Level::WARN,
+                    ?path,
+                    ?err,
+                    "Failed to remove orphaned action directory"
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the input staging directory if it is configured and removes
+    /// the files in it whose digest is no longer in the filesystem store.
+    /// Staged files are hardlinks, so they would otherwise keep evicted
+    /// content on disk.
+    pub async fn prune_input_staging_directory(&self) -> Result<(), Error> {
+        let Some(input_staging_directory) = &self.execution_configuration.input_staging_directory
+        else {
+            return Ok(());
+        };
+        fs::create_dir_all(input_staging_directory)
+            .await
+            .err_tip(|| {
+                format!("Could not make input_staging_directory : {input_staging_directory}")
+            })?;
+        let (_permit, mut dir_handle) = fs::read_dir(input_staging_directory)
+            .await
+            .err_tip(|| "Failed opening input_staging_directory")?
+            .into_inner();
+        let mut staged_files = Vec::new();
+        while let Some(dir_entry) = dir_handle
+            .next_entry()
+            .await
+            .err_tip(|| "Failed reading input_staging_directory")?
+        {
+            // Staged files are named `{hash}-{size}`.
+            let file_name = dir_entry.file_name();
+            let maybe_digest = file_name
+                .to_str()
+                .and_then(|file_name| file_name.split_once('-'))
+                .and_then(|(hash, size)| Some((hash, size.parse::<u64>().ok()?)))
+                .and_then(|(hash, size)| DigestInfo::try_new(hash, size).ok());
+            staged_files.push((dir_entry.path(), maybe_digest));
+        }
+        let keys: Vec<StoreKey<'_>> = staged_files
+            .iter()
+            .filter_map(|(_, maybe_digest)| maybe_digest.map(StoreKey::from))
+            .collect();
+        let mut results = self
+            .filesystem_store
+            .has_many(&keys)
+            .await
+            .err_tip(|| "In prune_input_staging_directory")?
+            .into_iter();
+        for (path, maybe_digest) in staged_files {
+            let is_in_store = maybe_digest.is_some() && results.next().flatten().is_some();
+            if is_in_store {
+                continue;
+            }
+            if let Err(err) = fs::remove_file(&path).await {
+                event!(
+                    Level::WARN,
+                    ?path,
+                    ?err,
+                    "Failed to remove file from input_staging_directory"
+                );
+            }
+        }
+        Ok(())
+    }
+
     fn make_action_directory<'a>(
         &'a self,
         operation_id: &'a OperationId,
@@ -1800,13 +2621,15 @@ impl RunningActionsManager for RunningActionsManagerImpl {
         self.metrics
             .create_and_add_action
             .wrap(async move {
+                let action_slot = self.acquire_action_slot().await?;
                 let queued_timestamp = start_execute
                     .queued_timestamp
                     .and_then(|time| time.try_into().ok())
                     .unwrap_or(SystemTime::UNIX_EPOCH);
                 let operation_id = start_execute
                     .operation_id.as_str().into();
-                let action_info = self.create_action_info(start_execute, queued_timestamp).await?;
+                let action_info =
+                    self.create_action_info(start_execute, queued_timestamp).await?;
                 event!(
                     Level::INFO,
                     ?action_info,
@@ -1845,6 +2668,7 @@ impl RunningActionsManager for RunningActionsManagerImpl {
                     action_info,
                     timeout,
                     self.clone(),
+                    action_slot,
                 ));
                 {
                     let mut running_actions = self.running_actions.lock();
@@ -1951,6 +2775,8 @@ pub struct Metrics {
     get_finished_result: AsyncCounterWrapper,
     #[metric(help = "Stats about the get_proto_command_from_store command.")]
     get_proto_command_from_store: AsyncCounterWrapper,
+    #[metric(help = "Stats about the check_input_digests_exist command.")]
+    check_input_digests_exist: AsyncCounterWrapper,
     #[metric(help = "Stats about the download_to_directory command.")]
     download_to_directory: AsyncCounterWrapper,
     #[metric(help = "Stats about the prepare_output_files command.")]
@@ -1969,4 +2795,23 @@ pub struct Metrics {
     upload_stderr: AsyncCounterWrapper,
     #[metric(help = "Total number of task timeouts.")]
     task_timeouts: CounterWithTime,
+    #[metric(help = "Number of actions currently on this worker.")]
+    running_actions: Counter,
+    #[metric(help = "Maximum number of concurrent actions, zero is unlimited.")]
+    max_concurrent_actions: u64,
+    #[metric(help = "Total number of orphaned action directories removed.")]
+    orphaned_action_directories_removed: CounterWithTime,
+    #[metric(
+        help = "Number of running actions that have not written any output for longer than the stuck action threshold."
+    )]
+    stuck_actions: Counter,
+    #[metric(help = "Total number of actions detected as stuck.")]
+    stuck_action_warnings: CounterWithTime,
+}
+
+impl Metrics {
+    /// Total number of actions detected as stuck.
+    pub fn stuck_action_warnings(&self) -> u64 {
+        self.stuck_action_warnings.counter.load(Ordering::Acquire)
+    }
 }
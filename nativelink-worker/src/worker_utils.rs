@@ -17,6 +17,7 @@ use std::hash::BuildHasher;
 use std::io::{BufRead, BufReader, Cursor};
 use std::process::Stdio;
 use std::str::from_utf8;
+use std::thread::available_parallelism;
 
 use futures::future::try_join_all;
 use nativelink_config::cas_server::WorkerProperty;
@@ -101,3 +102,61 @@ pub async fn make_supported_properties<S: BuildHasher>(
         properties: try_join_all(futures).await?.into_iter().flatten().collect(),
     })
 }
+
+/// Name of the platform property advertising the number of CPUs.
+pub const CPU_COUNT_PROPERTY_NAME: &str = "cpu_count";
+
+/// Name of the platform property advertising the memory in KiB.
+pub const MEMORY_KB_PROPERTY_NAME: &str = "memory_kb";
+
+/// Detects the CPUs and memory of the host and returns them as
+/// properties, skipping any property already set in `worker_properties`.
+pub async fn make_host_resource_properties<S: BuildHasher>(
+    worker_properties: &HashMap<String, WorkerProperty, S>,
+) -> Result<Vec<Property>, Error> {
+    let mut props = Vec::with_capacity(2);
+    if !worker_properties.contains_key(CPU_COUNT_PROPERTY_NAME) {
+        let cpu_count = available_parallelism()
+            .err_tip(|| "Could not detect the number of CPUs of the host")?;
+        props.push(Property {
+            name: CPU_COUNT_PROPERTY_NAME.to_string(),
+            value: cpu_count.to_string(),
+        });
+    }
+    if !worker_properties.contains_key(MEMORY_KB_PROPERTY_NAME) {
+        props.push(Property {
+            name: MEMORY_KB_PROPERTY_NAME.to_string(),
+            value: detect_memory_kb().await?.to_string(),
+        });
+    }
+    Ok(props)
+}
+
+#[cfg(target_os = "linux")]
+async fn detect_memory_kb() -> Result<u64, Error> {
+    let meminfo = tokio::fs::read_to_string("/proc/meminfo")
+        .await
+        .err_tip(|| "Could not read /proc/meminfo")?;
+    let mut memory_kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.trim().strip_suffix("kB"))
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .err_tip(|| "Could not find MemTotal in /proc/meminfo")?;
+    // Containers are usually limited by their cgroup rather than the host.
+    // The file contains "max" if there is no limit.
+    if let Ok(limit) = tokio::fs::read_to_string("/sys/fs/cgroup/memory.max").await {
+        if let Ok(limit_bytes) = limit.trim().parse::<u64>() {
+            memory_kb = memory_kb.min(limit_bytes / 1024);
+        }
+    }
+    Ok(memory_kb)
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn detect_memory_kb() -> Result<u64, Error> {
+    Err(make_err!(
+        nativelink_error::Code::Unimplemented,
+        "Detecting the memory of the host is only supported on Linux, set the {MEMORY_KB_PROPERTY_NAME} platform property instead"
+    ))
+}
@@ -21,6 +21,7 @@ use std::io::Write;
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
@@ -30,15 +31,15 @@ mod utils {
 }
 
 use hyper::body::Frame;
-use nativelink_config::cas_server::{LocalWorkerConfig, WorkerProperty};
-use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec};
+use nativelink_config::cas_server::{EndpointConfig, LocalWorkerConfig, WorkerProperty};
+use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, Retry, StoreSpec};
 use nativelink_error::{make_err, make_input_err, Code, Error};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::platform::Property;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::update_for_worker::Update;
 use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::{
-    execute_result, ConnectionResult, ExecuteResult, KillOperationRequest, StartExecute,
-    SupportedProperties, UpdateForWorker,
+    execute_result, ConnectionResult, ExecuteResult, KeepAliveRequest, KillOperationRequest,
+    StartExecute, SupportedProperties, UpdateForWorker,
 };
 use nativelink_store::fast_slow_store::FastSlowStore;
 use nativelink_store::filesystem_store::FilesystemStore;
@@ -55,9 +56,11 @@ use pretty_assertions::assert_eq;
 use prost::Message;
 use rand::{thread_rng, Rng};
 use tokio::io::AsyncWriteExt;
-use tonic::Response;
+use tokio::sync::mpsc;
+use tonic::{Response, Status};
 use utils::local_worker_test_utils::{
-    setup_grpc_stream, setup_local_worker, setup_local_worker_with_config,
+    setup_grpc_stream, setup_local_worker, setup_local_worker_with_callbacks,
+    setup_local_worker_with_config, setup_local_worker_with_config_and_sleep_fn,
 };
 use utils::mock_running_actions_manager::MockRunningAction;
 
@@ -129,6 +132,51 @@ async fn platform_properties_smoke_test() -> Result<(), Error> {
     Ok(())
 }
 
+#[nativelink_test]
+async fn advertise_host_resources_adds_detected_properties_test() -> Result<(), Error> {
+    const ARBITRARY_LARGE_TIMEOUT: f32 = 10000.;
+    let mut test_context = setup_local_worker_with_config(LocalWorkerConfig {
+        // The configured value takes precedence over the detected one.
+        platform_properties: HashMap::from([(
+            "memory_kb".to_string(),
+            WorkerProperty::values(vec!["1024".to_string()]),
+        )]),
+        advertise_host_resources: true,
+        worker_api_endpoint: EndpointConfig {
+            timeout: Some(ARBITRARY_LARGE_TIMEOUT),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .await;
+    let streaming_response = test_context.maybe_streaming_response.take().unwrap();
+
+    let mut supported_properties = test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+    supported_properties
+        .properties
+        .sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(
+        supported_properties,
+        SupportedProperties {
+            properties: vec![
+                Property {
+                    name: "cpu_count".to_string(),
+                    value: std::thread::available_parallelism()?.to_string(),
+                },
+                Property {
+                    name: "memory_kb".to_string(),
+                    value: "1024".to_string(),
+                },
+            ]
+        }
+    );
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn reconnect_on_server_disconnect_test() -> Result<(), Box<dyn std::error::Error>> {
     let mut test_context = setup_local_worker(HashMap::new()).await;
@@ -160,7 +208,7 @@ async fn reconnect_on_server_disconnect_test() -> Result<(), Box<dyn std::error:
 }
 
 #[nativelink_test]
-async fn kill_all_called_on_disconnect() -> Result<(), Box<dyn std::error::Error>> {
+async fn actions_keep_running_across_reconnect_test() -> Result<(), Box<dyn std::error::Error>> {
     let mut test_context = setup_local_worker(HashMap::new()).await;
     let streaming_response = test_context.maybe_streaming_response.take().unwrap();
 
@@ -173,7 +221,6 @@ async fn kill_all_called_on_disconnect() -> Result<(), Box<dyn std::error::Error
         assert_eq!(props, SupportedProperties::default());
     }
 
-    // Handle registration (kill_all not called unless registered).
     let tx_stream = test_context.maybe_tx_stream.take().unwrap();
     {
         tx_stream
@@ -186,11 +233,110 @@ async fn kill_all_called_on_disconnect() -> Result<(), Box<dyn std::error::Error
             .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
     }
 
-    // Disconnect our grpc stream.
+    let action_digest = DigestInfo::new([3u8; 32], 10);
+    let action_info = ActionInfo {
+        command_digest: DigestInfo::new([1u8; 32], 10),
+        input_root_digest: DigestInfo::new([2u8; 32], 10),
+        timeout: Duration::from_secs(1),
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: SystemTime::UNIX_EPOCH,
+        insert_timestamp: SystemTime::UNIX_EPOCH,
+        unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+            instance_name: INSTANCE_NAME.to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: action_digest,
+        }),
+        do_not_cache: false,
+    };
+
+    let operation_id = OperationId::default();
+    {
+        // Send execution request.
+        tx_stream
+            .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+                update: Some(Update::StartAction(StartExecute {
+                    execute_request: Some((&action_info).into()),
+                    operation_id: operation_id.to_string(),
+                    queued_timestamp: None,
+                })),
+            })?))
+            .await
+            .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+    }
+    let running_action = Arc::new(MockRunningAction::new());
+    test_context
+        .actions_manager
+        .expect_create_and_add_action(Ok(running_action.clone()))
+        .await;
+
+    // Disconnect our grpc stream while the action is running.
     drop(tx_stream);
 
-    // Check that kill_all is called.
-    test_context.actions_manager.expect_kill_all().await;
+    let reconnected_worker_id = "foobar2".to_string();
+    let (tx_stream, streaming_response) = setup_grpc_stream();
+    test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+    tx_stream
+        .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+            update: Some(Update::ConnectionResult(ConnectionResult {
+                worker_id: reconnected_worker_id.clone(),
+            })),
+        })?))
+        .await
+        .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+
+    // The scheduler put the action back in its queue, so the worker asks it
+    // not to send new actions while the action is still running.
+    let keep_alive = test_context
+        .client
+        .expect_keep_alive(Ok(Response::new(())))
+        .await;
+    assert_eq!(
+        keep_alive,
+        KeepAliveRequest {
+            worker_id: reconnected_worker_id.clone(),
+            paused: true,
+        }
+    );
+
+    // The action was not killed and finishes after the worker reconnected.
+    running_action
+        .simple_expect_get_finished_result(Ok(ActionResult::default()))
+        .await?;
+    test_context
+        .actions_manager
+        .expect_cache_action_result()
+        .await;
+
+    // Its result is sent on the new connection, under the worker id the
+    // action was started on.
+    let execution_response = test_context
+        .client
+        .expect_execution_response(Ok(Response::new(())))
+        .await;
+    assert_eq!(execution_response.worker_id, "foobar");
+    assert_eq!(execution_response.operation_id, operation_id.to_string());
+    assert!(matches!(
+        execution_response.result,
+        Some(execute_result::Result::ExecuteResponse(_))
+    ));
+
+    // Once the result was sent, the worker accepts new actions again.
+    let keep_alive = test_context
+        .client
+        .expect_keep_alive(Ok(Response::new(())))
+        .await;
+    assert_eq!(
+        keep_alive,
+        KeepAliveRequest {
+            worker_id: reconnected_worker_id,
+            paused: false,
+        }
+    );
+    test_context.actions_manager.expect_no_kill_all().await;
 
     Ok(())
 }
@@ -238,6 +384,7 @@ async fn blake3_digest_function_registerd_properly() -> Result<(), Box<dyn std::
             digest_function: DigestHasherFunc::Blake3,
             digest: action_digest,
         }),
+        do_not_cache: false,
     };
 
     {
@@ -320,6 +467,7 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        do_not_cache: false,
     };
 
     {
@@ -341,8 +489,10 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
         output_file_symlinks: vec![],
         output_directory_symlinks: vec![],
         exit_code: 5,
-        stdout_digest: DigestInfo::new([21u8; 32], 10),
-        stderr_digest: DigestInfo::new([22u8; 32], 10),
+        stdout_digest: Some(DigestInfo::new([21u8; 32], 10)),
+        stderr_digest: Some(DigestInfo::new([22u8; 32], 10)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
         execution_metadata: ExecutionMetadata {
             worker: expected_worker_id.clone(),
             queued_timestamp: SystemTime::UNIX_EPOCH,
@@ -404,6 +554,130 @@ async fn simple_worker_start_action_test() -> Result<(), Box<dyn std::error::Err
     Ok(())
 }
 
+#[nativelink_test]
+async fn do_not_cache_action_result_is_not_cached_test() -> Result<(), Box<dyn std::error::Error>> {
+    let mut test_context = setup_local_worker(HashMap::new()).await;
+    let streaming_response = test_context.maybe_streaming_response.take().unwrap();
+
+    {
+        // Ensure our worker connects and properties were sent.
+        let props = test_context
+            .client
+            .expect_connect_worker(Ok(streaming_response))
+            .await;
+        assert_eq!(props, SupportedProperties::default());
+    }
+
+    let expected_worker_id = "foobar".to_string();
+
+    let tx_stream = test_context.maybe_tx_stream.take().unwrap();
+    {
+        // First initialize our worker by sending the response to the connection request.
+        tx_stream
+            .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+                update: Some(Update::ConnectionResult(ConnectionResult {
+                    worker_id: expected_worker_id.clone(),
+                })),
+            })?))
+            .await
+            .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+    }
+
+    let action_digest = DigestInfo::new([3u8; 32], 10);
+    let action_info = ActionInfo {
+        command_digest: DigestInfo::new([1u8; 32], 10),
+        input_root_digest: DigestInfo::new([2u8; 32], 10),
+        timeout: Duration::from_secs(1),
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: SystemTime::UNIX_EPOCH,
+        insert_timestamp: SystemTime::UNIX_EPOCH,
+        unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+            instance_name: INSTANCE_NAME.to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: action_digest,
+        }),
+        do_not_cache: false,
+    };
+
+    {
+        // Send execution request.
+        tx_stream
+            .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+                update: Some(Update::StartAction(StartExecute {
+                    execute_request: Some((&action_info).into()),
+                    operation_id: String::new(),
+                    queued_timestamp: None,
+                })),
+            })?))
+            .await
+            .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+    }
+    let action_result = ActionResult {
+        output_files: vec![],
+        output_folders: vec![],
+        output_file_symlinks: vec![],
+        output_directory_symlinks: vec![],
+        exit_code: 5,
+        stdout_digest: Some(DigestInfo::new([21u8; 32], 10)),
+        stderr_digest: Some(DigestInfo::new([22u8; 32], 10)),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
+        execution_metadata: ExecutionMetadata {
+            worker: expected_worker_id.clone(),
+            queued_timestamp: SystemTime::UNIX_EPOCH,
+            worker_start_timestamp: SystemTime::UNIX_EPOCH,
+            worker_completed_timestamp: SystemTime::UNIX_EPOCH,
+            input_fetch_start_timestamp: SystemTime::UNIX_EPOCH,
+            input_fetch_completed_timestamp: SystemTime::UNIX_EPOCH,
+            execution_start_timestamp: SystemTime::UNIX_EPOCH,
+            execution_completed_timestamp: SystemTime::UNIX_EPOCH,
+            output_upload_start_timestamp: SystemTime::UNIX_EPOCH,
+            output_upload_completed_timestamp: SystemTime::UNIX_EPOCH,
+        },
+        server_logs: HashMap::new(),
+        error: None,
+        message: String::new(),
+    };
+    let running_action = Arc::new(MockRunningAction::new_do_not_cache());
+
+    // Send and wait for response from create_and_add_action to RunningActionsManager.
+    test_context
+        .actions_manager
+        .expect_create_and_add_action(Ok(running_action.clone()))
+        .await;
+
+    // Now the RunningAction needs to send a series of state updates. This shortcuts them
+    // into a single call (shortcut for prepare, execute, upload, collect_results, cleanup).
+    running_action
+        .simple_expect_get_finished_result(Ok(action_result.clone()))
+        .await?;
+
+    // Now our client should be notified that our runner finished.
+    let execution_response = test_context
+        .client
+        .expect_execution_response(Ok(Response::new(())))
+        .await;
+
+    // The result was reported, but never written to the action cache.
+    test_context.actions_manager.expect_no_pending_calls().await;
+
+    // Now ensure the final results match our expectations.
+    assert_eq!(
+        execution_response,
+        ExecuteResult {
+            worker_id: expected_worker_id,
+            instance_name: INSTANCE_NAME.to_string(),
+            operation_id: String::new(),
+            result: Some(execute_result::Result::ExecuteResponse(
+                ActionStage::Completed(action_result).into()
+            )),
+        }
+    );
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn new_local_worker_creates_work_directory_test() -> Result<(), Box<dyn std::error::Error>> {
     let cas_store = Store::new(FastSlowStore::new(
@@ -411,6 +685,8 @@ async fn new_local_worker_creates_work_directory_test() -> Result<(), Box<dyn st
             // Note: These are not needed for this test, so we put dummy memory stores here.
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         Store::new(
             <FilesystemStore>::new(&FilesystemSpec {
@@ -451,6 +727,8 @@ async fn new_local_worker_removes_work_directory_before_start_test(
             // Note: These are not needed for this test, so we put dummy memory stores here.
             fast: StoreSpec::memory(MemorySpec::default()),
             slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         Store::new(
             <FilesystemStore>::new(&FilesystemSpec {
@@ -571,6 +849,7 @@ async fn experimental_precondition_script_fails() -> Result<(), Box<dyn std::err
             digest_function: DigestHasherFunc::Sha256,
             digest: action_digest,
         }),
+        do_not_cache: false,
     };
 
     {
@@ -609,6 +888,152 @@ async fn experimental_precondition_script_fails() -> Result<(), Box<dyn std::err
     Ok(())
 }
 
+#[nativelink_test]
+async fn low_free_disk_space_pauses_action_intake() -> Result<(), Box<dyn std::error::Error>> {
+    const WORK_DIRECTORY: &str = "/some/work_directory";
+    const MIN_FREE_DISK_SPACE: u64 = 1000;
+
+    let available_space = Arc::new(AtomicU64::new(MIN_FREE_DISK_SPACE - 1));
+    let local_worker_config = LocalWorkerConfig {
+        work_directory: WORK_DIRECTORY.to_string(),
+        min_free_disk_space: MIN_FREE_DISK_SPACE,
+        ..Default::default()
+    };
+    let mut test_context = setup_local_worker_with_callbacks(
+        local_worker_config,
+        Box::new(move |_| Box::pin(async move { /* No sleep */ })),
+        Arc::new({
+            let available_space = available_space.clone();
+            move |path: &str| {
+                assert_eq!(path, WORK_DIRECTORY);
+                let available_space = available_space.load(Ordering::Acquire);
+                Box::pin(async move { Ok(available_space) })
+            }
+        }),
+    )
+    .await;
+    let streaming_response = test_context.maybe_streaming_response.take().unwrap();
+    test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+
+    let expected_worker_id = "foobar".to_string();
+    let tx_stream = test_context.maybe_tx_stream.take().unwrap();
+    tx_stream
+        .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+            update: Some(Update::ConnectionResult(ConnectionResult {
+                worker_id: expected_worker_id.clone(),
+            })),
+        })?))
+        .await
+        .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+
+    // The worker asks the scheduler to stop sending actions right away.
+    let keep_alive = test_context
+        .client
+        .expect_keep_alive(Ok(Response::new(())))
+        .await;
+    assert_eq!(
+        keep_alive,
+        KeepAliveRequest {
+            worker_id: expected_worker_id.clone(),
+            paused: true,
+        }
+    );
+
+    let action_info = ActionInfo {
+        command_digest: DigestInfo::new([1u8; 32], 10),
+        input_root_digest: DigestInfo::new([2u8; 32], 10),
+        timeout: Duration::from_secs(1),
+        platform_properties: HashMap::new(),
+        priority: 0,
+        load_timestamp: SystemTime::UNIX_EPOCH,
+        insert_timestamp: SystemTime::UNIX_EPOCH,
+        unique_qualifier: ActionUniqueQualifier::Uncachable(ActionUniqueKey {
+            instance_name: INSTANCE_NAME.to_string(),
+            digest_function: DigestHasherFunc::Sha256,
+            digest: DigestInfo::new([3u8; 32], 10),
+        }),
+        do_not_cache: false,
+    };
+    let start_action = encode_stream_proto(&UpdateForWorker {
+        update: Some(Update::StartAction(StartExecute {
+            execute_request: Some((&action_info).into()),
+            operation_id: String::new(),
+            queued_timestamp: None,
+        })),
+    })?;
+
+    // Actions the scheduler sent before it paused the worker are rejected
+    // while the disk is too full.
+    tx_stream
+        .send(Frame::data(start_action.clone()))
+        .await
+        .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+    let execution_response = test_context
+        .client
+        .expect_execution_response(Ok(Response::new(())))
+        .await;
+    assert_eq!(
+        execution_response,
+        ExecuteResult {
+            worker_id: expected_worker_id.clone(),
+            instance_name: INSTANCE_NAME.to_string(),
+            operation_id: String::new(),
+            result: Some(execute_result::Result::InternalError(
+                make_err!(
+                    Code::ResourceExhausted,
+                    "Only {} bytes are free on the filesystem of {WORK_DIRECTORY}, below min_free_disk_space of {MIN_FREE_DISK_SPACE} bytes",
+                    MIN_FREE_DISK_SPACE - 1
+                )
+                .into()
+            )),
+        }
+    );
+
+    // Once space is freed, the worker asks for actions again and accepts
+    // them.
+    available_space.store(MIN_FREE_DISK_SPACE, Ordering::Release);
+    let keep_alive = test_context
+        .client
+        .expect_keep_alive(Ok(Response::new(())))
+        .await;
+    assert_eq!(
+        keep_alive,
+        KeepAliveRequest {
+            worker_id: expected_worker_id.clone(),
+            paused: false,
+        }
+    );
+    tx_stream
+        .send(Frame::data(start_action))
+        .await
+        .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+    let running_action = Arc::new(MockRunningAction::new());
+    test_context
+        .actions_manager
+        .expect_create_and_add_action(Ok(running_action.clone()))
+        .await;
+    running_action
+        .simple_expect_get_finished_result(Ok(ActionResult::default()))
+        .await?;
+    test_context
+        .actions_manager
+        .expect_cache_action_result()
+        .await;
+    let execution_response = test_context
+        .client
+        .expect_execution_response(Ok(Response::new(())))
+        .await;
+    assert!(matches!(
+        execution_response.result,
+        Some(execute_result::Result::ExecuteResponse(_))
+    ));
+
+    Ok(())
+}
+
 #[nativelink_test]
 async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Error>> {
     let mut test_context = setup_local_worker(HashMap::new()).await;
@@ -624,7 +1049,7 @@ async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Er
         assert_eq!(props, SupportedProperties::default());
     }
 
-    // Handle registration (kill_all not called unless registered).
+    // Handle registration.
     let tx_stream = test_context.maybe_tx_stream.take().unwrap();
     {
         tx_stream
@@ -651,6 +1076,7 @@ async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Er
             digest_function: DigestHasherFunc::Blake3,
             digest: action_digest,
         }),
+        do_not_cache: false,
     };
 
     let operation_id = OperationId::default();
@@ -694,3 +1120,140 @@ async fn kill_action_request_kills_action() -> Result<(), Box<dyn std::error::Er
 
     Ok(())
 }
+
+#[nativelink_test]
+async fn reconnects_with_exponential_backoff_test() -> Result<(), Box<dyn std::error::Error>> {
+    const ARBITRARY_LARGE_TIMEOUT: f32 = 10000.;
+    let (tx_sleep, mut rx_sleep) = mpsc::unbounded_channel();
+    let mut test_context = setup_local_worker_with_config_and_sleep_fn(
+        LocalWorkerConfig {
+            platform_properties: HashMap::from([(
+                "foo".to_string(),
+                WorkerProperty::values(vec!["bar".to_string()]),
+            )]),
+            worker_api_endpoint: EndpointConfig {
+                timeout: Some(ARBITRARY_LARGE_TIMEOUT),
+                ..Default::default()
+            },
+            connection_retry: Retry {
+                max_retries: 2,
+                delay: 1.,
+                jitter: 0.,
+                retry_on_errors: None,
+                retry_budget: None,
+            },
+            ..Default::default()
+        },
+        Box::new(move |delay| {
+            tx_sleep.send(delay).expect("Could not send sleep delay");
+            Box::pin(async move {})
+        }),
+    )
+    .await;
+    let expected_properties = SupportedProperties {
+        properties: vec![Property {
+            name: "foo".to_string(),
+            value: "bar".to_string(),
+        }],
+    };
+
+    // The delay doubles after every failed attempt until it has doubled
+    // `max_retries` times.
+    for expected_delay_s in [1, 2, 4, 4] {
+        test_context
+            .client
+            .expect_connect_worker(Err(Status::unavailable("Scheduler is restarting")))
+            .await;
+        assert_eq!(
+            rx_sleep.recv().await,
+            Some(Duration::from_secs(expected_delay_s))
+        );
+    }
+
+    {
+        // The worker registers once the scheduler is back.
+        let streaming_response = test_context.maybe_streaming_response.take().unwrap();
+        let props = test_context
+            .client
+            .expect_connect_worker(Ok(streaming_response))
+            .await;
+        assert_eq!(props, expected_properties);
+        let tx_stream = test_context.maybe_tx_stream.take().unwrap();
+        tx_stream
+            .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+                update: Some(Update::ConnectionResult(ConnectionResult {
+                    worker_id: "foobar".to_string(),
+                })),
+            })?))
+            .await
+            .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+        // Dropping the stream simulates the scheduler going away again.
+    }
+
+    // Registering reset the backoff.
+    assert_eq!(rx_sleep.recv().await, Some(Duration::from_secs(1)));
+    let (_tx_stream, streaming_response) = setup_grpc_stream();
+    let props = test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+    assert_eq!(props, expected_properties);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn reconnects_when_scheduler_is_silent_test() -> Result<(), Box<dyn std::error::Error>> {
+    const ARBITRARY_LARGE_TIMEOUT: f32 = 10000.;
+    const SCHEDULER_KEEP_ALIVE_TIMEOUT_S: u64 = 30;
+    let (tx_sleep, mut rx_sleep) = mpsc::unbounded_channel();
+    let mut test_context = setup_local_worker_with_config_and_sleep_fn(
+        LocalWorkerConfig {
+            worker_api_endpoint: EndpointConfig {
+                timeout: Some(ARBITRARY_LARGE_TIMEOUT),
+                ..Default::default()
+            },
+            scheduler_keep_alive_timeout: SCHEDULER_KEEP_ALIVE_TIMEOUT_S as usize,
+            ..Default::default()
+        },
+        // Every sleep finishes right away, so the scheduler is silent for
+        // longer than `scheduler_keep_alive_timeout` as soon as the worker
+        // is registered.
+        Box::new(move |delay| {
+            tx_sleep.send(delay).expect("Could not send sleep delay");
+            Box::pin(async move {})
+        }),
+    )
+    .await;
+
+    let streaming_response = test_context.maybe_streaming_response.take().unwrap();
+    test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+    let tx_stream = test_context.maybe_tx_stream.take().unwrap();
+    tx_stream
+        .send(Frame::data(encode_stream_proto(&UpdateForWorker {
+            update: Some(Update::ConnectionResult(ConnectionResult {
+                worker_id: "foobar".to_string(),
+            })),
+        })?))
+        .await
+        .map_err(|e| make_input_err!("Could not send : {:?}", e))?;
+
+    assert_eq!(
+        rx_sleep.recv().await,
+        Some(Duration::from_secs(SCHEDULER_KEEP_ALIVE_TIMEOUT_S))
+    );
+    // The connection is considered dead even though the stream is still
+    // open, so the worker reconnects.
+    let (_tx_stream, streaming_response) = setup_grpc_stream();
+    let props = test_context
+        .client
+        .expect_connect_worker(Ok(streaming_response))
+        .await;
+    assert_eq!(props, SupportedProperties::default());
+    drop(tx_stream);
+
+    Ok(())
+}
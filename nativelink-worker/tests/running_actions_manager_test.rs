@@ -20,15 +20,22 @@ use std::fs::Permissions;
 use std::io::{Cursor, Write};
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(target_family = "unix")]
+use std::path::PathBuf;
 use std::str::from_utf8;
 use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use futures::{FutureExt, StreamExt, TryFutureExt, TryStreamExt};
-use nativelink_config::cas_server::EnvironmentSource;
-use nativelink_config::stores::{FastSlowSpec, FilesystemSpec, MemorySpec, StoreSpec};
-use nativelink_error::{make_input_err, Code, Error, ResultExt};
+use nativelink_config::cas_server::{
+    ByteStreamConfig, CasStoreConfig, EnvironmentSource, OutputCompressor,
+};
+use nativelink_config::stores::{
+    EvictionPolicy, FastSlowSpec, FilesystemSpec, GrpcEndpoint, GrpcSpec, MemorySpec, Retry,
+    StoreSpec, StoreType,
+};
+use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_macro::nativelink_test;
 use nativelink_proto::build::bazel::remote::execution::v2::command::EnvironmentVariable;
 #[cfg_attr(target_family = "windows", allow(unused_imports))]
@@ -41,10 +48,14 @@ use nativelink_proto::com::github::trace_machina::nativelink::remote_execution::
     HistoricalExecuteResponse, StartExecute,
 };
 use nativelink_proto::google::rpc::Status;
+use nativelink_service::bytestream_server::ByteStreamServer;
+use nativelink_service::cas_server::CasServer;
 use nativelink_store::ac_utils::{get_and_decode_digest, serialize_and_upload_message};
 use nativelink_store::fast_slow_store::FastSlowStore;
 use nativelink_store::filesystem_store::FilesystemStore;
+use nativelink_store::grpc_store::GrpcStore;
 use nativelink_store::memory_store::MemoryStore;
+use nativelink_store::store_manager::StoreManager;
 #[cfg_attr(target_family = "windows", allow(unused_imports))]
 use nativelink_util::action_messages::SymlinkInfo;
 use nativelink_util::action_messages::{
@@ -52,15 +63,19 @@ use nativelink_util::action_messages::{
 };
 use nativelink_util::common::{fs, DigestInfo};
 use nativelink_util::digest_hasher::{DigestHasher, DigestHasherFunc};
+use nativelink_util::spawn;
 use nativelink_util::store_trait::{Store, StoreLike};
 use nativelink_worker::running_actions_manager::{
-    download_to_directory, Callbacks, ExecutionConfiguration, RunningAction, RunningActionImpl,
-    RunningActionsManager, RunningActionsManagerArgs, RunningActionsManagerImpl,
+    check_input_digests_exist, download_to_directory, Callbacks, ExecutionConfiguration,
+    RunningAction, RunningActionImpl, RunningActionsManager, RunningActionsManagerArgs,
+    RunningActionsManagerImpl,
 };
 use pretty_assertions::assert_eq;
 use prost::Message;
 use rand::{thread_rng, Rng};
-use tokio::sync::oneshot;
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Notify};
+use tonic::transport::Server;
 
 /// Get temporary path from either `TEST_TMPDIR` or best effort temp directory if
 /// not set.
@@ -104,6 +119,8 @@ async fn setup_stores() -> Result<
         &FastSlowSpec {
             fast: StoreSpec::filesystem(fast_config),
             slow: StoreSpec::memory(slow_config),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
         },
         Store::new(fast_store.clone()),
         Store::new(slow_store.clone()),
@@ -214,6 +231,8 @@ async fn download_to_directory_file_download_test() -> Result<(), Box<dyn std::e
             fast_store.as_pin(),
             &root_directory_digest,
             &download_dir,
+            false,
+            None,
         )
         .await?;
         download_dir
@@ -228,9 +247,9 @@ async fn download_to_directory_file_download_test() -> Result<(), Box<dyn std::e
         assert_eq!(std::str::from_utf8(&file2_content)?, FILE2_CONTENT);
 
         let file2_metadata = fs::metadata(&file2_path).await?;
-        // Note: We sent 0o710, but because is_executable was set it turns into 0o711.
+        // Note: We sent 0o710, which is already executable, so it is kept as is.
         #[cfg(target_family = "unix")]
-        assert_eq!(file2_metadata.mode() & 0o777, FILE2_MODE | 0o111);
+        assert_eq!(file2_metadata.mode() & 0o777, FILE2_MODE);
         assert_eq!(
             file2_metadata
                 .modified()?
@@ -318,6 +337,8 @@ async fn download_to_directory_folder_download_test() -> Result<(), Box<dyn std:
             fast_store.as_pin(),
             &root_directory_digest,
             &download_dir,
+            false,
+            None,
         )
         .await?;
         download_dir
@@ -391,6 +412,8 @@ async fn download_to_directory_symlink_download_test() -> Result<(), Box<dyn std
             fast_store.as_pin(),
             &root_directory_digest,
             &download_dir,
+            false,
+            None,
         )
         .await?;
         download_dir
@@ -411,6 +434,200 @@ async fn download_to_directory_symlink_download_test() -> Result<(), Box<dyn std
     Ok(())
 }
 
+// Windows does not support symlinks.
+#[cfg(not(target_family = "windows"))]
+async fn download_symlinks_to_directory(
+    symlinks: Vec<SymlinkNode>,
+    allow_absolute_symlink_targets: bool,
+) -> Result<(String, Result<(), Error>), Box<dyn std::error::Error>> {
+    let (fast_store, slow_store, cas_store, _ac_store) = setup_stores().await?;
+
+    let root_directory_digest = DigestInfo::new([2u8; 32], 32);
+    let root_directory = Directory {
+        symlinks,
+        ..Default::default()
+    };
+    slow_store
+        .as_ref()
+        .update_oneshot(root_directory_digest, root_directory.encode_to_vec().into())
+        .await?;
+
+    let download_dir = make_temp_path("download_dir");
+    fs::create_dir_all(&download_dir)
+        .await
+        .err_tip(|| format!("Could not make download_dir : {download_dir}"))?;
+    let result = download_to_directory(
+        cas_store.as_ref(),
+        fast_store.as_pin(),
+        &root_directory_digest,
+        &download_dir,
+        allow_absolute_symlink_targets,
+        None,
+    )
+    .await;
+    Ok((download_dir, result))
+}
+
+#[cfg(not(target_family = "windows"))]
+#[nativelink_test]
+async fn download_to_directory_rejects_absolute_symlink_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let symlinks = vec![SymlinkNode {
+        name: "etc".to_string(),
+        target: "/etc".to_string(),
+        node_properties: None,
+    }];
+    let (download_dir, result) = download_symlinks_to_directory(symlinks, false).await?;
+    let err = result.expect_err("Expected absolute symlink to be rejected");
+    assert_eq!(err.code, Code::InvalidArgument);
+    assert!(
+        err.message_string()
+            .contains("Symlink target '/etc' is absolute, which is not allowed"),
+        "Unexpected error: {err:?}"
+    );
+    assert_eq!(
+        fs::symlink_metadata(format!("{download_dir}/etc"))
+            .await
+            .map(|_| ())
+            .map_err(|e| e.code),
+        Err(Code::NotFound)
+    );
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[nativelink_test]
+async fn download_to_directory_allows_absolute_symlink_when_configured_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let symlinks = vec![SymlinkNode {
+        name: "etc".to_string(),
+        target: "/etc".to_string(),
+        node_properties: None,
+    }];
+    let (download_dir, result) = download_symlinks_to_directory(symlinks, true).await?;
+    result?;
+    assert_eq!(
+        fs::read_link(format!("{download_dir}/etc")).await?,
+        PathBuf::from("/etc")
+    );
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[nativelink_test]
+async fn download_to_directory_rejects_escaping_symlink_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    for target in ["../outside", "dir/../../outside"] {
+        let symlinks = vec![SymlinkNode {
+            name: "escape".to_string(),
+            target: target.to_string(),
+            node_properties: None,
+        }];
+        let (_download_dir, result) = download_symlinks_to_directory(symlinks, true).await?;
+        let err = result.expect_err("Expected escaping symlink to be rejected");
+        assert_eq!(err.code, Code::InvalidArgument, "For target {target}");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_family = "windows"))]
+#[nativelink_test]
+async fn download_to_directory_contains_symlink_cycle_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let symlinks = vec![
+        SymlinkNode {
+            name: "a".to_string(),
+            target: "b".to_string(),
+            node_properties: None,
+        },
+        SymlinkNode {
+            name: "b".to_string(),
+            target: "./a".to_string(),
+            node_properties: None,
+        },
+    ];
+    let (download_dir, result) = download_symlinks_to_directory(symlinks, false).await?;
+    result?;
+    // Both symlinks stay inside of the download directory, so the cycle is
+    // created as is and only fails to resolve.
+    assert_eq!(
+        fs::read_link(format!("{download_dir}/a")).await?,
+        PathBuf::from("b")
+    );
+    assert_eq!(
+        fs::read_link(format!("{download_dir}/b")).await?,
+        PathBuf::from("./a")
+    );
+    assert!(fs::metadata(format!("{download_dir}/a")).await.is_err());
+    Ok(())
+}
+
+#[nativelink_test]
+async fn check_input_digests_exist_lists_missing_digests_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const PRESENT_CONTENT: &str = "present";
+    let (fast_store, slow_store, cas_store, _ac_store) = setup_stores().await?;
+
+    let present_digest = DigestInfo::new([3u8; 32], PRESENT_CONTENT.len() as u64);
+    slow_store
+        .as_ref()
+        .update_oneshot(present_digest, PRESENT_CONTENT.into())
+        .await?;
+    let missing_digest = DigestInfo::new([4u8; 32], 11);
+
+    let child_directory_digest = DigestInfo::new([5u8; 32], 32);
+    let child_directory = Directory {
+        files: vec![FileNode {
+            name: "missing".to_string(),
+            digest: Some(missing_digest.into()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    slow_store
+        .as_ref()
+        .update_oneshot(
+            child_directory_digest,
+            child_directory.encode_to_vec().into(),
+        )
+        .await?;
+    let root_directory_digest = DigestInfo::new([6u8; 32], 32);
+    let root_directory = Directory {
+        files: vec![FileNode {
+            name: "present".to_string(),
+            digest: Some(present_digest.into()),
+            ..Default::default()
+        }],
+        directories: vec![DirectoryNode {
+            name: "child".to_string(),
+            digest: Some(child_directory_digest.into()),
+        }],
+        ..Default::default()
+    };
+    slow_store
+        .as_ref()
+        .update_oneshot(root_directory_digest, root_directory.encode_to_vec().into())
+        .await?;
+
+    let err = check_input_digests_exist(cas_store.as_ref(), &root_directory_digest)
+        .await
+        .expect_err("Expected missing input to be reported");
+    assert_eq!(err.code, Code::FailedPrecondition);
+    assert_eq!(
+        err.message_string(),
+        format!("Input digests missing from CAS: {missing_digest}")
+    );
+    // No input was downloaded into the fast store.
+    assert_eq!(fast_store.as_ref().has(present_digest).await?, None);
+
+    slow_store
+        .as_ref()
+        .update_oneshot(missing_digest, "now present".into())
+        .await?;
+    check_input_digests_exist(cas_store.as_ref(), &root_directory_digest).await?;
+    Ok(())
+}
+
 #[nativelink_test]
 async fn ensure_output_files_full_directories_are_created_no_working_directory_test(
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -439,6 +656,8 @@ async fn ensure_output_files_full_directories_are_created_no_working_directory_t
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
             now_fn: test_monotonic_clock,
@@ -554,6 +773,8 @@ async fn ensure_output_files_full_directories_are_created_test(
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
             now_fn: test_monotonic_clock,
@@ -644,6 +865,122 @@ async fn ensure_output_files_full_directories_are_created_test(
     Ok(())
 }
 
+#[nativelink_test]
+async fn output_paths_escaping_work_directory_are_rejected_test(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    let test_cases = [
+        ("", "../etc/passwd"),
+        ("", "foo/../../etc/passwd"),
+        ("some_cwd", "../../etc/passwd"),
+        ("", "/etc/passwd"),
+    ];
+    for (working_directory, output_path) in test_cases {
+        let command = Command {
+            arguments: vec!["true".to_string()],
+            output_paths: vec![output_path.to_string()],
+            working_directory: working_directory.to_string(),
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory {
+                directories: vec![DirectoryNode {
+                    name: "some_cwd".to_string(),
+                    digest: Some(
+                        serialize_and_upload_message(
+                            &Directory::default(),
+                            cas_store.as_pin(),
+                            &mut DigestHasherFunc::Sha256.hasher(),
+                        )
+                        .await?
+                        .into(),
+                    ),
+                }],
+                ..Default::default()
+            },
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let running_action = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: None,
+                },
+            )
+            .await?;
+
+        let Err(err) = running_action.clone().prepare_action().await else {
+            panic!("Expected output path {output_path} to be rejected");
+        };
+        assert_eq!(
+            err.code,
+            Code::InvalidArgument,
+            "Unexpected error for {output_path}: {err:?}"
+        );
+
+        running_action.cleanup().await?;
+    }
+    Ok(())
+}
+
 #[nativelink_test]
 async fn blake3_upload_files() -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
@@ -671,6 +1008,8 @@ async fn blake3_upload_files() -> Result<(), Box<dyn std::error::Error>> {
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
             now_fn: test_monotonic_clock,
@@ -765,12 +1104,12 @@ async fn blake3_upload_files() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(from_utf8(&file_content)?, "123 ");
     let stdout_content = slow_store
         .as_ref()
-        .get_part_unchunked(action_result.stdout_digest, 0, None)
+        .get_part_unchunked(action_result.stdout_digest.unwrap(), 0, None)
         .await?;
     assert_eq!(from_utf8(&stdout_content)?, "foo-stdout ");
     let stderr_content = slow_store
         .as_ref()
-        .get_part_unchunked(action_result.stderr_digest, 0, None)
+        .get_part_unchunked(action_result.stderr_digest.unwrap(), 0, None)
         .await?;
     assert_eq!(from_utf8(&stderr_content)?, "bar-stderr  ");
     let mut clock_time = make_system_time(0);
@@ -784,15 +1123,18 @@ async fn blake3_upload_files() -> Result<(), Box<dyn std::error::Error>> {
                     4
                 )?,
                 is_executable: false,
+                unix_mode: None,
             }],
-            stdout_digest: DigestInfo::try_new(
+            stdout_digest: Some(DigestInfo::try_new(
                 "af1720193ae81515067a3ef39f0dfda3ad54a1a9d216e55d32fe5c1e178c6a7d",
                 11
-            )?,
-            stderr_digest: DigestInfo::try_new(
+            )?),
+            stderr_digest: Some(DigestInfo::try_new(
                 "65e0abbae32a3aedaf040b654c6f02ace03c7690c17a8415a90fc2ec9c809a16",
                 12
-            )?,
+            )?),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
             exit_code: 0,
             output_folders: vec![],
             output_file_symlinks: vec![],
@@ -844,6 +1186,8 @@ async fn upload_files_from_above_cwd_test() -> Result<(), Box<dyn std::error::Er
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
             now_fn: test_monotonic_clock,
@@ -937,12 +1281,12 @@ async fn upload_files_from_above_cwd_test() -> Result<(), Box<dyn std::error::Er
     assert_eq!(from_utf8(&file_content)?, "123 ");
     let stdout_content = slow_store
         .as_ref()
-        .get_part_unchunked(action_result.stdout_digest, 0, None)
+        .get_part_unchunked(action_result.stdout_digest.unwrap(), 0, None)
         .await?;
     assert_eq!(from_utf8(&stdout_content)?, "foo-stdout ");
     let stderr_content = slow_store
         .as_ref()
-        .get_part_unchunked(action_result.stderr_digest, 0, None)
+        .get_part_unchunked(action_result.stderr_digest.unwrap(), 0, None)
         .await?;
     assert_eq!(from_utf8(&stderr_content)?, "bar-stderr  ");
     let mut clock_time = make_system_time(0);
@@ -956,15 +1300,18 @@ async fn upload_files_from_above_cwd_test() -> Result<(), Box<dyn std::error::Er
                     4
                 )?,
                 is_executable: false,
+                unix_mode: None,
             }],
-            stdout_digest: DigestInfo::try_new(
+            stdout_digest: Some(DigestInfo::try_new(
                 "15019a676f057d97d1ad3af86f3cc1e623cb33b18ff28422bbe3248d2471cc94",
                 11
-            )?,
-            stderr_digest: DigestInfo::try_new(
+            )?),
+            stderr_digest: Some(DigestInfo::try_new(
                 "2375ab8a01ca11e1ea7606dfb58756c153d49733cde1dbfb5a1e00f39afacf06",
                 12
-            )?,
+            )?),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
             exit_code: 0,
             output_folders: vec![],
             output_file_symlinks: vec![],
@@ -989,26 +1336,31 @@ async fn upload_files_from_above_cwd_test() -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
-// Windows does not support symlinks.
-#[cfg(not(target_family = "windows"))]
-#[cfg_attr(feature = "nix", ignore)]
+#[cfg(target_family = "unix")]
 #[nativelink_test]
-async fn upload_dir_and_symlink_test() -> Result<(), Box<dyn std::error::Error>> {
+async fn action_without_output_is_reported_as_stuck_test() -> Result<(), Box<dyn std::error::Error>>
+{
     const WORKER_ID: &str = "foo_worker_id";
+    const STUCK_ACTION_THRESHOLD: Duration = Duration::from_secs(60);
 
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
+    // The time in seconds returned by `now_fn`, advanced by the test.
+    static CLOCK: AtomicU64 = AtomicU64::new(0);
+    // Number of stuck checks the action scheduled so far.
+    static STUCK_CHECKS_SCHEDULED: AtomicU64 = AtomicU64::new(0);
+    // Notified to let the pending stuck check run.
+    static STUCK_CHECK_DUE: LazyLock<Notify> = LazyLock::new(Notify::new);
 
-    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
     let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
         RunningActionsManagerArgs {
             root_action_directory,
-            execution_configuration: ExecutionConfiguration::default(),
+            execution_configuration: ExecutionConfiguration {
+                stuck_action_threshold: STUCK_ACTION_THRESHOLD,
+                ..Default::default()
+            },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
@@ -1019,227 +1371,199 @@ async fn upload_dir_and_symlink_test() -> Result<(), Box<dyn std::error::Error>>
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
-            now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+            now_fn: || make_system_time(CLOCK.load(Ordering::Acquire)),
+            sleep_fn: |duration| {
+                if duration != STUCK_ACTION_THRESHOLD {
+                    return Box::pin(futures::future::pending());
+                }
+                STUCK_CHECKS_SCHEDULED.fetch_add(1, Ordering::AcqRel);
+                Box::pin(STUCK_CHECK_DUE.notified())
+            },
         },
     )?);
-    let queued_timestamp = make_system_time(1000);
-    let action_result = {
-        let command = Command {
-            arguments: vec![
-                "sh".to_string(),
-                "-c".to_string(),
-                concat!(
-                    "mkdir -p dir1/dir2 && ",
-                    "echo foo > dir1/file && ",
-                    "touch dir1/file2 && ",
-                    "ln -s ../file dir1/dir2/sym &&",
-                    "ln -s /dev/null empty_sym",
-                )
-                .to_string(),
-            ],
-            output_paths: vec!["dir1".to_string(), "empty_sym".to_string()],
-            working_directory: ".".to_string(),
-            environment_variables: vec![EnvironmentVariable {
-                name: "PATH".to_string(),
-                value: std::env::var("PATH").unwrap(),
-            }],
-            ..Default::default()
-        };
-        let command_digest = serialize_and_upload_message(
-            &command,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-        let input_root_digest = serialize_and_upload_message(
-            &Directory::default(),
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-        let action = Action {
-            command_digest: Some(command_digest.into()),
-            input_root_digest: Some(input_root_digest.into()),
-            ..Default::default()
-        };
-        let action_digest = serialize_and_upload_message(
-            &action,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-
-        let execute_request = ExecuteRequest {
-            action_digest: Some(action_digest.into()),
-            ..Default::default()
-        };
-        let operation_id = OperationId::default().to_string();
-
-        let running_action_impl = running_actions_manager
-            .create_and_add_action(
-                WORKER_ID.to_string(),
-                StartExecute {
-                    execute_request: Some(execute_request),
-                    operation_id,
-                    queued_timestamp: Some(queued_timestamp.into()),
-                },
-            )
-            .await?;
-
-        run_action(running_action_impl.clone()).await?
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "sleep infinity".to_string(),
+        ],
+        working_directory: ".".to_string(),
+        ..Default::default()
     };
-    let tree = get_and_decode_digest::<Tree>(
-        slow_store.as_ref(),
-        action_result.output_folders[0].tree_digest.into(),
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-    let root_directory = Directory {
-        files: vec![
-            FileNode {
-                name: "file".to_string(),
-                digest: Some(
-                    DigestInfo::try_new(
-                        "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c",
-                        4,
-                    )?
-                    .into(),
-                ),
-                ..Default::default()
-            },
-            FileNode {
-                name: "file2".to_string(),
-                digest: Some(
-                    DigestInfo::try_new(
-                        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
-                        0,
-                    )?
-                    .into(),
-                ),
-                ..Default::default()
-            },
-        ],
-        directories: vec![DirectoryNode {
-            name: "dir2".to_string(),
-            digest: Some(
-                DigestInfo::try_new(
-                    "cce0098e0b0f1d785edb0da50beedb13e27dcd459b091b2f8f82543cb7cd0527",
-                    16,
-                )?
-                .into(),
-            ),
-        }],
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
         ..Default::default()
     };
-    assert_eq!(
-        tree,
-        Tree {
-            root: Some(root_directory.clone()),
-            children: vec![
-                Directory {
-                    symlinks: vec![SymlinkNode {
-                        name: "sym".to_string(),
-                        target: "../file".to_string(),
-                        ..Default::default()
-                    }],
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let running_action_impl = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
                     ..Default::default()
-                },
-                root_directory
-            ],
-        }
-    );
-    let mut clock_time = make_system_time(0);
-    assert_eq!(
-        action_result,
-        ActionResult {
-            output_files: vec![],
-            stdout_digest: DigestInfo::try_new(
-                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
-                0
-            )?,
-            stderr_digest: DigestInfo::try_new(
-                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
-                0
-            )?,
-            exit_code: 0,
-            output_folders: vec![DirectoryInfo {
-                path: "dir1".to_string(),
-                tree_digest: DigestInfo::try_new(
-                    "adbb04fa6e166e663c1310bbf8ba494e468b1b6c33e1e5346e2216b6904c9917",
-                    490
-                )?,
-            }],
-            output_file_symlinks: vec![SymlinkInfo {
-                name_or_path: NameOrPath::Path("empty_sym".to_string()),
-                target: "/dev/null".to_string(),
-            }],
-            output_directory_symlinks: vec![],
-            server_logs: HashMap::new(),
-            execution_metadata: ExecutionMetadata {
-                worker: WORKER_ID.to_string(),
-                queued_timestamp,
-                worker_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
-                execution_start_timestamp: increment_clock(&mut clock_time),
-                execution_completed_timestamp: increment_clock(&mut clock_time),
-                output_upload_start_timestamp: increment_clock(&mut clock_time),
-                output_upload_completed_timestamp: increment_clock(&mut clock_time),
-                worker_completed_timestamp: increment_clock(&mut clock_time),
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: None,
             },
-            error: None,
-            message: String::new(),
+        )
+        .await?;
+
+    let wait_for_stuck_checks = |count| async move {
+        while STUCK_CHECKS_SCHEDULED.load(Ordering::Acquire) < count {
+            tokio::task::yield_now().await;
         }
-    );
+    };
+    let drive_clock_fut = async {
+        wait_for_stuck_checks(1).await;
+        assert_eq!(running_actions_manager.metrics().stuck_action_warnings(), 0);
+        // The action stays silent for several thresholds, but is only
+        // reported once.
+        for checks in 2..=4 {
+            CLOCK.fetch_add(STUCK_ACTION_THRESHOLD.as_secs(), Ordering::AcqRel);
+            STUCK_CHECK_DUE.notify_one();
+            wait_for_stuck_checks(checks).await;
+            assert_eq!(running_actions_manager.metrics().stuck_action_warnings(), 1);
+        }
+        running_actions_manager.kill_all().await;
+    };
+    let (result, ()) = futures::join!(run_action(running_action_impl), drive_clock_fut);
+    // Stuck actions are only reported, killing them is up to the caller.
+    assert_eq!(result?.exit_code, 9);
     Ok(())
 }
 
+#[cfg(target_family = "unix")]
 #[nativelink_test]
-async fn cleanup_happens_on_job_failure() -> Result<(), Box<dyn std::error::Error>> {
+async fn output_compression_keeps_uncompressed_digest_test(
+) -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
+    const INSTANCE_NAME: &str = "compressed_instance";
+    const FILE_CONTENT: &str = "123 123 123 123 123 123 123 123 123 123 ";
+
+    // Serve a memory backed CAS over gRPC, like a remote CAS the worker
+    // uploads its outputs to.
+    let backend_store = MemoryStore::new(&MemorySpec::default());
+    let store_manager = StoreManager::new();
+    store_manager.add_store("main_cas", Store::new(backend_store.clone()));
+    let cas_server = CasServer::new(
+        &HashMap::from([(
+            INSTANCE_NAME.to_string(),
+            CasStoreConfig {
+                cas_store: "main_cas".to_string(),
+                ..Default::default()
+            },
+        )]),
+        &store_manager,
+    )?;
+    let bytestream_server = ByteStreamServer::new(
+        &ByteStreamConfig {
+            cas_stores: HashMap::from([(INSTANCE_NAME.to_string(), "main_cas".to_string())]),
+            ..Default::default()
+        },
+        &store_manager,
+    )?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let _server_spawn = spawn!("upstream_cas", async move {
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            let stream = listener.accept().await.map(|(stream, _)| stream);
+            Some((stream, listener))
+        });
+        Server::builder()
+            .add_service(cas_server.into_service())
+            .add_service(bytestream_server.into_service())
+            .serve_with_incoming(incoming)
+            .await
+            .expect("Upstream CAS failed");
+    });
 
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let fast_config = FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: make_temp_path("temp_path"),
+        eviction_policy: None,
+        ..Default::default()
+    };
+    let slow_config = GrpcSpec {
+        instance_name: INSTANCE_NAME.to_string(),
+        endpoints: vec![GrpcEndpoint {
+            address: format!("grpc://{address}"),
+            ..Default::default()
+        }],
+        store_type: StoreType::cas,
+        retry: Retry::default(),
+        max_concurrent_requests: 0,
+        connections_per_endpoint: 0,
+        max_inline_size: 0,
+        max_concurrent_has_requests: 0,
+        compress_uploads: false,
+    };
+    let fast_store = FilesystemStore::new(&fast_config).await?;
+    let slow_store = GrpcStore::new(&slow_config).await?;
+    let cas_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::filesystem(fast_config),
+            slow: StoreSpec::grpc(slow_config),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
+        },
+        Store::new(fast_store),
+        Store::new(slow_store.clone()),
+    );
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-        RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
-            ac_store: Some(Store::new(ac_store.clone())),
+            ac_store: None,
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
                 upload_ac_results_strategy:
                     nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                output_compression: HashMap::from([(
+                    INSTANCE_NAME.to_string(),
+                    OutputCompressor::zstd,
+                )]),
                 ..Default::default()
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        },
-        Callbacks {
-            now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| Box::pin(futures::future::pending()),
-        },
-    )?);
-    let queued_timestamp = make_system_time(1000);
-
-    #[cfg(target_family = "unix")]
-    let arguments = vec!["sh".to_string(), "-c".to_string(), "exit 33".to_string()];
-    #[cfg(target_family = "windows")]
-    let arguments = vec!["cmd".to_string(), "/C".to_string(), "exit 33".to_string()];
-
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
     let action_result = {
         let command = Command {
-            arguments,
-            output_paths: vec![],
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("printf '{FILE_CONTENT}' > ./test.txt"),
+            ],
+            output_paths: vec!["test.txt".to_string()],
             working_directory: ".".to_string(),
             ..Default::default()
         };
@@ -1268,78 +1592,66 @@ async fn cleanup_happens_on_job_failure() -> Result<(), Box<dyn std::error::Erro
         .await?;
 
         let execute_request = ExecuteRequest {
+            instance_name: INSTANCE_NAME.to_string(),
             action_digest: Some(action_digest.into()),
             ..Default::default()
         };
-        let operation_id = OperationId::default().to_string();
-
         let running_action_impl = running_actions_manager
             .create_and_add_action(
                 WORKER_ID.to_string(),
                 StartExecute {
                     execute_request: Some(execute_request),
-                    operation_id,
-                    queued_timestamp: Some(queued_timestamp.into()),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: None,
                 },
             )
             .await?;
 
         run_action(running_action_impl.clone()).await?
     };
-    let mut clock_time = make_system_time(0);
-    assert_eq!(
-        action_result,
-        ActionResult {
-            output_files: vec![],
-            stdout_digest: DigestInfo::try_new(
-                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
-                0
-            )?,
-            stderr_digest: DigestInfo::try_new(
-                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
-                0
-            )?,
-            exit_code: 33,
-            output_folders: vec![],
-            output_file_symlinks: vec![],
-            output_directory_symlinks: vec![],
-            server_logs: HashMap::new(),
-            execution_metadata: ExecutionMetadata {
-                worker: WORKER_ID.to_string(),
-                queued_timestamp,
-                worker_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
-                execution_start_timestamp: increment_clock(&mut clock_time),
-                execution_completed_timestamp: increment_clock(&mut clock_time),
-                output_upload_start_timestamp: increment_clock(&mut clock_time),
-                output_upload_completed_timestamp: increment_clock(&mut clock_time),
-                worker_completed_timestamp: increment_clock(&mut clock_time),
-            },
-            error: None,
-            message: String::new(),
-        }
-    );
-    let mut dir_stream = fs::read_dir(&root_action_directory).await?;
-    assert!(
-        dir_stream.as_mut().next_entry().await?.is_none(),
-        "Expected empty directory at {root_action_directory}"
-    );
-    Ok(())
-}
 
+    // The recorded digest is the hash of the uncompressed content.
+    let expected_digest = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new(FILE_CONTENT))
+        .await?;
+    let output_digest = action_result.output_files[0].digest;
+    assert_eq!(output_digest, expected_digest);
+
+    // The upstream decompressed the upload, so readers that do not know
+    // about the compression get the original content.
+    let fetched_content = slow_store
+        .as_ref()
+        .get_part_unchunked(output_digest, 0, None)
+        .await?;
+    assert_eq!(from_utf8(&fetched_content)?, FILE_CONTENT);
+    let backend_content = backend_store
+        .as_ref()
+        .get_part_unchunked(output_digest, 0, None)
+        .await?;
+    assert_eq!(from_utf8(&backend_content)?, FILE_CONTENT);
+    Ok(())
+}
+
+#[cfg(target_family = "unix")]
 #[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn kill_ends_action() -> Result<(), Box<dyn std::error::Error>> {
+async fn unix_mode_round_trips_through_upload_and_download_test(
+) -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
 
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    let (fast_store, _slow_store, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory,
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -1351,156 +1663,149 @@ async fn kill_ends_action() -> Result<(), Box<dyn std::error::Error>> {
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        })?);
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    let action_result = {
+        let command = Command {
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                concat!(
+                    "echo foo > script && chmod 700 script && ",
+                    "echo bar > read_only && chmod 444 read_only",
+                )
+                .to_string(),
+            ],
+            output_paths: vec!["read_only".to_string(), "script".to_string()],
+            output_node_properties: vec!["unix_mode".to_string()],
+            working_directory: ".".to_string(),
+            environment_variables: vec![EnvironmentVariable {
+                name: "PATH".to_string(),
+                value: std::env::var("PATH").unwrap(),
+            }],
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
 
-    #[cfg(target_family = "unix")]
-    let arguments = vec![
-        "sh".to_string(),
-        "-c".to_string(),
-        "sleep infinity".to_string(),
-    ];
-    #[cfg(target_family = "windows")]
-    // Windows is weird with timeout, so we use ping. See:
-    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
-    let arguments = vec![
-        "cmd".to_string(),
-        "/C".to_string(),
-        "ping -n 99999 127.0.0.1".to_string(),
-    ];
+        let running_action_impl = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: None,
+                },
+            )
+            .await?;
 
-    let command = Command {
-        arguments,
-        output_paths: vec![],
-        working_directory: ".".to_string(),
-        ..Default::default()
+        run_action(running_action_impl.clone()).await?
     };
-    let command_digest = serialize_and_upload_message(
-        &command,
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let input_root_digest = serialize_and_upload_message(
-        &Directory::default(),
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let action = Action {
-        command_digest: Some(command_digest.into()),
-        input_root_digest: Some(input_root_digest.into()),
+    let mut output_files = action_result.output_files;
+    output_files.sort_by(|a, b| a.name_or_path.cmp(&b.name_or_path));
+    assert_eq!(
+        output_files
+            .iter()
+            .map(|file| (file.is_executable, file.unix_mode))
+            .collect::<Vec<_>>(),
+        vec![(false, Some(0o444)), (true, Some(0o700))]
+    );
+
+    // Download the outputs again and check the modes are restored.
+    let root_directory = Directory {
+        files: output_files
+            .into_iter()
+            .map(|mut file| {
+                let NameOrPath::Path(path) = file.name_or_path else {
+                    unreachable!("Output files always have a path");
+                };
+                file.name_or_path = NameOrPath::Name(path);
+                FileNode::from(file)
+            })
+            .collect(),
         ..Default::default()
     };
-    let action_digest = serialize_and_upload_message(
-        &action,
+    let root_directory_digest = serialize_and_upload_message(
+        &root_directory,
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-
-    let execute_request = ExecuteRequest {
-        action_digest: Some(action_digest.into()),
-        ..Default::default()
-    };
-    let operation_id = OperationId::default().to_string();
-
-    let running_action_impl = running_actions_manager
-        .clone()
-        .create_and_add_action(
-            WORKER_ID.to_string(),
-            StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
-                queued_timestamp: Some(make_system_time(1000).into()),
-            },
-        )
-        .await?;
-
-    // Start the action and kill it at the same time.
-    let result = futures::join!(
-        run_action(running_action_impl),
-        running_actions_manager.kill_all()
+    let download_dir = make_temp_path("download_dir");
+    fs::create_dir_all(&download_dir).await?;
+    download_to_directory(
+        cas_store.as_ref(),
+        fast_store.as_pin(),
+        &root_directory_digest,
+        &download_dir,
+        false,
+        None,
     )
-    .0?;
-
-    // Check that the action was killed.
-    #[cfg(target_family = "unix")]
-    assert_eq!(9, result.exit_code);
-    // Note: Windows kill command returns exit code 1.
-    #[cfg(target_family = "windows")]
-    assert_eq!(1, result.exit_code);
-
+    .await?;
+    assert_eq!(
+        fs::metadata(format!("{download_dir}/script")).await?.mode() & 0o7777,
+        0o700
+    );
+    assert_eq!(
+        fs::metadata(format!("{download_dir}/read_only"))
+            .await?
+            .mode()
+            & 0o7777,
+        0o444
+    );
     Ok(())
 }
 
-// This script runs a command under a wrapper script set in a config.
-// The wrapper script will print a constant string to stderr, and the test itself will
-// print to stdout. We then check the results of both to make sure the shell script was
-// invoked and the actual command was invoked under the shell script.
+// Windows does not support symlinks.
+#[cfg(not(target_family = "windows"))]
 #[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn entrypoint_does_invoke_if_set() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(target_family = "unix")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-#!/usr/bin/env bash
-# Print some static text to stderr. This is what the test uses to
-# make sure the script did run.
->&2 printf \"Wrapper script did run\"
-
-# Now run the real command.
-exec \"$@\"
-";
-    #[cfg(target_family = "windows")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-@echo off
-:: Print some static text to stderr. This is what the test uses to
-:: make sure the script did run.
-echo | set /p=\"Wrapper script did run\" 1>&2
-
-:: Run command, but morph the echo to ensure it doesn't
-:: add a new line to the end of the output.
-%1 | set /p=%2
-exit 0
-";
+async fn upload_dir_and_symlink_test() -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
-    const EXPECTED_STDOUT: &str = "Action did run";
 
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let test_wrapper_script = {
-        let test_wrapper_dir = make_temp_path("wrapper_dir");
-        fs::create_dir_all(&test_wrapper_dir).await?;
-        #[cfg(target_family = "unix")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
-        #[cfg(target_family = "windows")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
-
-        // We use std::fs::File here because we sometimes get strange bugs here
-        // that result in: "Text file busy (os error 26)" if it is an executeable.
-        // It is likley because somewhere the file descriotor does not get closed
-        // in tokio's async context.
-        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
-        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
-        #[cfg(target_family = "unix")]
-        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
-        test_wrapper_script_handle.sync_all()?;
-        drop(test_wrapper_script_handle);
-
-        test_wrapper_script
-    };
-
-    // TODO(#527) Sleep to reduce flakey chances.
-    tokio::time::sleep(Duration::from_millis(250)).await;
-
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
-            execution_configuration: ExecutionConfiguration {
-                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
-                additional_environment: None,
-            },
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
@@ -1511,162 +1816,203 @@ exit 0
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        })?);
-    #[cfg(target_family = "unix")]
-    let arguments = vec!["printf".to_string(), EXPECTED_STDOUT.to_string()];
-    #[cfg(target_family = "windows")]
-    let arguments = vec!["echo".to_string(), EXPECTED_STDOUT.to_string()];
-    let command = Command {
-        arguments,
-        working_directory: ".".to_string(),
-        environment_variables: vec![EnvironmentVariable {
-            name: "PATH".to_string(),
-            value: std::env::var("PATH").unwrap(),
-        }],
-        ..Default::default()
-    };
-    let command_digest = serialize_and_upload_message(
-        &command,
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let input_root_digest = serialize_and_upload_message(
-        &Directory::default(),
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let action = Action {
-        command_digest: Some(command_digest.into()),
-        input_root_digest: Some(input_root_digest.into()),
-        ..Default::default()
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    let queued_timestamp = make_system_time(1000);
+    let action_result = {
+        let command = Command {
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                concat!(
+                    "mkdir -p dir1/dir2 && ",
+                    "echo foo > dir1/file && ",
+                    "touch dir1/file2 && ",
+                    "ln -s ../file dir1/dir2/sym &&",
+                    "ln -s /dev/null empty_sym",
+                )
+                .to_string(),
+            ],
+            output_paths: vec!["dir1".to_string(), "empty_sym".to_string()],
+            working_directory: ".".to_string(),
+            environment_variables: vec![EnvironmentVariable {
+                name: "PATH".to_string(),
+                value: std::env::var("PATH").unwrap(),
+            }],
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        let running_action_impl = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    queued_timestamp: Some(queued_timestamp.into()),
+                },
+            )
+            .await?;
+
+        run_action(running_action_impl.clone()).await?
     };
-    let action_digest = serialize_and_upload_message(
-        &action,
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
+    let tree = get_and_decode_digest::<Tree>(
+        slow_store.as_ref(),
+        action_result.output_folders[0].tree_digest.into(),
     )
     .await?;
-
-    let execute_request = ExecuteRequest {
-        action_digest: Some(action_digest.into()),
+    let root_directory = Directory {
+        files: vec![
+            FileNode {
+                name: "file".to_string(),
+                digest: Some(
+                    DigestInfo::try_new(
+                        "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c",
+                        4,
+                    )?
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            FileNode {
+                name: "file2".to_string(),
+                digest: Some(
+                    DigestInfo::try_new(
+                        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                        0,
+                    )?
+                    .into(),
+                ),
+                ..Default::default()
+            },
+        ],
+        directories: vec![DirectoryNode {
+            name: "dir2".to_string(),
+            digest: Some(
+                DigestInfo::try_new(
+                    "cce0098e0b0f1d785edb0da50beedb13e27dcd459b091b2f8f82543cb7cd0527",
+                    16,
+                )?
+                .into(),
+            ),
+        }],
         ..Default::default()
     };
-    let operation_id = OperationId::default().to_string();
-
-    let running_action_impl = running_actions_manager
-        .clone()
-        .create_and_add_action(
-            WORKER_ID.to_string(),
-            StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
-                queued_timestamp: Some(make_system_time(1000).into()),
+    assert_eq!(
+        tree,
+        Tree {
+            root: Some(root_directory.clone()),
+            children: vec![
+                Directory {
+                    symlinks: vec![SymlinkNode {
+                        name: "sym".to_string(),
+                        target: "../file".to_string(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                root_directory
+            ],
+        }
+    );
+    let mut clock_time = make_system_time(0);
+    assert_eq!(
+        action_result,
+        ActionResult {
+            output_files: vec![],
+            stdout_digest: Some(DigestInfo::try_new(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                0
+            )?),
+            stderr_digest: Some(DigestInfo::try_new(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                0
+            )?),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
+            exit_code: 0,
+            output_folders: vec![DirectoryInfo {
+                path: "dir1".to_string(),
+                tree_digest: DigestInfo::try_new(
+                    "adbb04fa6e166e663c1310bbf8ba494e468b1b6c33e1e5346e2216b6904c9917",
+                    490
+                )?,
+            }],
+            output_file_symlinks: vec![SymlinkInfo {
+                name_or_path: NameOrPath::Path("empty_sym".to_string()),
+                target: "/dev/null".to_string(),
+            }],
+            output_directory_symlinks: vec![],
+            server_logs: HashMap::new(),
+            execution_metadata: ExecutionMetadata {
+                worker: WORKER_ID.to_string(),
+                queued_timestamp,
+                worker_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
+                execution_start_timestamp: increment_clock(&mut clock_time),
+                execution_completed_timestamp: increment_clock(&mut clock_time),
+                output_upload_start_timestamp: increment_clock(&mut clock_time),
+                output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                worker_completed_timestamp: increment_clock(&mut clock_time),
             },
-        )
-        .await?;
-
-    let result = run_action(running_action_impl).await?;
-    assert_eq!(result.exit_code, 0, "Exit code should be 0");
-
-    let expected_stdout = DigestHasherFunc::Sha256
-        .hasher()
-        .compute_from_reader(Cursor::new(EXPECTED_STDOUT))
-        .await?;
-    // Note: This string should match what is in worker_for_test.sh
-    let expected_stderr = DigestHasherFunc::Sha256
-        .hasher()
-        .compute_from_reader(Cursor::new("Wrapper script did run"))
-        .await?;
-    assert_eq!(expected_stdout, result.stdout_digest);
-    assert_eq!(expected_stderr, result.stderr_digest);
-
+            error: None,
+            message: String::new(),
+        }
+    );
     Ok(())
 }
 
+// Windows does not support symlinks.
+#[cfg(not(target_family = "windows"))]
 #[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn entrypoint_injects_properties() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(target_family = "unix")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-#!/usr/bin/env bash
-# Print some static text to stderr. This is what the test uses to
-# make sure the script did run.
->&2 printf \"Wrapper script did run with property $PROPERTY $VALUE $INNER_TIMEOUT\"
-
-# Now run the real command.
-exec \"$@\"
-";
-    #[cfg(target_family = "windows")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-@echo off
-:: Print some static text to stderr. This is what the test uses to
-:: make sure the script did run.
-echo | set /p=\"Wrapper script did run with property %PROPERTY% %VALUE% %INNER_TIMEOUT%\" 1>&2
-
-:: Run command, but morph the echo to ensure it doesn't
-:: add a new line to the end of the output.
-%1 | set /p=%2
-exit 0
-";
+async fn upload_directory_symlink_and_dangling_symlink_test(
+) -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
-    const EXPECTED_STDOUT: &str = "Action did run";
-    const TASK_TIMEOUT: Duration = Duration::from_secs(122);
 
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let test_wrapper_script = {
-        let test_wrapper_dir = make_temp_path("wrapper_dir");
-        fs::create_dir_all(&test_wrapper_dir).await?;
-        #[cfg(target_family = "unix")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
-        #[cfg(target_family = "windows")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
-
-        // We use std::fs::File here because we sometimes get strange bugs here
-        // that result in: "Text file busy (os error 26)" if it is an executeable.
-        // It is likley because somewhere the file descriotor does not get closed
-        // in tokio's async context.
-        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
-        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
-        #[cfg(target_family = "unix")]
-        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
-        test_wrapper_script_handle.sync_all()?;
-        drop(test_wrapper_script_handle);
-
-        test_wrapper_script
-    };
-
-    // TODO(#527) Sleep to reduce flakey chances.
-    tokio::time::sleep(Duration::from_millis(250)).await;
-
     let running_actions_manager =
         Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
-            execution_configuration: ExecutionConfiguration {
-                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
-                additional_environment: Some(HashMap::from([
-                    (
-                        "PROPERTY".to_string(),
-                        EnvironmentSource::property("property_name".to_string()),
-                    ),
-                    (
-                        "VALUE".to_string(),
-                        EnvironmentSource::value("raw_value".to_string()),
-                    ),
-                    (
-                        "INNER_TIMEOUT".to_string(),
-                        EnvironmentSource::timeout_millis,
-                    ),
-                    (
-                        "PATH".to_string(),
-                        EnvironmentSource::value(std::env::var("PATH").unwrap()),
-                    ),
-                ])),
-            },
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
@@ -1677,150 +2023,371 @@ exit 0
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         })?);
-    #[cfg(target_family = "unix")]
-    let arguments = vec!["printf".to_string(), EXPECTED_STDOUT.to_string()];
-    #[cfg(target_family = "windows")]
-    let arguments = vec!["echo".to_string(), EXPECTED_STDOUT.to_string()];
-    let command = Command {
-        arguments,
-        working_directory: ".".to_string(),
-        ..Default::default()
-    };
-    let command_digest = serialize_and_upload_message(
-        &command,
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let input_root_digest = serialize_and_upload_message(
-        &Directory::default(),
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
-    )
-    .await?;
-    let action = Action {
-        command_digest: Some(command_digest.into()),
-        input_root_digest: Some(input_root_digest.into()),
-        platform: Some(Platform {
-            properties: vec![Property {
-                name: "property_name".into(),
-                value: "property_value".into(),
+    let action_result = {
+        let command = Command {
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                concat!(
+                    "mkdir -p dir1/dir2 && ",
+                    "ln -s dir1 dir_sym && ",
+                    "ln -s does_not_exist dangling_sym && ",
+                    "ln -s \"$(pwd)/dir1/dir2\" dir1/abs_dir_sym",
+                )
+                .to_string(),
+            ],
+            output_paths: vec![
+                "dangling_sym".to_string(),
+                "dir1".to_string(),
+                "dir_sym".to_string(),
+            ],
+            working_directory: ".".to_string(),
+            environment_variables: vec![EnvironmentVariable {
+                name: "PATH".to_string(),
+                value: std::env::var("PATH").unwrap(),
             }],
-        }),
-        timeout: Some(prost_types::Duration {
-            seconds: TASK_TIMEOUT.as_secs() as i64,
-            nanos: 0,
-        }),
-        ..Default::default()
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let running_action_impl = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .await?;
+
+        run_action(running_action_impl.clone()).await?
     };
-    let action_digest = serialize_and_upload_message(
-        &action,
-        cas_store.as_pin(),
-        &mut DigestHasherFunc::Sha256.hasher(),
+
+    // The symlink to a directory is emitted as a symlink and the dangling
+    // symlink is emitted even though its target does not exist.
+    assert_eq!(
+        action_result.output_directory_symlinks,
+        vec![SymlinkInfo {
+            name_or_path: NameOrPath::Path("dir_sym".to_string()),
+            target: "dir1".to_string(),
+        }]
+    );
+    assert_eq!(
+        action_result.output_file_symlinks,
+        vec![SymlinkInfo {
+            name_or_path: NameOrPath::Path("dangling_sym".to_string()),
+            target: "does_not_exist".to_string(),
+        }]
+    );
+
+    // The symlink inside of the output directory is not recursed into and
+    // its absolute target is made relative to the directory holding it.
+    let tree = get_and_decode_digest::<Tree>(
+        slow_store.as_ref(),
+        action_result.output_folders[0].tree_digest.into(),
     )
     .await?;
+    let root_directory = tree.root.err_tip(|| "Expected tree to have a root")?;
+    assert_eq!(
+        root_directory.symlinks,
+        vec![SymlinkNode {
+            name: "abs_dir_sym".to_string(),
+            target: "dir2".to_string(),
+            ..Default::default()
+        }]
+    );
+    assert_eq!(
+        root_directory
+            .directories
+            .iter()
+            .map(|directory| directory.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["dir2"]
+    );
+    assert_eq!(tree.children.len(), 2, "Expected dir1 and dir2 in the tree");
+    Ok(())
+}
 
-    let execute_request = ExecuteRequest {
-        action_digest: Some(action_digest.into()),
-        ..Default::default()
-    };
-    let operation_id = OperationId::default().to_string();
+#[cfg(not(target_family = "windows"))]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn upload_directory_respects_tree_limits_test() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
 
-    let running_action_impl = running_actions_manager
-        .clone()
-        .create_and_add_action(
-            WORKER_ID.to_string(),
-            StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
-                queued_timestamp: Some(make_system_time(1000).into()),
-            },
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    // The action outputs five directories, nested four levels below `out`.
+    let test_cases = [(0, 0, true), (4, 5, true), (3, 0, false), (0, 4, false)];
+    for (max_tree_depth, max_tree_nodes, expect_success) in test_cases {
+        let root_action_directory = make_temp_path("root_action_directory");
+        fs::create_dir_all(&root_action_directory).await?;
+        let running_actions_manager =
+            Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+                root_action_directory,
+                execution_configuration: ExecutionConfiguration {
+                    max_tree_depth,
+                    max_tree_nodes,
+                    ..Default::default()
+                },
+                cas_store: cas_store.clone(),
+                ac_store: Some(Store::new(ac_store.clone())),
+                historical_store: Store::new(cas_store.clone()),
+                upload_action_result_config:
+                    &nativelink_config::cas_server::UploadActionResultConfig {
+                        upload_ac_results_strategy:
+                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                        ..Default::default()
+                    },
+                max_action_timeout: Duration::MAX,
+                timeout_handled_externally: false,
+                max_concurrent_actions: 0,
+                queue_actions_over_limit: false,
+            })?);
+        let command = Command {
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "mkdir -p out/a/b/c/d && touch out/a/b/c/d/file".to_string(),
+            ],
+            output_paths: vec!["out".to_string()],
+            working_directory: ".".to_string(),
+            environment_variables: vec![EnvironmentVariable {
+                name: "PATH".to_string(),
+                value: std::env::var("PATH").unwrap(),
+            }],
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
         )
         .await?;
-
-    let result = run_action(running_action_impl).await?;
-    assert_eq!(result.exit_code, 0, "Exit code should be 0");
-
-    let expected_stdout = DigestHasherFunc::Sha256
-        .hasher()
-        .compute_from_reader(Cursor::new(EXPECTED_STDOUT))
-        .await?;
-    // Note: This string should match what is in worker_for_test.sh
-    let expected_stderr = "Wrapper script did run with property property_value raw_value 122000";
-    let expected_stderr_digest = DigestHasherFunc::Sha256
-        .hasher()
-        .compute_from_reader(Cursor::new(expected_stderr))
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
         .await?;
-
-    let actual_stderr: prost::bytes::Bytes = cas_store
-        .as_ref()
-        .get_part_unchunked(result.stderr_digest, 0, None)
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
         .await?;
-    let actual_stderr_decoded = std::str::from_utf8(&actual_stderr)?;
-    assert_eq!(expected_stderr, actual_stderr_decoded);
-    assert_eq!(expected_stdout, result.stdout_digest);
-    assert_eq!(expected_stderr_digest, result.stderr_digest);
+        let running_action = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(ExecuteRequest {
+                        action_digest: Some(action_digest.into()),
+                        ..Default::default()
+                    }),
+                    operation_id: OperationId::default().to_string(),
+                    queued_timestamp: None,
+                },
+            )
+            .await?;
 
+        let result = run_action(running_action).await;
+        if expect_success {
+            assert_eq!(
+                result?.output_folders.len(),
+                1,
+                "Expected upload to succeed for max_tree_depth {max_tree_depth} and max_tree_nodes {max_tree_nodes}"
+            );
+        } else {
+            let Err(err) = result else {
+                panic!("Expected upload to fail for max_tree_depth {max_tree_depth} and max_tree_nodes {max_tree_nodes}");
+            };
+            assert_eq!(err.code, Code::ResourceExhausted, "{err:?}");
+        }
+    }
     Ok(())
 }
 
-#[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn entrypoint_sends_timeout_via_side_channel() -> Result<(), Box<dyn std::error::Error>> {
-    #[cfg(target_family = "unix")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-#!/bin/bash
-echo '{\"failure\":\"timeout\"}' > \"$SIDE_CHANNEL_FILE\"
-exit 1
-";
-    #[cfg(target_family = "windows")]
-    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
-@echo off
-echo | set /p={\"failure\":\"timeout\"} 1>&2 > %SIDE_CHANNEL_FILE%
-exit 1
-";
+async fn cleanup_happens_on_job_failure() -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
 
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
     let (_, _, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let test_wrapper_script = {
-        let test_wrapper_dir = make_temp_path("wrapper_dir");
-        fs::create_dir_all(&test_wrapper_dir).await?;
-        #[cfg(target_family = "unix")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
-        #[cfg(target_family = "windows")]
-        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    let queued_timestamp = make_system_time(1000);
 
-        // We use std::fs::File here because we sometimes get strange bugs here
-        // that result in: "Text file busy (os error 26)" if it is an executeable.
-        // It is likley because somewhere the file descriotor does not get closed
-        // in tokio's async context.
-        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
-        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
-        #[cfg(target_family = "unix")]
-        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
-        test_wrapper_script_handle.sync_all()?;
-        drop(test_wrapper_script_handle);
+    #[cfg(target_family = "unix")]
+    let arguments = vec!["sh".to_string(), "-c".to_string(), "exit 33".to_string()];
+    #[cfg(target_family = "windows")]
+    let arguments = vec!["cmd".to_string(), "/C".to_string(), "exit 33".to_string()];
 
-        test_wrapper_script
+    let action_result = {
+        let command = Command {
+            arguments,
+            output_paths: vec![],
+            working_directory: ".".to_string(),
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        let running_action_impl = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    queued_timestamp: Some(queued_timestamp.into()),
+                },
+            )
+            .await?;
+
+        run_action(running_action_impl.clone()).await?
     };
+    let mut clock_time = make_system_time(0);
+    assert_eq!(
+        action_result,
+        ActionResult {
+            output_files: vec![],
+            stdout_digest: Some(DigestInfo::try_new(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                0
+            )?),
+            stderr_digest: Some(DigestInfo::try_new(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                0
+            )?),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
+            exit_code: 33,
+            output_folders: vec![],
+            output_file_symlinks: vec![],
+            output_directory_symlinks: vec![],
+            server_logs: HashMap::new(),
+            execution_metadata: ExecutionMetadata {
+                worker: WORKER_ID.to_string(),
+                queued_timestamp,
+                worker_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
+                execution_start_timestamp: increment_clock(&mut clock_time),
+                execution_completed_timestamp: increment_clock(&mut clock_time),
+                output_upload_start_timestamp: increment_clock(&mut clock_time),
+                output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                worker_completed_timestamp: increment_clock(&mut clock_time),
+            },
+            error: None,
+            message: String::new(),
+        }
+    );
+    let mut dir_stream = fs::read_dir(&root_action_directory).await?;
+    assert!(
+        dir_stream.as_mut().next_entry().await?.is_none(),
+        "Expected empty directory at {root_action_directory}"
+    );
+    Ok(())
+}
 
-    // TODO(#527) Sleep to reduce flakey chances.
-    tokio::time::sleep(Duration::from_millis(250)).await;
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn kill_ends_action() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
 
     let running_actions_manager =
         Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
             root_action_directory: root_action_directory.clone(),
-            execution_configuration: ExecutionConfiguration {
-                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
-                additional_environment: Some(HashMap::from([(
-                    "SIDE_CHANNEL_FILE".to_string(),
-                    EnvironmentSource::side_channel_file,
-                )])),
-            },
+            execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
@@ -1831,10 +2398,28 @@ exit 1
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         })?);
-    let arguments = vec!["true".to_string()];
+
+    #[cfg(target_family = "unix")]
+    let arguments = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "sleep infinity".to_string(),
+    ];
+    #[cfg(target_family = "windows")]
+    // Windows is weird with timeout, so we use ping. See:
+    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
+    let arguments = vec![
+        "cmd".to_string(),
+        "/C".to_string(),
+        "ping -n 99999 127.0.0.1".to_string(),
+    ];
+
     let command = Command {
         arguments,
+        output_paths: vec![],
         working_directory: ".".to_string(),
         ..Default::default()
     };
@@ -1880,22 +2465,34 @@ exit 1
         )
         .await?;
 
-    let result = run_action(running_action_impl).await?;
-    assert_eq!(result.exit_code, 1, "Exit code should be 1");
-    assert_eq!(
-        result.error.err_tip(|| "Error should exist")?.code,
-        Code::DeadlineExceeded
-    );
+    // Start the action and kill it at the same time.
+    let result = futures::join!(
+        run_action(running_action_impl),
+        running_actions_manager.kill_all()
+    )
+    .0?;
+
+    // Check that the action was killed.
+    #[cfg(target_family = "unix")]
+    assert_eq!(9, result.exit_code);
+    // Note: Windows kill command returns exit code 1.
+    #[cfg(target_family = "windows")]
+    assert_eq!(1, result.exit_code);
+
     Ok(())
 }
 
 #[nativelink_test]
-async fn caches_results_in_action_cache_store() -> Result<(), Box<dyn std::error::Error>> {
+async fn do_not_cache_is_read_from_action() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
     let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
 
     let running_actions_manager =
         Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
+            root_action_directory: root_action_directory.clone(),
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -1907,415 +2504,1871 @@ async fn caches_results_in_action_cache_store() -> Result<(), Box<dyn std::error
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         })?);
 
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        output_files: vec![FileInfo {
-            name_or_path: NameOrPath::Path("test.txt".to_string()),
-            digest: DigestInfo::try_new(
-                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
-                3,
-            )?,
-            is_executable: false,
-        }],
-        stdout_digest: DigestInfo::try_new(
-            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
-            10,
-        )?,
-        stderr_digest: DigestInfo::try_new(
-            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
-            10,
-        )?,
-        exit_code: 0,
-        output_folders: vec![],
-        output_file_symlinks: vec![],
-        output_directory_symlinks: vec![],
-        server_logs: HashMap::new(),
-        execution_metadata: ExecutionMetadata {
-            worker: "WORKER_ID".to_string(),
-            queued_timestamp: SystemTime::UNIX_EPOCH,
-            worker_start_timestamp: make_system_time(0),
-            input_fetch_start_timestamp: make_system_time(1),
-            input_fetch_completed_timestamp: make_system_time(2),
-            execution_start_timestamp: make_system_time(3),
-            execution_completed_timestamp: make_system_time(4),
-            output_upload_start_timestamp: make_system_time(5),
-            output_upload_completed_timestamp: make_system_time(6),
-            worker_completed_timestamp: make_system_time(7),
-        },
-        error: None,
-        message: String::new(),
+    let command_digest = serialize_and_upload_message(
+        &Command::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        do_not_cache: true,
+        ..Default::default()
     };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
-        .await?;
-
-    let retrieved_result =
-        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_digest.into()).await?;
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
 
-    let proto_result: ProtoActionResult = action_result.into();
-    assert_eq!(proto_result, retrieved_result);
+    let running_action_impl = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
 
+    // The local worker checks this before writing the result to the AC.
+    assert!(running_action_impl.do_not_cache());
+    running_action_impl.cleanup().await?;
     Ok(())
 }
 
-#[nativelink_test]
-async fn failed_action_does_not_cache_in_action_cache() -> Result<(), Box<dyn std::error::Error>> {
+// This script runs a command under a wrapper script set in a config.
+// The wrapper script will print a constant string to stderr, and the test itself will
+// print to stdout. We then check the results of both to make sure the shell script was
+// invoked and the actual command was invoked under the shell script.
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn entrypoint_does_invoke_if_set() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(target_family = "unix")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+#!/usr/bin/env bash
+# Print some static text to stderr. This is what the test uses to
+# make sure the script did run.
+>&2 printf \"Wrapper script did run\"
+
+# Now run the real command.
+exec \"$@\"
+";
+    #[cfg(target_family = "windows")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+@echo off
+:: Print some static text to stderr. This is what the test uses to
+:: make sure the script did run.
+echo | set /p=\"Wrapper script did run\" 1>&2
+
+:: Run command, but morph the echo to ensure it doesn't
+:: add a new line to the end of the output.
+%1 | set /p=%2
+exit 0
+";
+    const WORKER_ID: &str = "foo_worker_id";
+    const EXPECTED_STDOUT: &str = "Action did run";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let test_wrapper_script = {
+        let test_wrapper_dir = make_temp_path("wrapper_dir");
+        fs::create_dir_all(&test_wrapper_dir).await?;
+        #[cfg(target_family = "unix")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
+        #[cfg(target_family = "windows")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
+
+        // We use std::fs::File here because we sometimes get strange bugs here
+        // that result in: "Text file busy (os error 26)" if it is an executeable.
+        // It is likley because somewhere the file descriotor does not get closed
+        // in tokio's async context.
+        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
+        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
+        #[cfg(target_family = "unix")]
+        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
+        test_wrapper_script_handle.sync_all()?;
+        drop(test_wrapper_script_handle);
+
+        test_wrapper_script
+    };
+
+    // TODO(#527) Sleep to reduce flakey chances.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration {
+                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
+                additional_environment: None,
+                allow_absolute_symlink_targets: false,
+                check_inputs_exist_before_download: false,
+                stuck_action_threshold: Duration::ZERO,
+                max_tree_depth: 0,
+                max_tree_nodes: 0,
+                input_staging_directory: None,
+                action_cgroup_directory: None,
+                max_inline_output_bytes: 0,
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    #[cfg(target_family = "unix")]
+    let arguments = vec!["printf".to_string(), EXPECTED_STDOUT.to_string()];
+    #[cfg(target_family = "windows")]
+    let arguments = vec!["echo".to_string(), EXPECTED_STDOUT.to_string()];
+    let command = Command {
+        arguments,
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
+
+    let running_action_impl = running_actions_manager
+        .clone()
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+
+    let result = run_action(running_action_impl).await?;
+    assert_eq!(result.exit_code, 0, "Exit code should be 0");
+
+    let expected_stdout = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new(EXPECTED_STDOUT))
+        .await?;
+    // Note: This string should match what is in worker_for_test.sh
+    let expected_stderr = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new("Wrapper script did run"))
+        .await?;
+    assert_eq!(Some(expected_stdout), result.stdout_digest);
+    assert_eq!(Some(expected_stderr), result.stderr_digest);
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn entrypoint_injects_properties() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(target_family = "unix")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+#!/usr/bin/env bash
+# Print some static text to stderr. This is what the test uses to
+# make sure the script did run.
+>&2 printf \"Wrapper script did run with property $PROPERTY $VALUE $INNER_TIMEOUT\"
+
+# Now run the real command.
+exec \"$@\"
+";
+    #[cfg(target_family = "windows")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+@echo off
+:: Print some static text to stderr. This is what the test uses to
+:: make sure the script did run.
+echo | set /p=\"Wrapper script did run with property %PROPERTY% %VALUE% %INNER_TIMEOUT%\" 1>&2
+
+:: Run command, but morph the echo to ensure it doesn't
+:: add a new line to the end of the output.
+%1 | set /p=%2
+exit 0
+";
+    const WORKER_ID: &str = "foo_worker_id";
+    const EXPECTED_STDOUT: &str = "Action did run";
+    const TASK_TIMEOUT: Duration = Duration::from_secs(122);
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let test_wrapper_script = {
+        let test_wrapper_dir = make_temp_path("wrapper_dir");
+        fs::create_dir_all(&test_wrapper_dir).await?;
+        #[cfg(target_family = "unix")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
+        #[cfg(target_family = "windows")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
+
+        // We use std::fs::File here because we sometimes get strange bugs here
+        // that result in: "Text file busy (os error 26)" if it is an executeable.
+        // It is likley because somewhere the file descriotor does not get closed
+        // in tokio's async context.
+        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
+        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
+        #[cfg(target_family = "unix")]
+        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
+        test_wrapper_script_handle.sync_all()?;
+        drop(test_wrapper_script_handle);
+
+        test_wrapper_script
+    };
+
+    // TODO(#527) Sleep to reduce flakey chances.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration {
+                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
+                additional_environment: Some(HashMap::from([
+                    (
+                        "PROPERTY".to_string(),
+                        EnvironmentSource::property("property_name".to_string()),
+                    ),
+                    (
+                        "VALUE".to_string(),
+                        EnvironmentSource::value("raw_value".to_string()),
+                    ),
+                    (
+                        "INNER_TIMEOUT".to_string(),
+                        EnvironmentSource::timeout_millis,
+                    ),
+                    (
+                        "PATH".to_string(),
+                        EnvironmentSource::value(std::env::var("PATH").unwrap()),
+                    ),
+                ])),
+                allow_absolute_symlink_targets: false,
+                check_inputs_exist_before_download: false,
+                stuck_action_threshold: Duration::ZERO,
+                max_tree_depth: 0,
+                max_tree_nodes: 0,
+                input_staging_directory: None,
+                action_cgroup_directory: None,
+                max_inline_output_bytes: 0,
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    #[cfg(target_family = "unix")]
+    let arguments = vec!["printf".to_string(), EXPECTED_STDOUT.to_string()];
+    #[cfg(target_family = "windows")]
+    let arguments = vec!["echo".to_string(), EXPECTED_STDOUT.to_string()];
+    let command = Command {
+        arguments,
+        working_directory: ".".to_string(),
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        platform: Some(Platform {
+            properties: vec![Property {
+                name: "property_name".into(),
+                value: "property_value".into(),
+            }],
+        }),
+        timeout: Some(prost_types::Duration {
+            seconds: TASK_TIMEOUT.as_secs() as i64,
+            nanos: 0,
+        }),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
+
+    let running_action_impl = running_actions_manager
+        .clone()
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+
+    let result = run_action(running_action_impl).await?;
+    assert_eq!(result.exit_code, 0, "Exit code should be 0");
+
+    let expected_stdout = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new(EXPECTED_STDOUT))
+        .await?;
+    // Note: This string should match what is in worker_for_test.sh
+    let expected_stderr = "Wrapper script did run with property property_value raw_value 122000";
+    let expected_stderr_digest = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new(expected_stderr))
+        .await?;
+
+    let actual_stderr: prost::bytes::Bytes = cas_store
+        .as_ref()
+        .get_part_unchunked(result.stderr_digest.unwrap(), 0, None)
+        .await?;
+    let actual_stderr_decoded = std::str::from_utf8(&actual_stderr)?;
+    assert_eq!(expected_stderr, actual_stderr_decoded);
+    assert_eq!(Some(expected_stdout), result.stdout_digest);
+    assert_eq!(Some(expected_stderr_digest), result.stderr_digest);
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn entrypoint_sends_timeout_via_side_channel() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(target_family = "unix")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+#!/bin/bash
+echo '{\"failure\":\"timeout\"}' > \"$SIDE_CHANNEL_FILE\"
+exit 1
+";
+    #[cfg(target_family = "windows")]
+    const TEST_WRAPPER_SCRIPT_CONTENT: &str = "\
+@echo off
+echo | set /p={\"failure\":\"timeout\"} 1>&2 > %SIDE_CHANNEL_FILE%
+exit 1
+";
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let test_wrapper_script = {
+        let test_wrapper_dir = make_temp_path("wrapper_dir");
+        fs::create_dir_all(&test_wrapper_dir).await?;
+        #[cfg(target_family = "unix")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "/test_wrapper_script.sh");
+        #[cfg(target_family = "windows")]
+        let test_wrapper_script = OsString::from(test_wrapper_dir + "\\test_wrapper_script.bat");
+
+        // We use std::fs::File here because we sometimes get strange bugs here
+        // that result in: "Text file busy (os error 26)" if it is an executeable.
+        // It is likley because somewhere the file descriotor does not get closed
+        // in tokio's async context.
+        let mut test_wrapper_script_handle = std::fs::File::create(&test_wrapper_script)?;
+        test_wrapper_script_handle.write_all(TEST_WRAPPER_SCRIPT_CONTENT.as_bytes())?;
+        #[cfg(target_family = "unix")]
+        test_wrapper_script_handle.set_permissions(Permissions::from_mode(0o777))?;
+        test_wrapper_script_handle.sync_all()?;
+        drop(test_wrapper_script_handle);
+
+        test_wrapper_script
+    };
+
+    // TODO(#527) Sleep to reduce flakey chances.
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
+            execution_configuration: ExecutionConfiguration {
+                entrypoint: Some(test_wrapper_script.into_string().unwrap()),
+                additional_environment: Some(HashMap::from([(
+                    "SIDE_CHANNEL_FILE".to_string(),
+                    EnvironmentSource::side_channel_file,
+                )])),
+                allow_absolute_symlink_targets: false,
+                check_inputs_exist_before_download: false,
+                stuck_action_threshold: Duration::ZERO,
+                max_tree_depth: 0,
+                max_tree_nodes: 0,
+                input_staging_directory: None,
+                action_cgroup_directory: None,
+                max_inline_output_bytes: 0,
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    let arguments = vec!["true".to_string()];
+    let command = Command {
+        arguments,
+        working_directory: ".".to_string(),
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
+
+    let running_action_impl = running_actions_manager
+        .clone()
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+
+    let result = run_action(running_action_impl).await?;
+    assert_eq!(result.exit_code, 1, "Exit code should be 1");
+    assert_eq!(
+        result.error.err_tip(|| "Error should exist")?.code,
+        Code::DeadlineExceeded
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn caches_results_in_action_cache_store() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        output_files: vec![FileInfo {
+            name_or_path: NameOrPath::Path("test.txt".to_string()),
+            digest: DigestInfo::try_new(
+                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
+                3,
+            )?,
+            is_executable: false,
+            unix_mode: None,
+        }],
+        stdout_digest: Some(DigestInfo::try_new(
+            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
+            10,
+        )?),
+        stderr_digest: Some(DigestInfo::try_new(
+            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
+            10,
+        )?),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
+        exit_code: 0,
+        output_folders: vec![],
+        output_file_symlinks: vec![],
+        output_directory_symlinks: vec![],
+        server_logs: HashMap::new(),
+        execution_metadata: ExecutionMetadata {
+            worker: "WORKER_ID".to_string(),
+            queued_timestamp: SystemTime::UNIX_EPOCH,
+            worker_start_timestamp: make_system_time(0),
+            input_fetch_start_timestamp: make_system_time(1),
+            input_fetch_completed_timestamp: make_system_time(2),
+            execution_start_timestamp: make_system_time(3),
+            execution_completed_timestamp: make_system_time(4),
+            output_upload_start_timestamp: make_system_time(5),
+            output_upload_completed_timestamp: make_system_time(6),
+            worker_completed_timestamp: make_system_time(7),
+        },
+        error: None,
+        message: String::new(),
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    let retrieved_result =
+        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_digest.into()).await?;
+
+    let proto_result: ProtoActionResult = action_result.into();
+    assert_eq!(proto_result, retrieved_result);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn oversized_result_does_not_cache_in_action_cache() -> Result<(), Box<dyn std::error::Error>>
+{
+    const MAX_ACTION_RESULT_SIZE: usize = 1024;
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
+                max_action_result_size: MAX_ACTION_RESULT_SIZE,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let make_action_result = |output_file_count: usize| -> Result<ActionResult, Error> {
+        Ok(ActionResult {
+            output_files: (0..output_file_count)
+                .map(|i| {
+                    Ok(FileInfo {
+                        name_or_path: NameOrPath::Path(format!("some/output/path{i}")),
+                        digest: DigestInfo::try_new(
+                            "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
+                            3,
+                        )?,
+                        is_executable: false,
+                        unix_mode: None,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+            exit_code: 0,
+            ..ActionResult::default()
+        })
+    };
+
+    {
+        // A result within the limit is cached.
+        let action_digest = DigestInfo::new([2u8; 32], 32);
+        let mut action_result = make_action_result(1)?;
+        running_actions_manager
+            .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+            .await?;
+        assert!(ac_store.has(action_digest).await?.is_some());
+    }
+    {
+        // A result over the limit is not.
+        let action_digest = DigestInfo::new([3u8; 32], 32);
+        let mut action_result = make_action_result(100)?;
+        running_actions_manager
+            .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+            .await?;
+        assert_eq!(ac_store.has(action_digest).await?, None);
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn failed_action_does_not_cache_in_action_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::everything,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        output_files: vec![FileInfo {
+            name_or_path: NameOrPath::Path("test.txt".to_string()),
+            digest: DigestInfo::try_new(
+                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
+                3,
+            )?,
+            is_executable: false,
+            unix_mode: None,
+        }],
+        stdout_digest: Some(DigestInfo::try_new(
+            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
+            10,
+        )?),
+        stderr_digest: Some(DigestInfo::try_new(
+            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
+            10,
+        )?),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
+        exit_code: 1,
+        output_folders: vec![],
+        output_file_symlinks: vec![],
+        output_directory_symlinks: vec![],
+        server_logs: HashMap::new(),
+        execution_metadata: ExecutionMetadata {
+            worker: "WORKER_ID".to_string(),
+            queued_timestamp: SystemTime::UNIX_EPOCH,
+            worker_start_timestamp: make_system_time(0),
+            input_fetch_start_timestamp: make_system_time(1),
+            input_fetch_completed_timestamp: make_system_time(2),
+            execution_start_timestamp: make_system_time(3),
+            execution_completed_timestamp: make_system_time(4),
+            output_upload_start_timestamp: make_system_time(5),
+            output_upload_completed_timestamp: make_system_time(6),
+            worker_completed_timestamp: make_system_time(7),
+        },
+        error: None,
+        message: String::new(),
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    let retrieved_result =
+        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_digest.into()).await?;
+
+    let proto_result: ProtoActionResult = action_result.into();
+    assert_eq!(proto_result, retrieved_result);
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn success_does_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_historical_results_strategy: Some(
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
+                ),
+                success_message_template: "{historical_results_hash}-{historical_results_size}"
+                    .to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        output_files: vec![FileInfo {
+            name_or_path: NameOrPath::Path("test.txt".to_string()),
+            digest: DigestInfo::try_new(
+                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
+                3,
+            )?,
+            is_executable: false,
+            unix_mode: None,
+        }],
+        stdout_digest: Some(DigestInfo::try_new(
+            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
+            10,
+        )?),
+        stderr_digest: Some(DigestInfo::try_new(
+            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
+            10,
+        )?),
+        stdout_raw: Vec::new(),
+        stderr_raw: Vec::new(),
+        exit_code: 0,
+        output_folders: vec![],
+        output_file_symlinks: vec![],
+        output_directory_symlinks: vec![],
+        server_logs: HashMap::new(),
+        execution_metadata: ExecutionMetadata {
+            worker: "WORKER_ID".to_string(),
+            queued_timestamp: SystemTime::UNIX_EPOCH,
+            worker_start_timestamp: make_system_time(0),
+            input_fetch_start_timestamp: make_system_time(1),
+            input_fetch_completed_timestamp: make_system_time(2),
+            execution_start_timestamp: make_system_time(3),
+            execution_completed_timestamp: make_system_time(4),
+            output_upload_start_timestamp: make_system_time(5),
+            output_upload_completed_timestamp: make_system_time(6),
+            worker_completed_timestamp: make_system_time(7),
+        },
+        error: None,
+        message: String::new(),
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    assert!(!action_result.message.is_empty(), "Message should be set");
+
+    let historical_digest = {
+        let (historical_results_hash, historical_results_size) = action_result
+            .message
+            .split_once('-')
+            .expect("Message should be in format {hash}-{size}");
+
+        DigestInfo::try_new(
+            historical_results_hash,
+            historical_results_size.parse::<i64>()?,
+        )?
+    };
+    let retrieved_result = get_and_decode_digest::<HistoricalExecuteResponse>(
+        cas_store.as_ref(),
+        historical_digest.into(),
+    )
+    .await?;
+
+    assert_eq!(
+        HistoricalExecuteResponse {
+            action_digest: Some(action_digest.into()),
+            execute_response: Some(ExecuteResponse {
+                result: Some(action_result.into()),
+                status: Some(Status::default()),
+                ..Default::default()
+            }),
+        },
+        retrieved_result
+    );
+
+    Ok(())
+}
+
+#[nativelink_test]
+async fn failure_does_not_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_historical_results_strategy: Some(
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
+                ),
+                success_message_template: "{historical_results_hash}-{historical_results_size}"
+                    .to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        exit_code: 1,
+        ..Default::default()
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    assert!(
+        action_result.message.is_empty(),
+        "Message should not be set"
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn infra_failure_does_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>>
+{
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_historical_results_strategy: Some(
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::failures_only,
+                ),
+                failure_message_template: "{historical_results_hash}-{historical_results_size}"
+                    .to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        exit_code: 0,
+        error: Some(make_input_err!("test error")),
+        ..Default::default()
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    assert!(!action_result.message.is_empty(), "Message should be set");
+
+    let historical_digest = {
+        let (historical_results_hash, historical_results_size) = action_result
+            .message
+            .split_once('-')
+            .expect("Message should be in format {hash}-{size}");
+
+        DigestInfo::try_new(
+            historical_results_hash,
+            historical_results_size.parse::<i64>()?,
+        )?
+    };
+
+    let retrieved_result = get_and_decode_digest::<HistoricalExecuteResponse>(
+        cas_store.as_ref(),
+        historical_digest.into(),
+    )
+    .await?;
+
+    assert_eq!(
+        HistoricalExecuteResponse {
+            action_digest: Some(action_digest.into()),
+            execute_response: Some(ExecuteResponse {
+                result: Some(action_result.into()),
+                status: Some(make_input_err!("test error").into()),
+                ..Default::default()
+            }),
+        },
+        retrieved_result
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn timed_out_action_has_exit_reason_in_message() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_historical_results_strategy: Some(
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::failures_only,
+                ),
+                failure_message_template:
+                    "{exit_reason}|{historical_results_hash}-{historical_results_size}".to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        exit_code: 9,
+        error: Some(make_err!(
+            Code::DeadlineExceeded,
+            "Command 'sleep 10' timed out after 1 seconds"
+        )),
+        ..Default::default()
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    let (exit_reason, historical_digest) = action_result
+        .message
+        .split_once('|')
+        .expect("Message should be in format {exit_reason}|{hash}-{size}");
+    assert_eq!(exit_reason, "Command 'sleep 10' timed out after 1 seconds");
+
+    let historical_digest = {
+        let (historical_results_hash, historical_results_size) = historical_digest
+            .split_once('-')
+            .expect("Message should be in format {exit_reason}|{hash}-{size}");
+
+        DigestInfo::try_new(
+            historical_results_hash,
+            historical_results_size.parse::<i64>()?,
+        )?
+    };
+
+    let retrieved_result = get_and_decode_digest::<HistoricalExecuteResponse>(
+        cas_store.as_ref(),
+        historical_digest.into(),
+    )
+    .await?;
+
+    assert_eq!(retrieved_result.action_digest, Some(action_digest.into()));
+    let execute_response = retrieved_result
+        .execute_response
+        .expect("Historical result should have an ExecuteResponse");
+    assert_eq!(
+        execute_response.status.map(|status| status.code),
+        Some(Code::DeadlineExceeded as i32)
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn message_is_set_when_results_are_not_cached() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                failure_message_template: "{exit_reason}".to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        exit_code: 1,
+        ..Default::default()
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    assert_eq!(action_result.message, "Exited with code 1");
+    Ok(())
+}
+
+#[nativelink_test]
+async fn action_result_has_used_in_message() -> Result<(), Box<dyn std::error::Error>> {
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory: String::new(),
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
+                success_message_template: "{action_digest_hash}-{action_digest_size}".to_string(),
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+
+    let action_digest = DigestInfo::new([2u8; 32], 32);
+    let mut action_result = ActionResult {
+        exit_code: 0,
+        ..Default::default()
+    };
+    running_actions_manager
+        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+        .await?;
+
+    assert!(!action_result.message.is_empty(), "Message should be set");
+
+    let action_result_digest = {
+        let (action_result_hash, action_result_size) = action_result
+            .message
+            .split_once('-')
+            .expect("Message should be in format {hash}-{size}");
+
+        DigestInfo::try_new(action_result_hash, action_result_size.parse::<i64>()?)?
+    };
+
+    let retrieved_result =
+        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_result_digest.into())
+            .await?;
+
+    let proto_result: ProtoActionResult = action_result.into();
+    assert_eq!(proto_result, retrieved_result);
+    Ok(())
+}
+
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn ensure_worker_timeout_chooses_correct_values() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+
+    #[cfg(target_family = "unix")]
+    let arguments = vec!["true".to_string()];
+    #[cfg(target_family = "windows")]
+    let arguments = vec![
+        "cmd".to_string(),
+        "/C".to_string(),
+        "exit".to_string(),
+        "0".to_string(),
+    ];
+
+    let command = Command {
+        arguments,
+        output_paths: vec![],
+        working_directory: ".".to_string(),
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    {
+        // Test to ensure that the task timeout is choosen if it is less than the max timeout.
+        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
+        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
+        const TASK_TIMEOUT: Duration = Duration::from_secs(10);
+
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            timeout: Some(prost_types::Duration {
+                seconds: TASK_TIMEOUT.as_secs() as i64,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+            RunningActionsManagerArgs {
+                root_action_directory: root_action_directory.clone(),
+                execution_configuration: ExecutionConfiguration::default(),
+                cas_store: cas_store.clone(),
+                ac_store: Some(Store::new(ac_store.clone())),
+                historical_store: Store::new(cas_store.clone()),
+                upload_action_result_config:
+                    &nativelink_config::cas_server::UploadActionResultConfig {
+                        upload_ac_results_strategy:
+                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                        ..Default::default()
+                    },
+                max_action_timeout: MAX_TIMEOUT_DURATION,
+                timeout_handled_externally: false,
+                max_concurrent_actions: 0,
+                queue_actions_over_limit: false,
+            },
+            Callbacks {
+                now_fn: test_monotonic_clock,
+                sleep_fn: |duration| {
+                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
+                    Box::pin(futures::future::pending())
+                },
+            },
+        )?);
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .and_then(|action| {
+                action
+                    .clone()
+                    .prepare_action()
+                    .and_then(RunningAction::execute)
+                    .then(|result| async move {
+                        if let Err(e) = action.cleanup().await {
+                            return Result::<ActionResult, Error>::Err(e).merge(result);
+                        }
+                        result
+                    })
+            })
+            .await?;
+        assert_eq!(
+            SENT_TIMEOUT.load(Ordering::Relaxed),
+            TASK_TIMEOUT.as_millis() as i64
+        );
+    }
+    {
+        // Ensure if no timeout is set use max timeout.
+        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
+        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
+        const TASK_TIMEOUT: Duration = Duration::from_secs(0);
+
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            timeout: Some(prost_types::Duration {
+                seconds: TASK_TIMEOUT.as_secs() as i64,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+            RunningActionsManagerArgs {
+                root_action_directory: root_action_directory.clone(),
+                execution_configuration: ExecutionConfiguration::default(),
+                cas_store: cas_store.clone(),
+                ac_store: Some(Store::new(ac_store.clone())),
+                historical_store: Store::new(cas_store.clone()),
+                upload_action_result_config:
+                    &nativelink_config::cas_server::UploadActionResultConfig {
+                        upload_ac_results_strategy:
+                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                        ..Default::default()
+                    },
+                max_action_timeout: MAX_TIMEOUT_DURATION,
+                timeout_handled_externally: false,
+                max_concurrent_actions: 0,
+                queue_actions_over_limit: false,
+            },
+            Callbacks {
+                now_fn: test_monotonic_clock,
+                sleep_fn: |duration| {
+                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
+                    Box::pin(futures::future::pending())
+                },
+            },
+        )?);
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .and_then(|action| {
+                action
+                    .clone()
+                    .prepare_action()
+                    .and_then(RunningAction::execute)
+                    .then(|result| async move {
+                        if let Err(e) = action.cleanup().await {
+                            return Result::<ActionResult, Error>::Err(e).merge(result);
+                        }
+                        result
+                    })
+            })
+            .await?;
+        assert_eq!(
+            SENT_TIMEOUT.load(Ordering::Relaxed),
+            MAX_TIMEOUT_DURATION.as_millis() as i64
+        );
+    }
+    {
+        // Ensure we reject tasks that have a timeout set too high.
+        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
+        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
+        const TASK_TIMEOUT: Duration = Duration::from_secs(200);
+
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            timeout: Some(prost_types::Duration {
+                seconds: TASK_TIMEOUT.as_secs() as i64,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+
+        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+            RunningActionsManagerArgs {
+                root_action_directory: root_action_directory.clone(),
+                execution_configuration: ExecutionConfiguration::default(),
+                cas_store: cas_store.clone(),
+                ac_store: Some(Store::new(ac_store.clone())),
+                historical_store: Store::new(cas_store.clone()),
+                upload_action_result_config:
+                    &nativelink_config::cas_server::UploadActionResultConfig {
+                        upload_ac_results_strategy:
+                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                        ..Default::default()
+                    },
+                max_action_timeout: MAX_TIMEOUT_DURATION,
+                timeout_handled_externally: false,
+                max_concurrent_actions: 0,
+                queue_actions_over_limit: false,
+            },
+            Callbacks {
+                now_fn: test_monotonic_clock,
+                sleep_fn: |duration| {
+                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
+                    Box::pin(futures::future::pending())
+                },
+            },
+        )?);
+
+        let execute_request = ExecuteRequest {
+            action_digest: Some(action_digest.into()),
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        let result = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    queued_timestamp: Some(make_system_time(1000).into()),
+                },
+            )
+            .and_then(|action| {
+                action
+                    .clone()
+                    .prepare_action()
+                    .and_then(RunningAction::execute)
+                    .then(|result| async move {
+                        if let Err(e) = action.cleanup().await {
+                            return Result::<ActionResult, Error>::Err(e).merge(result);
+                        }
+                        result
+                    })
+            })
+            .await;
+        assert_eq!(SENT_TIMEOUT.load(Ordering::Relaxed), -1);
+        assert_eq!(result.err().unwrap().code, Code::InvalidArgument);
+    }
+    Ok(())
+}
+
+#[nativelink_test]
+async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    type StaticOneshotTuple = Mutex<(Option<oneshot::Sender<()>>, Option<oneshot::Receiver<()>>)>;
+    static TIMEOUT_ONESHOT: LazyLock<StaticOneshotTuple> = LazyLock::new(|| {
+        let (tx, rx) = oneshot::channel();
+        Mutex::new((Some(tx), Some(rx)))
+    });
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
     let (_, _, cas_store, ac_store) = setup_stores().await?;
-
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
                 upload_ac_results_strategy:
-                    nativelink_config::cas_server::UploadCacheResultsStrategy::everything,
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
                 ..Default::default()
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        })?);
-
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        output_files: vec![FileInfo {
-            name_or_path: NameOrPath::Path("test.txt".to_string()),
-            digest: DigestInfo::try_new(
-                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
-                3,
-            )?,
-            is_executable: false,
-        }],
-        stdout_digest: DigestInfo::try_new(
-            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
-            10,
-        )?,
-        stderr_digest: DigestInfo::try_new(
-            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
-            10,
-        )?,
-        exit_code: 1,
-        output_folders: vec![],
-        output_file_symlinks: vec![],
-        output_directory_symlinks: vec![],
-        server_logs: HashMap::new(),
-        execution_metadata: ExecutionMetadata {
-            worker: "WORKER_ID".to_string(),
-            queued_timestamp: SystemTime::UNIX_EPOCH,
-            worker_start_timestamp: make_system_time(0),
-            input_fetch_start_timestamp: make_system_time(1),
-            input_fetch_completed_timestamp: make_system_time(2),
-            execution_start_timestamp: make_system_time(3),
-            execution_completed_timestamp: make_system_time(4),
-            output_upload_start_timestamp: make_system_time(5),
-            output_upload_completed_timestamp: make_system_time(6),
-            worker_completed_timestamp: make_system_time(7),
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
-        error: None,
-        message: String::new(),
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| {
+                Box::pin(async move {
+                    let rx = TIMEOUT_ONESHOT.lock().unwrap().1.take().unwrap();
+                    rx.await.expect("Could not receive timeout signal");
+                })
+            },
+        },
+    )?);
+
+    #[cfg(target_family = "unix")]
+    let arguments = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "sleep infinity".to_string(),
+    ];
+    #[cfg(target_family = "windows")]
+    // Windows is weird with timeout, so we use ping. See:
+    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
+    let arguments = vec![
+        "cmd".to_string(),
+        "/C".to_string(),
+        "ping -n 99999 127.0.0.1".to_string(),
+    ];
+
+    let command = Command {
+        arguments,
+        output_paths: vec![],
+        working_directory: ".".to_string(),
+        ..Default::default()
     };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
-        .await?;
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
 
-    let retrieved_result =
-        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_digest.into()).await?;
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
 
-    let proto_result: ProtoActionResult = action_result.into();
-    assert_eq!(proto_result, retrieved_result);
+    let execute_results_fut = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .and_then(|action| {
+            action
+                .clone()
+                .prepare_action()
+                .and_then(RunningAction::execute)
+                .and_then(RunningAction::upload_results)
+                .and_then(RunningAction::get_finished_result)
+                .then(|result| async move {
+                    if let Err(e) = action.cleanup().await {
+                        return Result::<ActionResult, Error>::Err(e).merge(result);
+                    }
+                    result
+                })
+        });
+
+    let (results, ()) = tokio::join!(execute_results_fut, async move {
+        tokio::task::yield_now().await;
+        let tx = TIMEOUT_ONESHOT.lock().unwrap().0.take().unwrap();
+        tx.send(()).expect("Could not send timeout signal");
+    });
+    assert_eq!(results?.error.unwrap().code, Code::DeadlineExceeded);
 
     Ok(())
 }
 
+#[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn success_does_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>> {
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+async fn kill_all_waits_for_all_tasks_to_finish() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
 
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory: root_action_directory.clone(),
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
-                upload_historical_results_strategy: Some(
-                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
-                ),
-                success_message_template: "{historical_results_hash}-{historical_results_size}"
-                    .to_string(),
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
                 ..Default::default()
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        })?);
-
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        output_files: vec![FileInfo {
-            name_or_path: NameOrPath::Path("test.txt".to_string()),
-            digest: DigestInfo::try_new(
-                "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3",
-                3,
-            )?,
-            is_executable: false,
-        }],
-        stdout_digest: DigestInfo::try_new(
-            "426afaf613d8cfdd9fa8addcc030ae6c95a7950ae0301164af1d5851012081d5",
-            10,
-        )?,
-        stderr_digest: DigestInfo::try_new(
-            "7b2e400d08b8e334e3172d105be308b506c6036c62a9bde5c509d7808b28b213",
-            10,
-        )?,
-        exit_code: 0,
-        output_folders: vec![],
-        output_file_symlinks: vec![],
-        output_directory_symlinks: vec![],
-        server_logs: HashMap::new(),
-        execution_metadata: ExecutionMetadata {
-            worker: "WORKER_ID".to_string(),
-            queued_timestamp: SystemTime::UNIX_EPOCH,
-            worker_start_timestamp: make_system_time(0),
-            input_fetch_start_timestamp: make_system_time(1),
-            input_fetch_completed_timestamp: make_system_time(2),
-            execution_start_timestamp: make_system_time(3),
-            execution_completed_timestamp: make_system_time(4),
-            output_upload_start_timestamp: make_system_time(5),
-            output_upload_completed_timestamp: make_system_time(6),
-            worker_completed_timestamp: make_system_time(7),
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
-        error: None,
-        message: String::new(),
-    };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
-        .await?;
-
-    assert!(!action_result.message.is_empty(), "Message should be set");
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
 
-    let historical_digest = {
-        let (historical_results_hash, historical_results_size) = action_result
-            .message
-            .split_once('-')
-            .expect("Message should be in format {hash}-{size}");
+    #[cfg(target_family = "unix")]
+    let arguments = vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        "sleep infinity".to_string(),
+    ];
+    #[cfg(target_family = "windows")]
+    // Windows is weird with timeout, so we use ping. See:
+    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
+    let arguments = vec![
+        "cmd".to_string(),
+        "/C".to_string(),
+        "ping -n 99999 127.0.0.1".to_string(),
+    ];
 
-        DigestInfo::try_new(
-            historical_results_hash,
-            historical_results_size.parse::<i64>()?,
-        )?
+    let command = Command {
+        arguments,
+        output_paths: vec![],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
     };
-    let retrieved_result = get_and_decode_digest::<HistoricalExecuteResponse>(
-        cas_store.as_ref(),
-        historical_digest.into(),
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
 
-    assert_eq!(
-        HistoricalExecuteResponse {
-            action_digest: Some(action_digest.into()),
-            execute_response: Some(ExecuteResponse {
-                result: Some(action_result.into()),
-                status: Some(Status::default()),
-                ..Default::default()
-            }),
-        },
-        retrieved_result
-    );
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
 
-    Ok(())
-}
+    let (cleanup_tx, cleanup_rx) = oneshot::channel();
+    let cleanup_was_requested = AtomicBool::new(false);
+    let action = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(make_system_time(1000).into()),
+            },
+        )
+        .await?;
+    let execute_results_fut = action
+        .clone()
+        .prepare_action()
+        .and_then(RunningAction::execute)
+        .and_then(RunningAction::upload_results)
+        .and_then(RunningAction::get_finished_result)
+        .then(|result| async {
+            cleanup_was_requested.store(true, Ordering::Release);
+            cleanup_rx.await.expect("Could not receive cleanup signal");
+            if let Err(e) = action.cleanup().await {
+                return Result::<ActionResult, Error>::Err(e).merge(result);
+            }
+            result
+        });
 
-#[nativelink_test]
-async fn failure_does_not_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>> {
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    tokio::pin!(execute_results_fut);
+    {
+        // Advance the action as far as possible and ensure we are not waiting on cleanup.
+        for _ in 0..100 {
+            assert!(futures::poll!(&mut execute_results_fut).is_pending());
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(cleanup_was_requested.load(Ordering::Acquire), false);
+    }
 
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
-            execution_configuration: ExecutionConfiguration::default(),
-            cas_store: cas_store.clone(),
-            ac_store: Some(Store::new(ac_store.clone())),
-            historical_store: Store::new(cas_store.clone()),
-            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
-                upload_historical_results_strategy: Some(
-                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
-                ),
-                success_message_template: "{historical_results_hash}-{historical_results_size}"
-                    .to_string(),
-                ..Default::default()
-            },
-            max_action_timeout: Duration::MAX,
-            timeout_handled_externally: false,
-        })?);
+    let kill_all_fut = running_actions_manager.kill_all();
+    tokio::pin!(kill_all_fut);
 
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        exit_code: 1,
-        ..Default::default()
-    };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
-        .await?;
+    {
+        // * Advance the action as far as possible.
+        // * Ensure we are now waiting on cleanup.
+        // * Ensure our kill_action is still pending.
+        while !cleanup_was_requested.load(Ordering::Acquire) {
+            // Wait for cleanup to be triggered.
+            tokio::task::yield_now().await;
+            assert!(futures::poll!(&mut execute_results_fut).is_pending());
+            assert!(futures::poll!(&mut kill_all_fut).is_pending());
+        }
+    }
+    // Allow cleanup, which allows execute_results_fut to advance.
+    cleanup_tx.send(()).expect("Could not send cleanup signal");
+    // Advance our two futures to completion now.
+    let result = execute_results_fut.await;
+    kill_all_fut.await;
+    {
+        // Ensure our results are correct.
+        let action_result = result?;
+        let err = action_result
+            .error
+            .as_ref()
+            .err_tip(|| format!("No error exists in result : {action_result:?}"))?;
+        assert_eq!(
+            err.code,
+            Code::Aborted,
+            "Expected Aborted : {action_result:?}"
+        );
+    }
 
-    assert!(
-        action_result.message.is_empty(),
-        "Message should not be set"
-    );
     Ok(())
 }
 
+/// Regression Test for Issue #675
+#[cfg(target_family = "unix")]
+#[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn infra_failure_does_cache_in_historical_results() -> Result<(), Box<dyn std::error::Error>>
-{
+async fn unix_executable_file_test() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+    const FILE_1_NAME: &str = "file1";
+
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
+    }
+
     let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
 
-    let running_actions_manager =
-        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
-            execution_configuration: ExecutionConfiguration::default(),
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory,
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
+            execution_configuration: ExecutionConfiguration::default(),
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
-                upload_historical_results_strategy: Some(
-                    nativelink_config::cas_server::UploadCacheResultsStrategy::failures_only,
-                ),
-                failure_message_template: "{historical_results_hash}-{historical_results_size}"
-                    .to_string(),
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
                 ..Default::default()
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        })?);
-
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        exit_code: 0,
-        error: Some(make_input_err!("test error")),
-        ..Default::default()
-    };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    // Create and run an action which
+    // creates a file with owner executable permissions.
+    let action_result = {
+        let command = Command {
+            arguments: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                format!("touch {FILE_1_NAME} && chmod 700 {FILE_1_NAME}").to_string(),
+            ],
+            output_paths: vec![FILE_1_NAME.to_string()],
+            working_directory: ".".to_string(),
+            environment_variables: vec![EnvironmentVariable {
+                name: "PATH".to_string(),
+                value: std::env::var("PATH").unwrap(),
+            }],
+            ..Default::default()
+        };
+        let command_digest = serialize_and_upload_message(
+            &command,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let input_root_digest = serialize_and_upload_message(
+            &Directory::default(),
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
+        let action = Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        };
+        let action_digest = serialize_and_upload_message(
+            &action,
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
         .await?;
 
-    assert!(!action_result.message.is_empty(), "Message should be set");
-
-    let historical_digest = {
-        let (historical_results_hash, historical_results_size) = action_result
-            .message
-            .split_once('-')
-            .expect("Message should be in format {hash}-{size}");
-
-        DigestInfo::try_new(
-            historical_results_hash,
-            historical_results_size.parse::<i64>()?,
-        )?
-    };
-
-    let retrieved_result = get_and_decode_digest::<HistoricalExecuteResponse>(
-        cas_store.as_ref(),
-        historical_digest.into(),
-    )
-    .await?;
-
-    assert_eq!(
-        HistoricalExecuteResponse {
+        let execute_request = ExecuteRequest {
             action_digest: Some(action_digest.into()),
-            execute_response: Some(ExecuteResponse {
-                result: Some(action_result.into()),
-                status: Some(make_input_err!("test error").into()),
-                ..Default::default()
-            }),
-        },
-        retrieved_result
+            ..Default::default()
+        };
+        let operation_id = OperationId::default().to_string();
+
+        let running_action_impl = running_actions_manager
+            .create_and_add_action(
+                WORKER_ID.to_string(),
+                StartExecute {
+                    execute_request: Some(execute_request),
+                    operation_id,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        run_action(running_action_impl.clone()).await?
+    };
+    // Ensure the file copied from worker to CAS is executable.
+    assert!(
+        action_result.output_files[0].is_executable,
+        "Expected output file to be executable"
     );
     Ok(())
 }
 
 #[nativelink_test]
-async fn action_result_has_used_in_message() -> Result<(), Box<dyn std::error::Error>> {
+async fn action_directory_contents_are_cleaned() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
     let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+    let temp_action_directory = make_temp_path("root_action_directory/temp");
+    fs::create_dir_all(&temp_action_directory).await?;
 
     let running_actions_manager =
         Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: String::new(),
+            root_action_directory: root_action_directory.clone(),
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
                 upload_ac_results_strategy:
-                    nativelink_config::cas_server::UploadCacheResultsStrategy::success_only,
-                success_message_template: "{action_digest_hash}-{action_digest_size}".to_string(),
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
                 ..Default::default()
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         })?);
-
-    let action_digest = DigestInfo::new([2u8; 32], 32);
-    let mut action_result = ActionResult {
-        exit_code: 0,
-        ..Default::default()
-    };
-    running_actions_manager
-        .cache_action_result(action_digest, &mut action_result, DigestHasherFunc::Sha256)
-        .await?;
-
-    assert!(!action_result.message.is_empty(), "Message should be set");
-
-    let action_result_digest = {
-        let (action_result_hash, action_result_size) = action_result
-            .message
-            .split_once('-')
-            .expect("Message should be in format {hash}-{size}");
-
-        DigestInfo::try_new(action_result_hash, action_result_size.parse::<i64>()?)?
-    };
-
-    let retrieved_result =
-        get_and_decode_digest::<ProtoActionResult>(ac_store.as_ref(), action_result_digest.into())
-            .await?;
-
-    let proto_result: ProtoActionResult = action_result.into();
-    assert_eq!(proto_result, retrieved_result);
-    Ok(())
-}
-
-#[cfg_attr(feature = "nix", ignore)]
-#[nativelink_test]
-async fn ensure_worker_timeout_chooses_correct_values() -> Result<(), Box<dyn std::error::Error>> {
-    const WORKER_ID: &str = "foo_worker_id";
-
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
-    let root_action_directory = make_temp_path("root_action_directory");
-    fs::create_dir_all(&root_action_directory).await?;
-
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let queued_timestamp = make_system_time(1000);
 
     #[cfg(target_family = "unix")]
-    let arguments = vec!["true".to_string()];
+    let arguments = vec!["sh".to_string(), "-c".to_string(), "exit 0".to_string()];
     #[cfg(target_family = "windows")]
-    let arguments = vec![
-        "cmd".to_string(),
-        "/C".to_string(),
-        "exit".to_string(),
-        "0".to_string(),
-    ];
+    let arguments = vec!["cmd".to_string(), "/C".to_string(), "exit 0".to_string()];
 
     let command = Command {
         arguments,
         output_paths: vec![],
         working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
         ..Default::default()
     };
     let command_digest = serialize_and_upload_message(
@@ -2330,180 +4383,145 @@ async fn ensure_worker_timeout_chooses_correct_values() -> Result<(), Box<dyn st
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
 
-    {
-        // Test to ensure that the task timeout is choosen if it is less than the max timeout.
-        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
-        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
-        const TASK_TIMEOUT: Duration = Duration::from_secs(10);
+    let execute_request = ExecuteRequest {
+        action_digest: Some(action_digest.into()),
+        ..Default::default()
+    };
+    let operation_id = OperationId::default().to_string();
 
-        let action = Action {
-            command_digest: Some(command_digest.into()),
-            input_root_digest: Some(input_root_digest.into()),
-            timeout: Some(prost_types::Duration {
-                seconds: TASK_TIMEOUT.as_secs() as i64,
-                nanos: 0,
-            }),
-            ..Default::default()
-        };
-        let action_digest = serialize_and_upload_message(
-            &action,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
+    let running_action_impl = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(execute_request),
+                operation_id,
+                queued_timestamp: Some(queued_timestamp.into()),
+            },
         )
         .await?;
 
-        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-            RunningActionsManagerArgs {
-                root_action_directory: root_action_directory.clone(),
-                execution_configuration: ExecutionConfiguration::default(),
-                cas_store: cas_store.clone(),
-                ac_store: Some(Store::new(ac_store.clone())),
-                historical_store: Store::new(cas_store.clone()),
-                upload_action_result_config:
-                    &nativelink_config::cas_server::UploadActionResultConfig {
-                        upload_ac_results_strategy:
-                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
-                        ..Default::default()
-                    },
-                max_action_timeout: MAX_TIMEOUT_DURATION,
-                timeout_handled_externally: false,
-            },
-            Callbacks {
-                now_fn: test_monotonic_clock,
-                sleep_fn: |duration| {
-                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
-                    Box::pin(futures::future::pending())
-                },
-            },
-        )?);
+    run_action(running_action_impl.clone()).await?;
 
-        let execute_request = ExecuteRequest {
-            action_digest: Some(action_digest.into()),
-            ..Default::default()
-        };
-        let operation_id = OperationId::default().to_string();
+    let mut dir_stream = fs::read_dir(&root_action_directory).await?;
+    assert!(
+        dir_stream.as_mut().next_entry().await?.is_none(),
+        "Expected empty directory at {root_action_directory}"
+    );
+    Ok(())
+}
 
-        running_actions_manager
-            .create_and_add_action(
-                WORKER_ID.to_string(),
-                StartExecute {
-                    execute_request: Some(execute_request),
-                    operation_id,
-                    queued_timestamp: Some(make_system_time(1000).into()),
-                },
-            )
-            .and_then(|action| {
-                action
-                    .clone()
-                    .prepare_action()
-                    .and_then(RunningAction::execute)
-                    .then(|result| async move {
-                        if let Err(e) = action.cleanup().await {
-                            return Result::<ActionResult, Error>::Err(e).merge(result);
-                        }
-                        result
-                    })
-            })
-            .await?;
-        assert_eq!(
-            SENT_TIMEOUT.load(Ordering::Relaxed),
-            TASK_TIMEOUT.as_millis() as i64
-        );
+// We've experienced deadlocks when uploading, so make only a single permit available and
+// check it's able to handle uploading some directories with some files in.
+// Be default this test is ignored because it *must* be run single threaded... to run this
+// test execute:
+// cargo test -p nativelink-worker --test running_actions_manager_test -- --test-threads=1 --ignored
+#[nativelink_test]
+#[ignore]
+async fn upload_with_single_permit() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    fn test_monotonic_clock() -> SystemTime {
+        static CLOCK: AtomicU64 = AtomicU64::new(0);
+        monotonic_clock(&CLOCK)
     }
-    {
-        // Ensure if no timeout is set use max timeout.
-        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
-        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
-        const TASK_TIMEOUT: Duration = Duration::from_secs(0);
 
-        let action = Action {
-            command_digest: Some(command_digest.into()),
-            input_root_digest: Some(input_root_digest.into()),
-            timeout: Some(prost_types::Duration {
-                seconds: TASK_TIMEOUT.as_secs() as i64,
-                nanos: 0,
-            }),
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+
+    // Take all but one FD permit away.
+    let _permits = futures::stream::iter(1..fs::OPEN_FILE_SEMAPHORE.available_permits())
+        .then(|_| fs::OPEN_FILE_SEMAPHORE.acquire())
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(1, fs::OPEN_FILE_SEMAPHORE.available_permits());
+
+    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
+        RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration::default(),
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
+                ..Default::default()
+            },
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        },
+        Callbacks {
+            now_fn: test_monotonic_clock,
+            sleep_fn: |_duration| Box::pin(futures::future::pending()),
+        },
+    )?);
+    let action_result = {
+        #[cfg(target_family = "unix")]
+            let arguments = vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "printf '123 ' > ./test.txt; mkdir ./tst; printf '456 ' > ./tst/tst.txt; printf 'foo-stdout '; >&2 printf 'bar-stderr  '"
+                    .to_string(),
+            ];
+        #[cfg(target_family = "windows")]
+            let arguments = vec![
+                "cmd".to_string(),
+                "/C".to_string(),
+                // Note: Windows adds two spaces after 'set /p=XXX'.
+                "echo | set /p=123> ./test.txt & mkdir ./tst & echo | set /p=456> ./tst/tst.txt & echo | set /p=foo-stdout & echo | set /p=bar-stderr 1>&2 & exit 0"
+                    .to_string(),
+            ];
+        let working_directory = "some_cwd";
+        let command = Command {
+            arguments,
+            output_paths: vec!["test.txt".to_string(), "tst".to_string()],
+            working_directory: working_directory.to_string(),
             ..Default::default()
         };
-        let action_digest = serialize_and_upload_message(
-            &action,
+        let command_digest = serialize_and_upload_message(
+            &command,
             cas_store.as_pin(),
             &mut DigestHasherFunc::Sha256.hasher(),
         )
         .await?;
-
-        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-            RunningActionsManagerArgs {
-                root_action_directory: root_action_directory.clone(),
-                execution_configuration: ExecutionConfiguration::default(),
-                cas_store: cas_store.clone(),
-                ac_store: Some(Store::new(ac_store.clone())),
-                historical_store: Store::new(cas_store.clone()),
-                upload_action_result_config:
-                    &nativelink_config::cas_server::UploadActionResultConfig {
-                        upload_ac_results_strategy:
-                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
-                        ..Default::default()
-                    },
-                max_action_timeout: MAX_TIMEOUT_DURATION,
-                timeout_handled_externally: false,
-            },
-            Callbacks {
-                now_fn: test_monotonic_clock,
-                sleep_fn: |duration| {
-                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
-                    Box::pin(futures::future::pending())
-                },
+        let input_root_digest = serialize_and_upload_message(
+            &Directory {
+                directories: vec![DirectoryNode {
+                    name: working_directory.to_string(),
+                    digest: Some(
+                        serialize_and_upload_message(
+                            &Directory::default(),
+                            cas_store.as_pin(),
+                            &mut DigestHasherFunc::Sha256.hasher(),
+                        )
+                        .await?
+                        .into(),
+                    ),
+                }],
+                ..Default::default()
             },
-        )?);
-
-        let execute_request = ExecuteRequest {
-            action_digest: Some(action_digest.into()),
-            ..Default::default()
-        };
-        let operation_id = OperationId::default().to_string();
-
-        running_actions_manager
-            .create_and_add_action(
-                WORKER_ID.to_string(),
-                StartExecute {
-                    execute_request: Some(execute_request),
-                    operation_id,
-                    queued_timestamp: Some(make_system_time(1000).into()),
-                },
-            )
-            .and_then(|action| {
-                action
-                    .clone()
-                    .prepare_action()
-                    .and_then(RunningAction::execute)
-                    .then(|result| async move {
-                        if let Err(e) = action.cleanup().await {
-                            return Result::<ActionResult, Error>::Err(e).merge(result);
-                        }
-                        result
-                    })
-            })
-            .await?;
-        assert_eq!(
-            SENT_TIMEOUT.load(Ordering::Relaxed),
-            MAX_TIMEOUT_DURATION.as_millis() as i64
-        );
-    }
-    {
-        // Ensure we reject tasks that have a timeout set too high.
-        static SENT_TIMEOUT: AtomicI64 = AtomicI64::new(-1);
-        const MAX_TIMEOUT_DURATION: Duration = Duration::from_secs(100);
-        const TASK_TIMEOUT: Duration = Duration::from_secs(200);
-
+            cas_store.as_pin(),
+            &mut DigestHasherFunc::Sha256.hasher(),
+        )
+        .await?;
         let action = Action {
             command_digest: Some(command_digest.into()),
             input_root_digest: Some(input_root_digest.into()),
-            timeout: Some(prost_types::Duration {
-                seconds: TASK_TIMEOUT.as_secs() as i64,
-                nanos: 0,
-            }),
             ..Default::default()
         };
         let action_digest = serialize_and_upload_message(
@@ -2513,86 +4531,113 @@ async fn ensure_worker_timeout_chooses_correct_values() -> Result<(), Box<dyn st
         )
         .await?;
 
-        let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-            RunningActionsManagerArgs {
-                root_action_directory: root_action_directory.clone(),
-                execution_configuration: ExecutionConfiguration::default(),
-                cas_store: cas_store.clone(),
-                ac_store: Some(Store::new(ac_store.clone())),
-                historical_store: Store::new(cas_store.clone()),
-                upload_action_result_config:
-                    &nativelink_config::cas_server::UploadActionResultConfig {
-                        upload_ac_results_strategy:
-                            nativelink_config::cas_server::UploadCacheResultsStrategy::never,
-                        ..Default::default()
-                    },
-                max_action_timeout: MAX_TIMEOUT_DURATION,
-                timeout_handled_externally: false,
-            },
-            Callbacks {
-                now_fn: test_monotonic_clock,
-                sleep_fn: |duration| {
-                    SENT_TIMEOUT.store(duration.as_millis() as i64, Ordering::Relaxed);
-                    Box::pin(futures::future::pending())
-                },
-            },
-        )?);
-
         let execute_request = ExecuteRequest {
             action_digest: Some(action_digest.into()),
             ..Default::default()
         };
         let operation_id = OperationId::default().to_string();
 
-        let result = running_actions_manager
+        let running_action_impl = running_actions_manager
             .create_and_add_action(
                 WORKER_ID.to_string(),
                 StartExecute {
                     execute_request: Some(execute_request),
                     operation_id,
-                    queued_timestamp: Some(make_system_time(1000).into()),
+                    queued_timestamp: None,
                 },
             )
-            .and_then(|action| {
-                action
-                    .clone()
-                    .prepare_action()
-                    .and_then(RunningAction::execute)
-                    .then(|result| async move {
-                        if let Err(e) = action.cleanup().await {
-                            return Result::<ActionResult, Error>::Err(e).merge(result);
-                        }
-                        result
-                    })
-            })
-            .await;
-        assert_eq!(SENT_TIMEOUT.load(Ordering::Relaxed), -1);
-        assert_eq!(result.err().unwrap().code, Code::InvalidArgument);
-    }
+            .await?;
+
+        run_action(running_action_impl.clone()).await?
+    };
+    let file_content = slow_store
+        .as_ref()
+        .get_part_unchunked(action_result.output_files[0].digest, 0, None)
+        .await?;
+    assert_eq!(from_utf8(&file_content)?, "123 ");
+    let stdout_content = slow_store
+        .as_ref()
+        .get_part_unchunked(action_result.stdout_digest.unwrap(), 0, None)
+        .await?;
+    assert_eq!(from_utf8(&stdout_content)?, "foo-stdout ");
+    let stderr_content = slow_store
+        .as_ref()
+        .get_part_unchunked(action_result.stderr_digest.unwrap(), 0, None)
+        .await?;
+    assert_eq!(from_utf8(&stderr_content)?, "bar-stderr  ");
+    let mut clock_time = make_system_time(0);
+    assert_eq!(
+        action_result,
+        ActionResult {
+            output_files: vec![FileInfo {
+                name_or_path: NameOrPath::Path("test.txt".to_string()),
+                digest: DigestInfo::try_new(
+                    "c69e10a5f54f4e28e33897fbd4f8701595443fa8c3004aeaa20dd4d9a463483b",
+                    4
+                )?,
+                is_executable: false,
+                unix_mode: None,
+            }],
+            stdout_digest: Some(DigestInfo::try_new(
+                "15019a676f057d97d1ad3af86f3cc1e623cb33b18ff28422bbe3248d2471cc94",
+                11
+            )?),
+            stderr_digest: Some(DigestInfo::try_new(
+                "2375ab8a01ca11e1ea7606dfb58756c153d49733cde1dbfb5a1e00f39afacf06",
+                12
+            )?),
+            stdout_raw: Vec::new(),
+            stderr_raw: Vec::new(),
+            exit_code: 0,
+            output_folders: vec![DirectoryInfo {
+                path: "tst".to_string(),
+                tree_digest: DigestInfo::try_new(
+                    "95711c1905d4898a70209dd6e98241dcafb479c00241a1ea4ed8415710d706f3",
+                    166,
+                )?,
+            },],
+            output_file_symlinks: vec![],
+            output_directory_symlinks: vec![],
+            server_logs: HashMap::new(),
+            execution_metadata: ExecutionMetadata {
+                worker: WORKER_ID.to_string(),
+                queued_timestamp: SystemTime::UNIX_EPOCH,
+                worker_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_start_timestamp: increment_clock(&mut clock_time),
+                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
+                execution_start_timestamp: increment_clock(&mut clock_time),
+                execution_completed_timestamp: increment_clock(&mut clock_time),
+                output_upload_start_timestamp: increment_clock(&mut clock_time),
+                output_upload_completed_timestamp: increment_clock(&mut clock_time),
+                worker_completed_timestamp: increment_clock(&mut clock_time),
+            },
+            error: None,
+            message: String::new(),
+        }
+    );
     Ok(())
 }
 
+#[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
+async fn running_actions_manager_respects_action_timeout() -> Result<(), Box<dyn std::error::Error>>
+{
     const WORKER_ID: &str = "foo_worker_id";
 
+    // Ignore the sleep and immediately timeout.
+    static ACTION_TIMEOUT: i64 = 1;
     fn test_monotonic_clock() -> SystemTime {
         static CLOCK: AtomicU64 = AtomicU64::new(0);
         monotonic_clock(&CLOCK)
     }
 
-    type StaticOneshotTuple = Mutex<(Option<oneshot::Sender<()>>, Option<oneshot::Receiver<()>>)>;
-    static TIMEOUT_ONESHOT: LazyLock<StaticOneshotTuple> = LazyLock::new(|| {
-        let (tx, rx) = oneshot::channel();
-        Mutex::new((Some(tx), Some(rx)))
-    });
-    let root_action_directory = make_temp_path("root_action_directory");
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_work_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
     let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
         RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
+            root_action_directory,
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -2604,37 +4649,35 @@ async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
         },
         Callbacks {
             now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| {
-                Box::pin(async move {
-                    let rx = TIMEOUT_ONESHOT.lock().unwrap().1.take().unwrap();
-                    rx.await.expect("Could not receive timeout signal");
-                })
+            // If action_timeout is the passed duration then return immeidately,
+            // which will cause the action to be killed and pass the test,
+            // otherwise return pending and fail the test.
+            sleep_fn: |duration| {
+                assert_eq!(duration.as_secs(), ACTION_TIMEOUT as u64);
+                Box::pin(futures::future::ready(()))
             },
         },
     )?);
-
     #[cfg(target_family = "unix")]
-    let arguments = vec![
-        "sh".to_string(),
-        "-c".to_string(),
-        "sleep infinity".to_string(),
-    ];
+    let arguments = vec!["sh".to_string(), "-c".to_string(), "sleep 2".to_string()];
     #[cfg(target_family = "windows")]
-    // Windows is weird with timeout, so we use ping. See:
-    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
     let arguments = vec![
         "cmd".to_string(),
         "/C".to_string(),
         "ping -n 99999 127.0.0.1".to_string(),
     ];
-
     let command = Command {
         arguments,
-        output_paths: vec![],
         working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
         ..Default::default()
     };
     let command_digest = serialize_and_upload_message(
@@ -2652,6 +4695,16 @@ async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
     let action = Action {
         command_digest: Some(command_digest.into()),
         input_root_digest: Some(input_root_digest.into()),
+        platform: Some(Platform {
+            properties: vec![Property {
+                name: "property_name".into(),
+                value: "property_value".into(),
+            }],
+        }),
+        timeout: Some(prost_types::Duration {
+            seconds: ACTION_TIMEOUT,
+            nanos: 0,
+        }),
         ..Default::default()
     };
     let action_digest = serialize_and_upload_message(
@@ -2667,7 +4720,8 @@ async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
     };
     let operation_id = OperationId::default().to_string();
 
-    let execute_results_fut = running_actions_manager
+    let running_action_impl = running_actions_manager
+        .clone()
         .create_and_add_action(
             WORKER_ID.to_string(),
             StartExecute {
@@ -2676,49 +4730,39 @@ async fn worker_times_out() -> Result<(), Box<dyn std::error::Error>> {
                 queued_timestamp: Some(make_system_time(1000).into()),
             },
         )
-        .and_then(|action| {
-            action
-                .clone()
-                .prepare_action()
-                .and_then(RunningAction::execute)
-                .and_then(RunningAction::upload_results)
-                .and_then(RunningAction::get_finished_result)
-                .then(|result| async move {
-                    if let Err(e) = action.cleanup().await {
-                        return Result::<ActionResult, Error>::Err(e).merge(result);
-                    }
-                    result
-                })
-        });
+        .await?;
 
-    let (results, ()) = tokio::join!(execute_results_fut, async move {
-        tokio::task::yield_now().await;
-        let tx = TIMEOUT_ONESHOT.lock().unwrap().0.take().unwrap();
-        tx.send(()).expect("Could not send timeout signal");
-    });
-    assert_eq!(results?.error.unwrap().code, Code::DeadlineExceeded);
+    let result = run_action(running_action_impl).await?;
 
+    #[cfg(target_family = "unix")]
+    assert_eq!(result.exit_code, 9, "Action process should be been killed");
+    #[cfg(target_family = "windows")]
+    assert_eq!(result.exit_code, 1, "Action process should be been killed");
     Ok(())
 }
 
-#[cfg_attr(feature = "nix", ignore)]
+// Creating cgroups needs a cgroup v2 directory that is writable by the test
+// and has the `memory` controller enabled in its `cgroup.subtree_control`,
+// which is rarely the case, so the test only runs if one is provided.
+#[cfg(target_os = "linux")]
 #[nativelink_test]
-async fn kill_all_waits_for_all_tasks_to_finish() -> Result<(), Box<dyn std::error::Error>> {
+async fn action_over_memory_limit_is_killed_in_cgroup() -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
+    let Ok(action_cgroup_directory) = std::env::var("NATIVELINK_TEST_CGROUP_DIRECTORY") else {
+        return Ok(());
+    };
 
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
-    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-        RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
-            execution_configuration: ExecutionConfiguration::default(),
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                action_cgroup_directory: Some(action_cgroup_directory.clone()),
+                ..Default::default()
+            },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
             historical_store: Store::new(cas_store.clone()),
@@ -2729,31 +4773,18 @@ async fn kill_all_waits_for_all_tasks_to_finish() -> Result<(), Box<dyn std::err
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        },
-        Callbacks {
-            now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| Box::pin(futures::future::pending()),
-        },
-    )?);
-
-    #[cfg(target_family = "unix")]
-    let arguments = vec![
-        "sh".to_string(),
-        "-c".to_string(),
-        "sleep infinity".to_string(),
-    ];
-    #[cfg(target_family = "windows")]
-    // Windows is weird with timeout, so we use ping. See:
-    // https://www.ibm.com/support/pages/timeout-command-run-batch-job-exits-immediately-and-returns-error-input-redirection-not-supported-exiting-process-immediately
-    let arguments = vec![
-        "cmd".to_string(),
-        "/C".to_string(),
-        "ping -n 99999 127.0.0.1".to_string(),
-    ];
-
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    // `dd` allocates and fills a single 256MiB block, far over the limit.
     let command = Command {
-        arguments,
-        output_paths: vec![],
+        arguments: vec![
+            "dd".to_string(),
+            "if=/dev/zero".to_string(),
+            "of=/dev/null".to_string(),
+            "bs=256M".to_string(),
+            "count=1".to_string(),
+        ],
         working_directory: ".".to_string(),
         environment_variables: vec![EnvironmentVariable {
             name: "PATH".to_string(),
@@ -2776,6 +4807,12 @@ async fn kill_all_waits_for_all_tasks_to_finish() -> Result<(), Box<dyn std::err
     let action = Action {
         command_digest: Some(command_digest.into()),
         input_root_digest: Some(input_root_digest.into()),
+        platform: Some(Platform {
+            properties: vec![Property {
+                name: "memory_kb".into(),
+                value: "16384".into(),
+            }],
+        }),
         ..Default::default()
     };
     let action_digest = serialize_and_upload_message(
@@ -2785,108 +4822,63 @@ async fn kill_all_waits_for_all_tasks_to_finish() -> Result<(), Box<dyn std::err
     )
     .await?;
 
-    let execute_request = ExecuteRequest {
-        action_digest: Some(action_digest.into()),
-        ..Default::default()
-    };
-    let operation_id = OperationId::default().to_string();
-
-    let (cleanup_tx, cleanup_rx) = oneshot::channel();
-    let cleanup_was_requested = AtomicBool::new(false);
-    let action = running_actions_manager
+    let running_action_impl = running_actions_manager
+        .clone()
         .create_and_add_action(
             WORKER_ID.to_string(),
             StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
                 queued_timestamp: Some(make_system_time(1000).into()),
             },
         )
         .await?;
-    let execute_results_fut = action
-        .clone()
-        .prepare_action()
-        .and_then(RunningAction::execute)
-        .and_then(RunningAction::upload_results)
-        .and_then(RunningAction::get_finished_result)
-        .then(|result| async {
-            cleanup_was_requested.store(true, Ordering::Release);
-            cleanup_rx.await.expect("Could not receive cleanup signal");
-            if let Err(e) = action.cleanup().await {
-                return Result::<ActionResult, Error>::Err(e).merge(result);
-            }
-            result
-        });
-
-    tokio::pin!(execute_results_fut);
-    {
-        // Advance the action as far as possible and ensure we are not waiting on cleanup.
-        for _ in 0..100 {
-            assert!(futures::poll!(&mut execute_results_fut).is_pending());
-            tokio::task::yield_now().await;
-        }
-        assert_eq!(cleanup_was_requested.load(Ordering::Acquire), false);
-    }
 
-    let kill_all_fut = running_actions_manager.kill_all();
-    tokio::pin!(kill_all_fut);
+    let result = run_action(running_action_impl).await?;
 
-    {
-        // * Advance the action as far as possible.
-        // * Ensure we are now waiting on cleanup.
-        // * Ensure our kill_action is still pending.
-        while !cleanup_was_requested.load(Ordering::Acquire) {
-            // Wait for cleanup to be triggered.
-            tokio::task::yield_now().await;
-            assert!(futures::poll!(&mut execute_results_fut).is_pending());
-            assert!(futures::poll!(&mut kill_all_fut).is_pending());
-        }
-    }
-    // Allow cleanup, which allows execute_results_fut to advance.
-    cleanup_tx.send(()).expect("Could not send cleanup signal");
-    // Advance our two futures to completion now.
-    let result = execute_results_fut.await;
-    kill_all_fut.await;
-    {
-        // Ensure our results are correct.
-        let action_result = result?;
-        let err = action_result
-            .error
-            .as_ref()
-            .err_tip(|| format!("No error exists in result : {action_result:?}"))?;
-        assert_eq!(
-            err.code,
-            Code::Aborted,
-            "Expected Aborted : {action_result:?}"
+    assert_eq!(
+        result.exit_code, 9,
+        "Action process should have been killed"
+    );
+    assert_eq!(
+        result.error.map(|err| err.code),
+        Some(Code::ResourceExhausted)
+    );
+    // The cgroup of the action is removed in cleanup().
+    let mut cgroups = fs::read_dir(&action_cgroup_directory).await?;
+    while let Some(entry) = cgroups.as_mut().next_entry().await? {
+        assert!(
+            !entry.file_type().await?.is_dir(),
+            "Expected cgroup {:?} to be removed",
+            entry.path()
         );
     }
-
     Ok(())
 }
 
-/// Regression Test for Issue #675
-#[cfg(target_family = "unix")]
 #[cfg_attr(feature = "nix", ignore)]
 #[nativelink_test]
-async fn unix_executable_file_test() -> Result<(), Box<dyn std::error::Error>> {
+async fn small_outputs_are_inlined_and_large_outputs_uploaded(
+) -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
-    const FILE_1_NAME: &str = "file1";
+    const MAX_INLINE_OUTPUT_BYTES: usize = 16;
 
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
+    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-        RunningActionsManagerArgs {
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
             root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                max_inline_output_bytes: MAX_INLINE_OUTPUT_BYTES,
+                ..Default::default()
+            },
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
-            execution_configuration: ExecutionConfiguration::default(),
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
                 upload_ac_results_strategy:
@@ -2895,93 +4887,95 @@ async fn unix_executable_file_test() -> Result<(), Box<dyn std::error::Error>> {
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        },
-        Callbacks {
-            now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| Box::pin(futures::future::pending()),
-        },
-    )?);
-    // Create and run an action which
-    // creates a file with owner executable permissions.
-    let action_result = {
-        let command = Command {
-            arguments: vec![
-                "sh".to_string(),
-                "-c".to_string(),
-                format!("touch {FILE_1_NAME} && chmod 700 {FILE_1_NAME}").to_string(),
-            ],
-            output_paths: vec![FILE_1_NAME.to_string()],
-            working_directory: ".".to_string(),
-            environment_variables: vec![EnvironmentVariable {
-                name: "PATH".to_string(),
-                value: std::env::var("PATH").unwrap(),
-            }],
-            ..Default::default()
-        };
-        let command_digest = serialize_and_upload_message(
-            &command,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-        let input_root_digest = serialize_and_upload_message(
-            &Directory::default(),
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-        let action = Action {
-            command_digest: Some(command_digest.into()),
-            input_root_digest: Some(input_root_digest.into()),
-            ..Default::default()
-        };
-        let action_digest = serialize_and_upload_message(
-            &action,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    let large_stderr = "x".repeat(MAX_INLINE_OUTPUT_BYTES + 1);
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("printf 'foo-stdout'; >&2 printf '{large_stderr}'"),
+        ],
+        working_directory: ".".to_string(),
+        ..Default::default()
+    };
+    let command_digest = serialize_and_upload_message(
+        &command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action = Action {
+        command_digest: Some(command_digest.into()),
+        input_root_digest: Some(input_root_digest.into()),
+        ..Default::default()
+    };
+    let action_digest = serialize_and_upload_message(
+        &action,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let running_action_impl = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: None,
+            },
         )
         .await?;
 
-        let execute_request = ExecuteRequest {
-            action_digest: Some(action_digest.into()),
-            ..Default::default()
-        };
-        let operation_id = OperationId::default().to_string();
+    let action_result = run_action(running_action_impl).await?;
 
-        let running_action_impl = running_actions_manager
-            .create_and_add_action(
-                WORKER_ID.to_string(),
-                StartExecute {
-                    execute_request: Some(execute_request),
-                    operation_id,
-                    ..Default::default()
-                },
-            )
-            .await?;
+    // Stdout is small enough to be inlined, so it is not uploaded.
+    assert_eq!(from_utf8(&action_result.stdout_raw)?, "foo-stdout");
+    assert_eq!(action_result.stdout_digest, None);
+    let stdout_digest = DigestHasherFunc::Sha256
+        .hasher()
+        .compute_from_reader(Cursor::new("foo-stdout"))
+        .await?;
+    assert_eq!(slow_store.as_ref().has(stdout_digest).await?, None);
+    // Stderr is too large to be inlined, so it is only referenced by digest.
+    assert!(action_result.stderr_raw.is_empty());
+    let stderr_content = slow_store
+        .as_ref()
+        .get_part_unchunked(action_result.stderr_digest.unwrap(), 0, None)
+        .await?;
+    assert_eq!(from_utf8(&stderr_content)?, large_stderr);
 
-        run_action(running_action_impl.clone()).await?
-    };
-    // Ensure the file copied from worker to CAS is executable.
-    assert!(
-        action_result.output_files[0].is_executable,
-        "Expected output file to be executable"
-    );
+    let proto_result: ProtoActionResult = action_result.into();
+    assert_eq!(proto_result.stdout_raw, "foo-stdout".as_bytes());
+    assert_eq!(proto_result.stdout_digest, None);
+    assert!(proto_result.stderr_raw.is_empty());
+    assert!(proto_result.stderr_digest.is_some());
     Ok(())
 }
 
-#[nativelink_test]
-async fn action_directory_contents_are_cleaned() -> Result<(), Box<dyn std::error::Error>> {
-    const WORKER_ID: &str = "foo_worker_id";
-
+/// Makes a manager that runs at most one action at a time and a
+/// `StartExecute` for an action that was never run.
+async fn setup_single_action_slot_manager(
+    queue_actions_over_limit: bool,
+) -> Result<(Arc<RunningActionsManagerImpl>, ExecuteRequest), Box<dyn std::error::Error>> {
     let (_, _, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
-    let temp_action_directory = make_temp_path("root_action_directory/temp");
-    fs::create_dir_all(&temp_action_directory).await?;
 
     let running_actions_manager =
         Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
-            root_action_directory: root_action_directory.clone(),
+            root_action_directory,
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
             ac_store: Some(Store::new(ac_store.clone())),
@@ -2993,26 +4987,12 @@ async fn action_directory_contents_are_cleaned() -> Result<(), Box<dyn std::erro
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
+            max_concurrent_actions: 1,
+            queue_actions_over_limit,
         })?);
-    let queued_timestamp = make_system_time(1000);
-
-    #[cfg(target_family = "unix")]
-    let arguments = vec!["sh".to_string(), "-c".to_string(), "exit 0".to_string()];
-    #[cfg(target_family = "windows")]
-    let arguments = vec!["cmd".to_string(), "/C".to_string(), "exit 0".to_string()];
 
-    let command = Command {
-        arguments,
-        output_paths: vec![],
-        working_directory: ".".to_string(),
-        environment_variables: vec![EnvironmentVariable {
-            name: "PATH".to_string(),
-            value: std::env::var("PATH").unwrap(),
-        }],
-        ..Default::default()
-    };
     let command_digest = serialize_and_upload_message(
-        &command,
+        &Command::default(),
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
@@ -3023,73 +5003,94 @@ async fn action_directory_contents_are_cleaned() -> Result<(), Box<dyn std::erro
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-    let action = Action {
-        command_digest: Some(command_digest.into()),
-        input_root_digest: Some(input_root_digest.into()),
-        ..Default::default()
-    };
     let action_digest = serialize_and_upload_message(
-        &action,
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        },
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-
     let execute_request = ExecuteRequest {
         action_digest: Some(action_digest.into()),
         ..Default::default()
     };
-    let operation_id = OperationId::default().to_string();
+    Ok((running_actions_manager, execute_request))
+}
 
-    let running_action_impl = running_actions_manager
-        .create_and_add_action(
-            WORKER_ID.to_string(),
-            StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
-                queued_timestamp: Some(queued_timestamp.into()),
-            },
-        )
+fn make_start_execute(execute_request: &ExecuteRequest) -> StartExecute {
+    StartExecute {
+        execute_request: Some(execute_request.clone()),
+        operation_id: OperationId::default().to_string(),
+        queued_timestamp: Some(make_system_time(1000).into()),
+    }
+}
+
+#[nativelink_test]
+async fn actions_over_max_concurrent_actions_are_rejected() -> Result<(), Box<dyn std::error::Error>>
+{
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (running_actions_manager, execute_request) =
+        setup_single_action_slot_manager(false).await?;
+
+    let first_action = running_actions_manager
+        .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request))
         .await?;
+    let result = running_actions_manager
+        .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request))
+        .await;
+    assert_eq!(result.err().map(|e| e.code), Some(Code::ResourceExhausted));
 
-    run_action(running_action_impl.clone()).await?;
+    // Once the first action is done its slot can be used again.
+    drop(first_action.cleanup().await?);
+    running_actions_manager
+        .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request))
+        .await?
+        .cleanup()
+        .await?;
+    Ok(())
+}
 
-    let mut dir_stream = fs::read_dir(&root_action_directory).await?;
+#[nativelink_test]
+async fn actions_over_max_concurrent_actions_are_queued() -> Result<(), Box<dyn std::error::Error>>
+{
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (running_actions_manager, execute_request) = setup_single_action_slot_manager(true).await?;
+
+    let first_action = running_actions_manager
+        .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request))
+        .await?;
+    let mut second_action_fut = Box::pin(
+        running_actions_manager
+            .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request)),
+    );
     assert!(
-        dir_stream.as_mut().next_entry().await?.is_none(),
-        "Expected empty directory at {root_action_directory}"
+        futures::poll!(&mut second_action_fut).is_pending(),
+        "Expected second action to wait for the first one"
     );
+
+    drop(first_action.cleanup().await?);
+    second_action_fut.await?.cleanup().await?;
     Ok(())
 }
 
-// We've experienced deadlocks when uploading, so make only a single permit available and
-// check it's able to handle uploading some directories with some files in.
-// Be default this test is ignored because it *must* be run single threaded... to run this
-// test execute:
-// cargo test -p nativelink-worker --test running_actions_manager_test -- --test-threads=1 --ignored
-#[nativelink_test]
-#[ignore]
-async fn upload_with_single_permit() -> Result<(), Box<dyn std::error::Error>> {
+/// Runs `command` to completion with an empty input root and returns its result.
+#[cfg(not(target_family = "windows"))]
+async fn run_command_with_empty_input_root(
+    command: &Command,
+) -> Result<ActionResult, Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
 
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
-    let (_, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let (_, _, cas_store, ac_store) = setup_stores().await?;
     let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
 
-    // Take all but one FD permit away.
-    let _permits = futures::stream::iter(1..fs::OPEN_FILE_SEMAPHORE.available_permits())
-        .then(|_| fs::OPEN_FILE_SEMAPHORE.acquire())
-        .try_collect::<Vec<_>>()
-        .await?;
-    assert_eq!(1, fs::OPEN_FILE_SEMAPHORE.available_permits());
-
-    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-        RunningActionsManagerArgs {
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
             root_action_directory,
             execution_configuration: ExecutionConfiguration::default(),
             cas_store: cas_store.clone(),
@@ -3102,180 +5103,339 @@ async fn upload_with_single_permit() -> Result<(), Box<dyn std::error::Error>> {
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        },
-        Callbacks {
-            now_fn: test_monotonic_clock,
-            sleep_fn: |_duration| Box::pin(futures::future::pending()),
-        },
-    )?);
-    let action_result = {
-        #[cfg(target_family = "unix")]
-            let arguments = vec![
-                "sh".to_string(),
-                "-c".to_string(),
-                "printf '123 ' > ./test.txt; mkdir ./tst; printf '456 ' > ./tst/tst.txt; printf 'foo-stdout '; >&2 printf 'bar-stderr  '"
-                    .to_string(),
-            ];
-        #[cfg(target_family = "windows")]
-            let arguments = vec![
-                "cmd".to_string(),
-                "/C".to_string(),
-                // Note: Windows adds two spaces after 'set /p=XXX'.
-                "echo | set /p=123> ./test.txt & mkdir ./tst & echo | set /p=456> ./tst/tst.txt & echo | set /p=foo-stdout & echo | set /p=bar-stderr 1>&2 & exit 0"
-                    .to_string(),
-            ];
-        let working_directory = "some_cwd";
-        let command = Command {
-            arguments,
-            output_paths: vec!["test.txt".to_string(), "tst".to_string()],
-            working_directory: working_directory.to_string(),
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    let command_digest = serialize_and_upload_message(
+        command,
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let input_root_digest = serialize_and_upload_message(
+        &Directory::default(),
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+    let action_digest = serialize_and_upload_message(
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
             ..Default::default()
-        };
-        let command_digest = serialize_and_upload_message(
-            &command,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
+        },
+        cas_store.as_pin(),
+        &mut DigestHasherFunc::Sha256.hasher(),
+    )
+    .await?;
+
+    let running_action_impl = running_actions_manager
+        .create_and_add_action(
+            WORKER_ID.to_string(),
+            StartExecute {
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: None,
+            },
         )
         .await?;
-        let input_root_digest = serialize_and_upload_message(
-            &Directory {
-                directories: vec![DirectoryNode {
-                    name: working_directory.to_string(),
-                    digest: Some(
-                        serialize_and_upload_message(
-                            &Directory::default(),
-                            cas_store.as_pin(),
-                            &mut DigestHasherFunc::Sha256.hasher(),
-                        )
-                        .await?
-                        .into(),
-                    ),
-                }],
+    Ok(run_action(running_action_impl).await?)
+}
+
+// The command below uses `sh`.
+#[cfg(not(target_family = "windows"))]
+#[cfg_attr(feature = "nix", ignore)]
+#[nativelink_test]
+async fn legacy_output_fields_are_captured_like_output_paths(
+) -> Result<(), Box<dyn std::error::Error>> {
+    const OUTPUT_FILE: &str = "file_parent/out.txt";
+    const OUTPUT_DIRECTORY: &str = "dir_parent/out_dir";
+
+    // Neither `mkdir` nor the redirect create parent directories, so this only
+    // succeeds if the worker created them for the declared outputs.
+    let command = Command {
+        arguments: vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            concat!(
+                "mkdir dir_parent/out_dir && ",
+                "echo foo > dir_parent/out_dir/file && ",
+                "echo bar > file_parent/out.txt",
+            )
+            .to_string(),
+        ],
+        working_directory: ".".to_string(),
+        environment_variables: vec![EnvironmentVariable {
+            name: "PATH".to_string(),
+            value: std::env::var("PATH").unwrap(),
+        }],
+        ..Default::default()
+    };
+    let legacy_result = run_command_with_empty_input_root(&Command {
+        output_files: vec![OUTPUT_FILE.to_string()],
+        output_directories: vec![OUTPUT_DIRECTORY.to_string()],
+        ..command.clone()
+    })
+    .await?;
+    let output_paths_result = run_command_with_empty_input_root(&Command {
+        output_paths: vec![OUTPUT_FILE.to_string(), OUTPUT_DIRECTORY.to_string()],
+        ..command
+    })
+    .await?;
+
+    assert_eq!(legacy_result.exit_code, 0);
+    assert_eq!(legacy_result.output_files.len(), 1);
+    assert_eq!(legacy_result.output_folders.len(), 1);
+    assert_eq!(legacy_result.output_files, output_paths_result.output_files);
+    assert_eq!(
+        legacy_result.output_folders,
+        output_paths_result.output_folders
+    );
+    Ok(())
+}
+
+#[nativelink_test]
+async fn orphaned_action_directories_are_removed() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+
+    let (running_actions_manager, execute_request) =
+        setup_single_action_slot_manager(false).await?;
+    let running_action = running_actions_manager
+        .create_and_add_action(WORKER_ID.to_string(), make_start_execute(&execute_request))
+        .await?;
+    // The work directory is the `work` subdirectory of the action's directory.
+    let work_directory = std::path::PathBuf::from(running_action.get_work_directory());
+    let action_directory = work_directory.parent().unwrap();
+    let root_action_directory = action_directory.parent().unwrap();
+
+    // Left behind by a worker that stopped before cleaning up its actions.
+    let stale_directory = root_action_directory.join("stale_operation_id");
+    fs::create_dir_all(stale_directory.join("some_dir")).await?;
+    let stale_file = root_action_directory.join("stale_file");
+    std::fs::write(&stale_file, b"foo")?;
+
+    // Entries that were modified recently are kept.
+    running_actions_manager
+        .remove_orphaned_action_directories(Duration::from_secs(3600))
+        .await?;
+    assert!(stale_directory.exists());
+    assert!(stale_file.exists());
+
+    running_actions_manager
+        .remove_orphaned_action_directories(Duration::ZERO)
+        .await?;
+    assert!(!stale_directory.exists());
+    assert!(!stale_file.exists());
+    // The directory of the running action is not touched.
+    assert!(action_directory.exists());
+
+    running_action.cleanup().await?;
+    Ok(())
+}
+
+#[nativelink_test]
+async fn prepare_action_reuses_staged_inputs_test() -> Result<(), Box<dyn std::error::Error>> {
+    const WORKER_ID: &str = "foo_worker_id";
+    const FILE1_CONTENT: &str = "HELLOFILE1";
+    const FILE2_CONTENT: &str = "HELLOFILE2";
+    const FILE3_CONTENT: &str = "HELLOFILE3";
+
+    let (fast_store, slow_store, cas_store, ac_store) = setup_stores().await?;
+    let root_action_directory = make_temp_path("root_action_directory");
+    fs::create_dir_all(&root_action_directory).await?;
+    let input_staging_directory = make_temp_path("input_staging_directory");
+
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
+            root_action_directory,
+            execution_configuration: ExecutionConfiguration {
+                input_staging_directory: Some(input_staging_directory.clone()),
+                ..Default::default()
+            },
+            cas_store: cas_store.clone(),
+            ac_store: Some(Store::new(ac_store.clone())),
+            historical_store: Store::new(cas_store.clone()),
+            upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
+                upload_ac_results_strategy:
+                    nativelink_config::cas_server::UploadCacheResultsStrategy::never,
                 ..Default::default()
             },
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
-        .await?;
-        let action = Action {
-            command_digest: Some(command_digest.into()),
-            input_root_digest: Some(input_root_digest.into()),
-            ..Default::default()
-        };
-        let action_digest = serialize_and_upload_message(
-            &action,
-            cas_store.as_pin(),
-            &mut DigestHasherFunc::Sha256.hasher(),
-        )
+            max_action_timeout: Duration::MAX,
+            timeout_handled_externally: false,
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    running_actions_manager
+        .prune_input_staging_directory()
         .await?;
 
-        let execute_request = ExecuteRequest {
-            action_digest: Some(action_digest.into()),
-            ..Default::default()
-        };
-        let operation_id = OperationId::default().to_string();
+    // The file contents are only in the slow store, so they must be
+    // populated into the fast store unless they are staged.
+    let file1_digest = DigestInfo::new([2u8; 32], FILE1_CONTENT.len() as u64);
+    let file2_digest = DigestInfo::new([3u8; 32], FILE2_CONTENT.len() as u64);
+    let file3_digest = DigestInfo::new([4u8; 32], FILE3_CONTENT.len() as u64);
+    for (digest, content) in [
+        (file1_digest, FILE1_CONTENT),
+        (file2_digest, FILE2_CONTENT),
+        (file3_digest, FILE3_CONTENT),
+    ] {
+        slow_store
+            .as_ref()
+            .update_oneshot(digest, content.into())
+            .await?;
+    }
 
-        let running_action_impl = running_actions_manager
-            .create_and_add_action(
-                WORKER_ID.to_string(),
-                StartExecute {
-                    execute_request: Some(execute_request),
-                    operation_id,
-                    queued_timestamp: None,
+    let prepare_action_with_inputs = |files: Vec<(&'static str, DigestInfo)>| {
+        let cas_store = cas_store.clone();
+        let running_actions_manager = running_actions_manager.clone();
+        async move {
+            let command_digest = serialize_and_upload_message(
+                &Command {
+                    arguments: vec!["true".to_string()],
+                    ..Default::default()
                 },
+                cas_store.as_pin(),
+                &mut DigestHasherFunc::Sha256.hasher(),
             )
             .await?;
-
-        run_action(running_action_impl.clone()).await?
-    };
-    let file_content = slow_store
-        .as_ref()
-        .get_part_unchunked(action_result.output_files[0].digest, 0, None)
-        .await?;
-    assert_eq!(from_utf8(&file_content)?, "123 ");
-    let stdout_content = slow_store
-        .as_ref()
-        .get_part_unchunked(action_result.stdout_digest, 0, None)
-        .await?;
-    assert_eq!(from_utf8(&stdout_content)?, "foo-stdout ");
-    let stderr_content = slow_store
-        .as_ref()
-        .get_part_unchunked(action_result.stderr_digest, 0, None)
-        .await?;
-    assert_eq!(from_utf8(&stderr_content)?, "bar-stderr  ");
-    let mut clock_time = make_system_time(0);
-    assert_eq!(
-        action_result,
-        ActionResult {
-            output_files: vec![FileInfo {
-                name_or_path: NameOrPath::Path("test.txt".to_string()),
-                digest: DigestInfo::try_new(
-                    "c69e10a5f54f4e28e33897fbd4f8701595443fa8c3004aeaa20dd4d9a463483b",
-                    4
-                )?,
-                is_executable: false,
-            }],
-            stdout_digest: DigestInfo::try_new(
-                "15019a676f057d97d1ad3af86f3cc1e623cb33b18ff28422bbe3248d2471cc94",
-                11
-            )?,
-            stderr_digest: DigestInfo::try_new(
-                "2375ab8a01ca11e1ea7606dfb58756c153d49733cde1dbfb5a1e00f39afacf06",
-                12
-            )?,
-            exit_code: 0,
-            output_folders: vec![DirectoryInfo {
-                path: "tst".to_string(),
-                tree_digest: DigestInfo::try_new(
-                    "95711c1905d4898a70209dd6e98241dcafb479c00241a1ea4ed8415710d706f3",
-                    166,
-                )?,
-            },],
-            output_file_symlinks: vec![],
-            output_directory_symlinks: vec![],
-            server_logs: HashMap::new(),
-            execution_metadata: ExecutionMetadata {
-                worker: WORKER_ID.to_string(),
-                queued_timestamp: SystemTime::UNIX_EPOCH,
-                worker_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_start_timestamp: increment_clock(&mut clock_time),
-                input_fetch_completed_timestamp: increment_clock(&mut clock_time),
-                execution_start_timestamp: increment_clock(&mut clock_time),
-                execution_completed_timestamp: increment_clock(&mut clock_time),
-                output_upload_start_timestamp: increment_clock(&mut clock_time),
-                output_upload_completed_timestamp: increment_clock(&mut clock_time),
-                worker_completed_timestamp: increment_clock(&mut clock_time),
-            },
-            error: None,
-            message: String::new(),
+            let input_root_digest = serialize_and_upload_message(
+                &Directory {
+                    files: files
+                        .into_iter()
+                        .map(|(name, digest)| FileNode {
+                            name: name.to_string(),
+                            digest: Some(digest.into()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                },
+                cas_store.as_pin(),
+                &mut DigestHasherFunc::Sha256.hasher(),
+            )
+            .await?;
+            let action_digest = serialize_and_upload_message(
+                &Action {
+                    command_digest: Some(command_digest.into()),
+                    input_root_digest: Some(input_root_digest.into()),
+                    ..Default::default()
+                },
+                cas_store.as_pin(),
+                &mut DigestHasherFunc::Sha256.hasher(),
+            )
+            .await?;
+            let running_action = running_actions_manager
+                .create_and_add_action(
+                    WORKER_ID.to_string(),
+                    StartExecute {
+                        execute_request: Some(ExecuteRequest {
+                            action_digest: Some(action_digest.into()),
+                            ..Default::default()
+                        }),
+                        operation_id: OperationId::default().to_string(),
+                        queued_timestamp: None,
+                    },
+                )
+                .await?;
+            running_action.prepare_action().await
         }
+    };
+
+    let running_action =
+        prepare_action_with_inputs(vec![("file1", file1_digest), ("file2", file2_digest)]).await?;
+    running_action.cleanup().await?;
+
+    // `file2` is still in the fast store, so its staged file is reused.
+    // The fast store copy of `file1` is lost, so its staged file is not
+    // reused and `file1` is populated into the fast store again.
+    assert!(fast_store.remove(file1_digest).await?);
+    assert!(
+        fs::metadata(format!("{input_staging_directory}/{file1_digest}"))
+            .await
+            .is_ok()
     );
+
+    let running_action = prepare_action_with_inputs(vec![
+        ("file1", file1_digest),
+        ("file2", file2_digest),
+        ("file3", file3_digest),
+    ])
+    .await?;
+    let work_directory = running_action.get_work_directory();
+    for (name, content) in [
+        ("file1", FILE1_CONTENT),
+        ("file2", FILE2_CONTENT),
+        ("file3", FILE3_CONTENT),
+    ] {
+        assert_eq!(
+            fs::read(format!("{work_directory}/{name}")).await?,
+            content.as_bytes()
+        );
+    }
+    assert!(fast_store.has(file1_digest).await?.is_some());
+    assert!(fast_store.has(file2_digest).await?.is_some());
+    assert!(fast_store.has(file3_digest).await?.is_some());
+    running_action.cleanup().await?;
+
+    // Staged files that are still in the fast store are kept.
+    running_actions_manager
+        .prune_input_staging_directory()
+        .await?;
+    for digest in [file1_digest, file2_digest, file3_digest] {
+        assert!(fs::metadata(format!("{input_staging_directory}/{digest}"))
+            .await
+            .is_ok());
+    }
     Ok(())
 }
 
-#[cfg_attr(feature = "nix", ignore)]
+#[cfg(target_family = "unix")]
 #[nativelink_test]
-async fn running_actions_manager_respects_action_timeout() -> Result<(), Box<dyn std::error::Error>>
-{
+async fn pruning_input_staging_directory_frees_evicted_inputs_test(
+) -> Result<(), Box<dyn std::error::Error>> {
     const WORKER_ID: &str = "foo_worker_id";
+    const FILE1_CONTENT: &str = "HELLOFILE1";
+    const FILE2_CONTENT: &str = "HELLOFILE2";
 
-    // Ignore the sleep and immediately timeout.
-    static ACTION_TIMEOUT: i64 = 1;
-    fn test_monotonic_clock() -> SystemTime {
-        static CLOCK: AtomicU64 = AtomicU64::new(0);
-        monotonic_clock(&CLOCK)
-    }
-
-    let (_, _, cas_store, ac_store) = setup_stores().await?;
-    let root_action_directory = make_temp_path("root_work_directory");
+    // The fast store only holds a single entry, so every insert evicts the
+    // previous one.
+    let fast_config = FilesystemSpec {
+        content_path: make_temp_path("content_path"),
+        temp_path: make_temp_path("temp_path"),
+        eviction_policy: Some(EvictionPolicy {
+            max_count: 1,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let fast_store: Arc<FilesystemStore> = FilesystemStore::new(&fast_config).await?;
+    let slow_store = MemoryStore::new(&MemorySpec::default());
+    let cas_store = FastSlowStore::new(
+        &FastSlowSpec {
+            fast: StoreSpec::filesystem(fast_config),
+            slow: StoreSpec::memory(MemorySpec::default()),
+            max_concurrent_populations: 0,
+            fast_store_max_write_size: 0,
+        },
+        Store::new(fast_store.clone()),
+        Store::new(slow_store.clone()),
+    );
+    let root_action_directory = make_temp_path("root_action_directory");
     fs::create_dir_all(&root_action_directory).await?;
+    let input_staging_directory = make_temp_path("input_staging_directory");
 
-    let running_actions_manager = Arc::new(RunningActionsManagerImpl::new_with_callbacks(
-        RunningActionsManagerArgs {
+    let running_actions_manager =
+        Arc::new(RunningActionsManagerImpl::new(RunningActionsManagerArgs {
             root_action_directory,
-            execution_configuration: ExecutionConfiguration::default(),
+            execution_configuration: ExecutionConfiguration {
+                input_staging_directory: Some(input_staging_directory.clone()),
+                ..Default::default()
+            },
             cas_store: cas_store.clone(),
-            ac_store: Some(Store::new(ac_store.clone())),
+            ac_store: None,
             historical_store: Store::new(cas_store.clone()),
             upload_action_result_config: &nativelink_config::cas_server::UploadActionResultConfig {
                 upload_ac_results_strategy:
@@ -3284,92 +5444,94 @@ async fn running_actions_manager_respects_action_timeout() -> Result<(), Box<dyn
             },
             max_action_timeout: Duration::MAX,
             timeout_handled_externally: false,
-        },
-        Callbacks {
-            now_fn: test_monotonic_clock,
-            // If action_timeout is the passed duration then return immeidately,
-            // which will cause the action to be killed and pass the test,
-            // otherwise return pending and fail the test.
-            sleep_fn: |duration| {
-                assert_eq!(duration.as_secs(), ACTION_TIMEOUT as u64);
-                Box::pin(futures::future::ready(()))
-            },
-        },
-    )?);
-    #[cfg(target_family = "unix")]
-    let arguments = vec!["sh".to_string(), "-c".to_string(), "sleep 2".to_string()];
-    #[cfg(target_family = "windows")]
-    let arguments = vec![
-        "cmd".to_string(),
-        "/C".to_string(),
-        "ping -n 99999 127.0.0.1".to_string(),
-    ];
-    let command = Command {
-        arguments,
-        working_directory: ".".to_string(),
-        environment_variables: vec![EnvironmentVariable {
-            name: "PATH".to_string(),
-            value: std::env::var("PATH").unwrap(),
-        }],
-        ..Default::default()
-    };
+            max_concurrent_actions: 0,
+            queue_actions_over_limit: false,
+        })?);
+    running_actions_manager
+        .prune_input_staging_directory()
+        .await?;
+
+    let file1_digest = DigestInfo::new([2u8; 32], FILE1_CONTENT.len() as u64);
+    slow_store
+        .as_ref()
+        .update_oneshot(file1_digest, FILE1_CONTENT.into())
+        .await?;
     let command_digest = serialize_and_upload_message(
-        &command,
+        &Command {
+            arguments: vec!["true".to_string()],
+            ..Default::default()
+        },
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
     let input_root_digest = serialize_and_upload_message(
-        &Directory::default(),
+        &Directory {
+            files: vec![FileNode {
+                name: "file1".to_string(),
+                digest: Some(file1_digest.into()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-    let action = Action {
-        command_digest: Some(command_digest.into()),
-        input_root_digest: Some(input_root_digest.into()),
-        platform: Some(Platform {
-            properties: vec![Property {
-                name: "property_name".into(),
-                value: "property_value".into(),
-            }],
-        }),
-        timeout: Some(prost_types::Duration {
-            seconds: ACTION_TIMEOUT,
-            nanos: 0,
-        }),
-        ..Default::default()
-    };
     let action_digest = serialize_and_upload_message(
-        &action,
+        &Action {
+            command_digest: Some(command_digest.into()),
+            input_root_digest: Some(input_root_digest.into()),
+            ..Default::default()
+        },
         cas_store.as_pin(),
         &mut DigestHasherFunc::Sha256.hasher(),
     )
     .await?;
-
-    let execute_request = ExecuteRequest {
-        action_digest: Some(action_digest.into()),
-        ..Default::default()
-    };
-    let operation_id = OperationId::default().to_string();
-
-    let running_action_impl = running_actions_manager
-        .clone()
+    let running_action = running_actions_manager
         .create_and_add_action(
             WORKER_ID.to_string(),
             StartExecute {
-                execute_request: Some(execute_request),
-                operation_id,
-                queued_timestamp: Some(make_system_time(1000).into()),
+                execute_request: Some(ExecuteRequest {
+                    action_digest: Some(action_digest.into()),
+                    ..Default::default()
+                }),
+                operation_id: OperationId::default().to_string(),
+                queued_timestamp: None,
             },
         )
         .await?;
+    running_action.clone().prepare_action().await?;
+    running_action.cleanup().await?;
+
+    // Evicts `file1` from the fast store by inserting another entry.
+    let staged_path = format!("{input_staging_directory}/{file1_digest}");
+    Store::new(fast_store.clone())
+        .update_oneshot(
+            DigestInfo::new([3u8; 32], FILE2_CONTENT.len() as u64),
+            FILE2_CONTENT.into(),
+        )
+        .await?;
+    assert_eq!(fast_store.has(file1_digest).await?, None);
+
+    // The fast store deletes evicted files in the background, after which
+    // the staged hardlink is the only thing keeping the content on disk.
+    let mut nlink = fs::metadata(&staged_path).await?.nlink();
+    for _ in 0..100 {
+        if nlink == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        nlink = fs::metadata(&staged_path).await?.nlink();
+    }
+    assert_eq!(nlink, 1, "Expected the staged file to be the last link");
 
-    let result = run_action(running_action_impl).await?;
-
-    #[cfg(target_family = "unix")]
-    assert_eq!(result.exit_code, 9, "Action process should be been killed");
-    #[cfg(target_family = "windows")]
-    assert_eq!(result.exit_code, 1, "Action process should be been killed");
+    running_actions_manager
+        .prune_input_staging_directory()
+        .await?;
+    assert!(
+        fs::metadata(&staged_path).await.is_err(),
+        "Expected the evicted input to be removed from the staging directory"
+    );
     Ok(())
 }
@@ -14,9 +14,11 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_lock::Mutex;
 use bytes::Bytes;
+use futures::future::BoxFuture;
 use hyper::body::Frame;
 use nativelink_config::cas_server::{EndpointConfig, LocalWorkerConfig, WorkerProperty};
 use nativelink_error::Error;
@@ -27,7 +29,7 @@ use nativelink_util::channel_body_for_tests::ChannelBody;
 use nativelink_util::shutdown_guard::ShutdownGuard;
 use nativelink_util::spawn;
 use nativelink_util::task::JoinHandleDropGuard;
-use nativelink_worker::local_worker::LocalWorker;
+use nativelink_worker::local_worker::{AvailableSpaceFn, LocalWorker};
 use nativelink_worker::worker_api_client_wrapper::WorkerApiClientTrait;
 use tokio::sync::{broadcast, mpsc};
 use tonic::Status;
@@ -48,12 +50,14 @@ const BROADCAST_CAPACITY: usize = 1;
 #[derive(Debug)]
 enum WorkerClientApiCalls {
     ConnectWorker(SupportedProperties),
+    KeepAlive(KeepAliveRequest),
     ExecutionResponse(ExecuteResult),
 }
 
 #[derive(Debug)]
 enum WorkerClientApiReturns {
     ConnectWorker(Result<Response<Streaming<UpdateForWorker>>, Status>),
+    KeepAlive(Result<Response<()>, Status>),
     ExecutionResponse(Result<Response<()>, Status>),
 }
 
@@ -96,7 +100,7 @@ impl MockWorkerApiClient {
             .expect("Could not receive msg in mpsc")
         {
             WorkerClientApiCalls::ConnectWorker(req) => req,
-            req @ WorkerClientApiCalls::ExecutionResponse(_) => {
+            req => {
                 panic!("expect_connect_worker expected ConnectWorker, got : {req:?}")
             }
         };
@@ -106,6 +110,27 @@ impl MockWorkerApiClient {
         req
     }
 
+    pub async fn expect_keep_alive(
+        &mut self,
+        result: Result<Response<()>, Status>,
+    ) -> KeepAliveRequest {
+        let mut rx_call_lock = self.rx_call.lock().await;
+        let req = match rx_call_lock
+            .recv()
+            .await
+            .expect("Could not receive msg in mpsc")
+        {
+            WorkerClientApiCalls::KeepAlive(req) => req,
+            req => {
+                panic!("expect_keep_alive expected KeepAlive, got : {req:?}")
+            }
+        };
+        self.tx_resp
+            .send(WorkerClientApiReturns::KeepAlive(result))
+            .expect("Could not send request to mpsc");
+        req
+    }
+
     pub async fn expect_execution_response(
         &mut self,
         result: Result<Response<()>, Status>,
@@ -117,7 +142,7 @@ impl MockWorkerApiClient {
             .expect("Could not receive msg in mpsc")
         {
             WorkerClientApiCalls::ExecutionResponse(req) => req,
-            req @ WorkerClientApiCalls::ConnectWorker(_) => {
+            req => {
                 panic!("expect_execution_response expected ExecutionResponse, got : {req:?}")
             }
         };
@@ -143,14 +168,27 @@ impl WorkerApiClientTrait for MockWorkerApiClient {
             .expect("Could not receive msg in mpsc")
         {
             WorkerClientApiReturns::ConnectWorker(result) => result,
-            resp @ WorkerClientApiReturns::ExecutionResponse(_) => {
+            resp => {
                 panic!("connect_worker expected ConnectWorker response, received {resp:?}")
             }
         }
     }
 
-    async fn keep_alive(&mut self, _request: KeepAliveRequest) -> Result<Response<()>, Status> {
-        unreachable!();
+    async fn keep_alive(&mut self, request: KeepAliveRequest) -> Result<Response<()>, Status> {
+        self.tx_call
+            .send(WorkerClientApiCalls::KeepAlive(request))
+            .expect("Could not send request to mpsc");
+        let mut rx_resp_lock = self.rx_resp.lock().await;
+        match rx_resp_lock
+            .recv()
+            .await
+            .expect("Could not receive msg in mpsc")
+        {
+            WorkerClientApiReturns::KeepAlive(result) => result,
+            resp => {
+                panic!("keep_alive expected KeepAlive response, received {resp:?}")
+            }
+        }
     }
 
     async fn going_away(&mut self, _request: GoingAwayRequest) -> Result<Response<()>, Status> {
@@ -168,7 +206,7 @@ impl WorkerApiClientTrait for MockWorkerApiClient {
             .expect("Could not receive msg in mpsc")
         {
             WorkerClientApiReturns::ExecutionResponse(result) => result,
-            resp @ WorkerClientApiReturns::ConnectWorker(_) => {
+            resp => {
                 panic!("execution_response expected ExecutionResponse response, received {resp:?}")
             }
         }
@@ -187,6 +225,30 @@ pub fn setup_grpc_stream() -> (
 }
 
 pub async fn setup_local_worker_with_config(local_worker_config: LocalWorkerConfig) -> TestContext {
+    setup_local_worker_with_config_and_sleep_fn(
+        local_worker_config,
+        Box::new(move |_| Box::pin(async move { /* No sleep */ })),
+    )
+    .await
+}
+
+pub async fn setup_local_worker_with_config_and_sleep_fn(
+    local_worker_config: LocalWorkerConfig,
+    sleep_fn: Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>,
+) -> TestContext {
+    setup_local_worker_with_callbacks(
+        local_worker_config,
+        sleep_fn,
+        Arc::new(|_: &str| Box::pin(async move { Ok(u64::MAX) })),
+    )
+    .await
+}
+
+pub async fn setup_local_worker_with_callbacks(
+    local_worker_config: LocalWorkerConfig,
+    sleep_fn: Box<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>,
+    available_space_fn: AvailableSpaceFn,
+) -> TestContext {
     let mock_worker_api_client = MockWorkerApiClient::new();
     let mock_worker_api_client_clone = mock_worker_api_client.clone();
     let actions_manager = Arc::new(MockRunningActionsManager::new());
@@ -197,7 +259,8 @@ pub async fn setup_local_worker_with_config(local_worker_config: LocalWorkerConf
             let mock_worker_api_client = mock_worker_api_client_clone.clone();
             Box::pin(async move { Ok(mock_worker_api_client) })
         }),
-        Box::new(move |_| Box::pin(async move { /* No sleep */ })),
+        sleep_fn,
+        available_space_fn,
     );
     let (shutdown_tx_test, _) = broadcast::channel::<ShutdownGuard>(BROADCAST_CAPACITY);
 
@@ -111,12 +111,20 @@ impl MockRunningActionsManager {
         }
     }
 
-    pub async fn expect_kill_all(&self) {
+    pub async fn expect_no_pending_calls(&self) {
+        let mut rx_call_lock = self.rx_call.lock().await;
+        assert!(
+            rx_call_lock.try_recv().is_err(),
+            "Expected no pending calls to RunningActionsManager"
+        );
+    }
+
+    pub async fn expect_no_kill_all(&self) {
         let mut rx_kill_all_lock = self.rx_kill_all.lock().await;
-        rx_kill_all_lock
-            .recv()
-            .await
-            .expect("Could not receive msg in mpsc");
+        assert!(
+            rx_kill_all_lock.try_recv().is_err(),
+            "Expected kill_all to not be called"
+        );
     }
 
     pub async fn expect_kill_operation(&self) -> OperationId {
@@ -215,6 +223,8 @@ pub struct MockRunningAction {
 
     rx_resp: Mutex<mpsc::UnboundedReceiver<RunningActionReturns>>,
     tx_resp: mpsc::UnboundedSender<RunningActionReturns>,
+
+    do_not_cache: bool,
 }
 
 impl Default for MockRunningAction {
@@ -232,6 +242,14 @@ impl MockRunningAction {
             tx_call,
             rx_resp: Mutex::new(rx_resp),
             tx_resp,
+            do_not_cache: false,
+        }
+    }
+
+    pub fn new_do_not_cache() -> Self {
+        Self {
+            do_not_cache: true,
+            ..Self::new()
         }
     }
 
@@ -435,4 +453,8 @@ impl RunningAction for MockRunningAction {
     fn get_work_directory(&self) -> &String {
         unreachable!();
     }
+
+    fn do_not_cache(&self) -> bool {
+        self.do_not_cache
+    }
 }
@@ -12,13 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Bound;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_lock::Mutex as AsyncMutex;
-use axum::Router;
+use axum::{Extension, Router};
 use clap::Parser;
 use futures::future::{try_join_all, BoxFuture, Either, OptionFuture, TryFutureExt};
 use futures::FutureExt;
@@ -28,29 +29,42 @@ use hyper_util::server::conn::auto;
 use hyper_util::service::TowerToHyperService;
 use mimalloc::MiMalloc;
 use nativelink_config::cas_server::{
-    CasConfig, GlobalConfig, HttpCompressionAlgorithm, ListenerConfig, ServerConfig, WorkerConfig,
+    CasConfig, GlobalConfig, HttpCompressionAlgorithm, InstanceName, ListenerConfig, ServerConfig,
+    ServicesConfig, WorkerConfig,
 };
 use nativelink_config::stores::ConfigDigestHashFunction;
-use nativelink_error::{make_err, make_input_err, Code, Error, ResultExt};
+use nativelink_error::{error_if, make_err, make_input_err, Code, Error, ResultExt};
 use nativelink_metric::{
     MetricFieldData, MetricKind, MetricPublishKnownKindData, MetricsComponent, RootMetricsComponent,
 };
-use nativelink_metric_collector::{otel_export, MetricsCollectorLayer};
-use nativelink_scheduler::default_scheduler_factory::scheduler_factory;
+use nativelink_metric_collector::{otel_export_with_labels, MetricsCollectorLayer};
+use nativelink_scheduler::awaited_action_db::{SortedAwaitedAction, SortedAwaitedActionState};
+use nativelink_scheduler::default_scheduler_factory::{
+    scheduler_factory, validate_scheduler_specs,
+};
+use nativelink_scheduler::worker_scheduler::WorkerScheduler;
 use nativelink_service::ac_server::AcServer;
 use nativelink_service::bep_server::BepServer;
 use nativelink_service::bytestream_server::ByteStreamServer;
 use nativelink_service::capabilities_server::CapabilitiesServer;
 use nativelink_service::cas_server::CasServer;
 use nativelink_service::execution_server::ExecutionServer;
+use nativelink_service::grpc_health_server::GrpcHealthServer;
+use nativelink_service::grpc_reflection_server::GrpcReflectionServer;
 use nativelink_service::health_server::HealthServer;
 use nativelink_service::worker_api_server::WorkerApiServer;
-use nativelink_store::default_store_factory::store_factory;
+use nativelink_store::default_store_factory::{
+    check_ref_store_cycles, store_factory, validate_store_specs,
+};
+use nativelink_store::s3_store::set_max_concurrent_s3_requests;
 use nativelink_store::store_manager::StoreManager;
 use nativelink_util::action_messages::WorkerId;
 use nativelink_util::common::fs::{set_idle_file_descriptor_timeout, set_open_file_limit};
+use nativelink_util::common::{ClientAddr, DigestInfo};
 use nativelink_util::digest_hasher::{set_default_digest_hasher_func, DigestHasherFunc};
 use nativelink_util::health_utils::HealthRegistryBuilder;
+use nativelink_util::inflight_writes::InflightWrites;
+use nativelink_util::instance_name_aliases::InstanceNameAliases;
 use nativelink_util::metrics_utils::{set_metrics_enabled_for_this_thread, Counter};
 use nativelink_util::operation_state_manager::ClientStateManager;
 use nativelink_util::origin_context::{ActiveOriginContext, OriginContext};
@@ -58,7 +72,7 @@ use nativelink_util::origin_event_middleware::OriginEventMiddlewareLayer;
 use nativelink_util::origin_event_publisher::OriginEventPublisher;
 use nativelink_util::shutdown_guard::{Priority, ShutdownGuard};
 use nativelink_util::store_trait::{
-    set_default_digest_size_health_check, DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG,
+    set_default_digest_size_health_check, StoreLike, DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG,
 };
 use nativelink_util::task::TaskExecutor;
 use nativelink_util::{background_spawn, init_tracing, spawn, spawn_blocking};
@@ -89,12 +103,30 @@ static GLOBAL: MiMalloc = MiMalloc;
 /// Note: This must be kept in sync with the documentation in `PrometheusConfig::path`.
 const DEFAULT_PROMETHEUS_METRICS_PATH: &str = "/metrics";
 
+/// Top level metric groups whose children are exported to Prometheus as a
+/// label, so the same metric of two stores, servers, workers or schedulers
+/// shares a name and can be told apart by the label value.
+const PROMETHEUS_LABEL_GROUPS: &[(&str, &str)] = &[
+    ("stores", "store"),
+    ("servers", "server"),
+    ("workers", "worker"),
+    ("action_schedulers", "scheduler"),
+];
+
 /// Note: This must be kept in sync with the documentation in `AdminConfig::path`.
 const DEFAULT_ADMIN_API_PATH: &str = "/admin";
 
+/// Maximum number of operations returned by one call to the admin
+/// operations endpoint.
+const ADMIN_LIST_OPERATIONS_PAGE_SIZE: usize = 100;
+
 // Note: This must be kept in sync with the documentation in `HealthConfig::path`.
 const DEFAULT_HEALTH_STATUS_CHECK_PATH: &str = "/status";
 
+/// Note: This must be kept in sync with the documentation in
+/// `GrpcHealthConfig::check_interval`.
+const DEFAULT_GRPC_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 /// Name of environment variable to disable metrics.
 const METRICS_DISABLE_ENV: &str = "NATIVELINK_DISABLE_METRICS";
 
@@ -106,6 +138,10 @@ const DEFAULT_MAX_QUEUE_EVENTS: usize = 65536;
 /// Note: The actual capacity may be greater than the provided capacity.
 const BROADCAST_CAPACITY: usize = 1;
 
+/// Note: If this changes, make sure you update the documentation in
+/// `config/cas_server.rs`.
+const DEFAULT_SHUTDOWN_WRITE_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Backend for bazel remote execution / cache API.
 #[derive(Parser, Debug)]
 #[clap(
@@ -118,6 +154,11 @@ struct Args {
     /// Config file to use.
     #[clap(value_parser)]
     config_file: String,
+
+    /// Check the stores and schedulers of the config file and exit, without
+    /// starting any servers or workers.
+    #[clap(long)]
+    validate_config: bool,
 }
 
 /// The root metrics collector struct. All metrics will be
@@ -169,6 +210,17 @@ struct ConnectedClientsMetrics {
     server_start_ts: u64,
 }
 
+impl ConnectedClientsMetrics {
+    /// Number of connections currently open from `ip`.
+    fn connections_from(&self, ip: IpAddr) -> usize {
+        self.inner
+            .lock()
+            .iter()
+            .filter(|addr| addr.0.ip() == ip)
+            .count()
+    }
+}
+
 impl RootMetricsComponent for ConnectedClientsMetrics {}
 
 async fn inner_main(
@@ -186,6 +238,7 @@ async fn inner_main(
     let health_registry_builder =
         Arc::new(AsyncMutex::new(HealthRegistryBuilder::new("nativelink")));
 
+    check_ref_store_cycles(&cfg.stores)?;
     let store_manager = Arc::new(StoreManager::new());
     {
         let mut health_registry_lock = health_registry_builder.lock().await;
@@ -275,6 +328,35 @@ async fn inner_main(
         })
         .transpose()?;
 
+    let inflight_writes = InflightWrites::default();
+    {
+        let shutdown_write_drain_timeout = match cfg
+            .global
+            .map(|global_cfg| global_cfg.shutdown_write_drain_timeout)
+        {
+            None | Some(0) => DEFAULT_SHUTDOWN_WRITE_DRAIN_TIMEOUT,
+            Some(timeout) => Duration::from_secs(timeout as u64),
+        };
+        let inflight_writes = inflight_writes.clone();
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        root_futures.push(Box::pin(async move {
+            // The shutdown guard is held until the in flight writes are
+            // drained, so the process does not exit while they are running.
+            let Ok(_shutdown_guard) = shutdown_rx.recv().await else {
+                return Ok(());
+            };
+            event!(
+                Level::WARN,
+                count = inflight_writes.count(),
+                "Draining in flight writes before shutting down"
+            );
+            if let Err(err) = inflight_writes.drain(shutdown_write_drain_timeout).await {
+                event!(Level::ERROR, ?err, "Aborting in flight writes on shutdown");
+            }
+            Ok::<_, Error>(())
+        }));
+    }
+
     for (server_cfg, connected_clients_mux) in servers_and_clients {
         let services = server_cfg
             .services
@@ -283,13 +365,79 @@ async fn inner_main(
         // Currently we only support http as our socket type.
         let ListenerConfig::http(http_config) = server_cfg.listener;
 
+        if let Some(err) = validate_digest_functions(&services)
+            .into_iter()
+            .reduce(Error::merge)
+        {
+            return Err(err);
+        }
+        // Must be created before the services below take their configs.
+        let digest_functions = instance_digest_functions(&services);
+        let instance_name_aliases =
+            InstanceNameAliases::new(services.instance_name_aliases.clone());
+
+        // Must be created before the services below take their configs.
+        let maybe_grpc_health_service = services
+            .experimental_grpc_health
+            .as_ref()
+            .map(|grpc_health_cfg| {
+                let (grpc_health_server, service) =
+                    GrpcHealthServer::new(&services, &store_manager)
+                        .err_tip(|| "Could not create gRPC health service")?;
+                let check_interval = if grpc_health_cfg.check_interval == 0 {
+                    DEFAULT_GRPC_HEALTH_CHECK_INTERVAL
+                } else {
+                    Duration::from_secs(grpc_health_cfg.check_interval as u64)
+                };
+                root_futures.push(Box::pin(grpc_health_server.run(check_interval).map(Ok)));
+                Ok::<_, Error>(service)
+            })
+            .transpose()?;
+
+        // Must be created before the services below take their configs.
+        let maybe_grpc_reflection_server = services
+            .experimental_grpc_reflection
+            .as_ref()
+            .map(|_| GrpcReflectionServer::new(&services));
+
+        // Must be created before the services below take their configs.
+        let maybe_capabilities_server =
+            OptionFuture::from(services.capabilities.as_ref().map(|capabilities_cfg| {
+                CapabilitiesServer::new(
+                    capabilities_cfg,
+                    services.cas.as_ref(),
+                    services.ac.as_ref(),
+                    &action_schedulers,
+                )
+            }))
+            .await
+            .transpose()
+            .err_tip(|| "Could not create Capabilities service")?;
+
         let tonic_services = TonicServer::builder()
+            .add_optional_service(maybe_grpc_health_service)
+            .add_optional_service(
+                maybe_grpc_reflection_server
+                    .as_ref()
+                    .map(GrpcReflectionServer::v1_service)
+                    .transpose()
+                    .err_tip(|| "Could not create gRPC reflection service")?,
+            )
+            .add_optional_service(
+                maybe_grpc_reflection_server
+                    .as_ref()
+                    .map(GrpcReflectionServer::v1alpha_service)
+                    .transpose()
+                    .err_tip(|| "Could not create gRPC reflection service")?,
+            )
             .add_optional_service(
                 services
                     .ac
                     .map_or(Ok(None), |cfg| {
                         AcServer::new(&cfg, &store_manager).map(|v| {
-                            let mut service = v.into_service();
+                            let mut service = v
+                                .with_instance_name_aliases(instance_name_aliases.clone())
+                                .into_service();
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -315,7 +463,14 @@ async fn inner_main(
                     .cas
                     .map_or(Ok(None), |cfg| {
                         CasServer::new(&cfg, &store_manager).map(|v| {
-                            let mut service = v.into_service();
+                            let mut service = v
+                                .with_instance_name_aliases(instance_name_aliases.clone())
+                                .with_inflight_writes(inflight_writes.clone())
+                                .with_max_message_sizes(
+                                    http_config.max_decoding_message_size,
+                                    http_config.max_encoding_message_size,
+                                )
+                                .into_service();
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -341,7 +496,14 @@ async fn inner_main(
                     .execution
                     .map_or(Ok(None), |cfg| {
                         ExecutionServer::new(&cfg, &action_schedulers, &store_manager).map(|v| {
-                            let mut service = v.into_service();
+                            let mut service = v
+                                .with_instance_name_aliases(instance_name_aliases.clone())
+                                .with_instance_digest_functions(digest_functions.clone())
+                                .with_max_message_sizes(
+                                    http_config.max_decoding_message_size,
+                                    http_config.max_encoding_message_size,
+                                )
+                                .into_service();
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -367,7 +529,10 @@ async fn inner_main(
                     .bytestream
                     .map_or(Ok(None), |cfg| {
                         ByteStreamServer::new(&cfg, &store_manager).map(|v| {
-                            let mut service = v.into_service();
+                            let mut service = v
+                                .with_instance_name_aliases(instance_name_aliases.clone())
+                                .with_inflight_writes(inflight_writes.clone())
+                                .into_service();
                             let send_algo = &http_config.compression.send_compression_algorithm;
                             if let Some(encoding) =
                                 into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
@@ -388,44 +553,27 @@ async fn inner_main(
                     })
                     .err_tip(|| "Could not create ByteStream service")?,
             )
-            .add_optional_service(
-                OptionFuture::from(
-                    services
-                        .capabilities
-                        .as_ref()
-                        // Borrow checker fighting here...
-                        .map(|_| {
-                            CapabilitiesServer::new(
-                                services.capabilities.as_ref().unwrap(),
-                                &action_schedulers,
-                            )
-                        }),
-                )
-                .await
-                .map_or(Ok::<Option<CapabilitiesServer>, Error>(None), |server| {
-                    Ok(Some(server?))
-                })
-                .err_tip(|| "Could not create Capabilities service")?
-                .map(|v| {
-                    let mut service = v.into_service();
-                    let send_algo = &http_config.compression.send_compression_algorithm;
-                    if let Some(encoding) =
-                        into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
-                    {
-                        service = service.send_compressed(encoding);
-                    }
-                    for encoding in http_config
-                        .compression
-                        .accepted_compression_algorithms
-                        .iter()
-                        // Filter None values.
-                        .filter_map(|from: &HttpCompressionAlgorithm| into_encoding(*from))
-                    {
-                        service = service.accept_compressed(encoding);
-                    }
-                    service
-                }),
-            )
+            .add_optional_service(maybe_capabilities_server.map(|v| {
+                let mut service = v
+                    .with_instance_name_aliases(instance_name_aliases.clone())
+                    .into_service();
+                let send_algo = &http_config.compression.send_compression_algorithm;
+                if let Some(encoding) =
+                    into_encoding(send_algo.unwrap_or(HttpCompressionAlgorithm::none))
+                {
+                    service = service.send_compressed(encoding);
+                }
+                for encoding in http_config
+                    .compression
+                    .accepted_compression_algorithms
+                    .iter()
+                    // Filter None values.
+                    .filter_map(|from: &HttpCompressionAlgorithm| into_encoding(*from))
+                {
+                    service = service.accept_compressed(encoding);
+                }
+                service
+            }))
             .add_optional_service(
                 services
                     .worker_api
@@ -594,10 +742,11 @@ async fn inner_main(
                                     }
 
                                     // Export the metrics to OpenTelemetry.
-                                    otel_export(
+                                    otel_export_with_labels(
                                         "nativelink".to_string(),
                                         &meter,
                                         &output_metrics.lock(),
+                                        PROMETHEUS_LABEL_GROUPS,
                                     );
 
                                     // Translate the OpenTelemetry metrics to Prometheus format and encode
@@ -635,10 +784,92 @@ async fn inner_main(
             } else {
                 &admin_config.path
             };
+            let purge_router = if admin_config.purge_auth_token.is_empty() {
+                Router::new()
+            } else {
+                let purge_auth_token = Arc::new(admin_config.purge_auth_token.clone());
+                let purge_digest_auth_token = purge_auth_token.clone();
+                let purge_digest_store_manager = store_manager.clone();
+                let purge_prefix_store_manager = store_manager.clone();
+                Router::new()
+                    .route(
+                        "/store/:store_name/purge/digest/:hash/:size",
+                        axum::routing::post(
+                            move |params: axum::extract::Path<(String, String, u64)>,
+                                  headers: axum::http::HeaderMap| async move {
+                                let (store_name, hash, size) = params.0;
+                                check_admin_auth(&headers, &purge_digest_auth_token)?;
+                                (async move {
+                                    let digest = DigestInfo::try_new(&hash, size)?;
+                                    purge_store_json(
+                                        &purge_digest_store_manager,
+                                        &store_name,
+                                        PurgeTarget::Digest(digest),
+                                    )
+                                    .await
+                                })
+                                .await
+                                .map_err(admin_error_response)
+                            },
+                        ),
+                    )
+                    .route(
+                        "/store/:store_name/purge/prefix/:prefix",
+                        axum::routing::post(
+                            move |params: axum::extract::Path<(String, String)>,
+                                  headers: axum::http::HeaderMap| async move {
+                                let (store_name, prefix) = params.0;
+                                check_admin_auth(&headers, &purge_auth_token)?;
+                                purge_store_json(
+                                    &purge_prefix_store_manager,
+                                    &store_name,
+                                    PurgeTarget::Prefix(&prefix),
+                                )
+                                .await
+                                .map_err(admin_error_response)
+                            },
+                        ),
+                    )
+            };
             let worker_schedulers = Arc::new(worker_schedulers.clone());
+            let list_operations_schedulers = worker_schedulers.clone();
+            let list_operations_after_schedulers = worker_schedulers.clone();
             svc = svc.nest_service(
                 path,
-                Router::new().route(
+                Router::new()
+                .route(
+                    "/scheduler/:instance_name/operations/:state",
+                    axum::routing::get(
+                        move |params: axum::extract::Path<(String, String)>| async move {
+                            let (instance_name, state) = params.0;
+                            list_operations_json(
+                                &list_operations_schedulers,
+                                &instance_name,
+                                &state,
+                                None,
+                            )
+                            .await
+                            .map_err(admin_error_response)
+                        },
+                    ),
+                )
+                .route(
+                    "/scheduler/:instance_name/operations/:state/:start_after",
+                    axum::routing::get(
+                        move |params: axum::extract::Path<(String, String, String)>| async move {
+                            let (instance_name, state, start_after) = params.0;
+                            list_operations_json(
+                                &list_operations_after_schedulers,
+                                &instance_name,
+                                &state,
+                                Some(&start_after),
+                            )
+                            .await
+                            .map_err(admin_error_response)
+                        },
+                    ),
+                )
+                .route(
                     "/scheduler/:instance_name/set_drain_worker/:worker_id/:is_draining",
                     axum::routing::post(
                         move |params: axum::extract::Path<(String, String, String)>| async move {
@@ -680,7 +911,8 @@ async fn inner_main(
                             })
                         },
                     ),
-                ),
+                )
+                .merge(purge_router),
             );
         }
 
@@ -776,6 +1008,7 @@ async fn inner_main(
         let tcp_listener = TcpListener::bind(&socket_addr).await?;
         let mut http = auto::Builder::new(TaskExecutor::default());
 
+        let max_connections_per_client = http_config.max_connections_per_client;
         let http_config = &http_config.advanced_http;
         if let Some(value) = http_config.http2_keep_alive_interval {
             http.http2()
@@ -824,6 +1057,20 @@ async fn inner_main(
                 select! {
                     accept_result = tcp_listener.accept() => {
                         match accept_result {
+                            Ok((_tcp_stream, remote_addr))
+                                if max_connections_per_client != 0
+                                    && connected_clients_mux.connections_from(remote_addr.ip())
+                                        >= max_connections_per_client =>
+                            {
+                                event!(
+                                    target: "nativelink::services",
+                                    Level::WARN,
+                                    ?remote_addr,
+                                    ?socket_addr,
+                                    max_connections_per_client,
+                                    "Client has too many connections, closing new connection"
+                                );
+                            },
                             Ok((tcp_stream, remote_addr)) => {
                                 event!(
                                     target: "nativelink::services",
@@ -859,8 +1106,11 @@ async fn inner_main(
                                     },
                                 );
 
-                                let (http, svc, maybe_tls_acceptor) =
-                                    (http.clone(), svc.clone(), maybe_tls_acceptor.clone());
+                                let (http, svc, maybe_tls_acceptor) = (
+                                    http.clone(),
+                                    svc.clone().layer(Extension(ClientAddr(remote_addr))),
+                                    maybe_tls_acceptor.clone(),
+                                );
                                 Arc::new(OriginContext::new()).background_spawn(
                                     error_span!(
                                         target: "nativelink::services",
@@ -1000,8 +1250,109 @@ async fn inner_main(
     Ok(())
 }
 
-async fn get_config() -> Result<CasConfig, Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// Lists a page of the operations of a scheduler in the given state as
+/// JSON. `start_after` is the `cursor` of the last operation of the
+/// previous page.
+async fn list_operations_json(
+    worker_schedulers: &HashMap<String, Arc<dyn WorkerScheduler>>,
+    instance_name: &str,
+    state: &str,
+    start_after: Option<&str>,
+) -> Result<String, Error> {
+    let state = state.parse::<SortedAwaitedActionState>()?;
+    let start = match start_after {
+        Some(cursor) => Bound::Excluded(cursor.parse::<SortedAwaitedAction>()?),
+        None => Bound::Unbounded,
+    };
+    let operations = worker_schedulers
+        .get(instance_name)
+        .err_tip_with_code(|_| {
+            (
+                Code::NotFound,
+                format!("Can not get an instance with the name of '{instance_name}'"),
+            )
+        })?
+        .list_operations(state, start, ADMIN_LIST_OPERATIONS_PAGE_SIZE)
+        .await?;
+    serde_json::to_string(&operations)
+        .map_err(|e| make_err!(Code::Internal, "Could not convert to json {e:?}"))
+}
+
+/// Converts an error of an admin endpoint into its HTTP response. Errors
+/// caused by the request are reported as client errors.
+fn admin_error_response(e: Error) -> (axum::http::StatusCode, String) {
+    let status = match e.code {
+        Code::InvalidArgument => axum::http::StatusCode::BAD_REQUEST,
+        Code::NotFound => axum::http::StatusCode::NOT_FOUND,
+        _ => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, format!("Error: {e:?}"))
+}
+
+/// What to remove from a store with the admin purge endpoints.
+enum PurgeTarget<'a> {
+    /// A single entry.
+    Digest(DigestInfo),
+    /// Every entry whose key starts with the prefix.
+    Prefix(&'a str),
+}
+
+/// Compares `a` and `b` in time independent of where they differ, so the
+/// admin token can not be guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks that `headers` carry `auth_token` as bearer token.
+fn check_admin_auth(
+    headers: &axum::http::HeaderMap,
+    auth_token: &str,
+) -> Result<(), (axum::http::StatusCode, String)> {
+    let is_authorized = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), auth_token.as_bytes()));
+    if is_authorized {
+        return Ok(());
+    }
+    Err((
+        axum::http::StatusCode::UNAUTHORIZED,
+        "Missing or invalid bearer token".to_string(),
+    ))
+}
+
+async fn purge_store_json(
+    store_manager: &StoreManager,
+    store_name: &str,
+    target: PurgeTarget<'_>,
+) -> Result<String, Error> {
+    let store = store_manager.get_store(store_name).err_tip_with_code(|_| {
+        (
+            Code::NotFound,
+            format!("Can not get a store with the name of '{store_name}'"),
+        )
+    })?;
+    let removed = match target {
+        PurgeTarget::Digest(digest) => u64::from(store.remove(digest).await?),
+        PurgeTarget::Prefix(prefix) => {
+            error_if!(prefix.is_empty(), "Purge prefix must not be empty");
+            store.remove_prefix(prefix).await?
+        }
+    };
+    event!(
+        Level::WARN,
+        store_name,
+        removed,
+        "Purged entries from store"
+    );
+    Ok(serde_json::json!({ "removed": removed }).to_string())
+}
+
+async fn get_config(args: &Args) -> Result<CasConfig, Box<dyn std::error::Error>> {
     let json_contents = String::from_utf8(
         std::fs::read(&args.config_file)
             .err_tip(|| format!("Could not open config file {}", args.config_file))?,
@@ -1009,10 +1360,82 @@ async fn get_config() -> Result<CasConfig, Box<dyn std::error::Error>> {
     Ok(serde_json5::from_str(&json_contents)?)
 }
 
+/// Returns the digest function each instance of `services` is restricted to
+/// by its CAS or ByteStream config. `validate_digest_functions` ensures the
+/// two agree.
+fn instance_digest_functions(services: &ServicesConfig) -> HashMap<InstanceName, DigestHasherFunc> {
+    let mut digest_functions = HashMap::new();
+    if let Some(bytestream_cfg) = &services.bytestream {
+        for (instance_name, digest_function) in &bytestream_cfg.digest_functions {
+            digest_functions.insert(instance_name.clone(), (*digest_function).into());
+        }
+    }
+    if let Some(cas_cfg) = &services.cas {
+        for (instance_name, cas_instance_cfg) in cas_cfg {
+            if let Some(digest_function) = cas_instance_cfg.digest_function {
+                digest_functions.insert(instance_name.clone(), digest_function.into());
+            }
+        }
+    }
+    digest_functions
+}
+
+/// Returns an error for every instance that is served by both the CAS and
+/// ByteStream services, but restricted to different digest functions.
+fn validate_digest_functions(services: &ServicesConfig) -> Vec<Error> {
+    let (Some(cas_cfg), Some(bytestream_cfg)) = (&services.cas, &services.bytestream) else {
+        return Vec::new();
+    };
+    let mut errors = Vec::new();
+    // Sorted so that errors are reported in a stable order.
+    for (instance_name, cas_instance_cfg) in cas_cfg.iter().collect::<BTreeMap<_, _>>() {
+        if !bytestream_cfg.cas_stores.contains_key(instance_name) {
+            continue;
+        }
+        let cas_digest_function = cas_instance_cfg.digest_function.map(DigestHasherFunc::from);
+        let bytestream_digest_function = bytestream_cfg
+            .digest_functions
+            .get(instance_name)
+            .copied()
+            .map(DigestHasherFunc::from);
+        if cas_digest_function != bytestream_digest_function {
+            errors.push(make_input_err!(
+                "Instance '{instance_name}' uses digest function {cas_digest_function:?} in 'cas', but {bytestream_digest_function:?} in 'bytestream.digest_functions'; they must match"
+            ));
+        }
+    }
+    errors
+}
+
+/// Logs every problem with the stores, schedulers and services of `cfg`.
+fn validate_config(cfg: &CasConfig) -> Result<(), Error> {
+    let mut errors = validate_store_specs(&cfg.stores);
+    if let Some(schedulers) = &cfg.schedulers {
+        errors.extend(validate_scheduler_specs(schedulers, &cfg.stores));
+    }
+    for server_cfg in &cfg.servers {
+        if let Some(services) = &server_cfg.services {
+            errors.extend(validate_digest_functions(services));
+        }
+    }
+    for err in &errors {
+        event!(Level::ERROR, %err, "Invalid config");
+    }
+    if !errors.is_empty() {
+        return Err(make_input_err!("Found {} problems in config", errors.len()));
+    }
+    event!(Level::INFO, "Config is valid");
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     init_tracing()?;
 
-    let mut cfg = futures::executor::block_on(get_config())?;
+    let args = Args::parse();
+    let mut cfg = futures::executor::block_on(get_config(&args))?;
+    if args.validate_config {
+        return Ok(validate_config(&cfg)?);
+    }
 
     let (mut metrics_enabled, max_blocking_threads) = {
         // Note: If the default changes make sure you update the documentation in
@@ -1038,6 +1461,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             GlobalConfig {
                 max_open_files: DEFAULT_MAX_OPEN_FILES,
                 idle_file_descriptor_timeout_millis: DEFAULT_IDLE_FILE_DESCRIPTOR_TIMEOUT_MILLIS,
+                max_concurrent_s3_requests: 0,
                 disable_metrics: cfg.servers.iter().all(|v| {
                     let Some(service) = &v.services else {
                         return true;
@@ -1046,6 +1470,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }),
                 default_digest_hash_function: None,
                 default_digest_size_health_check: DEFAULT_DIGEST_SIZE_HEALTH_CHECK_CFG,
+                shutdown_write_drain_timeout: 0,
             }
         };
         set_open_file_limit(global_cfg.max_open_files);
@@ -1058,6 +1483,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .unwrap_or(ConfigDigestHashFunction::sha256),
         ))?;
         set_default_digest_size_health_check(global_cfg.default_digest_size_health_check)?;
+        set_max_concurrent_s3_requests(global_cfg.max_concurrent_s3_requests)?;
         // TODO (#513): prevent deadlocks by assigning max blocking threads number of open files * ten
         (!global_cfg.disable_metrics, global_cfg.max_open_files * 10)
     };